@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Parser benchmarks over the repository corpus
+//!
+//! Run with `cargo bench --bench parser`; criterion keeps the previous
+//! results under `target/criterion` and reports changes against them.
+
+use castagne_rs::corpus::Corpus;
+use castagne_rs::parser::{CastagneParser, JsonFormat};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::Path;
+
+fn corpus() -> Corpus {
+    let corpus = Corpus::repository(Path::new(env!("CARGO_MANIFEST_DIR")))
+        .expect("Failed to load the benchmark corpus");
+    assert!(!corpus.is_empty(), "The benchmark corpus is empty");
+    corpus
+}
+
+fn full_parse(c: &mut Criterion) {
+    let corpus = corpus();
+    let mut group = c.benchmark_group("full_parse");
+    for file in &corpus.files {
+        let path = file.path.to_string_lossy();
+        group.throughput(Throughput::Bytes(file.source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(file.name()), file, |b, file| {
+            let mut parser = CastagneParser::new();
+            b.iter(|| parser.create_full_character_from_source(&path, black_box(&file.source)))
+        });
+    }
+    group.finish();
+}
+
+fn metadata_parse(c: &mut Criterion) {
+    let corpus = corpus();
+    let mut group = c.benchmark_group("metadata_parse");
+    for file in &corpus.files {
+        group.throughput(Throughput::Bytes(file.source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(file.name()),
+            &file.path,
+            |b, path| {
+                let mut parser = CastagneParser::new();
+                b.iter(|| parser.get_character_metadata(black_box(&path.to_string_lossy())))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn json_serialization(c: &mut Criterion) {
+    let corpus = corpus();
+    let mut group = c.benchmark_group("json_serialization");
+    for file in &corpus.files {
+        let path = file.path.to_string_lossy();
+        let Ok(character) =
+            CastagneParser::new().create_full_character_from_source(&path, &file.source)
+        else {
+            continue;
+        };
+        group.bench_with_input(
+            BenchmarkId::from_parameter(file.name()),
+            &character,
+            |b, character| b.iter(|| black_box(character).to_json(JsonFormat::Internal).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn whole_corpus(c: &mut Criterion) {
+    let corpus = corpus();
+    let mut group = c.benchmark_group("corpus");
+    group.throughput(Throughput::Bytes(corpus.total_bytes() as u64));
+    group.bench_function("full_parse", |b| {
+        let mut parser = CastagneParser::new();
+        b.iter(|| {
+            for file in &corpus.files {
+                let path = file.path.to_string_lossy();
+                let _ = black_box(parser.create_full_character_from_source(&path, &file.source));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    full_parse,
+    metadata_parse,
+    json_serialization,
+    whole_corpus
+);
+criterion_main!(benches);
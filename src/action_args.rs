@@ -0,0 +1,252 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Action Args - Action arguments stored in one buffer
+//!
+//! A big character has tens of thousands of actions, and a `Vec<String>`
+//! of arguments each meant as many small allocations. `ActionArgs` keeps
+//! the arguments of an action end to end in one `String`, with the offset
+//! each one ends at: two allocations per action, whatever its arguments.
+//! The parser pushes the arguments it splits straight into the buffer.
+//!
+//! Arguments are read as `&str`, by index or with `iter`, `first` and
+//! `get` as on a vector. To change them, build new ones with `push`, or
+//! collect them from an iterator of strings.
+
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Index, Range};
+
+/// Arguments of one action, end to end in one buffer
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct ActionArgs {
+    text: String,
+    /// Offset in `text` where each argument ends
+    ends: Vec<usize>,
+}
+
+impl ActionArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Room for `count` arguments of `len` bytes in all
+    pub fn with_capacity(len: usize, count: usize) -> Self {
+        Self {
+            text: String::with_capacity(len),
+            ends: Vec::with_capacity(count),
+        }
+    }
+
+    pub fn push(&mut self, argument: &str) {
+        self.text.push_str(argument);
+        self.ends.push(self.text.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let end = *self.ends.get(index)?;
+        let start = match index {
+            0 => 0,
+            _ => self.ends[index - 1],
+        };
+        Some(&self.text[start..end])
+    }
+
+    pub fn first(&self) -> Option<&str> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            args: self,
+            range: 0..self.len(),
+        }
+    }
+
+    /// The arguments with `separator` between them, as written in a call
+    pub fn join(&self, separator: &str) -> String {
+        self.iter().collect::<Vec<_>>().join(separator)
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.iter().map(str::to_string).collect()
+    }
+}
+
+/// Iterator over the arguments of an action
+#[derive(Clone)]
+pub struct Iter<'a> {
+    args: &'a ActionArgs,
+    range: Range<usize>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.range.next().map(|index| &self.args[index])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|index| &self.args[index])
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl Index<usize> for ActionArgs {
+    type Output = str;
+
+    fn index(&self, index: usize) -> &str {
+        match self.get(index) {
+            Some(argument) => argument,
+            None => panic!(
+                "argument index {} out of range for {} arguments",
+                index,
+                self.len()
+            ),
+        }
+    }
+}
+
+impl<S: AsRef<str>> From<Vec<S>> for ActionArgs {
+    fn from(args: Vec<S>) -> Self {
+        args.into_iter().collect()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for ActionArgs {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut args = Self::new();
+        args.extend(iter);
+        args
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for ActionArgs {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        for argument in iter {
+            self.push(argument.as_ref());
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a ActionArgs {
+    type Item = &'a str;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<T: AsRef<str>> PartialEq<[T]> for ActionArgs {
+    fn eq(&self, other: &[T]) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter().map(AsRef::as_ref))
+    }
+}
+
+impl<T: AsRef<str>> PartialEq<Vec<T>> for ActionArgs {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        *self == *other.as_slice()
+    }
+}
+
+impl<T: AsRef<str>, const N: usize> PartialEq<[T; N]> for ActionArgs {
+    fn eq(&self, other: &[T; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+impl fmt::Debug for ActionArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for ActionArgs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for argument in self {
+            seq.serialize_element(argument)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ActionArgs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<String>::deserialize(deserializer).map(ActionArgs::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_like_a_vector() {
+        let jab = ActionArgs::from(vec!["Health", "Add(100, 50)"]);
+        assert_eq!(jab.len(), 2);
+        assert_eq!(&jab[1], "Add(100, 50)");
+        assert_eq!(jab.first(), Some("Health"));
+        assert_eq!(jab.last(), Some("Add(100, 50)"));
+        assert_eq!(jab.get(2), None);
+        assert_eq!(jab, vec!["Health", "Add(100, 50)"]);
+        assert_eq!(
+            jab.iter().rev().collect::<Vec<_>>(),
+            ["Add(100, 50)", "Health"]
+        );
+        assert_eq!(jab.join(", "), "Health, Add(100, 50)");
+        assert!(ActionArgs::default().is_empty());
+        assert_eq!(
+            serde_json::to_string(&jab).unwrap(),
+            r#"["Health","Add(100, 50)"]"#
+        );
+        let back: ActionArgs = serde_json::from_str(r#"["Health","Add(100, 50)"]"#).unwrap();
+        assert_eq!(back, jab);
+    }
+
+    #[test]
+    fn test_empty_arguments_keep_their_place() {
+        let mut args = ActionArgs::new();
+        args.push("");
+        args.push("1");
+        args.push("");
+        assert_eq!(args, ["", "1", ""]);
+        assert_ne!(args, ["1"]);
+        assert_ne!(ActionArgs::from(vec!["a", "b"]), ["ab"]);
+    }
+
+    #[test]
+    fn test_parsed_actions_hold_one_buffer() {
+        let source = ":Idle:\n---Init:\nSet(Health, Add(100, 50))\n";
+        let character = crate::parser::CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let args = &character.states["Idle"].actions["Init"][0].args;
+        assert_eq!(*args, ["Health", "Add(100, 50)"]);
+        assert_eq!(args.text, "HealthAdd(100, 50)");
+        assert_eq!(args.ends, [6, 18]);
+    }
+}
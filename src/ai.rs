@@ -0,0 +1,303 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! AI - What a CPU opponent can decide, per AI state
+//!
+//! Castagne runs a second state machine for the CPU: AI states (`AI-Init`,
+//! `AI-Neutral-WalkAround`...) have an `AI` phase deciding inputs with
+//! `InputPress`, `AIInputTransition` and `AIAttackCancelOnHit`, and move
+//! to other AI states with `AITransition`. Random branches (`R50:`, then
+//! `R35` for the next choice) give the chance of each decision.
+//!
+//! The `AI` specblock adds tuning the CPU reads directly:
+//! `AI_WEIGHT_<Decision>: 30` weights and `AI_REACTION_<Situation>: 2B,
+//! 623C` lists of states to answer a situation with.
+
+use crate::diagnostics::Diagnostic;
+use crate::frame_data::is_branch;
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState};
+use crate::specs::SpecReader;
+use std::collections::BTreeMap;
+
+/// Phase the AI state machine runs
+pub const AI_PHASE: &str = "AI";
+
+/// Prefix of the AI states in the Castagne skeleton
+pub(crate) const AI_STATE_PREFIX: &str = "AI-";
+
+/// AI state the CPU starts in
+const AI_ENTRY_STATE: &str = "AI-Init";
+
+const AI_SPECBLOCK: &str = "AI";
+const WEIGHT_PREFIX: &str = "AI_WEIGHT_";
+const REACTION_PREFIX: &str = "AI_REACTION_";
+
+/// What an AI decision does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIDecisionKind {
+    /// `AITransition`, to another AI state
+    Transition,
+    /// `AIInputTransition`, to an attack or movement state
+    InputTransition,
+    CancelOnHit,
+    CancelOnBlock,
+    /// `AIAttackCancelOnTouch`, on hit and on block
+    CancelOnTouch,
+    /// `InputPress` of a button or direction
+    Press,
+    Release,
+}
+
+impl AIDecisionKind {
+    fn of_instruction(instruction: &str) -> Option<Self> {
+        let kind = match instruction {
+            "AITransition" => AIDecisionKind::Transition,
+            "AIInputTransition" => AIDecisionKind::InputTransition,
+            "AIAttackCancelOnHit" => AIDecisionKind::CancelOnHit,
+            "AIAttackCancelOnBlock" => AIDecisionKind::CancelOnBlock,
+            "AIAttackCancelOnTouch" => AIDecisionKind::CancelOnTouch,
+            "InputPress" => AIDecisionKind::Press,
+            "InputRelease" => AIDecisionKind::Release,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// One decision of an AI state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIDecision {
+    pub kind: AIDecisionKind,
+    /// State or input it names
+    pub target: String,
+    /// Percent chance of the random branch it is in, `None` outside one
+    pub chance: Option<u32>,
+    pub line: usize,
+}
+
+/// Decisions of one AI state, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIStateProfile {
+    pub name: String,
+    pub decisions: Vec<AIDecision>,
+    /// States whose decisions it runs with `Call`
+    pub calls: Vec<String>,
+}
+
+/// Everything a CPU opponent needs to play a character
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedAIProfile {
+    /// AI state it starts in, if the character has one
+    pub entry: Option<String>,
+    /// States with AI decisions, sorted by name
+    pub states: Vec<AIStateProfile>,
+    /// `AI_WEIGHT_` entries of the `AI` specblock
+    pub weights: BTreeMap<String, i64>,
+    /// `AI_REACTION_` entries of the `AI` specblock, states in order
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+impl ParsedAIProfile {
+    pub fn state(&self, name: &str) -> Option<&AIStateProfile> {
+        self.states.iter().find(|state| state.name == name)
+    }
+}
+
+impl ParsedCharacter {
+    /// The AI states and `AI` specblock of the character
+    pub fn ai_profile(&self) -> Result<ParsedAIProfile, Vec<Diagnostic>> {
+        let mut states: Vec<&ParsedState> = self.states.values().collect();
+        states.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let states = states
+            .into_iter()
+            .filter_map(|state| self.ai_state(state))
+            .collect();
+
+        let mut reader = SpecReader::new(self, AI_SPECBLOCK);
+        let mut keys: Vec<&String> = reader.block.into_iter().flat_map(|b| b.keys()).collect();
+        keys.sort();
+        let mut weights = BTreeMap::new();
+        let mut reactions = BTreeMap::new();
+        for key in keys {
+            if let Some(decision) = key.strip_prefix(WEIGHT_PREFIX) {
+                if let Some(weight) = reader.int(key) {
+                    weights.insert(decision.to_string(), weight);
+                }
+            } else if let Some(situation) = key.strip_prefix(REACTION_PREFIX) {
+                let answers = reader.raw(key).unwrap_or_default().split(',');
+                let answers = answers
+                    .map(str::trim)
+                    .filter(|answer| !answer.is_empty())
+                    .map(str::to_string);
+                reactions.insert(situation.to_string(), answers.collect());
+            }
+        }
+
+        reader.finish(ParsedAIProfile {
+            entry: self
+                .states
+                .contains_key(AI_ENTRY_STATE)
+                .then(|| AI_ENTRY_STATE.to_string()),
+            states,
+            weights,
+            reactions,
+        })
+    }
+
+    /// Decisions of a state's `AI` phase, or of all the phases of an
+    /// `AI-` state; `None` if it decides nothing
+    fn ai_state(&self, state: &ParsedState) -> Option<AIStateProfile> {
+        let actions: Vec<&ParsedAction> = match state.actions.get(AI_PHASE) {
+            Some(actions) => actions.iter().collect(),
+            None if state.name.starts_with(AI_STATE_PREFIX) => {
+                let mut actions: Vec<_> = state.actions.values().flatten().collect();
+                actions.sort_by_key(|action| action.line_number);
+                actions
+            }
+            None => return None,
+        };
+
+        let mut decisions = Vec::new();
+        let mut calls = Vec::new();
+        // Chance of each open branch, `None` for the non-random ones
+        let mut branches: Vec<Option<u32>> = Vec::new();
+        for action in actions {
+            let instruction = &*action.instruction;
+            if instruction.eq_ignore_ascii_case("endif") {
+                branches.pop();
+                continue;
+            }
+            if instruction.eq_ignore_ascii_case("else") {
+                if let Some(branch) = branches.last_mut() {
+                    *branch = None;
+                }
+                continue;
+            }
+            if let Some(chance) = random_chance(instruction) {
+                // `R35` without a colon is the next choice of the open one
+                match instruction.ends_with(':') {
+                    true => branches.push(Some(chance)),
+                    false => {
+                        if let Some(branch) = branches.last_mut() {
+                            *branch = Some(chance);
+                        }
+                    }
+                }
+                continue;
+            }
+            if is_branch(instruction) {
+                branches.push(None);
+                continue;
+            }
+
+            if let Some(kind) = AIDecisionKind::of_instruction(instruction) {
+                let Some(target) = action.args.first() else {
+                    continue;
+                };
+                decisions.push(AIDecision {
+                    kind,
+                    target: self.timeline_name(target),
+                    chance: branches.iter().rev().find_map(|chance| *chance),
+                    line: action.line_number,
+                });
+            } else if let Some(called) = self.called_state(&state.name, action) {
+                calls.push(called);
+            }
+        }
+
+        let is_ai = state.name.starts_with(AI_STATE_PREFIX) || !decisions.is_empty();
+        is_ai.then(|| AIStateProfile {
+            name: state.name.to_string(),
+            decisions,
+            calls,
+        })
+    }
+}
+
+/// Percent chance of a random branch, `R50:` or `R50`
+fn random_chance(instruction: &str) -> Option<u32> {
+    let digits = instruction.strip_prefix('R')?;
+    let digits = digits.strip_suffix(':').unwrap_or(digits);
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_ai_profile() {
+        let source = ":Character:
+Name: Ryu
+:AI:
+AI_WEIGHT_Fireball: 30
+AI_WEIGHT_Jump: 10
+AI_REACTION_Jumped: 623C, 5B
+:AI-Init:
+---Action:
+Call(AI-Neutral)
+:AI-Neutral:
+---AI:
+F1%38:
+R50:
+InputPress(Right)
+R25
+AIInputTransition(Fireball)
+endif
+endif
+LClose:
+AITransition(AI-Pressure)
+endif
+:AI-Pressure:
+---AI:
+AIAttackCancelOnHit(Hadoken)
+:5B:
+---AI:
+AIAttackCancelOnTouch(2B)
+---Action:
+Stop
+:Idle:
+---Init:
+Stop
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        let profile = character.ai_profile().unwrap();
+
+        assert_eq!(profile.entry.as_deref(), Some("AI-Init"));
+        let names: Vec<&str> = profile.states.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["5B", "AI-Init", "AI-Neutral", "AI-Pressure"]);
+        assert_eq!(profile.state("AI-Init").unwrap().calls, vec!["AI-Neutral"]);
+
+        let neutral: Vec<(AIDecisionKind, &str, Option<u32>)> = profile
+            .state("AI-Neutral")
+            .unwrap()
+            .decisions
+            .iter()
+            .map(|d| (d.kind, d.target.as_str(), d.chance))
+            .collect();
+        assert_eq!(
+            neutral,
+            vec![
+                (AIDecisionKind::Press, "Right", Some(50)),
+                (AIDecisionKind::InputTransition, "Fireball", Some(25)),
+                (AIDecisionKind::Transition, "AI-Pressure", None),
+            ]
+        );
+        assert_eq!(
+            profile.state("5B").unwrap().decisions[0].kind,
+            AIDecisionKind::CancelOnTouch
+        );
+
+        assert_eq!(profile.weights["Fireball"], 30);
+        assert_eq!(profile.reactions["Jumped"], vec!["623C", "5B"]);
+
+        let broken = CastagneParser::new()
+            .create_full_character_from_source("ryu.casp", ":AI:\nAI_WEIGHT_Jump: often\n")
+            .unwrap();
+        assert!(broken.ai_profile().is_err());
+    }
+}
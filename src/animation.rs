@@ -0,0 +1,340 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Animation - What a state shows on each of its frames
+//!
+//! The sprite previewer plays a state frame by frame. `animation_timeline`
+//! runs the `Init` phase of a state, then its `Action` phase once per frame,
+//! following frame branches (`F5:`) and calls, and applies the animation
+//! instructions the way the Graphics module does: `Anim`, `AnimFrame`,
+//! `AnimProgress` and `AnimLoop` for animations, `Sprite` and
+//! `SpriteProgress` for spritesheet frames.
+//!
+//! Names are looked up in the defines and the `Anims` and `Graphics`
+//! specblocks, so `Anim(ANIM_Movement_Basic_Stand)` gives the animation the
+//! character sets up. Other branches are assumed taken and their `else`
+//! parts skipped.
+
+use crate::frame_data::{is_branch, FrameRange};
+use crate::parser::ParsedCharacter;
+use crate::string_literal;
+
+/// Longest timeline built, for states that never end
+pub const MAX_TIMELINE_FRAMES: usize = 600;
+
+/// Specblocks holding the animation names
+const ANIMATION_SPECBLOCKS: &[&str] = &["Anims", "Graphics"];
+
+/// What a state shows on one frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimelineFrame {
+    /// Frame of the state, 1 being the first
+    pub frame: usize,
+    /// Animation playing, `None` when showing a spritesheet frame
+    pub animation: Option<String>,
+    pub animation_frame: i64,
+    /// Spritesheet last selected with `Sprite`, `None` for the default one
+    pub spritesheet: Option<String>,
+    pub sprite: i64,
+}
+
+/// Frames of a state as the sprite previewer plays them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationTimeline {
+    pub state: String,
+    pub frames: Vec<TimelineFrame>,
+}
+
+impl AnimationTimeline {
+    /// What the state shows on a frame, 1 being the first
+    pub fn at(&self, frame: usize) -> Option<&TimelineFrame> {
+        self.frames.get(frame.checked_sub(1)?)
+    }
+}
+
+/// Graphics variables of the entity while the state plays
+#[derive(Debug, Clone, Default)]
+struct Playback {
+    shown: TimelineFrame,
+    /// Name and offset of the last `Anim` call, which keeps advancing
+    /// the animation while it is called again the same way
+    anim_call: Option<(String, i64)>,
+}
+
+/// An open branch of a phase
+struct Branch {
+    active: bool,
+    taken: bool,
+}
+
+impl ParsedCharacter {
+    /// Length of a state in frames: its `AttackDuration`, else the last
+    /// frame a frame branch of its `Action` phase names, else 1
+    pub fn state_length(&self, state: &str) -> usize {
+        let Some(state) = self.states.get(state) else {
+            return 1;
+        };
+        let duration = state
+            .actions
+            .values()
+            .flatten()
+            .filter(|action| &*action.instruction == "AttackDuration")
+            .find_map(|action| self.timeline_int(action.args.first()?));
+        if let Some(duration) = duration {
+            return duration.max(1) as usize;
+        }
+        let actions = state.actions.get("Action").into_iter().flatten();
+        actions
+            .filter_map(|action| FrameRange::of_branch(self, &action.instruction)?)
+            .map(|range| range.end.unwrap_or(range.start))
+            .max()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// What a state shows on each frame, for `frames` frames or its
+    /// `state_length`, up to `MAX_TIMELINE_FRAMES`
+    pub fn animation_timeline(
+        &self,
+        state: &str,
+        frames: Option<usize>,
+    ) -> Option<AnimationTimeline> {
+        self.states.get(state)?;
+        let length = frames.unwrap_or_else(|| self.state_length(state));
+        let mut playback = Playback::default();
+        self.play_phase(
+            state,
+            "Init",
+            0,
+            &mut playback,
+            &mut vec![state.to_string()],
+        );
+
+        let frames = (1..=length.clamp(1, MAX_TIMELINE_FRAMES))
+            .map(|frame| {
+                let mut calls = vec![state.to_string()];
+                self.play_phase(state, "Action", frame, &mut playback, &mut calls);
+                TimelineFrame {
+                    frame,
+                    ..playback.shown.clone()
+                }
+            })
+            .collect();
+        Some(AnimationTimeline {
+            state: state.to_string(),
+            frames,
+        })
+    }
+
+    /// Apply the animation instructions of a phase of `state` on a frame
+    fn play_phase(
+        &self,
+        state: &str,
+        phase: &str,
+        frame: usize,
+        playback: &mut Playback,
+        calls: &mut Vec<String>,
+    ) {
+        let Some(actions) = self
+            .states
+            .get(state)
+            .and_then(|state| state.actions.get(phase))
+        else {
+            return;
+        };
+
+        let mut branches: Vec<Branch> = Vec::new();
+        for action in actions {
+            let instruction = &*action.instruction;
+            let active = branches.last().is_none_or(|branch| branch.active);
+            if instruction.eq_ignore_ascii_case("endif") {
+                branches.pop();
+                continue;
+            }
+            if instruction.eq_ignore_ascii_case("else") {
+                let outer = branches
+                    .len()
+                    .checked_sub(2)
+                    .map(|outer| branches[outer].active);
+                if let Some(branch) = branches.last_mut() {
+                    branch.active = outer.unwrap_or(true) && !branch.taken;
+                }
+                continue;
+            }
+            if is_branch(instruction) {
+                let taken = match FrameRange::of_branch(self, instruction) {
+                    Some(Some(range)) => range.contains(frame),
+                    _ => true,
+                };
+                branches.push(Branch {
+                    active: active && taken,
+                    taken,
+                });
+                continue;
+            }
+            if !active {
+                continue;
+            }
+
+            let text = |index: usize| action.args.get(index).map(|arg| self.timeline_name(arg));
+            let int = |index: usize| {
+                action
+                    .args
+                    .get(index)
+                    .and_then(|arg| self.timeline_int(arg))
+            };
+            let shown = &mut playback.shown;
+            match instruction {
+                "Anim" => {
+                    let (Some(name), offset) = (text(0), int(1).unwrap_or(0)) else {
+                        continue;
+                    };
+                    let call = Some((name.clone(), offset));
+                    if playback.anim_call == call {
+                        shown.animation_frame += 1;
+                    } else {
+                        playback.anim_call = call;
+                        shown.animation = Some(name);
+                        shown.animation_frame = frame as i64 - offset;
+                    }
+                }
+                "AnimFrame" => {
+                    let (Some(name), Some(animation_frame)) = (text(0), int(1)) else {
+                        continue;
+                    };
+                    shown.animation = Some(name);
+                    shown.animation_frame = animation_frame;
+                    playback.anim_call = None;
+                }
+                "AnimProgress" => shown.animation_frame += int(0).unwrap_or(1),
+                "AnimLoop" => {
+                    let Some(end) = int(0) else {
+                        continue;
+                    };
+                    let start = int(1).unwrap_or(1);
+                    let span = (end - start).max(1);
+                    if shown.animation_frame >= end {
+                        shown.animation_frame =
+                            (shown.animation_frame - start).rem_euclid(span) + start;
+                    }
+                }
+                "Sprite" => {
+                    shown.animation = None;
+                    match action.args.len() {
+                        1 => shown.sprite = int(0).unwrap_or(shown.sprite),
+                        _ => {
+                            shown.spritesheet = text(0);
+                            shown.sprite = int(1).unwrap_or(0);
+                        }
+                    }
+                }
+                "SpriteProgress" => {
+                    shown.animation = None;
+                    shown.sprite += int(0).unwrap_or(1);
+                }
+                _ => {
+                    if let Some(called) = self.called_state(state, action) {
+                        if !calls.contains(&called) {
+                            calls.push(called.clone());
+                            self.play_phase(&called, phase, frame, playback, calls);
+                            calls.pop();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Value of an animation argument: a string, or a define or specblock
+    /// entry replaced by its value
+    pub(crate) fn timeline_name(&self, arg: &str) -> String {
+        let arg = arg.trim();
+        if let Some(Ok(decoded)) = string_literal::decode(arg) {
+            return decoded;
+        }
+        if let Some(variable) = self.variables.get(arg) {
+            return variable.value.clone();
+        }
+        ANIMATION_SPECBLOCKS
+            .iter()
+            .find_map(|block| self.specblocks.get(*block)?.get(arg))
+            .cloned()
+            .unwrap_or_else(|| arg.to_string())
+    }
+
+    pub(crate) fn timeline_int(&self, arg: &str) -> Option<i64> {
+        self.timeline_name(arg).trim().parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_animation_timeline() {
+        let source = ":Character:
+Name: Ryu
+:Anims:
+ANIM_Attacks_Light: Jab
+ANIM_Movement_Basic_Stand: Stand
+:Variables:
+def JabOffset: 1
+:Jab:
+---Init:
+AttackDuration(6)
+---Action:
+Anim(ANIM_Attacks_Light, JabOffset)
+F4+:
+AnimLoop(4, 2)
+endif
+:Idle:
+---Action:
+Call(IdleGraphics)
+:IdleGraphics(Helper):
+---Action:
+F1-2:
+Sprite(Idle, 3)
+else
+SpriteProgress()
+endif
+F5:
+AnimFrame(ANIM_Movement_Basic_Stand, 7)
+endif
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+
+        let jab = character.animation_timeline("Jab", None).unwrap();
+        let frames: Vec<(Option<&str>, i64)> = jab
+            .frames
+            .iter()
+            .map(|frame| (frame.animation.as_deref(), frame.animation_frame))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                (Some("Jab"), 0),
+                (Some("Jab"), 1),
+                (Some("Jab"), 2),
+                (Some("Jab"), 3),
+                (Some("Jab"), 2),
+                (Some("Jab"), 3)
+            ]
+        );
+
+        assert_eq!(character.state_length("Idle"), 1);
+        let idle = character.animation_timeline("Idle", Some(5)).unwrap();
+        let sprites: Vec<i64> = idle.frames.iter().map(|frame| frame.sprite).collect();
+        assert_eq!(sprites, vec![3, 3, 4, 5, 6]);
+        assert_eq!(idle.at(1).unwrap().spritesheet.as_deref(), Some("Idle"));
+        let last = idle.at(5).unwrap();
+        assert_eq!(
+            (last.animation.as_deref(), last.animation_frame),
+            (Some("Stand"), 7)
+        );
+        assert!(character.animation_timeline("Missing", None).is_none());
+    }
+}
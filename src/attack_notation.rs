@@ -0,0 +1,126 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Attack Notation - Structured data from numpad attack names
+//!
+//! Castagne characters name their attacks in numpad notation: `5A`
+//! (standing A), `2B` (crouching B), `j.C` (air C), `236A` (quarter
+//! circle forward A), `j.214B`. States named this way get an
+//! `AttackNotation` so frame data tools and movelists can group attacks
+//! without re-parsing names. Other state names are left alone.
+
+use serde::{Deserialize, Serialize};
+
+/// Input read from an attack name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttackNotation {
+    /// Button letters, e.g. `A`, or `AB` for a two-button attack
+    pub button: String,
+    /// Numpad direction of the last input, 5 for neutral
+    pub direction: u8,
+    /// Full motion for specials, e.g. `236`
+    pub motion: Option<String>,
+    pub airborne: bool,
+}
+
+impl AttackNotation {
+    /// Parse a state name, `None` if it isn't in numpad notation
+    ///
+    /// Ground attacks need a direction (`5A`, not `A`) so that all-caps
+    /// state names are not mistaken for attacks. Air attacks may omit it.
+    pub fn parse(name: &str) -> Option<Self> {
+        let (airborne, rest) = match name.strip_prefix("j.") {
+            Some(rest) => (true, rest),
+            None => match name.strip_prefix('j') {
+                Some(rest) => (true, rest),
+                None => (false, name),
+            },
+        };
+
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (digits, button) = rest.split_at(digits_end);
+
+        if button.is_empty() || !button.chars().all(|c| c.is_ascii_uppercase()) {
+            return None;
+        }
+        if digits.contains('0') || (digits.is_empty() && !airborne) {
+            return None;
+        }
+
+        let direction = digits
+            .chars()
+            .last()
+            .and_then(|c| c.to_digit(10))
+            .unwrap_or(5) as u8;
+
+        Some(Self {
+            button: button.to_string(),
+            direction,
+            motion: (digits.len() > 1).then(|| digits.to_string()),
+            airborne,
+        })
+    }
+
+    /// Grounded attack with a down direction (1, 2 or 3)
+    pub fn is_crouching(&self) -> bool {
+        !self.airborne && (1..=3).contains(&self.direction)
+    }
+
+    /// Attack needing a motion input
+    pub fn is_special(&self) -> bool {
+        self.motion.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notation(
+        button: &str,
+        direction: u8,
+        motion: Option<&str>,
+        airborne: bool,
+    ) -> AttackNotation {
+        AttackNotation {
+            button: button.to_string(),
+            direction,
+            motion: motion.map(String::from),
+            airborne,
+        }
+    }
+
+    #[test]
+    fn test_parse_attack_names() {
+        let cases = vec![
+            ("5A", notation("A", 5, None, false)),
+            ("2B", notation("B", 2, None, false)),
+            ("6C", notation("C", 6, None, false)),
+            ("j.A", notation("A", 5, None, true)),
+            ("jB", notation("B", 5, None, true)),
+            ("j.2C", notation("C", 2, None, true)),
+            ("236A", notation("A", 6, Some("236"), false)),
+            ("j.214B", notation("B", 4, Some("214"), true)),
+            ("5AB", notation("AB", 5, None, false)),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(AttackNotation::parse(name), Some(expected), "{}", name);
+        }
+        assert!(AttackNotation::parse("2B").unwrap().is_crouching());
+        assert!(!AttackNotation::parse("j.2B").unwrap().is_crouching());
+        assert!(AttackNotation::parse("623C").unwrap().is_special());
+    }
+
+    #[test]
+    fn test_other_names_are_not_attacks() {
+        for name in [
+            "Idle", "Walk", "AI", "A", "5", "5a", "jump", "0A", "j.", "5A+B",
+        ] {
+            assert_eq!(AttackNotation::parse(name), None, "{}", name);
+        }
+    }
+}
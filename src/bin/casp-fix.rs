@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! casp-fix - Applies the fix-its of the diagnostics to .casp files
+//!
+//! `casp-fix [--legacy-syntax] [--flag NAME]... FILE...` parses each file
+//! and writes it back with the fixes of its own diagnostics applied, see
+//! `fixes::apply_file_fixes`. Files without fixes are left untouched.
+
+use castagne_rs::fixes::apply_file_fixes;
+use castagne_rs::parser::{CastagneParser, ParserConfig};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: casp-fix [--legacy-syntax] [--flag NAME]... FILE...";
+
+fn main() -> ExitCode {
+    let mut config = ParserConfig::default();
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--legacy-syntax" => config = config.with_legacy_syntax(true),
+            "--flag" => match args.next() {
+                Some(flag) => config = config.with_flag(&flag),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    for file in &files {
+        if let Err(e) = fix_file(file, &config) {
+            eprintln!("casp-fix: {}: {}", file, e);
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
+
+/// Write `file` back with the fixes of its diagnostics
+fn fix_file(file: &str, config: &ParserConfig) -> std::io::Result<()> {
+    let source = std::fs::read_to_string(file)?;
+    let mut parser = CastagneParser::with_config(config.clone());
+    // Files that fail to parse still get the fixes found so far
+    let _ = parser.create_full_character(file);
+    let fixed = apply_file_fixes(&source, file, parser.get_diagnostics());
+    if fixed != source {
+        std::fs::write(file, fixed)?;
+        println!("fixed {}", file);
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! casp-lsp - Language server for .casp files, spoken over stdin and stdout
+
+use castagne_rs::parser::ParserConfig;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match castagne_rs::lsp::run_stdio(ParserConfig::default()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("casp-lsp: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
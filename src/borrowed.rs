@@ -0,0 +1,633 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Borrowed - Zero-copy parse results
+//!
+//! Checking a whole roster in CI only needs to look at the parsed data, not
+//! keep it. `parse` splits a loaded source buffer into a `ParsedCharacterRef`
+//! whose names, values and arguments are slices of that buffer, so a parse
+//! allocates little more than its maps. Values are kept as written: string
+//! literals keep their quotes and escapes, and expression defaults aren't
+//! evaluated. `into_owned` turns the result into the `ParsedCharacter` the
+//! full parser would have produced, without a `metadata.filepath` as a
+//! buffer has none.
+//!
+//! Only self-contained files can be parsed this way. Skeletons, includes,
+//! templates, conditional lines, block comments, multi-line strings and
+//! line continuations are rejected with an error naming the line; such
+//! files go through `CastagneParser`.
+
+use crate::attack_notation::AttackNotation;
+use crate::format_version::{FormatVersion, FORMAT_VERSION_FIELD};
+use crate::frame_data::scope_frames;
+use crate::intern::Interner;
+use crate::lazy_states::LazyStates;
+use crate::legacy::{self, LegacyKind};
+use crate::parser::{
+    looks_like_specblock, split_action, split_arguments, split_name_and_type, split_state_header,
+    split_variables_header, subentity_header, unbalanced_delimiter, validate_metadata_field,
+    CastagneParser, CharacterMetadata, ParsedAction, ParsedCharacter, ParsedState, ParsedVariable,
+    StateType, VariableMutability, VariableType, OVERRIDE_MARKER,
+};
+use crate::source_index::{SourceIndex, SourceRef};
+use crate::string_literal;
+use std::collections::HashMap;
+
+/// Character metadata borrowed from the source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CharacterMetadataRef<'src> {
+    pub name: &'src str,
+    pub author: &'src str,
+    pub description: &'src str,
+    pub other_fields: HashMap<&'src str, &'src str>,
+}
+
+/// Variable definition borrowed from the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedVariableRef<'src> {
+    pub name: &'src str,
+    pub mutability: VariableMutability,
+    pub var_type: VariableType,
+    pub subtype: &'src str,
+    /// Default as written
+    pub value: &'src str,
+    pub section: Option<&'src str>,
+}
+
+/// State borrowed from the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedStateRef<'src> {
+    pub name: &'src str,
+    pub state_type: StateType,
+    pub parent: Option<&'src str>,
+    /// Phases and their actions, in declaration order, see `Phases`
+    pub actions: Vec<(&'src str, Vec<ParsedActionRef<'src>>)>,
+    /// `##` comment lines between the header and the first phase
+    pub description: Vec<&'src str>,
+}
+
+impl<'src> ParsedStateRef<'src> {
+    /// Actions of the phase `name`
+    pub fn phase(&self, name: &str) -> Option<&[ParsedActionRef<'src>]> {
+        self.actions
+            .iter()
+            .find(|(phase, _)| *phase == name)
+            .map(|(_, actions)| actions.as_slice())
+    }
+
+    /// Actions of the phase `name`, declared after the others if new
+    fn phase_mut(&mut self, name: &'src str) -> &mut Vec<ParsedActionRef<'src>> {
+        let index = match self.actions.iter().position(|(phase, _)| *phase == name) {
+            Some(index) => index,
+            None => {
+                self.actions.push((name, Vec::new()));
+                self.actions.len() - 1
+            }
+        };
+        &mut self.actions[index].1
+    }
+}
+
+/// Action borrowed from the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedActionRef<'src> {
+    pub instruction: &'src str,
+    pub args: Vec<&'src str>,
+    /// 1-indexed line in the file the action was parsed from
+    pub line_number: usize,
+}
+
+/// Parsed character borrowing from its source buffer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedCharacterRef<'src> {
+    pub metadata: CharacterMetadataRef<'src>,
+    pub variables: HashMap<&'src str, ParsedVariableRef<'src>>,
+    /// Variables of subentities, from `:Entity---Variables:` blocks
+    pub entity_variables: HashMap<&'src str, HashMap<&'src str, ParsedVariableRef<'src>>>,
+    pub states: HashMap<&'src str, ParsedStateRef<'src>>,
+    pub specblocks: HashMap<&'src str, HashMap<&'src str, &'src str>>,
+}
+
+/// Block a source line belongs to
+enum Block<'src> {
+    None,
+    Character,
+    Variables(Option<&'src str>, Option<&'src str>),
+    Specblock(&'src str),
+    State(&'src str),
+}
+
+/// Parse a self-contained character file without copying its text
+pub fn parse(source: &str) -> Result<ParsedCharacterRef<'_>, String> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let lines: Vec<&str> = source.lines().map(str::trim).collect();
+    for (index, line) in lines.iter().enumerate() {
+        check_supported(line, index + 1)?;
+    }
+
+    let mut character = ParsedCharacterRef::default();
+    let mut block = Block::None;
+    let mut seen_character = false;
+    let mut phase: Option<&str> = None;
+    for (index, line) in lines.iter().copied().enumerate() {
+        let line_number = index + 1;
+
+        if line.starts_with(':') && line.ends_with(':') {
+            let header = line.get(1..line.len() - 1).unwrap_or_default();
+            let name = header.split('(').next().unwrap_or_default().trim();
+            phase = None;
+            block = if name == "Character" {
+                // Only the first block holds the metadata
+                match header == name && !std::mem::replace(&mut seen_character, true) {
+                    true => Block::Character,
+                    false => Block::None,
+                }
+            } else if let Some((entity, section)) = split_variables_header(header) {
+                Block::Variables(entity, section)
+            } else if subentity_header(name).is_some() {
+                return Err(unsupported("Subentity blocks", line_number));
+            } else if name.starts_with("Template ") {
+                return Err(unsupported("Templates", line_number));
+            } else if looks_like_specblock(lines[index + 1..].iter().copied()) {
+                Block::Specblock(name)
+            } else {
+                let (name, state_type, parent) = split_state_header(header);
+                if parent == Some(OVERRIDE_MARKER) {
+                    return Err(unsupported("Override markers", line_number));
+                }
+                character.states.insert(
+                    name,
+                    ParsedStateRef {
+                        name,
+                        state_type,
+                        parent,
+                        actions: Vec::new(),
+                        description: Vec::new(),
+                    },
+                );
+                Block::State(name)
+            };
+            continue;
+        }
+
+        if line.is_empty() || (line.starts_with('#') && !matches!(block, Block::State(_))) {
+            continue;
+        }
+        match block {
+            Block::None => {}
+            Block::Character => {
+                let Some((key, value)) = split_key_value(line) else {
+                    continue;
+                };
+                let metadata = &mut character.metadata;
+                match key {
+                    "Name" => metadata.name = value,
+                    "Author" => metadata.author = value,
+                    "Description" => metadata.description = value,
+                    _ if legacy::current_name(LegacyKind::MetadataKey, key).is_some() => {
+                        return Err(unsupported("Legacy metadata keys", line_number))
+                    }
+                    "Skeleton" | "Include" | "NoInherit" => {
+                        return Err(unsupported(&format!("{} files", key), line_number))
+                    }
+                    FORMAT_VERSION_FIELD
+                        if FormatVersion::parse(value) != Ok(FormatVersion::LATEST) =>
+                    {
+                        return Err(unsupported("Older format versions", line_number))
+                    }
+                    _ => {
+                        if let Err(message) = validate_metadata_field(key, &decode(value)) {
+                            return Err(format!("{} (line {})", message, line_number));
+                        }
+                        metadata.other_fields.insert(key, value);
+                    }
+                }
+            }
+            Block::Variables(entity, section) => {
+                let Some(mut var) = parse_variable(strip_comment(line).trim(), line_number)? else {
+                    continue;
+                };
+                var.section = section;
+                let variables = match entity {
+                    Some(entity) => character.entity_variables.entry(entity).or_default(),
+                    None => &mut character.variables,
+                };
+                variables.insert(var.name, var);
+            }
+            Block::Specblock(name) => {
+                if let Some((key, value)) = split_key_value(line) {
+                    character
+                        .specblocks
+                        .entry(name)
+                        .or_default()
+                        .insert(key, value);
+                }
+            }
+            Block::State(name) => {
+                let Some(state) = character.states.get_mut(name) else {
+                    continue;
+                };
+                if let Some(marker) = line.strip_prefix("---") {
+                    if let Some((phase_name, _)) = marker.split_once(':') {
+                        let phase_name = phase_name.trim();
+                        if legacy::current_name(LegacyKind::Phase, phase_name).is_some() {
+                            return Err(unsupported("Legacy phase names", line_number));
+                        }
+                        state.phase_mut(phase_name);
+                        phase = Some(phase_name);
+                    }
+                } else if phase.is_none() && line.starts_with("##") {
+                    state.description.push(line.trim_start_matches('#').trim());
+                } else if let Some(phase) = phase.filter(|_| !line.starts_with('#')) {
+                    let cleaned = strip_comment(line).trim();
+                    if cleaned.is_empty() {
+                        continue;
+                    }
+                    if let Some((column, message)) = unbalanced_delimiter(cleaned) {
+                        return Err(format!(
+                            "{} (line {}, column {})",
+                            message,
+                            line_number,
+                            column + 1
+                        ));
+                    }
+                    let Some((instruction, args)) = split_action(cleaned) else {
+                        continue;
+                    };
+                    if instruction == "UseTemplate" {
+                        return Err(unsupported("Templates", line_number));
+                    }
+                    if legacy::current_name(LegacyKind::Instruction, instruction).is_some() {
+                        return Err(unsupported("Legacy instruction names", line_number));
+                    }
+                    state.phase_mut(phase).push(ParsedActionRef {
+                        instruction,
+                        args: split_arguments(args),
+                        line_number,
+                    });
+                }
+            }
+        }
+    }
+    Ok(character)
+}
+
+impl ParsedCharacterRef<'_> {
+    /// Copy into the character the full parser would produce
+    ///
+    /// String literals are decoded and expression defaults evaluated; a
+    /// value that doesn't decode or evaluate is kept as written. The
+    /// result has no source index.
+    pub fn into_owned(self) -> ParsedCharacter {
+        self.into_owned_with(&Interner::new())
+    }
+
+    /// `into_owned`, interning names into a shared interner
+    pub fn into_owned_with(self, interner: &Interner) -> ParsedCharacter {
+        let owned_variables = |variables: HashMap<&str, ParsedVariableRef>| {
+            variables
+                .into_values()
+                .map(|var| {
+                    let value = match var.var_type {
+                        VariableType::Str => decode(var.value),
+                        _ => var.value.to_string(),
+                    };
+                    let var = ParsedVariable {
+                        name: interner.intern(var.name),
+                        mutability: var.mutability,
+                        var_type: var.var_type,
+                        subtype: var.subtype.to_string(),
+                        value,
+                        section: var.section.map(str::to_string),
+                        expression: None,
+                        origin: SourceRef::default(),
+                    };
+                    (var.name.clone(), var)
+                })
+                .collect()
+        };
+
+        let states = self
+            .states
+            .into_values()
+            .map(|state| {
+                let name = interner.intern(state.name);
+                let actions = state
+                    .actions
+                    .into_iter()
+                    .map(|(phase, actions)| {
+                        let actions = actions
+                            .into_iter()
+                            .map(|action| ParsedAction {
+                                instruction: interner.intern(action.instruction),
+                                args: action.args.into_iter().map(str::to_string).collect(),
+                                line_number: action.line_number,
+                                frames: Vec::new(),
+                                defaulted: Vec::new(),
+                                flags: Vec::new(),
+                            })
+                            .collect();
+                        (interner.intern(phase), actions)
+                    })
+                    .collect();
+                let state = ParsedState {
+                    attack: AttackNotation::parse(&name),
+                    name: name.clone(),
+                    state_type: state.state_type,
+                    parent: state.parent.map(str::to_string),
+                    actions,
+                    description: (!state.description.is_empty())
+                        .then(|| state.description.join("\n")),
+                    origin: SourceRef::default(),
+                    locals: Vec::new(),
+                };
+                (name, state)
+            })
+            .collect();
+
+        let metadata = self.metadata;
+        let mut character = ParsedCharacter {
+            metadata: CharacterMetadata {
+                name: decode(metadata.name),
+                author: decode(metadata.author),
+                description: decode(metadata.description),
+                skeleton: None,
+                includes: Vec::new(),
+                no_inherit: Vec::new(),
+                ..CharacterMetadata::default()
+            },
+            variables: owned_variables(self.variables),
+            entity_variables: self
+                .entity_variables
+                .into_iter()
+                .map(|(entity, variables)| (entity.to_string(), owned_variables(variables)))
+                .collect(),
+            states,
+            specblocks: self
+                .specblocks
+                .into_iter()
+                .map(|(name, block)| {
+                    let block = block
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect();
+                    (name.to_string(), block)
+                })
+                .collect(),
+            specblock_origins: HashMap::new(),
+            subentities: HashMap::new(),
+            transformed_data: HashMap::new(),
+            templates: HashMap::new(),
+            generic_states: HashMap::new(),
+            source_index: SourceIndex::new(),
+            overrides: Vec::new(),
+            lazy_states: LazyStates::default(),
+        };
+        for (key, value) in metadata.other_fields {
+            // Checked by `parse` already
+            let _ = character.metadata.set_field(key, decode(value));
+        }
+        CastagneParser::new().evaluate_character_defaults(&mut character);
+        for state in character.states.values_mut() {
+            scope_frames(&character.variables, state);
+        }
+        character
+    }
+}
+
+/// Reject the syntax only `CastagneParser` understands
+fn check_supported(line: &str, line_number: usize) -> Result<(), String> {
+    let feature = if line.starts_with('?') {
+        "Conditional lines"
+    } else if line.contains("\"\"\"") {
+        "Multi-line strings"
+    } else if line.contains("/*") {
+        "Block comments"
+    } else if line.starts_with("let ") {
+        "State locals"
+    } else if line.ends_with('\\') {
+        "Line continuations"
+    } else if line.contains('\r') {
+        "Carriage return line endings"
+    } else {
+        return Ok(());
+    };
+    Err(unsupported(feature, line_number))
+}
+
+fn unsupported(feature: &str, line_number: usize) -> String {
+    format!("{} need the full parser (line {})", feature, line_number)
+}
+
+/// The line up to a `#` comment outside strings
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escape_next = false;
+    for (pos, ch) in line.char_indices() {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_string => escape_next = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..pos],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// `Key: Value`, without its comment
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = strip_comment(line).split_once(':')?;
+    Some((key.trim(), value.trim()))
+}
+
+/// A `var`, `def` or `internal` declaration, `None` if malformed
+fn parse_variable(line: &str, line_number: usize) -> Result<Option<ParsedVariableRef<'_>>, String> {
+    let (mutability, rest) = if let Some(rest) = line.strip_prefix("var ") {
+        (VariableMutability::Variable, rest)
+    } else if let Some(rest) = line.strip_prefix("def ") {
+        (VariableMutability::Define, rest)
+    } else if let Some(rest) = line.strip_prefix("internal ") {
+        if rest.contains(':') {
+            return Err(format!(
+                "Can't assign an internal variable (line {})",
+                line_number
+            ));
+        }
+        if !rest.contains('(') {
+            return Err(unsupported("Untyped internal variables", line_number));
+        }
+        (VariableMutability::Internal, rest)
+    } else {
+        return Ok(None);
+    };
+
+    let (declaration, value) = match mutability {
+        VariableMutability::Internal => (rest, ""),
+        _ => match rest.split_once(':') {
+            Some((declaration, value)) => (declaration.trim(), value.trim()),
+            None => return Ok(None),
+        },
+    };
+    let (name, var_type, subtype) = match mutability {
+        // Defines can be any type
+        VariableMutability::Define => (declaration, VariableType::Var, ""),
+        _ => match split_name_and_type(declaration) {
+            Some((name, type_str, subtype)) => (
+                name,
+                VariableType::from_name(type_str).unwrap_or(VariableType::Var),
+                subtype,
+            ),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(ParsedVariableRef {
+        name,
+        mutability,
+        var_type,
+        subtype,
+        value,
+        section: None,
+    }))
+}
+
+/// Decoded string literal, or the text as written
+fn decode(text: &str) -> String {
+    match string_literal::decode(text) {
+        Some(Ok(decoded)) => decoded,
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\u{feff}:Character:\r
+Name: \"Ryu\\tKen\"\r
+Author: Someone # comment\r
+EditorName: Shoto\r
+\r
+:Variables:\r
+var Health(Int): 1000\r
+var Speed(Vec2): Vec2(2, 3) * 2\r
+var Quote(Str): \"Hadou\\nken\"\r
+def MaxJumps: 2\r
+internal Timer(Int)\r
+\r
+:Fireball---Variables-Motion:\r
+var Speed(Int): 5 * 2\r
+\r
+:PhysicsSystem:\r
+Gravity: -1 # down\r
+Friction: 0.5\r
+\r
+:5A(Normal):\r
+## Quick jab\r
+## Cancels into specials\r
+---Init:\r
+Set(\"Label\", \"a#b\") # comment\r
+Attack(10, Damage(5, 2))\r
+# Skipped\r
+---Action:\r
+Stop\r
+:Idle(BaseState, Stand):\r
+---Init:\r
+Anim(Idle)\r
+";
+
+    #[test]
+    fn test_parse_borrows_from_source() {
+        let character = parse(SAMPLE).unwrap();
+        let range = SAMPLE.as_bytes().as_ptr_range();
+        let borrowed = |text: &str| range.contains(&text.as_ptr());
+
+        assert_eq!(character.metadata.name, "\"Ryu\\tKen\"");
+        assert_eq!(character.metadata.author, "Someone");
+        assert_eq!(character.metadata.other_fields["EditorName"], "Shoto");
+        assert!(borrowed(character.metadata.name));
+
+        let speed = &character.variables["Speed"];
+        assert_eq!(speed.value, "Vec2(2, 3) * 2");
+        assert!(borrowed(speed.value) && borrowed(speed.name));
+        assert_eq!(
+            character.variables["Timer"].mutability,
+            VariableMutability::Internal
+        );
+        assert_eq!(
+            character.entity_variables["Fireball"]["Speed"].section,
+            Some("Motion")
+        );
+        assert_eq!(character.specblocks["PhysicsSystem"]["Gravity"], "-1");
+
+        let jab = &character.states["5A"];
+        assert_eq!(jab.description, vec!["Quick jab", "Cancels into specials"]);
+        let init = jab.phase("Init").unwrap();
+        assert_eq!(init[0].args, vec!["\"Label\"", "\"a#b\""]);
+        assert_eq!(init[1].args, vec!["10", "Damage(5, 2)"]);
+        assert_eq!(init[1].line_number, 25);
+        assert!(init[1].args.iter().all(|arg| borrowed(arg)));
+        assert_eq!(jab.phase("Action").unwrap()[0].instruction, "Stop");
+        assert_eq!(character.states["Idle"].parent, Some("Stand"));
+    }
+
+    #[test]
+    fn test_into_owned_matches_full_parser() {
+        let mut owned = parse(SAMPLE).unwrap().into_owned();
+        let full = CastagneParser::new()
+            .create_full_character_from_source("sample.casp", SAMPLE)
+            .unwrap();
+
+        assert_eq!(owned.metadata.name, "Ryu\tKen");
+        assert_eq!(owned.metadata.editor_name.as_deref(), Some("Shoto"));
+        // A source parsed alone has no path
+        assert_eq!(owned.metadata.filepath, "");
+        owned.metadata.filepath = "sample.casp".to_string();
+        assert_eq!(owned.variables["Speed"].value, "(4, 6)");
+        assert_eq!(
+            owned.to_json_value().unwrap(),
+            full.to_json_value().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_full_parser_features_are_rejected() {
+        let cases = [
+            (":Character:\nSkeleton: base.casp", "Skeleton files", 2),
+            (":Character:\nInclude: a.casp", "Include files", 2),
+            (":Template Hit(X):\nAttack(X)", "Templates", 1),
+            (":Idle:\n---Init:\nUseTemplate(Hit, 1)", "Templates", 3),
+            (":Idle:\n---Init:\n?Debug Log(1)", "Conditional lines", 3),
+            (
+                ":Character:\nName: \"\"\"\nA\n\"\"\"",
+                "Multi-line strings",
+                2,
+            ),
+            (":Idle:\n---Init:\n/* off */", "Block comments", 3),
+            (":Idle:\n---Init:\nSet(A, \\\n1)", "Line continuations", 3),
+        ];
+        for (source, feature, line) in cases {
+            assert_eq!(
+                parse(source).unwrap_err(),
+                format!("{} need the full parser (line {})", feature, line)
+            );
+        }
+
+        assert_eq!(
+            parse(":Variables:\ninternal Timer(Int): 3").unwrap_err(),
+            "Can't assign an internal variable (line 2)"
+        );
+        assert_eq!(
+            parse(":Idle:\n---Init:\nAttack(1, (2)").unwrap_err(),
+            "Unclosed '(' (line 3, column 7)"
+        );
+        assert_eq!(
+            parse(":Character:\nPaletteCount: many").unwrap_err(),
+            "PaletteCount should be a positive integer, got \"many\" (line 2)"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Character Resource - Compiled characters as Godot resources
+//!
+//! `CastagneCharacterResource` holds a parsed character as exported
+//! properties, so it can be saved as a `.tres`, preloaded, referenced from
+//! scenes and browsed in the inspector. The full parse result is kept as
+//! JSON; the name, states, variables and specblocks are also split out for
+//! the inspector and for scripts that only need a summary.
+
+use crate::parser::{CastagneParser, JsonFormat, ParsedCharacter, ParserConfig};
+use crate::pooling::{pooling_hints, SpawnCount};
+use crate::training::move_properties;
+use godot::classes::{ProjectSettings, Resource};
+use godot::prelude::*;
+
+/// Parse a character file, returning the parser's errors on failure
+pub(crate) fn compile_character(
+    path: &str,
+    config: &ParserConfig,
+) -> Result<ParsedCharacter, Vec<String>> {
+    let mut parser = CastagneParser::with_config(config.clone());
+    // The failure is also the last of the errors
+    parser
+        .create_full_character(path)
+        .map_err(|_| parser.errors)
+}
+
+/// A compiled .casp character, saveable as a Godot resource
+#[derive(GodotClass)]
+#[class(base=Resource, init)]
+pub struct CastagneCharacterResource {
+    base: Base<Resource>,
+    /// The .casp file it was compiled from
+    #[export]
+    source_path: GString,
+    #[export]
+    character_name: GString,
+    #[export]
+    author: GString,
+    #[export]
+    skeleton: GString,
+    /// Names of the states, sorted
+    #[export]
+    state_names: PackedStringArray,
+    /// Default value of each variable and define
+    #[export]
+    variables: VarDictionary,
+    /// Each specblock as a dictionary of its string values
+    #[export]
+    specblocks: VarDictionary,
+    /// Subentities and helper states each state may spawn, for pooling,
+    /// only for the states spawning something
+    #[export]
+    subentity_dependencies: VarDictionary,
+    /// Pool size to preallocate for each subentity and helper state it
+    /// spawns, -1 when it spawns them on every frame of a state
+    #[export]
+    pool_sizes: VarDictionary,
+    /// Palettes of the `Graphics` specblock as dictionaries with `name`,
+    /// `display_name`, `sprite_palette_path`, `model_path`, `extra` and
+    /// `colors`, in `PaletteApply` order
+    #[export]
+    palettes: VarArray,
+    /// Properties of each attack state for a training mode overlay, as
+    /// dictionaries with `input`, `attack_type`, `damage`, `chip_damage`,
+    /// `guard`, `cancels`, `cancel_types`, `meter_gain`, `duration`,
+    /// `advantage_hit` and `advantage_block`; unknown values are null
+    #[export]
+    training_data: VarDictionary,
+    /// Audio file of each declared sound cue, to preload its stream
+    #[export]
+    sound_files: VarDictionary,
+    /// States and transitions for an `AnimationNodeStateMachine`, see
+    /// `StateMachine::to_dictionary`
+    #[export]
+    state_machine: VarDictionary,
+    /// `ParsedCharacter::content_hash`, to tell whether a saved copy is stale
+    #[export]
+    content_hash: i64,
+    /// The full parse result, as written by `ParsedCharacter::to_json`
+    #[export(multiline)]
+    character_json: GString,
+    /// Errors of the last compile, empty when it succeeded
+    #[export]
+    errors: PackedStringArray,
+    /// The parse result, when compiled in this session
+    character: Option<ParsedCharacter>,
+}
+
+#[godot_api]
+impl CastagneCharacterResource {
+    /// Compile a .casp file into a new resource, see `compile_file`
+    #[func]
+    pub fn compile(path: GString) -> Gd<Self> {
+        let mut resource = Self::new_gd();
+        resource.bind_mut().compile_file(path);
+        resource
+    }
+
+    /// Compile a .casp file (`res://` paths included) into this resource,
+    /// returning whether it parsed
+    #[func]
+    pub fn compile_file(&mut self, path: GString) -> bool {
+        self.compile_with(&path.to_string(), &ParserConfig::default())
+    }
+
+    /// Whether the resource holds a character
+    #[func]
+    pub fn is_compiled(&self) -> bool {
+        self.errors.is_empty() && !self.character_json.is_empty()
+    }
+}
+
+impl CastagneCharacterResource {
+    /// `compile_file` with a parser configuration
+    pub fn compile_with(&mut self, path: &str, config: &ParserConfig) -> bool {
+        let file = match path.starts_with("res://") || path.starts_with("user://") {
+            true => ProjectSettings::singleton()
+                .globalize_path(path)
+                .to_string(),
+            false => path.to_string(),
+        };
+        let compiled = compile_character(&file, config);
+        self.source_path = GString::from(path);
+        match compiled {
+            Ok(character) => self.set_character(character),
+            Err(errors) => {
+                self.errors = errors.iter().map(|e| GString::from(e.as_str())).collect();
+                self.base_mut().emit_changed();
+            }
+        }
+        self.errors.is_empty()
+    }
+
+    /// Errors of the last compile
+    pub fn errors(&self) -> Vec<String> {
+        self.errors
+            .as_slice()
+            .iter()
+            .map(GString::to_string)
+            .collect()
+    }
+
+    /// Replace the content of the resource with a parsed character
+    pub fn set_character(&mut self, character: ParsedCharacter) {
+        let metadata = &character.metadata;
+        self.character_name = GString::from(metadata.name.as_str());
+        self.author = GString::from(metadata.author.as_str());
+        self.skeleton = GString::from(metadata.skeleton.as_deref().unwrap_or_default());
+
+        let mut states: Vec<&str> = character.states.keys().map(|name| name.as_str()).collect();
+        states.sort_unstable();
+        self.state_names = states.into_iter().map(GString::from).collect();
+
+        self.variables = VarDictionary::new();
+        for (name, variable) in &character.variables {
+            self.variables.set(name.as_str(), variable.to_variant());
+        }
+        self.specblocks = VarDictionary::new();
+        for (block, entries) in &character.specblocks {
+            let mut values = VarDictionary::new();
+            for (key, value) in entries {
+                values.set(key.as_str(), value.as_str());
+            }
+            self.specblocks.set(block.as_str(), values);
+        }
+
+        self.subentity_dependencies = VarDictionary::new();
+        for (state, dependencies) in character.subentity_dependency_map() {
+            let dependencies: PackedStringArray = dependencies
+                .iter()
+                .map(|name| GString::from(name.as_str()))
+                .collect();
+            self.subentity_dependencies
+                .set(state.as_str(), dependencies);
+        }
+
+        self.pool_sizes = VarDictionary::new();
+        for hint in pooling_hints(&character) {
+            let size = match hint.size {
+                SpawnCount::Bounded(size) => size as i64,
+                SpawnCount::Unbounded => -1,
+            };
+            self.pool_sizes.set(hint.entity.as_str(), size);
+        }
+
+        // Left empty when a palette has invalid values, see `palettes`
+        self.palettes = character
+            .palettes()
+            .unwrap_or_default()
+            .iter()
+            .map(|palette| {
+                let colors: PackedColorArray = palette
+                    .colors
+                    .iter()
+                    .map(|color| color.to_godot())
+                    .collect();
+                let mut row = VarDictionary::new();
+                row.set("name", palette.name.as_str());
+                row.set("display_name", palette.display_name.as_str());
+                row.set(
+                    "sprite_palette_path",
+                    palette.sprite_palette_path.as_deref().unwrap_or_default(),
+                );
+                row.set(
+                    "model_path",
+                    palette.model_path.as_deref().unwrap_or_default(),
+                );
+                row.set("extra", palette.extra);
+                row.set("colors", colors);
+                row.to_variant()
+            })
+            .collect();
+
+        self.training_data = VarDictionary::new();
+        for properties in move_properties(&character) {
+            let optional = |value: Option<i64>| value.map_or(Variant::nil(), |v| v.to_variant());
+            let names = |names: &[String]| -> PackedStringArray {
+                names
+                    .iter()
+                    .map(|name| GString::from(name.as_str()))
+                    .collect()
+            };
+            let mut row = VarDictionary::new();
+            row.set("input", properties.input.as_deref().unwrap_or_default());
+            row.set(
+                "attack_type",
+                properties.attack_type.as_deref().unwrap_or_default(),
+            );
+            row.set("damage", properties.damage);
+            row.set("chip_damage", properties.chip_damage);
+            row.set("guard", properties.guard.to_string().as_str());
+            row.set("cancels", names(&properties.cancels));
+            row.set("cancel_types", names(&properties.cancel_types));
+            row.set("meter_gain", optional(properties.meter_gain));
+            row.set("duration", optional(properties.duration));
+            row.set("advantage_hit", optional(properties.advantage_hit));
+            row.set("advantage_block", optional(properties.advantage_block));
+            self.training_data.set(properties.state.as_str(), row);
+        }
+
+        self.sound_files = VarDictionary::new();
+        for cue in character.sound_catalog() {
+            if let Some(path) = &cue.path {
+                self.sound_files.set(cue.name.as_str(), path.as_str());
+            }
+        }
+
+        self.state_machine = character.state_machine().to_dictionary();
+        self.content_hash = character.content_hash() as i64;
+        let json = character.to_json(JsonFormat::Internal).unwrap_or_default();
+        self.character_json = GString::from(json.as_str());
+        self.errors = PackedStringArray::new();
+        let name = self.character_name.clone();
+        self.base_mut().set_name(&name);
+        self.character = Some(character);
+        self.base_mut().emit_changed();
+    }
+
+    /// The parse result, only available when compiled in this session;
+    /// a loaded resource only has `character_json`
+    pub fn character(&self) -> Option<&ParsedCharacter> {
+        self.character.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_character() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ryu.casp");
+        std::fs::write(&path, ":Character:\nName: Ryu\n:Idle:\n---Init:\nStop\n").unwrap();
+        let character = compile_character(path.to_str().unwrap(), &ParserConfig::default());
+        assert_eq!(character.unwrap().metadata.name, "Ryu");
+
+        let missing = dir.path().join("missing.casp");
+        let errors =
+            compile_character(missing.to_str().unwrap(), &ParserConfig::default()).unwrap_err();
+        assert!(errors[0].contains("does not exist"));
+    }
+}
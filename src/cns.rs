@@ -0,0 +1,249 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! CNS - MUGEN-style summary of a character
+//!
+//! Teams porting a character from MUGEN or Ikemen compare it with the
+//! original state by state. `export_cns` writes the states of the
+//! `state_machine` as CNS `[Statedef]` blocks: the attack frame data as a
+//! `HitDef`, each transition as a `ChangeState` whose triggers are the
+//! Castagne branches it is under, as written. It is a summary to read next
+//! to the original `.cns`, not a file MUGEN can run.
+//!
+//! States get the numbers MUGEN conventionally uses where there is one:
+//! 0 for `Stand`, 200 and up for standing normals, 400 for crouching ones,
+//! 600 for air ones, 1000 for specials and 5000 for everything else.
+
+use crate::attack_notation::AttackNotation;
+use crate::frame_data::AttackData;
+use crate::parser::ParsedCharacter;
+use crate::state_machine::StateMachineTransition;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Conventional numbers of common states
+pub(crate) const COMMON_STATES: &[(&str, u32)] = &[
+    ("Stand", 0),
+    ("Crouch", 11),
+    ("Walk", 20),
+    ("Jump", 40),
+    ("Land", 52),
+];
+
+/// First number and step of each range of states
+const STANDING_ATTACKS: (u32, u32) = (200, 10);
+const CROUCHING_ATTACKS: (u32, u32) = (400, 10);
+const AIR_ATTACKS: (u32, u32) = (600, 10);
+const SPECIALS: (u32, u32) = (1000, 10);
+const OTHER_STATES: (u32, u32) = (5000, 1);
+
+/// A CNS-style summary of the states of a character
+pub fn export_cns(character: &ParsedCharacter) -> String {
+    let machine = character.state_machine();
+    let mut names: Vec<&str> = machine.nodes.iter().map(|n| n.name.as_str()).collect();
+    names.sort_unstable();
+    let numbers = state_numbers(character, &names);
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "; {} - summary exported from Castagne",
+        character.metadata.name
+    );
+    let _ = writeln!(
+        out,
+        "; Triggers are Castagne branches, not MUGEN expressions"
+    );
+    for name in names {
+        let state = &character.states[name];
+        let attack = AttackData::from_state(state);
+        let stance = state.attack.as_ref().map_or("U", stance);
+        let _ = writeln!(out, "\n; {}", name);
+        if let Some(description) = &state.description {
+            for line in description.lines() {
+                let _ = writeln!(out, "; {}", line);
+            }
+        }
+        let _ = writeln!(out, "[Statedef {}]", numbers[name]);
+        let _ = writeln!(out, "type = {}", stance);
+        let movetype = if attack.is_some() { "A" } else { "I" };
+        let _ = writeln!(out, "movetype = {}", movetype);
+        let _ = writeln!(out, "physics = {}", stance);
+
+        if let Some(attack) = attack {
+            write_hitdef(&mut out, numbers[name], &attack);
+        }
+        let transitions = machine.transitions.iter().filter(|t| t.from == name);
+        for transition in transitions {
+            write_change_state(
+                &mut out,
+                numbers[name],
+                numbers[&*transition.to],
+                transition,
+            );
+        }
+    }
+    out
+}
+
+/// MUGEN state number of each state
+fn state_numbers<'a>(character: &ParsedCharacter, names: &[&'a str]) -> BTreeMap<&'a str, u32> {
+    let mut next: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+    let mut numbers = BTreeMap::new();
+    for name in names {
+        if let Some((_, number)) = COMMON_STATES.iter().find(|(common, _)| common == name) {
+            numbers.insert(*name, *number);
+            continue;
+        }
+        let range = match &character.states[*name].attack {
+            Some(notation) if notation.is_special() => SPECIALS,
+            Some(notation) if notation.airborne => AIR_ATTACKS,
+            Some(notation) if notation.is_crouching() => CROUCHING_ATTACKS,
+            Some(_) => STANDING_ATTACKS,
+            None => OTHER_STATES,
+        };
+        let number = next.entry(range).or_insert(range.0);
+        numbers.insert(*name, *number);
+        *number += range.1;
+    }
+    numbers
+}
+
+/// State type of an attack: standing, crouching or air
+fn stance(notation: &AttackNotation) -> &'static str {
+    if notation.airborne {
+        "A"
+    } else if notation.is_crouching() {
+        "C"
+    } else {
+        "S"
+    }
+}
+
+fn write_hitdef(out: &mut String, number: u32, attack: &AttackData) {
+    let _ = writeln!(out, "\n[State {}, Attack]", number);
+    let _ = writeln!(out, "type = HitDef");
+    let _ = writeln!(out, "trigger1 = 1");
+    if let Some(damage) = &attack.damage {
+        let _ = writeln!(out, "damage = {}", damage);
+    }
+    let frame_data = [
+        ("attack type", &attack.attack_type),
+        ("input", &attack.input),
+        ("duration", &attack.duration),
+        ("advantage on hit", &attack.advantage_hit),
+        ("advantage on block", &attack.advantage_block),
+    ];
+    for (label, value) in frame_data {
+        if let Some(value) = value {
+            let _ = writeln!(out, "; {} = {}", label, value);
+        }
+    }
+}
+
+/// One `ChangeState`, each `Transition` to its target being a trigger
+/// number whose branches must all hold
+fn write_change_state(
+    out: &mut String,
+    number: u32,
+    target: u32,
+    transition: &StateMachineTransition,
+) {
+    let _ = writeln!(out, "\n[State {}, To {}]", number, transition.to);
+    let _ = writeln!(out, "type = ChangeState");
+    let mut alternatives: Vec<&Vec<String>> = Vec::new();
+    for conditions in &transition.conditions {
+        if !alternatives.contains(&conditions) {
+            alternatives.push(conditions);
+        }
+    }
+    for (index, conditions) in alternatives.into_iter().enumerate() {
+        if conditions.is_empty() {
+            let _ = writeln!(out, "trigger{} = 1", index + 1);
+        }
+        for condition in conditions {
+            let _ = writeln!(out, "trigger{} = {}", index + 1, condition);
+        }
+    }
+    let _ = writeln!(out, "value = {}", target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_export_cns() {
+        let source = ":Character:
+Name: Ryu
+:Stand:
+---Action:
+LAttack:
+F2:
+Transition(5A)
+endif
+endif
+LCrouch:
+Transition(Crouch)
+endif
+:Crouch:
+---Action:
+Transition(2B)
+:5A:
+## Quick jab
+---Init:
+AttackRegister(Light)
+AttackDamage(300)
+AttackDuration(18)
+AttackFrameAdvantage(3, -2)
+---Action:
+Transition(Stand)
+:2B:
+:5B:
+:j.C:
+:236C:
+:Intro:
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        let cns = export_cns(&character);
+
+        let numbers = state_numbers(
+            &character,
+            &["236C", "2B", "5A", "5B", "Crouch", "Intro", "Stand", "j.C"],
+        );
+        let number = |name: &str| numbers[name];
+        assert_eq!(
+            [
+                number("Stand"),
+                number("Crouch"),
+                number("5A"),
+                number("5B")
+            ],
+            [0, 11, 200, 210]
+        );
+        assert_eq!(
+            [number("2B"), number("j.C"), number("236C"), number("Intro")],
+            [400, 600, 1000, 5000]
+        );
+
+        assert!(cns.starts_with("; Ryu - summary exported from Castagne\n"));
+        assert!(cns.contains(
+            "\n; 5A\n; Quick jab\n[Statedef 200]\ntype = S\nmovetype = A\nphysics = S\n"
+        ));
+        assert!(cns.contains(
+            "[State 200, Attack]\ntype = HitDef\ntrigger1 = 1\ndamage = 300\n\
+             ; attack type = Light\n; input = 5A\n; duration = 18\n\
+             ; advantage on hit = 3\n; advantage on block = -2\n"
+        ));
+        assert!(cns.contains(
+            "[State 0, To 5A]\ntype = ChangeState\ntrigger1 = LAttack\ntrigger1 = F2\nvalue = 200\n"
+        ));
+        assert!(cns.contains("[State 11, To 2B]\ntype = ChangeState\ntrigger1 = 1\nvalue = 400\n"));
+        assert!(cns.contains("[Statedef 5000]\ntype = U\nmovetype = I\nphysics = U\n"));
+    }
+}
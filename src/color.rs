@@ -0,0 +1,170 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Color - Color values and lists of them
+//!
+//! `Color` variables and palette colors are written `#RGB`, `#RRGGBB`,
+//! `#RRGGBBAA`, `rgb(255, 0, 0)` or `rgba(255, 0, 0, 128)`, with 8-bit
+//! channels. A palette is a comma-separated list of such colors, so
+//! alternate color schemes can be written next to the sprites they recolor.
+//!
+//! `#` starts a comment, so hex colors are quoted: `"#FF8000"`, or
+//! `"#000, #FFF"` for a whole list.
+
+use crate::string_literal;
+use godot::builtin::Color;
+use std::fmt;
+
+/// An 8-bit RGBA color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Parse one color, `None` if `text` isn't one
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(decoded) = string_literal::decode(text) {
+            return Self::parse(&decoded.ok()?);
+        }
+        let text = text.trim();
+        if let Some(hex) = text.strip_prefix('#') {
+            return parse_hex(hex);
+        }
+        let (function, rest) = text.split_once('(')?;
+        let channels: Vec<u8> = rest
+            .strip_suffix(')')?
+            .split(',')
+            .map(|channel| channel.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match (function.trim().to_ascii_lowercase().as_str(), &*channels) {
+            ("rgb", &[r, g, b]) => Some(Self::new(r, g, b, 255)),
+            ("rgba", &[r, g, b, a]) => Some(Self::new(r, g, b, a)),
+            _ => None,
+        }
+    }
+
+    pub fn to_godot(self) -> Color {
+        Color::from_rgba8(self.r, self.g, self.b, self.a)
+    }
+}
+
+impl fmt::Display for Rgba {
+    /// `#RRGGBB`, or `#RRGGBBAA` when not opaque
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02X}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |index: usize, width: usize| {
+        let digits = &hex[index * width..(index + 1) * width];
+        let value = u8::from_str_radix(digits, 16).ok()?;
+        // `#F80` is `#FF8800`
+        Some(if width == 1 { value * 0x11 } else { value })
+    };
+    match hex.len() {
+        3 => Some(Rgba::new(
+            channel(0, 1)?,
+            channel(1, 1)?,
+            channel(2, 1)?,
+            255,
+        )),
+        6 => Some(Rgba::new(
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            255,
+        )),
+        8 => Some(Rgba::new(
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Parse a comma-separated list of colors, `None` if one isn't a color
+pub fn parse_color_list(text: &str) -> Option<Vec<Rgba>> {
+    if let Some(decoded) = string_literal::decode(text) {
+        return parse_color_list(&decoded.ok()?);
+    }
+    let mut colors = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                colors.push(Rgba::parse(&text[start..index])?);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() || !colors.is_empty() {
+        colors.push(Rgba::parse(last)?);
+    }
+    Some(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_colors() {
+        assert_eq!(Rgba::parse("#FF8000"), Some(Rgba::new(255, 128, 0, 255)));
+        assert_eq!(
+            Rgba::parse("\"#FF8000\""),
+            Some(Rgba::new(255, 128, 0, 255))
+        );
+        assert_eq!(Rgba::parse("#f80"), Some(Rgba::new(255, 136, 0, 255)));
+        assert_eq!(Rgba::parse("#10203040"), Some(Rgba::new(16, 32, 48, 64)));
+        assert_eq!(Rgba::parse(" rgb(1, 2, 3) "), Some(Rgba::new(1, 2, 3, 255)));
+        assert_eq!(Rgba::parse("RGBA(1,2,3,4)"), Some(Rgba::new(1, 2, 3, 4)));
+        assert_eq!(Rgba::parse("rgb(1, 2)"), None);
+        assert_eq!(Rgba::parse("rgb(256, 0, 0)"), None);
+        assert_eq!(Rgba::parse("#12345"), None);
+        assert_eq!(Rgba::parse("#+1+2+3"), None);
+        assert_eq!(Rgba::parse("red"), None);
+
+        assert_eq!(Rgba::new(255, 0, 16, 255).to_string(), "#FF0010");
+        assert_eq!(Rgba::new(255, 0, 16, 0).to_string(), "#FF001000");
+
+        assert_eq!(
+            parse_color_list("#000, rgb(255, 255, 255),#FF0000"),
+            Some(vec![
+                Rgba::new(0, 0, 0, 255),
+                Rgba::new(255, 255, 255, 255),
+                Rgba::new(255, 0, 0, 255)
+            ])
+        );
+        assert_eq!(
+            parse_color_list("\"#000, #0F0\""),
+            Some(vec![Rgba::new(0, 0, 0, 255), Rgba::new(0, 255, 0, 255)])
+        );
+        assert_eq!(parse_color_list(" "), Some(Vec::new()));
+        assert_eq!(parse_color_list("#000,"), None);
+        assert_eq!(parse_color_list("#000, blue"), None);
+    }
+}
@@ -0,0 +1,612 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Config - The engine configuration of a Castagne project
+//!
+//! `castagne-config.json` is a flat object of keys, as read by the engine's
+//! `CastagneConfig.gd`: `Modules` lists the modules to load, `Modules-*`
+//! keys name module lists, `CharacterPaths` the characters, and each
+//! module adds its own options, like the `InputLayout` of the input module
+//! or the arena size of the physics module. Keys the file leaves out take
+//! the default the engine's modules register.
+//!
+//! `CastagneConfig` keeps every key as JSON, so options this crate doesn't
+//! know about survive, and reads the ones the parser and tools use into
+//! typed structs. `CastagneRsConfig` gives Godot scripts the same view.
+
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+
+/// Name of the config file at the root of a project
+pub const CONFIG_FILE_NAME: &str = "castagne-config.json";
+/// Name of the per-user config file, overriding the project one
+pub const LOCAL_CONFIG_FILE_NAME: &str = "castagne-local-config.json";
+
+/// `PHYSICALINPUT_TYPES` of the engine, in the same order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PhysicalInputType {
+    Raw,
+    Button,
+    Axis,
+    Stick,
+    Combination,
+    Any,
+}
+
+impl PhysicalInputType {
+    fn from_index(index: i64) -> Option<Self> {
+        use PhysicalInputType::*;
+        [Raw, Button, Axis, Stick, Combination, Any]
+            .get(usize::try_from(index).ok()?)
+            .copied()
+    }
+}
+
+/// A physical input of an `InputLayout`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhysicalInput {
+    pub name: String,
+    pub input_type: PhysicalInputType,
+    /// Godot key codes, per default layout then per direction
+    pub keyboard_inputs: Vec<Vec<Vec<i64>>>,
+    /// Godot joypad buttons, per default layout then per direction
+    pub controller_inputs: Vec<Vec<Vec<i64>>>,
+    /// Names of the game inputs of a stick, empty for other types
+    pub game_input_names: Vec<String>,
+    /// `[input, direction]` pairs making up a combination
+    pub combination: Vec<(usize, usize)>,
+}
+
+impl PhysicalInput {
+    fn new(name: &str, input_type: PhysicalInputType) -> Self {
+        Self {
+            name: name.to_string(),
+            input_type,
+            keyboard_inputs: Vec::new(),
+            controller_inputs: Vec::new(),
+            game_input_names: Vec::new(),
+            combination: Vec::new(),
+        }
+    }
+
+    /// Read an entry of an `InputLayout`
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let name = value
+            .get("Name")
+            .and_then(Value::as_str)
+            .ok_or("Input layout entry without a Name")?;
+        let input_type = value
+            .get("Type")
+            .and_then(Value::as_i64)
+            .and_then(PhysicalInputType::from_index)
+            .ok_or_else(|| format!("Input {} has no valid Type", name))?;
+        let field = |key: &str| value.get(key).cloned().unwrap_or(Value::Array(Vec::new()));
+        let invalid = |key: &str| format!("Input {} has an invalid {}", name, key);
+        Ok(Self {
+            name: name.to_string(),
+            input_type,
+            keyboard_inputs: serde_json::from_value(field("KeyboardInputs"))
+                .map_err(|_| invalid("KeyboardInputs"))?,
+            controller_inputs: serde_json::from_value(field("ControllerInputs"))
+                .map_err(|_| invalid("ControllerInputs"))?,
+            game_input_names: serde_json::from_value(field("GameInputNames"))
+                .map_err(|_| invalid("GameInputNames"))?,
+            combination: serde_json::from_value(field("Combination"))
+                .map_err(|_| invalid("Combination"))?,
+        })
+    }
+
+    /// Names of the game inputs it provides
+    pub fn game_inputs(&self) -> Vec<&str> {
+        match self.input_type {
+            PhysicalInputType::Stick => self.game_input_names.iter().map(String::as_str).collect(),
+            _ => vec![self.name.as_str()],
+        }
+    }
+}
+
+/// Options of the input module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputConfig {
+    pub layout: Vec<PhysicalInput>,
+    pub menu_layout: Vec<PhysicalInput>,
+    pub keyboard_players: i64,
+    pub keyboard_layouts: i64,
+    pub controller_players: i64,
+    pub controller_layouts: i64,
+    pub motion_inputs: bool,
+}
+
+impl InputConfig {
+    /// Names notations can use for buttons: buttons, combinations and
+    /// `Any` inputs of the layout, in the order the engine reads them
+    pub fn buttons(&self) -> Vec<&str> {
+        use PhysicalInputType::*;
+        let of_type = |combined: bool| {
+            self.layout
+                .iter()
+                .filter(move |input| match input.input_type {
+                    Button => !combined,
+                    Combination | Any => combined,
+                    _ => false,
+                })
+                .map(|input| input.name.as_str())
+        };
+        of_type(false).chain(of_type(true)).collect()
+    }
+}
+
+/// Options of the physics module, arena sizes in engine units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhysicsConfig {
+    /// `ATTACK_CLASH_MODE`: 0 disabled, 1 trade priority, 2 clash priority
+    pub attack_clash_mode: i64,
+    pub attacks_can_hit_on_landing_hitstun_frame: bool,
+    pub use_fighting_arena: bool,
+    pub arena_size: i64,
+    pub arena_max_player_distance: i64,
+    pub buckets: i64,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            attack_clash_mode: 1,
+            attacks_can_hit_on_landing_hitstun_frame: false,
+            use_fighting_arena: true,
+            arena_size: 180000,
+            arena_max_player_distance: 75000,
+            buckets: 1,
+        }
+    }
+}
+
+/// The inputs of the engine's default `InputLayout`, without the key and
+/// button bindings
+pub fn default_input_layout() -> Vec<PhysicalInput> {
+    use PhysicalInputType::*;
+    let mut movement = PhysicalInput::new("Movement", Stick);
+    movement.game_input_names = [
+        "Left",
+        "Right",
+        "Down",
+        "Up",
+        "Back",
+        "Forward",
+        "Portside",
+        "Starboard",
+        "NeutralH",
+        "NeutralV",
+    ]
+    .map(String::from)
+    .to_vec();
+    let combination = |name: &str, input_type, combination: &[(usize, usize)]| {
+        let mut input = PhysicalInput::new(name, input_type);
+        input.combination = combination.to_vec();
+        input
+    };
+
+    let mut layout = vec![movement];
+    layout.extend(["L", "M", "H", "S", "E"].map(|name| PhysicalInput::new(name, Button)));
+    layout.push(combination("Throw", Combination, &[(1, 0), (2, 0)]));
+    layout.push(combination("Jump", Any, &[(0, 3)]));
+    layout.push(combination("Tech", Any, &[(1, 0), (2, 0), (3, 0)]));
+    layout.extend(
+        ["Pause", "Reset", "TrainingButton1", "TrainingButton2"]
+            .map(|name| PhysicalInput::new(name, Button)),
+    );
+    layout
+}
+
+/// The engine configuration of a project
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CastagneConfig {
+    values: Map<String, Value>,
+}
+
+impl CastagneConfig {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json =
+            fs::read_to_string(path).map_err(|e| format!("File {} cannot be read: {}", path, e))?;
+        Self::from_json(&json)
+    }
+
+    /// Load a project config, then the local config over it if there is one
+    pub fn load_with_local(path: &str, local_path: &str) -> Result<Self, String> {
+        let mut config = Self::load(path)?;
+        if fs::metadata(local_path).is_ok() {
+            config.merge(Self::load(local_path)?);
+        }
+        Ok(config)
+    }
+
+    /// Overwrite keys with those of `other`, like `FuseDataOverwrite`
+    pub fn merge(&mut self, other: CastagneConfig) {
+        self.values.extend(other.values);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: Value) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    /// Keys set by the file, sorted
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.values.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn int(&self, key: &str, default: i64) -> i64 {
+        self.get(key).and_then(Value::as_i64).unwrap_or(default)
+    }
+
+    fn bool(&self, key: &str, default: bool) -> bool {
+        self.get(key).and_then(Value::as_bool).unwrap_or(default)
+    }
+
+    /// A list key, written as an array or as a comma separated string
+    fn list(&self, key: &str) -> Vec<String> {
+        match self.get(key) {
+            Some(Value::String(list)) => split_list(list),
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Entries of `Modules`, module files or names of `Modules-*` lists
+    pub fn modules(&self) -> Vec<String> {
+        self.list("Modules")
+    }
+
+    /// Module files to load, with the `Modules-*` lists expanded
+    pub fn module_paths(&self) -> Result<Vec<String>, String> {
+        let mut paths = Vec::new();
+        self.expand_modules(&self.modules(), &mut Vec::new(), &mut paths)?;
+        Ok(paths)
+    }
+
+    fn expand_modules(
+        &self,
+        modules: &[String],
+        lists: &mut Vec<String>,
+        paths: &mut Vec<String>,
+    ) -> Result<(), String> {
+        for module in modules {
+            if module.ends_with(".tscn") || module.ends_with(".gd") {
+                paths.push(module.clone());
+                continue;
+            }
+            let key = format!("Modules-{}", module);
+            if lists.contains(&key) {
+                return Err(format!("Module list {} includes itself", module));
+            }
+            if self.get(&key).is_none() {
+                return Err(format!("Can't find module list to load: {}", module));
+            }
+            lists.push(key.clone());
+            self.expand_modules(&self.list(&key), lists, paths)?;
+            lists.pop();
+        }
+        Ok(())
+    }
+
+    pub fn character_paths(&self) -> Vec<String> {
+        self.list("CharacterPaths")
+    }
+
+    /// Skeleton files by name
+    pub fn skeletons(&self) -> HashMap<String, String> {
+        let Some(Value::Object(skeletons)) = self.get("Skeletons") else {
+            return HashMap::new();
+        };
+        skeletons
+            .iter()
+            .filter_map(|(name, path)| Some((name.clone(), path.as_str()?.to_string())))
+            .collect()
+    }
+
+    pub fn physics(&self) -> PhysicsConfig {
+        let default = PhysicsConfig::default();
+        PhysicsConfig {
+            attack_clash_mode: self.int("AttackClashMode", default.attack_clash_mode),
+            attacks_can_hit_on_landing_hitstun_frame: self.bool(
+                "AttacksCanHitOnLandingHitstunFrame",
+                default.attacks_can_hit_on_landing_hitstun_frame,
+            ),
+            use_fighting_arena: self.bool("UseFightingArena", default.use_fighting_arena),
+            arena_size: self.int("ArenaSize", default.arena_size),
+            arena_max_player_distance: self
+                .int("ArenaMaxPlayerDistance", default.arena_max_player_distance),
+            buckets: self.int("PhysicsNbBuckets", default.buckets),
+        }
+    }
+
+    /// Input options; an `InputLayout` left out is `default_input_layout`
+    pub fn input(&self) -> Result<InputConfig, String> {
+        let layout = |key: &str| -> Result<Option<Vec<PhysicalInput>>, String> {
+            let Some(layout) = self.get(key) else {
+                return Ok(None);
+            };
+            let entries = layout
+                .as_array()
+                .ok_or_else(|| format!("{} should be an array", key))?;
+            entries
+                .iter()
+                .map(PhysicalInput::from_json)
+                .collect::<Result<_, _>>()
+                .map(Some)
+        };
+        Ok(InputConfig {
+            layout: layout("InputLayout")?.unwrap_or_else(default_input_layout),
+            menu_layout: layout("InputLayoutMenu")?.unwrap_or_default(),
+            keyboard_players: self.int("NumberOfKeyboardPlayers", 2),
+            keyboard_layouts: self.int("NumberOfKeyboardLayouts", 2),
+            controller_players: self.int("NumberOfControllerPlayers", 4),
+            controller_layouts: self.int("NumberOfControllerLayouts", 2),
+            motion_inputs: self.bool("EnableMotionInputs", true),
+        })
+    }
+}
+
+/// `SplitStringToArray` of the engine
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn json_to_variant(value: &Value) -> Variant {
+    match value {
+        Value::Null => Variant::nil(),
+        Value::Bool(value) => value.to_variant(),
+        Value::Number(number) => match number.as_i64() {
+            Some(int) => int.to_variant(),
+            None => number.as_f64().unwrap_or_default().to_variant(),
+        },
+        Value::String(string) => string.as_str().to_variant(),
+        Value::Array(items) => items
+            .iter()
+            .map(json_to_variant)
+            .collect::<VarArray>()
+            .to_variant(),
+        Value::Object(entries) => {
+            let mut dictionary = VarDictionary::new();
+            for (key, value) in entries {
+                dictionary.set(key.as_str(), json_to_variant(value));
+            }
+            dictionary.to_variant()
+        }
+    }
+}
+
+fn strings(items: &[String]) -> PackedStringArray {
+    items
+        .iter()
+        .map(|item| GString::from(item.as_str()))
+        .collect()
+}
+
+/// Godot-facing view of a project's config
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneRsConfig {
+    base: Base<RefCounted>,
+    config: CastagneConfig,
+    error: GString,
+}
+
+#[godot_api]
+impl CastagneRsConfig {
+    /// Load a config file (`res://` and `user://` paths included), then
+    /// `local_path` over it if it exists, returning whether both loaded
+    #[func]
+    pub fn load_file(&mut self, path: GString, local_path: GString) -> bool {
+        let globalize = |path: GString| {
+            godot::classes::ProjectSettings::singleton()
+                .globalize_path(&path)
+                .to_string()
+        };
+        match CastagneConfig::load_with_local(&globalize(path), &globalize(local_path)) {
+            Ok(config) => {
+                self.config = config;
+                self.error = GString::new();
+                true
+            }
+            Err(error) => {
+                self.error = GString::from(error.as_str());
+                false
+            }
+        }
+    }
+
+    /// Why the last load failed, empty if it didn't
+    #[func]
+    pub fn get_error(&self) -> GString {
+        self.error.clone()
+    }
+
+    /// Value of a key as written in the file, null if unset
+    #[func]
+    pub fn get_value(&self, key: GString) -> Variant {
+        self.config
+            .get(&key.to_string())
+            .map_or_else(Variant::nil, json_to_variant)
+    }
+
+    #[func]
+    pub fn get_keys(&self) -> PackedStringArray {
+        self.config.keys().into_iter().map(GString::from).collect()
+    }
+
+    #[func]
+    pub fn get_modules(&self) -> PackedStringArray {
+        strings(&self.config.modules())
+    }
+
+    /// Module files to load, empty if a module list is missing
+    #[func]
+    pub fn get_module_paths(&self) -> PackedStringArray {
+        strings(&self.config.module_paths().unwrap_or_default())
+    }
+
+    #[func]
+    pub fn get_character_paths(&self) -> PackedStringArray {
+        strings(&self.config.character_paths())
+    }
+
+    #[func]
+    pub fn get_skeletons(&self) -> VarDictionary {
+        let mut skeletons = VarDictionary::new();
+        for (name, path) in self.config.skeletons() {
+            skeletons.set(name.as_str(), path.as_str());
+        }
+        skeletons
+    }
+
+    /// Physics options, keyed as in the config file
+    #[func]
+    pub fn get_physics(&self) -> VarDictionary {
+        let physics = self.config.physics();
+        let mut dictionary = VarDictionary::new();
+        dictionary.set("AttackClashMode", physics.attack_clash_mode);
+        dictionary.set(
+            "AttacksCanHitOnLandingHitstunFrame",
+            physics.attacks_can_hit_on_landing_hitstun_frame,
+        );
+        dictionary.set("UseFightingArena", physics.use_fighting_arena);
+        dictionary.set("ArenaSize", physics.arena_size);
+        dictionary.set("ArenaMaxPlayerDistance", physics.arena_max_player_distance);
+        dictionary.set("PhysicsNbBuckets", physics.buckets);
+        dictionary
+    }
+
+    /// Names of the game inputs of the input layout, empty if it's invalid
+    #[func]
+    pub fn get_game_inputs(&self) -> PackedStringArray {
+        let Ok(input) = self.config.input() else {
+            return PackedStringArray::new();
+        };
+        input
+            .layout
+            .iter()
+            .flat_map(PhysicalInput::game_inputs)
+            .map(GString::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_typed_view_of_the_project_config() {
+        let json = fs::read_to_string(CONFIG_FILE_NAME).unwrap();
+        let mut config = CastagneConfig::from_json(&json).unwrap();
+        assert_eq!(
+            config.modules(),
+            ["coreset", "physics", "graphics", "flow", "user"]
+        );
+        assert!(config.character_paths().is_empty());
+        assert_eq!(config.physics(), PhysicsConfig::default());
+        let input = config.input().unwrap();
+        assert_eq!(input.layout, default_input_layout());
+        let game_inputs: Vec<&str> = input.layout.iter().flat_map(|i| i.game_inputs()).collect();
+        assert!(game_inputs.contains(&"Forward") && game_inputs.contains(&"Throw"));
+
+        assert_eq!(
+            config.module_paths().unwrap_err(),
+            "Can't find module list to load: coreset"
+        );
+        config.merge(CastagneConfig {
+            values: json!({
+                "Modules-coreset": "res://core/CMCore.gd, physics",
+                "Modules-physics": ["res://physics/CMPhysics2D.gd"],
+                "Modules-graphics": "res://graphics/CMGraphics2D.gd",
+                "Modules-flow": "",
+                "Modules-user": "res://user/Module.tscn",
+                "CharacterPaths": "res://ryu.casp,res://ken.casp",
+                "Skeletons": {"Base": "res://base.casp"},
+                "ArenaSize": 90000,
+                "InputLayout": [
+                    {"Name": "A", "Type": 1, "KeyboardInputs": [[[65]]], "ControllerInputs": [[[0]]]},
+                ],
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        });
+        assert_eq!(
+            config.module_paths().unwrap(),
+            [
+                "res://core/CMCore.gd",
+                "res://physics/CMPhysics2D.gd",
+                "res://physics/CMPhysics2D.gd",
+                "res://graphics/CMGraphics2D.gd",
+                "res://user/Module.tscn",
+            ]
+        );
+        assert_eq!(
+            config.character_paths(),
+            ["res://ryu.casp", "res://ken.casp"]
+        );
+        assert_eq!(config.skeletons()["Base"], "res://base.casp");
+        assert_eq!(config.physics().arena_size, 90000);
+        let layout = config.input().unwrap().layout;
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].keyboard_inputs, [[[65]]]);
+    }
+
+    #[test]
+    fn test_local_config_overrides_project_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path(CONFIG_FILE_NAME),
+            r#"{"Modules": "a.gd", "LocalConfig-Audio": {"Music": 5, "Sfx": 5}}"#,
+        )
+        .unwrap();
+        let config =
+            CastagneConfig::load_with_local(&path(CONFIG_FILE_NAME), &path(LOCAL_CONFIG_FILE_NAME))
+                .unwrap();
+        assert_eq!(config.keys(), ["LocalConfig-Audio", "Modules"]);
+
+        fs::write(
+            path(LOCAL_CONFIG_FILE_NAME),
+            r#"{"LocalConfig-Audio": {"Music": 0}}"#,
+        )
+        .unwrap();
+        let config =
+            CastagneConfig::load_with_local(&path(CONFIG_FILE_NAME), &path(LOCAL_CONFIG_FILE_NAME))
+                .unwrap();
+        assert_eq!(config.get("LocalConfig-Audio"), Some(&json!({"Music": 0})));
+
+        fs::write(path(LOCAL_CONFIG_FILE_NAME), "{").unwrap();
+        assert!(CastagneConfig::load_with_local(
+            &path(CONFIG_FILE_NAME),
+            &path(LOCAL_CONFIG_FILE_NAME)
+        )
+        .unwrap_err()
+        .starts_with("Invalid config"));
+    }
+}
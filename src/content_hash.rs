@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Content Hash - Checksum of what a character does, not how it is written
+//!
+//! Netplay peers must run identical character data, and caches need a key
+//! that survives reformatting. `content_hash` hashes the parsed character
+//! with FNV-1a, which unlike `DefaultHasher` gives the same value on every
+//! platform and Rust version. Line numbers, `##` state descriptions and
+//! the paths of the skeleton and includes are left out, so comments,
+//! blank lines and where the base files are installed don't change it.
+
+use crate::parser::ParsedCharacter;
+use serde_json::Value;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Fields hashed nowhere, wherever they appear
+const LAYOUT_FIELDS: &[&str] = &["line_number"];
+/// Fields of metadata blocks that only say where files are
+const METADATA_PATH_FIELDS: &[&str] = &["skeleton", "filepath", "includes"];
+
+/// 64-bit FNV-1a
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Write a length-prefixed string, so `"ab", "c"` and `"a", "bc"` differ
+    fn write_str(&mut self, text: &str) {
+        self.write(&(text.len() as u64).to_le_bytes());
+        self.write(text.as_bytes());
+    }
+
+    /// Write a JSON value, object keys sorted
+    fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.write(b"n"),
+            Value::Bool(b) => self.write(if *b { b"t" } else { b"f" }),
+            Value::Number(n) => {
+                self.write(b"#");
+                self.write_str(&n.to_string());
+            }
+            Value::String(s) => {
+                self.write(b"s");
+                self.write_str(s);
+            }
+            Value::Array(items) => {
+                self.write(b"[");
+                self.write(&(items.len() as u64).to_le_bytes());
+                for item in items {
+                    self.write_value(item);
+                }
+            }
+            Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                self.write(b"{");
+                self.write(&(keys.len() as u64).to_le_bytes());
+                for key in keys {
+                    self.write_str(key);
+                    self.write_value(&map[key]);
+                }
+            }
+        }
+    }
+}
+
+/// FNV-1a of length-prefixed `parts`, the same on every platform and run
+pub(crate) fn stable_hash<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hasher = Fnv1a(FNV_OFFSET_BASIS);
+    for part in parts {
+        hasher.write(&(part.len() as u64).to_le_bytes());
+        hasher.write(part);
+    }
+    hasher.0
+}
+
+/// Remove the fields that depend on formatting or install location
+fn strip_layout(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(strip_layout),
+        Value::Object(map) => {
+            for field in LAYOUT_FIELDS {
+                map.remove(*field);
+            }
+            map.values_mut().for_each(strip_layout);
+        }
+        _ => {}
+    }
+}
+
+fn strip_paths(metadata: &mut Value) {
+    if let Value::Object(map) = metadata {
+        for field in METADATA_PATH_FIELDS {
+            map.remove(*field);
+        }
+    }
+}
+
+impl ParsedCharacter {
+    /// Stable 64-bit hash of the semantic content of this character
+    ///
+    /// Two files differing only in comments, whitespace, line layout or
+    /// the location of their base files hash the same.
+    pub fn content_hash(&self) -> u64 {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        strip_layout(&mut value);
+        if let Some(map) = value.as_object_mut() {
+            if let Some(metadata) = map.get_mut("metadata") {
+                strip_paths(metadata);
+            }
+            if let Some(Value::Object(subentities)) = map.get_mut("subentities") {
+                subentities.values_mut().for_each(strip_paths);
+            }
+            if let Some(Value::Object(states)) = map.get_mut("states") {
+                for state in states.values_mut() {
+                    if let Value::Object(state) = state {
+                        state.remove("description");
+                    }
+                }
+            }
+        }
+
+        let mut hasher = Fnv1a(FNV_OFFSET_BASIS);
+        hasher.write_value(&value);
+        hasher.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    fn hash(source: &str) -> u64 {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ryu.casp");
+        std::fs::write(&path, source).unwrap();
+        CastagneParser::new()
+            .create_full_character(path.to_str().unwrap())
+            .unwrap()
+            .content_hash()
+    }
+
+    #[test]
+    fn test_content_hash_ignores_layout() {
+        let source =
+            ":Character:\nName: Ryu\n:Variables:\nvar Speed(Int): 5\n:Idle:\n---Init:\nMove(1)\n";
+        let reformatted = "# Ryu\n:Character:\nName: Ryu\n\n:Variables:\n  var Speed(Int): 5\n\n:Idle:\n## Standing still\n---Init:\n  # step forward\n  Move(1)\n";
+        let changed =
+            ":Character:\nName: Ryu\n:Variables:\nvar Speed(Int): 5\n:Idle:\n---Init:\nMove(2)\n";
+
+        assert_eq!(hash(source), hash(source));
+        assert_eq!(hash(source), hash(reformatted));
+        assert_ne!(hash(source), hash(changed));
+    }
+}
@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Corpus - The .casp files benchmarks and regression tests run on
+//!
+//! Collects the character files under a set of directories, in a stable
+//! order, along with the golden master JSON exported by the Godot parser
+//! for each file that has one. `Corpus::repository` loads the files
+//! shipped with this repository: the test characters, the engine's base
+//! modules and, when the Castagne submodule is checked out, its examples.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directories, relative to the repository root, holding the default corpus
+pub const REPOSITORY_DIRS: &[&str] = &[
+    "test_characters",
+    "castagne_godot4/modules",
+    "castagne/examples",
+];
+
+/// Directory of the golden master JSON files, relative to the repository root
+pub const GOLDEN_MASTER_DIR: &str = "golden_masters";
+
+/// A character file of the corpus
+#[derive(Debug, Clone)]
+pub struct CorpusFile {
+    pub path: PathBuf,
+    pub source: String,
+    /// Output of the Godot parser for this file, `golden_masters/<stem>.json`
+    pub golden_master: Option<PathBuf>,
+}
+
+impl CorpusFile {
+    /// File name without its extension
+    pub fn name(&self) -> &str {
+        self.path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+    }
+}
+
+/// A set of character files
+#[derive(Debug, Clone, Default)]
+pub struct Corpus {
+    pub files: Vec<CorpusFile>,
+}
+
+impl Corpus {
+    /// The .casp files under `dirs`, searched recursively
+    ///
+    /// Missing directories are skipped. Golden masters are looked up in
+    /// `golden_dir` when given.
+    pub fn load(dirs: &[&Path], golden_dir: Option<&Path>) -> io::Result<Self> {
+        let mut paths = Vec::new();
+        for dir in dirs {
+            if dir.is_dir() {
+                collect_casp_files(dir, &mut paths)?;
+            }
+        }
+        paths.sort();
+        paths.dedup();
+
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let source = fs::read_to_string(&path)?;
+            let golden_master = golden_dir.and_then(|dir| {
+                let stem = path.file_stem()?;
+                let golden = dir.join(stem).with_extension("json");
+                golden.is_file().then_some(golden)
+            });
+            files.push(CorpusFile {
+                path,
+                source,
+                golden_master,
+            });
+        }
+        Ok(Self { files })
+    }
+
+    /// The corpus shipped with the repository at `root`
+    pub fn repository(root: &Path) -> io::Result<Self> {
+        let dirs: Vec<PathBuf> = REPOSITORY_DIRS.iter().map(|dir| root.join(dir)).collect();
+        let dirs: Vec<&Path> = dirs.iter().map(PathBuf::as_path).collect();
+        Self::load(&dirs, Some(&root.join(GOLDEN_MASTER_DIR)))
+    }
+
+    /// Files with a golden master
+    pub fn with_golden_masters(&self) -> impl Iterator<Item = &CorpusFile> {
+        self.files
+            .iter()
+            .filter(|file| file.golden_master.is_some())
+    }
+
+    /// Total size of the sources, in bytes
+    pub fn total_bytes(&self) -> usize {
+        self.files.iter().map(|file| file.source.len()).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+fn collect_casp_files(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_casp_files(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "casp") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_corpus() {
+        let root = tempfile::tempdir().unwrap();
+        let chars = root.path().join("chars");
+        fs::create_dir_all(chars.join("nested")).unwrap();
+        fs::create_dir_all(root.path().join("golden")).unwrap();
+        fs::write(chars.join("b.casp"), ":Character:\nName: B\n").unwrap();
+        fs::write(chars.join("nested/a.casp"), ":Character:\nName: A\n").unwrap();
+        fs::write(chars.join("notes.txt"), "ignored").unwrap();
+        fs::write(root.path().join("golden/b.json"), "{}").unwrap();
+
+        let corpus = Corpus::load(
+            &[&chars, &root.path().join("missing")],
+            Some(&root.path().join("golden")),
+        )
+        .unwrap();
+
+        let names: Vec<&str> = corpus.files.iter().map(CorpusFile::name).collect();
+        assert_eq!(names, vec!["b", "a"]);
+        assert_eq!(corpus.total_bytes(), 40);
+        let golden: Vec<&str> = corpus.with_golden_masters().map(CorpusFile::name).collect();
+        assert_eq!(golden, vec!["b"]);
+    }
+
+    #[test]
+    fn test_repository_corpus_is_found() {
+        let corpus = Corpus::repository(Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+        assert!(!corpus.is_empty());
+        for file in &corpus.files {
+            assert!(!file.source.is_empty(), "{} is empty", file.path.display());
+        }
+    }
+}
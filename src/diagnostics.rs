@@ -0,0 +1,156 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Diagnostics - Structured messages about .casp files
+//!
+//! The parser historically reports problems as plain strings in `errors`.
+//! Diagnostics carry a stable code, a severity and an optional source span
+//! so tools (lints, the editor, CI) can filter and display them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Hint => "hint",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Location of a diagnostic in a source file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub file: Option<String>,
+    /// 1-indexed line number, as shown to the user
+    pub line: usize,
+    /// 0-indexed column of the first character
+    pub column: usize,
+    /// Length in characters (0 when the whole line is meant)
+    pub length: usize,
+}
+
+impl Span {
+    /// Span covering a whole line
+    pub fn line(line: usize) -> Self {
+        Self {
+            file: None,
+            line,
+            column: 0,
+            length: 0,
+        }
+    }
+}
+
+/// Edit fixing a diagnostic: replace the text at `span` with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixIt {
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// A single diagnostic message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub code: String,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+    /// Edits an editor can apply to fix it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fixes: Vec<FixIt>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &str, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            severity,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Suggest replacing the text at `span`
+    pub fn with_fix(mut self, span: Span, replacement: impl Into<String>) -> Self {
+        self.fixes.push(FixIt {
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = &self.span {
+            if let Some(file) = &span.file {
+                write!(f, "{}:", file)?;
+            }
+            write!(f, "{}: ", span.line)?;
+        }
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_display() {
+        let mut span = Span::line(12);
+        span.file = Some("Fighter.casp".to_string());
+        let diag = Diagnostic::new("empty-phase", Severity::Warning, "Phase Init is empty")
+            .with_span(span)
+            .with_note("remove the phase marker");
+
+        assert_eq!(
+            diag.to_string(),
+            "Fighter.casp:12: warning[empty-phase]: Phase Init is empty\n  note: remove the phase marker"
+        );
+        assert!(!diag.is_error());
+    }
+
+    #[test]
+    fn test_severity_ordering() {
+        assert!(Severity::Error > Severity::Warning);
+        assert!(Severity::Warning > Severity::Info);
+        assert!(Severity::Info > Severity::Hint);
+    }
+}
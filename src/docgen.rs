@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Docgen - Movelist generation
+//!
+//! Renders a character's attacks as a Markdown or HTML movelist: a frame
+//! data table followed by the description of each move, taken from the
+//! `##` comments at the top of its state.
+
+use crate::frame_data::{attacks, AttackData};
+use crate::parser::ParsedCharacter;
+
+/// Output format of the movelist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+const COLUMNS: &[&str] = &[
+    "Move", "Input", "Type", "Damage", "Frames", "On hit", "On block",
+];
+
+/// Render the movelist of a character
+pub fn generate(character: &ParsedCharacter, format: DocFormat) -> String {
+    let attacks = attacks(character);
+    match format {
+        DocFormat::Markdown => markdown(character, &attacks),
+        DocFormat::Html => html(character, &attacks),
+    }
+}
+
+fn row(attack: &AttackData) -> Vec<String> {
+    let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    vec![
+        attack.state.clone(),
+        field(&attack.input),
+        field(&attack.attack_type),
+        field(&attack.damage),
+        field(&attack.duration),
+        signed(&attack.advantage_hit),
+        signed(&attack.advantage_block),
+    ]
+}
+
+/// Frame advantage with an explicit `+` on positive numbers
+fn signed(value: &Option<String>) -> String {
+    match value {
+        Some(value) if value.parse::<i64>().map(|v| v > 0).unwrap_or(false) => {
+            format!("+{}", value)
+        }
+        Some(value) => value.clone(),
+        None => "-".to_string(),
+    }
+}
+
+fn markdown(character: &ParsedCharacter, attacks: &[AttackData]) -> String {
+    let mut out = format!("# {}\n\n", character.metadata.name);
+    if !character.metadata.description.is_empty() {
+        out.push_str(&format!("{}\n\n", character.metadata.description));
+    }
+
+    out.push_str(&format!("| {} |\n", COLUMNS.join(" | ")));
+    out.push_str(&format!("|{}\n", "---|".repeat(COLUMNS.len())));
+    for attack in attacks {
+        let cells: Vec<String> = row(attack)
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect();
+        out.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+
+    for attack in attacks {
+        if let Some(description) = &attack.description {
+            out.push_str(&format!("\n## {}\n\n{}\n", attack.state, description));
+        }
+    }
+    out
+}
+
+fn html(character: &ParsedCharacter, attacks: &[AttackData]) -> String {
+    let mut out = format!("<h1>{}</h1>\n", escape(&character.metadata.name));
+    if !character.metadata.description.is_empty() {
+        out.push_str(&format!(
+            "<p>{}</p>\n",
+            escape(&character.metadata.description)
+        ));
+    }
+
+    out.push_str("<table>\n<tr>");
+    for column in COLUMNS {
+        out.push_str(&format!("<th>{}</th>", column));
+    }
+    out.push_str("</tr>\n");
+    for attack in attacks {
+        out.push_str("<tr>");
+        for cell in row(attack) {
+            out.push_str(&format!("<td>{}</td>", escape(&cell)));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n");
+
+    for attack in attacks {
+        if let Some(description) = &attack.description {
+            out.push_str(&format!(
+                "<h2>{}</h2>\n<p>{}</p>\n",
+                escape(&attack.state),
+                escape(description).replace('\n', "<br>\n")
+            ));
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    const SOURCE: &str = ":Character:
+Name: Tester
+:2B:
+## Low poke
+## Cancels into specials
+---Init:
+AttackRegister(Low)
+AttackDamage(250)
+AttackDuration(20)
+AttackFrameAdvantage(2, -3)
+:Throw:
+---Init:
+AttackRegister(Throw, 6C)
+";
+
+    fn character() -> ParsedCharacter {
+        CastagneParser::new()
+            .create_full_character_from_source("test.casp", SOURCE)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_markdown_movelist() {
+        let doc = generate(&character(), DocFormat::Markdown);
+
+        assert_eq!(
+            doc,
+            "# Tester
+
+| Move | Input | Type | Damage | Frames | On hit | On block |
+|---|---|---|---|---|---|---|
+| 2B | 2B | Low | 250 | 20 | +2 | -3 |
+| Throw | 6C | Throw | - | - | - | - |
+
+## 2B
+
+Low poke
+Cancels into specials
+"
+        );
+    }
+
+    #[test]
+    fn test_html_movelist_escapes() {
+        let mut character = character();
+        character.metadata.name = "<Tester>".to_string();
+
+        let doc = generate(&character, DocFormat::Html);
+
+        assert!(doc.starts_with("<h1>&lt;Tester&gt;</h1>\n<table>"));
+        assert!(doc.contains("<tr><td>2B</td><td>2B</td><td>Low</td>"));
+        assert!(doc.contains("<p>Low poke<br>\nCancels into specials</p>"));
+    }
+}
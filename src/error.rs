@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Error - Why a parse gave no character
+//!
+//! Most problems of a file are reported as errors and diagnostics while the
+//! parse goes on. A few stop it: an unreadable file, a cycle between files,
+//! a dependency that fails in turn. Those end the parse with a
+//! `ParseFailure`; its message is also the last entry of `errors`, which
+//! is all the Godot-facing API shows.
+
+use crate::diagnostics::Span;
+use crate::limits::Limit;
+
+/// What stopped a parse
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseFailure {
+    /// A file couldn't be opened or read
+    #[error("File {path} {reason}")]
+    Io { path: String, reason: String },
+    /// A file isn't valid UTF-8
+    #[error("File {path} {reason}")]
+    Encoding { path: String, reason: String },
+    /// Syntax the parse can't go past, like an unknown `FormatVersion`
+    #[error("{message}")]
+    Syntax { message: String, span: Option<Span> },
+    /// Files including each other, from the outermost one
+    #[error("Include cycle: {}", chain.join(" -> "))]
+    IncludeCycle { chain: Vec<String> },
+    /// Skeletons inheriting from each other, from the outermost file
+    #[error("Skeleton cycle: {}", chain.join(" -> "))]
+    SkeletonCycle { chain: Vec<String> },
+    /// An included file failed to parse
+    #[error("Failed to load included file: {path}")]
+    Include {
+        path: String,
+        #[source]
+        cause: Box<ParseFailure>,
+    },
+    /// The skeleton failed to parse
+    #[error("Failed to load skeleton file: {path}")]
+    Skeleton {
+        path: String,
+        #[source]
+        cause: Box<ParseFailure>,
+    },
+    /// The parse was cancelled through its `CancellationToken`
+    #[error("Parse cancelled")]
+    Cancelled,
+    /// The file went past one of the configured `Limits`
+    #[error("Limit exceeded: {limit} {max} {}{}", limit.unit(), line_suffix(span))]
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+        span: Option<Span>,
+    },
+}
+
+impl ParseFailure {
+    /// The failure that started it all, through failed dependencies
+    pub fn root_cause(&self) -> &ParseFailure {
+        match self {
+            ParseFailure::Include { cause, .. } | ParseFailure::Skeleton { cause, .. } => {
+                cause.root_cause()
+            }
+            failure => failure,
+        }
+    }
+
+    /// Span of the failure, for the ones tied to a line
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            ParseFailure::Syntax { span, .. } | ParseFailure::LimitExceeded { span, .. } => {
+                span.as_ref()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// ` (line N)` for failures tied to a line
+fn line_suffix(span: &Option<Span>) -> String {
+    match span {
+        Some(span) => format!(" (line {})", span.line),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_failure_messages_and_causes() {
+        let cycle = ParseFailure::SkeletonCycle {
+            chain: vec!["a.casp".to_string(), "b.casp".to_string()],
+        };
+        let failure = ParseFailure::Include {
+            path: "moves.casp".to_string(),
+            cause: Box::new(ParseFailure::Skeleton {
+                path: "b.casp".to_string(),
+                cause: Box::new(cycle.clone()),
+            }),
+        };
+        assert_eq!(
+            failure.to_string(),
+            "Failed to load included file: moves.casp"
+        );
+        assert_eq!(failure.root_cause(), &cycle);
+        assert_eq!(
+            failure.source().unwrap().to_string(),
+            "Failed to load skeleton file: b.casp"
+        );
+        assert_eq!(cycle.to_string(), "Skeleton cycle: a.casp -> b.casp");
+        assert!(cycle.source().is_none());
+
+        let io = ParseFailure::Io {
+            path: "ryu.casp".to_string(),
+            reason: "cannot be read: denied".to_string(),
+        };
+        assert_eq!(io.to_string(), "File ryu.casp cannot be read: denied");
+        let syntax = ParseFailure::Syntax {
+            message: "Unknown FormatVersion 9".to_string(),
+            span: Some(Span::line(2)),
+        };
+        assert_eq!(syntax.span().map(|span| span.line), Some(2));
+        let limit = ParseFailure::LimitExceeded {
+            limit: Limit::LineLength,
+            max: 80,
+            span: Some(Span::line(3)),
+        };
+        assert_eq!(
+            limit.to_string(),
+            "Limit exceeded: line longer than 80 bytes (line 3)"
+        );
+    }
+}
@@ -0,0 +1,500 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Expression - Evaluation of variable default values
+//!
+//! Defaults may be small expressions instead of literals:
+//! `Vec2(10, 20) + Knockback`, `(BaseSpeed * 3) / 2`, `0.5s`. This module
+//! evaluates `+ - * /` over scalars and vectors, `Vec2(..)` / `Vec3(..)`
+//! constructors, tuples, references to other variables, and time units:
+//! `12f` is 12 frames, `0.5s` is converted to frames at the configured
+//! frame rate. Integer literals may be written in hexadecimal (`0xFF`) or
+//! binary (`0b1010`) and use `_` as a digit separator (`1_000`); decimal
+//! ones may take an exponent (`1e3`, `2.5E-2`).
+
+use std::fmt;
+
+/// Result of an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(f64),
+    Vector(Vec<f64>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Scalar(v) => write!(f, "{}", format_number(*v)),
+            Value::Vector(components) => {
+                let parts: Vec<String> = components.iter().map(|v| format_number(*v)).collect();
+                write!(f, "({})", parts.join(", "))
+            }
+        }
+    }
+}
+
+fn format_number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{}", v)
+    }
+}
+
+/// Parse an integer literal: decimal, `0x` hexadecimal or `0b` binary, with
+/// an optional sign and `_` between digits (`-0xFF`, `0b1010_0101`, `1_000`)
+pub fn parse_integer(text: &str) -> Option<i64> {
+    i64::try_from(parse_wide_integer(text)?).ok()
+}
+
+/// Parse an integer literal like `parse_integer`, past the range of `i64`,
+/// to tell values too large for their type from text that isn't a number
+pub(crate) fn parse_wide_integer(text: &str) -> Option<i128> {
+    let text = text.trim();
+    let (negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, digits) = match rest.get(..2) {
+        Some("0x" | "0X") => (16, &rest[2..]),
+        Some("0b" | "0B") => (2, &rest[2..]),
+        _ => (10, rest),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix) || c == '_') {
+        return None;
+    }
+    let magnitude = i128::from_str_radix(&strip_separators(digits, radix)?, radix).ok()?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+/// Parse a number literal, integer (see `parse_integer`) or decimal
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if let Some(value) = parse_integer(text) {
+        return Some(value as f64);
+    }
+    strip_separators(text, 10)?.parse().ok()
+}
+
+/// Remove `_` separators, which are only allowed between two digits
+fn strip_separators(text: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_'
+            && !(i > 0
+                && chars[i - 1].is_digit(radix)
+                && chars.get(i + 1).is_some_and(|n| n.is_digit(radix)))
+        {
+            return None;
+        }
+    }
+    Some(chars.into_iter().filter(|c| *c != '_').collect())
+}
+
+/// An evaluated expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluated {
+    pub value: Value,
+    /// Whether anything beyond a plain literal was involved (operators,
+    /// references, constructors or units), i.e. the text needs normalizing
+    pub derived: bool,
+}
+
+/// Evaluate `text`, resolving names with `lookup`
+pub fn evaluate(
+    text: &str,
+    frames_per_second: u32,
+    lookup: &mut dyn FnMut(&str) -> Result<Value, String>,
+) -> Result<Evaluated, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = ExprParser {
+        tokens,
+        position: 0,
+        frames_per_second,
+        lookup,
+        derived: false,
+    };
+
+    // A top-level comma list is a vector, as in `var Position(Vec2): 0, 0`
+    let mut items = vec![parser.expr()?];
+    while parser.eat(&Token::Comma) {
+        items.push(parser.expr()?);
+    }
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("Unexpected {}", token));
+    }
+
+    let value = if items.len() == 1 {
+        items.pop().unwrap()
+    } else {
+        vector(items)?
+    };
+    Ok(Evaluated {
+        value,
+        derived: parser.derived,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64, Option<char>),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(v, unit) => {
+                write!(f, "{}{}", v, unit.map(String::from).unwrap_or_default())
+            }
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '0'
+            && matches!(chars.get(i + 1), Some('x' | 'X' | 'b' | 'B'))
+            && chars.get(i + 2).is_some_and(|n| n.is_ascii_hexdigit())
+        {
+            // Prefixed literals take no unit, `f` being a hexadecimal digit
+            let start = i;
+            i += 2;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let value =
+                parse_integer(&number).ok_or_else(|| format!("Invalid number {}", number))?;
+            tokens.push(Token::Number(value as f64, None));
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()))
+        {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || chars[i] == '.'
+                    || (chars[i] == '_' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())))
+            {
+                i += 1;
+            }
+            // Exponent, as in `1e3` or `2.5E-2`
+            if matches!(chars.get(i), Some('e' | 'E')) {
+                let digits = match chars.get(i + 1) {
+                    Some('+' | '-') => i + 2,
+                    _ => i + 1,
+                };
+                if chars.get(digits).is_some_and(char::is_ascii_digit) {
+                    i = digits;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            let value =
+                parse_number(&number).ok_or_else(|| format!("Invalid number {}", number))?;
+            let unit = match chars.get(i) {
+                Some(&u @ ('s' | 'f'))
+                    if !chars
+                        .get(i + 1)
+                        .is_some_and(|n| n.is_alphanumeric() || *n == '_') =>
+                {
+                    i += 1;
+                    Some(u)
+                }
+                _ => None,
+            };
+            tokens.push(Token::Number(value, unit));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(match c {
+                '+' | '-' | '*' | '/' => Token::Op(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                _ => return Err(format!("Unexpected character '{}'", c)),
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    frames_per_second: u32,
+    lookup: &'a mut dyn FnMut(&str) -> Result<Value, String>,
+    derived: bool,
+}
+
+impl ExprParser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value, String> {
+        let mut value = self.term()?;
+        loop {
+            let op = match self.tokens.get(self.position) {
+                Some(Token::Op(op @ ('+' | '-'))) => *op,
+                _ => return Ok(value),
+            };
+            self.position += 1;
+            self.derived = true;
+            let rhs = self.term()?;
+            value = binary(op, value, rhs)?;
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        loop {
+            let op = match self.tokens.get(self.position) {
+                Some(Token::Op(op @ ('*' | '/'))) => *op,
+                _ => return Ok(value),
+            };
+            self.position += 1;
+            self.derived = true;
+            let rhs = self.unary()?;
+            value = binary(op, value, rhs)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat(&Token::Op('-')) {
+            return binary('*', Value::Scalar(-1.0), self.unary()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Number(value, unit)) => match unit {
+                Some('s') => {
+                    self.derived = true;
+                    Ok(Value::Scalar(value * self.frames_per_second as f64))
+                }
+                Some(_) => {
+                    self.derived = true;
+                    Ok(Value::Scalar(value))
+                }
+                None => Ok(Value::Scalar(value)),
+            },
+            Some(Token::Ident(name)) => {
+                self.derived = true;
+                if self.tokens.get(self.position) != Some(&Token::Open) {
+                    return (self.lookup)(&name);
+                }
+                let size = match name.as_str() {
+                    "Vec2" => 2,
+                    "Vec3" => 3,
+                    _ => return Err(format!("Unknown function {}", name)),
+                };
+                self.position += 1;
+                let items = self.list()?;
+                if items.len() != size {
+                    return Err(format!(
+                        "{} expects {} components, got {}",
+                        name,
+                        size,
+                        items.len()
+                    ));
+                }
+                vector(items)
+            }
+            Some(Token::Open) => {
+                let mut items = self.list()?;
+                if items.len() == 1 {
+                    Ok(items.pop().unwrap())
+                } else {
+                    vector(items)
+                }
+            }
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+
+    /// Comma-separated expressions after an opening parenthesis
+    fn list(&mut self) -> Result<Vec<Value>, String> {
+        let mut items = vec![self.expr()?];
+        while self.eat(&Token::Comma) {
+            items.push(self.expr()?);
+        }
+        if !self.eat(&Token::Close) {
+            return Err("Missing ')'".to_string());
+        }
+        Ok(items)
+    }
+}
+
+fn vector(items: Vec<Value>) -> Result<Value, String> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::Scalar(v) => Ok(v),
+            Value::Vector(_) => Err("Vector components must be scalars".to_string()),
+        })
+        .collect::<Result<Vec<f64>, String>>()
+        .map(Value::Vector)
+}
+
+fn binary(op: char, lhs: Value, rhs: Value) -> Result<Value, String> {
+    let apply = |a: f64, b: f64| match op {
+        '+' => a + b,
+        '-' => a - b,
+        '*' => a * b,
+        _ => a / b,
+    };
+    if op == '/' && rhs == Value::Scalar(0.0) {
+        return Err("Division by zero".to_string());
+    }
+
+    match (lhs, rhs) {
+        (Value::Scalar(a), Value::Scalar(b)) => Ok(Value::Scalar(apply(a, b))),
+        (Value::Vector(a), Value::Vector(b)) if matches!(op, '+' | '-') => {
+            if a.len() != b.len() {
+                return Err(format!(
+                    "Can't combine vectors of size {} and {}",
+                    a.len(),
+                    b.len()
+                ));
+            }
+            Ok(Value::Vector(
+                a.iter().zip(&b).map(|(a, b)| apply(*a, *b)).collect(),
+            ))
+        }
+        (Value::Vector(a), Value::Scalar(b)) if matches!(op, '*' | '/') => {
+            Ok(Value::Vector(a.iter().map(|a| apply(*a, b)).collect()))
+        }
+        (Value::Scalar(a), Value::Vector(b)) if op == '*' => {
+            Ok(Value::Vector(b.iter().map(|b| a * b).collect()))
+        }
+        _ => Err(format!("Can't apply '{}' to a vector and a scalar", op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<Evaluated, String> {
+        evaluate(text, 60, &mut |name| match name {
+            "Knockback" => Ok(Value::Vector(vec![5.0, -2.0])),
+            "BaseSpeed" => Ok(Value::Scalar(4.0)),
+            _ => Err(format!("Unknown variable {}", name)),
+        })
+    }
+
+    #[test]
+    fn test_literals_are_not_derived() {
+        for (text, expected) in [
+            ("12", "12"),
+            ("-3.5", "-3.5"),
+            ("(0, -5)", "(0, -5)"),
+            ("0, 0", "(0, 0)"),
+            ("1e3", "1000"),
+            ("2.5E-2", "0.025"),
+            ("-4e+1", "-40"),
+        ] {
+            let evaluated = eval(text).unwrap();
+            assert!(!evaluated.derived, "{}", text);
+            assert_eq!(evaluated.value.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_expressions_and_units() {
+        let cases = [
+            ("Vec2(10, 20) + Knockback", "(15, 18)"),
+            ("(BaseSpeed * 3) / 2", "6"),
+            ("2 * Knockback", "(10, -4)"),
+            ("-Vec3(1, 2, 3)", "(-1, -2, -3)"),
+            ("0.5s", "30"),
+            ("12f", "12"),
+            ("1s + 6f", "66"),
+            ("1e1s", "600"),
+        ];
+        for (text, expected) in cases {
+            let evaluated = eval(text).unwrap();
+            assert!(evaluated.derived, "{}", text);
+            assert_eq!(evaluated.value.to_string(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            eval("Vec2(1)").unwrap_err(),
+            "Vec2 expects 2 components, got 1"
+        );
+        assert_eq!(
+            eval("Knockback + 1").unwrap_err(),
+            "Can't apply '+' to a vector and a scalar"
+        );
+        assert_eq!(
+            eval("Vec2(1, 2) + Vec3(1, 2, 3)").unwrap_err(),
+            "Can't combine vectors of size 2 and 3"
+        );
+        assert_eq!(eval("Missing * 2").unwrap_err(), "Unknown variable Missing");
+        assert_eq!(eval("1 / 0").unwrap_err(), "Division by zero");
+        assert_eq!(eval("(1, 2").unwrap_err(), "Missing ')'");
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        assert_eq!(parse_integer("0xFF"), Some(255));
+        assert_eq!(parse_integer("-0x10"), Some(-16));
+        assert_eq!(parse_integer("0b1010_0101"), Some(0b1010_0101));
+        assert_eq!(parse_integer("1_000_000"), Some(1_000_000));
+        assert_eq!(parse_integer("+42"), Some(42));
+        for invalid in ["0x", "0b102", "_1", "1_", "1__0", "0x_F", "0x-1", "12.5"] {
+            assert_eq!(parse_integer(invalid), None, "{}", invalid);
+        }
+        assert_eq!(parse_number("1_000.25"), Some(1000.25));
+        assert_eq!(parse_number("in_f"), None);
+
+        assert_eq!(eval("0xFF").unwrap().value, Value::Scalar(255.0));
+        assert!(!eval("0b1010").unwrap().derived);
+        assert_eq!(
+            eval("0x0F + 0b1 + 1_000").unwrap().value.to_string(),
+            "1016"
+        );
+        assert_eq!(eval("0b12").unwrap_err(), "Invalid number 0b12");
+    }
+}
@@ -0,0 +1,150 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fixes - Applying the fix-its of diagnostics to a source
+//!
+//! Diagnostics such as `legacy-syntax`, `deprecated-name` and
+//! `missing-endif` carry edits that fix them without a human deciding
+//! anything. `apply_fixes` applies them all at once, for a "fix all"
+//! command in the editor or the `casp-fix` binary over a project.
+
+use crate::diagnostics::{Diagnostic, FixIt, Span};
+
+/// Apply every fix of `diagnostics` to `source`
+///
+/// Edits overlapping an earlier one are skipped; running the parser again
+/// on the result reports what is left. Fixes of diagnostics about other
+/// files must be left out, see `apply_file_fixes`.
+pub fn apply_fixes(source: &str, diagnostics: &[Diagnostic]) -> String {
+    apply_edits(source, diagnostics.iter().flat_map(|d| &d.fixes))
+}
+
+/// Apply edits to `source`, skipping the ones overlapping an earlier one
+pub(crate) fn apply_edits<'a>(source: &str, fixes: impl IntoIterator<Item = &'a FixIt>) -> String {
+    let line_starts = line_starts(source);
+
+    let mut edits: Vec<(usize, usize, &str)> = fixes
+        .into_iter()
+        .filter_map(|fix| {
+            let (start, end) = byte_range(source, &line_starts, &fix.span)?;
+            Some((start, end, fix.replacement.as_str()))
+        })
+        .collect();
+    // Stable, so insertions at one place keep their order
+    edits.sort_by_key(|(start, end, _)| (*start, *end));
+
+    let mut result = String::with_capacity(source.len());
+    let mut copied = 0;
+    for (start, end, replacement) in edits {
+        if start < copied {
+            continue;
+        }
+        result.push_str(&source[copied..start]);
+        if start == source.len() && !source.is_empty() && !source.ends_with('\n') {
+            // Lines inserted after the last one
+            result.push('\n');
+        }
+        result.push_str(replacement);
+        copied = end;
+    }
+    result.push_str(&source[copied..]);
+    result
+}
+
+/// Apply the fixes of the diagnostics about `file` to its source
+pub fn apply_file_fixes(source: &str, file: &str, diagnostics: &[Diagnostic]) -> String {
+    let own: Vec<Diagnostic> = diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            diagnostic
+                .fixes
+                .iter()
+                .all(|fix| fix.span.file.as_deref() == Some(file))
+        })
+        .cloned()
+        .collect();
+    apply_fixes(source, &own)
+}
+
+/// Whether any of the diagnostics can be fixed automatically
+pub fn has_fixes(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| !d.fixes.is_empty())
+}
+
+/// Byte offset of the start of each line
+fn line_starts(source: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(pos, _)| pos + 1))
+        .collect()
+}
+
+/// Bytes covered by a span, the line after the last one meaning the end
+fn byte_range(source: &str, line_starts: &[usize], span: &Span) -> Option<(usize, usize)> {
+    if span.line == line_starts.len() + 1 {
+        return Some((source.len(), source.len()));
+    }
+    let line_start = *line_starts.get(span.line.checked_sub(1)?)?;
+    let line_end = line_starts
+        .get(span.line)
+        .map_or(source.len(), |next| next - 1);
+    let line = &source[line_start..line_end];
+    let byte_at = |column: usize| {
+        line.char_indices()
+            .map(|(pos, _)| pos)
+            .chain(std::iter::once(line.len()))
+            .nth(column)
+    };
+    let start = byte_at(span.column)?;
+    let end = byte_at(span.column + span.length)?;
+    Some((line_start + start, line_start + end))
+}
+
+impl FixIt {
+    /// Text the fix replaces in `source`, `None` if the span is outside it
+    pub fn replaced_text<'a>(&self, source: &'a str) -> Option<&'a str> {
+        let line_starts = line_starts(source);
+        let (start, end) = byte_range(source, &line_starts, &self.span)?;
+        Some(&source[start..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::{Deprecations, LegacyKind};
+    use crate::parser::{CastagneParser, ParserConfig};
+
+    #[test]
+    fn test_fix_all() {
+        let source = ":Character:\nCreator: Old\n:Idle:\n---Start:\nSetVar(Health, 100)\n---Update:\n\tLFlag:\n\t\tChangeState(Walk)\n\n:Walk:\n---Action:\nMove(1)\nVGrounded:\n\tStop";
+        let mut renames = Deprecations::new();
+        renames
+            .rename(LegacyKind::MetadataKey, "Creator", "Author")
+            .rename(LegacyKind::Phase, "Start", "Init")
+            .rename(LegacyKind::Phase, "Update", "Action")
+            .rename(LegacyKind::Instruction, "SetVar", "Set")
+            .rename(LegacyKind::Instruction, "ChangeState", "Transition");
+        let config = ParserConfig::new()
+            .with_legacy_syntax(true)
+            .with_deprecations(renames);
+        let mut parser = CastagneParser::with_config(config);
+        let _ = parser.create_full_character_from_source("old.casp", source);
+        let diagnostics = parser.get_diagnostics().to_vec();
+        assert!(has_fixes(&diagnostics));
+        assert_eq!(
+            diagnostics[0].fixes[0].replaced_text(source),
+            Some("Creator")
+        );
+
+        let fixed = apply_file_fixes(source, "old.casp", &diagnostics);
+        assert_eq!(
+            fixed,
+            ":Character:\nAuthor: Old\n:Idle:\n---Init:\nSet(Health, 100)\n---Action:\n\tLFlag:\n\t\tTransition(Walk)\n\tendif\n\n:Walk:\n---Action:\nMove(1)\nVGrounded:\n\tStop\nendif\n"
+        );
+        assert_eq!(apply_file_fixes(source, "other.casp", &diagnostics), source);
+
+        let _ = parser.create_full_character_from_source("old.casp", &fixed);
+        assert!(parser.get_diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Format Version - Versions of the .casp syntax and migrating between them
+//!
+//! A file declares the syntax it is written in with a `FormatVersion:`
+//! field in its `:Character:` block, and is read as the latest version
+//! without one. The parser upgrades the lines of older files to the current
+//! syntax before parsing them, and `migrate_to_latest` does the same
+//! rewrite on the source text so the file can be saved in the new syntax.
+
+use crate::borrowed::strip_comment;
+use crate::parser::{block_header_name, is_block_header, split_variables_header};
+use std::fmt;
+
+/// Metadata key declaring the format version of a file
+pub const FORMAT_VERSION_FIELD: &str = "FormatVersion";
+
+/// Version of the .casp syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum FormatVersion {
+    /// Castagne 0.x, declaring variables as `var Name int() = value`
+    V0,
+    #[default]
+    V1,
+}
+
+impl FormatVersion {
+    pub const LATEST: FormatVersion = FormatVersion::V1;
+
+    pub fn number(self) -> u32 {
+        match self {
+            FormatVersion::V0 => 0,
+            FormatVersion::V1 => 1,
+        }
+    }
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        match number {
+            0 => Some(FormatVersion::V0),
+            1 => Some(FormatVersion::V1),
+            _ => None,
+        }
+    }
+
+    /// Version written as the value of a `FormatVersion:` field
+    pub fn parse(value: &str) -> Result<Self, String> {
+        value
+            .trim()
+            .parse()
+            .ok()
+            .and_then(Self::from_number)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown {}: {} (latest is {})",
+                    FORMAT_VERSION_FIELD,
+                    value.trim(),
+                    Self::LATEST
+                )
+            })
+    }
+
+    /// The version after this one
+    fn next(self) -> Option<Self> {
+        Self::from_number(self.number() + 1)
+    }
+
+    /// A variables block line of this version in the syntax of the next
+    /// one, `None` when it needs no change
+    fn upgrade_variable_line(self, line: &str) -> Option<String> {
+        match self {
+            FormatVersion::V0 => upgrade_v0_declaration(line),
+            FormatVersion::V1 => None,
+        }
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+/// `var Name int(Subtype) = value` as `var Name(Int, Subtype): value`
+fn upgrade_v0_declaration(line: &str) -> Option<String> {
+    let code = strip_comment(line);
+    let comment = &line[code.len()..];
+    let indent = &code[..code.len() - code.trim_start().len()];
+    let (keyword, rest) = code.trim().split_once(' ')?;
+    if !matches!(keyword, "var" | "def") {
+        return None;
+    }
+    let (declaration, value) = rest.split_once('=')?;
+    let (name, type_part) = declaration.trim().split_once(' ')?;
+    let (type_name, subtype) = type_part.trim().strip_suffix(')')?.split_once('(')?;
+    let type_name = match type_name {
+        "int" => "Int",
+        "str" => "Str",
+        "var" => "Var",
+        "vec2" => "Vec2",
+        "vec3" => "Vec3",
+        "box" => "Box",
+        "bool" => "Bool",
+        _ => return None,
+    };
+    let subtype = match subtype.trim() {
+        "" => String::new(),
+        subtype => format!(", {}", subtype),
+    };
+    let separator = if comment.is_empty() { "" } else { " " };
+    Some(format!(
+        "{}{} {}({}{}): {}{}{}",
+        indent,
+        keyword,
+        name,
+        type_name,
+        subtype,
+        value.trim(),
+        separator,
+        comment
+    ))
+}
+
+/// Version declared in the `:Character:` block, with its line index
+pub(crate) fn declared_version<S: AsRef<str>>(
+    lines: &[S],
+) -> Result<Option<(usize, FormatVersion)>, String> {
+    let mut in_character = false;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.as_ref().trim();
+        if is_block_header(line) {
+            in_character = line == ":Character:";
+            continue;
+        }
+        if !in_character {
+            continue;
+        }
+        if let Some((key, value)) = strip_comment(line).split_once(':') {
+            if key.trim() == FORMAT_VERSION_FIELD {
+                return FormatVersion::parse(value).map(|version| Some((i, version)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Rewrite `lines`, written in `version`, in the latest syntax
+///
+/// Returns the index, old and new text of each changed line.
+pub(crate) fn upgrade_lines(
+    version: FormatVersion,
+    lines: &mut [String],
+) -> Vec<(usize, String, String)> {
+    let mut changes = Vec::new();
+    let mut version = version;
+    while let Some(next) = version.next() {
+        let mut in_variables = false;
+        for (i, line) in lines.iter_mut().enumerate() {
+            let trimmed = line.trim();
+            if let Ok(header) = block_header_name(trimmed) {
+                in_variables = split_variables_header(header).is_some();
+                continue;
+            }
+            if !in_variables {
+                continue;
+            }
+            if let Some(upgraded) = version.upgrade_variable_line(line) {
+                let old = std::mem::replace(line, upgraded);
+                changes.push((i, old, line.clone()));
+            }
+        }
+        version = next;
+    }
+    changes
+}
+
+/// A source rewritten in the latest syntax
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub source: String,
+    /// Version the source was written in
+    pub from: FormatVersion,
+    /// Changed lines, as `line N: old -> new`
+    pub changes: Vec<String>,
+}
+
+/// Rewrite a .casp source in the latest syntax
+///
+/// Sources without a `FormatVersion:` field are already in it and are
+/// returned unchanged.
+pub fn migrate_to_latest(source: &str) -> Result<Migration, String> {
+    let newline = if source.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut lines: Vec<String> = source.split(newline).map(str::to_string).collect();
+    let Some((field_line, from)) = declared_version(&lines)? else {
+        return Ok(Migration {
+            source: source.to_string(),
+            from: FormatVersion::LATEST,
+            changes: Vec::new(),
+        });
+    };
+
+    let mut changes: Vec<String> = upgrade_lines(from, &mut lines)
+        .into_iter()
+        .map(|(i, old, new)| format!("line {}: {} -> {}", i + 1, old.trim(), new.trim()))
+        .collect();
+    if from != FormatVersion::LATEST {
+        let line = &mut lines[field_line];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let upgraded = format!(
+            "{}{}: {}",
+            indent,
+            FORMAT_VERSION_FIELD,
+            FormatVersion::LATEST
+        );
+        changes.insert(
+            0,
+            format!(
+                "line {}: {} -> {}",
+                field_line + 1,
+                line.trim(),
+                upgraded.trim()
+            ),
+        );
+        *line = upgraded;
+    }
+    Ok(Migration {
+        source: lines.join(newline),
+        from,
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_version_field() {
+        assert_eq!(FormatVersion::parse(" 0 "), Ok(FormatVersion::V0));
+        assert_eq!(FormatVersion::parse("1"), Ok(FormatVersion::LATEST));
+        assert_eq!(
+            FormatVersion::parse("7"),
+            Err("Unknown FormatVersion: 7 (latest is 1)".to_string())
+        );
+        assert!(FormatVersion::V0 < FormatVersion::LATEST);
+    }
+
+    #[test]
+    fn test_migrate_v0_source() {
+        let source = ":Character:\nName: Ryu\nFormatVersion: 0\n\n:Variables:\nvar Health int() = 1000 # hp\n  def Frames int(Frames) = 3\nvar Name(Str): \"Ryu\"\n:Idle:\n---Init:\nvar x int() = 1\n";
+        let migration = migrate_to_latest(source).unwrap();
+        assert_eq!(migration.from, FormatVersion::V0);
+        assert_eq!(
+            migration.source,
+            ":Character:\nName: Ryu\nFormatVersion: 1\n\n:Variables:\nvar Health(Int): 1000 # hp\n  def Frames(Int, Frames): 3\nvar Name(Str): \"Ryu\"\n:Idle:\n---Init:\nvar x int() = 1\n"
+        );
+        assert_eq!(
+            migration.changes,
+            vec![
+                "line 3: FormatVersion: 0 -> FormatVersion: 1",
+                "line 6: var Health int() = 1000 # hp -> var Health(Int): 1000 # hp",
+                "line 7: def Frames int(Frames) = 3 -> def Frames(Int, Frames): 3",
+            ]
+        );
+
+        let current = ":Character:\nName: Ryu\n:Variables:\nvar Health(Int): 1000\n";
+        let migration = migrate_to_latest(current).unwrap();
+        assert_eq!(migration.source, current);
+        assert!(migration.changes.is_empty());
+        assert!(migrate_to_latest(":Character:\nFormatVersion: two\n").is_err());
+    }
+
+    #[test]
+    fn test_parser_reads_v0_files() {
+        let source = ":Character:\nName: Ryu\nFormatVersion: 0\n:Variables:\nvar Health int() = 1000\n:Idle:\n---Init:\nSet(Health, 5)\n";
+        let mut parser = crate::parser::CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        assert_eq!(character.variables["Health"].value, "1000");
+        assert_eq!(character.states["Idle"].actions["Init"][0].line_number, 8);
+        assert!(crate::borrowed::parse(source)
+            .unwrap_err()
+            .starts_with("Older format versions need the full parser"));
+
+        let newer = ":Character:\nFormatVersion: 2\n";
+        assert!(parser
+            .create_full_character_from_source("ryu.casp", newer)
+            .is_err());
+        assert!(parser.get_errors()[0].contains("Unknown FormatVersion: 2 (latest is 1)"));
+    }
+}
@@ -0,0 +1,365 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Frame Data - Attack properties read from state actions
+//!
+//! Collects what the attack instructions of each state set up (type,
+//! notation, damage, duration, frame advantage) into one row per attack,
+//! for documentation and balancing tools. Values are kept as written,
+//! so a define or variable name shows up as is.
+//!
+//! It also reads the frame windows of `F` branches: once a character is
+//! parsed, every action lists in `ParsedAction::frames` the windows of the
+//! branches it is in, so tools can tell which frames of a state it runs on
+//! without walking the branches themselves.
+
+use crate::intern::Symbol;
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState, ParsedVariable};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Instructions starting an attack: `AttackRegister(Type, Notation)`
+const REGISTER_INSTRUCTIONS: &[&str] = &["AttackRegister", "AttackRegisterNoNotation"];
+
+/// Attack data of one state
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttackData {
+    pub state: String,
+    /// Input to perform the move, from `AttackRegister` or a numpad state name
+    pub input: Option<String>,
+    pub attack_type: Option<String>,
+    pub damage: Option<String>,
+    /// Total frames, from `AttackDuration`
+    pub duration: Option<String>,
+    pub advantage_hit: Option<String>,
+    pub advantage_block: Option<String>,
+    pub description: Option<String>,
+}
+
+impl AttackData {
+    /// Read the attack set up by a state, `None` if it registers no attack
+    pub fn from_state(state: &ParsedState) -> Option<Self> {
+        let mut actions: Vec<&ParsedAction> = state.actions.values().flatten().collect();
+        actions.sort_by_key(|action| action.line_number);
+
+        if !actions
+            .iter()
+            .any(|action| REGISTER_INSTRUCTIONS.contains(&action.instruction.as_str()))
+        {
+            return None;
+        }
+
+        let mut data = AttackData {
+            state: state.name.to_string(),
+            input: state.attack.as_ref().map(|_| state.name.to_string()),
+            description: state.description.clone(),
+            ..Default::default()
+        };
+
+        for action in actions {
+            let arg = |index: usize| action.args.get(index).map(str::to_string);
+            match action.instruction.as_str() {
+                "AttackRegister" => {
+                    data.attack_type = arg(0);
+                    if let Some(notation) = arg(1) {
+                        data.input = Some(notation);
+                    }
+                }
+                "AttackRegisterNoNotation" => data.attack_type = arg(0),
+                "AttackDamage" => data.damage = arg(0),
+                "AttackDuration" => data.duration = arg(0),
+                "AttackFrameAdvantage" => {
+                    data.advantage_hit = arg(0);
+                    data.advantage_block = arg(1).or_else(|| arg(0));
+                }
+                "AttackFrameAdvantageHit" => data.advantage_hit = arg(0),
+                "AttackFrameAdvantageBlock" => data.advantage_block = arg(0),
+                _ => {}
+            }
+        }
+
+        Some(data)
+    }
+}
+
+/// Frames of an `F` branch: `F5:`, `F3-6:`, `F5+:` or `F2%4:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRange {
+    pub start: usize,
+    /// Last frame, `None` for every frame from `start`
+    pub end: Option<usize>,
+    /// Length of the cycle the frame is taken modulo of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modulo: Option<usize>,
+}
+
+impl FrameRange {
+    /// Range of a branch action (`F3-6:`), with defines replaced by their
+    /// value; `None` if it isn't a frame branch, `Some(None)` if it names
+    /// something other than a number or a define
+    pub(crate) fn of_branch(
+        character: &ParsedCharacter,
+        instruction: &str,
+    ) -> Option<Option<FrameRange>> {
+        Self::of_branch_in(&character.variables, instruction)
+    }
+
+    /// Range of a branch action, with defines taken from `variables`
+    pub(crate) fn of_branch_in(
+        variables: &HashMap<Symbol, ParsedVariable>,
+        instruction: &str,
+    ) -> Option<Option<FrameRange>> {
+        let range = instruction.strip_suffix(':')?.strip_prefix('F')?;
+        let frame = |text: &str| -> Option<usize> {
+            let text = text.trim();
+            text.parse()
+                .ok()
+                .or_else(|| variables.get(text)?.value.trim().parse().ok())
+        };
+        let parse = || -> Option<FrameRange> {
+            let (range, modulo) = match range.split_once('%') {
+                Some((range, modulo)) => (range, Some(frame(modulo)?)),
+                None => (range, None),
+            };
+            let (start, end) = match range.strip_suffix('+') {
+                Some(start) => (frame(start)?, None),
+                None => match range.split_once('-') {
+                    Some((start, end)) => (frame(start)?, Some(frame(end)?)),
+                    None => (frame(range)?, Some(frame(range)?)),
+                },
+            };
+            Some(FrameRange { start, end, modulo })
+        };
+        Some(parse())
+    }
+
+    /// Whether the branch runs on a frame of the state, 1 being the first
+    pub fn contains(&self, frame: usize) -> bool {
+        let frame = match self.modulo {
+            Some(0) | None => frame,
+            Some(modulo) => match frame % modulo {
+                0 => modulo,
+                rest => rest,
+            },
+        };
+        frame >= self.start && self.end.is_none_or(|end| frame <= end)
+    }
+
+    /// Number of frames of a state the branch runs on, `None` if it keeps
+    /// running for as long as the state lasts
+    pub fn frame_count(&self) -> Option<usize> {
+        match (self.end, self.modulo) {
+            (Some(end), None) => Some((end + 1).saturating_sub(self.start)),
+            _ => None,
+        }
+    }
+}
+
+/// Frames an enclosing `F` branch scopes an action to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameWindow {
+    /// `None` if the branch names something other than a number or a define
+    pub range: Option<FrameRange>,
+    /// In the `else` of the branch: every frame outside the range
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inverted: bool,
+}
+
+impl FrameWindow {
+    /// Whether the window holds a frame of the state, 1 being the first;
+    /// `None` if its range is unknown
+    pub fn contains(&self, frame: usize) -> Option<bool> {
+        Some(self.range?.contains(frame) != self.inverted)
+    }
+}
+
+impl ParsedAction {
+    /// Whether the action runs on a frame of the state, as far as its
+    /// frame windows tell; `None` if one of them is unknown and none
+    /// excludes the frame
+    pub fn runs_on_frame(&self, frame: usize) -> Option<bool> {
+        let mut known = true;
+        for window in &self.frames {
+            match window.contains(frame) {
+                Some(false) => return Some(false),
+                Some(true) => {}
+                None => known = false,
+            }
+        }
+        known.then_some(true)
+    }
+}
+
+/// Fill the frame windows of the actions of a state, with defines taken
+/// from `variables`. An action gets the windows of the branches open when
+/// it is reached, outermost first: a branch's own action, `else` and
+/// `endif` included, and the actions of its body all do
+pub(crate) fn scope_frames(variables: &HashMap<Symbol, ParsedVariable>, state: &mut ParsedState) {
+    for actions in state.actions.values_mut() {
+        // Windows of the open branches, `None` for those not on frames
+        let mut open: Vec<Option<FrameWindow>> = Vec::new();
+        for action in actions {
+            action.frames = open.iter().flatten().copied().collect();
+            let instruction = &*action.instruction;
+            if instruction.eq_ignore_ascii_case("endif") {
+                open.pop();
+            } else if instruction.eq_ignore_ascii_case("else") {
+                if let Some(Some(window)) = open.last_mut() {
+                    window.inverted = !window.inverted;
+                }
+            } else if is_branch(instruction) {
+                let window =
+                    FrameRange::of_branch_in(variables, instruction).map(|range| FrameWindow {
+                        range,
+                        inverted: false,
+                    });
+                open.push(window);
+            }
+        }
+    }
+}
+
+/// Whether an action opens a branch: `LFlag:`, `F5:` or an `If` call
+pub(crate) fn is_branch(instruction: &str) -> bool {
+    instruction.ends_with(':')
+        || instruction
+            .strip_prefix("If")
+            .is_some_and(|rest| rest.chars().next().is_none_or(char::is_uppercase))
+}
+
+/// All attacks of a character, sorted by state name
+pub fn attacks(character: &ParsedCharacter) -> Vec<AttackData> {
+    let mut attacks: Vec<AttackData> = character
+        .states
+        .values()
+        .filter_map(AttackData::from_state)
+        .collect();
+    attacks.sort_by(|a, b| a.state.cmp(&b.state));
+    attacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_attack_data_from_actions() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":5A:
+## Quick jab
+---Init:
+AttackRegister(Light)
+AttackDamage(300)
+AttackDuration(18)
+AttackFrameAdvantage(3, -1)
+:Fireball:
+---Init:
+AttackRegister(Special, 236C)
+AttackFrameAdvantage(2)
+:Idle:
+---Init:
+Move(1)
+",
+            )
+            .unwrap();
+
+        let attacks = attacks(&character);
+
+        assert_eq!(
+            attacks,
+            vec![
+                AttackData {
+                    state: "5A".to_string(),
+                    input: Some("5A".to_string()),
+                    attack_type: Some("Light".to_string()),
+                    damage: Some("300".to_string()),
+                    duration: Some("18".to_string()),
+                    advantage_hit: Some("3".to_string()),
+                    advantage_block: Some("-1".to_string()),
+                    description: Some("Quick jab".to_string()),
+                },
+                AttackData {
+                    state: "Fireball".to_string(),
+                    input: Some("236C".to_string()),
+                    attack_type: Some("Special".to_string()),
+                    advantage_hit: Some("2".to_string()),
+                    advantage_block: Some("2".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frame_ranges() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":Variables:\ndef Startup: 4\n:Idle:\n---Init:\nStop\n",
+            )
+            .unwrap();
+        let range = |branch: &str| FrameRange::of_branch(&character, branch).unwrap();
+
+        let exact = range("F5:").unwrap();
+        assert!(exact.contains(5) && !exact.contains(6));
+        assert_eq!(exact.frame_count(), Some(1));
+        let window = range("F2-Startup:").unwrap();
+        assert!(window.contains(4) && !window.contains(1));
+        assert_eq!(window.frame_count(), Some(3));
+        let from = range("F3+:").unwrap();
+        assert!(from.contains(300) && from.frame_count().is_none());
+        let cycle = range("F2%4:").unwrap();
+        assert!(cycle.contains(6) && !cycle.contains(7));
+        assert_eq!(range("FUnknown:"), None);
+        assert!(FrameRange::of_branch(&character, "LFlag:").is_none());
+    }
+
+    #[test]
+    fn test_action_frame_windows() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":Variables:
+def Active: 6
+:5B:
+---Action:
+Anim(5B)
+F3-Active:
+LHit:
+AttackRegister(Medium)
+endif
+else
+Move(1)
+endif
+FMissing:
+Stop
+endif
+",
+            )
+            .unwrap();
+        let actions = &character.states["5B"].actions["Action"];
+        let frames = |index: usize| -> &[FrameWindow] { &actions[index].frames };
+        let window = FrameRange {
+            start: 3,
+            end: Some(6),
+            modulo: None,
+        };
+
+        assert!(frames(0).is_empty() && frames(1).is_empty());
+        // LHit: doesn't scope to frames, but its body is still in F3-6:
+        assert_eq!(frames(3).len(), 1);
+        assert_eq!(frames(3)[0].range, Some(window));
+        assert_eq!(actions[3].runs_on_frame(4), Some(true));
+        assert_eq!(actions[3].runs_on_frame(7), Some(false));
+        // The else runs on the other frames
+        assert!(frames(6)[0].inverted);
+        assert_eq!(actions[6].runs_on_frame(7), Some(true));
+        assert!(frames(8).is_empty());
+        assert_eq!(frames(9)[0].range, None);
+        assert_eq!(actions[9].runs_on_frame(1), None);
+    }
+}
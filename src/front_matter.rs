@@ -0,0 +1,364 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Front Matter - TOML or YAML in place of the `:Character:` block
+//!
+//! Teams generating metadata from an external pipeline can start a file
+//! with front-matter instead of a `:Character:` block: TOML between `+++`
+//! lines or in a ` ```toml ` fence, YAML between `---` lines or in a
+//! ` ```yaml ` fence. Each key becomes a metadata field, `Name` and the
+//! other known ones included, and everything else lands in `other_fields`:
+//! keys of nested tables as `table.key`, lists joined with `, `.
+//!
+//! The front-matter is read with the `toml` and `serde_yaml` crates, then
+//! rewritten in place into a `:Character:` block with one quoted
+//! `Key: "value"` line per field, on the line declaring the key, so line
+//! numbers don't move and the metadata goes through the usual parsing.
+
+use crate::string_literal;
+
+/// Language of a front-matter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+/// A key read from a front-matter, with its table keys before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    path: Vec<String>,
+    value: Result<String, String>,
+}
+
+/// Rewrite a front-matter opening the file into a `:Character:` block
+///
+/// Returns each error with the index of its line.
+pub(crate) fn rewrite(lines: &mut [String]) -> Vec<(usize, String)> {
+    let Some(start) = lines.iter().position(|line| !line.trim().is_empty()) else {
+        return Vec::new();
+    };
+    let (format, closers): (Format, &[&str]) = match lines[start].trim() {
+        "+++" => (Format::Toml, &["+++"]),
+        "---" => (Format::Yaml, &["---", "..."]),
+        "```toml" => (Format::Toml, &["```"]),
+        "```yaml" | "```yml" => (Format::Yaml, &["```"]),
+        _ => return Vec::new(),
+    };
+    let Some(end) = (start + 1..lines.len()).find(|i| closers.contains(&lines[*i].trim())) else {
+        return vec![(start, "Unterminated front-matter".to_string())];
+    };
+
+    let body = lines[start + 1..end].to_vec();
+    let text = body.join("\n");
+    let mut errors = Vec::new();
+    let fields = match format {
+        Format::Toml => parse_toml(&text),
+        Format::Yaml => parse_yaml(&text),
+    };
+    let fields = fields.unwrap_or_else(|(offset, message)| {
+        let line = text[..offset.min(text.len())].matches('\n').count();
+        errors.push((start + 1 + line.min(body.len().saturating_sub(1)), message));
+        Vec::new()
+    });
+
+    lines[start] = ":Character:".to_string();
+    for line in &mut lines[start + 1..=end] {
+        line.clear();
+    }
+    let mut used = vec![false; body.len()];
+    for field in fields {
+        let Some(line) = field_line(&body, &field.path, &used) else {
+            errors.push((start, "Front-matter has more fields than lines".to_string()));
+            continue;
+        };
+        used[line] = true;
+        let index = start + 1 + line;
+        let key = field.path.join(".");
+        match field.value {
+            Ok(_) if key.contains(':') => {
+                errors.push((
+                    index,
+                    format!("Front-matter key `{}` can't hold a colon", key),
+                ));
+            }
+            Ok(value) => lines[index] = format!("{}: {}", key, string_literal::encode(&value)),
+            Err(message) => errors.push((index, message)),
+        }
+    }
+    errors
+}
+
+/// Fields of a TOML front-matter, or the byte offset and message of its
+/// syntax error
+fn parse_toml(text: &str) -> Result<Vec<Field>, (usize, String)> {
+    let table = toml::from_str::<toml::Table>(text).map_err(|e| {
+        let offset = e.span().map_or(0, |span| span.start);
+        (offset, e.message().trim().to_string())
+    })?;
+    let mut fields = Vec::new();
+    toml_fields(&mut Vec::new(), &table, &mut fields);
+    Ok(fields)
+}
+
+fn toml_fields(path: &mut Vec<String>, table: &toml::Table, fields: &mut Vec<Field>) {
+    for (key, value) in table {
+        path.push(key.clone());
+        match value {
+            toml::Value::Table(table) => toml_fields(path, table, fields),
+            toml::Value::Array(items) => fields.push(Field {
+                path: path.clone(),
+                value: items
+                    .iter()
+                    .map(toml_item)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|items| items.join(", ")),
+            }),
+            value => fields.push(Field {
+                path: path.clone(),
+                value: toml_item(value),
+            }),
+        }
+        path.pop();
+    }
+}
+
+/// A TOML value that isn't a table, as text
+fn toml_item(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(text) => Ok(text.clone()),
+        toml::Value::Table(_) => Err("Arrays of tables aren't supported in front-matter".into()),
+        toml::Value::Array(_) => Err("Nested lists aren't supported in front-matter".into()),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// Fields of a YAML front-matter, or the byte offset and message of its
+/// syntax error
+fn parse_yaml(text: &str) -> Result<Vec<Field>, (usize, String)> {
+    let value = serde_yaml::from_str::<serde_yaml::Value>(text).map_err(|e| {
+        let offset = e.location().map_or(0, |location| location.index());
+        // The message ends with a position in the front-matter, not the file
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or_default();
+        (offset, message.to_string())
+    })?;
+    let mut fields = Vec::new();
+    match value {
+        serde_yaml::Value::Null => {}
+        serde_yaml::Value::Mapping(mapping) => yaml_fields(&mut Vec::new(), &mapping, &mut fields),
+        _ => return Err((0, "Front-matter must be a mapping".into())),
+    }
+    Ok(fields)
+}
+
+fn yaml_fields(path: &mut Vec<String>, mapping: &serde_yaml::Mapping, fields: &mut Vec<Field>) {
+    for (key, value) in mapping {
+        let key = match yaml_item(key) {
+            Ok(key) => key,
+            Err(_) => {
+                fields.push(Field {
+                    path: path.clone(),
+                    value: Err("Front-matter keys must be plain values".into()),
+                });
+                continue;
+            }
+        };
+        path.push(key);
+        match value {
+            serde_yaml::Value::Mapping(mapping) => yaml_fields(path, mapping, fields),
+            serde_yaml::Value::Sequence(items) => fields.push(Field {
+                path: path.clone(),
+                value: items
+                    .iter()
+                    .map(yaml_item)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|items| items.join(", ")),
+            }),
+            value => fields.push(Field {
+                path: path.clone(),
+                value: yaml_item(value),
+            }),
+        }
+        path.pop();
+    }
+}
+
+/// A YAML value that isn't a mapping, as text
+fn yaml_item(value: &serde_yaml::Value) -> Result<String, String> {
+    match value {
+        serde_yaml::Value::Null => Ok(String::new()),
+        serde_yaml::Value::Bool(value) => Ok(value.to_string()),
+        serde_yaml::Value::Number(value) => Ok(value.to_string()),
+        serde_yaml::Value::String(text) => Ok(text.clone()),
+        serde_yaml::Value::Mapping(_) => {
+            Err("Lists of mappings aren't supported in front-matter".into())
+        }
+        serde_yaml::Value::Sequence(_) => {
+            Err("Nested lists aren't supported in front-matter".into())
+        }
+        serde_yaml::Value::Tagged(_) => {
+            Err("Tagged values aren't supported in front-matter".into())
+        }
+    }
+}
+
+/// Free line of the body to write a field on: the line declaring its last
+/// key, each key searched from the line of the one before, or the nearest
+/// free line when that one is taken or not found
+fn field_line(body: &[String], path: &[String], used: &[bool]) -> Option<usize> {
+    let mut from = 0;
+    for key in path {
+        if let Some(line) = (from..body.len()).find(|&i| declares(&body[i], key)) {
+            from = line;
+        }
+    }
+    (from..body.len())
+        .chain((0..from).rev())
+        .find(|&i| !used[i])
+}
+
+/// Whether a line holds `key` followed by what follows a key in TOML or YAML
+fn declares(line: &str, key: &str) -> bool {
+    !key.is_empty()
+        && line.match_indices(key).any(|(at, _)| {
+            let before = line[..at].trim_end_matches(['"', '\'']).chars().next_back();
+            let after = line[at + key.len()..]
+                .trim_start_matches(['"', '\''])
+                .trim_start()
+                .chars()
+                .next();
+            matches!(before, None | Some(' ' | '\t' | '.' | '[' | '{' | ','))
+                && matches!(after, Some('=' | ':' | '.' | ']'))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    fn rewritten(source: &str) -> (Vec<String>, Vec<(usize, String)>) {
+        let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+        let errors = rewrite(&mut lines);
+        (lines, errors)
+    }
+
+    #[test]
+    fn test_front_matter_formats() {
+        let toml = "
++++
+# Generated
+Name = \"Ryu\"   # the name
+Author = 'Team # 1'
+Tags = [\"rushdown\",
+  \"shoto\", # shared
+]
+Description = \"\"\"
+Wandering \"warrior\"
+with a headband\"\"\"
+Version = 3
+Balance.Health = 10000
+[Palette]
+Main = { hue = 12, mode = \"hsv\" }
++++
+:Stand:";
+        let yaml = "```yaml
+# Generated
+Name: Ryu   # the name
+Author: 'Team # 1'
+Tags:
+  - rushdown
+  - \"shoto\" # shared
+
+Description: |-
+  Wandering \"warrior\"
+  with a headband
+Version: 3
+Balance:
+  Health: 10000
+Palette:
+  Main: { hue: 12, mode: hsv }
+```
+:Stand:";
+        for source in [toml, yaml] {
+            let mut parser = CastagneParser::new();
+            let character = parser
+                .create_full_character_from_source("ryu.casp", source)
+                .unwrap();
+            assert!(parser.get_errors().is_empty(), "{:?}", parser.get_errors());
+            let metadata = &character.metadata;
+            assert_eq!(metadata.name, "Ryu");
+            assert_eq!(metadata.author, "Team # 1");
+            assert_eq!(metadata.other_fields["Tags"], "rushdown, shoto");
+            assert_eq!(
+                metadata.description,
+                "Wandering \"warrior\"\nwith a headband"
+            );
+            assert_eq!(metadata.version.as_deref(), Some("3"));
+            assert_eq!(metadata.other_fields["Balance.Health"], "10000");
+            assert_eq!(metadata.other_fields["Palette.Main.hue"], "12");
+            assert_eq!(metadata.other_fields["Palette.Main.mode"], "hsv");
+            assert!(character.states.contains_key("Stand"));
+        }
+
+        // Line numbers don't move
+        let (lines, errors) = rewritten(toml);
+        assert!(errors.is_empty());
+        assert_eq!(lines[1], ":Character:");
+        assert_eq!(lines[3], "Name: \"Ryu\"");
+        assert_eq!(lines[5], "Tags: \"rushdown, shoto\"");
+        assert_eq!(lines[6], "");
+        assert_eq!(lines[13], "Palette.Main.mode: \"hsv\"");
+        assert_eq!(lines[14], "Palette.Main.hue: \"12\"");
+        assert_eq!(lines[16], ":Stand:");
+    }
+
+    #[test]
+    fn test_front_matter_yaml_scalars() {
+        let (lines, errors) = rewritten(
+            "---\nTagline: >\n  Fast\n  and strong\n\n  Really\nKeep: |+\n  a\n\nQuote: 'It''s'\nEmpty:\n...",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(lines[1], "Tagline: \"Fast and strong\\nReally\\n\"");
+        assert_eq!(lines[6], "Keep: \"a\\n\\n\"");
+        assert_eq!(lines[9], "Quote: \"It's\"");
+        assert_eq!(lines[10], "Empty: \"\"");
+        assert_eq!(lines[11], "");
+    }
+
+    #[test]
+    fn test_front_matter_errors() {
+        // Not front-matter: the file is left alone
+        let (lines, errors) = rewritten(":Character:\nName: Ryu");
+        assert_eq!(lines, [":Character:", "Name: Ryu"]);
+        assert!(errors.is_empty());
+
+        let (_, errors) = rewritten("+++\nName = \"Ryu\"");
+        assert_eq!(errors, [(0, "Unterminated front-matter".to_string())]);
+
+        // Syntax errors are reported on their line, and drop the fields
+        let (lines, errors) = rewritten("+++\nName = \"Ryu\"\nOops\n+++\n:Stand:");
+        assert_eq!(lines, [":Character:", "", "", "", ":Stand:"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 2);
+        let (_, errors) = rewritten("---\nName: Ryu\n Tags: [a\n---");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 2);
+
+        let (lines, errors) =
+            rewritten("+++\nName = \"Ryu\"\n\"a:b\" = 1\n[[moves]]\nDamage = 1\n+++");
+        assert_eq!(lines[1], "Name: \"Ryu\"");
+        assert_eq!(
+            errors,
+            [
+                (2, "Front-matter key `a:b` can't hold a colon".to_string()),
+                (
+                    3,
+                    "Arrays of tables aren't supported in front-matter".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fuzz - Random .casp files and entry points for fuzzers
+//!
+//! Community files are hand-edited and often half-broken; none of them
+//! should make the parser panic or hang. The property tests below
+//! generate files following the .casp structure (metadata, variables,
+//! specblocks, templates, states with branches) with proptest, then
+//! damage them, so they reach the recovery paths that well-formed samples
+//! never take; a failing file is shrunk to a small one. `parse_bytes` is
+//! the entry point for coverage-guided fuzzers such as cargo-fuzz.
+
+use crate::parser::{CastagneParser, JsonFormat};
+
+/// Parse arbitrary bytes as a .casp file, for fuzzers: it must return,
+/// whatever the input
+pub fn parse_bytes(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    let mut parser = CastagneParser::new();
+    if let Ok(character) = parser.create_full_character_from_source("fuzz.casp", &text) {
+        let _ = character.to_json(JsonFormat::Internal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::option::weighted;
+    use proptest::prelude::*;
+    use proptest::sample::{select, Index};
+
+    /// Characters most likely to break line splitting and slicing
+    const INTERESTING: &[&str] = &[
+        ":", "(", ")", ",", "\"", "'", "#", "\\", "/*", "*/", "\"\"\"", "---", "é", "🥊", "\t",
+        "\r", "\u{0}", "F", "endif", "else", "%", "-", "+", "___", "\n",
+    ];
+
+    const NAMES: &[&str] = &[
+        "Idle", "5A", "2B", "Jab", "Walk", "Base", "Fireball", "AI-Init", "Hadoken", "X", "é",
+    ];
+
+    const INSTRUCTIONS: &[&str] = &[
+        "Call",
+        "CallParent",
+        "Set",
+        "Flag",
+        "AttackRegister",
+        "AttackDamage",
+        "AttackDuration",
+        "Anim",
+        "Sprite",
+        "CreateEntity",
+        "UseTemplate",
+        "Transition",
+        "SFXPlay",
+    ];
+
+    const TYPES: &[&str] = &[
+        "Int", "Float", "Str", "Bool", "Vec2", "Vec3", "Box", "Var", "Color", "Nope",
+    ];
+
+    fn name() -> impl Strategy<Value = &'static str> {
+        select(NAMES)
+    }
+
+    fn number() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("0".to_string()),
+            Just(i64::MAX.to_string()),
+            (0..1000u32).prop_map(|n| format!("-{}", n)),
+            (0..0xFFFFu32).prop_map(|n| format!("0x{:X}", n)),
+            (0..10u32, 0..100u32).prop_map(|(whole, fraction)| format!("{}.{}", whole, fraction)),
+            (0..1000u32).prop_map(|n| n.to_string()),
+        ]
+    }
+
+    fn variable() -> impl Strategy<Value = String> {
+        let value = prop_oneof![
+            number(),
+            name().prop_map(|name| format!("\"{}\"", name)),
+            (number(), number()).prop_map(|(x, y)| format!("({}, {})", x, y)),
+            name().prop_map(|name| format!("{} * 2", name)),
+        ];
+        (
+            select(&["var", "def", "internal"][..]),
+            name(),
+            select(TYPES),
+            value,
+        )
+            .prop_map(|(keyword, name, var_type, value)| {
+                format!("{} {}({}): {}", keyword, name, var_type, value)
+            })
+    }
+
+    fn specblock() -> impl Strategy<Value = Vec<String>> {
+        let field = (name(), name(), number())
+            .prop_map(|(first, second, value)| format!("KEY_{}___{}: {}", first, second, value));
+        (
+            select(&["Graphics", "Anims", "AI", "Audio"][..]),
+            vec(field, 0..4),
+        )
+            .prop_map(|(block, fields)| {
+                std::iter::once(format!(":{}:", block))
+                    .chain(fields)
+                    .collect()
+            })
+    }
+
+    /// A line of a phase; `endif` and `else` only count inside a branch
+    #[derive(Debug, Clone)]
+    enum Line {
+        Branch(String),
+        Endif,
+        Else,
+        Action(String),
+    }
+
+    fn line() -> impl Strategy<Value = Line> {
+        let branch = prop_oneof![
+            (0..20u32).prop_map(|frame| format!("F{}:", frame)),
+            (0..20u32, name()).prop_map(|(start, end)| format!("F{}-{}:", start, end)),
+            name().prop_map(|flag| format!("L{}:", flag)),
+            (name(), number()).prop_map(|(name, value)| format!("IfLt({}, {}):", name, value)),
+        ];
+        let arg = prop_oneof![
+            number(),
+            name().prop_map(|name| format!("\"{}\\n\"", name)),
+            name().prop_map(str::to_string),
+        ];
+        let action = (select(INSTRUCTIONS), vec(arg, 0..4))
+            .prop_map(|(instruction, args)| format!("{}({})", instruction, args.join(", ")));
+        prop_oneof![
+            1 => branch.prop_map(Line::Branch),
+            1 => Just(Line::Endif),
+            1 => Just(Line::Else),
+            5 => action.prop_map(Line::Action),
+        ]
+    }
+
+    fn phase() -> impl Strategy<Value = Vec<String>> {
+        (
+            select(&["Init", "Action", "AI", "Reaction", "Nope"][..]),
+            vec(line(), 0..8),
+            // Sometimes leave branches open on purpose
+            prop::bool::weighted(0.8),
+        )
+            .prop_map(|(phase, body, close)| {
+                let mut lines = vec![format!("---{}:", phase)];
+                let mut open = 0;
+                for line in body {
+                    match line {
+                        Line::Branch(branch) => {
+                            lines.push(branch);
+                            open += 1;
+                        }
+                        Line::Endif if open > 0 => {
+                            lines.push("endif".to_string());
+                            open -= 1;
+                        }
+                        Line::Else if open > 0 => lines.push("else".to_string()),
+                        Line::Action(action) => lines.push(action),
+                        Line::Endif | Line::Else => {}
+                    }
+                }
+                if close {
+                    lines.extend((0..open).map(|_| "endif".to_string()));
+                }
+                lines
+            })
+    }
+
+    fn state() -> impl Strategy<Value = Vec<String>> {
+        let suffix = select(&["", "(Helper)", "(BaseState)", "(Override)", "(Base)"][..]);
+        (name(), suffix, weighted(0.3, name()), vec(phase(), 0..3)).prop_map(
+            |(name, suffix, description, phases)| {
+                let mut lines = vec![format!(":{}{}:", name, suffix)];
+                lines.extend(description.map(|description| format!("## {}", description)));
+                lines.extend(phases.into_iter().flatten());
+                lines
+            },
+        )
+    }
+
+    /// A file following the .casp structure, as lines
+    fn casp() -> impl Strategy<Value = Vec<String>> {
+        (
+            name(),
+            weighted(0.2, select(&["none", "", "missing.casp"][..])),
+            weighted(0.7, vec(variable(), 0..5)),
+            vec(specblock(), 0..3),
+            weighted(0.3, name()),
+            weighted(0.2, name()),
+            vec(state(), 0..6),
+        )
+            .prop_map(
+                |(name, skeleton, variables, specblocks, template, subentity, states)| {
+                    let mut lines = vec![":Character:".to_string(), format!("Name: {}", name)];
+                    lines.extend(skeleton.map(|skeleton| format!("Skeleton: {}", skeleton)));
+                    if let Some(variables) = variables {
+                        lines.push(":Variables:".to_string());
+                        lines.extend(variables);
+                    }
+                    lines.extend(specblocks.into_iter().flatten());
+                    if let Some(template) = template {
+                        lines.push(format!(":Template {}(A, B):", template));
+                        lines.push("Set(A, B)".to_string());
+                    }
+                    if let Some(subentity) = subentity {
+                        lines.push(format!(":{}---Subentity:", subentity));
+                        lines.push("Skeleton: none".to_string());
+                    }
+                    lines.extend(states.into_iter().flatten());
+                    lines
+                },
+            )
+    }
+
+    /// An edit damaging one line of a file
+    #[derive(Debug, Clone)]
+    enum Mutation {
+        Insert(Index, Index, &'static str),
+        Truncate(Index, Index),
+        Cut(Index, Index, Index),
+        Duplicate(Index),
+        Swap(Index, Index),
+        Remove(Index),
+    }
+
+    fn mutation() -> impl Strategy<Value = Mutation> {
+        let index = any::<Index>;
+        prop_oneof![
+            (index(), index(), select(INTERESTING))
+                .prop_map(|(line, at, text)| Mutation::Insert(line, at, text)),
+            (index(), index()).prop_map(|(line, at)| Mutation::Truncate(line, at)),
+            (index(), index(), index()).prop_map(|(line, at, end)| Mutation::Cut(line, at, end)),
+            index().prop_map(Mutation::Duplicate),
+            (index(), index()).prop_map(|(line, other)| Mutation::Swap(line, other)),
+            index().prop_map(Mutation::Remove),
+        ]
+    }
+
+    fn mutate(lines: &mut Vec<String>, mutation: &Mutation) {
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let boundaries = |line: &str| -> Vec<usize> {
+            line.char_indices()
+                .map(|(at, _)| at)
+                .chain([line.len()])
+                .collect()
+        };
+        match mutation {
+            Mutation::Insert(line, at, text) => {
+                let index = line.index(lines.len());
+                let line = &mut lines[index];
+                let at = *at.get(&boundaries(line));
+                line.insert_str(at, text);
+            }
+            Mutation::Truncate(line, at) => {
+                let index = line.index(lines.len());
+                let line = &mut lines[index];
+                let at = *at.get(&boundaries(line));
+                line.truncate(at);
+            }
+            Mutation::Cut(line, at, end) => {
+                let index = line.index(lines.len());
+                let line = &mut lines[index];
+                let boundaries = boundaries(line);
+                let at = *at.get(&boundaries);
+                let end = (*end.get(&boundaries)).max(at);
+                line.replace_range(at..end, "");
+            }
+            Mutation::Duplicate(line) => {
+                let index = line.index(lines.len());
+                lines.insert(index, lines[index].clone());
+            }
+            Mutation::Swap(line, other) => {
+                let (index, other) = (line.index(lines.len()), other.index(lines.len()));
+                lines.swap(index, other);
+            }
+            Mutation::Remove(line) => {
+                lines.remove(line.index(lines.len()));
+            }
+        }
+    }
+
+    /// A .casp file damaged by up to 12 edits
+    fn damaged_casp() -> impl Strategy<Value = String> {
+        (casp(), vec(mutation(), 0..12)).prop_map(|(mut lines, mutations)| {
+            for mutation in &mutations {
+                mutate(&mut lines, mutation);
+            }
+            lines.join("\n")
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(400))]
+
+        #[test]
+        fn test_generated_files_parse_without_panicking(source in damaged_casp()) {
+            parse_bytes(source.as_bytes());
+        }
+
+        #[test]
+        fn test_arbitrary_bytes_parse_without_panicking(
+            bytes in vec(
+                prop_oneof![
+                    1 => select(INTERESTING).prop_map(|text| text.as_bytes()[0]),
+                    2 => any::<u8>(),
+                ],
+                0..200,
+            )
+        ) {
+            parse_bytes(&bytes);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Generic States - States with parameters, instantiated by other states
+//!
+//! EX and strength versions of a move usually differ by a few values. A
+//! state header can list parameters between angle brackets, and a state
+//! starting with `UseState` before its phases becomes a copy of it with
+//! the values it gives:
+//!
+//! ```text
+//! :Fireball<Speed, Damage>(Helper):
+//! ---Init:
+//! Move(Speed)
+//! AttackDamage(Damage)
+//!
+//! :FireballEX:
+//! UseState(Fireball, 20, 150)
+//! ```
+//!
+//! As with templates, parameter names in the arguments and `let` values,
+//! expressions included, are replaced by the call's values, and the
+//! actions take the line of the `UseState`. The copy keeps the type and parent of the generic state
+//! unless its own header gives some, and phases written after `UseState`
+//! add to the copied ones.
+//!
+//! Generic states aren't states of the character: they are kept in
+//! `ParsedCharacter::generic_states`, so files inheriting them can use them
+//! too.
+
+use crate::parser::{substitute_params, ParsedState};
+use serde::{Deserialize, Serialize};
+
+/// Instruction filling a state from a generic state
+pub(crate) const USE_STATE: &str = "UseState";
+
+/// A state defined with `:Name<Params>:`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenericState {
+    pub params: Vec<String>,
+    /// The state as written, parameters unreplaced
+    pub state: ParsedState,
+}
+
+impl GenericState {
+    /// Phases and locals of the generic state with `values` in place of
+    /// its parameters, copied into `state`, actions on `line`
+    pub(crate) fn instantiate(&self, values: &[&str], line: usize, state: &mut ParsedState) {
+        let replace = |value: &str| substitute_params(value, &self.params, values);
+        let mut actions = self.state.actions.clone();
+        for action in actions.values_mut().flatten() {
+            action.args = action.args.iter().map(replace).collect();
+            action.line_number = line;
+        }
+        let mut locals = self.state.locals.clone();
+        for local in &mut locals {
+            local.value = replace(&local.value);
+            local.line_number = line;
+        }
+        state.actions = actions;
+        state.locals = locals;
+        if state.description.is_none() {
+            state.description = self.state.description.clone();
+        }
+    }
+}
+
+/// Header without its `<Params>`, and the parameters, if it has some
+pub(crate) fn split_generic_header(header: &str) -> Option<(String, Vec<String>)> {
+    let open = header.find('<')?;
+    let close = open + header[open..].find('>')?;
+    if header[..open].contains('(') {
+        return None;
+    }
+    let params = header[open + 1..close]
+        .split(',')
+        .map(|param| param.trim().to_string())
+        .filter(|param| !param.is_empty())
+        .collect();
+    let header = format!("{}{}", header[..open].trim(), &header[close + 1..]);
+    Some((header, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{CastagneParser, StateType};
+
+    #[test]
+    fn test_generic_states() {
+        let source = "\
+:Character:
+Name: Tester
+:Fireball<Speed, Damage>(Helper):
+## Projectile
+---Init:
+let Boost: Speed
+Move(Speed)
+AttackDamage(Damage)
+AttackFrameAdvantage(Speed / 2, Add(Damage, 1))
+:FireballL:
+UseState(Fireball, 10, 100)
+:FireballEX(Special):
+UseState(Fireball, 20, 150)
+---Action:
+Flash
+:Bad:
+UseState(Fireball, 1)
+UseState(Nothing)
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("tester.casp", source)
+            .unwrap();
+        assert!(!character.states.contains_key("Fireball"));
+        assert_eq!(
+            character.generic_states["Fireball"].params,
+            ["Speed", "Damage"]
+        );
+
+        let light = &character.states["FireballL"];
+        assert_eq!(light.state_type, StateType::Helper);
+        assert_eq!(light.description.as_deref(), Some("Projectile"));
+        let init = &light.actions["Init"];
+        assert_eq!(init[0].args, ["10"]);
+        assert_eq!(init[1].args, ["100"]);
+        assert_eq!(init[2].args, ["10 / 2", "Add(100, 1)"]);
+        assert_eq!(init[0].line_number, 11);
+        assert_eq!(light.locals[0].value, "10");
+
+        let ex = &character.states["FireballEX"];
+        assert_eq!(ex.state_type, StateType::Special);
+        assert_eq!(ex.actions["Init"][1].args, ["150"]);
+        assert_eq!(ex.actions["Action"][0].instruction, "Flash");
+
+        assert_eq!(
+            parser.get_errors(),
+            [
+                "Generic state Fireball expects 2 arguments, got 1 (line 17)",
+                "Unknown generic state Nothing (line 18)",
+            ]
+        );
+    }
+}
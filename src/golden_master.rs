@@ -0,0 +1,172 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Golden Master - A character in the JSON layout of the GDScript parser
+//!
+//! `ParsedCharacter` serializes its own fields, in snake_case. The golden
+//! masters exported from the GDScript parser name them differently, so
+//! `to_golden_master` writes a character in their layout, to compare the
+//! two parsers value for value; `to_json(JsonFormat::Engine)` writes it
+//! as text:
+//!
+//! ```text
+//! metadata        lowercase Character keys, only those set
+//! variables       Name -> {Name, Value, Type, Subtype, Mutability}
+//! states          Name -> {Parent, Type, TransitionFlags, Phases}
+//! Phases          Phase -> {Actions: [{function, args}]}
+//! ```
+//!
+//! Values are written as the GDScript parser stores them: booleans are `1`
+//! or `0`, defines are `Constant`, and a normal state has a `null` type.
+//! Flags go back to the end of the arguments with their `+`. Subentity
+//! variables have no place in this layout and are left out.
+
+use crate::parser::{
+    CharacterMetadata, ParsedAction, ParsedCharacter, ParsedState, ParsedVariable, StateType,
+    VariableType,
+};
+use serde_json::{json, Map, Value};
+
+impl ParsedCharacter {
+    /// The character in the layout of the golden masters
+    pub fn to_golden_master(&self) -> Value {
+        let subentities: Map<String, Value> = self
+            .subentities
+            .iter()
+            .map(|(name, metadata)| (name.clone(), metadata_json(metadata)))
+            .collect();
+        let variables: Map<String, Value> = self
+            .variables
+            .iter()
+            .map(|(name, variable)| (name.to_string(), variable_json(variable)))
+            .collect();
+        let states: Map<String, Value> = self
+            .states
+            .iter()
+            .map(|(name, state)| (name.to_string(), state_json(state)))
+            .collect();
+        json!({
+            "metadata": metadata_json(&self.metadata),
+            "subentities": subentities,
+            "variables": variables,
+            "states": states,
+            "transformed_data": self.transformed_data,
+        })
+    }
+}
+
+/// Set fields of a `:Character:` block, by lowercase key
+fn metadata_json(metadata: &CharacterMetadata) -> Value {
+    let Ok(Value::Object(fields)) = serde_json::to_value(metadata) else {
+        return Value::Object(Map::new());
+    };
+    let fields = fields
+        .into_iter()
+        .filter(|(_, value)| !matches!(value, Value::Null) && value != "")
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+    Value::Object(fields)
+}
+
+fn variable_json(variable: &ParsedVariable) -> Value {
+    let value = match (&variable.var_type, variable.as_bool()) {
+        (VariableType::Bool, Some(value)) => u8::from(value).to_string(),
+        _ => variable.value.clone(),
+    };
+    json!({
+        "Name": variable.name.as_str(),
+        "Value": value,
+        "Type": variable.var_type,
+        "Subtype": variable.subtype,
+        "Mutability": variable.mutability,
+    })
+}
+
+fn state_json(state: &ParsedState) -> Value {
+    let state_type = match state.state_type {
+        StateType::Normal => Value::Null,
+        ref state_type => json!(state_type),
+    };
+    let phases: Map<String, Value> = state
+        .actions
+        .iter()
+        .map(|(phase, actions)| {
+            let actions: Vec<Value> = actions.iter().map(action_json).collect();
+            (phase.to_string(), json!({ "Actions": actions }))
+        })
+        .collect();
+    json!({
+        "Parent": state.parent,
+        "Type": state_type,
+        "TransitionFlags": [],
+        "Phases": phases,
+    })
+}
+
+fn action_json(action: &ParsedAction) -> Value {
+    let flags = action.flags.iter().map(|flag| format!("+{}", flag));
+    let args: Vec<String> = action
+        .args
+        .iter()
+        .map(str::to_string)
+        .chain(flags)
+        .collect();
+    json!({
+        "function": action.instruction.as_str(),
+        "args": args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_golden_master_layout() {
+        let source = "\
+:Character:
+Name: Tester
+EditorName: Tester (Custom)
+:Variables:
+var Health(Int): 1000
+var Grounded(Bool): true
+def MaxSpeed: 12
+:Idle:
+---Init:
+Set(Health, 5)
+Hitbox(0, 10, +NoCancel)
+:Fireball(Helper):
+---Action:
+Move(3)
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("tester.casp", source)
+            .unwrap();
+        let golden = character.to_golden_master();
+
+        assert_eq!(golden["metadata"]["name"], "Tester");
+        assert_eq!(golden["metadata"]["editorname"], "Tester (Custom)");
+        assert!(golden["metadata"].get("author").is_none());
+
+        let health = &golden["variables"]["Health"];
+        assert_eq!(health["Name"], "Health");
+        assert_eq!(health["Value"], "1000");
+        assert_eq!(health["Type"], "Int");
+        assert_eq!(health["Mutability"], "Variable");
+        assert_eq!(golden["variables"]["Grounded"]["Value"], "1");
+        assert_eq!(golden["variables"]["MaxSpeed"]["Mutability"], "Constant");
+
+        let idle = &golden["states"]["Idle"];
+        assert!(idle["Parent"].is_null() && idle["Type"].is_null());
+        assert_eq!(idle["TransitionFlags"], serde_json::json!([]));
+        let actions = &idle["Phases"]["Init"]["Actions"];
+        assert_eq!(actions[0]["function"], "Set");
+        assert_eq!(actions[0]["args"], serde_json::json!(["Health", "5"]));
+        assert_eq!(
+            actions[1]["args"],
+            serde_json::json!(["0", "10", "+NoCancel"])
+        );
+        assert_eq!(golden["states"]["Fireball"]["Type"], "Helper");
+    }
+}
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Import Plugin - Compiling .casp files when the editor scans the project
+//!
+//! `CastagneEditorPlugin` is registered with the editor by the extension
+//! and adds `CastagneCaspImporter`, which turns every `.casp` file into a
+//! `CastagneCharacterResource`. A file that doesn't parse fails its import,
+//! with the parser errors printed for the import dock, instead of only
+//! failing when the game loads it. So does a file showing sprites past the
+//! end of its spritesheets, checked against the size of their images.
+
+use crate::character_resource::CastagneCharacterResource;
+use crate::diagnostics::Severity;
+use crate::parser::ParserConfig;
+use godot::classes::{
+    EditorImportPlugin, EditorPlugin, IEditorImportPlugin, IEditorPlugin, ResourceLoader,
+    ResourceSaver, Texture2D,
+};
+use godot::global::Error;
+use godot::prelude::*;
+
+/// Import option with the comma-separated build flags to enable
+const FLAGS_OPTION: &str = "build_flags";
+/// Import option reading renamed names, see `ParserConfig::legacy_syntax`
+const LEGACY_OPTION: &str = "legacy_syntax";
+
+/// Parser configuration of the import options
+fn import_config(flags: &str, legacy_syntax: bool) -> ParserConfig {
+    flags
+        .split(',')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .fold(ParserConfig::new(), ParserConfig::with_flag)
+        .with_legacy_syntax(legacy_syntax)
+}
+
+/// Width and height of the texture at a resource path
+fn texture_size(path: &str) -> Option<(i64, i64)> {
+    let mut loader = ResourceLoader::singleton();
+    if !loader.exists(path) {
+        return None;
+    }
+    let texture = loader.load(path)?.try_cast::<Texture2D>().ok()?;
+    Some((texture.get_width() as i64, texture.get_height() as i64))
+}
+
+/// Editor importer compiling .casp files into character resources
+#[derive(GodotClass)]
+#[class(tool, init, base=EditorImportPlugin)]
+pub struct CastagneCaspImporter {
+    base: Base<EditorImportPlugin>,
+}
+
+#[godot_api]
+impl IEditorImportPlugin for CastagneCaspImporter {
+    fn get_importer_name(&self) -> GString {
+        "castagne.casp".into()
+    }
+
+    fn get_visible_name(&self) -> GString {
+        "Castagne Character".into()
+    }
+
+    fn get_recognized_extensions(&self) -> PackedStringArray {
+        PackedStringArray::from(&[GString::from("casp")])
+    }
+
+    fn get_save_extension(&self) -> GString {
+        "res".into()
+    }
+
+    fn get_resource_type(&self) -> GString {
+        "Resource".into()
+    }
+
+    fn get_priority(&self) -> f32 {
+        1.0
+    }
+
+    fn get_import_order(&self) -> i32 {
+        0
+    }
+
+    fn get_format_version(&self) -> i32 {
+        1
+    }
+
+    fn get_preset_count(&self) -> i32 {
+        1
+    }
+
+    fn get_preset_name(&self, _preset_index: i32) -> GString {
+        "Default".into()
+    }
+
+    fn get_import_options(&self, _path: GString, _preset_index: i32) -> Array<VarDictionary> {
+        let option = |name: &str, default: Variant| {
+            let mut option = VarDictionary::new();
+            option.set("name", name);
+            option.set("default_value", default);
+            option
+        };
+        let defaults = ParserConfig::default();
+        let mut options = Array::new();
+        options.push(&option(FLAGS_OPTION, "".to_variant()));
+        options.push(&option(LEGACY_OPTION, defaults.legacy_syntax.to_variant()));
+        options
+    }
+
+    fn get_option_visibility(
+        &self,
+        _path: GString,
+        _option_name: StringName,
+        _options: VarDictionary,
+    ) -> bool {
+        true
+    }
+
+    fn import(
+        &self,
+        source_file: GString,
+        save_path: GString,
+        options: VarDictionary,
+        _platform_variants: Array<GString>,
+        _gen_files: Array<GString>,
+    ) -> Error {
+        let flags = options
+            .get(FLAGS_OPTION)
+            .map(|flags| flags.to_string())
+            .unwrap_or_default();
+        let legacy_syntax = options
+            .get(LEGACY_OPTION)
+            .and_then(|legacy| legacy.try_to::<bool>().ok())
+            .unwrap_or(true);
+        let config = import_config(&flags, legacy_syntax);
+
+        let mut resource = CastagneCharacterResource::new_gd();
+        let compiled = resource
+            .bind_mut()
+            .compile_with(&source_file.to_string(), &config);
+        if !compiled {
+            for error in resource.bind().errors() {
+                godot_error!("{}: {}", source_file, error);
+            }
+            return Error::ERR_PARSE_ERROR;
+        }
+
+        let diagnostics = match resource.bind().character() {
+            Some(character) => character.validate_spritesheets(texture_size),
+            None => Vec::new(),
+        };
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => godot_error!("{}: {}", source_file, diagnostic),
+                _ => godot_warn!("{}: {}", source_file, diagnostic),
+            }
+        }
+        if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            return Error::ERR_INVALID_DATA;
+        }
+
+        let path = format!("{}.{}", save_path, self.get_save_extension());
+        ResourceSaver::singleton()
+            .save_ex(&resource)
+            .path(path.as_str())
+            .done()
+    }
+}
+
+/// Editor plugin adding the .casp importer
+#[derive(GodotClass)]
+#[class(tool, init, base=EditorPlugin)]
+pub struct CastagneEditorPlugin {
+    base: Base<EditorPlugin>,
+    importer: Option<Gd<CastagneCaspImporter>>,
+}
+
+#[godot_api]
+impl IEditorPlugin for CastagneEditorPlugin {
+    fn enter_tree(&mut self) {
+        let importer = CastagneCaspImporter::new_gd();
+        self.base_mut().add_import_plugin(&importer);
+        self.importer = Some(importer);
+    }
+
+    fn exit_tree(&mut self) {
+        if let Some(importer) = self.importer.take() {
+            self.base_mut().remove_import_plugin(&importer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_config() {
+        let config = import_config(" Training, Debug ,", false);
+        let mut flags: Vec<&str> = config.flags.iter().map(String::as_str).collect();
+        flags.sort_unstable();
+        assert_eq!(flags, vec!["Debug", "Training"]);
+        assert!(!config.legacy_syntax);
+        assert!(import_config("", true).flags.is_empty());
+    }
+}
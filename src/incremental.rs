@@ -0,0 +1,435 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Incremental Parser - Reparse only the blocks touched by an edit
+//!
+//! The editor calls `apply_edit` on every keystroke. When the edit stays
+//! inside state blocks, only those states are reparsed and spliced into
+//! the previous `ParsedCharacter`; line numbers of the states after the
+//! edit are shifted. Anything else (metadata, variables, specblocks,
+//! templates, duplicate state names, `?Flag` conditional lines, `"""`
+//! strings, block comments and `\` continuations spanning lines) falls
+//! back to a full reparse, so the result is always the same as parsing the
+//! new buffer from scratch.
+//!
+//! Line ranges are 0-indexed and end-exclusive, like editor APIs.
+
+use crate::frame_data::scope_frames;
+use crate::generic_states::split_generic_header;
+use crate::overrides::record_overrides;
+use crate::parser::{
+    segment_blocks, subentity_header, Block, CastagneParser, ParsedCharacter, ParsedState,
+    VariablesBlock,
+};
+use std::collections::HashSet;
+
+/// What `apply_edit` had to reparse
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReparseScope {
+    /// Only these states (sorted) were reparsed or removed
+    States(Vec<String>),
+    /// The whole file was reparsed
+    Full,
+}
+
+/// Parser session keeping the buffer and last result between edits
+pub struct IncrementalParser {
+    file_path: String,
+    lines: Vec<String>,
+    character: Option<ParsedCharacter>,
+    /// States from the skeleton and includes, parsed lazily
+    inherited: Option<ParsedCharacter>,
+    inherited_loaded: bool,
+    parser: CastagneParser,
+}
+
+impl IncrementalParser {
+    /// Start a session with a full parse of `source`
+    pub fn new(file_path: &str, source: &str) -> Self {
+        let mut session = Self {
+            file_path: file_path.to_string(),
+            lines: source.lines().map(String::from).collect(),
+            character: None,
+            inherited: None,
+            inherited_loaded: false,
+            parser: CastagneParser::new(),
+        };
+        session.full_reparse();
+        session
+    }
+
+    /// Result of the last parse, `None` if it failed
+    pub fn character(&self) -> Option<&ParsedCharacter> {
+        self.character.as_ref()
+    }
+
+    /// Errors from the last full reparse
+    pub fn errors(&self) -> &[String] {
+        &self.parser.errors
+    }
+
+    /// Current buffer contents
+    pub fn source(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Replace lines `[start, end)` with `replacement` and update the parse
+    pub fn apply_edit(&mut self, start: usize, end: usize, replacement: &str) -> ReparseScope {
+        let start = start.min(self.lines.len());
+        let end = end.clamp(start, self.lines.len());
+        let new_lines: Vec<String> = replacement.lines().map(String::from).collect();
+        let new_end = start + new_lines.len();
+
+        let old_lines = std::mem::take(&mut self.lines);
+        let mut lines = old_lines.clone();
+        lines.splice(start..end, new_lines);
+
+        let old_blocks = segment_blocks(&old_lines);
+        let new_blocks = segment_blocks(&lines);
+        let mut old_affected = overlapping(&old_blocks, start, end);
+        let mut new_affected = overlapping(&new_blocks, start, new_end);
+
+        // The block right before the edit keeps its header but may gain or
+        // lose lines; if it changed on either side, reparse it on both
+        if let Some(previous) = start.checked_sub(1) {
+            let old_previous = old_blocks.iter().find(|b| b.contains(previous));
+            let new_previous = new_blocks.iter().find(|b| b.contains(previous));
+            if let (Some(old_previous), Some(new_previous)) = (old_previous, new_previous) {
+                let changed = old_affected.iter().any(|b| b.start == old_previous.start)
+                    || new_affected.iter().any(|b| b.start == new_previous.start);
+                if changed {
+                    if !old_affected.iter().any(|b| b.start == old_previous.start) {
+                        old_affected.push(old_previous.clone());
+                    }
+                    if !new_affected.iter().any(|b| b.start == new_previous.start) {
+                        new_affected.push(new_previous.clone());
+                    }
+                }
+            }
+        }
+
+        let incremental = self.all_states(&old_lines, &old_affected)
+            && self.all_states(&lines, &new_affected)
+            && names_unique(&new_blocks, &new_affected)
+            && !lines.iter().any(|line| needs_full_reparse(line));
+        self.lines = lines;
+
+        if !incremental {
+            self.full_reparse();
+            return ReparseScope::Full;
+        }
+
+        let delta = new_end as isize - end as isize;
+        let old_affected_names: HashSet<&str> =
+            old_affected.iter().map(|b| b.name.as_str()).collect();
+        let shifted_names: HashSet<&str> = old_blocks
+            .iter()
+            .map(|b| b.name.as_str())
+            .filter(|name| !old_affected_names.contains(name))
+            .collect();
+
+        self.ensure_inherited();
+        let character = match self.character.as_mut() {
+            Some(character) => character,
+            None => {
+                self.full_reparse();
+                return ReparseScope::Full;
+            }
+        };
+
+        // Shift states that live after the edit in this file
+        for name in &shifted_names {
+            if let Some(state) = character.states.get_mut(*name) {
+                shift_state(state, end, delta);
+            }
+        }
+
+        let old_starts: Vec<usize> = old_affected.iter().map(|b| b.start + 1).collect();
+        character.source_index.remove_starting_at(&old_starts);
+        character.source_index.shift_after(end, delta);
+
+        // Drop the old definitions, falling back to the inherited ones
+        for name in &old_affected_names {
+            character.states.remove(*name);
+            if let Some(parent_state) = self.inherited.as_ref().and_then(|s| s.states.get(*name)) {
+                character
+                    .states
+                    .insert(parent_state.name.clone(), parent_state.clone());
+            }
+        }
+
+        // Parse the new definitions
+        self.parser.current_lines = self.lines.clone();
+        self.parser.line_ids = (1..=self.lines.len()).collect();
+        self.parser.states.clear();
+        self.parser.source_index.clear();
+        for block in &new_affected {
+            let mut i = block.start;
+            self.parser.parse_state(block.header.clone(), &mut i);
+        }
+
+        let mut reparsed: Vec<String> = self.parser.states.keys().map(|k| k.to_string()).collect();
+        reparsed.extend(old_affected_names.iter().map(|name| name.to_string()));
+        reparsed.sort();
+        reparsed.dedup();
+        character.states.extend(self.parser.states.drain());
+        // The post-pass of a full parse, for the states that changed
+        for name in &reparsed {
+            if let Some(state) = character.states.get_mut(name.as_str()) {
+                scope_frames(&character.variables, state);
+            }
+        }
+        let spans = self.parser.source_index.states().to_vec();
+        character.source_index.extend(spans);
+        character.overrides = record_overrides(&self.parser.inherited, &self.file_path, character);
+
+        ReparseScope::States(reparsed)
+    }
+
+    fn full_reparse(&mut self) {
+        self.parser = CastagneParser::new();
+        self.character = self
+            .parser
+            .create_full_character_from_source(&self.file_path, &self.source())
+            .ok();
+        self.inherited = None;
+        self.inherited_loaded = false;
+    }
+
+    /// Parse the inherited data once, to restore states on removal
+    ///
+    /// Parsing the `:Character:` block alone yields exactly what the
+    /// skeleton and includes provide.
+    fn ensure_inherited(&mut self) {
+        if self.inherited_loaded {
+            return;
+        }
+        self.inherited_loaded = true;
+        let header: String = segment_blocks(&self.lines)
+            .iter()
+            .find(|block| block.name == "Character")
+            .map(|block| self.lines[block.start..block.end].join("\n"))
+            .unwrap_or_default();
+        self.inherited = CastagneParser::new()
+            .create_full_character_from_source(&self.file_path, &header)
+            .ok();
+    }
+
+    /// Whether every block is a state block (not metadata, variables, a
+    /// specblock or a generic state)
+    fn all_states(&mut self, lines: &[String], blocks: &[Block]) -> bool {
+        let character = match self.character.as_ref() {
+            Some(character) => character,
+            None => return false,
+        };
+        self.parser.current_lines = lines.to_vec();
+        blocks.iter().all(|block| {
+            block.name != "Character"
+                && VariablesBlock::from_header(&block.name).is_none()
+                && subentity_header(&block.name).is_none()
+                && !block.name.starts_with("Template ")
+                && split_generic_header(&block.header).is_none()
+                && !character.specblocks.contains_key(&block.name)
+                && !self.parser.is_specblock(&block.name, block.start + 1)
+        })
+    }
+}
+
+/// Move every line of `state` past `end` by `delta`
+fn shift_state(state: &mut ParsedState, end: usize, delta: isize) {
+    let shift = |line: &mut usize| {
+        if *line > end {
+            *line = (*line as isize + delta) as usize;
+        }
+    };
+    shift(&mut state.origin.line);
+    for action in state.actions.values_mut().flatten() {
+        shift(&mut action.line_number);
+    }
+    for local in &mut state.locals {
+        shift(&mut local.line_number);
+    }
+}
+
+/// Blocks sharing at least one line with `[start, end)`
+fn overlapping(blocks: &[Block], start: usize, end: usize) -> Vec<Block> {
+    blocks
+        .iter()
+        .filter(|block| block.start < end && block.end > start)
+        .cloned()
+        .collect()
+}
+
+/// Whether a line uses syntax that can reach across block boundaries
+fn needs_full_reparse(line: &str) -> bool {
+    line.trim_start().starts_with('?')
+        || line.contains("\"\"\"")
+        || line.contains("/*")
+        || line.trim_end().ends_with('\\')
+}
+
+/// Whether no affected name is also defined by an untouched block
+fn names_unique(blocks: &[Block], affected: &[Block]) -> bool {
+    let affected_starts: HashSet<usize> = affected.iter().map(|b| b.start).collect();
+    let mut seen = HashSet::new();
+    for block in affected {
+        if !seen.insert(block.name.as_str()) {
+            return false;
+        }
+    }
+    !blocks
+        .iter()
+        .filter(|block| !affected_starts.contains(&block.start))
+        .any(|block| seen.contains(block.name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const SOURCE: &str = ":Character:
+Name: Test
+:Variables:
+var Health(Int): 100
+:Idle:
+---Init:
+Set(Health, 100)
+:Walk:
+---Action:
+Move(10)
+:Jump:
+---Action:
+Move(0, 20)
+";
+
+    fn assert_matches_full_parse(session: &IncrementalParser) {
+        let full = CastagneParser::new()
+            .create_full_character_from_source(&session.file_path, &session.source())
+            .unwrap();
+        let character = session.character().unwrap();
+        assert_eq!(
+            character.to_json_value().unwrap(),
+            full.to_json_value().unwrap()
+        );
+        assert_eq!(character.source_index, full.source_index);
+    }
+
+    #[test]
+    fn test_edit_inside_state_reparses_only_that_state() {
+        let mut session = IncrementalParser::new("test.casp", SOURCE);
+
+        let scope = session.apply_edit(9, 10, "Move(25)");
+
+        assert_eq!(scope, ReparseScope::States(vec!["Walk".to_string()]));
+        let walk = &session.character().unwrap().states["Walk"];
+        assert_eq!(walk.actions["Action"][0].args, vec!["25"]);
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_inserted_lines_shift_following_states() {
+        let mut session = IncrementalParser::new("test.casp", SOURCE);
+
+        let scope = session.apply_edit(7, 7, "Flag(A)\nFlag(B)");
+
+        assert_eq!(scope, ReparseScope::States(vec!["Idle".to_string()]));
+        let jump = &session.character().unwrap().states["Jump"];
+        assert_eq!(jump.actions["Action"][0].line_number, 15);
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_inserted_lines_shift_locals() {
+        let source = format!("{}let Boost: 5\nMove(Boost)\n", SOURCE);
+        let mut session = IncrementalParser::new("test.casp", &source);
+
+        session.apply_edit(7, 7, "Flag(A)");
+
+        let jump = &session.character().unwrap().states["Jump"];
+        assert_eq!(jump.locals[0].line_number, 15);
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_frame_windows_survive_an_edit() {
+        let source = format!("{}F2-4:\nFlag(Airborne)\nendif\n", SOURCE);
+        let mut session = IncrementalParser::new("test.casp", &source);
+
+        let scope = session.apply_edit(12, 13, "Move(0, 30)");
+
+        assert_eq!(scope, ReparseScope::States(vec!["Jump".to_string()]));
+        let flag = &session.character().unwrap().states["Jump"].actions["Action"][2];
+        assert_eq!(flag.runs_on_frame(3), Some(true));
+        assert_eq!(flag.runs_on_frame(5), Some(false));
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_renaming_and_adding_states() {
+        let mut session = IncrementalParser::new("test.casp", SOURCE);
+
+        assert_eq!(
+            session.apply_edit(7, 8, ":Run:"),
+            ReparseScope::States(vec!["Run".to_string(), "Walk".to_string()])
+        );
+        let states = &session.character().unwrap().states;
+        assert!(states.contains_key("Run"));
+        assert!(!states.contains_key("Walk"));
+        assert_matches_full_parse(&session);
+
+        session.apply_edit(13, 13, ":Crouch:\n---Init:\nFlag(Low)");
+        assert!(session.character().unwrap().states.contains_key("Crouch"));
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_edits_outside_states_fall_back_to_full_reparse() {
+        let mut session = IncrementalParser::new("test.casp", SOURCE);
+
+        assert_eq!(
+            session.apply_edit(3, 4, "var Health(Int): 50"),
+            ReparseScope::Full
+        );
+        assert_eq!(session.character().unwrap().variables["Health"].value, "50");
+
+        // A duplicate state name needs the full file to decide which wins
+        assert_eq!(session.apply_edit(10, 11, ":Idle:"), ReparseScope::Full);
+        assert_matches_full_parse(&session);
+    }
+
+    #[test]
+    fn test_removing_override_restores_inherited_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent_path = dir.path().join("parent.casp");
+        fs::write(
+            &parent_path,
+            ":Character:\nName: Parent\n:Idle:\n---Init:\nFlag(Parent)\n",
+        )
+        .unwrap();
+        let source = format!(
+            ":Character:\nName: Child\nSkeleton: {}\n:Idle:\n---Init:\nFlag(Child)\n:Walk:\n---Init:\nMove(1)\n",
+            parent_path.display()
+        );
+        let mut session = IncrementalParser::new("child.casp", &source);
+        let character = session.character().unwrap();
+        assert_eq!(
+            character.states["Idle"].actions["Init"][0].args,
+            vec!["Child"]
+        );
+        assert_eq!(character.overrides_of("Idle").len(), 1);
+
+        // Delete the child's Idle override
+        let scope = session.apply_edit(3, 6, "");
+
+        assert_eq!(scope, ReparseScope::States(vec!["Idle".to_string()]));
+        let character = session.character().unwrap();
+        assert_eq!(
+            character.states["Idle"].actions["Init"][0].args,
+            vec!["Parent"]
+        );
+        assert!(character.overrides_of("Idle").is_empty());
+        assert_matches_full_parse(&session);
+    }
+}
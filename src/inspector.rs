@@ -0,0 +1,390 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Inspector - Data for an editor dock browsing a character
+//!
+//! A dock lists states and variables, shows one state at a time and the
+//! diagnostics of the last parse. Characters built on a large skeleton
+//! have hundreds of states, so lists come in pages filtered by name, and
+//! rows are kept small: the actions of a state are only sent with
+//! `get_state_detail`.
+
+use crate::character_resource::CastagneCharacterResource;
+use crate::diagnostics::Diagnostic;
+use crate::parser::{CastagneParser, ParsedCharacter, ParsedState, ParsedVariable, ParserConfig};
+use crate::validation::parser_diagnostics;
+use godot::prelude::*;
+
+/// One page of a filtered list
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of items matching the filter, on every page
+    pub total: usize,
+}
+
+/// A state row of the dock
+#[derive(Debug, Clone)]
+pub struct StateSummary<'a> {
+    pub state: &'a ParsedState,
+    pub phases: usize,
+    pub actions: usize,
+    /// Whether it comes from the skeleton or an include
+    pub inherited: bool,
+}
+
+/// A parsed character and its diagnostics, as shown by the dock
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+    path: String,
+    character: Option<ParsedCharacter>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Inspector {
+    /// Parse a character file
+    pub fn load(path: &str, config: &ParserConfig) -> Self {
+        let mut parser = CastagneParser::with_config(config.clone());
+        let character = parser.create_full_character(path).ok();
+        Self {
+            path: path.to_string(),
+            character,
+            diagnostics: parser_diagnostics(&parser),
+        }
+    }
+
+    /// Inspect a character parsed elsewhere
+    pub fn from_character(path: &str, character: ParsedCharacter) -> Self {
+        Self {
+            path: path.to_string(),
+            character: Some(character),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn character(&self) -> Option<&ParsedCharacter> {
+        self.character.as_ref()
+    }
+
+    /// States whose name contains `filter` (ignoring case), by name, from
+    /// `offset`; a `limit` of 0 returns all of them
+    pub fn states(&self, filter: &str, offset: usize, limit: usize) -> Page<StateSummary<'_>> {
+        let Some(character) = &self.character else {
+            return page(Vec::new(), offset, limit);
+        };
+        let mut states: Vec<&ParsedState> = character
+            .states
+            .values()
+            .filter(|state| matches(&state.name, filter))
+            .collect();
+        states.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let summaries = states.into_iter().map(|state| StateSummary {
+            state,
+            phases: state.actions.len(),
+            actions: state.actions.values().map(Vec::len).sum(),
+            inherited: !state.origin.file.is_empty() && state.origin.file != self.path,
+        });
+        page(summaries.collect(), offset, limit)
+    }
+
+    pub fn state(&self, name: &str) -> Option<&ParsedState> {
+        self.character.as_ref()?.states.get(name)
+    }
+
+    /// Variables and defines whose name contains `filter`, like `states`
+    pub fn variables(&self, filter: &str, offset: usize, limit: usize) -> Page<&ParsedVariable> {
+        let Some(character) = &self.character else {
+            return page(Vec::new(), offset, limit);
+        };
+        let mut variables: Vec<&ParsedVariable> = character
+            .variables
+            .values()
+            .filter(|variable| matches(&variable.name, filter))
+            .collect();
+        variables.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        page(variables, offset, limit)
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+fn matches(name: &str, filter: &str) -> bool {
+    filter.is_empty() || name.to_lowercase().contains(&filter.to_lowercase())
+}
+
+fn page<T>(items: Vec<T>, offset: usize, limit: usize) -> Page<T> {
+    let total = items.len();
+    let limit = if limit == 0 { total } else { limit };
+    let items = items.into_iter().skip(offset).take(limit).collect();
+    Page { items, total }
+}
+
+/// Godot-facing data provider for a character inspector dock
+///
+/// Lists are dictionaries with `items`, `total` and `offset`, so the dock
+/// can show "41-60 of 312" and fetch the next page.
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneInspector {
+    base: Base<RefCounted>,
+    inspector: Inspector,
+}
+
+#[godot_api]
+impl CastagneInspector {
+    /// Parse a character file, returning whether it parsed
+    #[func]
+    pub fn load_file(&mut self, path: GString) -> bool {
+        self.inspector = Inspector::load(&path.to_string(), &ParserConfig::default());
+        self.inspector.character().is_some()
+    }
+
+    /// Inspect a resource compiled in this session
+    #[func]
+    pub fn load_resource(&mut self, resource: Gd<CastagneCharacterResource>) -> bool {
+        let resource = resource.bind();
+        let Some(character) = resource.character() else {
+            return false;
+        };
+        let path = resource.base().get_path().to_string();
+        self.inspector = Inspector::from_character(&path, character.clone());
+        true
+    }
+
+    /// A page of states as dictionaries with `name`, `type`, `parent`,
+    /// `phases`, `actions`, `inherited`, `file` and `line`
+    #[func]
+    pub fn get_state_list(&self, filter: GString, offset: i64, limit: i64) -> VarDictionary {
+        let (offset, limit) = (offset.max(0) as usize, limit.max(0) as usize);
+        let states = self.inspector.states(&filter.to_string(), offset, limit);
+        let items = states.items.iter().map(|summary| {
+            let state = summary.state;
+            let mut row = VarDictionary::new();
+            row.set("name", state.name.as_str());
+            row.set("type", format!("{:?}", state.state_type).as_str());
+            row.set("parent", state.parent.as_deref().unwrap_or_default());
+            row.set("phases", summary.phases as i64);
+            row.set("actions", summary.actions as i64);
+            row.set("inherited", summary.inherited);
+            row.set("file", state.origin.file.as_str());
+            row.set("line", state.origin.line as i64);
+            row.to_variant()
+        });
+        page_dictionary(items.collect(), states.total, offset)
+    }
+
+    /// A state with its `description` and `phases`, each phase an array
+    /// of actions with `instruction`, `args` and `line`; empty if unknown
+    #[func]
+    pub fn get_state_detail(&self, name: GString) -> VarDictionary {
+        let mut detail = VarDictionary::new();
+        let Some(state) = self.inspector.state(&name.to_string()) else {
+            return detail;
+        };
+        detail.set("name", state.name.as_str());
+        detail.set("type", format!("{:?}", state.state_type).as_str());
+        detail.set("parent", state.parent.as_deref().unwrap_or_default());
+        detail.set(
+            "description",
+            state.description.as_deref().unwrap_or_default(),
+        );
+        detail.set("file", state.origin.file.as_str());
+        detail.set("line", state.origin.line as i64);
+        let mut phases = VarDictionary::new();
+        for (phase, actions) in &state.actions {
+            let actions: VarArray = actions
+                .iter()
+                .map(|action| {
+                    let mut row = VarDictionary::new();
+                    row.set("instruction", action.instruction.as_str());
+                    let args: PackedStringArray = action.args.iter().map(GString::from).collect();
+                    row.set("args", args);
+                    row.set("line", action.line_number as i64);
+                    row.to_variant()
+                })
+                .collect();
+            phases.set(phase.as_str(), actions);
+        }
+        detail.set("phases", phases);
+        detail
+    }
+
+    /// Frames of a state for the sprite previewer, as dictionaries with
+    /// `frame`, `animation` (empty when showing a sprite), `animation_frame`,
+    /// `spritesheet` and `sprite`; `frames` 0 plays the state's length
+    #[func]
+    pub fn get_animation_timeline(&self, name: GString, frames: i64) -> VarArray {
+        let frames = usize::try_from(frames).ok().filter(|frames| *frames > 0);
+        let timeline = self
+            .inspector
+            .character()
+            .and_then(|character| character.animation_timeline(&name.to_string(), frames));
+        let Some(timeline) = timeline else {
+            return VarArray::new();
+        };
+        timeline
+            .frames
+            .iter()
+            .map(|frame| {
+                let mut row = VarDictionary::new();
+                row.set("frame", frame.frame as i64);
+                row.set("animation", frame.animation.as_deref().unwrap_or_default());
+                row.set("animation_frame", frame.animation_frame);
+                row.set(
+                    "spritesheet",
+                    frame.spritesheet.as_deref().unwrap_or_default(),
+                );
+                row.set("sprite", frame.sprite);
+                row.to_variant()
+            })
+            .collect()
+    }
+
+    /// Sound cues as dictionaries with `name`, `path` (empty when not
+    /// declared), `volume` and `usages`, each usage with `state`, `phase`,
+    /// `instruction`, `file` and `line`
+    #[func]
+    pub fn get_sound_catalog(&self) -> VarArray {
+        let Some(character) = self.inspector.character() else {
+            return VarArray::new();
+        };
+        character
+            .sound_catalog()
+            .iter()
+            .map(|cue| {
+                let usages: VarArray = cue
+                    .usages
+                    .iter()
+                    .map(|usage| {
+                        let mut row = VarDictionary::new();
+                        row.set("state", usage.state.as_str());
+                        row.set("phase", usage.phase.as_str());
+                        row.set("instruction", usage.instruction.as_str());
+                        row.set("file", usage.origin.file.as_str());
+                        row.set("line", usage.origin.line as i64);
+                        row.to_variant()
+                    })
+                    .collect();
+                let mut row = VarDictionary::new();
+                row.set("name", cue.name.as_str());
+                row.set("path", cue.path.as_deref().unwrap_or_default());
+                row.set("volume", cue.volume);
+                row.set("usages", usages);
+                row.to_variant()
+            })
+            .collect()
+    }
+
+    /// A page of variables as dictionaries with `name`, `type`,
+    /// `mutability`, `value`, `section`, `file` and `line`
+    #[func]
+    pub fn get_variable_table(&self, filter: GString, offset: i64, limit: i64) -> VarDictionary {
+        let (offset, limit) = (offset.max(0) as usize, limit.max(0) as usize);
+        let variables = self.inspector.variables(&filter.to_string(), offset, limit);
+        let items = variables.items.iter().map(|variable| {
+            let mut row = VarDictionary::new();
+            row.set("name", variable.name.as_str());
+            row.set("type", format!("{:?}", variable.var_type).as_str());
+            row.set("mutability", format!("{:?}", variable.mutability).as_str());
+            row.set("value", variable.value.as_str());
+            row.set("section", variable.section.as_deref().unwrap_or_default());
+            row.set("file", variable.origin.file.as_str());
+            row.set("line", variable.origin.line as i64);
+            row.to_variant()
+        });
+        page_dictionary(items.collect(), variables.total, offset)
+    }
+
+    /// Diagnostics of the last parse as dictionaries with `severity`,
+    /// `code`, `message`, `file`, `line` and `column` (line 0 if unknown)
+    #[func]
+    pub fn get_diagnostics(&self) -> VarArray {
+        self.inspector
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span.as_ref();
+                let mut row = VarDictionary::new();
+                row.set("severity", diagnostic.severity.to_string().as_str());
+                row.set("code", diagnostic.code.as_str());
+                row.set("message", diagnostic.message.as_str());
+                let file = span.and_then(|span| span.file.as_deref());
+                row.set("file", file.unwrap_or_default());
+                row.set("line", span.map_or(0, |span| span.line as i64));
+                row.set("column", span.map_or(0, |span| span.column as i64));
+                row.to_variant()
+            })
+            .collect()
+    }
+}
+
+fn page_dictionary(items: VarArray, total: usize, offset: usize) -> VarDictionary {
+    let mut page = VarDictionary::new();
+    page.set("items", items);
+    page.set("total", total as i64);
+    page.set("offset", offset as i64);
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_of_states_and_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp");
+        let ryu = dir.path().join("ryu.casp");
+        std::fs::write(
+            &base,
+            ":Character:\nName: Base\n:Variables:\nvar Health(Int): 100\n:Idle:\n---Init:\nStop\n:Walk:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        let states: String = (1..=25)
+            .map(|n| format!(":Move{:02}:\n---Init:\nStop\n---Action:\nStop\nStop\n", n))
+            .collect();
+        std::fs::write(
+            &ryu,
+            format!(
+                ":Character:\nName: Ryu\nSkeleton: {}\n:Variables:\nvar Meter(Int): 0\ndef MaxMeter: 100\n{}",
+                base.display(),
+                states
+            ),
+        )
+        .unwrap();
+
+        let inspector = Inspector::load(ryu.to_str().unwrap(), &ParserConfig::default());
+        let first = inspector.states("", 0, 10);
+        assert_eq!(first.total, 27);
+        assert_eq!(first.items.len(), 10);
+        assert_eq!(first.items[0].state.name, "Idle");
+        assert!(first.items[0].inherited);
+        assert!(!first.items[2].inherited);
+        assert_eq!((first.items[2].phases, first.items[2].actions), (2, 3));
+        let last = inspector.states("", 20, 10);
+        assert_eq!(last.items.len(), 7);
+        assert_eq!(last.items[6].state.name, "Walk");
+
+        let moves = inspector.states("move1", 0, 0);
+        assert_eq!(moves.total, 10);
+        assert_eq!(inspector.states("move1", 8, 0).items.len(), 2);
+
+        let names: Vec<&str> = inspector
+            .variables("", 0, 0)
+            .items
+            .iter()
+            .map(|variable| variable.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Health", "MaxMeter", "Meter"]);
+        assert_eq!(inspector.variables("meter", 1, 5).items.len(), 1);
+        assert!(inspector.diagnostics().is_empty());
+
+        let missing = Inspector::load("missing.casp", &ParserConfig::default());
+        assert!(missing.character().is_none());
+        assert_eq!(missing.states("", 0, 0).total, 0);
+        assert!(missing.diagnostics()[0].message.contains("does not exist"));
+    }
+}
@@ -0,0 +1,245 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Intern - Shared storage for repeated names
+//!
+//! State, phase, instruction and variable names repeat thousands of times
+//! across a roster. The parser interns them: every occurrence of a name is
+//! a `Symbol` pointing to the same allocation, and comparing two symbols
+//! of the same name only compares pointers. A parser keeps its interner
+//! between parses and hands it to the parsers of its skeleton and
+//! includes, so everything parsed with the same parser shares names.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/// An immutable, cheaply cloned name
+///
+/// Behaves like a `&str`: it derefs to one, hashes like one and can be
+/// used to look up maps keyed by `Symbol` with a plain `&str`.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        &*self.0 == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl PartialEq<Symbol> for String {
+    fn eq(&self, other: &Symbol) -> bool {
+        self.as_str() == &*other.0
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Must hash like `str` for `Borrow<str>` lookups
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self(Arc::from(name))
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Self(Arc::from(name))
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Self {
+        Self(Arc::from(name.as_str()))
+    }
+}
+
+impl From<Symbol> for String {
+    fn from(symbol: Symbol) -> Self {
+        symbol.0.to_string()
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Symbol::from)
+    }
+}
+
+/// Set of the symbols handed out so far
+///
+/// Clones share the same set, so parsers of a roster can all intern into
+/// one. Interning only needs `&self`.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: Arc<Mutex<HashSet<Symbol>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The symbol for `name`, shared with every earlier request for it
+    pub fn intern(&self, name: &str) -> Symbol {
+        let mut symbols = self.lock();
+        if let Some(symbol) = symbols.get(name) {
+            return symbol.clone();
+        }
+        let symbol = Symbol::from(name);
+        symbols.insert(symbol.clone());
+        symbol
+    }
+
+    /// Number of distinct names
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<Symbol>> {
+        // The set stays consistent even if a holder panicked
+        self.symbols.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_interning_shares_allocations() {
+        let interner = Interner::new();
+        let a = interner.intern("Idle");
+        let b = interner.intern(&String::from("Idle"));
+
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(interner.len(), 1);
+        assert_ne!(interner.intern("Walk"), a);
+        assert_eq!(interner.len(), 2);
+
+        let shared = interner.clone();
+        assert!(Arc::ptr_eq(
+            &shared.intern("Walk").0,
+            &interner.intern("Walk").0
+        ));
+        shared.intern("Crouch");
+        assert_eq!(interner.len(), 3);
+    }
+
+    #[test]
+    fn test_symbol_behaves_like_str() {
+        let symbol = Symbol::from("Idle");
+        assert_eq!(symbol, "Idle");
+        assert_eq!("Idle", symbol);
+        assert_eq!(symbol.len(), 4);
+        assert_eq!(format!("{} {:?}", symbol, symbol), "Idle \"Idle\"");
+        assert_eq!(serde_json::to_string(&symbol).unwrap(), "\"Idle\"");
+
+        let mut map = HashMap::new();
+        map.insert(symbol, 1);
+        assert_eq!(map.get("Idle"), Some(&1));
+        assert_eq!(map["Idle"], 1);
+    }
+}
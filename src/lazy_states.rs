@@ -0,0 +1,173 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Lazy States - State bodies parsed on first access
+//!
+//! Browsing characters in the editor lists every state but opens few of
+//! them. With `ParserConfig::lazy_states`, the parser only locates the
+//! state blocks of a file and keeps their lines; `ParsedCharacter::state`
+//! parses a block the first time it is asked for and keeps the result.
+//! Metadata, variables, specblocks, templates and generic states are
+//! parsed as usual, and skeletons and includes are always parsed in full.
+//!
+//! A state parsed this way reports no errors and isn't part of the
+//! `source_index`, `overrides` or JSON of the character; parse eagerly, or
+//! call `ParsedCharacter::parse_lazy_states`, to check or export a file.
+
+use crate::frame_data::scope_frames;
+use crate::intern::{Interner, Symbol};
+use crate::parser::{CastagneParser, ParsedCharacter, ParsedState, ParserConfig};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+/// A state block waiting to be parsed
+#[derive(Debug, Clone)]
+pub struct LazyState {
+    /// Line numbers and preprocessed lines, header first
+    lines: Vec<(usize, String)>,
+    /// `None` once parsed if the block goes past the limits
+    parsed: OnceLock<Option<ParsedState>>,
+}
+
+impl LazyState {
+    pub(crate) fn new(lines: Vec<(usize, String)>) -> Self {
+        Self {
+            lines,
+            parsed: OnceLock::new(),
+        }
+    }
+
+    /// 1-indexed line of the header
+    pub fn line_number(&self) -> usize {
+        self.lines.first().map_or(0, |(line, _)| *line)
+    }
+
+    pub fn is_parsed(&self) -> bool {
+        self.parsed.get().is_some()
+    }
+}
+
+/// State blocks of a file left for later, and what parsing them needs
+#[derive(Debug, Clone, Default)]
+pub struct LazyStates {
+    pub(crate) states: HashMap<Symbol, LazyState>,
+    pub(crate) config: Arc<ParserConfig>,
+    pub(crate) interner: Interner,
+    pub(crate) file: String,
+}
+
+impl LazyStates {
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    fn parse(&self, state: &LazyState, character: &ParsedCharacter) -> Option<ParsedState> {
+        let mut parser = CastagneParser::with_config(ParserConfig::clone(&self.config));
+        parser.parse_lazy_state(&self.interner, &self.file, character, &state.lines)
+    }
+}
+
+impl ParsedCharacter {
+    /// State `name`, parsed on first access if it was left for later
+    pub fn state(&self, name: &str) -> Option<&ParsedState> {
+        match self.lazy_states.states.get(name) {
+            Some(lazy) => lazy
+                .parsed
+                .get_or_init(|| {
+                    let mut state = self.lazy_states.parse(lazy, self)?;
+                    scope_frames(&self.variables, &mut state);
+                    Some(state)
+                })
+                .as_ref(),
+            None => self.states.get(name),
+        }
+    }
+
+    /// Names of all states, parsed or not, sorted
+    pub fn state_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .states
+            .keys()
+            .chain(self.lazy_states.states.keys())
+            .map(|name| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Parse the states left for later and move them into `states`
+    pub fn parse_lazy_states(&mut self) {
+        let lazy_states = std::mem::take(&mut self.lazy_states);
+        for (name, lazy) in &lazy_states.states {
+            let state = match lazy.parsed.get() {
+                Some(state) => state.clone(),
+                None => lazy_states.parse(lazy, self),
+            };
+            if let Some(state) = state {
+                self.states.insert(name.clone(), state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{CastagneParser, ParserConfig};
+
+    const SOURCE: &str = "\
+:Character:
+Name: Lazy
+:Template Hit(Damage):
+Deal(Damage)
+:Idle:
+---Init:
+Set(Health, 100)
+:Hadoken(Helper):
+---Action:
+UseTemplate(Hit, 20)
+Move(5)
+";
+
+    #[test]
+    fn test_states_parse_on_first_access() {
+        let mut parser = CastagneParser::with_config(ParserConfig::new().with_lazy_states(true));
+        let character = parser
+            .create_full_character_from_source("lazy.casp", SOURCE)
+            .unwrap();
+        assert_eq!(character.metadata.name, "Lazy");
+        assert!(character.states.is_empty());
+        assert_eq!(character.state_names(), ["Hadoken", "Idle"]);
+        assert!(!character.lazy_states.states["Hadoken"].is_parsed());
+        assert_eq!(character.lazy_states.states["Hadoken"].line_number(), 8);
+
+        let hadoken = character.state("Hadoken").unwrap();
+        let actions = &hadoken.actions["Action"];
+        assert_eq!(actions[0].instruction, "Deal");
+        assert_eq!(actions[0].args, ["20"]);
+        assert_eq!(actions[1].line_number, 11);
+        assert!(character.lazy_states.states["Hadoken"].is_parsed());
+        assert!(!character.lazy_states.states["Idle"].is_parsed());
+        assert!(character.state("Walk").is_none());
+    }
+
+    #[test]
+    fn test_lazy_parse_matches_eager_parse() {
+        let eager = CastagneParser::new()
+            .create_full_character_from_source("lazy.casp", SOURCE)
+            .unwrap();
+        let mut lazy = CastagneParser::with_config(ParserConfig::new().with_lazy_states(true))
+            .create_full_character_from_source("lazy.casp", SOURCE)
+            .unwrap();
+        lazy.parse_lazy_states();
+        assert!(lazy.lazy_states.is_empty());
+        assert_eq!(
+            serde_json::to_value(&lazy.states).unwrap(),
+            serde_json::to_value(&eager.states).unwrap()
+        );
+    }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Legacy - Names from Castagne 0.x still accepted by the parser
+//!
+//! With `ParserConfig::legacy_syntax` enabled, the parser reads renamed
+//! phase names, instruction spellings and metadata keys as their current
+//! names, warning with a `legacy-syntax` diagnostic, so older characters
+//! keep loading until they are updated. The renames come from
+//! `LEGACY_NAMES` and from the ones a project registers with
+//! `Deprecations::rename`. Modules can also mark their own names as
+//! deprecated in `Deprecations`; each warning carries a fix-it replacing
+//! the name.
+
+use std::fmt;
+
+/// Diagnostic code of names read through the compatibility table
+pub const LEGACY_SYNTAX: &str = "legacy-syntax";
+
+/// Diagnostic code of names marked deprecated in `Deprecations`
+pub const DEPRECATED_NAME: &str = "deprecated-name";
+
+/// Kind of name a legacy entry renames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LegacyKind {
+    Phase,
+    Instruction,
+    MetadataKey,
+}
+
+impl fmt::Display for LegacyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyKind::Phase => f.write_str("phase"),
+            LegacyKind::Instruction => f.write_str("instruction"),
+            LegacyKind::MetadataKey => f.write_str("metadata key"),
+        }
+    }
+}
+
+/// A renamed construct
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LegacyName {
+    pub kind: LegacyKind,
+    pub legacy: &'static str,
+    pub current: &'static str,
+}
+
+/// Names renamed since Castagne 0.x
+///
+/// Only names a released Castagne 0.x reads belong here, each checked
+/// against its modules and parser; none are listed yet. Until then,
+/// projects porting older characters register their renames with
+/// `Deprecations::rename`.
+pub const LEGACY_NAMES: &[LegacyName] = &[];
+
+/// Current name of a legacy one, `None` if `name` wasn't renamed
+pub fn current_name(kind: LegacyKind, name: &str) -> Option<&'static str> {
+    LEGACY_NAMES
+        .iter()
+        .find(|entry| entry.kind == kind && entry.legacy == name)
+        .map(|entry| entry.current)
+}
+
+/// A deprecated name and what replaces it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    pub kind: LegacyKind,
+    pub name: String,
+    pub replacement: String,
+    /// From `LEGACY_NAMES`: the name is read as its replacement
+    pub legacy: bool,
+}
+
+/// Names the parser warns about, the legacy ones by default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecations {
+    entries: Vec<Deprecation>,
+}
+
+impl Default for Deprecations {
+    fn default() -> Self {
+        let entries = LEGACY_NAMES
+            .iter()
+            .map(|entry| Deprecation {
+                kind: entry.kind,
+                name: entry.legacy.to_string(),
+                replacement: entry.current.to_string(),
+                legacy: true,
+            })
+            .collect();
+        Self { entries }
+    }
+}
+
+impl Deprecations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark a name as deprecated; it is still read as written
+    pub fn deprecate(&mut self, kind: LegacyKind, name: &str, replacement: &str) -> &mut Self {
+        self.insert(kind, name, replacement, false)
+    }
+
+    /// Mark a name as renamed; with `legacy_syntax`, it is read as its
+    /// replacement
+    pub fn rename(&mut self, kind: LegacyKind, name: &str, replacement: &str) -> &mut Self {
+        self.insert(kind, name, replacement, true)
+    }
+
+    fn insert(
+        &mut self,
+        kind: LegacyKind,
+        name: &str,
+        replacement: &str,
+        legacy: bool,
+    ) -> &mut Self {
+        self.entries
+            .retain(|entry| !(entry.kind == kind && entry.name == name));
+        self.entries.push(Deprecation {
+            kind,
+            name: name.to_string(),
+            replacement: replacement.to_string(),
+            legacy,
+        });
+        self
+    }
+
+    pub fn get(&self, kind: LegacyKind, name: &str) -> Option<&Deprecation> {
+        self.entries
+            .iter()
+            .find(|entry| entry.kind == kind && entry.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Deprecation> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Severity;
+    use crate::parser::{CastagneParser, ParserConfig};
+
+    const SOURCE: &str = ":Character:\nName: Old\n:Idle:\n---Start:\nSetVar(Health, 100)\n---Update:\n  ChangeState(Walk)\n";
+
+    fn renames() -> Deprecations {
+        let mut deprecations = Deprecations::new();
+        deprecations
+            .rename(LegacyKind::Phase, "Start", "Init")
+            .rename(LegacyKind::Phase, "Update", "Action")
+            .rename(LegacyKind::Instruction, "SetVar", "Set")
+            .rename(LegacyKind::Instruction, "ChangeState", "Transition");
+        deprecations
+    }
+
+    #[test]
+    fn test_legacy_names_are_read_as_current_ones() {
+        let config = ParserConfig::new()
+            .with_legacy_syntax(true)
+            .with_deprecations(renames());
+        let mut parser = CastagneParser::with_config(config);
+        let character = parser
+            .create_full_character_from_source("old.casp", SOURCE)
+            .unwrap();
+        let idle = &character.states["Idle"];
+        assert_eq!(idle.actions["Init"][0].instruction, "Set");
+        assert_eq!(idle.actions["Action"][0].instruction, "Transition");
+        assert!(!idle.actions.contains_key("Start"));
+
+        let diagnostics = parser.get_diagnostics();
+        assert_eq!(diagnostics.len(), 4);
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.code == LEGACY_SYNTAX && d.severity == Severity::Warning));
+        let change_state = &diagnostics[3];
+        assert_eq!(
+            change_state.message,
+            "Legacy instruction ChangeState, read as Transition"
+        );
+        let span = change_state.span.as_ref().unwrap();
+        assert_eq!((span.line, span.column, span.length), (7, 2, 11));
+        assert!(parser.get_errors().is_empty());
+    }
+
+    #[test]
+    fn test_legacy_syntax_is_opt_in() {
+        let config = ParserConfig::new().with_deprecations(renames());
+        assert!(!config.legacy_syntax);
+        let mut parser = CastagneParser::with_config(config);
+        let character = parser
+            .create_full_character_from_source("old.casp", SOURCE)
+            .unwrap();
+        assert_eq!(
+            character.states["Idle"].actions["Start"][0].instruction,
+            "SetVar"
+        );
+        assert!(parser.get_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_deprecations_carry_fix_its() {
+        let mut deprecations = Deprecations::new();
+        deprecations
+            .rename(LegacyKind::MetadataKey, "Creator", "Author")
+            .deprecate(LegacyKind::Instruction, "Transition", "TransitionBuffer");
+        let config = ParserConfig::new()
+            .with_legacy_syntax(true)
+            .with_deprecations(deprecations);
+        let mut parser = CastagneParser::with_config(config);
+        let source = ":Character:\nCreator: Someone\n:Idle:\n---Action:\nTransition(Walk)\n";
+        let character = parser
+            .create_full_character_from_source("old.casp", source)
+            .unwrap();
+        assert_eq!(character.metadata.author, "Someone");
+        assert_eq!(
+            character.states["Idle"].actions["Action"][0].instruction,
+            "Transition"
+        );
+
+        let diagnostics = parser.get_diagnostics();
+        assert_eq!(diagnostics[0].code, LEGACY_SYNTAX);
+        assert_eq!(diagnostics[0].fixes[0].replacement, "Author");
+        let deprecated = &diagnostics[1];
+        assert_eq!(deprecated.code, DEPRECATED_NAME);
+        assert_eq!(
+            deprecated.message,
+            "The instruction Transition is deprecated, use TransitionBuffer"
+        );
+        let fix = &deprecated.fixes[0];
+        assert_eq!(fix.replacement, "TransitionBuffer");
+        assert_eq!(
+            (fix.span.line, fix.span.column, fix.span.length),
+            (5, 0, 10)
+        );
+    }
+}
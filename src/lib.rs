@@ -11,12 +11,107 @@
 //! - Parser: Fast Rust implementation for parsing .casp character files
 //! - Engine: GDScript (original Castagne, ported to Godot 4.5)
 //! - Integration: GDExtension interface for seamless interop
+//!
+//! Thread safety: parse results hold no Godot types, so characters can be
+//! parsed on the worker threads Godot loads resources on and shared between
+//! them. Variants are only built on demand, by `ParsedVariable::to_variant`,
+//! on the thread calling it. The assertions below keep it that way.
 
 use godot::prelude::*;
 
 // Module declarations
+pub mod action_args;
+pub mod ai;
+pub mod animation;
+pub mod attack_notation;
+pub mod borrowed;
+pub mod cancellation;
+pub mod character_resource;
+pub mod cns;
+pub mod color;
+pub mod config;
+pub mod content_hash;
+pub mod corpus;
+pub mod diagnostics;
+pub mod docgen;
+pub mod error;
+pub mod expression;
+pub mod fixes;
+pub mod format_version;
+pub mod frame_data;
+pub mod front_matter;
+pub mod fuzz;
+pub mod generic_states;
+pub mod golden_master;
+pub mod import_plugin;
+pub mod incremental;
+pub mod inspector;
+pub mod intern;
+pub mod lazy_states;
+pub mod legacy;
+pub mod limits;
+pub mod lint;
+pub mod loops;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod metrics;
+pub mod mugen;
+pub mod netplay;
+pub mod overrides;
+pub mod parse_cache;
 pub mod parser;
+pub mod phases;
+pub mod pool;
+pub mod pooling;
+pub mod pragmas;
+pub mod refactor;
+pub mod references;
+pub mod roster;
+pub mod roster_manifest;
+pub mod roundtrip;
+pub mod scenario;
+pub mod semantic_tokens;
+pub mod signatures;
+pub mod simulation;
+pub mod skeleton_cache;
+pub mod skeletons;
+pub mod sounds;
+pub mod source_file;
+pub mod source_index;
+pub mod source_map;
+pub mod specs;
+pub mod spreadsheet;
+pub mod state_machine;
+pub mod string_literal;
+pub mod subentities;
+pub mod switches;
+pub mod syntax_tree;
 pub mod test_runner;
+pub mod training;
+pub mod validation;
+pub mod visitor;
+pub mod watcher;
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    const fn assert_send<T: Send>() {}
+
+    assert_send_sync::<parser::ParsedCharacter>();
+    assert_send_sync::<parser::ParsedState>();
+    assert_send_sync::<parser::ParsedVariable>();
+    assert_send_sync::<parser::ParsedAction>();
+    assert_send_sync::<action_args::ActionArgs>();
+    assert_send_sync::<parser::ParsedTemplate>();
+    assert_send_sync::<parser::CharacterMetadata>();
+    assert_send_sync::<borrowed::ParsedCharacterRef<'static>>();
+    assert_send_sync::<diagnostics::Diagnostic>();
+    assert_send_sync::<intern::Interner>();
+    assert_send_sync::<metrics::ParseMetrics>();
+    assert_send_sync::<pool::ParserPool>();
+    assert_send_sync::<skeleton_cache::SkeletonCache>();
+    assert_send_sync::<source_file::SourceFile>();
+    assert_send::<parser::CastagneParser>();
+};
 
 struct CastagneRsExtension;
 
@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Limits - Bounds keeping pathological files from freezing the editor
+//!
+//! A corrupted or generated file can hold a million lines, a single line
+//! of megabytes or thousands of nested branches, and the parser runs inside
+//! the Godot editor. Past any of the `Limits` set in `ParserConfig` the
+//! parse stops with a `limit-exceeded` diagnostic and a
+//! `ParseFailure::LimitExceeded`. The defaults are far above any real
+//! character; `Limits::unlimited` turns them off.
+
+use std::fmt;
+
+/// Diagnostic code of a file going past one of the `Limits`
+pub const LIMIT_EXCEEDED: &str = "limit-exceeded";
+
+/// Bounds on what a parse accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Branches open at once in a phase
+    pub max_nesting_depth: usize,
+    /// Bytes of a line
+    pub max_line_length: usize,
+    /// States of a character, inherited ones included
+    pub max_states: usize,
+    /// Skeletons above a file, its own included
+    pub max_skeleton_chain: usize,
+    /// Lines of a file
+    pub max_total_lines: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_nesting_depth: 64,
+            max_line_length: 64 * 1024,
+            max_states: 10_000,
+            max_skeleton_chain: 16,
+            max_total_lines: 1_000_000,
+        }
+    }
+}
+
+impl Limits {
+    /// No limit at all, for trusted files
+    pub fn unlimited() -> Self {
+        Self {
+            max_nesting_depth: usize::MAX,
+            max_line_length: usize::MAX,
+            max_states: usize::MAX,
+            max_skeleton_chain: usize::MAX,
+            max_total_lines: usize::MAX,
+        }
+    }
+
+    /// The configured maximum of `limit`
+    pub fn max(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::NestingDepth => self.max_nesting_depth,
+            Limit::LineLength => self.max_line_length,
+            Limit::States => self.max_states,
+            Limit::SkeletonChain => self.max_skeleton_chain,
+            Limit::TotalLines => self.max_total_lines,
+        }
+    }
+}
+
+/// One of the `Limits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    NestingDepth,
+    LineLength,
+    States,
+    SkeletonChain,
+    TotalLines,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::NestingDepth => "branches nested deeper than",
+            Limit::LineLength => "line longer than",
+            Limit::States => "more than",
+            Limit::SkeletonChain => "skeleton chain longer than",
+            Limit::TotalLines => "file longer than",
+        })
+    }
+}
+
+impl Limit {
+    /// Unit of the maximum, for messages
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Limit::NestingDepth => "levels",
+            Limit::LineLength => "bytes",
+            Limit::States => "states",
+            Limit::SkeletonChain => "skeletons",
+            Limit::TotalLines => "lines",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_states: 3,
+            ..Limits::default()
+        };
+        assert_eq!(limits.max(Limit::States), 3);
+        assert_eq!(limits.max(Limit::SkeletonChain), 16);
+        assert_eq!(Limits::unlimited().max(Limit::LineLength), usize::MAX);
+        assert_eq!(
+            format!("{} 64 {}", Limit::NestingDepth, Limit::NestingDepth.unit()),
+            "branches nested deeper than 64 levels"
+        );
+    }
+}
@@ -0,0 +1,1229 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Lint - Style and correctness checks on parsed characters
+//!
+//! Each rule has a stable ID and a default level. A `LintConfig` can
+//! allow (disable), warn or deny (report as error) any rule by ID.
+//! Results are returned as `Diagnostic`s.
+//!
+//! Numeric sanity rules flag values no fighting game wants, like negative
+//! durations or hitboxes with no area, within the `SanityLimits` of the
+//! config. With `lint_source` they point at the offending literal.
+//!
+//! `lint_roster` adds the rules needing every character of a roster, like
+//! skeleton states that no character can reach.
+//!
+//! `lint_child` checks what a derived character changes from its skeleton,
+//! for the noise those files gather: variables redeclared with the value
+//! they already had, and states overridden while the helper states they
+//! call or spawn are left as the skeleton wrote them.
+//!
+//! Given the buttons of the project's input layout, see
+//! `InputConfig::buttons`, `unknown-button` checks the numpad notations of
+//! input transitions and attacks: a notation using a button the layout
+//! doesn't have can never be input.
+//!
+//! `non-exhaustive-switch` reports switches on an enum variable, see
+//! `switches`, without a `Default` or a `Case` for every value.
+
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::expression::parse_number;
+use crate::parser::{
+    split_action, split_arguments, ParsedAction, ParsedCharacter, ParsedState, StateType,
+    VariableMutability,
+};
+use crate::pragmas::Suppressions;
+use crate::roster::RosterParse;
+use crate::subentities::spawned_name;
+use crate::switches::switches;
+use crate::visitor::{walk_state, Visitor};
+use std::collections::{HashMap, HashSet};
+
+/// What to do when a rule triggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Description of a built-in lint rule
+#[derive(Debug, Clone, Copy)]
+pub struct LintRule {
+    pub id: &'static str,
+    pub default_level: LintLevel,
+    pub description: &'static str,
+}
+
+pub const ATTACK_WITHOUT_REACTION: &str = "attack-without-reaction";
+pub const UNDECLARED_VARIABLE: &str = "undeclared-variable";
+pub const EMPTY_PHASE: &str = "empty-phase";
+pub const MAGIC_DAMAGE: &str = "magic-damage";
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+pub const OVERRIDDEN_STATE: &str = "overridden-state";
+pub const EXCESSIVE_DAMAGE: &str = "excessive-damage";
+pub const NEGATIVE_DURATION: &str = "negative-duration";
+pub const ZERO_DURATION: &str = "zero-duration";
+pub const ZERO_AREA_BOX: &str = "zero-area-box";
+pub const METER_OVER_MAX: &str = "meter-over-max";
+pub const UNKNOWN_BUTTON: &str = "unknown-button";
+pub const NON_EXHAUSTIVE_SWITCH: &str = "non-exhaustive-switch";
+pub const REDUNDANT_OVERRIDE: &str = "redundant-override";
+pub const PARTIAL_OVERRIDE: &str = "partial-override";
+
+/// All built-in rules
+pub const RULES: &[LintRule] = &[
+    LintRule {
+        id: ATTACK_WITHOUT_REACTION,
+        default_level: LintLevel::Warn,
+        description: "Attack state has no Reaction phase",
+    },
+    LintRule {
+        id: UNDECLARED_VARIABLE,
+        default_level: LintLevel::Warn,
+        description: "Set targets a variable that is not declared",
+    },
+    LintRule {
+        id: EMPTY_PHASE,
+        default_level: LintLevel::Warn,
+        description: "Phase marker with no actions",
+    },
+    LintRule {
+        id: MAGIC_DAMAGE,
+        default_level: LintLevel::Allow,
+        description: "Damage given as a literal instead of a define",
+    },
+    LintRule {
+        id: UNUSED_VARIABLE,
+        default_level: LintLevel::Allow,
+        description: "Variable or define declared but never read by an action",
+    },
+    LintRule {
+        id: OVERRIDDEN_STATE,
+        default_level: LintLevel::Warn,
+        description: "Skeleton state overridden by every character using it",
+    },
+    LintRule {
+        id: EXCESSIVE_DAMAGE,
+        default_level: LintLevel::Warn,
+        description: "Attack damage above the configured maximum",
+    },
+    LintRule {
+        id: NEGATIVE_DURATION,
+        default_level: LintLevel::Warn,
+        description: "Frame duration below zero",
+    },
+    LintRule {
+        id: ZERO_DURATION,
+        default_level: LintLevel::Warn,
+        description: "Attack lasting zero frames",
+    },
+    LintRule {
+        id: ZERO_AREA_BOX,
+        default_level: LintLevel::Warn,
+        description: "Hitbox, hurtbox or colbox with no width or height",
+    },
+    LintRule {
+        id: METER_OVER_MAX,
+        default_level: LintLevel::Warn,
+        description: "Meter gain above the meter's maximum",
+    },
+    LintRule {
+        id: UNKNOWN_BUTTON,
+        default_level: LintLevel::Warn,
+        description: "Input notation using a button missing from the input layout",
+    },
+    LintRule {
+        id: NON_EXHAUSTIVE_SWITCH,
+        default_level: LintLevel::Warn,
+        description: "Switch on an enum variable leaving some of its values unhandled",
+    },
+    LintRule {
+        id: REDUNDANT_OVERRIDE,
+        default_level: LintLevel::Warn,
+        description: "Child redeclares a skeleton variable with the same value",
+    },
+    LintRule {
+        id: PARTIAL_OVERRIDE,
+        default_level: LintLevel::Warn,
+        description: "Child overrides a state but not the helper states it uses",
+    },
+];
+
+/// Instructions that write to the variable named by their first argument
+const VARIABLE_WRITE_INSTRUCTIONS: &[&str] = &["Set", "SetStr"];
+
+/// Instructions whose first argument is a damage value
+const DAMAGE_INSTRUCTIONS: &[&str] = &["AttackDamage"];
+
+/// Instructions whose arguments are frame counts
+const DURATION_INSTRUCTIONS: &[&str] =
+    &["AttackDuration", "AttackHitstunBlockstun", "FreezeFrames"];
+
+/// Instructions whose first argument is the duration of the attack state
+const STATE_DURATION_INSTRUCTIONS: &[&str] = &["AttackDuration"];
+
+/// Instructions taking `(Back, Front, Bottom, Top)` or `(Width, Bottom, Top)`
+const BOX_INSTRUCTIONS: &[&str] = &["Hitbox", "Hurtbox", "Colbox"];
+
+/// Instructions giving the variable in their first argument the amount in
+/// their second: `Add` adds it, `Set` sets the variable to it
+const METER_GAIN_INSTRUCTIONS: &[&str] = &["Add", "Set"];
+
+/// Instructions taking a notation, and the index of that argument
+const NOTATION_INSTRUCTIONS: &[(&str, usize)] = &[
+    ("InputTransition", 0),
+    ("InputFlag", 0),
+    ("InputFlagNext", 0),
+    ("InputTransitionFlag", 0),
+    ("InputTransitionFlagNext", 0),
+    ("AttackRegister", 1),
+    ("AttackInternalRegister", 1),
+    ("AttackAddNotation", 0),
+];
+
+/// Instructions using the state name as notation when not given one
+const STATE_NOTATION_INSTRUCTIONS: &[&str] = &["AttackRegister", "AttackInternalRegister"];
+
+/// Thresholds of the numeric sanity rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct SanityLimits {
+    /// Highest damage a single attack may deal
+    pub max_damage: f64,
+    /// Variable holding the meter
+    pub meter_variable: String,
+    /// Variable whose default is the meter's maximum
+    pub meter_max_variable: String,
+}
+
+impl Default for SanityLimits {
+    fn default() -> Self {
+        Self {
+            max_damage: 10000.0,
+            meter_variable: "Meter".to_string(),
+            meter_max_variable: "MeterMax".to_string(),
+        }
+    }
+}
+
+/// Per-rule level overrides
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<String, LintLevel>,
+    limits: SanityLimits,
+    /// Buttons notations may use, `None` to skip `unknown-button`
+    buttons: Option<Vec<String>>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_level(&mut self, rule_id: &str, level: LintLevel) -> &mut Self {
+        self.levels.insert(rule_id.to_string(), level);
+        self
+    }
+
+    pub fn allow(&mut self, rule_id: &str) -> &mut Self {
+        self.set_level(rule_id, LintLevel::Allow)
+    }
+
+    pub fn warn(&mut self, rule_id: &str) -> &mut Self {
+        self.set_level(rule_id, LintLevel::Warn)
+    }
+
+    pub fn deny(&mut self, rule_id: &str) -> &mut Self {
+        self.set_level(rule_id, LintLevel::Deny)
+    }
+
+    pub fn set_limits(&mut self, limits: SanityLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &SanityLimits {
+        &self.limits
+    }
+
+    /// Check notations against these buttons
+    pub fn set_buttons<S: Into<String>>(
+        &mut self,
+        buttons: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        self.buttons = Some(buttons.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn buttons(&self) -> Option<&[String]> {
+        self.buttons.as_deref()
+    }
+
+    /// Effective level of a rule (override, else the rule's default)
+    pub fn level(&self, rule_id: &str) -> LintLevel {
+        if let Some(level) = self.levels.get(rule_id) {
+            return *level;
+        }
+        RULES
+            .iter()
+            .find(|rule| rule.id == rule_id)
+            .map(|rule| rule.default_level)
+            .unwrap_or(LintLevel::Allow)
+    }
+}
+
+/// Run all enabled rules on a character
+pub fn lint_character(character: &ParsedCharacter, config: &LintConfig) -> Vec<Diagnostic> {
+    lint(character, None, config)
+}
+
+/// Run all enabled rules on a character parsed from `source`, pointing at
+/// the offending literal where a rule is about one and leaving out what
+/// the `castagne-ignore` pragmas of `source` silence
+pub fn lint_source(
+    character: &ParsedCharacter,
+    source: &str,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = source.lines().collect();
+    let suppressions = Suppressions::scan(&lines);
+    let mut diagnostics = lint(character, Some(&lines), config);
+    diagnostics.retain(|diagnostic| !suppressions.silences(diagnostic, ""));
+    diagnostics
+}
+
+fn lint(
+    character: &ParsedCharacter,
+    lines: Option<&[&str]>,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        config,
+        character,
+        lines,
+        diagnostics: Vec::new(),
+    };
+    linter.visit_character(character);
+    linter.unused_variables();
+    linter.diagnostics
+}
+
+/// Run the rules spanning several characters on a parsed roster
+///
+/// Files in `roster` are played as is, so their states are never reported.
+pub fn lint_roster<P: AsRef<str>>(
+    roster: &[P],
+    parsed: &RosterParse,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let severity = match config.level(OVERRIDDEN_STATE) {
+        LintLevel::Allow => return Vec::new(),
+        LintLevel::Warn => Severity::Warning,
+        LintLevel::Deny => Severity::Error,
+    };
+    let character = |path: &str| parsed.characters.get(path)?.as_ref().ok();
+
+    let mut diagnostics = Vec::new();
+    for path in parsed.graph.files() {
+        if roster.iter().any(|played| played.as_ref() == path) {
+            continue;
+        }
+        let Some(skeleton) = character(path) else {
+            continue;
+        };
+        let children: Vec<_> = parsed
+            .graph
+            .dependents(path)
+            .into_iter()
+            .filter_map(character)
+            .collect();
+        if children.is_empty() {
+            continue;
+        }
+        for span in skeleton.source_index.states() {
+            let name = span.name.as_str();
+            let overridden = children.iter().all(|child| {
+                let own = child.source_index.state_range(name).is_some();
+                let inherits = child
+                    .states
+                    .values()
+                    .any(|state| state.parent.as_deref() == Some(name));
+                own && !inherits
+            });
+            if overridden {
+                let location = Span {
+                    file: Some(path.clone()),
+                    ..Span::line(span.start_line)
+                };
+                diagnostics.push(
+                    Diagnostic::new(
+                        OVERRIDDEN_STATE,
+                        severity,
+                        format!(
+                            "State {} is overridden by every character using {}",
+                            name, path
+                        ),
+                    )
+                    .with_span(location),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Run the rules about what `child` changes from `parent`, its skeleton
+///
+/// Only the variables and states written in `child` itself are reported.
+pub fn lint_child(
+    child: &ParsedCharacter,
+    parent: &ParsedCharacter,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        config,
+        character: child,
+        lines: None,
+        diagnostics: Vec::new(),
+    };
+    linter.redundant_overrides(parent);
+    linter.partial_overrides(parent);
+    linter.diagnostics
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    character: &'a ParsedCharacter,
+    /// Source of the character's own file, when known
+    lines: Option<&'a [&'a str]>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule_id: &str, message: String, line: Option<usize>) {
+        self.report_span(rule_id, message, line.map(Span::line));
+    }
+
+    /// The reported diagnostic, `None` if the rule is allowed
+    fn report_span(
+        &mut self,
+        rule_id: &str,
+        message: String,
+        span: Option<Span>,
+    ) -> Option<&mut Diagnostic> {
+        let severity = match self.config.level(rule_id) {
+            LintLevel::Allow => return None,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+        let mut diagnostic = Diagnostic::new(rule_id, severity, message);
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+        self.diagnostics.last_mut()
+    }
+
+    /// Report about argument `index` of an action, spanning the literal
+    /// when the state is in the linted source
+    fn report_argument(
+        &mut self,
+        rule_id: &str,
+        message: String,
+        state: &ParsedState,
+        action: &ParsedAction,
+        index: usize,
+    ) -> Option<&mut Diagnostic> {
+        let span = self
+            .argument_span(state, action, index)
+            .unwrap_or_else(|| Span::line(action.line_number));
+        self.report_span(rule_id, message, Some(span))
+    }
+
+    fn argument_span(
+        &self,
+        state: &ParsedState,
+        action: &ParsedAction,
+        index: usize,
+    ) -> Option<Span> {
+        // Inherited states have lines of another file
+        self.character.source_index.state_range(&state.name)?;
+        let line = *self.lines?.get(action.line_number.checked_sub(1)?)?;
+        let (_, args) = split_action(line.trim())?;
+        let argument = *split_arguments(args).get(index)?;
+        if argument != action.args.get(index)? {
+            return None;
+        }
+        let byte = argument.as_ptr() as usize - line.as_ptr() as usize;
+        Some(Span {
+            file: None,
+            line: action.line_number,
+            column: line[..byte].chars().count(),
+            length: argument.chars().count(),
+        })
+    }
+
+    /// Switches on an enum variable handling only some of its values
+    fn check_switches(&mut self, state: &ParsedState, actions: &[ParsedAction]) {
+        for switch in switches(actions) {
+            let Some(variable) = self.character.variables.get(switch.subject.trim()) else {
+                continue;
+            };
+            let missing = switch.missing_cases(variable);
+            if missing.is_empty() {
+                continue;
+            }
+            let message = format!(
+                "Switch on {} in state {} doesn't handle {}",
+                variable.name,
+                state.name,
+                missing.join(", ")
+            );
+            let note = format!("Add Case({}) or a Default", missing.join(", "));
+            let line = actions[switch.start].line_number;
+            if let Some(diagnostic) =
+                self.report_span(NON_EXHAUSTIVE_SWITCH, message, Some(Span::line(line)))
+            {
+                diagnostic.notes.push(note);
+            }
+        }
+    }
+
+    /// Numeric sanity rules on the literal arguments of an action
+    fn check_values(&mut self, state: &ParsedState, action: &ParsedAction) {
+        let instruction = action.instruction.as_str();
+        let literal = |index: usize| action.args.get(index).and_then(parse_number);
+        let limits = &self.config.limits;
+
+        if DAMAGE_INSTRUCTIONS.contains(&instruction) {
+            if let Some(damage) = literal(0).filter(|damage| *damage > limits.max_damage) {
+                let message = format!(
+                    "{} in state {} deals {} damage, above the maximum of {}",
+                    instruction, state.name, damage, limits.max_damage
+                );
+                self.report_argument(EXCESSIVE_DAMAGE, message, state, action, 0);
+            }
+        }
+
+        if DURATION_INSTRUCTIONS.contains(&instruction) {
+            for index in 0..action.args.len() {
+                if let Some(frames) = literal(index).filter(|frames| *frames < 0.0) {
+                    let message = format!(
+                        "{} in state {} has a negative duration of {} frames",
+                        instruction, state.name, frames
+                    );
+                    self.report_argument(NEGATIVE_DURATION, message, state, action, index);
+                }
+            }
+        }
+
+        if STATE_DURATION_INSTRUCTIONS.contains(&instruction) && literal(0) == Some(0.0) {
+            let message = format!("{} in state {} lasts 0 frames", instruction, state.name);
+            self.report_argument(ZERO_DURATION, message, state, action, 0);
+        }
+
+        if BOX_INSTRUCTIONS.contains(&instruction) {
+            // Arguments bounding the width, then the height
+            let bounds: &[(usize, usize, &str)] = match action.args.len() {
+                4 => &[(0, 1, "width"), (2, 3, "height")],
+                3 => &[(0, 0, "width"), (1, 2, "height")],
+                _ => &[],
+            };
+            for &(low, high, dimension) in bounds {
+                let (Some(a), Some(b)) = (literal(low), literal(high)) else {
+                    continue;
+                };
+                let empty = if low == high { a == 0.0 } else { a == b };
+                if empty {
+                    let message = format!(
+                        "{} in state {} has no {}",
+                        instruction, state.name, dimension
+                    );
+                    self.report_argument(ZERO_AREA_BOX, message, state, action, high);
+                }
+            }
+        }
+
+        if METER_GAIN_INSTRUCTIONS.contains(&instruction)
+            && action.args.first() == Some(&limits.meter_variable)
+        {
+            let max = self
+                .character
+                .variables
+                .get(limits.meter_max_variable.as_str())
+                .and_then(|variable| parse_number(&variable.value));
+            if let (Some(gain), Some(max)) = (literal(1), max) {
+                if gain > max {
+                    let message = format!(
+                        "{} in state {} gives {} {}, above {} ({})",
+                        instruction,
+                        state.name,
+                        gain,
+                        limits.meter_variable,
+                        limits.meter_max_variable,
+                        max
+                    );
+                    self.report_argument(METER_OVER_MAX, message, state, action, 1);
+                }
+            }
+        }
+    }
+
+    /// `unknown-button` on the notation an action registers, if any
+    fn check_notation(&mut self, state: &ParsedState, action: &ParsedAction) {
+        let Some(buttons) = self.config.buttons.as_deref() else {
+            return;
+        };
+        let instruction = action.instruction.as_str();
+        let Some(&(_, index)) = NOTATION_INSTRUCTIONS
+            .iter()
+            .find(|(name, _)| *name == instruction)
+        else {
+            return;
+        };
+        let notation = match action.args.get(index) {
+            Some(notation) => notation,
+            // Only names written like a notation, `Fireball` is just a name
+            None if STATE_NOTATION_INSTRUCTIONS.contains(&instruction)
+                && state
+                    .name
+                    .starts_with(|c: char| c.is_ascii_digit() || c == '[') =>
+            {
+                state.name.as_str()
+            }
+            None => return,
+        };
+
+        for button in unknown_buttons(notation, buttons) {
+            let note = match closest_button(button, buttons) {
+                Some(closest) => format!("Did you mean {}?", closest),
+                None => format!("Buttons of the input layout: {}", buttons.join(", ")),
+            };
+            let message = format!(
+                "{} in state {} uses button {} in {}, missing from the input layout",
+                instruction, state.name, button, notation
+            );
+            if let Some(diagnostic) =
+                self.report_argument(UNKNOWN_BUTTON, message, state, action, index)
+            {
+                diagnostic.notes.push(note);
+            }
+        }
+    }
+
+    /// Report the variables and defines no action, default or specblock reads
+    fn unused_variables(&mut self) {
+        let character = self.character;
+        let mut read = HashSet::new();
+        let template_actions = character.templates.values().flat_map(|t| &t.actions);
+        let actions = character
+            .states
+            .values()
+            .flat_map(|state| state.actions.values().flatten())
+            .chain(template_actions);
+        for action in actions {
+            let writes = VARIABLE_WRITE_INSTRUCTIONS.contains(&action.instruction.as_str());
+            let args = action.args.iter().skip(usize::from(writes));
+            args.for_each(|arg| read.extend(words(arg)));
+        }
+        for variable in character.variables.values() {
+            read.extend(variable.expression.iter().flat_map(|e| words(e)));
+        }
+        for entries in character.specblocks.values() {
+            read.extend(entries.values().flat_map(|value| words(value)));
+        }
+
+        let mut unused: Vec<_> = character
+            .variables
+            .values()
+            .filter(|variable| variable.mutability != VariableMutability::Internal)
+            .filter(|variable| !read.contains(variable.name.as_str()))
+            .collect();
+        unused.sort_by(|a, b| a.name.cmp(&b.name));
+        for variable in unused {
+            let kind = match variable.mutability {
+                VariableMutability::Define => "Define",
+                _ => "Variable",
+            };
+            self.report(
+                UNUSED_VARIABLE,
+                format!("{} {} is declared but never read", kind, variable.name),
+                None,
+            );
+        }
+    }
+}
+
+impl Linter<'_> {
+    /// Variables of the child declared again with the value of the parent
+    fn redundant_overrides(&mut self, parent: &ParsedCharacter) {
+        let mut redundant: Vec<_> = self
+            .character
+            .variables
+            .iter()
+            .filter_map(|(name, variable)| {
+                let inherited = parent.variables.get(name)?;
+                let redeclared = variable.origin != inherited.origin;
+                let same = variable.value == inherited.value
+                    && variable.var_type == inherited.var_type
+                    && variable.mutability == inherited.mutability;
+                (redeclared && same).then_some(variable)
+            })
+            .collect();
+        redundant.sort_by_key(|variable| variable.origin.line);
+        for variable in redundant {
+            let span = Span {
+                file: Some(variable.origin.file.clone()).filter(|file| !file.is_empty()),
+                ..Span::line(variable.origin.line)
+            };
+            self.report_span(
+                REDUNDANT_OVERRIDE,
+                format!(
+                    "{} is redeclared with the value it inherits, {}",
+                    variable.name, variable.value
+                ),
+                Some(span),
+            );
+        }
+    }
+
+    /// States of the child overriding one of the parent, but not the
+    /// helper states the parent's version calls or spawns
+    fn partial_overrides(&mut self, parent: &ParsedCharacter) {
+        let own = |name: &str| self.character.source_index.state_range(name).is_some();
+        let mut reports = Vec::new();
+        for span in self.character.source_index.states() {
+            let Some(state) = parent.states.get(span.name.as_str()) else {
+                continue;
+            };
+            let mut helpers: Vec<String> = state
+                .actions
+                .values()
+                .flatten()
+                .filter_map(|action| {
+                    let target = spawned_name(action)
+                        .map(str::to_string)
+                        .or_else(|| parent.called_state(&span.name, action))?;
+                    let helper = parent.states.get(target.as_str())?;
+                    (helper.state_type == StateType::Helper && !own(&target)).then_some(target)
+                })
+                .collect();
+            helpers.sort();
+            helpers.dedup();
+            for helper in helpers {
+                let message = format!(
+                    "State {} is overridden but its helper {} is inherited as is",
+                    span.name, helper
+                );
+                reports.push((message, span.start_line));
+            }
+        }
+        for (message, line) in reports {
+            self.report(PARTIAL_OVERRIDE, message, Some(line));
+        }
+    }
+}
+
+/// Parts of the buttons of a numpad notation that aren't `buttons`
+///
+/// Buttons follow the last digit of the motion, end to end, a released
+/// one written `]L[` as the engine does.
+fn unknown_buttons<'a>(notation: &'a str, buttons: &[String]) -> Vec<&'a str> {
+    let start = notation
+        .rfind(|c: char| c.is_ascii_digit())
+        .map_or(0, |digit| digit + 1);
+    let rest = &notation[start..];
+
+    let mut unknown = Vec::new();
+    let mut unknown_start = None;
+    let mut index = 0;
+    while let Some(c) = rest[index..].chars().next() {
+        let known = match c {
+            '[' | ']' => Some(1),
+            _ => buttons
+                .iter()
+                .filter(|button| !button.is_empty() && rest[index..].starts_with(button.as_str()))
+                .map(String::len)
+                .max(),
+        };
+        match known {
+            Some(len) => {
+                if let Some(start) = unknown_start.take() {
+                    unknown.push(&rest[start..index]);
+                }
+                index += len;
+            }
+            None => {
+                unknown_start.get_or_insert(index);
+                index += c.len_utf8();
+            }
+        }
+    }
+    if let Some(start) = unknown_start {
+        unknown.push(&rest[start..]);
+    }
+    unknown
+}
+
+/// The one button spelled closest to `name`, if close enough
+fn closest_button<'a>(name: &str, buttons: &'a [String]) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    let mut distances: Vec<(usize, &str)> = buttons
+        .iter()
+        .map(|button| {
+            (
+                edit_distance(&name, &button.to_lowercase()),
+                button.as_str(),
+            )
+        })
+        .collect();
+    distances.sort_unstable();
+    let (best, button) = *distances.first()?;
+    let tied = distances.get(1).is_some_and(|(next, _)| *next == best);
+    (!tied && best <= (name.chars().count() / 2).max(1)).then_some(button)
+}
+
+/// Levenshtein distance, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Names in an argument, leaving out string literals
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split('"')
+        .step_by(2)
+        .flat_map(|code| code.split(|c: char| !(c.is_alphanumeric() || c == '_')))
+        .filter(|word| !word.is_empty())
+}
+
+impl Visitor for Linter<'_> {
+    fn visit_state(&mut self, state: &ParsedState) {
+        let first_attack = state
+            .actions
+            .values()
+            .flatten()
+            .filter(|action| action.instruction.starts_with("Attack"))
+            .min_by_key(|action| action.line_number);
+        if let Some(action) = first_attack {
+            if !state.actions.contains_key("Reaction") {
+                self.report(
+                    ATTACK_WITHOUT_REACTION,
+                    format!("Attack state {} has no Reaction phase", state.name),
+                    Some(action.line_number),
+                );
+            }
+        }
+
+        walk_state(self, state);
+    }
+
+    fn visit_phase(&mut self, state: &ParsedState, phase: &str, actions: &[ParsedAction]) {
+        if actions.is_empty() {
+            self.report(
+                EMPTY_PHASE,
+                format!("Phase {} of state {} is empty", phase, state.name),
+                None,
+            );
+        }
+
+        self.check_switches(state, actions);
+        for action in actions {
+            self.visit_action(state, phase, action);
+        }
+    }
+
+    fn visit_action(&mut self, state: &ParsedState, _phase: &str, action: &ParsedAction) {
+        self.check_values(state, action);
+        self.check_notation(state, action);
+        let instruction = action.instruction.as_str();
+        let first_arg = match action.args.first() {
+            Some(arg) => arg,
+            None => return,
+        };
+
+        if VARIABLE_WRITE_INSTRUCTIONS.contains(&instruction)
+            && !self.character.variables.contains_key(first_arg)
+            && state.local(first_arg).is_none()
+        {
+            self.report(
+                UNDECLARED_VARIABLE,
+                format!(
+                    "{} in state {} targets undeclared variable {}",
+                    instruction, state.name, first_arg
+                ),
+                Some(action.line_number),
+            );
+        }
+
+        if DAMAGE_INSTRUCTIONS.contains(&instruction) {
+            let is_literal = first_arg.parse::<f64>().map(|v| v != 0.0).unwrap_or(false);
+            if is_literal {
+                self.report(
+                    MAGIC_DAMAGE,
+                    format!(
+                        "{} in state {} uses literal damage {}, consider a def",
+                        instruction, state.name, first_arg
+                    ),
+                    Some(action.line_number),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn parse(content: &str) -> ParsedCharacter {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        let mut parser = CastagneParser::new();
+        parser
+            .create_full_character(file.path().to_str().unwrap())
+            .unwrap()
+    }
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.code.as_str()).collect()
+    }
+
+    #[test]
+    fn test_attack_without_reaction() {
+        let character = parse(
+            ":Character:\nName: Test\n:Jab:\n---Init:\nAttackRegister(Light)\nAttackDamage(Dmg)\n",
+        );
+
+        let diagnostics = lint_character(&character, &LintConfig::new());
+
+        assert_eq!(codes(&diagnostics), vec![ATTACK_WITHOUT_REACTION]);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 5);
+    }
+
+    #[test]
+    fn test_undeclared_variable_and_empty_phase() {
+        let character = parse(
+            ":Character:\nName: Test\n:Variables:\nvar Health(Int): 100\n:Idle:\n---Init:\nSet(Health, 1)\nSet(Hleath, 2)\n---Action:\n",
+        );
+
+        let diagnostics = lint_character(&character, &LintConfig::new());
+
+        // Phases are visited in declaration order
+        assert_eq!(codes(&diagnostics), vec![UNDECLARED_VARIABLE, EMPTY_PHASE]);
+        assert!(diagnostics[0].message.contains("Hleath"));
+    }
+
+    #[test]
+    fn test_magic_damage_is_opt_in() {
+        let character = parse(
+            ":Character:\nName: Test\n:Jab:\n---Init:\nAttackDamage(150)\n---Reaction:\nTransition(Idle)\n",
+        );
+
+        assert!(lint_character(&character, &LintConfig::new()).is_empty());
+
+        let mut config = LintConfig::new();
+        config.deny(MAGIC_DAMAGE);
+        let diagnostics = lint_character(&character, &config);
+        assert_eq!(codes(&diagnostics), vec![MAGIC_DAMAGE]);
+        assert!(diagnostics[0].is_error());
+    }
+
+    #[test]
+    fn test_allow_disables_rule() {
+        let character = parse(":Character:\nName: Test\n:Idle:\n---Init:\n");
+
+        let mut config = LintConfig::new();
+        config.allow(EMPTY_PHASE);
+
+        assert!(lint_character(&character, &config).is_empty());
+        assert_eq!(config.level(EMPTY_PHASE), LintLevel::Allow);
+        assert_eq!(config.level("no-such-rule"), LintLevel::Allow);
+    }
+
+    #[test]
+    fn test_unused_variables_and_defines() {
+        let character = parse(
+            ":Character:\nName: Test\n:Variables:\nvar Health(Int): 100\nvar Meter(Int): 0\ndef MaxMeter: 100\ndef Unused: 1\ndef Twice: MaxMeter * 2\nvar Label(Str): \"x\"\ninternal Frame(Int): 0\n:Idle:\n---Init:\nSet(Health, 1)\nIfLt(Meter, Twice):\nLog(\"Label\")\nendif\n",
+        );
+
+        assert!(lint_character(&character, &LintConfig::new()).is_empty());
+        let mut config = LintConfig::new();
+        config.warn(UNUSED_VARIABLE);
+        let messages: Vec<String> = lint_character(&character, &config)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Variable Health is declared but never read",
+                "Variable Label is declared but never read",
+                "Define Unused is declared but never read",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_states_overridden_in_every_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n:Walk:\n---Init:\nMove(1)\n:Jump:\n---Init:\nMove(2)\n",
+        )
+        .unwrap();
+        let write_child = |name: &str, states: &str| {
+            let source = format!(
+                ":Character:\nName: {}\nSkeleton: {}\n{}",
+                name,
+                path("base.casp"),
+                states
+            );
+            std::fs::write(path(&format!("{}.casp", name)), source).unwrap();
+        };
+        write_child(
+            "ryu",
+            ":Walk:\n---Init:\nMove(3)\n:Jump:\n---Init:\nMove(4)\n",
+        );
+        write_child(
+            "ken",
+            ":Walk:\n---Init:\nMove(5)\n:Jump(Jump):\n---Init:\nMove(6)\n",
+        );
+
+        let roster = [path("ryu.casp"), path("ken.casp")];
+        let parsed = crate::roster::parse_roster(&roster, &crate::parser::ParserConfig::default());
+        let diagnostics = lint_roster(&roster, &parsed, &LintConfig::new());
+        assert_eq!(codes(&diagnostics), vec![OVERRIDDEN_STATE]);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("State Walk is overridden"));
+        let span = diagnostics[0].span.as_ref().unwrap();
+        assert_eq!((span.file.clone(), span.line), (Some(path("base.casp")), 6));
+
+        // A skeleton played on its own keeps its states
+        let with_base = [path("ryu.casp"), path("ken.casp"), path("base.casp")];
+        assert!(lint_roster(&with_base, &parsed, &LintConfig::new()).is_empty());
+    }
+
+    #[test]
+    fn test_child_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp").to_str().unwrap().to_string();
+        std::fs::write(
+            &base,
+            "\
+:Character:
+Name: Base
+:Variables:
+var Health(Int): 1000
+var Speed(Int): 5
+:Jab:
+---Init:
+Call(JabBox)
+CreateEntity(JabSpark)
+:JabBox(Helper):
+---Init:
+Hitbox(0, 10)
+:JabSpark(Helper):
+---Init:
+Move(1)
+:Walk:
+---Init:
+Call(JabBox)
+",
+        )
+        .unwrap();
+        let child = parse(&format!(
+            "\
+:Character:
+Name: Child
+Skeleton: {}
+:Variables:
+var Health(Int): 1000
+var Speed(Int): 7
+:Jab:
+---Init:
+Call(JabBox)
+:JabSpark(Helper):
+---Init:
+Move(2)
+",
+            base
+        ));
+        let parent = CastagneParser::new().create_full_character(&base).unwrap();
+
+        let diagnostics = lint_child(&child, &parent, &LintConfig::new());
+        let found: Vec<(&str, &str, usize)> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.code.as_str(),
+                    d.message.as_str(),
+                    d.span.as_ref().unwrap().line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    REDUNDANT_OVERRIDE,
+                    "Health is redeclared with the value it inherits, 1000",
+                    5
+                ),
+                (
+                    PARTIAL_OVERRIDE,
+                    "State Jab is overridden but its helper JabBox is inherited as is",
+                    7
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numeric_sanity_rules() {
+        let source = ":Character:\nName: Test\n:Variables:\nvar Meter(Int): 0\nvar MeterMax(Int): 100\n:Jab:\n---Init:\nAttackDamage(25000)\nAttackDuration(0)\nAttackHitstunBlockstun(12, -3)\nHitbox(0, 15000, 5000, 5000)\nHurtbox(0, 0, 100)\nAdd(Meter, 150)\n---Reaction:\nTransition(Idle)\n";
+        let character = parse(source);
+
+        let diagnostics = lint_source(&character, source, &LintConfig::new());
+        let found: Vec<(&str, usize, usize, usize)> = diagnostics
+            .iter()
+            .map(|d| {
+                let span = d.span.as_ref().unwrap();
+                (d.code.as_str(), span.line, span.column, span.length)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (EXCESSIVE_DAMAGE, 8, 13, 5),
+                (ZERO_DURATION, 9, 15, 1),
+                (NEGATIVE_DURATION, 10, 27, 2),
+                (ZERO_AREA_BOX, 11, 23, 4),
+                (ZERO_AREA_BOX, 12, 8, 1),
+                (METER_OVER_MAX, 13, 11, 3),
+            ]
+        );
+        assert_eq!(diagnostics[3].message, "Hitbox in state Jab has no height");
+        assert_eq!(diagnostics[4].message, "Hurtbox in state Jab has no width");
+
+        // Without the source, spans cover the line
+        let diagnostics = lint_character(&character, &LintConfig::new());
+        assert_eq!(diagnostics[0].span, Some(Span::line(8)));
+
+        let mut config = LintConfig::new();
+        config.set_limits(SanityLimits {
+            max_damage: 30000.0,
+            ..SanityLimits::default()
+        });
+        assert!(!codes(&lint_character(&character, &config)).contains(&EXCESSIVE_DAMAGE));
+
+        let source = source.replace(
+            "AttackDamage(25000)",
+            "AttackDamage(25000) # castagne-ignore: excessive-damage",
+        );
+        let diagnostics = lint_source(&parse(&source), &source, &LintConfig::new());
+        assert_eq!(codes(&diagnostics)[0], ZERO_DURATION);
+
+        // Setting the meter above its maximum is caught too
+        let source = source.replace("Add(Meter, 150)", "Set(Meter, 150)");
+        let diagnostics = lint_source(&parse(&source), &source, &LintConfig::new());
+        let over_max = diagnostics
+            .iter()
+            .find(|d| d.code == METER_OVER_MAX)
+            .unwrap();
+        assert_eq!(
+            over_max.message,
+            "Set in state Jab gives 150 Meter, above MeterMax (100)"
+        );
+    }
+
+    #[test]
+    fn test_unknown_buttons() {
+        let source = ":Character:\nName: Test\n:Idle:\n---Init:\nInputTransition(236L, Fireball)\nInputTransition(5A)\nInputFlag(5MThorw)\nInputTransition(2]l[)\n:5X:\n---Init:\nAttackRegister(Light)\n:Fireball:\n---Init:\nAttackRegister(Special)\n";
+        let character = parse(source);
+        let mut config = LintConfig::new();
+        config.allow(ATTACK_WITHOUT_REACTION);
+        assert!(lint_character(&character, &config).is_empty());
+
+        // The engine's default layout
+        let input = crate::config::CastagneConfig::default().input().unwrap();
+        config.set_buttons(input.buttons());
+        let diagnostics = lint_source(&character, source, &config);
+        let mut found: Vec<(usize, usize, &str, &str)> = diagnostics
+            .iter()
+            .map(|d| {
+                let span = d.span.as_ref().unwrap();
+                (
+                    span.line,
+                    span.column,
+                    d.message.as_str(),
+                    d.notes[0].as_str(),
+                )
+            })
+            .collect();
+        found.sort();
+        let buttons = "Buttons of the input layout: L, M, H, S, E, Pause, Reset, TrainingButton1, TrainingButton2, Throw, Jump, Tech";
+        assert_eq!(
+            found,
+            vec![
+                (
+                    6,
+                    16,
+                    "InputTransition in state Idle uses button A in 5A, missing from the input layout",
+                    buttons,
+                ),
+                (
+                    7,
+                    10,
+                    "InputFlag in state Idle uses button Thorw in 5MThorw, missing from the input layout",
+                    "Did you mean Throw?",
+                ),
+                (
+                    8,
+                    16,
+                    "InputTransition in state Idle uses button l in 2]l[, missing from the input layout",
+                    "Did you mean L?",
+                ),
+                (
+                    11,
+                    0,
+                    "AttackRegister in state 5X uses button X in 5X, missing from the input layout",
+                    buttons,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_exhaustive_switch() {
+        let character = parse(
+            ":Character:\nName: Test\n:Variables:\nvar Stance(Str, Stand|Crouch|Air): Stand\n:Idle:\n---Init:\nSwitch(Stance)\nCase(Stand)\nAnim(Idle)\nEndSwitch\nSwitch(Stance)\nCase(Air)\nDefault\nStop\nEndSwitch\n",
+        );
+
+        let diagnostics = lint_character(&character, &LintConfig::new());
+        assert_eq!(codes(&diagnostics), vec![NON_EXHAUSTIVE_SWITCH]);
+        assert_eq!(
+            diagnostics[0].message,
+            "Switch on Stance in state Idle doesn't handle Crouch, Air"
+        );
+        assert_eq!(diagnostics[0].notes, ["Add Case(Crouch, Air) or a Default"]);
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 7);
+    }
+}
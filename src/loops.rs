@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Loops - Repeated actions in state scripts
+//!
+//! `Repeat(n)` runs the actions up to its `EndRepeat` `n` times, `n` being
+//! a number or a define, and `While(Condition)` runs them up to its
+//! `EndWhile` for as long as the condition holds:
+//!
+//! ```text
+//! ---Action:
+//! F4:
+//! Repeat(3)
+//! AttackDamage(100)
+//! AttackRegisterHit
+//! EndRepeat
+//! endif
+//! ```
+//!
+//! Like branches, loops stay flat in the actions of a phase: the opening
+//! and closing instructions are actions of their own. `loops` gives the
+//! structure back, and the parser reports loops that are never closed or
+//! that cross the bounds of a branch. `unroll_repeats` writes repeats with
+//! a known count out in full, for tools and engines without loops.
+
+use crate::intern::Symbol;
+use crate::parser::{ParsedAction, ParsedVariable};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Kinds of loops, by their opening instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoopKind {
+    /// `Repeat(Count)` ... `EndRepeat`
+    Repeat,
+    /// `While(Condition)` ... `EndWhile`
+    While,
+}
+
+impl LoopKind {
+    /// Loop opened by an instruction
+    pub fn of_opening(instruction: &str) -> Option<Self> {
+        match instruction {
+            "Repeat" => Some(LoopKind::Repeat),
+            "While" => Some(LoopKind::While),
+            _ => None,
+        }
+    }
+
+    /// Loop closed by an instruction
+    pub fn of_closing(instruction: &str) -> Option<Self> {
+        match instruction {
+            "EndRepeat" => Some(LoopKind::Repeat),
+            "EndWhile" => Some(LoopKind::While),
+            _ => None,
+        }
+    }
+
+    pub fn opening(&self) -> &'static str {
+        match self {
+            LoopKind::Repeat => "Repeat",
+            LoopKind::While => "While",
+        }
+    }
+
+    pub fn closing(&self) -> &'static str {
+        match self {
+            LoopKind::Repeat => "EndRepeat",
+            LoopKind::While => "EndWhile",
+        }
+    }
+}
+
+/// A loop of a phase, with the positions of its bounds in the actions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub kind: LoopKind,
+    /// Count of a repeat or condition of a while, as written
+    pub argument: String,
+    /// Index of the opening action
+    pub start: usize,
+    /// Index of the closing action
+    pub end: usize,
+}
+
+impl Loop {
+    /// Actions between the bounds
+    pub fn body<'a>(&self, actions: &'a [ParsedAction]) -> &'a [ParsedAction] {
+        &actions[self.start + 1..self.end]
+    }
+
+    /// Number of runs of a repeat, with defines taken from `variables`;
+    /// `None` for a while or a count that isn't known when parsing
+    pub fn count(&self, variables: &HashMap<Symbol, ParsedVariable>) -> Option<usize> {
+        if self.kind != LoopKind::Repeat {
+            return None;
+        }
+        let count = self.argument.trim();
+        count
+            .parse()
+            .ok()
+            .or_else(|| variables.get(count)?.value.trim().parse().ok())
+    }
+}
+
+/// Loops closed within `actions`, by position of their opening action
+///
+/// A closing instruction only closes the innermost loop when no branch
+/// opened since is still open; those that don't, and loops never closed,
+/// are left out.
+pub fn loops(actions: &[ParsedAction]) -> Vec<Loop> {
+    // Open loops with their start, `None` for branches
+    let mut open: Vec<Option<(LoopKind, usize)>> = Vec::new();
+    let mut found = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let instruction = &*action.instruction;
+        if let Some(kind) = LoopKind::of_opening(instruction) {
+            open.push(Some((kind, index)));
+        } else if let Some(kind) = LoopKind::of_closing(instruction) {
+            if let Some(Some((opened, start))) = open.last().copied() {
+                if opened == kind {
+                    open.pop();
+                    found.push(Loop {
+                        kind,
+                        argument: actions[start]
+                            .args
+                            .first()
+                            .map(str::to_string)
+                            .unwrap_or_default(),
+                        start,
+                        end: index,
+                    });
+                }
+            }
+        } else if instruction.eq_ignore_ascii_case("endif") {
+            if let Some(None) = open.last() {
+                open.pop();
+            }
+        } else if instruction.ends_with(':') {
+            open.push(None);
+        }
+    }
+    found.sort_by_key(|found| found.start);
+    found
+}
+
+/// `actions` with every repeat of known count replaced by that many
+/// copies of its body, nested repeats included
+pub fn unroll_repeats(
+    actions: &[ParsedAction],
+    variables: &HashMap<Symbol, ParsedVariable>,
+) -> Vec<ParsedAction> {
+    let repeats: HashMap<usize, (Loop, usize)> = loops(actions)
+        .into_iter()
+        .filter_map(|found| {
+            let count = found.count(variables)?;
+            Some((found.start, (found, count)))
+        })
+        .collect();
+
+    let mut unrolled = Vec::new();
+    let mut index = 0;
+    while index < actions.len() {
+        match repeats.get(&index) {
+            Some((found, count)) => {
+                let body = unroll_repeats(found.body(actions), variables);
+                for _ in 0..*count {
+                    unrolled.extend(body.iter().cloned());
+                }
+                index = found.end + 1;
+            }
+            None => {
+                unrolled.push(actions[index].clone());
+                index += 1;
+            }
+        }
+    }
+    unrolled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{CastagneParser, MISMATCHED_BLOCK_END, UNCLOSED_LOOP};
+
+    #[test]
+    fn test_loops_and_unrolling() {
+        let source = "\
+:Variables:
+def Hits: 2
+:Barrage:
+---Action:
+Repeat(Hits)
+AttackRegisterHit
+Repeat(3)
+Move(1)
+EndRepeat
+EndRepeat
+While(IsHolding)
+Move(2)
+EndWhile
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let actions = &character.states["Barrage"].actions["Action"];
+        let found = loops(actions);
+        let bounds: Vec<(LoopKind, usize, usize)> = found
+            .iter()
+            .map(|found| (found.kind, found.start, found.end))
+            .collect();
+        assert_eq!(
+            bounds,
+            [
+                (LoopKind::Repeat, 0, 5),
+                (LoopKind::Repeat, 2, 4),
+                (LoopKind::While, 6, 8)
+            ]
+        );
+        assert_eq!(found[0].count(&character.variables), Some(2));
+        assert_eq!(found[2].count(&character.variables), None);
+
+        let unrolled = unroll_repeats(actions, &character.variables);
+        let instructions: Vec<&str> = unrolled.iter().map(|a| a.instruction.as_str()).collect();
+        let hit = ["AttackRegisterHit", "Move", "Move", "Move"];
+        let mut expected = [hit, hit].concat();
+        expected.extend(["While", "Move", "EndWhile"]);
+        assert_eq!(instructions, expected);
+    }
+
+    #[test]
+    fn test_loop_nesting_errors() {
+        let source = "\
+:Idle:
+---Init:
+Repeat(2)
+LFlag:
+EndRepeat
+endif
+EndRepeat
+While(IsHolding)
+Stop
+";
+        let mut parser = CastagneParser::new();
+        parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let found: Vec<(&str, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(found, [(MISMATCHED_BLOCK_END, 5), (UNCLOSED_LOOP, 8)]);
+        assert_eq!(
+            parser.get_errors()[0],
+            "EndRepeat would close LFlag: from line 4, close it with endif first (line 5, column 1)"
+        );
+    }
+}
@@ -0,0 +1,755 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! LSP - Language server for .casp files
+//!
+//! Lets editors such as VS Code show the parser's diagnostics as the file
+//! is typed, jump to the definition of states and variables, list their
+//! usages across the skeleton chain, hover them (with frame data for
+//! attacks) and rename them. `CaspLanguageServer`
+//! answers requests on open documents; `run_stdio` serves it over stdin
+//! and stdout, which is what the `casp-lsp` binary does. Documents are
+//! reparsed in full on every change, skeletons being read from disk.
+
+use crate::borrowed::strip_comment;
+use crate::diagnostics::{self, Severity};
+use crate::frame_data::AttackData;
+use crate::parser::{
+    block_header_name, split_name_and_type, split_variables_header, CastagneParser,
+    ParsedCharacter, ParserConfig,
+};
+use crate::refactor;
+use crate::references::{self, read_chain, References, Symbol};
+use crate::validation::error_line;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    GotoDefinition, HoverRequest, References as ReferencesRequest, Rename, Request as LspRequest,
+};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind,
+    OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams, RenameParams,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+    WorkspaceEdit,
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Characters that end a name in action arguments and headers
+const NAME_DELIMITERS: &[char] = &['(', ')', ',', ':', '"', '#', '=', '+', '*', '/'];
+
+/// An open .casp buffer and its last parse
+struct Document {
+    /// File path of the document, or its URI when it isn't a file
+    path: String,
+    lines: Vec<String>,
+    character: Option<ParsedCharacter>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Answers language server requests about open .casp documents
+pub struct CaspLanguageServer {
+    config: ParserConfig,
+    documents: HashMap<Url, Document>,
+}
+
+impl CaspLanguageServer {
+    pub fn new(config: ParserConfig) -> Self {
+        Self {
+            config,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Features announced to the editor on initialization
+    pub fn capabilities() -> ServerCapabilities {
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(true)),
+            rename_provider: Some(OneOf::Left(true)),
+            ..ServerCapabilities::default()
+        }
+    }
+
+    /// Open or replace a document, parsing it
+    pub fn update(&mut self, uri: Url, text: &str) {
+        let path = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.to_str().map(str::to_string))
+            .unwrap_or_else(|| uri.to_string());
+        let mut parser = CastagneParser::with_config(self.config.clone());
+        let character = parser.create_full_character_from_source(&path, text).ok();
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let diagnostics = lsp_diagnostics(&parser, &path, &lines);
+        self.documents.insert(
+            uri,
+            Document {
+                path,
+                lines,
+                character,
+                diagnostics,
+            },
+        );
+    }
+
+    pub fn close(&mut self, uri: &Url) {
+        self.documents.remove(uri);
+    }
+
+    /// Diagnostics of the last parse of a document
+    pub fn diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        self.documents
+            .get(uri)
+            .map(|document| document.diagnostics.clone())
+            .unwrap_or_default()
+    }
+
+    /// Where the state or variable under the cursor is defined, in the
+    /// document or else in its skeleton chain
+    pub fn definition(&self, uri: &Url, position: Position) -> Option<Location> {
+        let document = self.documents.get(uri)?;
+        let (_, name) = name_at(document, position)?;
+        let local = definitions(&document.lines)
+            .into_iter()
+            .rev()
+            .find(|(defined, _)| *defined == name);
+        if let Some((_, range)) = local {
+            return Some(Location::new(uri.clone(), range));
+        }
+        let (references, sources) = self.find_references(uri, position)?;
+        lsp_location(&references.definition?, &sources)
+    }
+
+    /// Definitions and usages of the state or variable under the cursor,
+    /// in the document and its skeleton chain
+    pub fn references(&self, uri: &Url, position: Position) -> Vec<Location> {
+        let Some((references, sources)) = self.find_references(uri, position) else {
+            return Vec::new();
+        };
+        references
+            .spans()
+            .filter_map(|span| lsp_location(span, &sources))
+            .collect()
+    }
+
+    fn find_references(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> Option<(References, Vec<(String, String)>)> {
+        let (symbol, sources) = self.symbol_at(uri, position)?;
+        Some((references::find_in_sources(&symbol, &sources), sources))
+    }
+
+    /// The state or variable under the cursor, with the sources of the
+    /// document and its skeleton chain, parents first
+    fn symbol_at(&self, uri: &Url, position: Position) -> Option<(Symbol, Vec<(String, String)>)> {
+        let document = self.documents.get(uri)?;
+        let character = document.character.as_ref()?;
+        let (_, name) = name_at(document, position)?;
+        let symbol = if character.states.contains_key(name.as_str()) {
+            Symbol::state(&name)
+        } else if character.variables.contains_key(name.as_str()) {
+            Symbol::variable(&name)
+        } else {
+            return None;
+        };
+        // Open documents are read from their buffers, unsaved edits included
+        let open = |path: &str| {
+            self.documents
+                .values()
+                .find(|open| open.path == path)
+                .map(|open| open.lines.join("\n"))
+        };
+        let metadata = &character.metadata;
+        let dependencies: Vec<&str> = metadata
+            .skeleton
+            .iter()
+            .chain(&metadata.includes)
+            .map(String::as_str)
+            .collect();
+        let mut sources = read_chain(&dependencies, &self.config, open).ok()?;
+        sources.push((document.path.clone(), document.lines.join("\n")));
+        Some((symbol, sources))
+    }
+
+    /// Description of the state or variable under the cursor
+    pub fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let document = self.documents.get(uri)?;
+        let character = document.character.as_ref()?;
+        let (range, name) = name_at(document, position)?;
+
+        let mut text = if let Some(state) = character.states.get(name.as_str()) {
+            let mut text = format!("**State** `{}` ({:?})", state.name, state.state_type);
+            if let Some(parent) = &state.parent {
+                text.push_str(&format!("\n\nParent: `{}`", parent));
+            }
+            if let Some(description) = &state.description {
+                text.push_str(&format!("\n\n{}", description));
+            }
+            if let Some(attack) = AttackData::from_state(state) {
+                text.push_str(&frame_data_table(&attack));
+            }
+            text
+        } else if let Some(variable) = character.variables.get(name.as_str()) {
+            let subtype = match variable.subtype.as_str() {
+                "" => String::new(),
+                subtype => format!(", {}", subtype),
+            };
+            format!(
+                "**{:?}** `{}({:?}{})` = `{}`",
+                variable.mutability, variable.name, variable.var_type, subtype, variable.value
+            )
+        } else {
+            return None;
+        };
+        let origin = match character.states.get(name.as_str()) {
+            Some(state) => &state.origin,
+            None => &character.variables[name.as_str()].origin,
+        };
+        if !origin.file.is_empty() && origin.file != document.path {
+            text.push_str(&format!("\n\nInherited from `{}`", origin));
+        }
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: text,
+            }),
+            range: Some(range),
+        })
+    }
+
+    /// Rename the state or variable under the cursor in the document and
+    /// its skeleton chain, see `refactor::rename_in_sources`
+    ///
+    /// `None` when there is nothing to rename under the cursor; invalid
+    /// names and names already taken are errors.
+    pub fn rename(
+        &self,
+        uri: &Url,
+        position: Position,
+        new_name: &str,
+    ) -> Result<Option<WorkspaceEdit>, String> {
+        let Some((symbol, sources)) = self.symbol_at(uri, position) else {
+            return Ok(None);
+        };
+        let mut changes = HashMap::new();
+        for file in refactor::rename_in_sources(&sources, &symbol, new_name)? {
+            let Some((_, source)) = sources.iter().find(|(path, _)| *path == file.path) else {
+                continue;
+            };
+            let Some(file_uri) = file_uri(&file.path) else {
+                continue;
+            };
+            // The whole file is replaced, as `rename_in_sources` returns texts
+            let last_line = source.split('\n').count() - 1;
+            let last = source.rsplit('\n').next().unwrap_or_default();
+            let end = span_range(last, last_line, 0, last.chars().count()).end;
+            let range = Range::new(Position::new(0, 0), end);
+            changes.insert(file_uri, vec![TextEdit::new(range, file.source)]);
+        }
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    /// Answer a request, with an error response for unsupported methods
+    pub fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => params::<HoverParams>(request).map(|params| {
+                let at = params.text_document_position_params;
+                serde_json::to_value(self.hover(&at.text_document.uri, at.position))
+            }),
+            GotoDefinition::METHOD => params::<GotoDefinitionParams>(request).map(|params| {
+                let at = params.text_document_position_params;
+                let location = self.definition(&at.text_document.uri, at.position);
+                serde_json::to_value(location.map(GotoDefinitionResponse::Scalar))
+            }),
+            ReferencesRequest::METHOD => params::<ReferenceParams>(request).map(|params| {
+                let at = params.text_document_position;
+                serde_json::to_value(self.references(&at.text_document.uri, at.position))
+            }),
+            Rename::METHOD => match params::<RenameParams>(request) {
+                Ok(params) => {
+                    let at = params.text_document_position;
+                    match self.rename(&at.text_document.uri, at.position, &params.new_name) {
+                        Ok(edit) => Ok(serde_json::to_value(edit)),
+                        Err(message) => {
+                            return Response::new_err(id, ErrorCode::RequestFailed as i32, message)
+                        }
+                    }
+                }
+                Err(message) => Err(message),
+            },
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request: {}", method),
+                )
+            }
+        };
+        match result {
+            Ok(Ok(value)) => Response::new_ok(id, value),
+            Ok(Err(e)) => Response::new_err(id, ErrorCode::InternalError as i32, e.to_string()),
+            Err(message) => Response::new_err(id, ErrorCode::InvalidParams as i32, message),
+        }
+    }
+
+    /// Apply a document notification, returning the document whose
+    /// diagnostics changed
+    pub fn handle_notification(&mut self, notification: Notification) -> Option<Url> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    params_of(notification.params).ok()?;
+                let uri = params.text_document.uri;
+                self.update(uri.clone(), &params.text_document.text);
+                Some(uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    params_of(notification.params).ok()?;
+                let uri = params.text_document.uri;
+                // Full sync: the last change holds the whole text
+                let change = params.content_changes.into_iter().last()?;
+                self.update(uri.clone(), &change.text);
+                Some(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    params_of(notification.params).ok()?;
+                self.close(&params.text_document.uri);
+                Some(params.text_document.uri)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Serve the language server over stdin and stdout until the editor exits
+pub fn run_stdio(config: ParserConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(CaspLanguageServer::capabilities())?)?;
+
+    let mut server = CaspLanguageServer::new(config);
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection
+                    .sender
+                    .send(Message::Response(server.handle_request(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.handle_notification(notification) {
+                    let params =
+                        PublishDiagnosticsParams::new(uri.clone(), server.diagnostics(&uri), None);
+                    connection
+                        .sender
+                        .send(Message::Notification(Notification::new(
+                            PublishDiagnostics::METHOD.to_string(),
+                            params,
+                        )))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    io_threads.join()?;
+    Ok(())
+}
+
+fn params<P: DeserializeOwned>(request: Request) -> Result<P, String> {
+    params_of(request.params)
+}
+
+fn params_of<P: DeserializeOwned>(params: serde_json::Value) -> Result<P, String> {
+    serde_json::from_value(params).map_err(|e| format!("Invalid parameters: {}", e))
+}
+
+/// The parser's diagnostics and errors about `path`, as LSP diagnostics
+fn lsp_diagnostics(parser: &CastagneParser, path: &str, lines: &[String]) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    for diagnostic in parser.get_diagnostics() {
+        let Some(span) = &diagnostic.span else {
+            continue;
+        };
+        if span.file.as_deref().is_some_and(|file| file != path) {
+            continue;
+        }
+        let line = span.line.saturating_sub(1);
+        let text = lines.get(line).map_or("", String::as_str);
+        let end = match span.length {
+            0 => text.chars().count(),
+            length => span.column + length,
+        };
+        result.push(Diagnostic {
+            range: span_range(text, line, span.column, end),
+            severity: Some(lsp_severity(diagnostic.severity)),
+            code: Some(lsp_types::NumberOrString::String(diagnostic.code.clone())),
+            source: Some("casp".to_string()),
+            message: diagnostic_message(diagnostic),
+            ..Diagnostic::default()
+        });
+    }
+
+    // Errors reported without a diagnostic, located by their `(line N`
+    for error in parser.get_errors() {
+        let reported = parser
+            .get_diagnostics()
+            .iter()
+            .any(|diagnostic| error.starts_with(&diagnostic.message));
+        if reported {
+            continue;
+        }
+        let line = error_line(error).unwrap_or(1).saturating_sub(1);
+        let text = lines.get(line).map_or("", String::as_str);
+        result.push(Diagnostic {
+            range: span_range(text, line, 0, text.chars().count()),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("casp".to_string()),
+            message: error.clone(),
+            ..Diagnostic::default()
+        });
+    }
+    result
+}
+
+fn diagnostic_message(diagnostic: &diagnostics::Diagnostic) -> String {
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str("\nnote: ");
+        message.push_str(note);
+    }
+    message
+}
+
+fn lsp_severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Hint => DiagnosticSeverity::HINT,
+        Severity::Info => DiagnosticSeverity::INFORMATION,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Error => DiagnosticSeverity::ERROR,
+    }
+}
+
+/// A span of one of the `(path, source)` pairs as an LSP location
+fn lsp_location(span: &diagnostics::Span, sources: &[(String, String)]) -> Option<Location> {
+    let file = span.file.as_deref()?;
+    let (_, source) = sources.iter().find(|(path, _)| path == file)?;
+    let line = span.line.checked_sub(1)?;
+    let text = source.lines().nth(line)?;
+    let uri = file_uri(file)?;
+    let range = span_range(text, line, span.column, span.column + span.length);
+    Some(Location::new(uri, range))
+}
+
+/// URI of a file path, or the path itself when it is already a URI
+fn file_uri(path: &str) -> Option<Url> {
+    Url::from_file_path(path).or_else(|_| Url::parse(path)).ok()
+}
+
+/// Range of the characters `start..end` of a line, in UTF-16 units
+fn span_range(text: &str, line: usize, start: usize, end: usize) -> Range {
+    let utf16 = |column: usize| -> u32 {
+        text.chars()
+            .take(column)
+            .map(|c| c.len_utf16() as u32)
+            .sum()
+    };
+    Range::new(
+        Position::new(line as u32, utf16(start)),
+        Position::new(line as u32, utf16(end)),
+    )
+}
+
+fn is_name_char(c: char) -> bool {
+    !c.is_whitespace() && !NAME_DELIMITERS.contains(&c)
+}
+
+/// The name under the cursor, with its range
+fn name_at(document: &Document, position: Position) -> Option<(Range, String)> {
+    let line = position.line as usize;
+    let text = document.lines.get(line)?;
+    let chars: Vec<char> = text.chars().collect();
+
+    let mut utf16 = 0;
+    let mut cursor = chars.len();
+    for (index, c) in chars.iter().enumerate() {
+        if utf16 >= position.character {
+            cursor = index;
+            break;
+        }
+        utf16 += c.len_utf16() as u32;
+    }
+
+    let mut start = cursor;
+    while start > 0 && is_name_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = cursor;
+    while end < chars.len() && is_name_char(chars[end]) {
+        end += 1;
+    }
+    if start == end {
+        return None;
+    }
+    let name: String = chars[start..end].iter().collect();
+    Some((span_range(text, line, start, end), name))
+}
+
+/// States and variables defined in the lines, with the range of their name
+fn definitions(lines: &[String]) -> Vec<(String, Range)> {
+    let mut result = Vec::new();
+    let mut in_variables = false;
+    for (line, text) in lines.iter().enumerate() {
+        let trimmed = text.trim();
+        let indent = text.chars().count() - text.trim_start().chars().count();
+        if let Ok(header) = block_header_name(trimmed) {
+            in_variables = split_variables_header(header).is_some();
+            if !in_variables && header != "Character" {
+                let name = header.split('(').next().unwrap_or(header).trim();
+                let start = indent + 1;
+                let end = start + name.chars().count();
+                result.push((name.to_string(), span_range(text, line, start, end)));
+            }
+            continue;
+        }
+        if !in_variables {
+            continue;
+        }
+        let code = strip_comment(trimmed);
+        let declaration = ["var ", "def ", "internal "]
+            .iter()
+            .find_map(|keyword| code.strip_prefix(keyword));
+        let Some(declaration) = declaration else {
+            continue;
+        };
+        let declaration = declaration.split(':').next().unwrap_or(declaration);
+        if let Some((name, _, _)) = split_name_and_type(declaration.trim()) {
+            if let Some((start, end)) = occurrences(text, name).next() {
+                result.push((name.to_string(), span_range(text, line, start, end)));
+            }
+        }
+    }
+    result
+}
+
+/// Character ranges of `name` in the code of a line, as a whole name
+fn occurrences<'a>(text: &'a str, name: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let code = strip_comment(text);
+    code.match_indices(name).filter_map(move |(byte, _)| {
+        let end_byte = byte + name.len();
+        let before = code[..byte].chars().next_back();
+        let after = code[end_byte..].chars().next();
+        if before.is_some_and(is_name_char) || after.is_some_and(is_name_char) {
+            return None;
+        }
+        let start = code[..byte].chars().count();
+        Some((start, start + name.chars().count()))
+    })
+}
+
+fn frame_data_table(attack: &AttackData) -> String {
+    let rows = [
+        ("Input", &attack.input),
+        ("Type", &attack.attack_type),
+        ("Damage", &attack.damage),
+        ("Duration", &attack.duration),
+        ("On hit", &attack.advantage_hit),
+        ("On block", &attack.advantage_block),
+    ];
+    let mut table = String::from("\n\n| Frame data | |\n|---|---|");
+    for (label, value) in rows {
+        if let Some(value) = value {
+            table.push_str(&format!("\n| {} | {} |", label, value));
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = ":Character:\nName: Ryu\n:Variables:\nvar Health(Int): 1000\n:Idle:\n---Action:\nSet(Health, 5)\nTransition(Jab)\n:Jab:\n---Init:\nAttackRegister(Light, 5A)\nAttackDamage(300)\nAttackDuration(18)\nAttackFrameAdvantage(3, -2)\nSetVar(Health, 1)\nIfLt(Health, 1):\n";
+
+    fn server() -> (CaspLanguageServer, Url) {
+        let uri = Url::parse("file:///tmp/ryu.casp").unwrap();
+        let mut deprecations = crate::legacy::Deprecations::new();
+        deprecations.deprecate(crate::legacy::LegacyKind::Instruction, "SetVar", "Set");
+        let config = ParserConfig::default().with_deprecations(deprecations);
+        let mut server = CaspLanguageServer::new(config);
+        server.update(uri.clone(), SOURCE);
+        (server, uri)
+    }
+
+    #[test]
+    fn test_diagnostics_have_ranges() {
+        let (server, uri) = server();
+        let diagnostics = server.diagnostics(&uri);
+        let deprecated = diagnostics
+            .iter()
+            .find(|d| d.code == Some(lsp_types::NumberOrString::String("deprecated-name".into())))
+            .unwrap();
+        assert_eq!(
+            deprecated.range,
+            Range::new(Position::new(14, 0), Position::new(14, 6))
+        );
+        assert_eq!(deprecated.severity, Some(DiagnosticSeverity::WARNING));
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.message.starts_with("Branch IfLt")));
+        assert_eq!(error_line("Bad thing (line 12, column 3)"), Some(12));
+    }
+
+    #[test]
+    fn test_definition_hover_and_rename() {
+        let (server, uri) = server();
+
+        let location = server.definition(&uri, Position::new(7, 12)).unwrap();
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(8, 1), Position::new(8, 4))
+        );
+        let location = server.definition(&uri, Position::new(6, 5)).unwrap();
+        assert_eq!(location.range.start, Position::new(3, 4));
+
+        let Some(Hover {
+            contents: HoverContents::Markup(hover),
+            ..
+        }) = server.hover(&uri, Position::new(7, 12))
+        else {
+            panic!("no hover on Jab");
+        };
+        assert!(hover.value.starts_with("**State** `Jab` (Normal)"));
+        assert!(hover.value.contains("| Damage | 300 |"));
+        assert!(hover.value.contains("| On block | -2 |"));
+
+        let edit = server.rename(&uri, Position::new(6, 5), "HP").unwrap();
+        let edits = &edit.unwrap().changes.unwrap()[&uri];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.end, Position::new(15, 16));
+        let lines: Vec<usize> = edits[0]
+            .new_text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.contains("HP"))
+            .map(|(line, _)| line)
+            .collect();
+        assert_eq!(lines, vec![3, 6, 14, 15]);
+        assert_eq!(server.rename(&uri, Position::new(10, 2), "X"), Ok(None));
+        assert_eq!(
+            server.rename(&uri, Position::new(7, 12), "Idle"),
+            Err("State Idle already exists (/tmp/ryu.casp, line 5)".to_string())
+        );
+        assert!(server
+            .rename(&uri, Position::new(7, 12), "Light Jab")
+            .is_err());
+    }
+
+    #[test]
+    fn test_definitions_and_references_in_skeletons() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp");
+        std::fs::write(
+            &base,
+            ":Character:\nName: Base\n:Variables:\nvar Meter(Int): 0\n:Idle:\n---Action:\nAdd(Meter, 1)\n",
+        )
+        .unwrap();
+        let uri = Url::from_file_path(dir.path().join("ryu.casp")).unwrap();
+        let source = format!(
+            ":Character:\nSkeleton: {}\n:Jab:\n---Init:\nSet(Meter, 2)\n",
+            base.display()
+        );
+        let mut server = CaspLanguageServer::new(ParserConfig::default());
+        server.update(uri.clone(), &source);
+
+        let base_uri = Url::from_file_path(&base).unwrap();
+        let location = server.definition(&uri, Position::new(4, 5)).unwrap();
+        assert_eq!(location.uri, base_uri);
+        assert_eq!(location.range.start, Position::new(3, 4));
+
+        let references = server.references(&uri, Position::new(4, 5));
+        let found: Vec<(bool, u32)> = references
+            .iter()
+            .map(|l| (l.uri == uri, l.range.start.line))
+            .collect();
+        assert_eq!(found, vec![(false, 3), (false, 6), (true, 4)]);
+
+        let Some(Hover {
+            contents: HoverContents::Markup(hover),
+            ..
+        }) = server.hover(&uri, Position::new(4, 5))
+        else {
+            panic!("no hover on Meter");
+        };
+        let inherited = format!("Inherited from `{}:4`", base.display());
+        assert!(hover.value.ends_with(&inherited));
+
+        let edit = server.rename(&uri, Position::new(4, 5), "Gauge").unwrap();
+        let changes = edit.unwrap().changes.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(changes[&base_uri][0].new_text.contains("Add(Gauge, 1)"));
+        assert!(changes[&uri][0].new_text.contains("Set(Gauge, 2)"));
+    }
+
+    #[test]
+    fn test_requests_and_notifications() {
+        let mut server = CaspLanguageServer::new(ParserConfig::default());
+        let uri = Url::parse("file:///tmp/ken.casp").unwrap();
+        let open = Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            serde_json::json!({
+                "textDocument": {"uri": uri, "languageId": "casp", "version": 1, "text": SOURCE}
+            }),
+        );
+        assert_eq!(server.handle_notification(open), Some(uri.clone()));
+
+        let hover = Request::new(
+            1.into(),
+            HoverRequest::METHOD.to_string(),
+            serde_json::json!({"textDocument": {"uri": uri}, "position": {"line": 3, "character": 5}}),
+        );
+        let response = server.handle_request(hover);
+        assert!(response.error.is_none());
+        assert!(response.result.unwrap()["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("`Health(Int)` = `1000`"));
+
+        let rename = Request::new(
+            2.into(),
+            Rename::METHOD.to_string(),
+            serde_json::json!({
+                "textDocument": {"uri": uri}, "position": {"line": 7, "character": 12}, "newName": "Idle"
+            }),
+        );
+        assert_eq!(
+            server.handle_request(rename).error.unwrap().code,
+            ErrorCode::RequestFailed as i32
+        );
+
+        let unknown = Request::new(
+            3.into(),
+            "casp/unknown".to_string(),
+            serde_json::Value::Null,
+        );
+        assert_eq!(
+            server.handle_request(unknown).error.unwrap().code,
+            ErrorCode::MethodNotFound as i32
+        );
+    }
+}
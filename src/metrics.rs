@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Metrics - Timing and allocation counts of a parse
+//!
+//! The parser times each phase of a parse and keeps the result as
+//! `ParseMetrics`, to compare against the GDScript parser and catch
+//! regressions. A profiler hook can also be handed to the parser to see
+//! each phase as it ends. Allocations are only counted when the program
+//! installs `CountingAllocator` as its global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Step of a parse
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParsePhase {
+    /// Reading and decoding the file, splitting it into lines
+    Io,
+    /// Block comments, multi-line strings and conditional lines
+    Preprocess,
+    Metadata,
+    /// Loading the skeleton and included files
+    Inheritance,
+    Specblocks,
+    /// Parsing variables and evaluating their defaults
+    Variables,
+    Templates,
+    States,
+}
+
+impl fmt::Display for ParsePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParsePhase::Io => "io",
+            ParsePhase::Preprocess => "preprocess",
+            ParsePhase::Metadata => "metadata",
+            ParsePhase::Inheritance => "inheritance",
+            ParsePhase::Specblocks => "specblocks",
+            ParsePhase::Variables => "variables",
+            ParsePhase::Templates => "templates",
+            ParsePhase::States => "states",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Called with each phase of a parse as it ends
+pub type Profiler = Arc<dyn Fn(ParsePhase, Duration) + Send + Sync>;
+
+/// Measurements of the last parse
+#[derive(Debug, Clone, Default)]
+pub struct ParseMetrics {
+    /// Time of each phase, in the order they first ran
+    pub phases: Vec<(ParsePhase, Duration)>,
+    /// Lines of the parsed file, not counting its skeleton or includes
+    pub lines: usize,
+    pub blank_lines: usize,
+    /// Lines holding only a `#` comment
+    pub comment_lines: usize,
+    /// Allocations made during the parse, `None` without `CountingAllocator`
+    pub allocations: Option<u64>,
+    pub allocated_bytes: Option<u64>,
+    /// Diagnostics silenced by `castagne-ignore` pragmas, by code
+    pub suppressed: BTreeMap<String, usize>,
+    start_counts: (u64, u64),
+}
+
+impl ParseMetrics {
+    /// Metrics of a parse starting now
+    pub(crate) fn start() -> Self {
+        Self {
+            start_counts: allocation_counts(),
+            ..Self::default()
+        }
+    }
+
+    /// Record the allocations made since `start`
+    pub(crate) fn finish(&mut self) {
+        let (allocations, bytes) = allocation_counts();
+        let allocations = allocations - self.start_counts.0;
+        // A parse always allocates, so nothing counted means no counter
+        if allocations > 0 {
+            self.allocations = Some(allocations);
+            self.allocated_bytes = Some(bytes - self.start_counts.1);
+        }
+    }
+
+    /// Count the lines of a loaded file
+    pub(crate) fn count_lines<S: AsRef<str>>(&mut self, lines: &[S]) {
+        for line in lines {
+            let line = line.as_ref().trim();
+            self.lines += 1;
+            if line.is_empty() {
+                self.blank_lines += 1;
+            } else if line.starts_with('#') {
+                self.comment_lines += 1;
+            }
+        }
+    }
+
+    /// Total time spent in a phase
+    pub fn time(&self, phase: ParsePhase) -> Duration {
+        self.phases
+            .iter()
+            .filter(|(timed, _)| *timed == phase)
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    /// Total time of the parse
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Lines that aren't blank or comments
+    pub fn code_lines(&self) -> usize {
+        self.lines - self.blank_lines - self.comment_lines
+    }
+}
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// System allocator that counts allocations for `ParseMetrics`
+///
+/// Install it in a benchmark or tool with
+/// `#[global_allocator] static ALLOC: CountingAllocator = CountingAllocator;`.
+/// Counts are process-wide: parses running on other threads at the same
+/// time are counted too.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+fn count(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// Allocations and bytes counted by `CountingAllocator` so far
+pub fn allocation_counts() -> (u64, u64) {
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_sum_phases_and_lines() {
+        let mut metrics = ParseMetrics::start();
+        metrics.phases = vec![
+            (ParsePhase::Io, Duration::from_millis(2)),
+            (ParsePhase::States, Duration::from_millis(5)),
+            (ParsePhase::Io, Duration::from_millis(1)),
+        ];
+        metrics.count_lines(&[":Idle:", "", "  # note", "---Init:", "Stop"]);
+
+        assert_eq!(metrics.time(ParsePhase::Io), Duration::from_millis(3));
+        assert_eq!(metrics.time(ParsePhase::Metadata), Duration::ZERO);
+        assert_eq!(metrics.total(), Duration::from_millis(8));
+        assert_eq!(
+            (metrics.lines, metrics.blank_lines, metrics.comment_lines),
+            (5, 1, 1)
+        );
+        assert_eq!(metrics.code_lines(), 3);
+        assert_eq!(ParsePhase::Inheritance.to_string(), "inheritance");
+    }
+
+    #[test]
+    fn test_counting_allocator_counts() {
+        let (allocations, bytes) = allocation_counts();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = CountingAllocator.alloc(layout);
+            let ptr = CountingAllocator.realloc(ptr, layout, 128);
+            CountingAllocator.dealloc(ptr, Layout::from_size_align(128, 8).unwrap());
+        }
+        let (after, after_bytes) = allocation_counts();
+        assert!(after >= allocations + 2);
+        assert!(after_bytes >= bytes + 192);
+    }
+}
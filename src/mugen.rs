@@ -0,0 +1,538 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! MUGEN - Experimental import of .cns states and .air animations
+//!
+//! Porting a MUGEN character starts from its `.cns` and `.air` files.
+//! `import_mugen` turns each `[Statedef]` into a .casp state to finish by
+//! hand: its `.air` action becomes `Sprite` calls on frame branches, a
+//! `ChangeState` on `trigger1 = 1` or `time = N` becomes a `Transition`
+//! and a `HitDef` an attack. Everything else is kept as a `# TODO:`
+//! comment next to where it belongs, so nothing of the original is lost.
+//!
+//! Only the basic syntax is read: sections, `key = value` lines, `;`
+//! comments and `.air` element lines. Expressions are never evaluated.
+
+use crate::cns::COMMON_STATES;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// A `[State N, label]` controller of a statedef
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CnsController {
+    pub label: String,
+    /// `type` parameter, e.g. `ChangeState`
+    pub kind: String,
+    /// `triggerall` conditions, then each numbered trigger's
+    pub trigger_all: Vec<String>,
+    pub triggers: Vec<Vec<String>>,
+    /// Other parameters, in file order, keys lowercased
+    pub params: Vec<(String, String)>,
+}
+
+impl CnsController {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A `[Statedef N]` and its controllers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CnsStatedef {
+    pub number: i64,
+    /// Parameters of the statedef itself, keys lowercased
+    pub params: Vec<(String, String)>,
+    pub controllers: Vec<CnsController>,
+}
+
+impl CnsStatedef {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// One element of an `.air` action: a sprite shown for some ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AirElement {
+    pub group: i64,
+    pub image: i64,
+    /// Ticks shown, -1 for forever
+    pub time: i64,
+}
+
+/// A `[Begin Action N]` of an `.air` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AirAction {
+    pub number: i64,
+    pub elements: Vec<AirElement>,
+    /// Element the action loops back to, when not the first
+    pub loop_start: Option<usize>,
+}
+
+/// Statedefs of a `.cns` file, in file order
+pub fn parse_cns(text: &str) -> Vec<CnsStatedef> {
+    let mut statedefs: Vec<CnsStatedef> = Vec::new();
+    for line in text.lines() {
+        let line = strip_comment(line);
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let section = section.trim();
+            let lower = section.to_ascii_lowercase();
+            if let Some(number) = lower.strip_prefix("statedef") {
+                statedefs.push(CnsStatedef {
+                    number: number.trim().parse().unwrap_or_default(),
+                    ..CnsStatedef::default()
+                });
+            } else if lower.starts_with("state ") {
+                let label = section
+                    .split_once(',')
+                    .map_or("", |(_, label)| label.trim());
+                if let Some(statedef) = statedefs.last_mut() {
+                    statedef.controllers.push(CnsController {
+                        label: label.to_string(),
+                        ..CnsController::default()
+                    });
+                }
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim().to_ascii_lowercase(), value.trim().to_string());
+        let Some(statedef) = statedefs.last_mut() else {
+            continue;
+        };
+        let Some(controller) = statedef.controllers.last_mut() else {
+            statedef.params.push((key, value));
+            continue;
+        };
+        if key == "type" {
+            controller.kind = value;
+        } else if key == "triggerall" {
+            controller.trigger_all.push(value);
+        } else if let Some(index) = key
+            .strip_prefix("trigger")
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            if controller.triggers.len() < index {
+                controller.triggers.resize(index, Vec::new());
+            }
+            if let Some(trigger) = index.checked_sub(1) {
+                controller.triggers[trigger].push(value);
+            }
+        } else {
+            controller.params.push((key, value));
+        }
+    }
+    statedefs
+}
+
+/// Actions of an `.air` file, in file order
+pub fn parse_air(text: &str) -> Vec<AirAction> {
+    let mut actions: Vec<AirAction> = Vec::new();
+    for line in text.lines() {
+        let line = strip_comment(line);
+        let lower = line.to_ascii_lowercase();
+        if let Some(section) = lower.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if let Some(number) = section.trim().strip_prefix("begin action") {
+                actions.push(AirAction {
+                    number: number.trim().parse().unwrap_or_default(),
+                    ..AirAction::default()
+                });
+            }
+            continue;
+        }
+        let Some(action) = actions.last_mut() else {
+            continue;
+        };
+        if lower.starts_with("loopstart") {
+            action.loop_start = Some(action.elements.len());
+            continue;
+        }
+        // Collision boxes and other `key: value` lines
+        if lower.starts_with("clsn") || line.contains(':') {
+            continue;
+        }
+        let fields: Vec<Option<i64>> = line
+            .split(',')
+            .take(5)
+            .map(|field| field.trim().parse().ok())
+            .collect();
+        if let [Some(group), Some(image), _, _, Some(time)] = fields[..] {
+            action.elements.push(AirElement { group, image, time });
+        }
+    }
+    actions
+}
+
+/// A .casp file with the states of a `.cns` and the animations of an
+/// `.air`, see the module documentation
+pub fn import_mugen(name: &str, cns: &str, air: Option<&str>) -> String {
+    let statedefs = parse_cns(cns);
+    let actions = air.map(parse_air).unwrap_or_default();
+    let anims: BTreeSet<i64> = statedefs
+        .iter()
+        .filter_map(|statedef| statedef.param("anim")?.trim().parse().ok())
+        .collect();
+
+    let mut out = String::new();
+    let _ = writeln!(out, ":Character:");
+    let _ = writeln!(out, "Name: {}", name);
+    let _ = writeln!(out, "# Imported from MUGEN: review every TODO");
+    let _ = writeln!(
+        out,
+        "# TODO: set up a spritesheet for each sprite group used by Sprite"
+    );
+    let skipped: Vec<String> = statedefs
+        .iter()
+        .filter(|statedef| statedef.number < 0)
+        .map(|statedef| statedef.number.to_string())
+        .collect();
+    if !skipped.is_empty() {
+        let _ = writeln!(
+            out,
+            "# TODO: port the special statedefs {} by hand",
+            skipped.join(", ")
+        );
+    }
+    let unused: Vec<String> = actions
+        .iter()
+        .filter(|action| !anims.contains(&action.number))
+        .map(|action| action.number.to_string())
+        .collect();
+    if !unused.is_empty() {
+        let _ = writeln!(
+            out,
+            "# TODO: .air actions used by no statedef: {}",
+            unused.join(", ")
+        );
+    }
+
+    for statedef in statedefs.iter().filter(|statedef| statedef.number >= 0) {
+        let _ = writeln!(out, ":{}:", state_name(statedef.number));
+        let kinds: Vec<String> = ["type", "movetype", "physics"]
+            .iter()
+            .filter_map(|key| Some(format!("{} = {}", key, statedef.param(key)?)))
+            .collect();
+        let _ = writeln!(out, "## MUGEN Statedef {}", statedef.number);
+        if !kinds.is_empty() {
+            let _ = writeln!(out, "## {}", kinds.join(", "));
+        }
+
+        let _ = writeln!(out, "---Init:");
+        for (key, value) in &statedef.params {
+            if !matches!(key.as_str(), "type" | "movetype" | "physics" | "anim") {
+                let _ = writeln!(out, "# TODO: {} = {}", key, value);
+            }
+        }
+        for controller in &statedef.controllers {
+            if controller.kind.eq_ignore_ascii_case("HitDef") {
+                write_hitdef(&mut out, controller);
+            }
+        }
+
+        let _ = writeln!(out, "---Action:");
+        let anim = statedef.param("anim").map(str::trim);
+        let action = anim
+            .and_then(|anim| anim.parse::<i64>().ok())
+            .and_then(|number| actions.iter().find(|a| a.number == number));
+        match (anim, action) {
+            (_, Some(action)) => write_sprites(&mut out, action),
+            (Some(anim), None) => {
+                let _ = writeln!(out, "# TODO: animation {}", anim);
+            }
+            (None, None) => {}
+        }
+        for controller in &statedef.controllers {
+            if !controller.kind.eq_ignore_ascii_case("HitDef") {
+                write_controller(&mut out, controller);
+            }
+        }
+    }
+    out
+}
+
+/// Name of the state imported from a statedef
+fn state_name(number: i64) -> String {
+    COMMON_STATES
+        .iter()
+        .find(|(_, common)| i64::from(*common) == number)
+        .map_or_else(|| format!("State{}", number), |(name, _)| name.to_string())
+}
+
+/// Frame branch a controller runs in: `Some(None)` for every frame,
+/// `None` when its triggers aren't `1` or `time = N`
+fn frame_branch(controller: &CnsController) -> Option<Option<String>> {
+    let [trigger] = &controller.triggers[..] else {
+        return None;
+    };
+    let conditions: Vec<&String> = controller.trigger_all.iter().chain(trigger).collect();
+    match conditions[..] {
+        [condition] if condition == "1" => Some(None),
+        [condition] => {
+            let (key, value) = condition.split_once('=')?;
+            if !key.trim().eq_ignore_ascii_case("time") {
+                return None;
+            }
+            // MUGEN counts ticks from 0, Castagne frames from 1
+            let time: usize = value.trim().parse().ok()?;
+            Some(Some(format!("F{}:", time + 1)))
+        }
+        _ => None,
+    }
+}
+
+/// Triggers of a controller as written, for TODO comments
+fn trigger_text(controller: &CnsController) -> String {
+    let mut groups: Vec<String> = controller
+        .triggers
+        .iter()
+        .filter(|trigger| !trigger.is_empty())
+        .map(|trigger| trigger.join(" && "))
+        .collect();
+    if groups.is_empty() {
+        groups.push("always".to_string());
+    }
+    let any = groups.join(" || ");
+    match controller.trigger_all.is_empty() {
+        true => any,
+        false => format!("{} && ({})", controller.trigger_all.join(" && "), any),
+    }
+}
+
+fn write_controller(out: &mut String, controller: &CnsController) {
+    let target = controller
+        .param("value")
+        .and_then(|value| value.trim().parse::<i64>().ok());
+    let change_state = controller.kind.eq_ignore_ascii_case("ChangeState");
+    match (change_state, target, frame_branch(controller)) {
+        (true, Some(target), Some(branch)) => {
+            let transition = format!("Transition({})", state_name(target));
+            match branch {
+                Some(branch) => {
+                    let _ = writeln!(out, "{}\n\t{}\nendif", branch, transition);
+                }
+                None => {
+                    let _ = writeln!(out, "{}", transition);
+                }
+            }
+        }
+        (true, Some(target), None) => {
+            let _ = writeln!(
+                out,
+                "# TODO: Transition({}) when {}",
+                state_name(target),
+                trigger_text(controller)
+            );
+        }
+        _ => {
+            let params: Vec<String> = controller
+                .params
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, value))
+                .collect();
+            let _ = writeln!(
+                out,
+                "# TODO: {} when {}: {}",
+                controller.kind,
+                trigger_text(controller),
+                params.join(", ")
+            );
+        }
+    }
+}
+
+fn write_hitdef(out: &mut String, controller: &CnsController) {
+    let attr = controller.param("attr").unwrap_or("?");
+    let _ = writeln!(out, "# TODO: attack type of MUGEN attr = {}", attr);
+    let _ = writeln!(out, "AttackRegister(Medium)");
+    // `damage = hit, guard`
+    let damage = controller
+        .param("damage")
+        .and_then(|damage| damage.split(',').next())
+        .map(str::trim)
+        .and_then(|damage| damage.parse::<i64>().ok());
+    if let Some(damage) = damage {
+        let _ = writeln!(out, "AttackDamage({})", damage);
+    }
+    let _ = writeln!(
+        out,
+        "# TODO: hitbox active when {}",
+        trigger_text(controller)
+    );
+}
+
+/// `Sprite` calls showing each element of an action on its frames,
+/// looping like MUGEN unless an element lasts forever
+fn write_sprites(out: &mut String, action: &AirAction) {
+    let total: i64 = action.elements.iter().map(|e| e.time.max(0)).sum();
+    let loops = total > 0 && action.elements.iter().all(|e| e.time >= 0);
+    if let Some(start) = action.loop_start.filter(|start| *start > 0) {
+        let _ = writeln!(out, "# TODO: loop from element {}", start + 1);
+    }
+    let mut start = 1;
+    for element in &action.elements {
+        let branch = match element.time {
+            time if time < 0 => format!("F{}+:", start),
+            0 => continue,
+            time if loops => format!("F{}-{}%{}:", start, start + time - 1, total),
+            time => format!("F{}-{}:", start, start + time - 1),
+        };
+        let _ = writeln!(
+            out,
+            "{}\n\tSprite(\"G{}\", {})\nendif",
+            branch, element.group, element.image
+        );
+        start += element.time.max(0);
+    }
+}
+
+/// A line without its `;` comment, trimmed
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return line[..index].trim(),
+            _ => {}
+        }
+    }
+    line.trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    const CNS: &str = "; Kung Fu Man
+[Statedef -1]
+
+[Statedef 0]
+type = S
+physics = S
+anim = 0
+velset = 0,0
+
+[State 0, To walk]
+type = ChangeState
+value = 20
+trigger1 = command = \"holdfwd\" ; walking
+[Statedef 20]
+type    = S
+anim = 20
+[State 20, Stop]
+type = ChangeState
+trigger1 = time = 30
+value = 0
+[Statedef 200]
+type = S
+movetype= A
+physics = S
+anim = 200
+ctrl = 0
+[State 200, Hit]
+type = HitDef
+trigger1 = Time = 0
+attr = S, NA
+damage = 23, 0
+[State 200, Sound]
+type = PlaySnd
+triggerall = !Time
+trigger1 = 1
+value = 0, 0
+[State 200, End]
+type = ChangeState
+trigger1 = 1
+value = 0
+";
+
+    const AIR: &str = "[Begin Action 0]
+Clsn2Default: 1
+ Clsn2[0] = -10, 0, 10, -79
+0,0, 0,0, 10
+0,1, 0,0, 10
+[Begin Action 20]
+20,0, 0,0, -1
+[Begin Action 200]
+200,0, 0,0, 3
+200,1, 0,0, 4, H
+[Begin Action 999]
+1,0, 0,0, 1
+";
+
+    #[test]
+    fn test_parse_cns_and_air() {
+        let statedefs = parse_cns(CNS);
+        assert_eq!(
+            statedefs.iter().map(|s| s.number).collect::<Vec<_>>(),
+            [-1, 0, 20, 200]
+        );
+        let walk = &statedefs[1].controllers[0];
+        assert_eq!(walk.label, "To walk");
+        assert_eq!(walk.kind, "ChangeState");
+        assert_eq!(walk.triggers, [vec!["command = \"holdfwd\"".to_string()]]);
+        assert_eq!(walk.param("value"), Some("20"));
+        assert_eq!(statedefs[3].param("movetype"), Some("A"));
+        assert_eq!(statedefs[3].controllers[1].trigger_all, ["!Time"]);
+
+        let actions = parse_air(AIR);
+        assert_eq!(actions.len(), 4);
+        assert_eq!(
+            actions[0].elements,
+            [
+                AirElement {
+                    group: 0,
+                    image: 0,
+                    time: 10
+                },
+                AirElement {
+                    group: 0,
+                    image: 1,
+                    time: 10
+                },
+            ]
+        );
+        assert_eq!(actions[1].elements[0].time, -1);
+    }
+
+    #[test]
+    fn test_import_mugen() {
+        let casp = import_mugen("Kung Fu Man", CNS, Some(AIR));
+        assert!(casp.contains("# TODO: port the special statedefs -1 by hand\n"));
+        assert!(casp.contains(
+            ":Stand:\n## MUGEN Statedef 0\n## type = S, physics = S\n---Init:\n# TODO: velset = 0,0\n"
+        ));
+        assert!(casp.contains("F1-10%20:\n\tSprite(\"G0\", 0)\nendif\nF11-20%20:\n"));
+        assert!(casp.contains("# TODO: Transition(Walk) when command = \"holdfwd\"\n"));
+        assert!(
+            casp.contains("F1+:\n\tSprite(\"G20\", 0)\nendif\nF31:\n\tTransition(Stand)\nendif\n")
+        );
+        assert!(casp.contains(
+            "# TODO: attack type of MUGEN attr = S, NA\nAttackRegister(Medium)\nAttackDamage(23)\n"
+        ));
+        assert!(casp.contains("# TODO: PlaySnd when !Time && (1): value = 0, 0\n"));
+        assert!(casp.contains("# TODO: .air actions used by no statedef: 999\n:Stand:\n"));
+        assert!(casp.ends_with("Transition(Stand)\n"));
+
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("kfm.casp", &casp)
+            .unwrap();
+        assert!(parser.get_errors().is_empty(), "{:?}", parser.get_errors());
+        assert_eq!(character.metadata.name, "Kung Fu Man");
+        let attack = &character.states["State200"];
+        assert_eq!(attack.actions["Init"][1].instruction, "AttackDamage");
+        assert_eq!(character.state_length("State200"), 7);
+        let timeline = character.animation_timeline("Stand", Some(25)).unwrap();
+        assert_eq!(timeline.at(11).unwrap().sprite, 1);
+        assert_eq!(timeline.at(21).unwrap().sprite, 0);
+    }
+}
@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Netplay - Manifest of a roster for connection-time checks
+//!
+//! Rollback netcode desyncs as soon as the two peers run different
+//! character data. Peers exchange a `NetplayManifest` when connecting and
+//! compare it with `mismatches` to refuse the match up front instead.
+
+use crate::parser::ParsedCharacter;
+use crate::roster::RosterParse;
+use serde::{Deserialize, Serialize};
+
+/// Summary of one character of the roster
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    /// `Version:` of the `:Character:` block, if any
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<String>,
+    /// `ParsedCharacter::content_hash` as 16 hex digits, as JSON numbers
+    /// lose precision past 53 bits
+    pub content_hash: String,
+    pub variable_count: usize,
+    pub state_count: usize,
+}
+
+impl ManifestEntry {
+    pub fn new(character: &ParsedCharacter) -> Self {
+        Self {
+            name: character.metadata.name.clone(),
+            version: character.metadata.version.clone(),
+            content_hash: format!("{:016x}", character.content_hash()),
+            variable_count: character.variables.len(),
+            state_count: character.states.len(),
+        }
+    }
+}
+
+/// Summaries of the characters of a roster, sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetplayManifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl NetplayManifest {
+    pub fn new<'a>(characters: impl IntoIterator<Item = &'a ParsedCharacter>) -> Self {
+        let mut entries: Vec<ManifestEntry> =
+            characters.into_iter().map(ManifestEntry::new).collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { entries }
+    }
+
+    /// Manifest of the given roster entries, leaving out the ones that
+    /// failed to parse
+    pub fn from_roster<P: AsRef<str>>(roster: &[P], parsed: &RosterParse) -> Self {
+        Self::new(
+            roster
+                .iter()
+                .filter_map(|path| parsed.characters.get(path.as_ref())?.as_ref().ok())
+                .map(|character| character.as_ref()),
+        )
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid netplay manifest: {}", e))
+    }
+
+    /// Differences with the manifest of the other peer, empty when both
+    /// run the same characters
+    pub fn mismatches(&self, remote: &NetplayManifest) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        for local in &self.entries {
+            let Some(other) = remote.entry(&local.name) else {
+                mismatches.push(format!("{}: missing on the remote side", local.name));
+                continue;
+            };
+            if local.version != other.version {
+                mismatches.push(format!(
+                    "{}: version {} here, {} on the remote side",
+                    local.name,
+                    local.version.as_deref().unwrap_or("(none)"),
+                    other.version.as_deref().unwrap_or("(none)")
+                ));
+            } else if local != other {
+                mismatches.push(format!(
+                    "{}: character data differs from the remote side",
+                    local.name
+                ));
+            }
+        }
+        for other in &remote.entries {
+            if self.entry(&other.name).is_none() {
+                mismatches.push(format!("{}: missing on the local side", other.name));
+            }
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParserConfig;
+    use crate::roster::parse_roster;
+    use std::fs;
+
+    #[test]
+    fn test_manifest_detects_mismatched_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        let write_ryu = |speed: i64| {
+            let source = format!(
+                ":Character:\nName: Ryu\nVersion: 1.2\nSkeleton: {}\n:Variables:\nvar Speed(Int): {}\n:Walk:\n---Init:\nMove(1)\n",
+                path("base.casp"),
+                speed
+            );
+            fs::write(path("ryu.casp"), source).unwrap();
+        };
+        write_ryu(5);
+        fs::write(path("ken.casp"), ":Character:\nName: Ken\n").unwrap();
+
+        let roster = [path("ryu.casp"), path("ken.casp")];
+        let local =
+            NetplayManifest::from_roster(&roster, &parse_roster(&roster, &ParserConfig::default()));
+        let names: Vec<&str> = local.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Ken", "Ryu"]);
+        let ryu = local.entry("Ryu").unwrap();
+        assert_eq!(ryu.version.as_deref(), Some("1.2"));
+        assert_eq!((ryu.variable_count, ryu.state_count), (1, 2));
+        assert_eq!(ryu.content_hash.len(), 16);
+
+        let exchanged = NetplayManifest::from_json(&local.to_json().unwrap()).unwrap();
+        assert!(local.mismatches(&exchanged).is_empty());
+
+        write_ryu(6);
+        let remote = NetplayManifest::from_roster(
+            &roster[..1],
+            &parse_roster(&roster[..1], &ParserConfig::default()),
+        );
+        assert_eq!(
+            local.mismatches(&remote),
+            vec![
+                "Ken: missing on the remote side",
+                "Ryu: character data differs from the remote side"
+            ]
+        );
+    }
+}
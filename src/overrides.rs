@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Overrides - What a character changed from the files it inherits
+//!
+//! Finding out why a variable ends up at some value means walking the
+//! skeleton chain by hand. The parser records an `OverrideRecord` each time
+//! an include or the file itself replaces a variable, specblock entry or
+//! state it inherited, and keeps the records of its parents, so
+//! `ParsedCharacter::override_report` shows the whole chain.
+
+use crate::parser::{ParsedCharacter, ParsedState};
+use std::fmt;
+use std::sync::Arc;
+
+/// Kind of data a file overrode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverrideKind {
+    Variable,
+    Specblock,
+    State,
+}
+
+impl fmt::Display for OverrideKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverrideKind::Variable => f.write_str("variable"),
+            OverrideKind::Specblock => f.write_str("specblock entry"),
+            OverrideKind::State => f.write_str("state"),
+        }
+    }
+}
+
+/// A value replaced by a file inheriting it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverrideRecord {
+    pub kind: OverrideKind,
+    /// Variable or state name, `Block.Key` for specblock entries
+    pub symbol: String,
+    pub parent_value: String,
+    pub child_value: String,
+    /// Skeleton or include the replaced value was inherited through
+    pub parent_file: String,
+    /// File replacing it
+    pub child_file: String,
+}
+
+impl fmt::Display for OverrideRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {} ({}) -> {} ({})",
+            self.kind,
+            self.symbol,
+            self.parent_value,
+            self.parent_file,
+            self.child_value,
+            self.child_file
+        )
+    }
+}
+
+impl ParsedCharacter {
+    /// Overrides of a variable, state or `Block.Key`, from the top of the
+    /// skeleton chain down
+    pub fn overrides_of(&self, symbol: &str) -> Vec<&OverrideRecord> {
+        self.overrides
+            .iter()
+            .filter(|record| record.symbol == symbol)
+            .collect()
+    }
+
+    /// Every override of the chain, one per line
+    pub fn override_report(&self) -> String {
+        self.overrides
+            .iter()
+            .map(|record| format!("{}\n", record))
+            .collect()
+    }
+}
+
+/// Overrides made while building `character` from `file` and the files it
+/// inherits, skeleton first then includes, keeping theirs first
+pub(crate) fn record_overrides(
+    inherited: &[(String, Arc<ParsedCharacter>)],
+    file: &str,
+    character: &ParsedCharacter,
+) -> Vec<OverrideRecord> {
+    let mut records: Vec<OverrideRecord> = inherited
+        .iter()
+        .flat_map(|(_, parent)| parent.overrides.iter().cloned())
+        .collect();
+    // Each include replaces what was merged before it
+    for (index, (path, layer)) in inherited.iter().enumerate() {
+        let states = layer.states.keys().map(|name| name.as_str());
+        records.extend(layer_overrides(&inherited[..index], path, layer, states));
+    }
+    // Only states written in the file itself replace inherited ones
+    let states = character
+        .source_index
+        .states()
+        .iter()
+        .map(|span| span.name.as_str());
+    records.extend(layer_overrides(inherited, file, character, states));
+    records
+}
+
+/// What `child` replaces from `layers`, sorted by kind and symbol
+fn layer_overrides<'a>(
+    layers: &[(String, Arc<ParsedCharacter>)],
+    child_file: &str,
+    child: &ParsedCharacter,
+    states: impl Iterator<Item = &'a str>,
+) -> Vec<OverrideRecord> {
+    let record =
+        |kind, symbol: String, parent_value: &str, child_value: &str, parent_file: &str| {
+            OverrideRecord {
+                kind,
+                symbol,
+                parent_value: parent_value.to_string(),
+                child_value: child_value.to_string(),
+                parent_file: parent_file.to_string(),
+                child_file: child_file.to_string(),
+            }
+        };
+    let mut records = Vec::new();
+    for (name, variable) in &child.variables {
+        let found = last_defining(layers, |c| c.variables.get(name.as_str()));
+        if let Some((parent_file, parent)) = found.filter(|(_, p)| p.value != variable.value) {
+            let symbol = name.to_string();
+            records.push(record(
+                OverrideKind::Variable,
+                symbol,
+                &parent.value,
+                &variable.value,
+                parent_file,
+            ));
+        }
+    }
+    for (block, entries) in &child.specblocks {
+        for (key, value) in entries {
+            let found = last_defining(layers, |c| c.specblocks.get(block)?.get(key));
+            if let Some((parent_file, parent)) = found.filter(|(_, p)| *p != value) {
+                let symbol = format!("{}.{}", block, key);
+                records.push(record(
+                    OverrideKind::Specblock,
+                    symbol,
+                    parent,
+                    value,
+                    parent_file,
+                ));
+            }
+        }
+    }
+    for name in states {
+        let Some(state) = child.states.get(name) else {
+            continue;
+        };
+        if let Some((parent_file, parent)) = last_defining(layers, |c| c.states.get(name)) {
+            let (parent, state) = (state_summary(parent), state_summary(state));
+            records.push(record(
+                OverrideKind::State,
+                name.to_string(),
+                &parent,
+                &state,
+                parent_file,
+            ));
+        }
+    }
+    records.sort_by(|a, b| (a.kind as u8, &a.symbol).cmp(&(b.kind as u8, &b.symbol)));
+    records
+}
+
+/// The last of `layers` defining something, with its path
+fn last_defining<'a, T: ?Sized>(
+    layers: &'a [(String, Arc<ParsedCharacter>)],
+    get: impl Fn(&'a ParsedCharacter) -> Option<&'a T>,
+) -> Option<(&'a str, &'a T)> {
+    layers
+        .iter()
+        .rev()
+        .find_map(|(path, layer)| Some((path.as_str(), get(layer)?)))
+}
+
+fn state_summary(state: &ParsedState) -> String {
+    let actions: usize = state.actions.values().map(Vec::len).sum();
+    format!("{} actions", actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+    use std::fs;
+
+    fn file_name(path: &str) -> &str {
+        path.rsplit('/').next().unwrap()
+    }
+
+    #[test]
+    fn test_overrides_along_a_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Variables:\nvar Health(Int): 100\nvar Meter(Int): 0\n:Physics:\nGravity: 10\nFriction: 2\n:Idle:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        fs::write(
+            path("mid.casp"),
+            format!(
+                ":Character:\nName: Mid\nSkeleton: {}\n:Variables:\nvar Health(Int): 120\n:Physics:\nGravity: 12\n",
+                path("base.casp")
+            ),
+        )
+        .unwrap();
+        fs::write(
+            path("stats.casp"),
+            ":Character:\nName: Stats\n:Physics:\nFriction: 3\n",
+        )
+        .unwrap();
+        fs::write(
+            path("ryu.casp"),
+            format!(
+                ":Character:\nName: Ryu\nSkeleton: {}\nInclude: {}\n:Variables:\nvar Health(Int): 150\nvar Meter(Int): 0\n:Idle:\n---Init:\nStop\nLog(\"Idle\")\n",
+                path("mid.casp"),
+                path("stats.casp")
+            ),
+        )
+        .unwrap();
+
+        let ryu = CastagneParser::new()
+            .create_full_character(&path("ryu.casp"))
+            .unwrap();
+        let health: Vec<(&str, &str, &str, &str)> = ryu
+            .overrides_of("Health")
+            .iter()
+            .map(|r| {
+                let (parent, child) = (file_name(&r.parent_file), file_name(&r.child_file));
+                (
+                    r.parent_value.as_str(),
+                    r.child_value.as_str(),
+                    parent,
+                    child,
+                )
+            })
+            .collect();
+        assert_eq!(
+            health,
+            vec![
+                ("100", "120", "base.casp", "mid.casp"),
+                ("120", "150", "mid.casp", "ryu.casp")
+            ]
+        );
+        // Same value as inherited, not an override
+        assert!(ryu.overrides_of("Meter").is_empty());
+        assert_eq!(ryu.overrides_of("Physics.Gravity").len(), 1);
+        let friction = ryu.overrides_of("Physics.Friction");
+        assert_eq!(friction.len(), 1);
+        assert_eq!(file_name(&friction[0].child_file), "stats.casp");
+        assert_eq!(ryu.overrides_of("Idle")[0].child_value, "2 actions");
+
+        let report = ryu.override_report();
+        assert_eq!(report.lines().count(), 5);
+        assert!(report
+            .lines()
+            .next()
+            .unwrap()
+            .starts_with("variable Health: 100 ("));
+        assert!(report.contains("state Idle: 1 actions ("));
+    }
+}
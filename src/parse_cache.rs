@@ -0,0 +1,290 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Parse Cache - Parsed characters kept on disk between runs
+//!
+//! Restarting the editor on a big project reparses the whole roster. A
+//! parser given a `ParseCache` stores each character it parses in the
+//! cache directory, keyed by a hash of the file's canonical path, its
+//! contents and the options it was parsed with, and serves it from there
+//! next time. The path is part of the key since the spans of an entry
+//! name its file: copies of a file elsewhere get entries of their own. An
+//! entry lists the skeletons and includes the file reads, directly or
+//! through other files, with the hash of their contents: editing any of
+//! them makes the entry stale, and the file is parsed again.
+//!
+//! The crate has no binary format, so entries are the character's JSON,
+//! along with the errors and diagnostics of its parse. What the JSON
+//! leaves out isn't cached: a character served from the cache has no
+//! `source_index`, `specblock_origins`, `overrides` or source origins.
+//! Parses with `lazy_states` aren't cached. Entries that can't be read
+//! or written are treated as missing.
+
+use crate::content_hash::stable_hash;
+use crate::diagnostics::Diagnostic;
+use crate::parser::{CastagneParser, CharacterMetadata, ParsedCharacter, ParserConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A skeleton or include a cached character was parsed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Dependency {
+    path: String,
+    /// `None` for a file that couldn't be read
+    hash: Option<u64>,
+}
+
+/// What a parse produced, as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CacheEntry {
+    dependencies: Vec<Dependency>,
+    pub character: ParsedCharacter,
+    pub errors: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Directory of parsed characters shared between runs
+#[derive(Debug, Clone)]
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    /// A cache in `dir`, created on the first write
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Key of a file parsed with `config`, `None` if it can't be read
+    pub(crate) fn key(file_path: &str, config: &ParserConfig) -> Option<u64> {
+        let path = fs::canonicalize(file_path).ok()?;
+        let bytes = fs::read(&path).ok()?;
+        let options = config_fingerprint(config);
+        Some(stable_hash([
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            path.as_os_str().as_encoded_bytes(),
+            options.as_bytes(),
+            &bytes,
+        ]))
+    }
+
+    /// The entry stored under `key`, unless a dependency changed since
+    pub(crate) fn get(&self, key: u64) -> Option<CacheEntry> {
+        let json = fs::read_to_string(self.entry_path(key)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&json).ok()?;
+        let fresh = entry
+            .dependencies
+            .iter()
+            .all(|dependency| file_hash(&dependency.path) == dependency.hash);
+        fresh.then_some(entry)
+    }
+
+    /// Store what a parse produced under `key`
+    pub(crate) fn insert(
+        &self,
+        key: u64,
+        config: &ParserConfig,
+        character: &ParsedCharacter,
+        errors: &[String],
+        diagnostics: &[Diagnostic],
+    ) {
+        let entry = CacheEntry {
+            dependencies: dependencies(&character.metadata, config),
+            character: character.clone(),
+            errors: errors.to_vec(),
+            diagnostics: diagnostics.to_vec(),
+        };
+        let Ok(json) = serde_json::to_string(&entry) else {
+            return;
+        };
+        // Written aside then renamed, so readers never see half an entry
+        let path = self.entry_path(key);
+        let partial = path.with_extension("partial");
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&partial, json))
+            .and_then(|()| fs::rename(&partial, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+
+    /// Number of entries in the directory
+    pub fn len(&self) -> usize {
+        self.entries().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) -> std::io::Result<()> {
+        for path in self.entries() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> impl Iterator<Item = PathBuf> {
+        fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+    }
+
+    fn entry_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", key))
+    }
+}
+
+/// The options changing what a parse produces
+fn config_fingerprint(config: &ParserConfig) -> String {
+    let mut flags: Vec<&String> = config.flags.iter().collect();
+    flags.sort();
+    let mut severities: Vec<_> = config.severities.iter().collect();
+    severities.sort_by(|a, b| a.0.cmp(b.0));
+    let mut aliases: Vec<_> = config.skeleton_aliases.iter().collect();
+    aliases.sort();
+    format!(
+        "{:?}",
+        (
+            flags,
+            config.frames_per_second,
+            config.lossy_decoding,
+            config.legacy_syntax,
+            &config.deprecations,
+            config.conflict_resolution,
+            severities,
+            config.deny_warnings,
+            config.limits,
+            &config.signatures,
+            &config.skeleton_paths,
+            aliases,
+        )
+    )
+}
+
+fn file_hash(path: &str) -> Option<u64> {
+    fs::read(path).ok().map(|bytes| stable_hash([&bytes[..]]))
+}
+
+/// Skeletons and includes of a file, and theirs, with their hash
+fn dependencies(metadata: &CharacterMetadata, config: &ParserConfig) -> Vec<Dependency> {
+    let mut seen = HashSet::new();
+    let mut dependencies = Vec::new();
+    let mut pending: Vec<String> = metadata
+        .skeleton
+        .iter()
+        .chain(&metadata.includes)
+        .map(|path| config.resolve_skeleton(path))
+        .collect();
+    while let Some(path) = pending.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        // A missing file still counts, so creating it makes the entry stale
+        let hash = file_hash(&path);
+        if let Ok(metadata) =
+            CastagneParser::with_config(config.clone()).get_character_metadata(&path)
+        {
+            let found = metadata.skeleton.into_iter().chain(metadata.includes);
+            pending.extend(found.map(|path| config.resolve_skeleton(&path)));
+        }
+        dependencies.push(Dependency { path, hash });
+    }
+    dependencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_characters_are_reused_until_a_skeleton_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("core.casp"), ":Idle:\n---Init:\nStop\n").unwrap();
+        fs::write(
+            path("base.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("core.casp")),
+        )
+        .unwrap();
+        let ryu = format!(
+            ":Character:\nName: Ryu\nSkeleton: {}\n:Walk:\n---Init:\nMove(1, 2)\n",
+            path("base.casp")
+        );
+        fs::write(path("ryu.casp"), &ryu).unwrap();
+
+        let cache = ParseCache::new(path("cache"));
+        let parse = || {
+            let mut parser = CastagneParser::new().with_parse_cache(cache.clone());
+            parser.create_full_character(&path("ryu.casp")).unwrap()
+        };
+        let parsed = parse();
+        assert_eq!(cache.len(), 1);
+        let key = ParseCache::key(&path("ryu.casp"), &ParserConfig::default()).unwrap();
+        let entry = cache.get(key).unwrap();
+        assert_eq!(entry.dependencies.len(), 2);
+
+        let cached = parse();
+        assert_eq!(
+            serde_json::to_value(&cached).unwrap(),
+            serde_json::to_value(&parsed).unwrap()
+        );
+        assert_eq!(cached.states["Walk"].actions["Init"][0].args, ["1", "2"]);
+
+        // Another run sees the core skeleton edited
+        fs::write(path("core.casp"), ":Crouch:\n---Init:\nStop\n").unwrap();
+        assert!(cache.get(key).is_none());
+        let reparsed = parse();
+        assert!(reparsed.states.contains_key("Crouch") && !reparsed.states.contains_key("Idle"));
+        assert!(cache.get(key).is_some());
+
+        // Other options make other entries
+        CastagneParser::with_config(ParserConfig::new().with_flag("Debug"))
+            .with_parse_cache(cache.clone())
+            .create_full_character(&path("ryu.casp"))
+            .unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.clear().unwrap();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_copies_of_a_file_get_their_own_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = ":Character:\nName: Ryu\n:Idle:\n---Init:\nF1:\nStop\n";
+        let cache = ParseCache::new(dir.path().join("cache"));
+        let mut files = Vec::new();
+        for folder in ["a", "b"] {
+            fs::create_dir(dir.path().join(folder)).unwrap();
+            let file = dir.path().join(folder).join("ryu.casp");
+            fs::write(&file, source).unwrap();
+            files.push(file.to_str().unwrap().to_string());
+        }
+
+        for _ in 0..2 {
+            for file in &files {
+                let mut parser = CastagneParser::new().with_parse_cache(cache.clone());
+                parser.create_full_character(file).unwrap();
+                let spans: Vec<_> = parser
+                    .get_diagnostics()
+                    .iter()
+                    .filter_map(|diagnostic| diagnostic.span.as_ref())
+                    .collect();
+                assert!(!spans.is_empty());
+                assert!(spans.iter().all(|span| span.file.as_ref() == Some(file)));
+            }
+        }
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -8,15 +8,121 @@
 //! The original GDScript version is ~2279 lines of complex parsing logic.
 //! This version provides the basic structure with TODOs for full implementation.
 
+use crate::action_args::ActionArgs;
+use crate::attack_notation::AttackNotation;
+use crate::cancellation::CancellationToken;
+use crate::color::Rgba;
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::error::ParseFailure;
+use crate::expression::{evaluate, parse_integer, parse_number, parse_wide_integer, Value};
+use crate::format_version::{upgrade_lines, FormatVersion, FORMAT_VERSION_FIELD};
+use crate::frame_data::{scope_frames, FrameWindow};
+use crate::front_matter;
+use crate::generic_states::{split_generic_header, GenericState, USE_STATE};
+use crate::intern::{Interner, Symbol};
+use crate::lazy_states::{LazyState, LazyStates};
+use crate::legacy::{Deprecations, LegacyKind, DEPRECATED_NAME, LEGACY_SYNTAX};
+use crate::limits::{Limit, Limits, LIMIT_EXCEEDED};
+use crate::loops::LoopKind;
+use crate::metrics::{ParseMetrics, ParsePhase, Profiler};
+use crate::overrides::{record_overrides, OverrideRecord};
+use crate::parse_cache::ParseCache;
+use crate::phases::Phases;
+use crate::pragmas::Suppressions;
+use crate::signatures::{normalize_arguments, split_flags, Signatures};
+use crate::skeleton_cache::{CachedFile, SkeletonCache};
+use crate::skeletons;
+use crate::source_index::{PhaseSpan, SourceIndex, SourceRef, StateSpan};
+use crate::source_map::{FileLines, SourceMap};
+use crate::string_literal;
+use crate::subentities::spawned_name;
+use crate::switches::{CASE, DEFAULT, END_SWITCH, SWITCH};
 use godot::prelude::*;
-use serde::Serialize;
-use std::collections::HashMap;
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
 use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Import vector types for type conversion
 use godot::builtin::{Vector2, Vector3};
 
+/// Header prefix of template blocks: `:Template Name(Params):`
+const TEMPLATE_PREFIX: &str = "Template ";
+
+/// Instruction expanding a template: `UseTemplate(Name, Args...)`
+const USE_TEMPLATE: &str = "UseTemplate";
+
+/// Header suffix of the metadata block of a subentity: `:Fireball---Subentity:`
+const SUBENTITY_SUFFIX: &str = "---Subentity";
+
+/// Header parameter of a state replacing an inherited one: `:Name(Override):`
+pub(crate) const OVERRIDE_MARKER: &str = "Override";
+
+/// Maximum nesting of templates using templates, to stop on recursion
+const MAX_TEMPLATE_DEPTH: usize = 16;
+
+/// Diagnostic code of action lines with unbalanced parentheses or quotes
+pub const UNBALANCED_DELIMITER: &str = "unbalanced-delimiter";
+
+/// Diagnostic code of source files that aren't valid UTF-8
+pub const INVALID_ENCODING: &str = "invalid-encoding";
+
+/// Diagnostic code of branches (`LFlag:`, `IfLt(A, B):`) a phase never closes
+pub const MISSING_ENDIF: &str = "missing-endif";
+
+/// Diagnostic code of loops (`Repeat(3)`, `While(Cond)`) a phase never closes
+pub const UNCLOSED_LOOP: &str = "unclosed-loop";
+
+/// Diagnostic code of switches (`Switch(Stance)`) a phase never closes
+pub const UNCLOSED_SWITCH: &str = "unclosed-switch";
+
+/// Diagnostic code of a `Case` or `Default` not directly in a switch
+pub const CASE_OUTSIDE_SWITCH: &str = "case-outside-switch";
+
+/// Diagnostic code of an `endif`, `EndRepeat`, `EndWhile` or `EndSwitch`
+/// that doesn't close the innermost open block
+pub const MISMATCHED_BLOCK_END: &str = "mismatched-block-end";
+
+/// Diagnostic code of a state temporary reusing the name of a variable or
+/// of another temporary of the state
+pub const SHADOWED_VARIABLE: &str = "shadowed-variable";
+
+/// Diagnostic code of named arguments that don't fit the instruction's
+/// signature, see `signatures`
+pub const INVALID_NAMED_ARGUMENT: &str = "invalid-named-argument";
+
+/// Diagnostic code of an `Int` default out of the range of its type
+pub const INTEGER_OVERFLOW: &str = "integer-overflow";
+
+/// Subtype of 64-bit `Int` variables: `var Score(Int, 64): 0`
+pub const WIDE_INT_SUBTYPE: &str = "64";
+
+/// Diagnostic code of a state or variable two includes define differently
+pub const INHERITANCE_CONFLICT: &str = "inheritance-conflict";
+
+/// Diagnostic code of a state marked `Override` that no parent defines
+pub const UNMATCHED_OVERRIDE: &str = "unmatched-override";
+
+/// Diagnostic code of a `NoInherit:` name that no parent defines
+pub const UNMATCHED_NO_INHERIT: &str = "unmatched-no-inherit";
+
+/// Diagnostic code of a spawn naming neither a subentity nor a helper state
+pub const UNKNOWN_SUBENTITY: &str = "unknown-subentity";
+
+/// Diagnostic code of a well-known metadata key with an invalid value
+pub const INVALID_METADATA: &str = "invalid-metadata";
+
+/// Diagnostic code of what stopped the parse of a skeleton or include
+pub const PARSE_FAILURE: &str = "parse-failure";
+
+/// Diagnostic code of a broken parser invariant, see `ParseError`
+pub const INTERNAL_ERROR: &str = "internal-parser-error";
+
 /// Phases that can have events
 const _PHASES_BASE: &[&str] = &[
     "Init",
@@ -30,27 +136,54 @@ const _PHASES_BASE: &[&str] = &[
 ];
 
 /// Variable mutability types
-#[derive(Debug, Clone, PartialEq, Serialize)]
+///
+/// Serialized as the GDScript parser writes them: `Constant` for defines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VariableMutability {
     Variable,
+    /// `def Name: Value`
+    #[serde(rename = "Constant", alias = "Define")]
     Define,
+    /// `internal Name`, a variable managed by the engine
     Internal,
 }
 
 /// Variable types
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VariableType {
     Int,
+    /// Decimal number, `1.5`, `-2` or `1e3`
+    Float,
     Str,
     Var,
     Vec2,
     Vec3,
     Box,
     Bool,
+    /// `#RRGGBB`, `rgb(r, g, b)` and the other forms of `Rgba::parse`
+    Color,
+}
+
+impl VariableType {
+    /// Type written in a declaration, `None` if unknown
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Int" => Some(VariableType::Int),
+            "Float" => Some(VariableType::Float),
+            "Str" => Some(VariableType::Str),
+            "Var" => Some(VariableType::Var),
+            "Vec2" => Some(VariableType::Vec2),
+            "Vec3" => Some(VariableType::Vec3),
+            "Box" => Some(VariableType::Box),
+            "Bool" => Some(VariableType::Bool),
+            "Color" => Some(VariableType::Color),
+            _ => None,
+        }
+    }
 }
 
 /// State type
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StateType {
     Normal,
     BaseState,
@@ -59,25 +192,75 @@ pub enum StateType {
     Specblock,
 }
 
+impl StateType {
+    /// Type written in a state header; anything else names a parent
+    pub(crate) fn from_name(name: &str) -> Self {
+        match name {
+            "Helper" => StateType::Helper,
+            "BaseState" => StateType::BaseState,
+            "Special" => StateType::Special,
+            "Specblock" => StateType::Specblock,
+            _ => StateType::Normal,
+        }
+    }
+}
+
 /// Parsed variable definition
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedVariable {
-    pub name: String,
+    pub name: Symbol,
     pub mutability: VariableMutability,
     pub var_type: VariableType,
     pub subtype: String,
     pub value: String,
+    /// Section of the block declaring it, `Internals-Core` for `:Variables-Internals-Core:`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// Default as written when it was an expression or an `Int` literal
+    /// not in decimal, `value` holding the result
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    /// Where it was declared, in this file or one it inherits
+    #[serde(skip)]
+    pub origin: SourceRef,
 }
 
 impl ParsedVariable {
     /// Convert the string value to a Godot Variant based on the variable type
+    ///
+    /// Variants aren't thread-safe: call this on the thread using the value.
     pub fn to_variant(&self) -> Variant {
-        CastagneParser::parse_value_to_variant(&self.value, &self.var_type)
+        match self.var_type {
+            // String defaults are decoded when parsed
+            VariableType::Str => Variant::from(GString::from(self.value.as_str())),
+            VariableType::Int if self.is_wide_int() => parse_integer(&self.value)
+                .map(Variant::from)
+                .unwrap_or_else(Variant::nil),
+            _ => CastagneParser::parse_value_to_variant(&self.value, &self.var_type),
+        }
     }
 
     /// Get the value as an integer (if possible)
     pub fn as_int(&self) -> Option<i32> {
-        self.value.trim().parse::<i32>().ok()
+        parse_integer(&self.value).and_then(|value| i32::try_from(value).ok())
+    }
+
+    /// Get the value of a 64-bit `Int` (if possible)
+    pub fn as_wide_int(&self) -> Option<i64> {
+        parse_integer(&self.value)
+    }
+
+    /// Whether it is an `Int` with the 64-bit subtype
+    pub fn is_wide_int(&self) -> bool {
+        self.var_type == VariableType::Int && self.subtype.trim() == WIDE_INT_SUBTYPE
+    }
+
+    /// Values an `Int` of its subtype holds
+    fn int_range(&self) -> std::ops::RangeInclusive<i128> {
+        match self.is_wide_int() {
+            true => i64::MIN.into()..=i64::MAX.into(),
+            false => i32::MIN.into()..=i32::MAX.into(),
+        }
     }
 
     /// Get the value as a boolean (if possible)
@@ -91,53 +274,309 @@ impl ParsedVariable {
 
     /// Get the value as a float (if possible)
     pub fn as_float(&self) -> Option<f64> {
-        self.value.trim().parse::<f64>().ok()
+        parse_number(&self.value)
+    }
+
+    /// Values of an enum, listed as the subtype: `Stance(Str, Stand|Crouch)`
+    pub fn enum_values(&self) -> Option<Vec<&str>> {
+        if !self.subtype.contains('|') {
+            return None;
+        }
+        Some(self.subtype.split('|').map(str::trim).collect())
     }
 }
 
 /// Parsed state information
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedState {
-    pub name: String,
+    pub name: Symbol,
     pub state_type: StateType,
     pub parent: Option<String>,
-    pub actions: HashMap<String, Vec<ParsedAction>>, // Phase -> Actions
+    /// Phases and their actions, in declaration order
+    pub actions: Phases,
+    /// Input read from a numpad-notation name like `5A` or `j.236B`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attack: Option<AttackNotation>,
+    /// `##` comment lines between the header and the first phase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Header the state was defined at, in this file or one it inherits
+    #[serde(skip)]
+    pub origin: SourceRef,
+    /// Temporaries declared with `let`, in declaration order, which gives
+    /// each its slot among the locals of the state
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locals: Vec<LocalVariable>,
+}
+
+impl ParsedState {
+    /// Last temporary of the state named `name`
+    pub fn local(&self, name: &str) -> Option<&LocalVariable> {
+        self.locals.iter().rev().find(|local| local.name == name)
+    }
+}
+
+/// A temporary of a state, declared in a phase with `let Name: Value`
+///
+/// It holds `value`, evaluated when the phase reaches the declaration, for
+/// the rest of the state's frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalVariable {
+    pub name: Symbol,
+    /// Expression as written
+    pub value: String,
+    pub phase: Symbol,
+    /// Number of actions of the phase before the declaration
+    pub position: usize,
+    /// 1-indexed line of the declaration
+    pub line_number: usize,
 }
 
 /// A parsed action/instruction
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedAction {
-    pub instruction: String,
-    pub args: Vec<String>,
+    pub instruction: Symbol,
+    pub args: ActionArgs,
+    /// 1-indexed line in the file the action was parsed from
+    pub line_number: usize,
+    /// Windows of the `F` branches the action is in, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frames: Vec<FrameWindow>,
+    /// Positions of the arguments filled with their default, see `signatures`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub defaulted: Vec<usize>,
+    /// Trailing flags like `+NoCancel`, without their `+`, see `signatures`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+}
+
+/// A reusable action sequence, defined with `:Template Name(Params):`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParsedTemplate {
+    pub name: String,
+    pub params: Vec<String>,
+    pub actions: Vec<ParsedAction>,
+    /// 1-indexed line of the template header
     pub line_number: usize,
 }
 
 /// Character metadata
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CharacterMetadata {
     pub name: String,
     pub author: String,
     pub description: String,
     pub skeleton: Option<String>,
+    /// Path the file was parsed from, as given to the parser
+    pub filepath: String,
+    /// Files spliced in with `Include:`, in declaration order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    /// Inherited states dropped with `NoInherit:`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_inherit: Vec<String>,
+    /// `EditorName:`, the name the editor lists the character under
+    #[serde(rename = "editorname", skip_serializing_if = "Option::is_none")]
+    pub editor_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Image paths of `Portrait:` and `SelectIcon:`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub portrait: Option<String>,
+    #[serde(rename = "selecticon", skip_serializing_if = "Option::is_none")]
+    pub select_icon: Option<String>,
+    /// `PaletteCount:`, palettes the character can be picked with
+    #[serde(rename = "palettecount", skip_serializing_if = "Option::is_none")]
+    pub palette_count: Option<u32>,
     #[serde(flatten)]
     pub other_fields: HashMap<String, String>,
 }
 
+/// Extensions of the images `Portrait:` and `SelectIcon:` can point to
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "svg", "bmp", "tga"];
+
+/// Check the value of a well-known metadata key, other keys take anything
+pub(crate) fn validate_metadata_field(key: &str, value: &str) -> Result<(), String> {
+    let valid = match key {
+        "EditorName" | "Version" => !value.trim().is_empty(),
+        "Portrait" | "SelectIcon" => value
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&&*ext.to_ascii_lowercase())),
+        "PaletteCount" => value.trim().parse::<u32>().is_ok_and(|count| count > 0),
+        _ => true,
+    };
+    if valid {
+        return Ok(());
+    }
+    let expected = match key {
+        "Portrait" | "SelectIcon" => "an image path",
+        "PaletteCount" => "a positive integer",
+        _ => "set",
+    };
+    Err(format!("{} should be {}, got {:?}", key, expected, value))
+}
+
+impl CharacterMetadata {
+    /// Name the editor lists the character under: its `EditorName:`, else
+    /// its name, else its path, as the Castagne editor does
+    pub fn display_name(&self) -> &str {
+        match &self.editor_name {
+            Some(editor_name) => editor_name,
+            None if self.name.is_empty() => &self.filepath,
+            None => &self.name,
+        }
+    }
+
+    /// Fill the fields left unset with those of a skeleton, like Castagne
+    /// fusing the metadata of every file of the chain
+    ///
+    /// The skeleton, includes and `NoInherit:` list stay the child's own.
+    pub(crate) fn inherit_from(&mut self, parent: &CharacterMetadata) {
+        for (own, inherited) in [
+            (&mut self.name, &parent.name),
+            (&mut self.author, &parent.author),
+            (&mut self.description, &parent.description),
+        ] {
+            if own.is_empty() {
+                own.clone_from(inherited);
+            }
+        }
+        for (own, inherited) in [
+            (&mut self.editor_name, &parent.editor_name),
+            (&mut self.version, &parent.version),
+            (&mut self.portrait, &parent.portrait),
+            (&mut self.select_icon, &parent.select_icon),
+        ] {
+            if own.is_none() {
+                own.clone_from(inherited);
+            }
+        }
+        self.palette_count = self.palette_count.or(parent.palette_count);
+        for (key, value) in &parent.other_fields {
+            if key != FORMAT_VERSION_FIELD && !self.other_fields.contains_key(key) {
+                self.other_fields.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Set the field a `Key: Value` line of a metadata block names, leaving
+    /// a well-known field unset when its value is invalid
+    pub(crate) fn set_field(&mut self, key: &str, value: String) -> Result<(), String> {
+        validate_metadata_field(key, &value)?;
+        let list = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        };
+        match key {
+            "Name" => self.name = value,
+            "Author" => self.author = value,
+            "Description" => self.description = value,
+            "Skeleton" => self.skeleton = Some(value),
+            "Include" => self.includes.extend(list(&value)),
+            "NoInherit" => self.no_inherit.extend(list(&value)),
+            "EditorName" => self.editor_name = Some(value),
+            "Version" => self.version = Some(value),
+            "Portrait" => self.portrait = Some(value),
+            "SelectIcon" => self.select_icon = Some(value),
+            "PaletteCount" => self.palette_count = value.trim().parse().ok(),
+            _ => {
+                self.other_fields.insert(key.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Full parsed character data
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedCharacter {
     pub metadata: CharacterMetadata,
-    pub variables: HashMap<String, ParsedVariable>,
-    pub states: HashMap<String, ParsedState>,
+    pub variables: HashMap<Symbol, ParsedVariable>,
+    /// Variables of subentities, from `:Entity---Variables:` blocks
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub entity_variables: HashMap<String, HashMap<Symbol, ParsedVariable>>,
+    pub states: HashMap<Symbol, ParsedState>,
     pub specblocks: HashMap<String, HashMap<String, String>>,
+    /// Where each specblock key was set, by block then key
+    #[serde(skip)]
+    pub specblock_origins: HashMap<String, HashMap<String, SourceRef>>,
     pub subentities: HashMap<String, CharacterMetadata>,
     pub transformed_data: HashMap<String, HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<String, ParsedTemplate>,
+    /// States with parameters, see `generic_states`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub generic_states: HashMap<String, GenericState>,
+    /// Line lookups for the states defined in this file
+    #[serde(skip)]
+    pub source_index: SourceIndex,
+    /// Inherited data replaced along the skeleton chain, parents first
+    #[serde(skip)]
+    pub overrides: Vec<OverrideRecord>,
+    /// States of this file left unparsed with `lazy_states`, see `state`
+    #[serde(skip)]
+    pub lazy_states: LazyStates,
+}
+
+/// Layouts a character can be written to JSON in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// Fields of the Rust types, which deserialize back into them
+    #[default]
+    Internal,
+    /// Layout of the GDScript parser's output, see `golden_master`
+    Engine,
 }
 
 impl ParsedCharacter {
     /// Serialize this character to JSON string
-    pub fn to_json(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string_pretty(self)
+    pub fn to_json(&self, format: JsonFormat) -> Result<String, serde_json::Error> {
+        match format {
+            JsonFormat::Internal => serde_json::to_string_pretty(self),
+            JsonFormat::Engine => serde_json::to_string_pretty(&self.to_golden_master()),
+        }
+    }
+
+    /// Action defined on a given line of this file
+    pub fn action_at(&self, line: usize) -> Option<&ParsedAction> {
+        let location = self.source_index.location_at(line)?;
+        let actions = self
+            .states
+            .get(location.state.as_str())?
+            .actions
+            .get(location.phase.as_deref()?)?;
+        actions.get(location.action_index?)
+    }
+
+    /// Variable as seen from an entity (`None` for the main entity)
+    ///
+    /// Subentities see their own variables, then the main entity's defines.
+    pub fn variable(&self, entity: Option<&str>, name: &str) -> Option<&ParsedVariable> {
+        let Some(entity) = entity else {
+            return self.variables.get(name);
+        };
+        self.entity_variables
+            .get(entity)
+            .and_then(|variables| variables.get(name))
+            .or_else(|| {
+                self.variables
+                    .get(name)
+                    .filter(|var| var.mutability == VariableMutability::Define)
+            })
+    }
+
+    /// Main entity variables declared in a section, sorted by name
+    pub fn section_variables(&self, section: &str) -> Vec<&ParsedVariable> {
+        let mut variables: Vec<&ParsedVariable> = self
+            .variables
+            .values()
+            .filter(|var| var.section.as_deref() == Some(section))
+            .collect();
+        variables.sort_by(|a, b| a.name.cmp(&b.name));
+        variables
     }
 
     /// Serialize this character to a JSON Value
@@ -146,3734 +585,8017 @@ impl ParsedCharacter {
     }
 }
 
-/// CastagneParser - Main parser struct
-///
-/// Parses .casp files to create Castagne characters.
-/// This is a minimal implementation - the original is much more complex!
-pub struct CastagneParser {
-    logs_active: bool,
-    pub errors: Vec<String>,
-
-    // Parsing state
-    current_lines: Vec<String>,
-    line_ids: Vec<usize>,
-    file_paths: Vec<String>,
-    current_file: usize,
+/// Options for a parse
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    /// Build flags, enabling lines prefixed with `?Flag`
+    pub flags: HashSet<String>,
+    /// Frame rate used to convert `s` units in defaults to frames
+    pub frames_per_second: u32,
+    /// Replace invalid UTF-8 with U+FFFD and warn, instead of rejecting the file
+    pub lossy_decoding: bool,
+    /// Read renamed phase, instruction and metadata key names as their
+    /// current ones, off by default, see `legacy::Deprecations::rename`
+    pub legacy_syntax: bool,
+    /// Names to warn about, with their replacement
+    pub deprecations: Deprecations,
+    /// Which include wins when two define a state or variable differently
+    pub conflict_resolution: ConflictResolution,
+    /// Severity of diagnostics by code, `None` to leave them out
+    pub severities: HashMap<String, Option<Severity>>,
+    /// Report warnings as errors, for CI; set a code to `Info` or `Hint`
+    /// in `severities` to keep it out
+    pub deny_warnings: bool,
+    /// Bounds past which the parse stops, see `Limits`
+    pub limits: Limits,
+    /// Stops the parse once cancelled, see `CancellationToken`
+    pub cancellation: CancellationToken,
+    /// Keep the lines of skeletons and includes, see `source_map`
+    pub keep_source_lines: bool,
+    /// Only locate the states of the file, parsing each on first access,
+    /// see `ParsedCharacter::state`
+    pub lazy_states: bool,
+    /// Parameters of the instructions, to name arguments and fill defaults
+    pub signatures: Signatures,
+    /// Directories `Skeleton:` and `Include:` paths are also looked up in,
+    /// see `ParserConfig::resolve_skeleton`
+    pub skeleton_paths: Vec<PathBuf>,
+    /// Logical names of skeletons and includes, with the path they stand for
+    pub skeleton_aliases: HashMap<String, String>,
+}
 
-    // Parsed data
-    metadata: CharacterMetadata,
-    variables: HashMap<String, ParsedVariable>,
-    states: HashMap<String, ParsedState>,
-    specblocks: HashMap<String, HashMap<String, String>>, // Specblock name -> key-value pairs
-    specblock_defines: HashMap<String, ParsedVariable>,
+/// Include kept when two includes define the same state or variable
+/// differently, the child file itself always winning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// The include listed last
+    #[default]
+    LastWins,
+    /// The include listed first
+    FirstWins,
+}
 
-    // Flags
-    pub aborting: bool,
-    pub invalid_file: bool,
+/// A state or variable two includes define differently
+#[derive(Debug, Clone)]
+struct InheritanceConflict {
+    kind: &'static str,
+    name: String,
+    /// Include path and origin of the definitions, in include order
+    first: (String, SourceRef),
+    second: (String, SourceRef),
 }
 
-impl CastagneParser {
-    /// Create a new parser instance
-    pub fn new() -> Self {
+impl Default for ParserConfig {
+    fn default() -> Self {
         Self {
-            logs_active: false,
-            errors: Vec::new(),
-            current_lines: Vec::new(),
-            line_ids: Vec::new(),
-            file_paths: Vec::new(),
-            current_file: 0,
-            metadata: CharacterMetadata {
-                name: String::new(),
-                author: String::new(),
-                description: String::new(),
-                skeleton: None,
-                other_fields: HashMap::new(),
-            },
-            variables: HashMap::new(),
-            states: HashMap::new(),
-            specblocks: HashMap::new(),
-            specblock_defines: HashMap::new(),
-            aborting: false,
-            invalid_file: false,
+            flags: HashSet::new(),
+            frames_per_second: 60,
+            lossy_decoding: false,
+            legacy_syntax: false,
+            deprecations: Deprecations::default(),
+            conflict_resolution: ConflictResolution::default(),
+            severities: HashMap::new(),
+            deny_warnings: false,
+            limits: Limits::default(),
+            cancellation: CancellationToken::default(),
+            keep_source_lines: false,
+            lazy_states: false,
+            signatures: Signatures::default(),
+            skeleton_paths: Vec::new(),
+            skeleton_aliases: HashMap::new(),
         }
     }
+}
 
-    /// Strip inline comments from a line (everything after # that's not in a string)
-    fn strip_inline_comment(&self, line: &str) -> String {
-        let mut result = String::new();
-        let mut in_string = false;
-        let mut escape_next = false;
-        let mut chars = line.chars().peekable();
+impl ParserConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        while let Some(ch) = chars.next() {
-            if escape_next {
-                result.push(ch);
-                escape_next = false;
-                continue;
-            }
+    /// Enable a build flag
+    pub fn with_flag(mut self, flag: &str) -> Self {
+        self.flags.insert(flag.to_string());
+        self
+    }
 
-            match ch {
-                '\\' if in_string => {
-                    escape_next = true;
-                    result.push(ch);
-                }
-                '"' => {
-                    in_string = !in_string;
-                    result.push(ch);
-                }
-                '#' if !in_string => {
-                    // Found comment marker outside string, stop here
-                    break;
-                }
-                _ => {
-                    result.push(ch);
-                }
-            }
-        }
+    /// Accept files that aren't valid UTF-8, see `lossy_decoding`
+    pub fn with_lossy_decoding(mut self, lossy: bool) -> Self {
+        self.lossy_decoding = lossy;
+        self
+    }
 
-        result
+    /// Accept renamed names, see `legacy_syntax`
+    pub fn with_legacy_syntax(mut self, legacy: bool) -> Self {
+        self.legacy_syntax = legacy;
+        self
     }
 
-    /// Get only character metadata (lightweight parse)
-    pub fn get_character_metadata(&mut self, file_path: &str) -> Option<CharacterMetadata> {
-        self.start_parsing(file_path);
-        self.parse_metadata(0);
-        self.end_parsing().map(|c| c.metadata)
+    /// Warn about other deprecated names, see `deprecations`
+    pub fn with_deprecations(mut self, deprecations: Deprecations) -> Self {
+        self.deprecations = deprecations;
+        self
     }
 
-    /// Get character info (parse metadata and specblocks)
-    pub fn get_character_info(&mut self, file_path: &str) -> Option<ParsedCharacter> {
-        self.start_parsing(file_path);
-        // TODO: Implement parse_full_file with stop_after_specblocks=true
-        self.end_parsing()
+    /// Choose the include kept on conflicts, see `conflict_resolution`
+    pub fn with_conflict_resolution(mut self, resolution: ConflictResolution) -> Self {
+        self.conflict_resolution = resolution;
+        self
     }
 
-    /// Parse a full character file
-    pub fn create_full_character(&mut self, file_path: &str) -> Option<ParsedCharacter> {
-        self.start_parsing(file_path);
-        self.parse_full_file();
-        self.end_parsing()
+    /// Report diagnostics with `code` with another severity
+    pub fn with_severity(mut self, code: &str, severity: Severity) -> Self {
+        self.severities.insert(code.to_string(), Some(severity));
+        self
     }
 
-    /// Reset error list
-    pub fn reset_errors(&mut self) {
-        self.errors.clear();
+    /// Leave out diagnostics with `code`
+    pub fn without_diagnostic(mut self, code: &str) -> Self {
+        self.severities.insert(code.to_string(), None);
+        self
     }
 
-    // -------------------------------------------------------------------------
-    // Internal parsing methods
+    /// Report warnings as errors, see `deny_warnings`
+    pub fn with_deny_warnings(mut self, deny: bool) -> Self {
+        self.deny_warnings = deny;
+        self
+    }
 
-    fn start_parsing(&mut self, file_path: &str) {
-        self.reset_errors();
-        self.current_lines.clear();
-        self.line_ids.clear();
-        self.file_paths.clear();
-        self.current_file = 0;
-        self.variables.clear();
-        self.states.clear();
-        self.specblocks.clear();
-        self.specblock_defines.clear();
-        self.aborting = false;
-        self.invalid_file = false;
+    /// Keep the lines of every file merged, see `keep_source_lines`
+    pub fn with_source_lines(mut self, keep: bool) -> Self {
+        self.keep_source_lines = keep;
+        self
+    }
 
-        self.open_file(file_path);
+    /// Parse states on first access, see `lazy_states`
+    pub fn with_lazy_states(mut self, lazy: bool) -> Self {
+        self.lazy_states = lazy;
+        self
     }
 
-    pub fn end_parsing(&mut self) -> Option<ParsedCharacter> {
-        if self.aborting || self.invalid_file {
-            return None;
-        }
+    /// Stop parses going past other bounds, see `limits`
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
 
-        Some(ParsedCharacter {
-            metadata: self.metadata.clone(),
-            variables: self.variables.clone(),
-            states: self.states.clone(),
-            specblocks: self.specblocks.clone(),
-            subentities: HashMap::new(), // TODO: Implement subentity parsing
-            transformed_data: HashMap::new(), // TODO: Implement data transformation
-        })
+    /// Accept named arguments for these instructions, see `signatures`
+    pub fn with_signatures(mut self, signatures: Signatures) -> Self {
+        self.signatures = signatures;
+        self
     }
 
-    pub fn open_file(&mut self, file_path: &str) {
-        self.log(&format!("Opening file {}", file_path));
+    /// Look skeletons and includes up in `dir` too, after those given before
+    pub fn with_skeleton_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.skeleton_paths.push(dir.into());
+        self
+    }
 
-        let _file_id = self.file_paths.len();
-        self.file_paths.push(file_path.to_string());
+    /// Let files name the skeleton or include at `path` by `name`
+    pub fn with_skeleton_alias(mut self, name: &str, path: &str) -> Self {
+        self.skeleton_aliases
+            .insert(name.to_string(), path.to_string());
+        self
+    }
 
-        // Read the file
-        match File::open(file_path) {
-            Ok(file) => {
-                let reader = BufReader::new(file);
-                for (line_num, line_result) in reader.lines().enumerate() {
-                    match line_result {
-                        Ok(line) => {
-                            self.current_lines.push(line);
-                            self.line_ids.push(line_num + 1); // 1-indexed for user display
-                        }
-                        Err(e) => {
-                            self.fatal_error(&format!(
-                                "Error reading line {} from {}: {}",
-                                line_num, file_path, e
-                            ));
-                            return;
-                        }
-                    }
-                }
-                self.log(&format!(
-                    "Successfully loaded {} lines from {}",
-                    self.current_lines.len(),
-                    file_path
-                ));
-            }
-            Err(e) => {
-                self.fatal_error(&format!(
-                    "File {} does not exist or cannot be opened: {}",
-                    file_path, e
-                ));
-            }
-        }
+    /// Let `token` cancel the parses, see `cancellation`
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
     }
 
-    pub fn parse_full_file(&mut self) {
-        if self.aborting {
-            return;
+    /// Severity a diagnostic with `code` is reported with, `None` if it is
+    /// left out
+    pub fn severity_of(&self, code: &str, severity: Severity) -> Option<Severity> {
+        let severity = match self.severities.get(code) {
+            Some(configured) => (*configured)?,
+            None => severity,
+        };
+        match severity {
+            Severity::Warning if self.deny_warnings => Some(Severity::Error),
+            severity => Some(severity),
         }
+    }
+}
 
-        self.log(">>> Starting to parse the full file.");
-
-        // Step 1: Parse metadata
-        self.parse_metadata(0);
+/// Value of a variable referenced from an expression default
+fn resolve_reference<'a>(
+    lookup: &impl Fn(&str) -> Option<&'a ParsedVariable>,
+    name: &str,
+    fps: u32,
+    stack: &mut Vec<String>,
+) -> Result<Value, String> {
+    if stack.iter().any(|seen| seen == name) {
+        return Err(format!("Circular reference to {}", name));
+    }
+    let var = lookup(name).ok_or_else(|| format!("Unknown variable {}", name))?;
+    stack.push(name.to_string());
+    let result = evaluate(&var.value, fps, &mut |next| {
+        resolve_reference(lookup, next, fps, stack)
+    });
+    stack.pop();
+    result.map(|evaluated| evaluated.value)
+}
 
-        // Step 2: If metadata has skeleton, load and parse parent file first
-        if let Some(skeleton_path) = self.metadata.skeleton.clone() {
-            self.log(&format!("Loading skeleton file: {}", skeleton_path));
-            self.load_skeleton(&skeleton_path);
-            if self.aborting {
-                return;
-            }
-        }
+/// Scope of a variables block
+///
+/// `:Variables:` holds the main entity's variables, `:Variables-Internals-Core:`
+/// a section of them, and `:Fireball---Variables:` those of subentity Fireball.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VariablesBlock {
+    pub entity: Option<String>,
+    pub section: Option<String>,
+}
 
-        // Step 3: Parse specblocks
-        self.parse_specblocks(0);
+impl VariablesBlock {
+    /// Scope of a block header (without colons), `None` for other blocks
+    pub(crate) fn from_header(header: &str) -> Option<Self> {
+        let (entity, section) = split_variables_header(header)?;
+        Some(Self {
+            entity: entity.map(str::to_string),
+            section: section.map(str::to_string),
+        })
+    }
+}
 
-        // Step 4: Parse variables
-        self.parse_variables(0);
+/// Entity and section of a variables block header, see `VariablesBlock`
+pub(crate) fn split_variables_header(header: &str) -> Option<(Option<&str>, Option<&str>)> {
+    let header = header.trim();
+    let (entity, name) = match header.split_once("---") {
+        Some((entity, name)) => (Some(entity), name),
+        None => (None, header),
+    };
+    let section = match name.strip_prefix("Variables")? {
+        "" => None,
+        rest => Some(rest.strip_prefix('-')?),
+    };
+    Some((entity, section))
+}
 
-        // Step 5: Parse states
-        self.parse_states(0);
+/// Finds where blocks start in a file read line by line
+///
+/// A block header only starts a block outside block comments, multi-line
+/// strings and continued lines, so each block can be preprocessed alone.
+#[derive(Default)]
+struct BlockSplitter {
+    scanner: LineScanner,
+    continued: bool,
+}
 
-        // TODO: Step 6: Optimize
-        self.log(">>> Parsing complete!");
+impl BlockSplitter {
+    fn starts_block(&mut self, line: &str) -> bool {
+        let scanner = &self.scanner;
+        let in_code = !scanner.in_block_comment && !scanner.in_multiline_string && !self.continued;
+        let trimmed = line.trim_start();
+        // A conditional header still starts a block, dropped or not
+        let header = match trimmed.strip_prefix('?') {
+            Some(condition) => condition
+                .split_once(char::is_whitespace)
+                .map_or("", |(_, rest)| rest),
+            None => trimmed,
+        };
+        let starts = in_code && is_block_header(header);
+        self.continued = self.scanner.scan(line).1;
+        starts
     }
+}
 
-    fn load_skeleton(&mut self, skeleton_path: &str) {
-        // Save current parsing state
-        let current_lines = self.current_lines.clone();
-        let current_line_ids = self.line_ids.clone();
-        let current_file_paths = self.file_paths.clone();
-
-        // Store child metadata separately (we want to keep the child's metadata)
-        let child_metadata = self.metadata.clone();
-
-        // Parse the skeleton file
-        let mut skeleton_parser = CastagneParser::new();
-        skeleton_parser.logs_active = self.logs_active;
-
-        match skeleton_parser.create_full_character(skeleton_path) {
-            Some(skeleton_character) => {
-                self.log(&format!("Successfully loaded skeleton: {}", skeleton_path));
-
-                // Merge skeleton data into current parser
-                // Parent data is added first, child can override
-
-                // Merge specblocks (child overrides parent on a per-key basis)
-                for (block_name, parent_data) in skeleton_character.specblocks {
-                    let child_block = self
-                        .specblocks
-                        .entry(block_name)
-                        .or_insert_with(HashMap::new);
-                    // Insert parent values that don't exist in child
-                    for (key, value) in parent_data {
-                        child_block.entry(key).or_insert(value);
-                    }
-                }
+/// Lexical state carried from one source line to the next
+#[derive(Default)]
+pub(crate) struct LineScanner {
+    pub(crate) in_block_comment: bool,
+    pub(crate) in_multiline_string: bool,
+    /// Whether the last scanned line opened a block comment
+    opened_comment: bool,
+}
 
-                // Merge variables (child overrides parent)
-                for (name, var) in skeleton_character.variables {
-                    self.variables.entry(name).or_insert(var);
-                }
+/// What `LineScanner` keeps of a line, as byte ranges of it
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ScannedLine {
+    /// Code, in order, with any `#` comment left at its end
+    pub(crate) code: Vec<Range<usize>>,
+    /// Block comments, and a `#` comment removed after a `\\`
+    pub(crate) comments: Vec<Range<usize>>,
+    /// `"""` strings, quotes included, which are also part of the code
+    pub(crate) strings: Vec<Range<usize>>,
+    /// Whether the line ends with a `\\` continuation
+    pub(crate) continues: bool,
+}
 
-                // Merge states (child overrides parent)
-                for (name, state) in skeleton_character.states {
-                    self.states.entry(name).or_insert(state);
-                }
+impl ScannedLine {
+    /// The code of `line`, which was scanned into this
+    pub(crate) fn text(&self, line: &str) -> String {
+        self.code.iter().map(|range| &line[range.clone()]).collect()
+    }
 
-                self.log("Skeleton data merged successfully");
-            }
-            None => {
-                self.fatal_error(&format!("Failed to load skeleton file: {}", skeleton_path));
-                return;
-            }
+    fn keep(&mut self, range: Range<usize>) {
+        match self.code.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.code.push(range),
         }
+    }
 
-        // Restore current parsing state
-        self.current_lines = current_lines;
-        self.line_ids = current_line_ids;
-        self.file_paths = current_file_paths;
-        self.metadata = child_metadata;
+    /// Keep only the first `len` bytes of code
+    fn truncate(&mut self, mut len: usize) {
+        self.code.retain_mut(|range| {
+            let kept = range.len().min(len);
+            range.end = range.start + kept;
+            len -= kept;
+            kept > 0
+        });
     }
 
-    fn parse_metadata(&mut self, _file_id: usize) -> &CharacterMetadata {
-        self.log("Parsing metadata...");
+    /// Drop the trailing `\\` of a continued line, with the blanks around it
+    fn strip_continuation(&mut self, line: &str) {
+        let last = self
+            .code
+            .iter()
+            .rev()
+            .find_map(|range| line[range.clone()].trim_end().chars().last());
+        if last != Some('\\') {
+            return;
+        }
+        let text = self.text(line);
+        if let Some(continued) = text.trim_end().strip_suffix('\\') {
+            self.truncate(continued.trim_end().len());
+            self.continues = true;
+        }
+    }
+}
 
-        // Find :Character: block
-        let mut in_character_block = false;
-        let mut i = 0;
+impl LineScanner {
+    const TRIPLE_QUOTE: &'static str = "\"\"\"";
 
-        while i < self.current_lines.len() {
-            let line = self.current_lines[i].trim();
+    /// The line without its block comments, and whether it ends with a `\\`
+    /// continuation, which is removed along with any `#` comment after it
+    fn scan(&mut self, line: &str) -> (String, bool) {
+        let scanned = self.scan_line(line);
+        (scanned.text(line), scanned.continues)
+    }
 
-            // Check for :Character: block start
-            if line == ":Character:" {
-                in_character_block = true;
-                i += 1;
+    /// Like `scan`, keeping where each piece of the line comes from
+    pub(crate) fn scan_line(&mut self, line: &str) -> ScannedLine {
+        self.opened_comment = false;
+        let mut scanned = ScannedLine::default();
+        let mut in_string = false;
+        let mut comment_start = 0;
+        let mut string_start = 0;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((pos, ch)) = chars.next() {
+            let rest = &line[pos..];
+            let next = pos + ch.len_utf8();
+            if self.in_block_comment {
+                if rest.starts_with("*/") {
+                    chars.next();
+                    self.in_block_comment = false;
+                    scanned.comments.push(comment_start..pos + 2);
+                }
                 continue;
             }
-
-            // Check for end of block (another : block or empty line after content)
-            if in_character_block
-                && line.starts_with(':')
-                && line.ends_with(':')
-                && line != ":Character:"
-            {
-                break;
+            if (in_string || self.in_multiline_string) && ch == '\\' {
+                let end = chars
+                    .next()
+                    .map_or(next, |(at, escaped)| at + escaped.len_utf8());
+                scanned.keep(pos..end);
+                continue;
+            }
+            if !in_string && rest.starts_with(Self::TRIPLE_QUOTE) {
+                self.in_multiline_string = !self.in_multiline_string;
+                let end = pos + Self::TRIPLE_QUOTE.len();
+                match self.in_multiline_string {
+                    true => string_start = pos,
+                    false => scanned.strings.push(string_start..end),
+                }
+                scanned.keep(pos..end);
+                chars.nth(1);
+                continue;
+            }
+            if self.in_multiline_string {
+                scanned.keep(pos..next);
+                continue;
             }
 
-            if in_character_block && !line.is_empty() && !line.starts_with('#') {
-                // Strip inline comments and parse metadata fields
-                let cleaned_line = self.strip_inline_comment(line);
-                let cleaned = cleaned_line.trim();
-
-                if !cleaned.is_empty() {
-                    if let Some(colon_pos) = cleaned.find(':') {
-                        let key = cleaned[..colon_pos].trim();
-                        let value = cleaned[colon_pos + 1..].trim().to_string();
-
-                        match key {
-                            "Name" => self.metadata.name = value,
-                            "Author" => self.metadata.author = value,
-                            "Description" => self.metadata.description = value,
-                            "Skeleton" => self.metadata.skeleton = Some(value),
-                            _ => {
-                                self.metadata.other_fields.insert(key.to_string(), value);
-                            }
-                        }
+            match ch {
+                '"' => in_string = !in_string,
+                '#' if !in_string => {
+                    scanned.strip_continuation(line);
+                    match scanned.continues {
+                        true => scanned.comments.push(pos..line.len()),
+                        false => scanned.keep(pos..line.len()),
                     }
+                    return scanned;
                 }
+                '/' if !in_string && rest.starts_with("/*") => {
+                    chars.next();
+                    self.in_block_comment = true;
+                    self.opened_comment = true;
+                    comment_start = pos;
+                    continue;
+                }
+                _ => {}
             }
-
-            i += 1;
+            scanned.keep(pos..next);
         }
 
-        self.log(&format!("Parsed metadata: Name={}", self.metadata.name));
-        &self.metadata
+        if self.in_block_comment {
+            scanned.comments.push(comment_start..line.len());
+        } else if self.in_multiline_string {
+            scanned.strings.push(string_start..line.len());
+        } else if !in_string {
+            scanned.strip_continuation(line);
+        }
+        scanned
     }
+}
 
-    fn parse_specblocks(&mut self, _file_id: usize) -> HashMap<String, String> {
-        self.log("Parsing specblocks...");
-
-        let mut i = 0;
-        while i < self.current_lines.len() {
-            let line = self.current_lines[i].trim();
-
-            // Check if this is a specblock definition (starts with ':' and ends with ':')
-            // but exclude known special blocks
-            if line.starts_with(':') && line.ends_with(':') {
-                // Extract block name (including any parentheses for states)
-                let full_block_name = line[1..line.len() - 1].to_string();
-
-                // Extract just the name part (before any parentheses) for comparison
-                let block_name = if let Some(paren_pos) = full_block_name.find('(') {
-                    full_block_name[..paren_pos].trim()
-                } else {
-                    full_block_name.as_str()
-                };
-
-                // Check if this is a specblock (not Character, Variables, or a state)
-                // Specblocks typically have specific patterns, but for now we'll identify them
-                // by checking if the content is key-value pairs (not phase markers or actions)
-                if block_name != "Character" && block_name != "Variables" {
-                    // Peek ahead to see if this looks like a specblock
-                    if self.is_specblock(block_name, i + 1) {
-                        self.parse_specblock(block_name.to_string(), &mut i);
+/// Column and description of the first unbalanced delimiter of a line
+///
+/// An unclosed parenthesis or string points at where it was opened.
+pub(crate) fn unbalanced_delimiter(line: &str) -> Option<(usize, String)> {
+    let mut open: Vec<(usize, char)> = Vec::new();
+    let mut string_start = None;
+    let mut escape_next = false;
+    for (column, ch) in line.chars().enumerate() {
+        if string_start.is_some() {
+            match ch {
+                _ if escape_next => escape_next = false,
+                '\\' => escape_next = true,
+                '"' => string_start = None,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => string_start = Some(column),
+            '(' | '[' => open.push((column, ch)),
+            ')' | ']' => {
+                let expected = if ch == ')' { '(' } else { '[' };
+                match open.pop() {
+                    Some((_, opening)) if opening == expected => {}
+                    Some((start, opening)) => {
+                        return Some((
+                            start,
+                            format!("'{}' closed by '{}' at column {}", opening, ch, column + 1),
+                        ))
                     }
+                    None => return Some((column, format!("Unexpected '{}'", ch))),
                 }
             }
-
-            i += 1;
+            _ => {}
         }
-
-        self.log(&format!("Parsed {} specblocks", self.specblocks.len()));
-        HashMap::new() // Return empty for compatibility with existing code
     }
 
-    fn is_specblock(&self, _block_name: &str, start_idx: usize) -> bool {
-        // Look at the first few non-empty lines to determine if this is a specblock
-        // Specblocks contain key-value pairs (Key: Value) without phase markers (---)
-        let mut idx = start_idx;
-        let mut line_count = 0;
-
-        while idx < self.current_lines.len() && line_count < 5 {
-            let line = self.current_lines[idx].trim();
+    if let Some(start) = string_start {
+        return Some((start, "Unclosed string".to_string()));
+    }
+    open.pop()
+        .map(|(start, opening)| (start, format!("Unclosed '{}'", opening)))
+}
 
-            // Stop at next block
-            if line.starts_with(':') && line.ends_with(':') {
-                break;
-            }
+/// Decode the bytes of a source file as UTF-8, without its BOM
+///
+/// Returns the text and, when decoded lossily, a warning. The error
+/// completes a sentence starting with the file name.
+///
+/// `first_line` is the line number of the first byte, the BOM only being
+/// looked for at the start of the file.
+pub(crate) fn decode_source(
+    bytes: &[u8],
+    lossy: bool,
+    first_line: usize,
+) -> Result<(String, Option<String>), String> {
+    let bytes = match first_line {
+        1 if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) => {
+            return Err("is encoded as UTF-16, save it as UTF-8".to_string());
+        }
+        1 => bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes),
+        _ => bytes,
+    };
+
+    let error = match std::str::from_utf8(bytes) {
+        Ok(text) => return Ok((text.to_string(), None)),
+        Err(error) => error,
+    };
+    let valid = &bytes[..error.valid_up_to()];
+    let line_start = valid
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let line = valid.iter().filter(|b| **b == b'\n').count() + first_line;
+    let column = String::from_utf8_lossy(&valid[line_start..])
+        .chars()
+        .count()
+        + 1;
+    let position = format!("line {}, column {}", line, column);
+
+    if lossy {
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        let warning = format!("invalid UTF-8 replaced, first at {}", position);
+        Ok((text, Some(warning)))
+    } else {
+        Err(format!("is not valid UTF-8 ({})", position))
+    }
+}
 
-            // Skip empty lines and comments
-            if line.is_empty() || line.starts_with('#') {
-                idx += 1;
-                continue;
-            }
+/// Split `Instruction(Args)` into the instruction and the text between the
+/// outer parentheses, `None` when a parenthesis is opened but never closed
+pub(crate) fn split_action(line: &str) -> Option<(&str, &str)> {
+    let Some(open_paren) = line.find('(') else {
+        // No parentheses, treat as instruction with no args
+        return Some((line, ""));
+    };
+    let close_paren = line.rfind(')')?;
+    let args = line.get(open_paren + 1..close_paren).unwrap_or_default();
+    Some((line[..open_paren].trim(), args))
+}
 
-            // If we see a phase marker, this is definitely a state, not a specblock
-            if line.starts_with("---") {
-                return false;
-            }
+/// Instruction closing the loop or switch an instruction opens
+fn block_closing(instruction: &str) -> Option<&'static str> {
+    match instruction {
+        SWITCH => Some(END_SWITCH),
+        _ => LoopKind::of_opening(instruction).map(|kind| kind.closing()),
+    }
+}
 
-            // If we see a line that looks like an action (function call), it's a state
-            if line.contains('(') && line.contains(')') && !line.contains(':') {
-                return false;
-            }
+/// The instruction itself if it closes a loop or switch
+fn block_end(instruction: &str) -> Option<&'static str> {
+    match instruction {
+        END_SWITCH => Some(END_SWITCH),
+        _ => LoopKind::of_closing(instruction).map(|kind| kind.closing()),
+    }
+}
 
-            // If we see a key-value pair, it might be a specblock
-            if line.contains(':') {
-                line_count += 1;
+/// Split arguments by comma, respecting nested parentheses and quotes
+pub(crate) fn split_arguments(args_str: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut start = 0;
+    let mut paren_depth = 0;
+    let mut in_string = false;
+    let mut escape_next = false;
+
+    for (pos, ch) in args_str.char_indices() {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            '\\' => escape_next = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => paren_depth += 1,
+            ')' if !in_string => paren_depth -= 1,
+            ',' if !in_string && paren_depth == 0 => {
+                // Found a separator at the top level
+                let arg = args_str[start..pos].trim();
+                if !arg.is_empty() {
+                    args.push(arg);
+                }
+                start = pos + 1;
             }
-
-            idx += 1;
+            _ => {}
         }
-
-        // If we found any key-value pairs, consider it a specblock
-        line_count > 0
     }
 
-    fn parse_specblock(&mut self, block_name: String, i: &mut usize) {
-        self.log(&format!("Parsing specblock: {}", block_name));
+    // Add the last argument
+    let arg = args_str[start..].trim();
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
 
-        let mut specblock_data = HashMap::new();
-        *i += 1; // Move past the block name line
+/// Subentity whose metadata a block header (without colons) starts
+pub(crate) fn subentity_header(header: &str) -> Option<&str> {
+    let entity = header.trim().strip_suffix(SUBENTITY_SUFFIX)?;
+    (!entity.is_empty()).then_some(entity)
+}
 
-        while *i < self.current_lines.len() {
-            let line = self.current_lines[*i].trim();
+/// Split a state header into name, type and parent
+///
+/// `Name`, `Name(Helper)`, `Name(Parent)` or `Name(Helper, Parent)`.
+pub(crate) fn split_state_header(header: &str) -> (&str, StateType, Option<&str>) {
+    let (Some(paren_start), Some(paren_end)) = (header.find('('), header.rfind(')')) else {
+        return (header, StateType::Normal, None);
+    };
+    let name = header[..paren_start].trim();
+    let params = header
+        .get(paren_start + 1..paren_end)
+        .unwrap_or_default()
+        .trim();
+
+    if let Some((type_str, parent)) = params.split_once(',') {
+        return (
+            name,
+            StateType::from_name(type_str.trim()),
+            Some(parent.trim()),
+        );
+    }
+    // Single parameter - could be type or parent
+    match StateType::from_name(params) {
+        StateType::Normal => (name, StateType::Normal, Some(params)),
+        state_type => (name, state_type, None),
+    }
+}
 
-            // Check if we've reached another block
-            if line.starts_with(':') && line.ends_with(':') {
-                break;
-            }
+/// Whether `name` names a state: `:name:` opens a state block under that
+/// name, like `Idle`, `5A` or `j.236B`
+pub(crate) fn is_state_name(name: &str) -> bool {
+    let syntax = |c: char| c.is_whitespace() || matches!(c, '(' | ')' | ',' | ':' | '"' | '#');
+    !name.is_empty()
+        && !name.contains(syntax)
+        && !name.contains("/*")
+        && !name.starts_with('?')
+        && name != "Character"
+        && split_variables_header(name).is_none()
+        && subentity_header(name).is_none()
+}
 
-            // Parse key-value pairs (strip inline comments first)
-            if !line.is_empty() && !line.starts_with('#') {
-                let cleaned_line = self.strip_inline_comment(line);
-                let cleaned = cleaned_line.trim();
+/// Split `Name(Type)` or `Name(Type, Subtype)` into its three parts
+pub(crate) fn split_name_and_type(name_part: &str) -> Option<(&str, &str, &str)> {
+    let open_paren = name_part.find('(')?;
+    let close_paren = name_part.find(')')?;
+    let name = name_part[..open_paren].trim();
+    let type_str = name_part.get(open_paren + 1..close_paren)?.trim();
+    Some(match type_str.split_once(',') {
+        Some((main_type, subtype)) => (name, main_type.trim(), subtype.trim()),
+        None => (name, type_str, ""),
+    })
+}
 
-                if !cleaned.is_empty() {
-                    if let Some(colon_pos) = cleaned.find(':') {
-                        let key = cleaned[..colon_pos].trim().to_string();
-                        let value = cleaned[colon_pos + 1..].trim().to_string();
-                        specblock_data.insert(key, value);
-                    }
+/// `text` with each parameter name standing as an identifier replaced by
+/// its value, string literals left alone
+///
+/// A value replacing part of a larger expression is parenthesized unless
+/// it is a single name or number, so `Damage * 2` with `Damage` set to
+/// `A + 1` reads `(A + 1) * 2`.
+pub(crate) fn substitute_params(text: &str, params: &[String], values: &[&str]) -> String {
+    let is_token = |value: &str| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_alphanumeric() || "_.".contains(c))
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == '"' {
+            out.push(c);
+            let mut escaped = false;
+            for (_, c) in chars.by_ref() {
+                out.push(c);
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => break,
+                    _ => escaped = false,
                 }
             }
-
-            *i += 1;
+            continue;
         }
-
-        if !specblock_data.is_empty() {
-            // Merge with existing specblock (if from parent) instead of replacing
-            let existing_block = self
-                .specblocks
-                .entry(block_name.clone())
-                .or_insert_with(HashMap::new);
-            for (key, value) in specblock_data {
-                // Child values override parent values
-                existing_block.insert(key, value);
+        if !(c.is_alphanumeric() || c == '_') {
+            out.push(c);
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some(&(index, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
             }
+            end = index + c.len_utf8();
+            chars.next();
+        }
+        let word = &text[start..end];
+        // Numbers like `1e5` aren't names
+        let position = match c.is_ascii_digit() {
+            true => None,
+            false => params.iter().position(|param| param == word),
+        };
+        match position {
+            Some(index) if word.len() == text.len() || is_token(values[index].trim()) => {
+                out.push_str(values[index])
+            }
+            Some(index) => {
+                out.push('(');
+                out.push_str(values[index]);
+                out.push(')');
+            }
+            None => out.push_str(word),
         }
-        *i -= 1; // Back up one so the outer loop doesn't skip a line
     }
+    out
+}
 
-    fn parse_variables(&mut self, _file_id: usize) {
-        self.log("Parsing variables...");
-
-        // Find :Variables: block
-        let mut in_variables_block = false;
-        let mut i = 0;
+/// Whether the lines after a block header are `Key: Value` pairs
+///
+/// Looks at the first few non-empty lines: a phase marker or an action
+/// call makes the block a state.
+pub(crate) fn looks_like_specblock<'a>(lines: impl Iterator<Item = &'a str>) -> bool {
+    let mut line_count = 0;
+    for line in lines {
+        let line = line.trim();
+        // Stop at the next block
+        if line_count >= 5 || is_block_header(line) {
+            break;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with("---") {
+            return false;
+        }
+        if line.contains('(') && line.contains(')') && !line.contains(':') {
+            return false;
+        }
+        if line.contains(':') {
+            line_count += 1;
+        }
+    }
+    line_count > 0
+}
 
-        while i < self.current_lines.len() {
-            let line = self.current_lines[i].trim().to_string();
+/// Whether a line opens a block like `:Character:` or `:Idle:`
+pub(crate) fn is_block_header(line: &str) -> bool {
+    let line = line.trim();
+    line.len() > 1 && line.starts_with(':') && line.ends_with(':')
+}
 
-            // Check for :Variables: block start
-            if line == ":Variables:" {
-                in_variables_block = true;
-                i += 1;
-                continue;
-            }
+/// A `:Name:` block of a file's lines
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Block {
+    /// Text between the colons, e.g. `Jab(Helper)`
+    pub header: String,
+    /// Name before any parentheses
+    pub name: String,
+    /// Index of the header line
+    pub start: usize,
+    /// Index of the next header line (or end of lines)
+    pub end: usize,
+}
 
-            // Check for end of block
-            if in_variables_block
-                && line.starts_with(':')
-                && line.ends_with(':')
-                && line != ":Variables:"
-            {
-                break;
-            }
+impl Block {
+    pub(crate) fn contains(&self, line: usize) -> bool {
+        self.start <= line && line < self.end
+    }
 
-            if in_variables_block && !line.is_empty() && !line.starts_with('#') {
-                let cleaned_line = self.strip_inline_comment(&line);
-                let cleaned = cleaned_line.trim();
+    /// Indices of the lines after the header
+    pub(crate) fn body(&self) -> std::ops::Range<usize> {
+        self.start + 1..self.end
+    }
+}
 
-                if !cleaned.is_empty() {
-                    self.parse_variable_line(cleaned);
-                }
+/// Split lines into blocks in a single pass
+///
+/// Lines before the first header belong to no block.
+pub(crate) fn segment_blocks(lines: &[String]) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if let Ok(header) = block_header_name(line) {
+            if let Some(last) = blocks.last_mut() {
+                last.end = index;
             }
-
-            i += 1;
+            let header = header.to_string();
+            let name = header.split('(').next().unwrap_or("").trim().to_string();
+            blocks.push(Block {
+                header,
+                name,
+                start: index,
+                end: lines.len(),
+            });
         }
-
-        self.log(&format!("Parsed {} variables", self.variables.len()));
     }
+    blocks
+}
 
-    fn parse_variable_line(&mut self, line: &str) {
-        // Parse variable definition: var VariableName(Type): DefaultValue
-        // or constant definition: def ConstantName: Value
+/// A broken invariant of the parser itself rather than a problem of the
+/// file, reported as an internal parser error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// Bytes `start..end` aren't a slice of a `len`-byte line
+    Slice {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    /// A line handed to a block parser isn't a block header
+    NotABlockHeader(String),
+    /// A streamed block without lines
+    EmptyBlock,
+}
 
-        if line.starts_with("var ") {
-            self.parse_var_declaration(&line[4..]);
-        } else if line.starts_with("def ") {
-            self.parse_def_declaration(&line[4..]);
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Slice { start, end, len } => {
+                write!(f, "bytes {}..{} out of a {}-byte line", start, end, len)
+            }
+            ParseError::NotABlockHeader(line) => write!(f, "{:?} is not a block header", line),
+            ParseError::EmptyBlock => f.write_str("empty block"),
         }
     }
+}
 
-    fn parse_var_declaration(&mut self, line: &str) {
-        // Format: VariableName(Type): DefaultValue
-        // or: VariableName(Type, Subtype): DefaultValue
+/// Bytes `start..end` of `text`, without panicking on bad offsets
+pub(crate) fn slice(text: &str, start: usize, end: usize) -> Result<&str, ParseError> {
+    text.get(start..end).ok_or(ParseError::Slice {
+        start,
+        end,
+        len: text.len(),
+    })
+}
 
-        if let Some(colon_pos) = line.find(':') {
-            let name_part = line[..colon_pos].trim();
-            let value_part = line[colon_pos + 1..].trim();
-
-            // Parse name and type
-            if let Some(open_paren) = name_part.find('(') {
-                if let Some(close_paren) = name_part.find(')') {
-                    let name = name_part[..open_paren].trim().to_string();
-                    let type_str = name_part[open_paren + 1..close_paren].trim();
-
-                    // Parse type and optional subtype
-                    let (var_type, subtype) = if let Some(comma_pos) = type_str.find(',') {
-                        let main_type = type_str[..comma_pos].trim();
-                        let sub = type_str[comma_pos + 1..].trim().to_string();
-                        (self.parse_variable_type(main_type), sub)
-                    } else {
-                        (self.parse_variable_type(type_str), String::new())
-                    };
+/// Text between the colons of a block header like `:Idle(Helper):`
+pub(crate) fn block_header_name(line: &str) -> Result<&str, ParseError> {
+    let line = line.trim();
+    if !is_block_header(line) {
+        return Err(ParseError::NotABlockHeader(line.to_string()));
+    }
+    slice(line, 1, line.len() - 1)
+}
 
-                    let var = ParsedVariable {
-                        name: name.clone(),
-                        mutability: VariableMutability::Variable,
-                        var_type,
-                        subtype,
-                        value: value_part.to_string(),
-                    };
+/// Column, in characters, of byte `byte` of `line`; 0 if it isn't one
+pub(crate) fn char_column(line: &str, byte: usize) -> usize {
+    line.get(..byte).map_or(0, |before| before.chars().count())
+}
 
-                    self.variables.insert(name, var);
-                }
-            }
-        }
-    }
+/// CastagneParser - Main parser struct
+///
+/// Parses .casp files to create Castagne characters.
+/// This is a minimal implementation - the original is much more complex!
+pub struct CastagneParser {
+    logs_active: bool,
+    config: ParserConfig,
+    pub errors: Vec<String>,
+    /// Errors that carry a code and a source position
+    diagnostics: Vec<Diagnostic>,
 
-    fn parse_def_declaration(&mut self, line: &str) {
-        // Format: ConstantName: Value
+    // Parsing state
+    pub(crate) current_lines: Vec<String>,
+    pub(crate) line_ids: Vec<usize>,
+    file_paths: Vec<String>,
+    current_file: usize,
 
-        if let Some(colon_pos) = line.find(':') {
-            let name = line[..colon_pos].trim().to_string();
-            let value = line[colon_pos + 1..].trim().to_string();
+    // Parsed data
+    metadata: CharacterMetadata,
+    variables: HashMap<Symbol, ParsedVariable>,
+    entity_variables: HashMap<String, HashMap<Symbol, ParsedVariable>>,
+    subentities: HashMap<String, CharacterMetadata>,
+    pub(crate) states: HashMap<Symbol, ParsedState>,
+    /// State blocks left for later with `lazy_states`
+    lazy_states: HashMap<Symbol, LazyState>,
+    templates: HashMap<String, ParsedTemplate>,
+    generic_states: HashMap<String, GenericState>,
+    pub(crate) source_index: SourceIndex,
+    /// Files currently including this one or using it as a skeleton, to
+    /// detect cycles
+    include_chain: Vec<String>,
+    /// Skeletons above this file, for `Limits::max_skeleton_chain`
+    skeleton_depth: usize,
+    /// Skeleton then includes merged into this file, in merge order
+    pub(crate) inherited: Vec<(String, Arc<ParsedCharacter>)>,
+    /// Conflicts between includes, reported unless this file resolves them
+    inheritance_conflicts: Vec<InheritanceConflict>,
+    specblocks: HashMap<String, HashMap<String, String>>, // Specblock name -> key-value pairs
+    specblock_origins: HashMap<String, HashMap<String, SourceRef>>,
+    specblock_defines: HashMap<String, ParsedVariable>,
+    /// Names shared by everything this parser produces, kept across parses
+    interner: Interner,
+    metrics: ParseMetrics,
+    profiler: Option<Profiler>,
+    /// Whether the `:Character:` block was parsed, when streaming
+    streamed_metadata: bool,
+    /// Syntax of the file being parsed, from its `FormatVersion:` field
+    format_version: FormatVersion,
+    /// Lines of the skeletons and includes read, with `keep_source_lines`
+    dependency_lines: Vec<FileLines>,
+    /// Already parsed skeletons and includes, by path, used instead of parsing them again
+    parents: HashMap<String, Arc<ParsedCharacter>>,
+    skeleton_cache: Option<SkeletonCache>,
+    /// Characters parsed in earlier runs, see `with_parse_cache`
+    parse_cache: Option<ParseCache>,
 
-            let var = ParsedVariable {
-                name: name.clone(),
-                mutability: VariableMutability::Define,
-                var_type: VariableType::Var, // Defines can be any type
-                subtype: String::new(),
-                value,
-            };
+    // Flags
+    pub aborting: bool,
+    pub invalid_file: bool,
+    /// What stopped the last parse, if anything did
+    failure: Option<ParseFailure>,
+    /// `castagne-ignore` pragmas of the file being parsed
+    suppressions: Suppressions,
+}
 
-            self.variables.insert(name, var);
-        }
+impl CastagneParser {
+    /// Create a new parser instance
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
     }
 
-    fn parse_variable_type(&self, type_str: &str) -> VariableType {
-        match type_str {
-            "Int" => VariableType::Int,
-            "Str" => VariableType::Str,
-            "Var" => VariableType::Var,
-            "Vec2" => VariableType::Vec2,
-            "Vec3" => VariableType::Vec3,
-            "Box" => VariableType::Box,
-            "Bool" => VariableType::Bool,
-            _ => {
-                self.log(&format!(
-                    "Unknown variable type: {}, defaulting to Var",
-                    type_str
-                ));
-                VariableType::Var
-            }
-        }
+    /// Intern names into a shared interner, e.g. one for a whole roster
+    pub fn with_interner(mut self, interner: Interner) -> Self {
+        self.interner = interner;
+        self
     }
 
-    fn parse_states(&mut self, _file_id: usize) {
-        self.log("Parsing states...");
-
-        let mut i = 0;
-        while i < self.current_lines.len() {
-            let line = self.current_lines[i].trim();
+    /// Interner holding the names of everything this parser produced
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
 
-            // Check if this is a state definition (starts and ends with ':' but not a known special block)
-            if line.starts_with(':') && line.ends_with(':') {
-                let full_state_name = line[1..line.len() - 1].to_string();
+    /// Call `profiler` with the duration of each parse phase as it ends
+    pub fn with_profiler(
+        mut self,
+        profiler: impl Fn(ParsePhase, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.profiler = Some(Arc::new(profiler));
+        self
+    }
 
-                // Extract just the name part (before any parentheses) for comparison
-                let state_name = if let Some(paren_pos) = full_state_name.find('(') {
-                    full_state_name[..paren_pos].trim()
-                } else {
-                    full_state_name.as_str()
-                };
+    /// Use an already parsed character when `path` is a skeleton or include
+    pub fn with_parent(mut self, path: &str, parent: Arc<ParsedCharacter>) -> Self {
+        self.parents.insert(path.to_string(), parent);
+        self
+    }
 
-                // Skip special blocks we've already handled, and skip specblocks
-                if state_name != "Character"
-                    && state_name != "Variables"
-                    && !self.specblocks.contains_key(state_name)
-                {
-                    self.parse_state(full_state_name, &mut i);
-                }
-            }
+    /// Share parsed skeletons and includes with other parsers of a batch
+    pub fn with_skeleton_cache(mut self, cache: SkeletonCache) -> Self {
+        self.skeleton_cache = Some(cache);
+        self
+    }
 
-            i += 1;
-        }
+    /// Reuse the characters stored in a cache directory by earlier runs,
+    /// storing the ones parsed; see `ParseCache`
+    pub fn with_parse_cache(mut self, cache: ParseCache) -> Self {
+        self.parse_cache = Some(cache);
+        self
+    }
 
-        self.log(&format!("Parsed {} states", self.states.len()));
+    /// Measurements of the last parse
+    pub fn metrics(&self) -> &ParseMetrics {
+        &self.metrics
     }
 
-    fn parse_state_header(&self, state_header: &str) -> (String, StateType, Option<String>) {
-        // Parse state header to extract name, type, and parent
-        // Formats:
-        // - StateName -> (StateName, Normal, None)
-        // - StateName(Helper) -> (StateName, Helper, None)
-        // - StateName(Idle) -> (StateName, Normal, Some(Idle))
-        // - StateName(Helper, Idle) -> (StateName, Helper, Some(Idle))
-
-        if let Some(paren_start) = state_header.find('(') {
-            if let Some(paren_end) = state_header.rfind(')') {
-                let name = state_header[..paren_start].trim().to_string();
-                let params = state_header[paren_start + 1..paren_end].trim();
-
-                // Check if params contains a comma (type and parent)
-                if let Some(comma_pos) = params.find(',') {
-                    let type_str = params[..comma_pos].trim();
-                    let parent_str = params[comma_pos + 1..].trim().to_string();
-                    let state_type = self.parse_state_type(type_str);
-                    return (name, state_type, Some(parent_str));
-                } else {
-                    // Single parameter - could be type or parent
-                    // Types are: Helper, BaseState, Special, Specblock
-                    let state_type = self.parse_state_type(params);
-                    if state_type != StateType::Normal {
-                        // It's a type
-                        return (name, state_type, None);
-                    } else {
-                        // It's a parent state
-                        return (name, StateType::Normal, Some(params.to_string()));
-                    }
-                }
-            }
-        }
-
-        // No parentheses, just a simple state name
-        (state_header.to_string(), StateType::Normal, None)
-    }
-
-    fn parse_state_type(&self, type_str: &str) -> StateType {
-        match type_str {
-            "Helper" => StateType::Helper,
-            "BaseState" => StateType::BaseState,
-            "Special" => StateType::Special,
-            "Specblock" => StateType::Specblock,
-            _ => StateType::Normal,
-        }
-    }
-
-    fn parse_state(&mut self, state_name: String, i: &mut usize) {
-        self.log(&format!("Parsing state: {}", state_name));
-
-        // Parse state name with optional type and parent
-        // Format: :StateName: or :StateName(Type): or :StateName(Parent): or :StateName(Type, Parent):
-        let (actual_name, state_type, parent) = self.parse_state_header(&state_name);
-
-        let mut state = ParsedState {
-            name: actual_name.clone(),
-            state_type,
-            parent,
-            actions: HashMap::new(),
-        };
-
-        let mut current_phase: Option<String> = None;
-        *i += 1; // Move past the state name line
-
-        while *i < self.current_lines.len() {
-            let line = self.current_lines[*i].trim();
-
-            // Check if we've reached another state
-            if line.starts_with(':') && line.ends_with(':') {
-                break;
-            }
-
-            // Check for phase marker (---PhaseName:)
-            if line.starts_with("---") {
-                if let Some(colon_pos) = line.find(':') {
-                    let phase_name = line[3..colon_pos].trim().to_string();
-                    current_phase = Some(phase_name.clone());
-                    state.actions.entry(phase_name).or_insert_with(Vec::new);
-                }
-            }
-            // Parse action line (strip inline comments first)
-            else if !line.is_empty() && !line.starts_with('#') {
-                let cleaned_line = self.strip_inline_comment(line);
-                let cleaned = cleaned_line.trim();
-
-                if !cleaned.is_empty() {
-                    if let Some(ref phase) = current_phase {
-                        if let Some(action) = self.parse_action_line(cleaned, *i) {
-                            state
-                                .actions
-                                .entry(phase.clone())
-                                .or_insert_with(Vec::new)
-                                .push(action);
-                        }
-                    }
-                }
-            }
-
-            *i += 1;
-        }
-
-        self.states.insert(actual_name, state);
-        *i -= 1; // Back up one so the outer loop doesn't skip a line
-    }
-
-    fn parse_action_line(&self, line: &str, line_number: usize) -> Option<ParsedAction> {
-        // Parse function call: FunctionName(Arg1, Arg2, ...)
-        // or simple instruction: FunctionName
-
-        if let Some(open_paren) = line.find('(') {
-            if let Some(close_paren) = line.rfind(')') {
-                let instruction = line[..open_paren].trim().to_string();
-                let args_str = &line[open_paren + 1..close_paren];
-
-                // Parse arguments with better handling of nested calls and strings
-                let args = self.parse_arguments(args_str);
-
-                return Some(ParsedAction {
-                    instruction,
-                    args,
-                    line_number,
-                });
-            }
-        } else {
-            // No parentheses, treat as instruction with no args
-            return Some(ParsedAction {
-                instruction: line.to_string(),
-                args: Vec::new(),
-                line_number,
-            });
+    /// Create a parser with build flags and other options
+    pub fn with_config(config: ParserConfig) -> Self {
+        Self {
+            logs_active: false,
+            config,
+            errors: Vec::new(),
+            diagnostics: Vec::new(),
+            current_lines: Vec::new(),
+            line_ids: Vec::new(),
+            file_paths: Vec::new(),
+            current_file: 0,
+            metadata: CharacterMetadata {
+                name: String::new(),
+                author: String::new(),
+                description: String::new(),
+                skeleton: None,
+                filepath: String::new(),
+                includes: Vec::new(),
+                no_inherit: Vec::new(),
+                editor_name: None,
+                version: None,
+                portrait: None,
+                select_icon: None,
+                palette_count: None,
+                other_fields: HashMap::new(),
+            },
+            variables: HashMap::new(),
+            entity_variables: HashMap::new(),
+            subentities: HashMap::new(),
+            states: HashMap::new(),
+            lazy_states: HashMap::new(),
+            templates: HashMap::new(),
+            generic_states: HashMap::new(),
+            source_index: SourceIndex::new(),
+            include_chain: Vec::new(),
+            skeleton_depth: 0,
+            inherited: Vec::new(),
+            inheritance_conflicts: Vec::new(),
+            dependency_lines: Vec::new(),
+            specblocks: HashMap::new(),
+            specblock_origins: HashMap::new(),
+            specblock_defines: HashMap::new(),
+            interner: Interner::new(),
+            metrics: ParseMetrics::default(),
+            profiler: None,
+            streamed_metadata: false,
+            format_version: FormatVersion::LATEST,
+            parents: HashMap::new(),
+            skeleton_cache: None,
+            parse_cache: None,
+            aborting: false,
+            invalid_file: false,
+            failure: None,
+            suppressions: Suppressions::default(),
         }
-
-        None
     }
 
-    fn parse_arguments(&self, args_str: &str) -> Vec<String> {
-        // Split arguments by comma, but respect nested parentheses and quotes
-        let mut args = Vec::new();
-        let mut current_arg = String::new();
-        let mut paren_depth = 0;
+    /// Strip inline comments from a line (everything after # that's not in a
+    /// string, and `/* ... */` spans)
+    fn strip_inline_comment(&self, line: &str) -> String {
+        let mut result = String::new();
         let mut in_string = false;
+        let mut in_block_comment = false;
         let mut escape_next = false;
-
-        for ch in args_str.chars() {
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
             if escape_next {
-                current_arg.push(ch);
+                result.push(ch);
                 escape_next = false;
                 continue;
             }
+            if in_block_comment {
+                if ch == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    in_block_comment = false;
+                }
+                continue;
+            }
 
             match ch {
-                '\\' => {
+                '\\' if in_string => {
                     escape_next = true;
-                    current_arg.push(ch);
+                    result.push(ch);
                 }
                 '"' => {
                     in_string = !in_string;
-                    current_arg.push(ch);
-                }
-                '(' if !in_string => {
-                    paren_depth += 1;
-                    current_arg.push(ch);
+                    result.push(ch);
                 }
-                ')' if !in_string => {
-                    paren_depth -= 1;
-                    current_arg.push(ch);
+                '#' if !in_string => {
+                    // Found comment marker outside string, stop here
+                    break;
                 }
-                ',' if !in_string && paren_depth == 0 => {
-                    // Found a separator at the top level
-                    if !current_arg.trim().is_empty() {
-                        args.push(current_arg.trim().to_string());
-                    }
-                    current_arg.clear();
+                '/' if !in_string && chars.peek() == Some(&'*') => {
+                    chars.next();
+                    in_block_comment = true;
                 }
                 _ => {
-                    current_arg.push(ch);
+                    result.push(ch);
                 }
             }
         }
 
-        // Add the last argument
-        if !current_arg.trim().is_empty() {
-            args.push(current_arg.trim().to_string());
-        }
-
-        args
+        result
     }
 
-    // -------------------------------------------------------------------------
-    // Instruction execution (for runtime)
-
-    /// Standard parse function (used by modules to register functions)
-    pub fn standard_parse_function(&self, _function_name: &str, args: &[String]) -> Vec<String> {
-        // TODO: Parse function arguments according to function signature
-        // For now, just return the args as-is
-        args.to_vec()
+    /// Get only character metadata (lightweight parse)
+    pub fn get_character_metadata(
+        &mut self,
+        file_path: &str,
+    ) -> Result<CharacterMetadata, ParseFailure> {
+        self.start_parsing(file_path);
+        self.timed(ParsePhase::Metadata, |parser| {
+            parser.parse_metadata(0);
+        });
+        self.end_parsing().map(|c| c.metadata)
     }
 
-    // Note: Instruction execution is handled by the GDScript engine.
-    // The parser's job is to parse .casp files into data structures only.
-
-    // -------------------------------------------------------------------------
-    // Logging and errors
+    /// Get character info (parse metadata and specblocks)
+    pub fn get_character_info(&mut self, file_path: &str) -> Result<ParsedCharacter, ParseFailure> {
+        self.start_parsing(file_path);
+        // TODO: Implement parse_full_file with stop_after_specblocks=true
+        self.end_parsing()
+    }
 
-    fn log(&self, message: &str) {
-        if self.logs_active {
-            godot_print!("[CastagneParser] {}", message);
+    /// Parse a full character file
+    pub fn create_full_character(
+        &mut self,
+        file_path: &str,
+    ) -> Result<ParsedCharacter, ParseFailure> {
+        if let Some(cache) = self.parse_cache.clone() {
+            if !self.config.lazy_states {
+                return self.create_cached_character(&cache, file_path);
+            }
         }
+        self.start_parsing(file_path);
+        self.parse_full_file();
+        self.end_parsing()
     }
 
-    fn fatal_error(&mut self, message: &str) {
-        self.errors.push(message.to_string());
-        godot_error!("[CastagneParser] FATAL: {}", message);
-        self.aborting = true;
-        self.invalid_file = true;
-    }
+    /// Serve a character from `cache`, or parse and store it
+    fn create_cached_character(
+        &mut self,
+        cache: &ParseCache,
+        file_path: &str,
+    ) -> Result<ParsedCharacter, ParseFailure> {
+        let key = ParseCache::key(file_path, &self.config);
+        if let Some(entry) = key.and_then(|key| cache.get(key)) {
+            self.reset_parsing_state();
+            self.file_paths.push(file_path.to_string());
+            self.errors = entry.errors;
+            self.diagnostics = entry.diagnostics;
+            let mut character = entry.character;
+            character.metadata.filepath = file_path.to_string();
+            return Ok(character);
+        }
 
-    #[allow(dead_code)]
-    fn error(&mut self, message: &str) {
-        self.errors.push(message.to_string());
-        godot_error!("[CastagneParser] ERROR: {}", message);
+        self.start_parsing(file_path);
+        self.parse_full_file();
+        let character = self.end_parsing();
+        if let (Some(key), Ok(character)) = (key, &character) {
+            cache.insert(
+                key,
+                &self.config,
+                character,
+                &self.errors,
+                &self.diagnostics,
+            );
+        }
+        character
     }
 
-    /// Get all errors from last parse
-    pub fn get_errors(&self) -> &[String] {
-        &self.errors
+    /// Parse a full character file, along with how long each phase took
+    pub fn create_full_character_with_metrics(
+        &mut self,
+        file_path: &str,
+    ) -> (Result<ParsedCharacter, ParseFailure>, ParseMetrics) {
+        let character = self.create_full_character(file_path);
+        (character, self.metrics.clone())
     }
 
-    // -------------------------------------------------------------------------
-    // Type conversion utilities
-
-    /// Parse a string value into a Variant based on the variable type
-    pub fn parse_value_to_variant(value_str: &str, var_type: &VariableType) -> Variant {
-        let trimmed = value_str.trim();
-
-        match var_type {
-            VariableType::Int => trimmed
-                .parse::<i32>()
-                .map(Variant::from)
-                .unwrap_or_else(|_| Variant::nil()),
-            VariableType::Bool => {
-                let bool_val = match trimmed.to_lowercase().as_str() {
-                    "true" | "1" => true,
-                    "false" | "0" => false,
-                    _ => false,
-                };
-                Variant::from(bool_val)
-            }
-            VariableType::Str => {
-                // Remove quotes if present
-                let unquoted = if (trimmed.starts_with('"') && trimmed.ends_with('"'))
-                    || (trimmed.starts_with('\'') && trimmed.ends_with('\''))
-                {
-                    &trimmed[1..trimmed.len() - 1]
-                } else {
-                    trimmed
+    /// Parse a full character file block by block, see `create_full_character_from_reader`
+    pub fn create_full_character_streaming(
+        &mut self,
+        file_path: &str,
+    ) -> Result<ParsedCharacter, ParseFailure> {
+        match fs::File::open(file_path) {
+            Ok(file) => self.create_full_character_from_reader(file_path, BufReader::new(file)),
+            Err(e) => {
+                self.reset_parsing_state();
+                let failure = ParseFailure::Io {
+                    path: file_path.to_string(),
+                    reason: format!("does not exist or cannot be opened: {}", e),
                 };
-                Variant::from(GString::from(unquoted))
-            }
-            VariableType::Vec2 => {
-                // Parse (x, y) or x, y
-                Self::parse_vec2(trimmed).unwrap_or_else(|| Variant::nil())
+                self.fatal_error(failure.clone());
+                Err(failure)
             }
-            VariableType::Vec3 => {
-                // Parse (x, y, z) or x, y, z
-                Self::parse_vec3(trimmed).unwrap_or_else(|| Variant::nil())
-            }
-            VariableType::Var | VariableType::Box => {
-                // Try to infer the type
-                // First try int
-                if let Ok(i) = trimmed.parse::<i32>() {
-                    return Variant::from(i);
+        }
+    }
+
+    /// Parse a full character read block by block
+    ///
+    /// Only the lines of the current block are kept, so memory is bounded
+    /// by the largest block rather than by the file, for generated files
+    /// of several megabytes. The result is that of a full parse, except
+    /// that templates must be defined before the states using them, and
+    /// a `:Character:` block with includes must come before other blocks.
+    pub fn create_full_character_from_reader(
+        &mut self,
+        file_path: &str,
+        mut reader: impl BufRead,
+    ) -> Result<ParsedCharacter, ParseFailure> {
+        self.reset_parsing_state();
+        self.file_paths.push(file_path.to_string());
+
+        let mut splitter = BlockSplitter::default();
+        let mut block = Vec::new();
+        let mut bytes = Vec::new();
+        let mut line_number = 0;
+        let mut io_time = Duration::ZERO;
+        while !self.aborting {
+            let start = Instant::now();
+            bytes.clear();
+            match reader.read_until(b'\n', &mut bytes) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    self.fatal_error(ParseFailure::Io {
+                        path: file_path.to_string(),
+                        reason: format!("cannot be read: {}", e),
+                    });
+                    break;
                 }
-                // Then try float
-                if let Ok(f) = trimmed.parse::<f64>() {
-                    return Variant::from(f);
+            }
+            let decoded = decode_source(&bytes, self.config.lossy_decoding, line_number + 1);
+            io_time += start.elapsed();
+            let Some(text) = self.decoded_text(file_path, decoded) else {
+                break;
+            };
+
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            // Lone carriage returns end lines too
+            for line in text.split('\r') {
+                line_number += 1;
+                if !self.within_line_limits(line_number, line) {
+                    break;
                 }
-                // Then try bool
-                match trimmed.to_lowercase().as_str() {
-                    "true" | "false" => {
-                        return Variant::from(trimmed.to_lowercase() == "true");
+                self.suppressions.add_line(line_number, line);
+                if splitter.starts_block(line) && !block.is_empty() {
+                    if self.stopping() {
+                        break;
+                    }
+                    let block = std::mem::take(&mut block);
+                    if let Err(error) = self.parse_streamed_block(block) {
+                        self.internal_error(error, line_number);
                     }
-                    _ => {}
                 }
-                // Otherwise treat as string
-                Variant::from(GString::from(trimmed))
+                block.push((line_number, line.to_string()));
             }
         }
-    }
-
-    /// Parse a Vec2 from string (supports "x, y" or "(x, y)")
-    fn parse_vec2(s: &str) -> Option<Variant> {
-        let cleaned = s.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts: Vec<&str> = cleaned.split(',').collect();
+        self.record(ParsePhase::Io, io_time);
 
-        if parts.len() == 2 {
-            if let (Ok(x), Ok(y)) = (
-                parts[0].trim().parse::<f32>(),
-                parts[1].trim().parse::<f32>(),
-            ) {
-                return Some(Variant::from(Vector2::new(x, y)));
+        if !self.stopping() && !block.is_empty() {
+            if let Err(error) = self.parse_streamed_block(block) {
+                self.internal_error(error, line_number);
             }
         }
-        None
+        if !self.aborting {
+            self.timed(ParsePhase::Variables, Self::evaluate_defaults);
+        }
+        self.current_lines.clear();
+        self.line_ids.clear();
+        self.end_parsing()
     }
 
-    /// Parse a Vec3 from string (supports "x, y, z" or "(x, y, z)")
-    fn parse_vec3(s: &str) -> Option<Variant> {
-        let cleaned = s.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts: Vec<&str> = cleaned.split(',').collect();
-
-        if parts.len() == 3 {
-            if let (Ok(x), Ok(y), Ok(z)) = (
-                parts[0].trim().parse::<f32>(),
-                parts[1].trim().parse::<f32>(),
-                parts[2].trim().parse::<f32>(),
-            ) {
-                return Some(Variant::from(Vector3::new(x, y, z)));
+    /// Parse one block of a streamed file, see `create_full_character_from_reader`
+    fn parse_streamed_block(&mut self, block: Vec<(usize, String)>) -> Result<(), ParseError> {
+        let first_line = block.first().ok_or(ParseError::EmptyBlock)?.0;
+        (self.line_ids, self.current_lines) = block.into_iter().unzip();
+        self.metrics.count_lines(&self.current_lines);
+        self.timed(ParsePhase::Preprocess, Self::preprocess);
+
+        let header = self.current_lines.first().map(|line| line.trim());
+        let is_character = header == Some(":Character:");
+        if is_character && !std::mem::replace(&mut self.streamed_metadata, true) {
+            self.timed(ParsePhase::Metadata, |parser| {
+                parser.parse_metadata(0);
+                parser.apply_format_version();
+            });
+            if self.aborting {
+                return Ok(());
+            }
+            // Includes override what was parsed before them
+            let parsed = !self.variables.is_empty()
+                || !self.entity_variables.is_empty()
+                || !self.subentities.is_empty()
+                || !self.specblocks.is_empty()
+                || !self.states.is_empty()
+                || !self.templates.is_empty();
+            if parsed && !self.metadata.includes.is_empty() {
+                self.fatal_error(ParseFailure::Syntax {
+                    message: format!(
+                        "The :Character: block must come first to stream a file with includes (line {})",
+                        first_line
+                    ),
+                    span: Some(Span::line(first_line)),
+                });
+                return Ok(());
             }
+            self.timed(ParsePhase::Inheritance, Self::load_inherited_files);
+            if self.aborting {
+                return Ok(());
+            }
+        } else {
+            self.upgrade_current_lines();
         }
-        None
+
+        let blocks = self.timed(ParsePhase::Specblocks, |parser| {
+            let blocks = segment_blocks(&parser.current_lines);
+            parser.parse_specblocks_in(&blocks);
+            blocks
+        });
+        self.timed(ParsePhase::Variables, |parser| {
+            parser.parse_variables_in(&blocks)
+        });
+        self.timed(ParsePhase::Templates, |parser| {
+            parser.parse_templates_in(&blocks)
+        });
+        self.timed(ParsePhase::States, |parser| parser.parse_states_in(&blocks));
+        Ok(())
     }
-}
 
-impl Default for CastagneParser {
-    fn default() -> Self {
-        Self::new()
+    /// Parse a full character from an in-memory buffer
+    ///
+    /// `file_path` is recorded as the buffer's path for error messages.
+    /// A skeleton referenced by the buffer is still loaded from disk.
+    pub fn create_full_character_from_source(
+        &mut self,
+        file_path: &str,
+        source: &str,
+    ) -> Result<ParsedCharacter, ParseFailure> {
+        self.reset_parsing_state();
+        self.load_source(file_path, source);
+        self.parse_full_file();
+        self.end_parsing()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Reset error list
+    pub fn reset_errors(&mut self) {
+        self.errors.clear();
+        self.diagnostics.clear();
+    }
 
-    #[test]
-    fn test_parse_metadata() {
-        let mut parser = CastagneParser::new();
-        // Don't enable logs in unit tests (requires Godot runtime)
-
-        // Simulate file content
-        parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Test Fighter".to_string(),
-            "Author: Test Author".to_string(),
-            "Description: A test character".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-        ];
-        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
-        parser.file_paths = vec!["test.casp".to_string()];
-
-        parser.parse_metadata(0);
+    // -------------------------------------------------------------------------
+    // Internal parsing methods
 
-        assert_eq!(parser.metadata.name, "Test Fighter");
-        assert_eq!(parser.metadata.author, "Test Author");
-        assert_eq!(parser.metadata.description, "A test character");
+    fn start_parsing(&mut self, file_path: &str) {
+        self.reset_parsing_state();
+        self.open_file(file_path);
     }
 
-    #[test]
-    fn test_parse_variables() {
-        let mut parser = CastagneParser::new();
-        // Don't enable logs in unit tests (requires Godot runtime)
-
-        parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),
-            "var Name(Str): TestName".to_string(),
-            "def MAX_HP: 150".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
-        ];
-        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
-        parser.file_paths = vec!["test.casp".to_string()];
+    /// Forget the last parse, keeping config, interner, profiler and the
+    /// capacity of every buffer so the next parse allocates less
+    pub fn reset(&mut self) {
+        self.reset_parsing_state();
+    }
 
-        parser.parse_variables(0);
+    fn reset_parsing_state(&mut self) {
+        self.reset_errors();
+        self.metadata.name.clear();
+        self.metadata.author.clear();
+        self.metadata.description.clear();
+        self.metadata.skeleton = None;
+        self.metadata.filepath.clear();
+        self.metadata.includes.clear();
+        self.metadata.no_inherit.clear();
+        self.metadata.editor_name = None;
+        self.metadata.version = None;
+        self.metadata.portrait = None;
+        self.metadata.select_icon = None;
+        self.metadata.palette_count = None;
+        self.metadata.other_fields.clear();
+        self.current_lines.clear();
+        self.line_ids.clear();
+        self.file_paths.clear();
+        self.current_file = 0;
+        self.variables.clear();
+        self.entity_variables.clear();
+        self.subentities.clear();
+        self.states.clear();
+        self.lazy_states.clear();
+        self.templates.clear();
+        self.generic_states.clear();
+        self.source_index.clear();
+        self.inherited.clear();
+        self.inheritance_conflicts.clear();
+        self.dependency_lines.clear();
+        self.specblocks.clear();
+        self.specblock_origins.clear();
+        self.specblock_defines.clear();
+        self.metrics = ParseMetrics::start();
+        self.streamed_metadata = false;
+        self.format_version = FormatVersion::LATEST;
+        self.aborting = false;
+        self.invalid_file = false;
+        self.failure = None;
+        self.suppressions = Suppressions::default();
+    }
 
-        assert_eq!(parser.variables.len(), 3);
+    pub fn end_parsing(&mut self) -> Result<ParsedCharacter, ParseFailure> {
+        self.report_inheritance_conflicts();
+        self.report_unknown_subentities();
+        self.report_shadowed_locals();
+        let character = self.finished_character();
+        self.metrics.finish();
+        character
+    }
 
-        let health = parser.variables.get("Health").unwrap();
-        assert_eq!(health.var_type, VariableType::Int);
-        assert_eq!(health.value, "100");
-        assert_eq!(health.mutability, VariableMutability::Variable);
+    fn finished_character(&self) -> Result<ParsedCharacter, ParseFailure> {
+        if self.aborting || self.invalid_file {
+            // The flags are public: they may be set without a failure
+            return Err(self.failure.clone().unwrap_or(ParseFailure::Syntax {
+                message: "The file is invalid".to_string(),
+                span: None,
+            }));
+        }
 
-        let max_hp = parser.variables.get("MAX_HP").unwrap();
-        assert_eq!(max_hp.value, "150");
-        assert_eq!(max_hp.mutability, VariableMutability::Define);
+        let mut character = ParsedCharacter {
+            metadata: self.metadata.clone(),
+            variables: self.variables.clone(),
+            entity_variables: self.entity_variables.clone(),
+            states: self.states.clone(),
+            specblocks: self.specblocks.clone(),
+            specblock_origins: self.specblock_origins.clone(),
+            subentities: self.subentities.clone(),
+            transformed_data: HashMap::new(), // TODO: Implement data transformation
+            templates: self.templates.clone(),
+            generic_states: self.generic_states.clone(),
+            source_index: self.source_index.clone(),
+            overrides: Vec::new(),
+            lazy_states: LazyStates::default(),
+        };
+        let file = self
+            .file_paths
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default();
+        character.metadata.filepath = file.to_string();
+        for state in character.states.values_mut() {
+            scope_frames(&character.variables, state);
+        }
+        character.overrides = record_overrides(&self.inherited, file, &character);
+        character.lazy_states = LazyStates {
+            states: self.lazy_states.clone(),
+            config: Arc::new(self.config.clone()),
+            interner: self.interner.clone(),
+            file: file.to_string(),
+        };
+        Ok(character)
     }
 
-    #[test]
-    fn test_parse_states() {
-        let mut parser = CastagneParser::new();
-        // Don't enable logs in unit tests (requires Godot runtime)
-
-        parser.current_lines = vec![
-            ":Idle:".to_string(),
-            "---Init:".to_string(),
-            "Set(Health, 100)".to_string(),
-            "---Action:".to_string(),
-            "CheckInput()".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
-        parser.file_paths = vec!["test.casp".to_string()];
+    /// What stopped the last parse, if anything did
+    pub fn failure(&self) -> Option<&ParseFailure> {
+        self.failure.as_ref()
+    }
 
-        parser.parse_states(0);
+    pub fn open_file(&mut self, file_path: &str) {
+        self.log(&format!("Opening file {}", file_path));
 
-        assert_eq!(parser.states.len(), 1);
+        let _file_id = self.file_paths.len();
+        self.file_paths.push(file_path.to_string());
+        if self.stopping() {
+            return;
+        }
 
-        let idle_state = parser.states.get("Idle").unwrap();
-        assert_eq!(idle_state.name, "Idle");
+        let start = Instant::now();
+        // Read the file
+        let bytes = match fs::read(file_path) {
+            Ok(bytes) => bytes,
+            // Not on disk, maybe one of the standard skeletons
+            Err(e) => match skeletons::get(file_path) {
+                Some(skeleton) => skeleton.source.as_bytes().to_vec(),
+                None => {
+                    self.fatal_error(ParseFailure::Io {
+                        path: file_path.to_string(),
+                        reason: format!("does not exist or cannot be opened: {}", e),
+                    });
+                    return;
+                }
+            },
+        };
 
-        let init_actions = idle_state.actions.get("Init").unwrap();
-        assert_eq!(init_actions.len(), 1);
-        assert_eq!(init_actions[0].instruction, "Set");
-        assert_eq!(init_actions[0].args.len(), 2);
-        assert_eq!(init_actions[0].args[0], "Health");
-        assert_eq!(init_actions[0].args[1], "100");
+        let decoded = decode_source(&bytes, self.config.lossy_decoding, 1);
+        if let Some(text) = self.decoded_text(file_path, decoded) {
+            self.load_lines(&text);
+            self.record(ParsePhase::Io, start.elapsed());
+            self.log(&format!(
+                "Successfully loaded {} lines from {}",
+                self.current_lines.len(),
+                file_path
+            ));
+            self.timed(ParsePhase::Preprocess, Self::preprocess);
+        }
+    }
 
-        let action_actions = idle_state.actions.get("Action").unwrap();
-        assert_eq!(action_actions.len(), 1);
-        assert_eq!(action_actions[0].instruction, "CheckInput");
-        assert_eq!(action_actions[0].args.len(), 0);
+    /// Text of a decoded source, reporting a warning or an error about its encoding
+    fn decoded_text(
+        &mut self,
+        file_path: &str,
+        decoded: Result<(String, Option<String>), String>,
+    ) -> Option<String> {
+        match decoded {
+            Ok((text, warning)) => {
+                if let Some(message) = warning {
+                    let message = format!("{}: {}", file_path, message);
+                    self.log(&message);
+                    self.push_diagnostic(Diagnostic::new(
+                        INVALID_ENCODING,
+                        Severity::Warning,
+                        message,
+                    ));
+                }
+                Some(text)
+            }
+            Err(reason) => {
+                let failure = ParseFailure::Encoding {
+                    path: file_path.to_string(),
+                    reason,
+                };
+                self.diagnostics.push(Diagnostic::new(
+                    INVALID_ENCODING,
+                    Severity::Error,
+                    failure.to_string(),
+                ));
+                self.fatal_error(failure);
+                None
+            }
+        }
     }
 
-    #[test]
-    fn test_parse_action_line() {
-        let parser = CastagneParser::new();
+    /// Load lines from an in-memory buffer instead of a file
+    pub fn load_source(&mut self, file_path: &str, source: &str) {
+        self.file_paths.push(file_path.to_string());
+        if self.stopping() {
+            return;
+        }
+        self.timed(ParsePhase::Io, |parser| parser.load_lines(source));
+        self.timed(ParsePhase::Preprocess, Self::preprocess);
+    }
 
-        // Test with arguments
-        let action = parser.parse_action_line("Set(Health, 100)", 1).unwrap();
-        assert_eq!(action.instruction, "Set");
-        assert_eq!(action.args.len(), 2);
-        assert_eq!(action.args[0], "Health");
-        assert_eq!(action.args[1], "100");
+    /// Split a source into lines, tolerating a BOM and any newline style
+    fn load_lines(&mut self, source: &str) {
+        let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+        let normalized = source.replace("\r\n", "\n").replace('\r', "\n");
+        let first = self.current_lines.len();
+        for (line_num, line) in normalized.lines().enumerate() {
+            if !self.within_line_limits(line_num + 1, line) {
+                break;
+            }
+            self.current_lines.push(line.to_string());
+            self.line_ids.push(line_num + 1); // 1-indexed for user display
+            self.suppressions.add_line(line_num + 1, line);
+        }
+        self.metrics.count_lines(&self.current_lines[first..]);
+    }
 
-        // Test without arguments
-        let action2 = parser.parse_action_line("DoSomething()", 1).unwrap();
-        assert_eq!(action2.instruction, "DoSomething");
-        assert_eq!(action2.args.len(), 0);
+    /// Whether line `line_number` of a file keeps within the length and
+    /// line count limits, stopping the parse otherwise
+    fn within_line_limits(&mut self, line_number: usize, line: &str) -> bool {
+        let limits = self.config.limits;
+        if line_number > limits.max_total_lines {
+            self.limit_exceeded(Limit::TotalLines, Some(line_number));
+        } else if line.len() > limits.max_line_length {
+            self.limit_exceeded(Limit::LineLength, Some(line_number));
+        }
+        !self.aborting
+    }
 
-        // Test without parentheses
-        let action3 = parser.parse_action_line("SimpleInstruction", 1).unwrap();
-        assert_eq!(action3.instruction, "SimpleInstruction");
-        assert_eq!(action3.args.len(), 0);
+    /// Line-level rewrites done before any parsing
+    fn preprocess(&mut self) {
+        for (index, message) in front_matter::rewrite(&mut self.current_lines) {
+            self.error(&format!("{} (line {})", message, self.line_id(index)));
+        }
+        self.strip_block_comments();
+        for index in string_literal::join_multiline(&mut self.current_lines) {
+            self.error(&format!(
+                "Unterminated multi-line string (line {})",
+                self.line_id(index)
+            ));
+        }
+        self.resolve_conditions();
     }
 
-    #[test]
-    fn test_parse_arguments() {
-        let parser = CastagneParser::new();
+    /// Remove `/* ... */` comments and join lines ending with `\\`
+    ///
+    /// Lines swallowed by a comment or joined into the line above become
+    /// blank so line numbers stay put.
+    fn strip_block_comments(&mut self) {
+        let mut scanner = LineScanner::default();
+        let mut comment_line = 0;
+        let mut joined_into: Option<usize> = None;
+        for i in 0..self.current_lines.len() {
+            let (code, continues) = scanner.scan(&self.current_lines[i]);
+            if scanner.opened_comment {
+                comment_line = self.line_id(i);
+            }
+            match joined_into {
+                Some(target) => {
+                    let line = &mut self.current_lines[target];
+                    if !line.is_empty() && !code.trim().is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(code.trim_start());
+                    self.current_lines[i].clear();
+                }
+                None => self.current_lines[i] = code,
+            }
+            joined_into = if continues {
+                joined_into.or(Some(i))
+            } else {
+                None
+            };
+        }
 
-        // Simple arguments
-        let args = parser.parse_arguments("a, b, c");
-        assert_eq!(args.len(), 3);
-        assert_eq!(args[0], "a");
-        assert_eq!(args[1], "b");
-        assert_eq!(args[2], "c");
+        if scanner.in_block_comment {
+            self.error(&format!(
+                "Unterminated block comment (line {})",
+                comment_line
+            ));
+        }
+    }
 
-        // Nested function calls
-        let args2 = parser.parse_arguments("Health, Add(10, 5), Position");
-        assert_eq!(args2.len(), 3);
-        assert_eq!(args2[0], "Health");
-        assert_eq!(args2[1], "Add(10, 5)");
-        assert_eq!(args2[2], "Position");
+    /// Apply `?Flag` / `?!Flag` line prefixes against the configured flags
+    ///
+    /// A line whose condition holds loses its prefix, any other becomes
+    /// blank so line numbers stay put. A conditional block header takes
+    /// the whole block with it, up to the next header.
+    fn resolve_conditions(&mut self) {
+        let mut dropping_block = false;
+        for line in &mut self.current_lines {
+            let trimmed = line.trim_start();
+            let Some(condition) = trimmed.strip_prefix('?') else {
+                if is_block_header(trimmed) {
+                    dropping_block = false;
+                }
+                if dropping_block {
+                    line.clear();
+                }
+                continue;
+            };
 
-        // String arguments with commas
-        let args3 = parser.parse_arguments(r#""Hello, World", Test"#);
-        assert_eq!(args3.len(), 2);
-        assert_eq!(args3[0], r#""Hello, World""#);
-        assert_eq!(args3[1], "Test");
+            let (flag, rest) = condition
+                .split_once(char::is_whitespace)
+                .unwrap_or((condition, ""));
+            let enabled = match flag.strip_prefix('!') {
+                Some(flag) => !self.config.flags.contains(flag),
+                None => self.config.flags.contains(flag),
+            };
+            let rest = rest.trim_start().to_string();
 
-        // Complex nested calls - note this is just the arguments part, not the full call
-        let args4 =
-            parser.parse_arguments("Greater(Health, 50), Set(Color, Red), Set(Color, Blue)");
-        assert_eq!(args4.len(), 3);
-        assert_eq!(args4[0], "Greater(Health, 50)");
-        assert_eq!(args4[1], "Set(Color, Red)");
-        assert_eq!(args4[2], "Set(Color, Blue)");
+            if is_block_header(&rest) {
+                dropping_block = !enabled;
+            }
+            if enabled && !dropping_block {
+                *line = rest;
+            } else {
+                line.clear();
+            }
+        }
     }
 
-    #[test]
-    fn test_parse_complex_actions() {
-        let parser = CastagneParser::new();
+    pub fn parse_full_file(&mut self) {
+        if self.stopping() {
+            return;
+        }
 
-        // Nested function call as argument
-        let action = parser
-            .parse_action_line("Set(Health, Add(100, 50))", 1)
-            .unwrap();
-        assert_eq!(action.instruction, "Set");
-        assert_eq!(action.args.len(), 2);
-        assert_eq!(action.args[0], "Health");
-        assert_eq!(action.args[1], "Add(100, 50)");
+        self.log(">>> Starting to parse the full file.");
 
-        // String with special characters
-        let action2 = parser
-            .parse_action_line(r#"Log("Player health: ", Health)"#, 1)
-            .unwrap();
-        assert_eq!(action2.instruction, "Log");
-        assert_eq!(action2.args.len(), 2);
-        assert_eq!(action2.args[0], r#""Player health: ""#);
-        assert_eq!(action2.args[1], "Health");
-    }
+        // Step 1: Parse metadata, then bring older syntax up to date
+        self.timed(ParsePhase::Metadata, |parser| {
+            parser.parse_metadata(0);
+            parser.apply_format_version();
+        });
+        if self.stopping() {
+            return;
+        }
 
-    #[test]
-    fn test_full_file_parse() {
-        let mut parser = CastagneParser::new();
+        // Step 2: Load the skeleton, then splice included files over it
+        self.timed(ParsePhase::Inheritance, Self::load_inherited_files);
+        if self.stopping() {
+            return;
+        }
 
-        // Test with the test_character.casp file if it exists
-        // This test will be skipped if the file doesn't exist
-        if std::path::Path::new("test_character.casp").exists() {
-            let result = parser.create_full_character("test_character.casp");
+        // Step 3: Split the file into blocks once, then parse specblocks
+        let blocks = self.timed(ParsePhase::Specblocks, |parser| {
+            let blocks = segment_blocks(&parser.current_lines);
+            parser.parse_specblocks_in(&blocks);
+            blocks
+        });
+
+        // Step 4: Parse variables, then evaluate expression defaults
+        self.timed(ParsePhase::Variables, |parser| {
+            parser.parse_variables_in(&blocks);
+            parser.evaluate_defaults();
+        });
+        if self.stopping() {
+            return;
+        }
 
-            assert!(result.is_some(), "Failed to parse test_character.casp");
-            let character = result.unwrap();
+        // Step 5: Parse templates, then the states using them
+        self.timed(ParsePhase::Templates, |parser| {
+            parser.parse_templates_in(&blocks)
+        });
+        self.timed(ParsePhase::States, |parser| parser.parse_states_in(&blocks));
 
-            // Check metadata
-            assert_eq!(character.metadata.name, "Test Character");
-            assert_eq!(character.metadata.author, "Parser Test");
+        // TODO: Step 6: Optimize
+        self.log(">>> Parsing complete!");
+    }
 
-            // Check variables
-            assert!(character.variables.contains_key("Health"));
-            assert!(character.variables.contains_key("MoveSpeed"));
-            assert!(character.variables.contains_key("MAX_HEALTH"));
+    /// Read the `FormatVersion:` field and rewrite older syntax in the current one
+    fn apply_format_version(&mut self) {
+        let Some(value) = self.metadata.other_fields.get(FORMAT_VERSION_FIELD) else {
+            return;
+        };
+        match FormatVersion::parse(value) {
+            Ok(version) => self.format_version = version,
+            Err(message) => {
+                self.fatal_error(ParseFailure::Syntax {
+                    message,
+                    span: None,
+                });
+                return;
+            }
+        }
+        self.upgrade_current_lines();
+    }
 
-            // Check states
-            assert!(character.states.contains_key("Idle"));
-            let idle_state = character.states.get("Idle").unwrap();
-            assert!(idle_state.actions.contains_key("Init"));
-            assert!(idle_state.actions.contains_key("Action"));
+    fn upgrade_current_lines(&mut self) {
+        if self.format_version < FormatVersion::LATEST {
+            upgrade_lines(self.format_version, &mut self.current_lines);
         }
     }
 
-    #[test]
-    fn test_parse_specblocks() {
-        let mut parser = CastagneParser::new();
+    /// Config for parsing a skeleton or include, whose states are merged
+    /// so can't be left for later
+    fn dependency_config(&self) -> ParserConfig {
+        ParserConfig {
+            lazy_states: false,
+            ..self.config.clone()
+        }
+    }
 
-        parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Test".to_string(),
-            "".to_string(),
-            ":Attacks:".to_string(),
-            "Damage: 10".to_string(),
-            "Range: 5".to_string(),
-            "Knockback: 3".to_string(),
-            "".to_string(),
-            ":Config:".to_string(),
-            "MaxSpeed: 100".to_string(),
-            "JumpHeight: 50".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),
+    fn load_inherited_files(&mut self) {
+        // If metadata has skeleton, load and parse parent file first
+        if let Some(skeleton_path) = self.metadata.skeleton.clone() {
+            self.log(&format!("Loading skeleton file: {}", skeleton_path));
+            self.load_skeleton(&skeleton_path);
+            if self.aborting {
+                return;
+            }
+        }
+
+        // Included files override the skeleton
+        for include_path in self.metadata.includes.clone() {
+            self.log(&format!("Loading included file: {}", include_path));
+            self.load_include(&include_path);
+            if self.aborting {
+                return;
+            }
+        }
+
+        self.drop_no_inherit();
+    }
+
+    /// Remove the inherited states listed in `NoInherit:`
+    fn drop_no_inherit(&mut self) {
+        if self.metadata.no_inherit.is_empty() {
+            return;
+        }
+        let index = self
+            .current_lines
+            .iter()
+            .position(|line| line.trim_start().starts_with("NoInherit"))
+            .unwrap_or_default();
+        for name in self.metadata.no_inherit.clone() {
+            if self.states.remove(name.as_str()).is_some() {
+                self.log(&format!("Dropped inherited state {}", name));
+                continue;
+            }
+            let column = self.current_lines.get(index).and_then(|line| {
+                let byte = line.find(name.as_str())?;
+                Some(char_column(line, byte))
+            });
+            self.diagnostic(
+                UNMATCHED_NO_INHERIT,
+                &format!("NoInherit: no parent defines a state {}", name),
+                self.line_id(index),
+                column.unwrap_or_default(),
+            );
+        }
+    }
+
+    /// Load an included file and splice its data in
+    ///
+    /// Unlike a skeleton, an include overrides what is already there:
+    /// the file's own blocks beat later includes, which beat earlier
+    /// includes, which beat the skeleton. Metadata is never taken from
+    /// an include. `ConflictResolution::FirstWins` reverses the order
+    /// between includes.
+    fn load_include(&mut self, include_path: &str) {
+        let current_path = self.file_paths.first().cloned().unwrap_or_default();
+        let resolved = self.config.resolve_skeleton(include_path);
+        if current_path == resolved || self.include_chain.contains(&resolved) {
+            let mut chain = self.include_chain.clone();
+            chain.push(current_path);
+            chain.push(resolved);
+            self.fatal_error(ParseFailure::IncludeCycle { chain });
+            return;
+        }
+
+        let mut include_parser = CastagneParser::with_config(self.dependency_config());
+        include_parser.logs_active = self.logs_active;
+        include_parser.interner = self.interner.clone();
+        include_parser.include_chain = self.include_chain.clone();
+        include_parser.include_chain.push(current_path);
+
+        let first_wins = self.config.conflict_resolution == ConflictResolution::FirstWins;
+        match self.parse_dependency(include_path, &resolved, &mut include_parser) {
+            Ok(included) => {
+                self.keep_dependency_lines(&include_parser);
+                for (block_name, data) in &included.specblocks {
+                    self.specblocks
+                        .entry(block_name.clone())
+                        .or_default()
+                        .extend(data.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                for (block_name, origins) in &included.specblock_origins {
+                    self.specblock_origins
+                        .entry(block_name.clone())
+                        .or_default()
+                        .extend(origins.iter().map(|(k, o)| (k.clone(), o.clone())));
+                }
+                for (name, var) in &included.variables {
+                    let earlier = self.earlier_include(|layer| {
+                        let earlier = layer.variables.get(name)?;
+                        (earlier.value != var.value).then(|| earlier.origin.clone())
+                    });
+                    if let Some(first) = earlier {
+                        let conflict = InheritanceConflict {
+                            kind: "Variable",
+                            name: name.to_string(),
+                            first,
+                            second: (include_path.to_string(), var.origin.clone()),
+                        };
+                        self.inheritance_conflicts.push(conflict);
+                        if first_wins {
+                            continue;
+                        }
+                    }
+                    self.variables.insert(name.clone(), var.clone());
+                }
+                for (entity, metadata) in &included.subentities {
+                    self.subentities.insert(entity.clone(), metadata.clone());
+                }
+                for (entity, variables) in &included.entity_variables {
+                    self.entity_variables
+                        .entry(entity.clone())
+                        .or_default()
+                        .extend(
+                            variables
+                                .iter()
+                                .map(|(name, var)| (name.clone(), var.clone())),
+                        );
+                }
+                for (name, state) in &included.states {
+                    let earlier = self.earlier_include(|layer| {
+                        let earlier = layer.states.get(name)?;
+                        (earlier.origin != state.origin).then(|| earlier.origin.clone())
+                    });
+                    if let Some(first) = earlier {
+                        let conflict = InheritanceConflict {
+                            kind: "State",
+                            name: name.to_string(),
+                            first,
+                            second: (include_path.to_string(), state.origin.clone()),
+                        };
+                        self.inheritance_conflicts.push(conflict);
+                        if first_wins {
+                            continue;
+                        }
+                    }
+                    self.states.insert(name.clone(), state.clone());
+                }
+                self.templates.extend(
+                    included
+                        .templates
+                        .iter()
+                        .map(|(name, template)| (name.clone(), template.clone())),
+                );
+                self.generic_states.extend(
+                    included
+                        .generic_states
+                        .iter()
+                        .map(|(name, generic)| (name.clone(), generic.clone())),
+                );
+                self.merge_dependency_diagnostics(
+                    "Include",
+                    include_path,
+                    &mut include_parser,
+                    None,
+                );
+                self.inherited.push((include_path.to_string(), included));
+                self.log(&format!("Included file merged: {}", include_path));
+            }
+            Err(cause) => {
+                for error in std::mem::take(&mut include_parser.errors) {
+                    self.errors.push(format!("{}: {}", include_path, error));
+                }
+                self.merge_dependency_diagnostics(
+                    "Include",
+                    include_path,
+                    &mut include_parser,
+                    Some(&cause),
+                );
+                self.fatal_error(ParseFailure::Include {
+                    path: include_path.to_string(),
+                    cause: Box::new(cause),
+                });
+            }
+        }
+    }
+
+    /// Path and origin of the last include merged so far for which `get`
+    /// finds a definition
+    fn earlier_include(
+        &self,
+        get: impl Fn(&ParsedCharacter) -> Option<SourceRef>,
+    ) -> Option<(String, SourceRef)> {
+        let skeleton = self.metadata.skeleton.is_some() as usize;
+        let includes = self.inherited.get(skeleton..).unwrap_or_default();
+        includes
+            .iter()
+            .rev()
+            .find_map(|(path, layer)| Some((path.clone(), get(layer)?)))
+    }
+
+    /// Warn about the include conflicts this file doesn't settle by
+    /// defining the symbol itself
+    fn report_inheritance_conflicts(&mut self) {
+        let file = self.file_paths.first().cloned().unwrap_or_default();
+        for conflict in std::mem::take(&mut self.inheritance_conflicts) {
+            let resolved = match conflict.kind {
+                "State" => self.source_index.state_range(&conflict.name).is_some(),
+                _ => self
+                    .variables
+                    .get(conflict.name.as_str())
+                    .is_some_and(|var| var.origin.file == file),
+            };
+            if resolved {
+                continue;
+            }
+            let (first, second) = (&conflict.first, &conflict.second);
+            let kept = match self.config.conflict_resolution {
+                ConflictResolution::LastWins => &second.0,
+                ConflictResolution::FirstWins => &first.0,
+            };
+            let message = format!(
+                "{} {} is defined differently by {} and {}, using {}",
+                conflict.kind, conflict.name, first.0, second.0, kept
+            );
+            self.log(&message);
+            self.push_diagnostic(
+                Diagnostic::new(INHERITANCE_CONFLICT, Severity::Warning, message)
+                    .with_note(format!("{} defines it at {}", first.0, first.1))
+                    .with_note(format!("{} defines it at {}", second.0, second.1))
+                    .with_note(format!("Define {} in {} to choose", conflict.name, file)),
+            );
+        }
+    }
+
+    /// Warn about the spawns of this file naming neither a subentity nor a
+    /// helper state; names of variables are left to the engine
+    /// Warn about temporaries of the file's states named like a variable of
+    /// the character or an earlier temporary of their state
+    fn report_shadowed_locals(&mut self) {
+        let file = self.file_paths.first().cloned().unwrap_or_default();
+        let mut shadowed: Vec<(usize, String, String)> = Vec::new();
+        for state in self.states.values() {
+            if state.origin.file != file {
+                continue;
+            }
+            for (position, local) in state.locals.iter().enumerate() {
+                let earlier = state.locals[..position]
+                    .iter()
+                    .find(|earlier| earlier.name == local.name);
+                let message = match (earlier, self.variables.get(&local.name)) {
+                    (Some(earlier), _) => format!(
+                        "Local {} of state {} shadows the one of line {}",
+                        local.name, state.name, earlier.line_number
+                    ),
+                    (None, Some(variable)) => format!(
+                        "Local {} of state {} shadows the {} of the character",
+                        local.name,
+                        state.name,
+                        match variable.mutability {
+                            VariableMutability::Define => "define",
+                            _ => "variable",
+                        }
+                    ),
+                    (None, None) => continue,
+                };
+                shadowed.push((local.line_number, local.name.to_string(), message));
+            }
+        }
+        shadowed.sort_unstable();
+
+        for (line, name, message) in shadowed {
+            let span = Span {
+                file: Some(file.clone()),
+                ..Span::line(line)
+            };
+            self.push_diagnostic(
+                Diagnostic::new(SHADOWED_VARIABLE, Severity::Warning, message)
+                    .with_span(span)
+                    .with_note(format!("Rename {} to keep both reachable", name)),
+            );
+        }
+    }
+
+    fn report_unknown_subentities(&mut self) {
+        let file = self.file_paths.first().cloned().unwrap_or_default();
+        let mut unknown: Vec<(usize, String)> = Vec::new();
+        for state in self.states.values() {
+            if state.origin.file != file {
+                continue;
+            }
+            for action in state.actions.values().flatten() {
+                let Some(name) = spawned_name(action) else {
+                    continue;
+                };
+                let helper = self
+                    .states
+                    .get(name)
+                    .is_some_and(|state| state.state_type == StateType::Helper);
+                if !helper
+                    && !self.subentities.contains_key(name)
+                    && !self.variables.contains_key(name)
+                {
+                    unknown.push((action.line_number, name.to_string()));
+                }
+            }
+        }
+        unknown.sort_unstable();
+
+        for (line, name) in unknown {
+            let message = format!("{} is neither a subentity nor a helper state", name);
+            self.log(&message);
+            let span = Span {
+                file: Some(file.clone()),
+                ..Span::line(line)
+            };
+            self.push_diagnostic(
+                Diagnostic::new(UNKNOWN_SUBENTITY, Severity::Warning, message)
+                    .with_span(span)
+                    .with_note(format!(
+                        "Declare it with a :{}{}: block or a :{}(Helper): state",
+                        name, SUBENTITY_SUFFIX, name
+                    )),
+            );
+        }
+    }
+
+    /// A parsed skeleton or include: given with `with_parent`, cached, or
+    /// parsed with `sub_parser` from the file `path` resolved to
+    fn parse_dependency(
+        &mut self,
+        path: &str,
+        resolved: &str,
+        sub_parser: &mut CastagneParser,
+    ) -> Result<Arc<ParsedCharacter>, ParseFailure> {
+        if let Some(parent) = self.parents.get(path) {
+            return Ok(Arc::clone(parent));
+        }
+        let path = resolved;
+        let Some(cache) = self.skeleton_cache.clone() else {
+            return sub_parser.create_full_character(path).map(Arc::new);
+        };
+
+        let key = SkeletonCache::key(path);
+        if let Some(cached) = key.as_ref().and_then(|key| cache.get(key)) {
+            sub_parser.diagnostics = cached.diagnostics.clone();
+            return Ok(Arc::clone(&cached.character));
+        }
+        sub_parser.skeleton_cache = Some(cache.clone());
+        let character = Arc::new(sub_parser.create_full_character(path)?);
+        if let Some(key) = key {
+            let cached = CachedFile {
+                character: Arc::clone(&character),
+                diagnostics: sub_parser.diagnostics.clone(),
+            };
+            cache.insert(key, cached);
+        }
+        Ok(character)
+    }
+
+    /// Keep the lines a skeleton or include parser read, and those of its
+    /// own dependencies, with `keep_source_lines`
+    fn keep_dependency_lines(&mut self, sub_parser: &CastagneParser) {
+        if self.config.keep_source_lines {
+            self.dependency_lines.extend(sub_parser.source_map().files);
+        }
+    }
+
+    /// Report the diagnostics of a skeleton or include, and what stopped its
+    /// parse if it failed, noting the `key:` line of this file that loaded it
+    ///
+    /// Each file of a chain adds its note, so the notes read like an include
+    /// trace from the file the diagnostic is about.
+    fn merge_dependency_diagnostics(
+        &mut self,
+        key: &str,
+        path: &str,
+        sub_parser: &mut CastagneParser,
+        failure: Option<&ParseFailure>,
+    ) {
+        let mut diagnostics = std::mem::take(&mut sub_parser.diagnostics);
+        // A failed dependency of the dependency already reported its cause
+        let own_failure = failure.filter(|failure| {
+            !matches!(
+                failure,
+                ParseFailure::Include { .. }
+                    | ParseFailure::Skeleton { .. }
+                    | ParseFailure::Cancelled
+            )
+        });
+        if let Some(failure) = own_failure {
+            if !diagnostics.iter().any(Diagnostic::is_error) {
+                let mut diagnostic =
+                    Diagnostic::new(PARSE_FAILURE, Severity::Error, failure.to_string());
+                diagnostic.span = failure.span().cloned();
+                diagnostics.push(diagnostic);
+            }
+        }
+
+        let file = self.file_paths.first().cloned().unwrap_or_default();
+        let line = self.current_lines.iter().position(|line| {
+            let line = line.trim_start();
+            line.starts_with(key) && line.contains(path)
+        });
+        let location = match line {
+            Some(index) => format!("{}:{}", file, self.line_id(index)),
+            None => file,
+        };
+        let note = match key {
+            "Skeleton" => format!("in the skeleton of {}", location),
+            _ => format!("in a file included from {}", location),
+        };
+        for mut diagnostic in diagnostics {
+            if let Some(span) = &mut diagnostic.span {
+                span.file.get_or_insert_with(|| path.to_string());
+            }
+            diagnostic.notes.push(note.clone());
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn load_skeleton(&mut self, skeleton_path: &str) {
+        let current_path = self.file_paths.first().cloned().unwrap_or_default();
+        let resolved = self.config.resolve_skeleton(skeleton_path);
+        if current_path == resolved || self.include_chain.contains(&resolved) {
+            let mut chain = self.include_chain.clone();
+            chain.push(current_path);
+            chain.push(resolved);
+            self.fatal_error(ParseFailure::SkeletonCycle { chain });
+            return;
+        }
+        if self.skeleton_depth >= self.config.limits.max_skeleton_chain {
+            self.limit_exceeded(Limit::SkeletonChain, None);
+            return;
+        }
+
+        // Save current parsing state
+        let current_lines = self.current_lines.clone();
+        let current_line_ids = self.line_ids.clone();
+        let current_file_paths = self.file_paths.clone();
+
+        // Store child metadata separately, it only takes what it lacks
+        let mut child_metadata = self.metadata.clone();
+
+        // Parse the skeleton file
+        let mut skeleton_parser = CastagneParser::with_config(self.dependency_config());
+        skeleton_parser.logs_active = self.logs_active;
+        skeleton_parser.interner = self.interner.clone();
+        skeleton_parser.include_chain = self.include_chain.clone();
+        skeleton_parser.include_chain.push(current_path);
+        skeleton_parser.skeleton_depth = self.skeleton_depth + 1;
+
+        match self.parse_dependency(skeleton_path, &resolved, &mut skeleton_parser) {
+            Ok(skeleton_character) => {
+                self.log(&format!("Successfully loaded skeleton: {}", skeleton_path));
+
+                // Merge skeleton data into current parser
+                // Parent data is added first, child can override
+
+                // The skeleton may be shared, only what the child lacks is copied
+
+                // Merge specblocks (child overrides parent on a per-key basis)
+                for (block_name, parent_data) in &skeleton_character.specblocks {
+                    let child_block = self.specblocks.entry(block_name.clone()).or_default();
+                    // Insert parent values that don't exist in child
+                    for (key, value) in parent_data {
+                        if !child_block.contains_key(key) {
+                            child_block.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+                for (block_name, parent_origins) in &skeleton_character.specblock_origins {
+                    let child_origins = self
+                        .specblock_origins
+                        .entry(block_name.clone())
+                        .or_default();
+                    for (key, origin) in parent_origins {
+                        child_origins
+                            .entry(key.clone())
+                            .or_insert_with(|| origin.clone());
+                    }
+                }
+
+                // Merge variables (child overrides parent)
+                for (name, var) in &skeleton_character.variables {
+                    if !self.variables.contains_key(name) {
+                        self.variables.insert(name.clone(), var.clone());
+                    }
+                }
+                for (entity, metadata) in &skeleton_character.subentities {
+                    self.subentities
+                        .entry(entity.clone())
+                        .or_insert_with(|| metadata.clone());
+                }
+                for (entity, variables) in &skeleton_character.entity_variables {
+                    let child_variables = self.entity_variables.entry(entity.clone()).or_default();
+                    for (name, var) in variables {
+                        if !child_variables.contains_key(name) {
+                            child_variables.insert(name.clone(), var.clone());
+                        }
+                    }
+                }
+
+                // Merge states (child overrides parent)
+                for (name, state) in &skeleton_character.states {
+                    if !self.states.contains_key(name) {
+                        self.states.insert(name.clone(), state.clone());
+                    }
+                }
+
+                for (name, template) in &skeleton_character.templates {
+                    if !self.templates.contains_key(name) {
+                        self.templates.insert(name.clone(), template.clone());
+                    }
+                }
+                for (name, generic) in &skeleton_character.generic_states {
+                    self.generic_states
+                        .entry(name.clone())
+                        .or_insert_with(|| generic.clone());
+                }
+                self.keep_dependency_lines(&skeleton_parser);
+                self.merge_dependency_diagnostics(
+                    "Skeleton",
+                    skeleton_path,
+                    &mut skeleton_parser,
+                    None,
+                );
+                child_metadata.inherit_from(&skeleton_character.metadata);
+                self.inherited
+                    .push((skeleton_path.to_string(), skeleton_character));
+
+                self.log("Skeleton data merged successfully");
+            }
+            Err(cause) => {
+                self.merge_dependency_diagnostics(
+                    "Skeleton",
+                    skeleton_path,
+                    &mut skeleton_parser,
+                    Some(&cause),
+                );
+                self.fatal_error(ParseFailure::Skeleton {
+                    path: skeleton_path.to_string(),
+                    cause: Box::new(cause),
+                });
+                return;
+            }
+        }
+
+        // Restore current parsing state
+        self.current_lines = current_lines;
+        self.line_ids = current_line_ids;
+        self.file_paths = current_file_paths;
+        self.metadata = child_metadata;
+    }
+
+    fn parse_metadata(&mut self, _file_id: usize) -> &CharacterMetadata {
+        self.log("Parsing metadata...");
+
+        // Only the first :Character: block holds metadata
+        let blocks = segment_blocks(&self.current_lines);
+        if let Some(block) = blocks.iter().find(|block| block.header == "Character") {
+            for i in block.body() {
+                let line = self.current_lines[i].trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                // Strip inline comments and parse metadata fields
+                let cleaned_line = self.strip_inline_comment(line);
+                let cleaned = cleaned_line.trim();
+                let Some((written_key, raw)) = cleaned.split_once(':') else {
+                    continue;
+                };
+                let written_key = written_key.trim();
+                let current = self.legacy_name(LegacyKind::MetadataKey, written_key, i);
+                let key = current.as_deref().unwrap_or(written_key);
+                let raw = raw.trim();
+                let value = match string_literal::decode(raw) {
+                    Some(Ok(decoded)) => decoded,
+                    Some(Err(message)) => {
+                        let line_number = self.line_id(i);
+                        self.error(&format!("{} (line {})", message, line_number));
+                        raw.to_string()
+                    }
+                    None => raw.to_string(),
+                };
+
+                if let Err(message) = self.metadata.set_field(key, value) {
+                    self.invalid_metadata(&message, i);
+                }
+            }
+        }
+
+        self.log(&format!("Parsed metadata: Name={}", self.metadata.name));
+        &self.metadata
+    }
+
+    #[cfg(test)]
+    fn parse_specblocks(&mut self, _file_id: usize) -> HashMap<String, String> {
+        let blocks = segment_blocks(&self.current_lines);
+        self.parse_specblocks_in(&blocks);
+        HashMap::new() // Return empty for compatibility with existing code
+    }
+
+    fn parse_specblocks_in(&mut self, blocks: &[Block]) {
+        self.log("Parsing specblocks...");
+
+        for block in blocks {
+            let block_name = block.name.as_str();
+            let mut i = block.start;
+            if let Some(entity) = subentity_header(block_name) {
+                self.parse_subentity(entity.to_string(), &mut i);
+            }
+            // Check if this is a specblock (not Character, Variables, or a state)
+            // Specblocks typically have specific patterns, but for now we'll identify them
+            // by checking if the content is key-value pairs (not phase markers or actions)
+            else if block_name != "Character"
+                && VariablesBlock::from_header(block_name).is_none()
+                && !block_name.starts_with(TEMPLATE_PREFIX)
+                && self.is_specblock(block_name, block.start + 1)
+            {
+                self.parse_specblock(block_name.to_string(), &mut i);
+            }
+        }
+
+        self.log(&format!("Parsed {} specblocks", self.specblocks.len()));
+    }
+
+    /// Metadata of a subentity, set over the one it inherited if any
+    fn parse_subentity(&mut self, entity: String, i: &mut usize) {
+        self.log(&format!("Parsing subentity: {}", entity));
+
+        let mut metadata = self.subentities.remove(&entity).unwrap_or_default();
+        *i += 1;
+        while *i < self.current_lines.len() {
+            let line = self.current_lines[*i].trim();
+            if is_block_header(line) {
+                break;
+            }
+            if !line.is_empty() && !line.starts_with('#') {
+                let cleaned = self.strip_inline_comment(line);
+                if let Some((key, value)) = cleaned.split_once(':') {
+                    if let Err(message) = metadata.set_field(key.trim(), value.trim().to_string()) {
+                        self.invalid_metadata(&message, *i);
+                    }
+                }
+            }
+            *i += 1;
+        }
+        self.subentities.insert(entity, metadata);
+        *i -= 1;
+    }
+
+    pub(crate) fn is_specblock(&self, _block_name: &str, start_idx: usize) -> bool {
+        let lines = self.current_lines.get(start_idx..).unwrap_or_default();
+        looks_like_specblock(lines.iter().map(String::as_str))
+    }
+
+    fn parse_specblock(&mut self, block_name: String, i: &mut usize) {
+        self.log(&format!("Parsing specblock: {}", block_name));
+
+        let mut specblock_data = HashMap::new();
+        let mut origins = HashMap::new();
+        *i += 1; // Move past the block name line
+
+        while *i < self.current_lines.len() {
+            let line = self.current_lines[*i].trim();
+
+            // Check if we've reached another block
+            if is_block_header(line) {
+                break;
+            }
+
+            // Parse key-value pairs (strip inline comments first)
+            if !line.is_empty() && !line.starts_with('#') {
+                let cleaned_line = self.strip_inline_comment(line);
+                let cleaned = cleaned_line.trim();
+
+                if !cleaned.is_empty() {
+                    if let Some((key, value)) = cleaned.split_once(':') {
+                        let (key, value) = (key.trim().to_string(), value.trim().to_string());
+                        origins.insert(key.clone(), self.source_ref(*i));
+                        specblock_data.insert(key, value);
+                    }
+                }
+            }
+
+            *i += 1;
+        }
+
+        if !specblock_data.is_empty() {
+            // Merge with existing specblock (if from parent) instead of replacing
+            let existing_block = self.specblocks.entry(block_name.clone()).or_default();
+            for (key, value) in specblock_data {
+                // Child values override parent values
+                existing_block.insert(key, value);
+            }
+            self.specblock_origins
+                .entry(block_name)
+                .or_default()
+                .extend(origins);
+        }
+        *i -= 1; // Back up one so the outer loop doesn't skip a line
+    }
+
+    #[cfg(test)]
+    fn parse_variables(&mut self, _file_id: usize) {
+        let blocks = segment_blocks(&self.current_lines);
+        self.parse_variables_in(&blocks);
+    }
+
+    fn parse_variables_in(&mut self, blocks: &[Block]) {
+        self.log("Parsing variables...");
+
+        // Variables blocks can be anywhere and repeated, one per section
+        for block in blocks {
+            let Some(scope) = VariablesBlock::from_header(&block.header) else {
+                continue;
+            };
+            for i in block.body() {
+                let line = self.current_lines[i].trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let cleaned_line = self.strip_inline_comment(line);
+                let cleaned = cleaned_line.trim();
+                if cleaned.is_empty() {
+                    continue;
+                }
+
+                if let Some(mut var) = self.parse_variable_line(cleaned, self.line_id(i)) {
+                    if !self.mark_internal(&mut var, scope.entity.as_deref(), cleaned, i) {
+                        continue;
+                    }
+                    self.normalize_integer(&mut var, i);
+                    var.section = scope.section.clone();
+                    var.origin = self.source_ref(i);
+                    let variables = match &scope.entity {
+                        Some(entity) => self.entity_variables.entry(entity.clone()).or_default(),
+                        None => &mut self.variables,
+                    };
+                    variables.insert(var.name.clone(), var);
+                }
+            }
+        }
+
+        self.log(&format!("Parsed {} variables", self.variables.len()));
+    }
+
+    /// Check a variable declared on line `index` against the internal one
+    /// of the same name it would replace, false if it must be dropped
+    ///
+    /// Like in Castagne, only `internal` can redeclare an internal
+    /// variable, and `internal Name` without a type marks a variable
+    /// declared before, taking its type and default. Without one, it names
+    /// a variable of the engine modules, whose type the parser can't know.
+    fn mark_internal(
+        &mut self,
+        var: &mut ParsedVariable,
+        entity: Option<&str>,
+        line: &str,
+        index: usize,
+    ) -> bool {
+        let variables = match entity {
+            Some(entity) => self.entity_variables.get(entity),
+            None => Some(&self.variables),
+        };
+        let declared = variables.and_then(|variables| variables.get(&var.name));
+        let internal =
+            declared.filter(|declared| declared.mutability == VariableMutability::Internal);
+        let line_number = self.line_id(index);
+        if var.mutability != VariableMutability::Internal {
+            if internal.is_some() {
+                self.error(&format!(
+                    "Variable {} has the same name as an internal variable, use internal to redeclare it (line {})",
+                    var.name, line_number
+                ));
+                return false;
+            }
+            return true;
+        }
+        if line.contains('(') {
+            return true;
+        }
+        if let Some(declared) = declared {
+            var.var_type = declared.var_type.clone();
+            var.subtype = declared.subtype.clone();
+            var.value = declared.value.clone();
+            var.expression = declared.expression.clone();
+        }
+        true
+    }
+
+    /// Write the literal default of an `Int` declared on line `index` in
+    /// decimal, or report it if its type can't hold it
+    fn normalize_integer(&mut self, var: &mut ParsedVariable, index: usize) {
+        if var.var_type != VariableType::Int {
+            return;
+        }
+        let Some(value) = parse_wide_integer(&var.value) else {
+            return;
+        };
+        if !var.int_range().contains(&value) {
+            let line = &self.current_lines[index];
+            let column = line
+                .rfind(var.value.as_str())
+                .map_or(0, |byte| line[..byte].chars().count());
+            let span = Span {
+                file: self.file_paths.get(self.current_file).cloned(),
+                line: self.line_id(index),
+                column,
+                length: var.value.chars().count(),
+            };
+            self.report_overflow(var, &var.value, span);
+            return;
+        }
+        let decimal = value.to_string();
+        if decimal != var.value {
+            var.expression = Some(std::mem::replace(&mut var.value, decimal));
+        }
+    }
+
+    fn report_overflow(&mut self, var: &ParsedVariable, value: &str, span: Span) {
+        let range = var.int_range();
+        let message = format!(
+            "Default of {} is out of range: {} doesn't fit in {} to {}",
+            var.name,
+            value,
+            range.start(),
+            range.end()
+        );
+        let mut diagnostic =
+            Diagnostic::new(INTEGER_OVERFLOW, Severity::Error, message).with_span(span);
+        if !var.is_wide_int() {
+            diagnostic = diagnostic.with_note(format!(
+                "Declare it as a 64-bit Int for larger values: {}(Int, {})",
+                var.name, WIDE_INT_SUBTYPE
+            ));
+        }
+        self.push_diagnostic(diagnostic);
+    }
+
+    fn parse_variable_line(&mut self, line: &str, line_number: usize) -> Option<ParsedVariable> {
+        // Parse variable definition: var VariableName(Type): DefaultValue
+        // constant definition: def ConstantName: Value
+        // or engine-managed variable: internal VariableName(Type)
+
+        if let Some(rest) = line.strip_prefix("var ") {
+            let mut var = self.parse_var_declaration(rest)?;
+            if var.var_type == VariableType::Str {
+                match string_literal::decode(&var.value) {
+                    Some(Ok(decoded)) => var.value = decoded,
+                    Some(Err(message)) => self.error(&format!(
+                        "Invalid string for {}: {} (line {})",
+                        var.name, message, line_number
+                    )),
+                    None => {}
+                }
+            }
+            Some(var)
+        } else if let Some(rest) = line.strip_prefix("def ") {
+            self.parse_def_declaration(rest)
+        } else if let Some(rest) = line.strip_prefix("internal ") {
+            if rest.contains(':') {
+                self.error(&format!(
+                    "Can't assign an internal variable (line {})",
+                    line_number
+                ));
+                return None;
+            }
+            // Untyped, it takes the type of the inherited variable it marks,
+            // see `mark_internal`
+            let (name, var_type, subtype) = match rest.contains('(') {
+                true => self.parse_name_and_type(rest)?,
+                false => (rest.trim().to_string(), VariableType::Var, String::new()),
+            };
+            Some(ParsedVariable {
+                name: self.interner.intern(&name),
+                mutability: VariableMutability::Internal,
+                var_type,
+                subtype,
+                value: String::new(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Split `Name(Type)` or `Name(Type, Subtype)`
+    fn parse_name_and_type(&self, name_part: &str) -> Option<(String, VariableType, String)> {
+        let (name, type_str, subtype) = split_name_and_type(name_part)?;
+        Some((
+            name.to_string(),
+            self.parse_variable_type(type_str),
+            subtype.to_string(),
+        ))
+    }
+
+    fn parse_var_declaration(&self, line: &str) -> Option<ParsedVariable> {
+        // Format: VariableName(Type): DefaultValue
+        // or: VariableName(Type, Subtype): DefaultValue
+
+        let (name_part, value_part) = line.split_once(':')?;
+        let (name_part, value_part) = (name_part.trim(), value_part.trim());
+        let (name, var_type, subtype) = self.parse_name_and_type(name_part)?;
+
+        Some(ParsedVariable {
+            name: self.interner.intern(&name),
+            mutability: VariableMutability::Variable,
+            var_type,
+            subtype,
+            value: value_part.to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        })
+    }
+
+    fn parse_def_declaration(&self, line: &str) -> Option<ParsedVariable> {
+        // Format: ConstantName: Value
+
+        let (name, value) = line.split_once(':')?;
+        Some(ParsedVariable {
+            name: self.interner.intern(name.trim()),
+            mutability: VariableMutability::Define,
+            var_type: VariableType::Var, // Defines can be any type
+            subtype: String::new(),
+            value: value.trim().to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        })
+    }
+
+    /// Evaluate the expression defaults of a character parsed elsewhere
+    pub(crate) fn evaluate_character_defaults(&mut self, character: &mut ParsedCharacter) {
+        self.variables = std::mem::take(&mut character.variables);
+        self.entity_variables = std::mem::take(&mut character.entity_variables);
+        self.evaluate_defaults();
+        character.variables = std::mem::take(&mut self.variables);
+        character.entity_variables = std::mem::take(&mut self.entity_variables);
+    }
+
+    /// Replace expression defaults (`Vec2(1, 2) * Scale`, `0.5s`) by their value
+    ///
+    /// Only number and vector variables are evaluated. An untyped `Var`
+    /// or define that doesn't evaluate is taken to be a plain string.
+    fn evaluate_defaults(&mut self) {
+        let fps = self.config.frames_per_second;
+        let mut updates = Vec::new();
+        let mut errors = Vec::new();
+        let mut overflows = Vec::new();
+
+        let scopes = std::iter::once((None, &self.variables)).chain(
+            self.entity_variables
+                .iter()
+                .map(|(entity, variables)| (Some(entity), variables)),
+        );
+        for (entity, variables) in scopes {
+            let lookup_scope = |name: &str| {
+                variables
+                    .get(name)
+                    .or_else(|| entity.and_then(|_| self.variables.get(name)))
+            };
+            for var in variables.values() {
+                if var.var_type == VariableType::Color {
+                    if !var.value.is_empty() && Rgba::parse(&var.value).is_none() {
+                        errors.push(format!(
+                            "Invalid default for {}: {} is not a color",
+                            var.name, var.value
+                        ));
+                    }
+                    continue;
+                }
+                let is_number = matches!(
+                    var.var_type,
+                    VariableType::Int
+                        | VariableType::Float
+                        | VariableType::Vec2
+                        | VariableType::Vec3
+                );
+                if (!is_number && var.var_type != VariableType::Var) || var.value.is_empty() {
+                    continue;
+                }
+
+                let mut stack = vec![var.name.to_string()];
+                let result = evaluate(&var.value, fps, &mut |name| {
+                    resolve_reference(&lookup_scope, name, fps, &mut stack)
+                });
+                match result {
+                    Ok(evaluated) if evaluated.derived => {
+                        let integral =
+                            matches!(evaluated.value, Value::Scalar(v) if v.fract() == 0.0);
+                        if var.var_type == VariableType::Int && !integral {
+                            errors.push(format!(
+                                "Invalid default for {}: {} is not an integer",
+                                var.name, evaluated.value
+                            ));
+                            continue;
+                        }
+                        if let (VariableType::Int, Value::Scalar(value)) =
+                            (&var.var_type, &evaluated.value)
+                        {
+                            let range = var.int_range();
+                            if *value < *range.start() as f64 || *value > *range.end() as f64 {
+                                overflows.push((var.clone(), evaluated.value.to_string()));
+                                continue;
+                            }
+                        }
+                        updates.push((
+                            entity.cloned(),
+                            var.name.clone(),
+                            evaluated.value.to_string(),
+                        ));
+                    }
+                    Ok(_) => {}
+                    Err(message) if is_number => {
+                        errors.push(format!("Invalid default for {}: {}", var.name, message));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+
+        errors.sort();
+        for error in errors {
+            self.error(&error);
+        }
+        overflows.sort_by_key(|(var, _)| var.origin.line);
+        for (var, value) in overflows {
+            let span = Span {
+                file: Some(var.origin.file.clone()).filter(|file| !file.is_empty()),
+                ..Span::line(var.origin.line)
+            };
+            self.report_overflow(&var, &value, span);
+        }
+        for (entity, name, value) in updates {
+            let variables = match entity {
+                Some(entity) => self.entity_variables.get_mut(&entity),
+                None => Some(&mut self.variables),
+            };
+            if let Some(var) = variables.and_then(|variables| variables.get_mut(&name)) {
+                var.expression = Some(std::mem::replace(&mut var.value, value));
+            }
+        }
+    }
+
+    fn parse_variable_type(&self, type_str: &str) -> VariableType {
+        VariableType::from_name(type_str).unwrap_or_else(|| {
+            self.log(&format!(
+                "Unknown variable type: {}, defaulting to Var",
+                type_str
+            ));
+            VariableType::Var
+        })
+    }
+
+    #[cfg(test)]
+    fn parse_states(&mut self, _file_id: usize) {
+        let blocks = segment_blocks(&self.current_lines);
+        self.parse_states_in(&blocks);
+    }
+
+    fn parse_states_in(&mut self, blocks: &[Block]) {
+        self.log("Parsing states...");
+
+        // Generic states first, for the states using them
+        for block in blocks {
+            if let Some((header, params)) = split_generic_header(&block.header) {
+                let mut i = block.start;
+                if let Some((state, _)) = self.read_state(header, &mut i) {
+                    let generic = GenericState { params, state };
+                    let name = generic.state.name.to_string();
+                    self.generic_states.insert(name, generic);
+                }
+            }
+        }
+
+        for block in blocks {
+            let state_name = block.name.as_str();
+
+            // Skip special blocks we've already handled, and skip specblocks
+            if state_name != "Character"
+                && VariablesBlock::from_header(state_name).is_none()
+                && subentity_header(state_name).is_none()
+                && !state_name.starts_with(TEMPLATE_PREFIX)
+                && !self.specblocks.contains_key(state_name)
+                && split_generic_header(&block.header).is_none()
+            {
+                let header_line = self.line_id(block.start);
+                if self.config.lazy_states {
+                    self.defer_state(block);
+                } else {
+                    let mut i = block.start;
+                    self.parse_state(block.header.clone(), &mut i);
+                }
+                if self.states.len() + self.lazy_states.len() > self.config.limits.max_states {
+                    self.limit_exceeded(Limit::States, Some(header_line));
+                }
+                if self.stopping() {
+                    break;
+                }
+            }
+        }
+
+        self.log(&format!("Parsed {} states", self.states.len()));
+    }
+
+    /// Keep the lines of a state block to parse it on first access, over
+    /// any inherited state of the same name
+    fn defer_state(&mut self, block: &Block) {
+        let (name, _, _) = split_state_header(&block.header);
+        let name = self.interner.intern(name);
+        self.states.remove(&name);
+        let lines = (block.start..block.end)
+            .map(|i| (self.line_id(i), self.current_lines[i].clone()))
+            .collect();
+        self.lazy_states.insert(name, LazyState::new(lines));
+    }
+
+    /// Parse a state block left for later, see `lazy_states`; `None` if
+    /// it goes past the limits
+    pub(crate) fn parse_lazy_state(
+        &mut self,
+        interner: &Interner,
+        file: &str,
+        character: &ParsedCharacter,
+        lines: &[(usize, String)],
+    ) -> Option<ParsedState> {
+        self.interner = interner.clone();
+        self.file_paths = vec![file.to_string()];
+        self.templates = character.templates.clone();
+        self.generic_states = character.generic_states.clone();
+        (self.line_ids, self.current_lines) = lines.iter().cloned().unzip();
+        let header = self
+            .current_lines
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default();
+        let header = block_header_name(header).unwrap_or_default().to_string();
+        let mut i = 0;
+        self.parse_state(header, &mut i);
+        self.states.drain().next().map(|(_, state)| state)
+    }
+
+    fn parse_state_header(&self, state_header: &str) -> (String, StateType, Option<String>) {
+        let (name, state_type, parent) = split_state_header(state_header);
+        (name.to_string(), state_type, parent.map(str::to_string))
+    }
+
+    pub(crate) fn parse_state(&mut self, state_name: String, i: &mut usize) {
+        if let Some((state, span)) = self.read_state(state_name, i) {
+            self.source_index.push(span);
+            self.states.insert(state.name.clone(), state);
+        }
+    }
+
+    /// Parse a state block, leaving `i` on its last line; `None` if it goes
+    /// past the limits
+    fn read_state(
+        &mut self,
+        state_name: String,
+        i: &mut usize,
+    ) -> Option<(ParsedState, StateSpan)> {
+        self.log(&format!("Parsing state: {}", state_name));
+
+        // Parse state name with optional type and parent
+        // Format: :StateName: or :StateName(Type): or :StateName(Parent): or :StateName(Type, Parent):
+        let (actual_name, state_type, parent) = self.parse_state_header(&state_name);
+        // `:Name(Override):` isn't a parent but asks for an inherited state to replace
+        let (parent, is_override) = match parent {
+            Some(parent) if parent == OVERRIDE_MARKER => (None, true),
+            parent => (parent, false),
+        };
+        let inherited = |name: &str| {
+            self.inherited
+                .iter()
+                .any(|(_, layer)| layer.states.contains_key(name))
+        };
+        if is_override && !inherited(&actual_name) {
+            let line = &self.current_lines[*i];
+            let column = line.rfind(OVERRIDE_MARKER).unwrap_or_default();
+            let column = char_column(line, column);
+            self.diagnostic(
+                UNMATCHED_OVERRIDE,
+                &format!(
+                    "State {} is marked Override but no parent defines it",
+                    actual_name
+                ),
+                self.line_id(*i),
+                column,
+            );
+        }
+
+        let actual_name = self.interner.intern(&actual_name);
+        let mut state = ParsedState {
+            name: actual_name.clone(),
+            state_type,
+            parent,
+            actions: Phases::new(),
+            attack: AttackNotation::parse(&actual_name),
+            description: None,
+            origin: self.source_ref(*i),
+            locals: Vec::new(),
+        };
+
+        let mut span = StateSpan {
+            name: actual_name.to_string(),
+            start_line: self.line_id(*i),
+            end_line: self.line_id(*i),
+            phases: Vec::new(),
+        };
+
+        let mut current_phase: Option<Symbol> = None;
+        // Lines of the blocks open in the current phase, with the
+        // instruction closing them, and its last action
+        let mut open_blocks: Vec<(usize, &'static str)> = Vec::new();
+        let mut last_action = *i;
+        *i += 1; // Move past the state name line
+
+        while *i < self.current_lines.len() {
+            let line = self.current_lines[*i].trim();
+
+            // Check if we've reached another state
+            if is_block_header(line) {
+                break;
+            }
+
+            // Check for phase marker (---PhaseName:)
+            if let Some(marker) = line.strip_prefix("---") {
+                if let Some((phase_name, _)) = marker.split_once(':') {
+                    let mut phase_name = phase_name.trim().to_string();
+                    self.report_open_blocks(&mut open_blocks, last_action);
+                    if let Some(current) = self.legacy_name(LegacyKind::Phase, &phase_name, *i) {
+                        phase_name = current;
+                    }
+                    let phase = self.interner.intern(&phase_name);
+                    current_phase = Some(phase.clone());
+                    let line_number = self.line_id(*i);
+                    if let Some(previous) = span.phases.last_mut() {
+                        previous.end_line = line_number - 1;
+                    }
+                    // A repeated marker continues the phase, see `Phases`
+                    let existing = state.actions.declare(phase, line_number);
+                    span.phases.push(PhaseSpan {
+                        name: phase_name,
+                        start_line: line_number,
+                        end_line: line_number,
+                        action_lines: Vec::new(),
+                        action_offset: existing.actions.len(),
+                    });
+                }
+            }
+            // Doc comment describing the state
+            else if current_phase.is_none() && line.starts_with("##") {
+                let text = line.trim_start_matches('#').trim();
+                match state.description.as_mut() {
+                    Some(description) => {
+                        description.push('\n');
+                        description.push_str(text);
+                    }
+                    None => state.description = Some(text.to_string()),
+                }
+            }
+            // Filled from a generic state: UseState(Name, Values...)
+            else if current_phase.is_none() && line.starts_with(USE_STATE) {
+                let cleaned_line = self.strip_inline_comment(line);
+                let line_number = self.line_id(*i);
+                if let Some(call) = self.parse_action_line(cleaned_line.trim(), line_number) {
+                    self.use_generic_state(&mut state, &call);
+                }
+            }
+            // Temporary of the state: let Name: Value
+            else if let (Some(phase), Some(declaration)) =
+                (&current_phase, line.strip_prefix("let "))
+            {
+                let declaration = self.strip_inline_comment(declaration);
+                self.parse_local(&mut state, phase.clone(), declaration.trim(), *i);
+            }
+            // Parse action line (strip inline comments first)
+            else if !line.is_empty() && !line.starts_with('#') {
+                let cleaned_line = self.strip_inline_comment(line);
+                let cleaned = cleaned_line.trim();
+
+                if !cleaned.is_empty() {
+                    if let Some(ref phase) = current_phase {
+                        let line_number = self.line_id(*i);
+                        self.check_delimiters(cleaned, *i);
+                        let instruction = split_action(cleaned).map_or("", |(name, _)| name);
+                        let opened = match block_closing(instruction) {
+                            Some(end) => Some(end),
+                            None => cleaned.ends_with(':').then_some("endif"),
+                        };
+                        if let Some(end) = opened {
+                            open_blocks.push((*i, end));
+                            if open_blocks.len() > self.config.limits.max_nesting_depth {
+                                self.limit_exceeded(Limit::NestingDepth, Some(line_number));
+                                return None;
+                            }
+                        } else if cleaned.eq_ignore_ascii_case("endif") {
+                            self.close_block(&mut open_blocks, "endif", *i);
+                        } else if let Some(end) = block_end(instruction) {
+                            self.close_block(&mut open_blocks, end, *i);
+                        } else if [CASE, DEFAULT].contains(&instruction)
+                            && open_blocks.last().is_none_or(|&(_, end)| end != END_SWITCH)
+                        {
+                            let message = format!("{} is outside of a {}", cleaned, SWITCH);
+                            let raw = &self.current_lines[*i];
+                            let column = raw.len() - raw.trim_start().len();
+                            self.diagnostic(CASE_OUTSIDE_SWITCH, &message, line_number, column);
+                        }
+                        last_action = *i;
+                        if let Some(mut action) = self.parse_action_line(cleaned, line_number) {
+                            self.upgrade_legacy_instruction(&mut action, *i);
+                            self.normalize_arguments(&mut action, *i);
+                            let actions = if action.instruction == USE_TEMPLATE {
+                                self.expand_template(&action, line_number, &mut Vec::new())
+                            } else {
+                                vec![action]
+                            };
+                            if let Some(phase_span) = span.phases.last_mut() {
+                                let lines = std::iter::repeat_n(line_number, actions.len());
+                                phase_span.action_lines.extend(lines);
+                            }
+                            state.actions.insert(phase.clone(), actions);
+                        }
+                    }
+                }
+            }
+
+            *i += 1;
+        }
+        self.report_open_blocks(&mut open_blocks, last_action);
+
+        span.end_line = self.line_id(*i - 1);
+        if let Some(last_phase) = span.phases.last_mut() {
+            last_phase.end_line = span.end_line;
+        }
+        *i -= 1; // Back up one so the outer loop doesn't skip a line
+        Some((state, span))
+    }
+
+    /// User-facing (1-indexed) line number of a line index
+    fn line_id(&self, index: usize) -> usize {
+        self.line_ids.get(index).copied().unwrap_or(index + 1)
+    }
+
+    /// File and line of a line index of the file being parsed
+    fn source_ref(&self, index: usize) -> SourceRef {
+        SourceRef {
+            file: self.file_paths.first().cloned().unwrap_or_default(),
+            line: self.line_id(index),
+        }
+    }
+
+    fn parse_templates_in(&mut self, blocks: &[Block]) {
+        self.log("Parsing templates...");
+
+        for block in blocks {
+            if let Some(signature) = block.header.trim().strip_prefix(TEMPLATE_PREFIX) {
+                let mut i = block.start;
+                self.parse_template(signature, &mut i);
+            }
+        }
+
+        self.log(&format!("Parsed {} templates", self.templates.len()));
+    }
+
+    /// Parse a template block, leaving `i` on the next block header
+    fn parse_template(&mut self, signature: &str, i: &mut usize) {
+        let line_number = self.line_id(*i);
+        let (name, params) = match signature.split_once('(') {
+            Some((name, params)) => {
+                let params = params
+                    .trim_end_matches(')')
+                    .split(',')
+                    .map(|param| param.trim().to_string())
+                    .filter(|param| !param.is_empty())
+                    .collect();
+                (name.trim().to_string(), params)
+            }
+            None => (signature.trim().to_string(), Vec::new()),
+        };
+
+        let mut actions = Vec::new();
+        *i += 1;
+        while *i < self.current_lines.len() {
+            let line = self.current_lines[*i].trim();
+            if is_block_header(line) {
+                break;
+            }
+            if line.starts_with("---") {
+                self.error(&format!(
+                    "Phase markers are not allowed in template {} (line {})",
+                    name,
+                    self.line_id(*i)
+                ));
+            } else if !line.is_empty() && !line.starts_with('#') {
+                let cleaned_line = self.strip_inline_comment(line);
+                let cleaned = cleaned_line.trim();
+                if !cleaned.is_empty() {
+                    self.check_delimiters(cleaned, *i);
+                    if let Some(mut action) = self.parse_action_line(cleaned, self.line_id(*i)) {
+                        self.upgrade_legacy_instruction(&mut action, *i);
+                        self.normalize_arguments(&mut action, *i);
+                        actions.push(action);
+                    }
+                }
+            }
+            *i += 1;
+        }
+
+        self.templates.insert(
+            name.clone(),
+            ParsedTemplate {
+                name,
+                params,
+                actions,
+                line_number,
+            },
+        );
+    }
+
+    /// Expand a `UseTemplate(Name, Args...)` call
+    ///
+    /// Parameter names in the arguments, nested calls and expressions
+    /// included, are replaced by the call's values. Expanded actions take the line of the outermost call, so
+    /// lookups by line land on the `UseTemplate`; errors also name the
+    /// template lines they come from. `via` holds the templates being
+    /// expanded, with the line of the nested call in each.
+    fn expand_template(
+        &mut self,
+        call: &ParsedAction,
+        call_line: usize,
+        via: &mut Vec<(String, usize)>,
+    ) -> Vec<ParsedAction> {
+        let location = std::iter::once(format!("line {}", call_line))
+            .chain(
+                via.iter()
+                    .map(|(name, line)| format!("via template {} at line {}", name, line)),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let Some(name) = call.args.first() else {
+            self.error(&format!(
+                "UseTemplate without a template name ({})",
+                location
+            ));
+            return Vec::new();
+        };
+        let Some(template) = self.templates.get(name).cloned() else {
+            self.error(&format!("Unknown template {} ({})", name, location));
+            return Vec::new();
+        };
+        let values: Vec<&str> = call.args.iter().skip(1).collect();
+        if values.len() != template.params.len() {
+            self.error(&format!(
+                "Template {} expects {} arguments, got {} ({})",
+                name,
+                template.params.len(),
+                values.len(),
+                location
+            ));
+            return Vec::new();
+        }
+        if via.len() >= MAX_TEMPLATE_DEPTH || via.iter().any(|(used, _)| used == name) {
+            self.error(&format!(
+                "Template {} expands recursively ({})",
+                name, location
+            ));
+            return Vec::new();
+        }
+
+        let mut expanded = Vec::new();
+        for action in &template.actions {
+            let body_line = action.line_number;
+            let args = action
+                .args
+                .iter()
+                .map(|arg| substitute_params(arg, &template.params, &values))
+                .collect();
+            let action = ParsedAction {
+                instruction: action.instruction.clone(),
+                args,
+                line_number: call_line,
+                frames: Vec::new(),
+                defaulted: action.defaulted.clone(),
+                flags: action.flags.clone(),
+            };
+
+            if action.instruction == USE_TEMPLATE {
+                via.push((template.name.clone(), body_line));
+                expanded.extend(self.expand_template(&action, call_line, via));
+                via.pop();
+            } else {
+                expanded.push(action);
+            }
+        }
+        expanded
+    }
+
+    /// Fill `state` from the generic state a `UseState(Name, Values...)`
+    /// call names, see `generic_states`
+    fn use_generic_state(&mut self, state: &mut ParsedState, call: &ParsedAction) {
+        let line = call.line_number;
+        let Some(name) = call.args.first() else {
+            self.error(&format!(
+                "UseState without a generic state name (line {})",
+                line
+            ));
+            return;
+        };
+        let Some(generic) = self.generic_states.get(name).cloned() else {
+            self.error(&format!("Unknown generic state {} (line {})", name, line));
+            return;
+        };
+        let values: Vec<&str> = call.args.iter().skip(1).collect();
+        if values.len() != generic.params.len() {
+            self.error(&format!(
+                "Generic state {} expects {} arguments, got {} (line {})",
+                name,
+                generic.params.len(),
+                values.len(),
+                line
+            ));
+            return;
+        }
+        generic.instantiate(&values, line, state);
+        if state.state_type == StateType::Normal && state.parent.is_none() {
+            state.state_type = generic.state.state_type.clone();
+            state.parent = generic.state.parent.clone();
+        }
+    }
+
+    fn parse_action_line(&self, line: &str, line_number: usize) -> Option<ParsedAction> {
+        // Parse function call: FunctionName(Arg1, Arg2, ...)
+        // or simple instruction: FunctionName
+        let (instruction, args_str) = split_action(line)?;
+        Some(ParsedAction {
+            instruction: self.interner.intern(instruction),
+            // Parse arguments with better handling of nested calls and strings
+            args: self.parse_arguments(args_str),
+            line_number,
+            frames: Vec::new(),
+            defaulted: Vec::new(),
+            flags: Vec::new(),
+        })
+    }
+
+    /// Declare a temporary of `state` from the `Name: Value` on line `index`
+    fn parse_local(
+        &mut self,
+        state: &mut ParsedState,
+        phase: Symbol,
+        declaration: &str,
+        index: usize,
+    ) {
+        let line_number = self.line_id(index);
+        let parts = declaration
+            .split_once(':')
+            .map(|(name, value)| (name.trim(), value.trim()));
+        let Some((name, value)) = parts.filter(|(name, value)| {
+            !value.is_empty()
+                && !name.is_empty()
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }) else {
+            self.error(&format!("Expected let Name: Value (line {})", line_number));
+            return;
+        };
+        state.locals.push(LocalVariable {
+            name: self.interner.intern(name),
+            value: value.to_string(),
+            position: state.actions.get(&phase).map_or(0, Vec::len),
+            phase,
+            line_number,
+        });
+    }
+
+    /// Close the innermost open block with the `endif` or block end on
+    /// line `index`, reporting an end that doesn't match it
+    ///
+    /// A stray `endif` is let through as before; a mismatched end is
+    /// otherwise ignored, leaving the block open.
+    fn close_block(
+        &mut self,
+        open_blocks: &mut Vec<(usize, &'static str)>,
+        end: &'static str,
+        index: usize,
+    ) {
+        let message = match open_blocks.last() {
+            Some(&(_, open)) if open == end => {
+                open_blocks.pop();
+                return;
+            }
+            None if end == "endif" => return,
+            None => format!(
+                "{} closes no {}",
+                end,
+                end.strip_prefix("End").unwrap_or(end)
+            ),
+            Some(&(opened, open)) => format!(
+                "{} would close {} from line {}, close it with {} first",
+                end,
+                self.current_lines[opened].trim(),
+                self.line_id(opened),
+                open
+            ),
+        };
+        let line = &self.current_lines[index];
+        let column = line.len() - line.trim_start().len();
+        self.diagnostic(MISMATCHED_BLOCK_END, &message, self.line_id(index), column);
+    }
+
+    /// Warn about blocks left open at the end of a phase, suggesting the
+    /// instruction closing them after its last action
+    fn report_open_blocks(
+        &mut self,
+        open_blocks: &mut Vec<(usize, &'static str)>,
+        last_action: usize,
+    ) {
+        while let Some((index, end)) = open_blocks.pop() {
+            let line = &self.current_lines[index];
+            let indent = line.strip_suffix(line.trim_start()).unwrap_or_default();
+            let insert_at = Span {
+                file: self.file_paths.get(self.current_file).cloned(),
+                line: self.line_id(last_action) + 1,
+                column: 0,
+                length: 0,
+            };
+            let fix = format!("{}{}\n", indent, end);
+            let span = Span {
+                column: indent.chars().count(),
+                length: line.trim().chars().count(),
+                line: self.line_id(index),
+                ..insert_at.clone()
+            };
+            let (code, block) = match end {
+                "endif" => (MISSING_ENDIF, "Branch"),
+                END_SWITCH => (UNCLOSED_SWITCH, "Switch"),
+                _ => (UNCLOSED_LOOP, "Loop"),
+            };
+            let message = format!("{} {} is never closed with {}", block, line.trim(), end);
+            self.push_diagnostic(
+                Diagnostic::new(code, Severity::Warning, message)
+                    .with_span(span)
+                    .with_note(format!("Add {} after line {}", end, insert_at.line - 1))
+                    .with_fix(insert_at, fix),
+            );
+        }
+    }
+
+    /// Set the trailing flags of an action on line `index` apart, put its
+    /// named arguments in positional order and fill in the defaults of
+    /// those left out, reporting the arguments that don't fit its signature
+    fn normalize_arguments(&mut self, action: &mut ParsedAction, index: usize) {
+        let signature = self.config.signatures.get(&action.instruction);
+        let (args, flags) = split_flags(signature, &action.args);
+        match normalize_arguments(&action.instruction, signature, &args) {
+            Ok((args, defaulted)) => {
+                action.args = args;
+                action.defaulted = defaulted;
+                action.flags = flags;
+            }
+            Err(errors) => {
+                for error in errors {
+                    self.diagnostic(INVALID_NAMED_ARGUMENT, &error, self.line_id(index), 0);
+                }
+            }
+        }
+    }
+
+    fn upgrade_legacy_instruction(&mut self, action: &mut ParsedAction, index: usize) {
+        let instruction = action.instruction.clone();
+        if let Some(current) = self.legacy_name(LegacyKind::Instruction, &instruction, index) {
+            action.instruction = self.interner.intern(&current);
+        }
+    }
+
+    /// Warn if `name`, on line `index`, is deprecated
+    ///
+    /// Returns the name to read instead for legacy names.
+    fn legacy_name(&mut self, kind: LegacyKind, name: &str, index: usize) -> Option<String> {
+        let deprecation = self.config.deprecations.get(kind, name)?;
+        if deprecation.legacy && !self.config.legacy_syntax {
+            return None;
+        }
+        let (replacement, legacy) = (deprecation.replacement.clone(), deprecation.legacy);
+        let line = self.line_id(index);
+        let column = self.current_lines[index]
+            .find(name)
+            .map_or(0, |byte| char_column(&self.current_lines[index], byte));
+        let span = Span {
+            file: self.file_paths.get(self.current_file).cloned(),
+            line,
+            column,
+            length: name.chars().count(),
+        };
+        let (code, message) = match legacy {
+            true => (
+                LEGACY_SYNTAX,
+                format!("Legacy {} {}, read as {}", kind, name, replacement),
+            ),
+            false => (
+                DEPRECATED_NAME,
+                format!("The {} {} is deprecated, use {}", kind, name, replacement),
+            ),
+        };
+        self.log(&format!("{} (line {})", message, line));
+        self.push_diagnostic(
+            Diagnostic::new(code, Severity::Warning, message)
+                .with_span(span.clone())
+                .with_note(format!("Replace {} with {}", name, replacement))
+                .with_fix(span, replacement.as_str()),
+        );
+        legacy.then_some(replacement)
+    }
+
+    fn parse_arguments(&self, args_str: &str) -> ActionArgs {
+        let parts = split_arguments(args_str);
+        let mut args = ActionArgs::with_capacity(args_str.len(), parts.len());
+        for part in parts {
+            args.push(part);
+        }
+        args
+    }
+
+    // -------------------------------------------------------------------------
+    // Instruction execution (for runtime)
+
+    /// Standard parse function (used by modules to register functions)
+    pub fn standard_parse_function(&self, _function_name: &str, args: &[String]) -> Vec<String> {
+        // TODO: Parse function arguments according to function signature
+        // For now, just return the args as-is
+        args.to_vec()
+    }
+
+    // Note: Instruction execution is handled by the GDScript engine.
+    // The parser's job is to parse .casp files into data structures only.
+
+    // -------------------------------------------------------------------------
+    // Logging and errors
+
+    /// Run one phase of a parse, recording how long it took
+    fn timed<T>(&mut self, phase: ParsePhase, run: impl FnOnce(&mut Self) -> T) -> T {
+        let start = Instant::now();
+        let result = run(self);
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    fn record(&mut self, phase: ParsePhase, duration: Duration) {
+        match self
+            .metrics
+            .phases
+            .iter_mut()
+            .find(|(timed, _)| *timed == phase)
+        {
+            Some((_, total)) => *total += duration,
+            None => self.metrics.phases.push((phase, duration)),
+        }
+        if let Some(profiler) = &self.profiler {
+            profiler(phase, duration);
+        }
+    }
+
+    fn log(&self, message: &str) {
+        if self.logs_active {
+            godot_print!("[CastagneParser] {}", message);
+        }
+    }
+
+    /// Stop the parse; the first failure is what the parse returns
+    fn fatal_error(&mut self, failure: ParseFailure) {
+        let message = failure.to_string();
+        self.errors.push(message.clone());
+        godot_error!("[CastagneParser] FATAL: {}", message);
+        self.aborting = true;
+        self.invalid_file = true;
+        self.failure.get_or_insert(failure);
+    }
+
+    /// Whether the parse stopped, stopping it first if it was cancelled
+    fn stopping(&mut self) -> bool {
+        if !self.aborting && self.config.cancellation.is_cancelled() {
+            self.fatal_error(ParseFailure::Cancelled);
+        }
+        self.aborting
+    }
+
+    /// Stop the parse on a file going past one of the `Limits`, at a line
+    /// when the limit is about one
+    fn limit_exceeded(&mut self, limit: Limit, line: Option<usize>) {
+        let span = line.map(|line| Span {
+            file: self.file_paths.first().cloned(),
+            ..Span::line(line)
+        });
+        let failure = ParseFailure::LimitExceeded {
+            limit,
+            max: self.config.limits.max(limit),
+            span: span.clone(),
+        };
+        let mut diagnostic = Diagnostic::new(LIMIT_EXCEEDED, Severity::Error, failure.to_string())
+            .with_note("Raise the limit in ParserConfig if the file is meant to be this large");
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+        self.fatal_error(failure);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors.push(message.to_string());
+        godot_error!("[CastagneParser] ERROR: {}", message);
+    }
+
+    /// Report an error with a position
+    fn diagnostic(&mut self, code: &str, message: &str, line: usize, column: usize) {
+        let span = Span {
+            file: self.file_paths.get(self.current_file).cloned(),
+            line,
+            column,
+            length: 1,
+        };
+        self.push_diagnostic(Diagnostic::new(code, Severity::Error, message).with_span(span));
+    }
+
+    /// Keep a diagnostic with the severity the config gives its code,
+    /// unless a `castagne-ignore` pragma of the file silences it, which is
+    /// counted in the metrics. Errors are also listed in `errors`.
+    fn push_diagnostic(&mut self, mut diagnostic: Diagnostic) {
+        let file = self.file_paths.first().map(String::as_str);
+        if self
+            .suppressions
+            .silences(&diagnostic, file.unwrap_or_default())
+        {
+            *self.metrics.suppressed.entry(diagnostic.code).or_default() += 1;
+            return;
+        }
+        let Some(severity) = self
+            .config
+            .severity_of(&diagnostic.code, diagnostic.severity)
+        else {
+            return;
+        };
+        diagnostic.severity = severity;
+        if diagnostic.is_error() {
+            let error = match &diagnostic.span {
+                Some(span) => format!(
+                    "{} (line {}, column {})",
+                    diagnostic.message,
+                    span.line,
+                    span.column + 1
+                ),
+                None => diagnostic.message.clone(),
+            };
+            self.error(&error);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report an invalid metadata value on a line index, at the value
+    fn invalid_metadata(&mut self, message: &str, index: usize) {
+        let line = &self.current_lines[index];
+        let value = line.find(':').map_or(0, |colon| {
+            colon + 1 + line[colon + 1..].len() - line[colon + 1..].trim_start().len()
+        });
+        let column = char_column(line, value);
+        self.diagnostic(INVALID_METADATA, message, self.line_id(index), column);
+    }
+
+    /// Report a broken parser invariant at a line, which is then skipped
+    fn internal_error(&mut self, error: ParseError, line: usize) {
+        let message = format!("Internal parser error: {}, please report it", error);
+        self.diagnostic(INTERNAL_ERROR, &message, line, 0);
+    }
+
+    /// Get all errors from last parse
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Get the errors of the last parse that have a code and position
+    /// Preprocessed lines of the file parsed last, and of its skeletons and
+    /// includes with `keep_source_lines`, see `source_map`
+    pub fn source_map(&self) -> SourceMap {
+        let own = self.file_paths.first().map(|path| FileLines {
+            path: path.clone(),
+            lines: self.current_lines.clone(),
+            line_ids: self.line_ids.clone(),
+        });
+        let files = own.into_iter().chain(self.dependency_lines.iter().cloned());
+        SourceMap {
+            files: files.collect(),
+        }
+    }
+
+    pub fn get_diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Report the first unbalanced delimiter of an action line
+    ///
+    /// `line` is the trimmed text of source line `index`. The action is
+    /// still parsed as well as possible.
+    fn check_delimiters(&mut self, line: &str, index: usize) {
+        let Some((column, message)) = unbalanced_delimiter(line) else {
+            return;
+        };
+        let indent = self.current_lines[index]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count();
+        let line_number = self.line_id(index);
+        self.diagnostic(UNBALANCED_DELIMITER, &message, line_number, indent + column);
+    }
+
+    // -------------------------------------------------------------------------
+    // Type conversion utilities
+
+    /// Parse a string value into a Variant based on the variable type
+    pub fn parse_value_to_variant(value_str: &str, var_type: &VariableType) -> Variant {
+        let trimmed = value_str.trim();
+
+        match var_type {
+            VariableType::Int => parse_integer(trimmed)
+                .and_then(|i| i32::try_from(i).ok())
+                .map(Variant::from)
+                .unwrap_or_else(Variant::nil),
+            VariableType::Float => parse_number(trimmed)
+                .map(Variant::from)
+                .unwrap_or_else(Variant::nil),
+            VariableType::Bool => {
+                let bool_val = match trimmed.to_lowercase().as_str() {
+                    "true" | "1" => true,
+                    "false" | "0" => false,
+                    _ => false,
+                };
+                Variant::from(bool_val)
+            }
+            VariableType::Str => {
+                // Remove quotes and process escapes if quoted
+                let unquoted = match string_literal::decode(trimmed) {
+                    Some(Ok(decoded)) => decoded,
+                    _ => trimmed.to_string(),
+                };
+                Variant::from(GString::from(unquoted.as_str()))
+            }
+            VariableType::Vec2 => {
+                // Parse (x, y) or x, y
+                Self::parse_vec2(trimmed).unwrap_or_else(Variant::nil)
+            }
+            VariableType::Vec3 => {
+                // Parse (x, y, z) or x, y, z
+                Self::parse_vec3(trimmed).unwrap_or_else(Variant::nil)
+            }
+            VariableType::Color => Rgba::parse(trimmed)
+                .map(|color| Variant::from(color.to_godot()))
+                .unwrap_or_else(Variant::nil),
+            VariableType::Var | VariableType::Box => {
+                // Try to infer the type
+                // First try int
+                if let Some(i) = parse_integer(trimmed).and_then(|i| i32::try_from(i).ok()) {
+                    return Variant::from(i);
+                }
+                // Then try float
+                if let Some(f) = parse_number(trimmed) {
+                    return Variant::from(f);
+                }
+                // Then try bool
+                match trimmed.to_lowercase().as_str() {
+                    "true" | "false" => {
+                        return Variant::from(trimmed.to_lowercase() == "true");
+                    }
+                    _ => {}
+                }
+                // Otherwise treat as string
+                Variant::from(GString::from(trimmed))
+            }
+        }
+    }
+
+    /// Parse a Vec2 from string (supports "x, y" or "(x, y)")
+    fn parse_vec2(s: &str) -> Option<Variant> {
+        let cleaned = s.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts: Vec<&str> = cleaned.split(',').collect();
+
+        if parts.len() == 2 {
+            if let (Some(x), Some(y)) = (parse_number(parts[0]), parse_number(parts[1])) {
+                return Some(Variant::from(Vector2::new(x as f32, y as f32)));
+            }
+        }
+        None
+    }
+
+    /// Parse a Vec3 from string (supports "x, y, z" or "(x, y, z)")
+    fn parse_vec3(s: &str) -> Option<Variant> {
+        let cleaned = s.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts: Vec<&str> = cleaned.split(',').collect();
+
+        if parts.len() == 3 {
+            if let (Some(x), Some(y), Some(z)) = (
+                parse_number(parts[0]),
+                parse_number(parts[1]),
+                parse_number(parts[2]),
+            ) {
+                return Some(Variant::from(Vector3::new(x as f32, y as f32, z as f32)));
+            }
+        }
+        None
+    }
+}
+
+impl Default for CastagneParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let mut parser = CastagneParser::new();
+        // Don't enable logs in unit tests (requires Godot runtime)
+
+        // Simulate file content
+        parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Test Fighter".to_string(),
+            "Author: Test Author".to_string(),
+            "Description: A test character".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+        ];
+        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        parser.parse_metadata(0);
+
+        assert_eq!(parser.metadata.name, "Test Fighter");
+        assert_eq!(parser.metadata.author, "Test Author");
+        assert_eq!(parser.metadata.description, "A test character");
+    }
+
+    #[test]
+    fn test_typed_metadata() {
+        let source = ":Character:
+Name: Ryu
+EditorName: Ryu (Shoto)
+Version: 1.2
+Portrait: res://ryu/portrait.PNG
+SelectIcon: icon.txt
+PaletteCount: 0
+Stage: Dojo
+:Fireball---Subentity:
+PaletteCount: 4
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        let metadata = &character.metadata;
+        assert_eq!(metadata.display_name(), "Ryu (Shoto)");
+        assert_eq!(metadata.version.as_deref(), Some("1.2"));
+        assert_eq!(metadata.portrait.as_deref(), Some("res://ryu/portrait.PNG"));
+        assert_eq!(metadata.select_icon, None);
+        assert_eq!(metadata.palette_count, None);
+        assert_eq!(character.subentities["Fireball"].palette_count, Some(4));
+        assert_eq!(metadata.other_fields.keys().collect::<Vec<_>>(), ["Stage"]);
+
+        let invalid: Vec<_> = parser
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.code == INVALID_METADATA)
+            .map(|d| {
+                (
+                    d.message.as_str(),
+                    d.span.as_ref().map(|s| (s.line, s.column)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            invalid,
+            [
+                (
+                    "SelectIcon should be an image path, got \"icon.txt\"",
+                    Some((6, 12))
+                ),
+                (
+                    "PaletteCount should be a positive integer, got \"0\"",
+                    Some((7, 14))
+                ),
+            ]
+        );
+
+        let json = serde_json::to_value(metadata).unwrap();
+        assert_eq!(json["editorname"], "Ryu (Shoto)");
+        assert_eq!(json["version"], "1.2");
+        assert!(json.get("palettecount").is_none());
+        let unnamed = CharacterMetadata {
+            name: "Ken".to_string(),
+            ..CharacterMetadata::default()
+        };
+        assert_eq!(unnamed.display_name(), "Ken");
+    }
+
+    #[test]
+    fn test_metadata_filepath_and_inheritance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("model.casp"),
+            ":Character:\nName: Baston\nAuthor: Panthavma\nEditorName: Baston (Custom)\nPaletteCount: 3\nStage: Dojo\nFormatVersion: 1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("2d.casp"),
+            format!(
+                ":Character:\nEditorName: Baston 2D\nStage: Lab\nSkeleton: {}\n",
+                path("model.casp")
+            ),
+        )
+        .unwrap();
+
+        let mut parser = CastagneParser::new();
+        let model = parser.create_full_character(&path("model.casp")).unwrap();
+        assert_eq!(model.metadata.filepath, path("model.casp"));
+        assert_eq!(model.metadata.display_name(), "Baston (Custom)");
+
+        let character = parser.create_full_character(&path("2d.casp")).unwrap();
+        let metadata = &character.metadata;
+        // The child's own fields win, the skeleton fills in the rest
+        assert_eq!(metadata.filepath, path("2d.casp"));
+        assert_eq!(metadata.name, "Baston");
+        assert_eq!(metadata.author, "Panthavma");
+        assert_eq!(metadata.display_name(), "Baston 2D");
+        assert_eq!(metadata.palette_count, Some(3));
+        assert_eq!(metadata.other_fields["Stage"], "Lab");
+        assert!(!metadata.other_fields.contains_key(FORMAT_VERSION_FIELD));
+
+        let json = character.to_json_value().unwrap();
+        assert_eq!(json["metadata"]["filepath"], path("2d.casp"));
+        assert_eq!(json["metadata"]["editorname"], "Baston 2D");
+
+        let unnamed = parser
+            .create_full_character_from_source("unnamed.casp", ":Character:\n")
+            .unwrap();
+        assert_eq!(unnamed.metadata.display_name(), "unnamed.casp");
+    }
+
+    #[test]
+    fn test_json_formats() {
+        let source =
+            ":Character:\nName: Tester\n:Variables:\ndef MaxSpeed: 12\n:Idle:\n---Init:\nMove(1)\n";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+
+        let internal = character.to_json(JsonFormat::Internal).unwrap();
+        let read: ParsedCharacter = serde_json::from_str(&internal).unwrap();
+        assert_eq!(read.states["Idle"].actions["Init"][0].args, ["1"]);
+
+        let engine = character.to_json(JsonFormat::Engine).unwrap();
+        let engine: serde_json::Value = serde_json::from_str(&engine).unwrap();
+        assert_eq!(engine, character.to_golden_master());
+        assert_eq!(engine["variables"]["MaxSpeed"]["Mutability"], "Constant");
+        assert_eq!(
+            engine["states"]["Idle"]["Phases"]["Init"]["Actions"][0]["function"],
+            "Move"
+        );
+    }
+
+    #[test]
+    fn test_parse_variables() {
+        let mut parser = CastagneParser::new();
+        // Don't enable logs in unit tests (requires Godot runtime)
+
+        parser.current_lines = vec![
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),
+            "var Name(Str): TestName".to_string(),
+            "def MAX_HP: 150".to_string(),
+            "".to_string(),
+            ":Idle:".to_string(),
+        ];
+        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        parser.parse_variables(0);
+
+        assert_eq!(parser.variables.len(), 3);
+
+        let health = parser.variables.get("Health").unwrap();
+        assert_eq!(health.var_type, VariableType::Int);
+        assert_eq!(health.value, "100");
+        assert_eq!(health.mutability, VariableMutability::Variable);
+
+        let max_hp = parser.variables.get("MAX_HP").unwrap();
+        assert_eq!(max_hp.value, "150");
+        assert_eq!(max_hp.mutability, VariableMutability::Define);
+    }
+
+    #[test]
+    fn test_parse_states() {
+        let mut parser = CastagneParser::new();
+        // Don't enable logs in unit tests (requires Godot runtime)
+
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(Health, 100)".to_string(),
+            "---Action:".to_string(),
+            "CheckInput()".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        parser.parse_states(0);
+
+        assert_eq!(parser.states.len(), 1);
+
+        let idle_state = parser.states.get("Idle").unwrap();
+        assert_eq!(idle_state.name, "Idle");
+
+        let init_actions = idle_state.actions.get("Init").unwrap();
+        assert_eq!(init_actions.len(), 1);
+        assert_eq!(init_actions[0].instruction, "Set");
+        assert_eq!(init_actions[0].args.len(), 2);
+        assert_eq!(&init_actions[0].args[0], "Health");
+        assert_eq!(&init_actions[0].args[1], "100");
+
+        let action_actions = idle_state.actions.get("Action").unwrap();
+        assert_eq!(action_actions.len(), 1);
+        assert_eq!(action_actions[0].instruction, "CheckInput");
+        assert_eq!(action_actions[0].args.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_action_line() {
+        let parser = CastagneParser::new();
+
+        // Test with arguments
+        let action = parser.parse_action_line("Set(Health, 100)", 1).unwrap();
+        assert_eq!(action.instruction, "Set");
+        assert_eq!(action.args.len(), 2);
+        assert_eq!(&action.args[0], "Health");
+        assert_eq!(&action.args[1], "100");
+
+        // Test without arguments
+        let action2 = parser.parse_action_line("DoSomething()", 1).unwrap();
+        assert_eq!(action2.instruction, "DoSomething");
+        assert_eq!(action2.args.len(), 0);
+
+        // Test without parentheses
+        let action3 = parser.parse_action_line("SimpleInstruction", 1).unwrap();
+        assert_eq!(action3.instruction, "SimpleInstruction");
+        assert_eq!(action3.args.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let parser = CastagneParser::new();
+
+        // Simple arguments
+        let args = parser.parse_arguments("a, b, c");
+        assert_eq!(args.len(), 3);
+        assert_eq!(&args[0], "a");
+        assert_eq!(&args[1], "b");
+        assert_eq!(&args[2], "c");
+
+        // Nested function calls
+        let args2 = parser.parse_arguments("Health, Add(10, 5), Position");
+        assert_eq!(args2.len(), 3);
+        assert_eq!(&args2[0], "Health");
+        assert_eq!(&args2[1], "Add(10, 5)");
+        assert_eq!(&args2[2], "Position");
+
+        // String arguments with commas
+        let args3 = parser.parse_arguments(r#""Hello, World", Test"#);
+        assert_eq!(args3.len(), 2);
+        assert_eq!(&args3[0], r#""Hello, World""#);
+        assert_eq!(&args3[1], "Test");
+
+        // Complex nested calls - note this is just the arguments part, not the full call
+        let args4 =
+            parser.parse_arguments("Greater(Health, 50), Set(Color, Red), Set(Color, Blue)");
+        assert_eq!(args4.len(), 3);
+        assert_eq!(&args4[0], "Greater(Health, 50)");
+        assert_eq!(&args4[1], "Set(Color, Red)");
+        assert_eq!(&args4[2], "Set(Color, Blue)");
+    }
+
+    #[test]
+    fn test_parse_complex_actions() {
+        let parser = CastagneParser::new();
+
+        // Nested function call as argument
+        let action = parser
+            .parse_action_line("Set(Health, Add(100, 50))", 1)
+            .unwrap();
+        assert_eq!(action.instruction, "Set");
+        assert_eq!(action.args.len(), 2);
+        assert_eq!(&action.args[0], "Health");
+        assert_eq!(&action.args[1], "Add(100, 50)");
+
+        // String with special characters
+        let action2 = parser
+            .parse_action_line(r#"Log("Player health: ", Health)"#, 1)
+            .unwrap();
+        assert_eq!(action2.instruction, "Log");
+        assert_eq!(action2.args.len(), 2);
+        assert_eq!(&action2.args[0], r#""Player health: ""#);
+        assert_eq!(&action2.args[1], "Health");
+    }
+
+    #[test]
+    fn test_full_file_parse() {
+        let mut parser = CastagneParser::new();
+
+        // Test with the test_character.casp file if it exists
+        // This test will be skipped if the file doesn't exist
+        if std::path::Path::new("test_character.casp").exists() {
+            let result = parser.create_full_character("test_character.casp");
+
+            assert!(result.is_ok(), "Failed to parse test_character.casp");
+            let character = result.unwrap();
+
+            // Check metadata
+            assert_eq!(character.metadata.name, "Test Character");
+            assert_eq!(character.metadata.author, "Parser Test");
+
+            // Check variables
+            assert!(character.variables.contains_key("Health"));
+            assert!(character.variables.contains_key("MoveSpeed"));
+            assert!(character.variables.contains_key("MAX_HEALTH"));
+
+            // Check states
+            assert!(character.states.contains_key("Idle"));
+            let idle_state = character.states.get("Idle").unwrap();
+            assert!(idle_state.actions.contains_key("Init"));
+            assert!(idle_state.actions.contains_key("Action"));
+        }
+    }
+
+    #[test]
+    fn test_segment_blocks() {
+        let lines: Vec<String> = ["# Preamble", ":Character:", "Name: A", "", ":Jab(Helper):"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let blocks = segment_blocks(&lines);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].start, blocks[0].end), (1, 4));
+        assert_eq!(blocks[0].body(), 2..4);
+        assert_eq!(blocks[1].header, "Jab(Helper)");
+        assert_eq!(blocks[1].name, "Jab");
+        assert_eq!((blocks[1].start, blocks[1].end), (4, 5));
+        assert!(!blocks[0].contains(0) && blocks[0].contains(3));
+    }
+
+    #[test]
+    fn test_parse_specblocks() {
+        let mut parser = CastagneParser::new();
+
+        parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Test".to_string(),
+            "".to_string(),
+            ":Attacks:".to_string(),
+            "Damage: 10".to_string(),
+            "Range: 5".to_string(),
+            "Knockback: 3".to_string(),
+            "".to_string(),
+            ":Config:".to_string(),
+            "MaxSpeed: 100".to_string(),
+            "JumpHeight: 50".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        parser.parse_specblocks(0);
+
+        // Should have parsed 2 specblocks (Attacks and Config)
+        assert_eq!(parser.specblocks.len(), 2);
+
+        // Check Attacks specblock
+        let attacks = parser.specblocks.get("Attacks").unwrap();
+        assert_eq!(attacks.get("Damage"), Some(&"10".to_string()));
+        assert_eq!(attacks.get("Range"), Some(&"5".to_string()));
+        assert_eq!(attacks.get("Knockback"), Some(&"3".to_string()));
+
+        // Check Config specblock
+        let config = parser.specblocks.get("Config").unwrap();
+        assert_eq!(config.get("MaxSpeed"), Some(&"100".to_string()));
+        assert_eq!(config.get("JumpHeight"), Some(&"50".to_string()));
+    }
+
+    #[test]
+    fn test_specblock_vs_state_detection() {
+        let mut parser = CastagneParser::new();
+
+        // This should be detected as a state (has phase markers)
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(Health, 100)".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = vec![1, 2, 3, 4];
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        assert!(!parser.is_specblock("Idle", 1));
+
+        // This should be detected as a specblock (has key-value pairs)
+        parser.current_lines = vec![
+            ":Config:".to_string(),
+            "MaxSpeed: 100".to_string(),
+            "JumpHeight: 50".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = vec![1, 2, 3, 4];
+
+        assert!(parser.is_specblock("Config", 1));
+    }
+
+    #[test]
+    fn test_full_parse_with_specblocks() {
+        let mut parser = CastagneParser::new();
+
+        parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Fighter".to_string(),
+            "".to_string(),
+            ":Attacks:".to_string(),
+            "LightDamage: 5".to_string(),
+            "HeavyDamage: 15".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),
+            "".to_string(),
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(Health, 100)".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
+
+        parser.parse_full_file();
+
+        let result = parser.end_parsing();
+        assert!(result.is_ok());
+
+        let character = result.unwrap();
+
+        // Should have metadata
+        assert_eq!(character.metadata.name, "Fighter");
+
+        // Should have specblock
+        assert_eq!(character.specblocks.len(), 1);
+        let attacks = character.specblocks.get("Attacks").unwrap();
+        assert_eq!(attacks.get("LightDamage"), Some(&"5".to_string()));
+        assert_eq!(attacks.get("HeavyDamage"), Some(&"15".to_string()));
+
+        // Should have variables
+        assert!(character.variables.contains_key("Health"));
+
+        // Should have states
+        assert!(character.states.contains_key("Idle"));
+    }
+
+    #[test]
+    fn test_skeleton_inheritance() {
+        // Test with actual files if they exist
+        if std::path::Path::new("test_parent.casp").exists()
+            && std::path::Path::new("test_child.casp").exists()
+        {
+            let mut parser = CastagneParser::new();
+            let result = parser.create_full_character("test_child.casp");
+
+            assert!(
+                result.is_ok(),
+                "Failed to parse child character with skeleton"
+            );
+            let character = result.unwrap();
+
+            // Check that child metadata is preserved (not parent's)
+            assert_eq!(character.metadata.name, "Child Character");
+            assert_eq!(character.metadata.author, "Parser Test");
+
+            // Check that child has parent's variables
+            assert!(
+                character.variables.contains_key("ParentOnly"),
+                "Child should inherit ParentOnly variable from parent"
+            );
+
+            // Check that child has its own variables
+            assert!(
+                character.variables.contains_key("ChildOnly"),
+                "Child should have its own ChildOnly variable"
+            );
+
+            // Check that child overrides parent's variables
+            let health = character.variables.get("Health").unwrap();
+            assert_eq!(
+                health.value, "150",
+                "Child should override parent's Health value"
+            );
+
+            // Check that child has parent's Speed variable
+            assert!(
+                character.variables.contains_key("Speed"),
+                "Child should inherit Speed variable from parent"
+            );
+
+            // Check specblock inheritance and override
+            let config = character.specblocks.get("Config").unwrap();
+            assert_eq!(
+                config.get("BaseSpeed"),
+                Some(&"10".to_string()),
+                "Child should override parent's BaseSpeed"
+            );
+            assert_eq!(
+                config.get("BaseJump"),
+                Some(&"10".to_string()),
+                "Child should inherit parent's BaseJump"
+            );
+            assert_eq!(
+                config.get("ChildSpeed"),
+                Some(&"8".to_string()),
+                "Child should have its own ChildSpeed"
+            );
+
+            // Check state inheritance
+            assert!(
+                character.states.contains_key("BaseAttack"),
+                "Child should inherit BaseAttack state from parent"
+            );
+            assert!(
+                character.states.contains_key("Idle"),
+                "Child should have its own Idle state"
+            );
+        }
+    }
+
+    #[test]
+    fn test_skeleton_inheritance_unit() {
+        // Unit test that doesn't require external files
+        let mut parent_parser = CastagneParser::new();
+        parent_parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Parent".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var ParentVar(Int): 10".to_string(),
+            "var SharedVar(Int): 5".to_string(),
+            "".to_string(),
+        ];
+        parent_parser.line_ids = (1..=parent_parser.current_lines.len()).collect();
+        parent_parser.file_paths = vec!["parent.casp".to_string()];
+
+        parent_parser.parse_full_file();
+
+        // Manually simulate child inheriting from parent
+        let mut child_parser = CastagneParser::new();
+        child_parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Child".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var ChildVar(Str): ChildValue".to_string(),
+            "var SharedVar(Int): 20".to_string(),
+            "".to_string(),
+        ];
+        child_parser.line_ids = (1..=child_parser.current_lines.len()).collect();
+        child_parser.file_paths = vec!["child.casp".to_string()];
+
+        // Parse child metadata first
+        child_parser.parse_metadata(0);
+
+        // Manually merge parent variables (simulating skeleton loading)
+        child_parser.variables = parent_parser.variables.clone();
+
+        // Then parse child variables (which should override)
+        child_parser.parse_variables(0);
+
+        // Child should have both parent and child variables
+        assert!(
+            child_parser.variables.contains_key("ParentVar"),
+            "Child should have parent's ParentVar"
+        );
+        assert!(
+            child_parser.variables.contains_key("ChildVar"),
+            "Child should have its own ChildVar"
+        );
+
+        // Child should override parent's SharedVar
+        let shared = child_parser.variables.get("SharedVar").unwrap();
+        assert_eq!(
+            shared.value, "20",
+            "Child should override parent's SharedVar with value 20"
+        );
+    }
+
+    #[test]
+    fn test_type_conversion_int() {
+        let var = ParsedVariable {
+            name: "TestInt".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Int,
+            subtype: String::new(),
+            value: "42".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        // Test the helper methods that don't require Godot runtime
+        assert_eq!(var.as_int(), Some(42));
+
+        // Note: to_variant() requires Godot runtime and is tested in integration tests
+    }
+
+    #[test]
+    fn test_type_conversion_bool() {
+        let var_true = ParsedVariable {
+            name: "TestBool".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Bool,
+            subtype: String::new(),
+            value: "true".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        assert_eq!(var_true.as_bool(), Some(true));
+
+        let var_false = ParsedVariable {
+            name: "TestBool2".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Bool,
+            subtype: String::new(),
+            value: "false".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        assert_eq!(var_false.as_bool(), Some(false));
+
+        // Test numeric bool representations
+        let var_one = ParsedVariable {
+            name: "TestBool3".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Bool,
+            subtype: String::new(),
+            value: "1".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        assert_eq!(var_one.as_bool(), Some(true));
+    }
+
+    #[test]
+    fn test_type_conversion_string() {
+        let var = ParsedVariable {
+            name: "TestStr".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Str,
+            subtype: String::new(),
+            value: "Hello World".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        assert_eq!(var.value, "Hello World");
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_type_conversion_float() {
+        let var = ParsedVariable {
+            name: "TestFloat".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Var,
+            subtype: String::new(),
+            value: "3.14".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+
+        assert_eq!(var.as_float(), Some(3.14));
+    }
+
+    #[test]
+    fn test_parse_vec2_string() {
+        // Test parsing Vec2 values (string representation)
+        // Note: Actual Variant creation requires Godot runtime, tested in integration tests
+
+        // Test that we can identify valid Vec2 format
+        let vec2_str = "(10, 20)";
+        let cleaned = vec2_str.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts: Vec<&str> = cleaned.split(',').collect();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].trim().parse::<f32>().is_ok());
+        assert!(parts[1].trim().parse::<f32>().is_ok());
+
+        // Test another valid format
+        let vec2_str2 = "5.5, 7.5";
+        let cleaned2 = vec2_str2.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts2: Vec<&str> = cleaned2.split(',').collect();
+        assert_eq!(parts2.len(), 2);
+        assert!(parts2[0].trim().parse::<f32>().is_ok());
+        assert!(parts2[1].trim().parse::<f32>().is_ok());
+
+        // Test invalid format
+        let invalid = "invalid";
+        let cleaned_invalid = invalid.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts_invalid: Vec<&str> = cleaned_invalid.split(',').collect();
+        assert!(parts_invalid.len() != 2 || parts_invalid[0].trim().parse::<f32>().is_err());
+    }
+
+    #[test]
+    fn test_parse_vec3_string() {
+        // Test parsing Vec3 values (string representation)
+        // Note: Actual Variant creation requires Godot runtime, tested in integration tests
+
+        // Test valid Vec3 format
+        let vec3_str = "(10, 20, 30)";
+        let cleaned = vec3_str.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts: Vec<&str> = cleaned.split(',').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].trim().parse::<f32>().is_ok());
+        assert!(parts[1].trim().parse::<f32>().is_ok());
+        assert!(parts[2].trim().parse::<f32>().is_ok());
+
+        // Test without parentheses
+        let vec3_str2 = "5.5, 7.5, 9.5";
+        let cleaned2 = vec3_str2.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts2: Vec<&str> = cleaned2.split(',').collect();
+        assert_eq!(parts2.len(), 3);
+
+        // Test invalid (only 2 components)
+        let invalid = "10, 20";
+        let cleaned_invalid = invalid.trim().trim_matches(|c| c == '(' || c == ')');
+        let parts_invalid: Vec<&str> = cleaned_invalid.split(',').collect();
+        assert_ne!(parts_invalid.len(), 3);
+    }
+
+    #[test]
+    fn test_type_inference_var_type() {
+        // For Var type, parser should infer the actual type
+
+        // Should infer as int
+        let int_var = ParsedVariable {
+            name: "AutoInt".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Var,
+            subtype: String::new(),
+            value: "100".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+        assert_eq!(int_var.as_int(), Some(100));
+
+        // Should infer as bool
+        let bool_var = ParsedVariable {
+            name: "AutoBool".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Var,
+            subtype: String::new(),
+            value: "true".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+        assert_eq!(bool_var.as_bool(), Some(true));
+
+        // Should infer as float
+        let float_var = ParsedVariable {
+            name: "AutoFloat".into(),
+            mutability: VariableMutability::Variable,
+            var_type: VariableType::Var,
+            subtype: String::new(),
+            value: "2.5".to_string(),
+            section: None,
+            expression: None,
+            origin: SourceRef::default(),
+        };
+        assert_eq!(float_var.as_float(), Some(2.5));
+    }
+
+    #[test]
+    fn test_complete_character_file() {
+        // Test parsing the comprehensive character file
+        if std::path::Path::new("test_character_complete.casp").exists() {
+            let mut parser = CastagneParser::new();
+            let result = parser.create_full_character("test_character_complete.casp");
+
+            assert!(result.is_ok(), "Failed to parse complete character file");
+            let character = result.unwrap();
+
+            // Verify metadata
+            assert_eq!(character.metadata.name, "Complete Test Fighter");
+            assert_eq!(character.metadata.author, "Parser Development Team");
+
+            // Verify specblocks
+            assert!(character.specblocks.contains_key("AttackData"));
+            assert!(character.specblocks.contains_key("PhysicsConfig"));
+
+            let attack_data = character.specblocks.get("AttackData").unwrap();
+            assert_eq!(attack_data.get("LightPunchDamage"), Some(&"5".to_string()));
+            assert_eq!(attack_data.get("HeavyPunchDamage"), Some(&"15".to_string()));
+
+            let physics = character.specblocks.get("PhysicsConfig").unwrap();
+            assert_eq!(physics.get("Gravity"), Some(&"10".to_string()));
+            assert_eq!(physics.get("JumpForce"), Some(&"25".to_string()));
+
+            // Verify variables with different types
+            assert!(character.variables.contains_key("Health"));
+            assert!(character.variables.contains_key("PlayerName"));
+            assert!(character.variables.contains_key("IsGrounded"));
+            assert!(character.variables.contains_key("Position"));
+            assert!(character.variables.contains_key("Meter"));
+            assert!(character.variables.contains_key("MAX_COMBO"));
+
+            let health = character.variables.get("Health").unwrap();
+            assert_eq!(health.var_type, VariableType::Int);
+            assert_eq!(health.value, "150");
+
+            let is_grounded = character.variables.get("IsGrounded").unwrap();
+            assert_eq!(is_grounded.var_type, VariableType::Bool);
+            assert_eq!(is_grounded.value, "true");
+
+            let position = character.variables.get("Position").unwrap();
+            assert_eq!(position.var_type, VariableType::Vec2);
+            assert_eq!(position.value, "0, 0");
+
+            // Verify states
+            assert!(character.states.contains_key("Idle"));
+            assert!(character.states.contains_key("Walk"));
+            assert!(character.states.contains_key("Jump"));
+            assert!(character.states.contains_key("LightPunch"));
+            assert!(character.states.contains_key("HeavyPunch"));
+
+            // Verify state phases
+            let idle = character.states.get("Idle").unwrap();
+            assert!(idle.actions.contains_key("Init"));
+            assert!(idle.actions.contains_key("Action"));
+
+            let jump = character.states.get("Jump").unwrap();
+            assert!(jump.actions.contains_key("Init"));
+            assert!(jump.actions.contains_key("Action"));
+
+            // Verify complex actions
+            let light_punch = character.states.get("LightPunch").unwrap();
+            assert!(light_punch.actions.contains_key("Init"));
+            assert!(light_punch.actions.contains_key("Action"));
+            assert!(light_punch.actions.contains_key("Reaction"));
+        }
+    }
+
+    #[test]
+    fn test_parse_state_header_simple() {
+        let parser = CastagneParser::new();
+
+        // Simple state name
+        let (name, state_type, parent) = parser.parse_state_header("Idle");
+        assert_eq!(name, "Idle");
+        assert_eq!(state_type, StateType::Normal);
+        assert_eq!(parent, None);
+    }
+
+    #[test]
+    fn test_parse_state_directly_simple() {
+        let mut parser = CastagneParser::new();
+
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(x, 1)".to_string(),
             "".to_string(),
         ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.line_ids = vec![1, 2, 3, 4];
         parser.file_paths = vec!["test.casp".to_string()];
 
-        parser.parse_specblocks(0);
+        parser.parse_states(0);
 
-        // Should have parsed 2 specblocks (Attacks and Config)
-        assert_eq!(parser.specblocks.len(), 2);
+        // This should work since we tested it before
+        assert_eq!(parser.states.len(), 1);
+        assert!(parser.states.contains_key("Idle"));
+    }
 
-        // Check Attacks specblock
-        let attacks = parser.specblocks.get("Attacks").unwrap();
-        assert_eq!(attacks.get("Damage"), Some(&"10".to_string()));
-        assert_eq!(attacks.get("Range"), Some(&"5".to_string()));
-        assert_eq!(attacks.get("Knockback"), Some(&"3".to_string()));
+    #[test]
+    fn test_parse_state_header_with_type() {
+        let parser = CastagneParser::new();
+
+        // State with Helper type
+        let (name, state_type, parent) = parser.parse_state_header("GroundBounce(Helper)");
+        assert_eq!(name, "GroundBounce");
+        assert_eq!(state_type, StateType::Helper);
+        assert_eq!(parent, None);
+
+        // State with BaseState type
+        let (name2, state_type2, parent2) = parser.parse_state_header("CommonActions(BaseState)");
+        assert_eq!(name2, "CommonActions");
+        assert_eq!(state_type2, StateType::BaseState);
+        assert_eq!(parent2, None);
+
+        // State with Special type
+        let (name3, state_type3, parent3) = parser.parse_state_header("SuperMove(Special)");
+        assert_eq!(name3, "SuperMove");
+        assert_eq!(state_type3, StateType::Special);
+        assert_eq!(parent3, None);
+
+        // State with Specblock type
+        let (name4, state_type4, parent4) = parser.parse_state_header("ConfigBlock(Specblock)");
+        assert_eq!(name4, "ConfigBlock");
+        assert_eq!(state_type4, StateType::Specblock);
+        assert_eq!(parent4, None);
+    }
+
+    #[test]
+    fn test_parse_state_header_with_parent() {
+        let parser = CastagneParser::new();
+
+        // State with parent (no type specified)
+        let (name, state_type, parent) = parser.parse_state_header("Walk(Idle)");
+        assert_eq!(name, "Walk");
+        assert_eq!(state_type, StateType::Normal);
+        assert_eq!(parent, Some("Idle".to_string()));
+
+        // State with different parent
+        let (name2, state_type2, parent2) = parser.parse_state_header("Run(Walk)");
+        assert_eq!(name2, "Run");
+        assert_eq!(state_type2, StateType::Normal);
+        assert_eq!(parent2, Some("Walk".to_string()));
+    }
+
+    #[test]
+    fn test_parse_state_header_with_type_and_parent() {
+        let parser = CastagneParser::new();
+
+        // State with both type and parent
+        let (name, state_type, parent) = parser.parse_state_header("Projectile(Helper, Idle)");
+        assert_eq!(name, "Projectile");
+        assert_eq!(state_type, StateType::Helper);
+        assert_eq!(parent, Some("Idle".to_string()));
 
-        // Check Config specblock
-        let config = parser.specblocks.get("Config").unwrap();
-        assert_eq!(config.get("MaxSpeed"), Some(&"100".to_string()));
-        assert_eq!(config.get("JumpHeight"), Some(&"50".to_string()));
+        // Another combination
+        let (name2, state_type2, parent2) =
+            parser.parse_state_header("CustomMove(Special, BaseAttack)");
+        assert_eq!(name2, "CustomMove");
+        assert_eq!(state_type2, StateType::Special);
+        assert_eq!(parent2, Some("BaseAttack".to_string()));
     }
 
     #[test]
-    fn test_specblock_vs_state_detection() {
+    fn test_parse_state_with_attributes() {
         let mut parser = CastagneParser::new();
 
-        // This should be detected as a state (has phase markers)
         parser.current_lines = vec![
-            ":Idle:".to_string(),
+            ":GroundBounce(Helper):".to_string(),
             "---Init:".to_string(),
-            "Set(Health, 100)".to_string(),
+            "Set(Velocity, 0, 10)".to_string(),
             "".to_string(),
         ];
         parser.line_ids = vec![1, 2, 3, 4];
         parser.file_paths = vec!["test.casp".to_string()];
 
-        assert!(!parser.is_specblock("Idle", 1));
-
-        // This should be detected as a specblock (has key-value pairs)
-        parser.current_lines = vec![
-            ":Config:".to_string(),
-            "MaxSpeed: 100".to_string(),
-            "JumpHeight: 50".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = vec![1, 2, 3, 4];
+        // Don't parse specblocks first - just parse states directly
+        parser.parse_states(0);
 
-        assert!(parser.is_specblock("Config", 1));
+        assert_eq!(
+            parser.states.len(),
+            1,
+            "Expected 1 state, found {}: {:?}",
+            parser.states.len(),
+            parser.states.keys().collect::<Vec<_>>()
+        );
+        let state = parser.states.get("GroundBounce").unwrap();
+        assert_eq!(state.name, "GroundBounce");
+        assert_eq!(state.state_type, StateType::Helper);
+        assert_eq!(state.parent, None);
     }
 
     #[test]
-    fn test_full_parse_with_specblocks() {
+    fn test_parse_state_with_parent_state() {
         let mut parser = CastagneParser::new();
 
         parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Fighter".to_string(),
-            "".to_string(),
-            ":Attacks:".to_string(),
-            "LightDamage: 5".to_string(),
-            "HeavyDamage: 15".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
+            ":Walk(Idle):".to_string(),
             "---Init:".to_string(),
-            "Set(Health, 100)".to_string(),
+            "Set(Speed, 5)".to_string(),
+            "---Action:".to_string(),
+            "Move()".to_string(),
             "".to_string(),
         ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
         parser.file_paths = vec!["test.casp".to_string()];
 
-        parser.parse_full_file();
-
-        let result = parser.end_parsing();
-        assert!(result.is_some());
-
-        let character = result.unwrap();
-
-        // Should have metadata
-        assert_eq!(character.metadata.name, "Fighter");
-
-        // Should have specblock
-        assert_eq!(character.specblocks.len(), 1);
-        let attacks = character.specblocks.get("Attacks").unwrap();
-        assert_eq!(attacks.get("LightDamage"), Some(&"5".to_string()));
-        assert_eq!(attacks.get("HeavyDamage"), Some(&"15".to_string()));
-
-        // Should have variables
-        assert!(character.variables.contains_key("Health"));
+        parser.parse_states(0);
 
-        // Should have states
-        assert!(character.states.contains_key("Idle"));
+        assert_eq!(parser.states.len(), 1);
+        let state = parser.states.get("Walk").unwrap();
+        assert_eq!(state.name, "Walk");
+        assert_eq!(state.state_type, StateType::Normal);
+        assert_eq!(state.parent, Some("Idle".to_string()));
+        assert!(state.actions.contains_key("Init"));
+        assert!(state.actions.contains_key("Action"));
     }
 
     #[test]
-    fn test_skeleton_inheritance() {
-        // Test with actual files if they exist
-        if std::path::Path::new("test_parent.casp").exists()
-            && std::path::Path::new("test_child.casp").exists()
-        {
-            let mut parser = CastagneParser::new();
-            let result = parser.create_full_character("test_child.casp");
-
-            assert!(
-                result.is_some(),
-                "Failed to parse child character with skeleton"
-            );
-            let character = result.unwrap();
-
-            // Check that child metadata is preserved (not parent's)
-            assert_eq!(character.metadata.name, "Child Character");
-            assert_eq!(character.metadata.author, "Parser Test");
-
-            // Check that child has parent's variables
-            assert!(
-                character.variables.contains_key("ParentOnly"),
-                "Child should inherit ParentOnly variable from parent"
-            );
+    fn test_parse_state_with_type_and_parent_combined() {
+        let mut parser = CastagneParser::new();
 
-            // Check that child has its own variables
-            assert!(
-                character.variables.contains_key("ChildOnly"),
-                "Child should have its own ChildOnly variable"
-            );
+        parser.current_lines = vec![
+            ":Fireball(Helper, Idle):".to_string(),
+            "---Init:".to_string(),
+            "Set(Damage, 10)".to_string(),
+            "Set(Speed, 20)".to_string(),
+            "---Action:".to_string(),
+            "MoveForward()".to_string(),
+            "CheckCollision()".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
 
-            // Check that child overrides parent's variables
-            let health = character.variables.get("Health").unwrap();
-            assert_eq!(
-                health.value, "150",
-                "Child should override parent's Health value"
-            );
+        parser.parse_states(0);
 
-            // Check that child has parent's Speed variable
-            assert!(
-                character.variables.contains_key("Speed"),
-                "Child should inherit Speed variable from parent"
-            );
+        assert_eq!(parser.states.len(), 1);
+        let state = parser.states.get("Fireball").unwrap();
+        assert_eq!(state.name, "Fireball");
+        assert_eq!(state.state_type, StateType::Helper);
+        assert_eq!(state.parent, Some("Idle".to_string()));
 
-            // Check specblock inheritance and override
-            let config = character.specblocks.get("Config").unwrap();
-            assert_eq!(
-                config.get("BaseSpeed"),
-                Some(&"10".to_string()),
-                "Child should override parent's BaseSpeed"
-            );
-            assert_eq!(
-                config.get("BaseJump"),
-                Some(&"10".to_string()),
-                "Child should inherit parent's BaseJump"
-            );
-            assert_eq!(
-                config.get("ChildSpeed"),
-                Some(&"8".to_string()),
-                "Child should have its own ChildSpeed"
-            );
+        // Verify actions were parsed correctly
+        let init_actions = state.actions.get("Init").unwrap();
+        assert_eq!(init_actions.len(), 2);
+        assert_eq!(init_actions[0].instruction, "Set");
+        assert_eq!(&init_actions[0].args[0], "Damage");
+        assert_eq!(&init_actions[0].args[1], "10");
 
-            // Check state inheritance
-            assert!(
-                character.states.contains_key("BaseAttack"),
-                "Child should inherit BaseAttack state from parent"
-            );
-            assert!(
-                character.states.contains_key("Idle"),
-                "Child should have its own Idle state"
-            );
-        }
+        let action_actions = state.actions.get("Action").unwrap();
+        assert_eq!(action_actions.len(), 2);
+        assert_eq!(action_actions[0].instruction, "MoveForward");
+        assert_eq!(action_actions[1].instruction, "CheckCollision");
     }
 
     #[test]
-    fn test_skeleton_inheritance_unit() {
-        // Unit test that doesn't require external files
-        let mut parent_parser = CastagneParser::new();
-        parent_parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Parent".to_string(),
+    fn test_multiple_states_with_different_types() {
+        let mut parser = CastagneParser::new();
+
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(State, 0)".to_string(),
             "".to_string(),
-            ":Variables:".to_string(),
-            "var ParentVar(Int): 10".to_string(),
-            "var SharedVar(Int): 5".to_string(),
+            ":Walk(Idle):".to_string(),
+            "---Init:".to_string(),
+            "Set(State, 1)".to_string(),
             "".to_string(),
-        ];
-        parent_parser.line_ids = (1..=parent_parser.current_lines.len()).collect();
-        parent_parser.file_paths = vec!["parent.casp".to_string()];
-
-        parent_parser.parse_full_file();
-
-        // Manually simulate child inheriting from parent
-        let mut child_parser = CastagneParser::new();
-        child_parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Child".to_string(),
+            ":Attack(Helper):".to_string(),
+            "---Init:".to_string(),
+            "Set(State, 2)".to_string(),
             "".to_string(),
-            ":Variables:".to_string(),
-            "var ChildVar(Str): ChildValue".to_string(),
-            "var SharedVar(Int): 20".to_string(),
+            ":SuperMove(Special, Attack):".to_string(),
+            "---Init:".to_string(),
+            "Set(State, 3)".to_string(),
             "".to_string(),
         ];
-        child_parser.line_ids = (1..=child_parser.current_lines.len()).collect();
-        child_parser.file_paths = vec!["child.casp".to_string()];
-
-        // Parse child metadata first
-        child_parser.parse_metadata(0);
-
-        // Manually merge parent variables (simulating skeleton loading)
-        child_parser.variables = parent_parser.variables.clone();
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
 
-        // Then parse child variables (which should override)
-        child_parser.parse_variables(0);
+        parser.parse_states(0);
 
-        // Child should have both parent and child variables
-        assert!(
-            child_parser.variables.contains_key("ParentVar"),
-            "Child should have parent's ParentVar"
-        );
-        assert!(
-            child_parser.variables.contains_key("ChildVar"),
-            "Child should have its own ChildVar"
-        );
+        assert_eq!(parser.states.len(), 4);
 
-        // Child should override parent's SharedVar
-        let shared = child_parser.variables.get("SharedVar").unwrap();
-        assert_eq!(
-            shared.value, "20",
-            "Child should override parent's SharedVar with value 20"
-        );
-    }
+        // Check Idle (simple state)
+        let idle = parser.states.get("Idle").unwrap();
+        assert_eq!(idle.state_type, StateType::Normal);
+        assert_eq!(idle.parent, None);
 
-    #[test]
-    fn test_type_conversion_int() {
-        let var = ParsedVariable {
-            name: "TestInt".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Int,
-            subtype: String::new(),
-            value: "42".to_string(),
-        };
+        // Check Walk (has parent)
+        let walk = parser.states.get("Walk").unwrap();
+        assert_eq!(walk.state_type, StateType::Normal);
+        assert_eq!(walk.parent, Some("Idle".to_string()));
 
-        // Test the helper methods that don't require Godot runtime
-        assert_eq!(var.as_int(), Some(42));
+        // Check Attack (has type)
+        let attack = parser.states.get("Attack").unwrap();
+        assert_eq!(attack.state_type, StateType::Helper);
+        assert_eq!(attack.parent, None);
 
-        // Note: to_variant() requires Godot runtime and is tested in integration tests
+        // Check SuperMove (has both type and parent)
+        let super_move = parser.states.get("SuperMove").unwrap();
+        assert_eq!(super_move.state_type, StateType::Special);
+        assert_eq!(super_move.parent, Some("Attack".to_string()));
     }
 
     #[test]
-    fn test_type_conversion_bool() {
-        let var_true = ParsedVariable {
-            name: "TestBool".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Bool,
-            subtype: String::new(),
-            value: "true".to_string(),
-        };
+    fn test_strip_inline_comment() {
+        let parser = CastagneParser::new();
 
-        assert_eq!(var_true.as_bool(), Some(true));
+        // Simple comment
+        assert_eq!(
+            parser.strip_inline_comment("Set(x, 5) # This sets x"),
+            "Set(x, 5) "
+        );
 
-        let var_false = ParsedVariable {
-            name: "TestBool2".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Bool,
-            subtype: String::new(),
-            value: "false".to_string(),
-        };
+        // Comment with no space
+        assert_eq!(
+            parser.strip_inline_comment("Set(x, 5)# comment"),
+            "Set(x, 5)"
+        );
 
-        assert_eq!(var_false.as_bool(), Some(false));
+        // Comment in string should be preserved
+        assert_eq!(
+            parser.strip_inline_comment(r#"Set(msg, "Hello # World")"#),
+            r#"Set(msg, "Hello # World")"#
+        );
 
-        // Test numeric bool representations
-        let var_one = ParsedVariable {
-            name: "TestBool3".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Bool,
-            subtype: String::new(),
-            value: "1".to_string(),
-        };
+        // Empty line with just comment
+        assert_eq!(parser.strip_inline_comment("# Just a comment"), "");
 
-        assert_eq!(var_one.as_bool(), Some(true));
-    }
+        // No comment
+        assert_eq!(parser.strip_inline_comment("Set(x, 5)"), "Set(x, 5)");
 
-    #[test]
-    fn test_type_conversion_string() {
-        let var = ParsedVariable {
-            name: "TestStr".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Str,
-            subtype: String::new(),
-            value: "Hello World".to_string(),
-        };
+        // Multiple # in string
+        assert_eq!(
+            parser.strip_inline_comment("Log(\"Test#123\") # comment"),
+            "Log(\"Test#123\") "
+        );
 
-        assert_eq!(var.value, "Hello World");
+        // Escaped quote in string
+        assert_eq!(
+            parser.strip_inline_comment(r#"Set(x, "He said \"Hi\"") # comment"#),
+            r#"Set(x, "He said \"Hi\"") "#
+        );
     }
 
     #[test]
-    fn test_type_conversion_float() {
-        let var = ParsedVariable {
-            name: "TestFloat".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Var,
-            subtype: String::new(),
-            value: "3.14".to_string(),
-        };
+    fn test_parse_with_inline_comments() {
+        let mut parser = CastagneParser::new();
 
-        assert_eq!(var.as_float(), Some(3.14));
-    }
+        parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Test Fighter # Main character".to_string(),
+            "Author: Test # Test author".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 100 # Player health".to_string(),
+            "def MAX_HP: 150 # Maximum health".to_string(),
+            "".to_string(),
+            ":Idle:".to_string(),
+            "---Init: # Initialization phase".to_string(),
+            "Set(Health, 100) # Set initial health".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
 
-    #[test]
-    fn test_parse_vec2_string() {
-        // Test parsing Vec2 values (string representation)
-        // Note: Actual Variant creation requires Godot runtime, tested in integration tests
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        // Test that we can identify valid Vec2 format
-        let vec2_str = "(10, 20)";
-        let cleaned = vec2_str.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts: Vec<&str> = cleaned.split(',').collect();
-        assert_eq!(parts.len(), 2);
-        assert!(parts[0].trim().parse::<f32>().is_ok());
-        assert!(parts[1].trim().parse::<f32>().is_ok());
+        assert!(result.is_ok());
+        let character = result.unwrap();
 
-        // Test another valid format
-        let vec2_str2 = "5.5, 7.5";
-        let cleaned2 = vec2_str2.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts2: Vec<&str> = cleaned2.split(',').collect();
-        assert_eq!(parts2.len(), 2);
-        assert!(parts2[0].trim().parse::<f32>().is_ok());
-        assert!(parts2[1].trim().parse::<f32>().is_ok());
+        // Verify metadata was parsed correctly (without comments)
+        assert_eq!(character.metadata.name, "Test Fighter");
+        assert_eq!(character.metadata.author, "Test");
 
-        // Test invalid format
-        let invalid = "invalid";
-        let cleaned_invalid = invalid.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts_invalid: Vec<&str> = cleaned_invalid.split(',').collect();
-        assert!(parts_invalid.len() != 2 || parts_invalid[0].trim().parse::<f32>().is_err());
+        // Verify variables were parsed correctly (without comments)
+        assert!(character.variables.contains_key("Health"));
+        let health = character.variables.get("Health").unwrap();
+        assert_eq!(health.value, "100");
+
+        assert!(character.variables.contains_key("MAX_HP"));
+        let max_hp = character.variables.get("MAX_HP").unwrap();
+        assert_eq!(max_hp.value, "150");
+
+        // Verify states were parsed correctly (without comments)
+        assert!(character.states.contains_key("Idle"));
+        let idle = character.states.get("Idle").unwrap();
+        let init_actions = idle.actions.get("Init").unwrap();
+        assert_eq!(init_actions.len(), 1);
+        assert_eq!(init_actions[0].instruction, "Set");
+        assert_eq!(&init_actions[0].args[0], "Health");
+        assert_eq!(&init_actions[0].args[1], "100");
     }
 
     #[test]
-    fn test_parse_vec3_string() {
-        // Test parsing Vec3 values (string representation)
-        // Note: Actual Variant creation requires Godot runtime, tested in integration tests
-
-        // Test valid Vec3 format
-        let vec3_str = "(10, 20, 30)";
-        let cleaned = vec3_str.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts: Vec<&str> = cleaned.split(',').collect();
-        assert_eq!(parts.len(), 3);
-        assert!(parts[0].trim().parse::<f32>().is_ok());
-        assert!(parts[1].trim().parse::<f32>().is_ok());
-        assert!(parts[2].trim().parse::<f32>().is_ok());
+    fn test_inline_comment_with_strings() {
+        let mut parser = CastagneParser::new();
 
-        // Test without parentheses
-        let vec3_str2 = "5.5, 7.5, 9.5";
-        let cleaned2 = vec3_str2.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts2: Vec<&str> = cleaned2.split(',').collect();
-        assert_eq!(parts2.len(), 3);
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            r#"Log("Test # not a comment") # This is a comment"#.to_string(),
+            r#"Set(Message, "Hello, World!") # Set greeting"#.to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
 
-        // Test invalid (only 2 components)
-        let invalid = "10, 20";
-        let cleaned_invalid = invalid.trim().trim_matches(|c| c == '(' || c == ')');
-        let parts_invalid: Vec<&str> = cleaned_invalid.split(',').collect();
-        assert_ne!(parts_invalid.len(), 3);
-    }
+        parser.parse_states(0);
 
-    #[test]
-    fn test_type_inference_var_type() {
-        // For Var type, parser should infer the actual type
+        let idle = parser.states.get("Idle").unwrap();
+        let init_actions = idle.actions.get("Init").unwrap();
 
-        // Should infer as int
-        let int_var = ParsedVariable {
-            name: "AutoInt".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Var,
-            subtype: String::new(),
-            value: "100".to_string(),
-        };
-        assert_eq!(int_var.as_int(), Some(100));
+        assert_eq!(init_actions.len(), 2);
 
-        // Should infer as bool
-        let bool_var = ParsedVariable {
-            name: "AutoBool".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Var,
-            subtype: String::new(),
-            value: "true".to_string(),
-        };
-        assert_eq!(bool_var.as_bool(), Some(true));
+        // First action: Log with # in string
+        assert_eq!(init_actions[0].instruction, "Log");
+        assert_eq!(init_actions[0].args.len(), 1);
+        assert_eq!(&init_actions[0].args[0], r#""Test # not a comment""#);
 
-        // Should infer as float
-        let float_var = ParsedVariable {
-            name: "AutoFloat".to_string(),
-            mutability: VariableMutability::Variable,
-            var_type: VariableType::Var,
-            subtype: String::new(),
-            value: "2.5".to_string(),
-        };
-        assert_eq!(float_var.as_float(), Some(2.5));
+        // Second action: Set with comment after
+        assert_eq!(init_actions[1].instruction, "Set");
+        assert_eq!(init_actions[1].args.len(), 2);
+        assert_eq!(&init_actions[1].args[0], "Message");
+        assert_eq!(&init_actions[1].args[1], r#""Hello, World!""#);
     }
 
     #[test]
-    fn test_complete_character_file() {
-        // Test parsing the comprehensive character file
-        if std::path::Path::new("test_character_complete.casp").exists() {
-            let mut parser = CastagneParser::new();
-            let result = parser.create_full_character("test_character_complete.casp");
-
-            assert!(result.is_some(), "Failed to parse complete character file");
-            let character = result.unwrap();
-
-            // Verify metadata
-            assert_eq!(character.metadata.name, "Complete Test Fighter");
-            assert_eq!(character.metadata.author, "Parser Development Team");
+    fn test_specblock_with_inline_comments() {
+        let mut parser = CastagneParser::new();
 
-            // Verify specblocks
-            assert!(character.specblocks.contains_key("AttackData"));
-            assert!(character.specblocks.contains_key("PhysicsConfig"));
+        parser.current_lines = vec![
+            ":Config:".to_string(),
+            "MaxSpeed: 100 # Maximum movement speed".to_string(),
+            "JumpHeight: 50 # Jump force".to_string(),
+            "Gravity: 10 # Gravity value".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["test.casp".to_string()];
 
-            let attack_data = character.specblocks.get("AttackData").unwrap();
-            assert_eq!(attack_data.get("LightPunchDamage"), Some(&"5".to_string()));
-            assert_eq!(attack_data.get("HeavyPunchDamage"), Some(&"15".to_string()));
+        parser.parse_specblocks(0);
 
-            let physics = character.specblocks.get("PhysicsConfig").unwrap();
-            assert_eq!(physics.get("Gravity"), Some(&"10".to_string()));
-            assert_eq!(physics.get("JumpForce"), Some(&"25".to_string()));
+        assert!(parser.specblocks.contains_key("Config"));
+        let config = parser.specblocks.get("Config").unwrap();
 
-            // Verify variables with different types
-            assert!(character.variables.contains_key("Health"));
-            assert!(character.variables.contains_key("PlayerName"));
-            assert!(character.variables.contains_key("IsGrounded"));
-            assert!(character.variables.contains_key("Position"));
-            assert!(character.variables.contains_key("Meter"));
-            assert!(character.variables.contains_key("MAX_COMBO"));
+        // Values should be parsed without comments
+        assert_eq!(config.get("MaxSpeed"), Some(&"100".to_string()));
+        assert_eq!(config.get("JumpHeight"), Some(&"50".to_string()));
+        assert_eq!(config.get("Gravity"), Some(&"10".to_string()));
+    }
 
-            let health = character.variables.get("Health").unwrap();
-            assert_eq!(health.var_type, VariableType::Int);
-            assert_eq!(health.value, "150");
+    // =========================================================================
+    // Error Handling Tests
+    // =========================================================================
 
-            let is_grounded = character.variables.get("IsGrounded").unwrap();
-            assert_eq!(is_grounded.var_type, VariableType::Bool);
-            assert_eq!(is_grounded.value, "true");
+    #[test]
+    fn test_error_empty_file() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![];
+        parser.line_ids = vec![];
+        parser.file_paths = vec!["empty.casp".to_string()];
 
-            let position = character.variables.get("Position").unwrap();
-            assert_eq!(position.var_type, VariableType::Vec2);
-            assert_eq!(position.value, "0, 0");
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-            // Verify states
-            assert!(character.states.contains_key("Idle"));
-            assert!(character.states.contains_key("Walk"));
-            assert!(character.states.contains_key("Jump"));
-            assert!(character.states.contains_key("LightPunch"));
-            assert!(character.states.contains_key("HeavyPunch"));
+        // Empty file should still parse (with no data)
+        assert!(result.is_ok());
+        let character = result.unwrap();
+        assert_eq!(character.states.len(), 0);
+        assert_eq!(character.variables.len(), 0);
+    }
 
-            // Verify state phases
-            let idle = character.states.get("Idle").unwrap();
-            assert!(idle.actions.contains_key("Init"));
-            assert!(idle.actions.contains_key("Action"));
+    #[test]
+    fn test_error_only_comments() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![
+            "# This is just a comment".to_string(),
+            "# Another comment".to_string(),
+            "".to_string(),
+            "# More comments".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["comments.casp".to_string()];
 
-            let jump = character.states.get("Jump").unwrap();
-            assert!(jump.actions.contains_key("Init"));
-            assert!(jump.actions.contains_key("Action"));
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-            // Verify complex actions
-            let light_punch = character.states.get("LightPunch").unwrap();
-            assert!(light_punch.actions.contains_key("Init"));
-            assert!(light_punch.actions.contains_key("Action"));
-            assert!(light_punch.actions.contains_key("Reaction"));
-        }
+        // File with only comments should parse successfully
+        assert!(result.is_ok());
+        let character = result.unwrap();
+        assert_eq!(character.states.len(), 0);
+        assert_eq!(character.variables.len(), 0);
     }
 
     #[test]
-    fn test_parse_state_header_simple() {
-        let parser = CastagneParser::new();
+    fn test_error_missing_character_block() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["no_character.casp".to_string()];
 
-        // Simple state name
-        let (name, state_type, parent) = parser.parse_state_header("Idle");
-        assert_eq!(name, "Idle");
-        assert_eq!(state_type, StateType::Normal);
-        assert_eq!(parent, None);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
+
+        // Should still parse even without Character block
+        assert!(result.is_ok());
+        let character = result.unwrap();
+        assert!(character.variables.contains_key("Health"));
     }
 
     #[test]
-    fn test_parse_state_directly_simple() {
+    fn test_error_invalid_variable_syntax() {
         let mut parser = CastagneParser::new();
+        parser.current_lines = vec![
+            ":Variables:".to_string(),
+            "var NoColon 100".to_string(),      // Missing colon
+            "var Health(Int): 100".to_string(), // Valid for comparison
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["invalid_var.casp".to_string()];
+
+        parser.parse_variables(0);
+
+        // Valid variable should parse
+        assert!(parser.variables.contains_key("Health"));
 
+        // Invalid variable should not parse
+        assert!(!parser.variables.contains_key("NoColon"));
+    }
+
+    #[test]
+    fn test_error_malformed_state_header() {
+        let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Idle:".to_string(),
+            "Idle".to_string(), // Missing colons
             "---Init:".to_string(),
             "Set(x, 1)".to_string(),
             "".to_string(),
         ];
-        parser.line_ids = vec![1, 2, 3, 4];
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["bad_state.casp".to_string()];
 
         parser.parse_states(0);
 
-        // This should work since we tested it before
-        assert_eq!(parser.states.len(), 1);
-        assert!(parser.states.contains_key("Idle"));
+        // Malformed state header should not create a state
+        assert!(!parser.states.contains_key("Idle"));
     }
 
     #[test]
-    fn test_parse_state_header_with_type() {
-        let parser = CastagneParser::new();
-
-        // State with Helper type
-        let (name, state_type, parent) = parser.parse_state_header("GroundBounce(Helper)");
-        assert_eq!(name, "GroundBounce");
-        assert_eq!(state_type, StateType::Helper);
-        assert_eq!(parent, None);
+    fn test_error_unclosed_parentheses_in_action() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(x, 1".to_string(), // Missing closing paren
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["unclosed_paren.casp".to_string()];
 
-        // State with BaseState type
-        let (name2, state_type2, parent2) = parser.parse_state_header("CommonActions(BaseState)");
-        assert_eq!(name2, "CommonActions");
-        assert_eq!(state_type2, StateType::BaseState);
-        assert_eq!(parent2, None);
+        parser.parse_states(0);
 
-        // State with Special type
-        let (name3, state_type3, parent3) = parser.parse_state_header("SuperMove(Special)");
-        assert_eq!(name3, "SuperMove");
-        assert_eq!(state_type3, StateType::Special);
-        assert_eq!(parent3, None);
+        // Should still create state, but action might be malformed
+        assert!(parser.states.contains_key("Idle"));
+        let idle = parser.states.get("Idle").unwrap();
+        let init_actions = idle.actions.get("Init");
 
-        // State with Specblock type
-        let (name4, state_type4, parent4) = parser.parse_state_header("ConfigBlock(Specblock)");
-        assert_eq!(name4, "ConfigBlock");
-        assert_eq!(state_type4, StateType::Specblock);
-        assert_eq!(parent4, None);
+        // Action parsing should handle this gracefully
+        assert!(init_actions.is_some());
+        assert_eq!(parser.get_diagnostics()[0].code, UNBALANCED_DELIMITER);
     }
 
     #[test]
-    fn test_parse_state_header_with_parent() {
-        let parser = CastagneParser::new();
-
-        // State with parent (no type specified)
-        let (name, state_type, parent) = parser.parse_state_header("Walk(Idle)");
-        assert_eq!(name, "Walk");
-        assert_eq!(state_type, StateType::Normal);
-        assert_eq!(parent, Some("Idle".to_string()));
-
-        // State with different parent
-        let (name2, state_type2, parent2) = parser.parse_state_header("Run(Walk)");
-        assert_eq!(name2, "Run");
-        assert_eq!(state_type2, StateType::Normal);
-        assert_eq!(parent2, Some("Walk".to_string()));
-    }
+    fn test_error_invalid_type_in_variable() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![
+            ":Variables:".to_string(),
+            "var BadType(InvalidType): 100".to_string(),
+            "var GoodType(Int): 100".to_string(),
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["invalid_type.casp".to_string()];
 
-    #[test]
-    fn test_parse_state_header_with_type_and_parent() {
-        let parser = CastagneParser::new();
+        parser.parse_variables(0);
 
-        // State with both type and parent
-        let (name, state_type, parent) = parser.parse_state_header("Projectile(Helper, Idle)");
-        assert_eq!(name, "Projectile");
-        assert_eq!(state_type, StateType::Helper);
-        assert_eq!(parent, Some("Idle".to_string()));
+        // Valid variable should parse
+        assert!(parser.variables.contains_key("GoodType"));
 
-        // Another combination
-        let (name2, state_type2, parent2) =
-            parser.parse_state_header("CustomMove(Special, BaseAttack)");
-        assert_eq!(name2, "CustomMove");
-        assert_eq!(state_type2, StateType::Special);
-        assert_eq!(parent2, Some("BaseAttack".to_string()));
+        // Invalid type should default to Var type
+        if parser.variables.contains_key("BadType") {
+            let bad_var = parser.variables.get("BadType").unwrap();
+            assert_eq!(bad_var.var_type, VariableType::Var);
+        }
     }
 
     #[test]
-    fn test_parse_state_with_attributes() {
+    fn test_error_invalid_state_type() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
-            ":GroundBounce(Helper):".to_string(),
+            ":BadState(InvalidType):".to_string(),
             "---Init:".to_string(),
-            "Set(Velocity, 0, 10)".to_string(),
+            "Set(x, 1)".to_string(),
             "".to_string(),
         ];
-        parser.line_ids = vec![1, 2, 3, 4];
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["invalid_state_type.casp".to_string()];
 
-        // Don't parse specblocks first - just parse states directly
         parser.parse_states(0);
 
-        assert_eq!(
-            parser.states.len(),
-            1,
-            "Expected 1 state, found {}: {:?}",
-            parser.states.len(),
-            parser.states.keys().collect::<Vec<_>>()
-        );
-        let state = parser.states.get("GroundBounce").unwrap();
-        assert_eq!(state.name, "GroundBounce");
-        assert_eq!(state.state_type, StateType::Helper);
-        assert_eq!(state.parent, None);
+        // Should still parse but might default to Normal type or handle gracefully
+        if parser.states.contains_key("BadState") {
+            let state = parser.states.get("BadState").unwrap();
+            // Should default to Normal or handle gracefully
+            assert!(matches!(
+                state.state_type,
+                StateType::Normal | StateType::Helper | StateType::Special
+            ));
+        }
     }
 
     #[test]
-    fn test_parse_state_with_parent_state() {
+    fn test_error_duplicate_variable_names() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
-            ":Walk(Idle):".to_string(),
-            "---Init:".to_string(),
-            "Set(Speed, 5)".to_string(),
-            "---Action:".to_string(),
-            "Move()".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),
+            "var Health(Int): 200".to_string(), // Duplicate
             "".to_string(),
         ];
-        parser.line_ids = vec![1, 2, 3, 4, 5, 6];
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["duplicate_var.casp".to_string()];
 
-        parser.parse_states(0);
+        parser.parse_variables(0);
 
-        assert_eq!(parser.states.len(), 1);
-        let state = parser.states.get("Walk").unwrap();
-        assert_eq!(state.name, "Walk");
-        assert_eq!(state.state_type, StateType::Normal);
-        assert_eq!(state.parent, Some("Idle".to_string()));
-        assert!(state.actions.contains_key("Init"));
-        assert!(state.actions.contains_key("Action"));
+        // Last definition should win (or first, depending on implementation)
+        assert!(parser.variables.contains_key("Health"));
+        let health = parser.variables.get("Health").unwrap();
+        // Could be either 100 or 200 depending on implementation
+        assert!(health.value == "100" || health.value == "200");
     }
 
     #[test]
-    fn test_parse_state_with_type_and_parent_combined() {
+    fn test_error_duplicate_state_names() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
-            ":Fireball(Helper, Idle):".to_string(),
+            ":Idle:".to_string(),
             "---Init:".to_string(),
-            "Set(Damage, 10)".to_string(),
-            "Set(Speed, 20)".to_string(),
-            "---Action:".to_string(),
-            "MoveForward()".to_string(),
-            "CheckCollision()".to_string(),
+            "Set(x, 1)".to_string(),
+            "".to_string(),
+            ":Idle:".to_string(), // Duplicate
+            "---Init:".to_string(),
+            "Set(x, 2)".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.file_paths = vec!["duplicate_state.casp".to_string()];
 
         parser.parse_states(0);
 
+        // Should have one Idle state (last one should overwrite or merge)
+        assert!(parser.states.contains_key("Idle"));
         assert_eq!(parser.states.len(), 1);
-        let state = parser.states.get("Fireball").unwrap();
-        assert_eq!(state.name, "Fireball");
-        assert_eq!(state.state_type, StateType::Helper);
-        assert_eq!(state.parent, Some("Idle".to_string()));
-
-        // Verify actions were parsed correctly
-        let init_actions = state.actions.get("Init").unwrap();
-        assert_eq!(init_actions.len(), 2);
-        assert_eq!(init_actions[0].instruction, "Set");
-        assert_eq!(init_actions[0].args[0], "Damage");
-        assert_eq!(init_actions[0].args[1], "10");
-
-        let action_actions = state.actions.get("Action").unwrap();
-        assert_eq!(action_actions.len(), 2);
-        assert_eq!(action_actions[0].instruction, "MoveForward");
-        assert_eq!(action_actions[1].instruction, "CheckCollision");
     }
 
     #[test]
-    fn test_multiple_states_with_different_types() {
+    fn test_error_missing_phase_marker() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
             ":Idle:".to_string(),
-            "---Init:".to_string(),
-            "Set(State, 0)".to_string(),
-            "".to_string(),
-            ":Walk(Idle):".to_string(),
-            "---Init:".to_string(),
-            "Set(State, 1)".to_string(),
-            "".to_string(),
-            ":Attack(Helper):".to_string(),
-            "---Init:".to_string(),
-            "Set(State, 2)".to_string(),
-            "".to_string(),
-            ":SuperMove(Special, Attack):".to_string(),
-            "---Init:".to_string(),
-            "Set(State, 3)".to_string(),
+            "Set(x, 1)".to_string(), // No phase marker
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.file_paths = vec!["no_phase.casp".to_string()];
 
         parser.parse_states(0);
 
-        assert_eq!(parser.states.len(), 4);
-
-        // Check Idle (simple state)
-        let idle = parser.states.get("Idle").unwrap();
-        assert_eq!(idle.state_type, StateType::Normal);
-        assert_eq!(idle.parent, None);
-
-        // Check Walk (has parent)
-        let walk = parser.states.get("Walk").unwrap();
-        assert_eq!(walk.state_type, StateType::Normal);
-        assert_eq!(walk.parent, Some("Idle".to_string()));
-
-        // Check Attack (has type)
-        let attack = parser.states.get("Attack").unwrap();
-        assert_eq!(attack.state_type, StateType::Helper);
-        assert_eq!(attack.parent, None);
-
-        // Check SuperMove (has both type and parent)
-        let super_move = parser.states.get("SuperMove").unwrap();
-        assert_eq!(super_move.state_type, StateType::Special);
-        assert_eq!(super_move.parent, Some("Attack".to_string()));
+        // Should handle gracefully - might skip action or assign to default phase
+        assert!(parser.states.contains_key("Idle"));
     }
 
     #[test]
-    fn test_strip_inline_comment() {
-        let parser = CastagneParser::new();
-
-        // Simple comment
-        assert_eq!(
-            parser.strip_inline_comment("Set(x, 5) # This sets x"),
-            "Set(x, 5) "
-        );
-
-        // Comment with no space
-        assert_eq!(
-            parser.strip_inline_comment("Set(x, 5)# comment"),
-            "Set(x, 5)"
-        );
-
-        // Comment in string should be preserved
-        assert_eq!(
-            parser.strip_inline_comment(r#"Set(msg, "Hello # World")"#),
-            r#"Set(msg, "Hello # World")"#
-        );
-
-        // Empty line with just comment
-        assert_eq!(parser.strip_inline_comment("# Just a comment"), "");
-
-        // No comment
-        assert_eq!(parser.strip_inline_comment("Set(x, 5)"), "Set(x, 5)");
+    fn test_error_empty_state() {
+        let mut parser = CastagneParser::new();
+        parser.current_lines = vec![":Empty:".to_string(), "".to_string()];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["empty_state.casp".to_string()];
 
-        // Multiple # in string
-        assert_eq!(
-            parser.strip_inline_comment("Log(\"Test#123\") # comment"),
-            "Log(\"Test#123\") "
-        );
+        parser.parse_states(0);
 
-        // Escaped quote in string
-        assert_eq!(
-            parser.strip_inline_comment(r#"Set(x, "He said \"Hi\"") # comment"#),
-            r#"Set(x, "He said \"Hi\"") "#
-        );
+        // Empty state should still be created
+        assert!(parser.states.contains_key("Empty"));
+        let empty = parser.states.get("Empty").unwrap();
+        assert_eq!(empty.actions.len(), 0);
     }
 
     #[test]
-    fn test_parse_with_inline_comments() {
+    fn test_error_special_characters_in_names() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Test Fighter # Main character".to_string(),
-            "Author: Test # Test author".to_string(),
-            "".to_string(),
             ":Variables:".to_string(),
-            "var Health(Int): 100 # Player health".to_string(),
-            "def MAX_HP: 150 # Maximum health".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
-            "---Init: # Initialization phase".to_string(),
-            "Set(Health, 100) # Set initial health".to_string(),
+            "var Test@Name(Int): 100".to_string(), // Special char in name
+            "var Normal_Name123(Int): 200".to_string(), // Valid name
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["test.casp".to_string()];
-
-        parser.parse_full_file();
-        let result = parser.end_parsing();
-
-        assert!(result.is_some());
-        let character = result.unwrap();
-
-        // Verify metadata was parsed correctly (without comments)
-        assert_eq!(character.metadata.name, "Test Fighter");
-        assert_eq!(character.metadata.author, "Test");
+        parser.file_paths = vec!["special_chars.casp".to_string()];
 
-        // Verify variables were parsed correctly (without comments)
-        assert!(character.variables.contains_key("Health"));
-        let health = character.variables.get("Health").unwrap();
-        assert_eq!(health.value, "100");
+        parser.parse_variables(0);
 
-        assert!(character.variables.contains_key("MAX_HP"));
-        let max_hp = character.variables.get("MAX_HP").unwrap();
-        assert_eq!(max_hp.value, "150");
+        // Normal name should parse
+        assert!(parser.variables.contains_key("Normal_Name123"));
 
-        // Verify states were parsed correctly (without comments)
-        assert!(character.states.contains_key("Idle"));
-        let idle = character.states.get("Idle").unwrap();
-        let init_actions = idle.actions.get("Init").unwrap();
-        assert_eq!(init_actions.len(), 1);
-        assert_eq!(init_actions[0].instruction, "Set");
-        assert_eq!(init_actions[0].args[0], "Health");
-        assert_eq!(init_actions[0].args[1], "100");
+        // Special character name might be rejected or sanitized
+        // (depends on implementation - just check it doesn't crash)
     }
 
     #[test]
-    fn test_inline_comment_with_strings() {
+    fn test_error_very_long_line() {
         let mut parser = CastagneParser::new();
 
+        // Create a very long action line
+        let long_args = (0..1000)
+            .map(|i| format!("arg{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let long_line = format!("Function({})", long_args);
+
         parser.current_lines = vec![
-            ":Idle:".to_string(),
+            ":Test:".to_string(),
             "---Init:".to_string(),
-            r#"Log("Test # not a comment") # This is a comment"#.to_string(),
-            r#"Set(Message, "Hello, World!") # Set greeting"#.to_string(),
+            long_line,
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["test.casp".to_string()];
+        parser.file_paths = vec!["long_line.casp".to_string()];
 
         parser.parse_states(0);
 
-        let idle = parser.states.get("Idle").unwrap();
-        let init_actions = idle.actions.get("Init").unwrap();
-
-        assert_eq!(init_actions.len(), 2);
-
-        // First action: Log with # in string
-        assert_eq!(init_actions[0].instruction, "Log");
-        assert_eq!(init_actions[0].args.len(), 1);
-        assert_eq!(init_actions[0].args[0], r#""Test # not a comment""#);
-
-        // Second action: Set with comment after
-        assert_eq!(init_actions[1].instruction, "Set");
-        assert_eq!(init_actions[1].args.len(), 2);
-        assert_eq!(init_actions[1].args[0], "Message");
-        assert_eq!(init_actions[1].args[1], r#""Hello, World!""#);
+        // Should handle long lines without crashing
+        assert!(parser.states.contains_key("Test"));
     }
 
     #[test]
-    fn test_specblock_with_inline_comments() {
+    fn test_error_nested_quotes_in_strings() {
         let mut parser = CastagneParser::new();
-
         parser.current_lines = vec![
-            ":Config:".to_string(),
-            "MaxSpeed: 100 # Maximum movement speed".to_string(),
-            "JumpHeight: 50 # Jump force".to_string(),
-            "Gravity: 10 # Gravity value".to_string(),
+            ":Test:".to_string(),
+            "---Init:".to_string(),
+            r#"Set(msg, "He said \"Hello\" to me")"#.to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["test.casp".to_string()];
-
-        parser.parse_specblocks(0);
+        parser.file_paths = vec!["nested_quotes.casp".to_string()];
 
-        assert!(parser.specblocks.contains_key("Config"));
-        let config = parser.specblocks.get("Config").unwrap();
+        parser.parse_states(0);
 
-        // Values should be parsed without comments
-        assert_eq!(config.get("MaxSpeed"), Some(&"100".to_string()));
-        assert_eq!(config.get("JumpHeight"), Some(&"50".to_string()));
-        assert_eq!(config.get("Gravity"), Some(&"10".to_string()));
-    }
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
 
-    // =========================================================================
-    // Error Handling Tests
-    // =========================================================================
+        // Should parse escaped quotes correctly
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].instruction, "Set");
+    }
 
     #[test]
-    fn test_error_empty_file() {
+    fn test_error_unicode_in_values() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![];
-        parser.line_ids = vec![];
-        parser.file_paths = vec!["empty.casp".to_string()];
+        parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: 戦士".to_string(),             // Japanese characters
+            "Description: Étoile ⭐".to_string(), // French + emoji
+            "".to_string(),
+        ];
+        parser.line_ids = (1..=parser.current_lines.len()).collect();
+        parser.file_paths = vec!["unicode.casp".to_string()];
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        parser.parse_metadata(0);
 
-        // Empty file should still parse (with no data)
-        assert!(result.is_some());
-        let character = result.unwrap();
-        assert_eq!(character.states.len(), 0);
-        assert_eq!(character.variables.len(), 0);
+        // Should handle unicode gracefully
+        assert_eq!(parser.metadata.name, "戦士");
+        assert_eq!(parser.metadata.description, "Étoile ⭐");
     }
 
     #[test]
-    fn test_error_only_comments() {
+    fn test_error_mixing_tabs_and_spaces() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            "# This is just a comment".to_string(),
-            "# Another comment".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 100".to_string(),  // Using spaces
+            "\tvar\tMana(Int):\t50".to_string(), // Using tabs
             "".to_string(),
-            "# More comments".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["comments.casp".to_string()];
+        parser.file_paths = vec!["mixed_whitespace.casp".to_string()];
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        parser.parse_variables(0);
 
-        // File with only comments should parse successfully
-        assert!(result.is_some());
-        let character = result.unwrap();
-        assert_eq!(character.states.len(), 0);
-        assert_eq!(character.variables.len(), 0);
+        // Should handle both gracefully
+        assert!(parser.variables.contains_key("Health"));
+        // Mana might or might not parse depending on implementation
     }
 
+    // =========================================================================
+    // Advanced Edge Case Tests
+    // =========================================================================
+
     #[test]
-    fn test_error_missing_character_block() {
+    fn test_edge_deeply_nested_function_calls() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),
+            ":Test:".to_string(),
+            "---Init:".to_string(),
+            "Call(Outer(Middle(Inner(Deepest(5)))))".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["no_character.casp".to_string()];
+        parser.file_paths = vec!["nested.casp".to_string()];
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        parser.parse_states(0);
 
-        // Should still parse even without Character block
-        assert!(result.is_some());
-        let character = result.unwrap();
-        assert!(character.variables.contains_key("Health"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
+
+        // Should parse deeply nested calls
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].instruction, "Call");
     }
 
     #[test]
-    fn test_error_invalid_variable_syntax() {
+    fn test_edge_mixed_nested_arguments() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var NoColon 100".to_string(),      // Missing colon
-            "var Health(Int): 100".to_string(), // Valid for comparison
+            ":Test:".to_string(),
+            "---Init:".to_string(),
+            r#"Complex(1, "text", Vec2(10, 20), true, Nested(a, b, c))"#.to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["invalid_var.casp".to_string()];
+        parser.file_paths = vec!["complex_args.casp".to_string()];
 
-        parser.parse_variables(0);
+        parser.parse_states(0);
 
-        // Valid variable should parse
-        assert!(parser.variables.contains_key("Health"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
 
-        // Invalid variable should not parse
-        assert!(!parser.variables.contains_key("NoColon"));
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].instruction, "Complex");
+        // Should have multiple arguments including nested call
+        assert!(!actions[0].args.is_empty());
     }
 
     #[test]
-    fn test_error_malformed_state_header() {
+    fn test_edge_empty_arguments() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            "Idle".to_string(), // Missing colons
+            ":Test:".to_string(),
             "---Init:".to_string(),
-            "Set(x, 1)".to_string(),
+            "EmptyCall()".to_string(),
+            "OneArg(x)".to_string(),
+            "TwoArgs(x, y)".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["bad_state.casp".to_string()];
+        parser.file_paths = vec!["empty_args.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Malformed state header should not create a state
-        assert!(!parser.states.contains_key("Idle"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
+
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0].instruction, "EmptyCall");
+        assert_eq!(actions[0].args.len(), 0);
+        assert_eq!(actions[1].args.len(), 1);
+        assert_eq!(actions[2].args.len(), 2);
     }
 
     #[test]
-    fn test_error_unclosed_parentheses_in_action() {
+    fn test_edge_whitespace_variations() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Idle:".to_string(),
+            ":Test:".to_string(),
             "---Init:".to_string(),
-            "Set(x, 1".to_string(), // Missing closing paren
+            "NoSpace(x,y,z)".to_string(),
+            "WithSpace(x, y, z)".to_string(),
+            "ExtraSpace(x  ,  y  ,  z)".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["unclosed_paren.casp".to_string()];
+        parser.file_paths = vec!["whitespace.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Should still create state, but action might be malformed
-        assert!(parser.states.contains_key("Idle"));
-        let idle = parser.states.get("Idle").unwrap();
-        let init_actions = idle.actions.get("Init");
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
 
-        // Action parsing should handle this gracefully
-        assert!(init_actions.is_some());
+        // All three should parse successfully
+        assert_eq!(actions.len(), 3);
+        for action in actions {
+            assert_eq!(action.args.len(), 3);
+        }
     }
 
     #[test]
-    fn test_error_invalid_type_in_variable() {
+    fn test_edge_multiple_phases_same_state() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var BadType(InvalidType): 100".to_string(),
-            "var GoodType(Int): 100".to_string(),
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "InitAction()".to_string(),
+            "---Action:".to_string(),
+            "ActionPhase()".to_string(),
+            "---Reaction:".to_string(),
+            "ReactionPhase()".to_string(),
+            "---Freeze:".to_string(),
+            "FreezePhase()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["invalid_type.casp".to_string()];
+        parser.file_paths = vec!["multi_phase.casp".to_string()];
 
-        parser.parse_variables(0);
+        parser.parse_states(0);
 
-        // Valid variable should parse
-        assert!(parser.variables.contains_key("GoodType"));
+        let idle = parser.states.get("Idle").unwrap();
 
-        // Invalid type should default to Var type
-        if parser.variables.contains_key("BadType") {
-            let bad_var = parser.variables.get("BadType").unwrap();
-            assert_eq!(bad_var.var_type, VariableType::Var);
-        }
+        // Should have all four phases
+        assert!(idle.actions.contains_key("Init"));
+        assert!(idle.actions.contains_key("Action"));
+        assert!(idle.actions.contains_key("Reaction"));
+        assert!(idle.actions.contains_key("Freeze"));
+
+        // Each phase should have one action
+        assert_eq!(idle.actions.get("Init").unwrap().len(), 1);
+        assert_eq!(idle.actions.get("Action").unwrap().len(), 1);
+        assert_eq!(idle.actions.get("Reaction").unwrap().len(), 1);
+        assert_eq!(idle.actions.get("Freeze").unwrap().len(), 1);
     }
 
     #[test]
-    fn test_error_invalid_state_type() {
+    fn test_edge_repeated_phases() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":BadState(InvalidType):".to_string(),
+            ":Idle:".to_string(),
             "---Init:".to_string(),
-            "Set(x, 1)".to_string(),
+            "First()".to_string(),
+            "---Action:".to_string(),
+            "Middle()".to_string(),
+            "---Init:".to_string(), // Repeated phase
+            "Second()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["invalid_state_type.casp".to_string()];
+        parser.file_paths = vec!["repeated_phase.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Should still parse but might default to Normal type or handle gracefully
-        if parser.states.contains_key("BadState") {
-            let state = parser.states.get("BadState").unwrap();
-            // Should default to Normal or handle gracefully
-            assert!(matches!(
-                state.state_type,
-                StateType::Normal | StateType::Helper | StateType::Special
-            ));
-        }
+        let idle = parser.states.get("Idle").unwrap();
+
+        // Init should have actions from both declarations
+        let init_actions = idle.actions.get("Init").unwrap();
+        assert!(!init_actions.is_empty()); // At least one action
     }
 
     #[test]
-    fn test_error_duplicate_variable_names() {
+    fn test_edge_all_variable_types() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
             ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),
-            "var Health(Int): 200".to_string(), // Duplicate
+            "var IntVar(Int): 42".to_string(),
+            "var StrVar(Str): Hello".to_string(),
+            "var BoolVar(Bool): true".to_string(),
+            "var Vec2Var(Vec2): 10, 20".to_string(),
+            "var Vec3Var(Vec3): 1, 2, 3".to_string(),
+            "var VarVar(Var): dynamic".to_string(),
+            "var BoxVar(Box): boxed".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["duplicate_var.casp".to_string()];
+        parser.file_paths = vec!["all_types.casp".to_string()];
 
         parser.parse_variables(0);
 
-        // Last definition should win (or first, depending on implementation)
-        assert!(parser.variables.contains_key("Health"));
-        let health = parser.variables.get("Health").unwrap();
-        // Could be either 100 or 200 depending on implementation
-        assert!(health.value == "100" || health.value == "200");
+        // All variable types should parse
+        assert!(parser.variables.contains_key("IntVar"));
+        assert!(parser.variables.contains_key("StrVar"));
+        assert!(parser.variables.contains_key("BoolVar"));
+        assert!(parser.variables.contains_key("Vec2Var"));
+        assert!(parser.variables.contains_key("Vec3Var"));
+        assert!(parser.variables.contains_key("VarVar"));
+        assert!(parser.variables.contains_key("BoxVar"));
+
+        // Check types are correct
+        assert_eq!(
+            parser.variables.get("IntVar").unwrap().var_type,
+            VariableType::Int
+        );
+        assert_eq!(
+            parser.variables.get("StrVar").unwrap().var_type,
+            VariableType::Str
+        );
+        assert_eq!(
+            parser.variables.get("BoolVar").unwrap().var_type,
+            VariableType::Bool
+        );
+        assert_eq!(
+            parser.variables.get("Vec2Var").unwrap().var_type,
+            VariableType::Vec2
+        );
+        assert_eq!(
+            parser.variables.get("Vec3Var").unwrap().var_type,
+            VariableType::Vec3
+        );
+        assert_eq!(
+            parser.variables.get("VarVar").unwrap().var_type,
+            VariableType::Var
+        );
+        assert_eq!(
+            parser.variables.get("BoxVar").unwrap().var_type,
+            VariableType::Box
+        );
     }
 
     #[test]
-    fn test_error_duplicate_state_names() {
+    fn test_edge_all_state_types() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Idle:".to_string(),
+            ":NormalState:".to_string(),
+            "---Init:".to_string(),
+            "Normal()".to_string(),
+            "".to_string(),
+            ":HelperState(Helper):".to_string(),
             "---Init:".to_string(),
-            "Set(x, 1)".to_string(),
+            "Helper()".to_string(),
             "".to_string(),
-            ":Idle:".to_string(), // Duplicate
+            ":SpecialState(Special):".to_string(),
             "---Init:".to_string(),
-            "Set(x, 2)".to_string(),
+            "Special()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["duplicate_state.casp".to_string()];
+        parser.file_paths = vec!["all_state_types.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Should have one Idle state (last one should overwrite or merge)
-        assert!(parser.states.contains_key("Idle"));
-        assert_eq!(parser.states.len(), 1);
+        assert_eq!(
+            parser.states.get("NormalState").unwrap().state_type,
+            StateType::Normal
+        );
+        assert_eq!(
+            parser.states.get("HelperState").unwrap().state_type,
+            StateType::Helper
+        );
+        assert_eq!(
+            parser.states.get("SpecialState").unwrap().state_type,
+            StateType::Special
+        );
     }
 
     #[test]
-    fn test_error_missing_phase_marker() {
+    fn test_edge_string_with_escaped_characters() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Idle:".to_string(),
-            "Set(x, 1)".to_string(), // No phase marker
+            ":Test:".to_string(),
+            "---Init:".to_string(),
+            r#"Log("Line1\nLine2\tTabbed")"#.to_string(),
+            r#"Set(quote, "He said: \"Hi\"")"#.to_string(),
+            r#"Set(path, "C:\\Users\\Game")"#.to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["no_phase.casp".to_string()];
+        parser.file_paths = vec!["escaped.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Should handle gracefully - might skip action or assign to default phase
-        assert!(parser.states.contains_key("Idle"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
+
+        // Should parse all escaped strings
+        assert_eq!(actions.len(), 3);
     }
 
     #[test]
-    fn test_error_empty_state() {
+    fn test_edge_negative_numbers() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![":Empty:".to_string(), "".to_string()];
+        parser.current_lines = vec![
+            ":Variables:".to_string(),
+            "var NegInt(Int): -100".to_string(),
+            "var NegVec2(Vec2): -5, -10".to_string(),
+            "var NegVec3(Vec3): -1, -2, -3".to_string(),
+            "".to_string(),
+        ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["empty_state.casp".to_string()];
+        parser.file_paths = vec!["negative.casp".to_string()];
 
-        parser.parse_states(0);
+        parser.parse_variables(0);
 
-        // Empty state should still be created
-        assert!(parser.states.contains_key("Empty"));
-        let empty = parser.states.get("Empty").unwrap();
-        assert_eq!(empty.actions.len(), 0);
+        assert!(parser.variables.contains_key("NegInt"));
+        assert!(parser.variables.contains_key("NegVec2"));
+        assert!(parser.variables.contains_key("NegVec3"));
+
+        let neg_int = parser.variables.get("NegInt").unwrap();
+        assert_eq!(neg_int.as_int(), Some(-100));
     }
 
     #[test]
-    fn test_error_special_characters_in_names() {
+    fn test_edge_float_numbers() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
             ":Variables:".to_string(),
-            "var Test@Name(Int): 100".to_string(), // Special char in name
-            "var Normal_Name123(Int): 200".to_string(), // Valid name
+            "var FloatVar(Int): 3.14".to_string(),
+            "var FloatVec2(Vec2): 1.5, 2.5".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["special_chars.casp".to_string()];
+        parser.file_paths = vec!["floats.casp".to_string()];
 
         parser.parse_variables(0);
 
-        // Normal name should parse
-        assert!(parser.variables.contains_key("Normal_Name123"));
-
-        // Special character name might be rejected or sanitized
-        // (depends on implementation - just check it doesn't crash)
+        // Should parse floats
+        assert!(parser.variables.contains_key("FloatVar"));
+        assert!(parser.variables.contains_key("FloatVec2"));
     }
 
     #[test]
-    fn test_error_very_long_line() {
+    fn test_edge_action_without_parentheses() {
         let mut parser = CastagneParser::new();
-
-        // Create a very long action line
-        let long_args = (0..1000)
-            .map(|i| format!("arg{}", i))
-            .collect::<Vec<_>>()
-            .join(", ");
-        let long_line = format!("Function({})", long_args);
-
         parser.current_lines = vec![
             ":Test:".to_string(),
             "---Init:".to_string(),
-            long_line,
+            "SimpleAction".to_string(),       // No parentheses
+            "ActionWithParens()".to_string(), // With parentheses
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["long_line.casp".to_string()];
+        parser.file_paths = vec!["no_parens.casp".to_string()];
 
         parser.parse_states(0);
 
-        // Should handle long lines without crashing
-        assert!(parser.states.contains_key("Test"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
+
+        // Both should parse (or at least not crash)
+        assert!(!actions.is_empty());
     }
 
     #[test]
-    fn test_error_nested_quotes_in_strings() {
+    fn test_edge_multiple_specblocks() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            r#"Set(msg, "He said \"Hello\" to me")"#.to_string(),
+            ":Config:".to_string(),
+            "Speed: 100".to_string(),
+            "".to_string(),
+            ":Attacks:".to_string(),
+            "Punch: 10".to_string(),
+            "Kick: 20".to_string(),
+            "".to_string(),
+            ":CustomData:".to_string(),
+            "Field1: Value1".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["nested_quotes.casp".to_string()];
+        parser.file_paths = vec!["multi_specblock.casp".to_string()];
 
-        parser.parse_states(0);
+        parser.parse_specblocks(0);
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        // All specblocks should be parsed
+        assert!(parser.specblocks.contains_key("Config"));
+        assert!(parser.specblocks.contains_key("Attacks"));
+        assert!(parser.specblocks.contains_key("CustomData"));
 
-        // Should parse escaped quotes correctly
-        assert_eq!(actions.len(), 1);
-        assert_eq!(actions[0].instruction, "Set");
+        assert_eq!(
+            parser.specblocks.get("Config").unwrap().get("Speed"),
+            Some(&"100".to_string())
+        );
+        assert_eq!(
+            parser.specblocks.get("Attacks").unwrap().get("Punch"),
+            Some(&"10".to_string())
+        );
     }
 
     #[test]
-    fn test_error_unicode_in_values() {
+    fn test_edge_state_inheritance_chain() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: 戦士".to_string(),             // Japanese characters
-            "Description: Étoile ⭐".to_string(), // French + emoji
+            ":Base:".to_string(),
+            "---Init:".to_string(),
+            "BaseAction()".to_string(),
+            "".to_string(),
+            ":Middle(Base):".to_string(),
+            "---Init:".to_string(),
+            "MiddleAction()".to_string(),
+            "".to_string(),
+            ":Final(Middle):".to_string(),
+            "---Init:".to_string(),
+            "FinalAction()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["unicode.casp".to_string()];
+        parser.file_paths = vec!["chain.casp".to_string()];
 
-        parser.parse_metadata(0);
+        parser.parse_states(0);
 
-        // Should handle unicode gracefully
-        assert_eq!(parser.metadata.name, "戦士");
-        assert_eq!(parser.metadata.description, "Étoile ⭐");
+        // All states should exist
+        assert!(parser.states.contains_key("Base"));
+        assert!(parser.states.contains_key("Middle"));
+        assert!(parser.states.contains_key("Final"));
+
+        // Check parent relationships
+        assert_eq!(parser.states.get("Base").unwrap().parent, None);
+        assert_eq!(
+            parser.states.get("Middle").unwrap().parent,
+            Some("Base".to_string())
+        );
+        assert_eq!(
+            parser.states.get("Final").unwrap().parent,
+            Some("Middle".to_string())
+        );
     }
 
     #[test]
-    fn test_error_mixing_tabs_and_spaces() {
+    fn test_edge_bool_value_variations() {
         let mut parser = CastagneParser::new();
         parser.current_lines = vec![
             ":Variables:".to_string(),
-            "var Health(Int): 100".to_string(),  // Using spaces
-            "\tvar\tMana(Int):\t50".to_string(), // Using tabs
+            "var Bool1(Bool): true".to_string(),
+            "var Bool2(Bool): false".to_string(),
+            "var Bool3(Bool): 1".to_string(),
+            "var Bool4(Bool): 0".to_string(),
+            "var Bool5(Bool): True".to_string(),  // Capitalized
+            "var Bool6(Bool): FALSE".to_string(), // All caps
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["mixed_whitespace.casp".to_string()];
+        parser.file_paths = vec!["bool_variations.casp".to_string()];
 
         parser.parse_variables(0);
 
-        // Should handle both gracefully
-        assert!(parser.variables.contains_key("Health"));
-        // Mana might or might not parse depending on implementation
+        // All should parse
+        assert!(parser.variables.contains_key("Bool1"));
+        assert!(parser.variables.contains_key("Bool2"));
+        assert!(parser.variables.contains_key("Bool3"));
+        assert!(parser.variables.contains_key("Bool4"));
+        assert!(parser.variables.contains_key("Bool5"));
+        assert!(parser.variables.contains_key("Bool6"));
+
+        // Check values are correct
+        assert_eq!(parser.variables.get("Bool1").unwrap().as_bool(), Some(true));
+        assert_eq!(
+            parser.variables.get("Bool2").unwrap().as_bool(),
+            Some(false)
+        );
+        assert_eq!(parser.variables.get("Bool3").unwrap().as_bool(), Some(true));
+        assert_eq!(
+            parser.variables.get("Bool4").unwrap().as_bool(),
+            Some(false)
+        );
     }
 
     // =========================================================================
-    // Advanced Edge Case Tests
+    // Performance and Stress Tests
     // =========================================================================
 
     #[test]
-    fn test_edge_deeply_nested_function_calls() {
+    fn test_stress_many_states() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            "Call(Outer(Middle(Inner(Deepest(5)))))".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["nested.casp".to_string()];
 
-        parser.parse_states(0);
+        // Create 100 states
+        let mut lines = vec![];
+        for i in 0..100 {
+            lines.push(format!(":State{}:", i));
+            lines.push("---Init:".to_string());
+            lines.push(format!("Set(StateID, {})", i));
+            lines.push("".to_string());
+        }
+
+        parser.current_lines = lines.clone();
+        parser.line_ids = (1..=lines.len()).collect();
+        parser.file_paths = vec!["stress.casp".to_string()];
+
+        parser.parse_states(0);
+
+        // All states should parse
+        assert_eq!(parser.states.len(), 100);
+
+        // Spot check a few states
+        assert!(parser.states.contains_key("State0"));
+        assert!(parser.states.contains_key("State50"));
+        assert!(parser.states.contains_key("State99"));
+    }
+
+    #[test]
+    fn test_stress_many_variables() {
+        let mut parser = CastagneParser::new();
+
+        // Create 200 variables
+        let mut lines = vec![":Variables:".to_string()];
+        for i in 0..200 {
+            lines.push(format!("var Var{}(Int): {}", i, i * 10));
+        }
+        lines.push("".to_string());
+
+        parser.current_lines = lines.clone();
+        parser.line_ids = (1..=lines.len()).collect();
+        parser.file_paths = vec!["stress_vars.casp".to_string()];
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        parser.parse_variables(0);
 
-        // Should parse deeply nested calls
-        assert_eq!(actions.len(), 1);
-        assert_eq!(actions[0].instruction, "Call");
+        // All variables should parse
+        assert_eq!(parser.variables.len(), 200);
+
+        // Spot check values
+        assert_eq!(parser.variables.get("Var0").unwrap().value, "0");
+        assert_eq!(parser.variables.get("Var100").unwrap().value, "1000");
+        assert_eq!(parser.variables.get("Var199").unwrap().value, "1990");
     }
 
     #[test]
-    fn test_edge_mixed_nested_arguments() {
+    fn test_stress_many_actions_per_state() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            r#"Complex(1, "text", Vec2(10, 20), true, Nested(a, b, c))"#.to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["complex_args.casp".to_string()];
+
+        // Create state with 100 actions
+        let mut lines = vec![":StressTest:".to_string(), "---Init:".to_string()];
+
+        for i in 0..100 {
+            lines.push(format!("Action{}(arg{})", i, i));
+        }
+        lines.push("".to_string());
+
+        parser.current_lines = lines.clone();
+        parser.line_ids = (1..=lines.len()).collect();
+        parser.file_paths = vec!["stress_actions.casp".to_string()];
 
         parser.parse_states(0);
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        let state = parser.states.get("StressTest").unwrap();
+        let actions = state.actions.get("Init").unwrap();
 
-        assert_eq!(actions.len(), 1);
-        assert_eq!(actions[0].instruction, "Complex");
-        // Should have multiple arguments including nested call
-        assert!(actions[0].args.len() > 0);
+        // All actions should parse
+        assert_eq!(actions.len(), 100);
+
+        // Check first and last action
+        assert_eq!(actions[0].instruction, "Action0");
+        assert_eq!(actions[99].instruction, "Action99");
     }
 
     #[test]
-    fn test_edge_empty_arguments() {
+    fn test_stress_deeply_nested_10_levels() {
         let mut parser = CastagneParser::new();
+
+        // Create deeply nested function call
+        let nested = "Call(L1(L2(L3(L4(L5(L6(L7(L8(L9(L10(value))))))))))";
+
         parser.current_lines = vec![
             ":Test:".to_string(),
             "---Init:".to_string(),
-            "EmptyCall()".to_string(),
-            "OneArg(x)".to_string(),
-            "TwoArgs(x, y)".to_string(),
+            nested.to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["empty_args.casp".to_string()];
+        parser.file_paths = vec!["deep_nest.casp".to_string()];
 
         parser.parse_states(0);
 
         let test = parser.states.get("Test").unwrap();
         let actions = test.actions.get("Init").unwrap();
 
-        assert_eq!(actions.len(), 3);
-        assert_eq!(actions[0].instruction, "EmptyCall");
-        assert_eq!(actions[0].args.len(), 0);
-        assert_eq!(actions[1].args.len(), 1);
-        assert_eq!(actions[2].args.len(), 2);
+        // Should handle deep nesting
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].instruction, "Call");
     }
 
     #[test]
-    fn test_edge_whitespace_variations() {
+    fn test_stress_many_arguments() {
         let mut parser = CastagneParser::new();
+
+        // Create action with 50 arguments
+        let args: Vec<String> = (0..50).map(|i| format!("arg{}", i)).collect();
+        let action_line = format!("ManyArgs({})", args.join(", "));
+
         parser.current_lines = vec![
             ":Test:".to_string(),
             "---Init:".to_string(),
-            "NoSpace(x,y,z)".to_string(),
-            "WithSpace(x, y, z)".to_string(),
-            "ExtraSpace(x  ,  y  ,  z)".to_string(),
+            action_line,
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["whitespace.casp".to_string()];
+        parser.file_paths = vec!["many_args.casp".to_string()];
 
         parser.parse_states(0);
 
         let test = parser.states.get("Test").unwrap();
         let actions = test.actions.get("Init").unwrap();
 
-        // All three should parse successfully
-        assert_eq!(actions.len(), 3);
-        for action in actions {
-            assert_eq!(action.args.len(), 3);
-        }
+        // Should handle many arguments
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].args.len(), 50);
     }
 
+    // =========================================================================
+    // Integration Tests with Complex Scenarios
+    // =========================================================================
+
     #[test]
-    fn test_edge_multiple_phases_same_state() {
+    fn test_integration_fighting_game_character() {
         let mut parser = CastagneParser::new();
+
         parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Ryu".to_string(),
+            "Author: Capcom".to_string(),
+            "Description: Wandering fighter".to_string(),
+            "".to_string(),
+            ":Variables:".to_string(),
+            "var Health(Int): 1000".to_string(),
+            "var MaxHealth(Int): 1000".to_string(),
+            "var Meter(Int): 0".to_string(),
+            "var MaxMeter(Int): 10000".to_string(),
+            "var Damage(Int): 0".to_string(),
+            "var PosX(Int): 0".to_string(),
+            "var PosY(Int): 0".to_string(),
+            "var VelX(Int): 0".to_string(),
+            "var VelY(Int): 0".to_string(),
+            "var Facing(Int): 1".to_string(),
+            "var ComboCounter(Int): 0".to_string(),
+            "def GROUND_Y: 0".to_string(),
+            "def JUMP_FORCE: -150".to_string(),
+            "def WALK_SPEED: 30".to_string(),
+            "".to_string(),
+            ":Config:".to_string(),
+            "MaxSpeed: 100".to_string(),
+            "JumpHeight: 150".to_string(),
+            "Gravity: 10".to_string(),
+            "".to_string(),
             ":Idle:".to_string(),
             "---Init:".to_string(),
-            "InitAction()".to_string(),
+            "Set(VelX, 0)".to_string(),
+            "Set(VelY, 0)".to_string(),
+            "ResetCombo()".to_string(),
             "---Action:".to_string(),
-            "ActionPhase()".to_string(),
-            "---Reaction:".to_string(),
-            "ReactionPhase()".to_string(),
-            "---Freeze:".to_string(),
-            "FreezePhase()".to_string(),
+            "CheckInput(Left, Walk)".to_string(),
+            "CheckInput(Right, Walk)".to_string(),
+            "CheckInput(Jump, Jump)".to_string(),
+            "CheckInput(Attack, LightPunch)".to_string(),
             "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["multi_phase.casp".to_string()];
-
-        parser.parse_states(0);
-
-        let idle = parser.states.get("Idle").unwrap();
-
-        // Should have all four phases
-        assert!(idle.actions.contains_key("Init"));
-        assert!(idle.actions.contains_key("Action"));
-        assert!(idle.actions.contains_key("Reaction"));
-        assert!(idle.actions.contains_key("Freeze"));
-
-        // Each phase should have one action
-        assert_eq!(idle.actions.get("Init").unwrap().len(), 1);
-        assert_eq!(idle.actions.get("Action").unwrap().len(), 1);
-        assert_eq!(idle.actions.get("Reaction").unwrap().len(), 1);
-        assert_eq!(idle.actions.get("Freeze").unwrap().len(), 1);
-    }
-
-    #[test]
-    fn test_edge_repeated_phases() {
-        let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Idle:".to_string(),
+            ":Walk(Idle):".to_string(),
             "---Init:".to_string(),
-            "First()".to_string(),
+            "Set(VelX, WALK_SPEED)".to_string(),
+            "PlayAnimation(Walk)".to_string(),
             "---Action:".to_string(),
-            "Middle()".to_string(),
-            "---Init:".to_string(), // Repeated phase
-            "Second()".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["repeated_phase.casp".to_string()];
-
-        parser.parse_states(0);
-
-        let idle = parser.states.get("Idle").unwrap();
-
-        // Init should have actions from both declarations
-        let init_actions = idle.actions.get("Init").unwrap();
-        assert!(init_actions.len() >= 1); // At least one action
-    }
-
-    #[test]
-    fn test_edge_all_variable_types() {
-        let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var IntVar(Int): 42".to_string(),
-            "var StrVar(Str): Hello".to_string(),
-            "var BoolVar(Bool): true".to_string(),
-            "var Vec2Var(Vec2): 10, 20".to_string(),
-            "var Vec3Var(Vec3): 1, 2, 3".to_string(),
-            "var VarVar(Var): dynamic".to_string(),
-            "var BoxVar(Box): boxed".to_string(),
+            "Move(VelX, 0)".to_string(),
+            "CheckInput(None, Idle)".to_string(),
+            "CheckInput(Jump, Jump)".to_string(),
             "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["all_types.casp".to_string()];
-
-        parser.parse_variables(0);
-
-        // All variable types should parse
-        assert!(parser.variables.contains_key("IntVar"));
-        assert!(parser.variables.contains_key("StrVar"));
-        assert!(parser.variables.contains_key("BoolVar"));
-        assert!(parser.variables.contains_key("Vec2Var"));
-        assert!(parser.variables.contains_key("Vec3Var"));
-        assert!(parser.variables.contains_key("VarVar"));
-        assert!(parser.variables.contains_key("BoxVar"));
-
-        // Check types are correct
-        assert_eq!(
-            parser.variables.get("IntVar").unwrap().var_type,
-            VariableType::Int
-        );
-        assert_eq!(
-            parser.variables.get("StrVar").unwrap().var_type,
-            VariableType::Str
-        );
-        assert_eq!(
-            parser.variables.get("BoolVar").unwrap().var_type,
-            VariableType::Bool
-        );
-        assert_eq!(
-            parser.variables.get("Vec2Var").unwrap().var_type,
-            VariableType::Vec2
-        );
-        assert_eq!(
-            parser.variables.get("Vec3Var").unwrap().var_type,
-            VariableType::Vec3
-        );
-        assert_eq!(
-            parser.variables.get("VarVar").unwrap().var_type,
-            VariableType::Var
-        );
-        assert_eq!(
-            parser.variables.get("BoxVar").unwrap().var_type,
-            VariableType::Box
-        );
-    }
-
-    #[test]
-    fn test_edge_all_state_types() {
-        let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":NormalState:".to_string(),
+            ":Jump(Idle):".to_string(),
             "---Init:".to_string(),
-            "Normal()".to_string(),
+            "Set(VelY, JUMP_FORCE)".to_string(),
+            "PlayAnimation(Jump)".to_string(),
+            "PlaySound(Jump)".to_string(),
+            "---Action:".to_string(),
+            "Add(VelY, Gravity)".to_string(),
+            "Move(VelX, VelY)".to_string(),
+            "If(Grounded())".to_string(),
+            "ChangeState(Idle)".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
-            ":HelperState(Helper):".to_string(),
+            ":LightPunch(Helper):".to_string(),
             "---Init:".to_string(),
-            "Helper()".to_string(),
+            "Set(Damage, 100)".to_string(),
+            "Set(Duration, 15)".to_string(),
+            "PlayAnimation(LightPunch)".to_string(),
+            "---Action:".to_string(),
+            "CreateHitbox(10, 10, 40, 20, Damage)".to_string(),
+            "---Reaction:".to_string(),
+            "If(Hit())".to_string(),
+            "Add(ComboCounter, 1)".to_string(),
+            "Add(Meter, 100)".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
-            ":SpecialState(Special):".to_string(),
+            ":Hadoken(Special):".to_string(),
             "---Init:".to_string(),
-            "Special()".to_string(),
+            "Sub(Meter, 1000)".to_string(),
+            "CreateProjectile(Fireball)".to_string(),
+            "PlayAnimation(Hadoken)".to_string(),
+            "PlaySound(Hadoken)".to_string(),
+            "---Action:".to_string(),
+            "If(AnimationFinished())".to_string(),
+            "ChangeState(Idle)".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["all_state_types.casp".to_string()];
+        parser.file_paths = vec!["ryu.casp".to_string()];
 
-        parser.parse_states(0);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
+
+        assert!(result.is_ok());
+        let character = result.unwrap();
+
+        // Check metadata
+        assert_eq!(character.metadata.name, "Ryu");
+        assert_eq!(character.metadata.author, "Capcom");
+
+        // Check variables (11 var + 3 def = 14 total)
+        assert_eq!(character.variables.len(), 14);
+        assert!(character.variables.contains_key("Health"));
+        assert!(character.variables.contains_key("Meter"));
+        assert!(character.variables.contains_key("WALK_SPEED"));
+
+        // Check states
+        assert_eq!(character.states.len(), 5);
+        assert!(character.states.contains_key("Idle"));
+        assert!(character.states.contains_key("Walk"));
+        assert!(character.states.contains_key("Jump"));
+        assert!(character.states.contains_key("LightPunch"));
+        assert!(character.states.contains_key("Hadoken"));
 
+        // Check state types
         assert_eq!(
-            parser.states.get("NormalState").unwrap().state_type,
+            character.states.get("Idle").unwrap().state_type,
             StateType::Normal
         );
         assert_eq!(
-            parser.states.get("HelperState").unwrap().state_type,
+            character.states.get("Walk").unwrap().state_type,
+            StateType::Normal
+        );
+        assert_eq!(
+            character.states.get("LightPunch").unwrap().state_type,
             StateType::Helper
         );
         assert_eq!(
-            parser.states.get("SpecialState").unwrap().state_type,
+            character.states.get("Hadoken").unwrap().state_type,
             StateType::Special
         );
-    }
-
-    #[test]
-    fn test_edge_string_with_escaped_characters() {
-        let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            r#"Log("Line1\nLine2\tTabbed")"#.to_string(),
-            r#"Set(quote, "He said: \"Hi\"")"#.to_string(),
-            r#"Set(path, "C:\\Users\\Game")"#.to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["escaped.casp".to_string()];
 
-        parser.parse_states(0);
-
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        // Check state parents
+        assert_eq!(
+            character.states.get("Walk").unwrap().parent,
+            Some("Idle".to_string())
+        );
 
-        // Should parse all escaped strings
-        assert_eq!(actions.len(), 3);
+        // Check specblocks
+        assert!(character.specblocks.contains_key("Config"));
+        assert_eq!(
+            character.specblocks.get("Config").unwrap().get("Gravity"),
+            Some(&"10".to_string())
+        );
     }
 
     #[test]
-    fn test_edge_negative_numbers() {
+    fn test_integration_projectile_character() {
         let mut parser = CastagneParser::new();
+
         parser.current_lines = vec![
+            ":Character:".to_string(),
+            "Name: Fireball".to_string(),
+            "Author: Game Dev".to_string(),
+            "".to_string(),
             ":Variables:".to_string(),
-            "var NegInt(Int): -100".to_string(),
-            "var NegVec2(Vec2): -5, -10".to_string(),
-            "var NegVec3(Vec3): -1, -2, -3".to_string(),
+            "var Damage(Int): 200".to_string(),
+            "var Speed(Int): 50".to_string(),
+            "var Lifetime(Int): 60".to_string(),
+            "var CurrentFrame(Int): 0".to_string(),
+            "".to_string(),
+            ":Active(Helper):".to_string(),
+            "---Init:".to_string(),
+            "PlayAnimation(Fireball)".to_string(),
+            "CreateHitbox(0, 0, 20, 20, Damage)".to_string(),
+            "---Action:".to_string(),
+            "MoveForward(Speed)".to_string(),
+            "Add(CurrentFrame, 1)".to_string(),
+            "If(CurrentFrame, Lifetime)".to_string(),
+            "Destroy()".to_string(),
+            "EndIf()".to_string(),
+            "---Reaction:".to_string(),
+            "If(Hit())".to_string(),
+            "Destroy()".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["negative.casp".to_string()];
+        parser.file_paths = vec!["fireball.casp".to_string()];
 
-        parser.parse_variables(0);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        assert!(parser.variables.contains_key("NegInt"));
-        assert!(parser.variables.contains_key("NegVec2"));
-        assert!(parser.variables.contains_key("NegVec3"));
+        assert!(result.is_ok());
+        let character = result.unwrap();
 
-        let neg_int = parser.variables.get("NegInt").unwrap();
-        assert_eq!(neg_int.as_int(), Some(-100));
+        // Check it parsed as a projectile/helper
+        assert_eq!(character.metadata.name, "Fireball");
+        assert_eq!(character.states.len(), 1);
+        assert!(character.states.contains_key("Active"));
+        assert_eq!(
+            character.states.get("Active").unwrap().state_type,
+            StateType::Helper
+        );
     }
 
     #[test]
-    fn test_edge_float_numbers() {
-        let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Variables:".to_string(),
-            "var FloatVar(Int): 3.14".to_string(),
-            "var FloatVec2(Vec2): 1.5, 2.5".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["floats.casp".to_string()];
-
-        parser.parse_variables(0);
-
-        // Should parse floats
-        assert!(parser.variables.contains_key("FloatVar"));
-        assert!(parser.variables.contains_key("FloatVec2"));
-    }
+    fn test_integration_multifile_inheritance() {
+        // This test simulates skeleton inheritance
+        // In a real scenario, this would load from separate files
 
-    #[test]
-    fn test_edge_action_without_parentheses() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            "SimpleAction".to_string(),       // No parentheses
-            "ActionWithParens()".to_string(), // With parentheses
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["no_parens.casp".to_string()];
 
-        parser.parse_states(0);
+        // Simulate parent character data
+        let parent_metadata = CharacterMetadata {
+            name: "BaseCharacter".to_string(),
+            author: "Framework".to_string(),
+            description: "Base template".to_string(),
+            skeleton: None,
+            includes: Vec::new(),
+            no_inherit: Vec::new(),
+            ..CharacterMetadata::default()
+        };
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        parser.metadata = parent_metadata;
 
-        // Both should parse (or at least not crash)
-        assert!(actions.len() >= 1);
-    }
+        // Add parent variables
+        parser.variables.insert(
+            "BaseHealth".into(),
+            ParsedVariable {
+                name: "BaseHealth".into(),
+                mutability: VariableMutability::Variable,
+                var_type: VariableType::Int,
+                subtype: String::new(),
+                value: "1000".to_string(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            },
+        );
 
-    #[test]
-    fn test_edge_multiple_specblocks() {
-        let mut parser = CastagneParser::new();
+        // Now parse child that overrides/extends
         parser.current_lines = vec![
-            ":Config:".to_string(),
-            "Speed: 100".to_string(),
+            ":Character:".to_string(),
+            "Name: DerivedCharacter".to_string(),
             "".to_string(),
-            ":Attacks:".to_string(),
-            "Punch: 10".to_string(),
-            "Kick: 20".to_string(),
+            ":Variables:".to_string(),
+            "var ChildHealth(Int): 1500".to_string(),
             "".to_string(),
-            ":CustomData:".to_string(),
-            "Field1: Value1".to_string(),
+            ":Idle:".to_string(),
+            "---Init:".to_string(),
+            "Set(x, 1)".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["multi_specblock.casp".to_string()];
+        parser.file_paths = vec!["child.casp".to_string()];
 
-        parser.parse_specblocks(0);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        // All specblocks should be parsed
-        assert!(parser.specblocks.contains_key("Config"));
-        assert!(parser.specblocks.contains_key("Attacks"));
-        assert!(parser.specblocks.contains_key("CustomData"));
+        assert!(result.is_ok());
+        let character = result.unwrap();
 
-        assert_eq!(
-            parser.specblocks.get("Config").unwrap().get("Speed"),
-            Some(&"100".to_string())
-        );
-        assert_eq!(
-            parser.specblocks.get("Attacks").unwrap().get("Punch"),
-            Some(&"10".to_string())
-        );
+        // Should have child metadata
+        assert_eq!(character.metadata.name, "DerivedCharacter");
+
+        // Should have both parent and child variables
+        assert!(character.variables.contains_key("BaseHealth"));
+        assert!(character.variables.contains_key("ChildHealth"));
     }
 
     #[test]
-    fn test_edge_state_inheritance_chain() {
+    fn test_integration_complex_conditionals() {
         let mut parser = CastagneParser::new();
-        parser.current_lines = vec![
-            ":Base:".to_string(),
-            "---Init:".to_string(),
-            "BaseAction()".to_string(),
-            "".to_string(),
-            ":Middle(Base):".to_string(),
-            "---Init:".to_string(),
-            "MiddleAction()".to_string(),
-            "".to_string(),
-            ":Final(Middle):".to_string(),
+
+        parser.current_lines = vec![
+            ":Test:".to_string(),
             "---Init:".to_string(),
-            "FinalAction()".to_string(),
+            "If(Health, MaxHealth)".to_string(),
+            "Set(Color, Green)".to_string(),
+            "Else()".to_string(),
+            "If(Health, HalfHealth)".to_string(),
+            "Set(Color, Yellow)".to_string(),
+            "Else()".to_string(),
+            "Set(Color, Red)".to_string(),
+            "EndIf()".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["chain.casp".to_string()];
+        parser.file_paths = vec!["conditionals.casp".to_string()];
 
         parser.parse_states(0);
 
-        // All states should exist
-        assert!(parser.states.contains_key("Base"));
-        assert!(parser.states.contains_key("Middle"));
-        assert!(parser.states.contains_key("Final"));
+        let test = parser.states.get("Test").unwrap();
+        let actions = test.actions.get("Init").unwrap();
 
-        // Check parent relationships
-        assert_eq!(parser.states.get("Base").unwrap().parent, None);
-        assert_eq!(
-            parser.states.get("Middle").unwrap().parent,
-            Some("Base".to_string())
-        );
-        assert_eq!(
-            parser.states.get("Final").unwrap().parent,
-            Some("Middle".to_string())
-        );
+        // Should parse all conditional statements
+        assert!(actions.len() >= 8);
+
+        // Check structure
+        assert_eq!(actions[0].instruction, "If");
+        assert!(actions.iter().any(|a| a.instruction == "Else"));
+        assert!(actions.iter().any(|a| a.instruction == "EndIf"));
     }
 
     #[test]
-    fn test_edge_bool_value_variations() {
+    fn test_integration_all_features_combined() {
         let mut parser = CastagneParser::new();
+
         parser.current_lines = vec![
+            "# Complete character with all features".to_string(),
+            ":Character:".to_string(),
+            "Name: Complete Fighter # Full featured".to_string(),
+            "Author: Test".to_string(),
+            "Description: Uses all parser features".to_string(),
+            "".to_string(),
+            ":Config:".to_string(),
+            "Speed: 100 # Movement speed".to_string(),
+            "".to_string(),
             ":Variables:".to_string(),
-            "var Bool1(Bool): true".to_string(),
-            "var Bool2(Bool): false".to_string(),
-            "var Bool3(Bool): 1".to_string(),
-            "var Bool4(Bool): 0".to_string(),
-            "var Bool5(Bool): True".to_string(),  // Capitalized
-            "var Bool6(Bool): FALSE".to_string(), // All caps
+            "var IntVal(Int): 42".to_string(),
+            "var StrVal(Str): Hello".to_string(),
+            "var BoolVal(Bool): true".to_string(),
+            "var Vec2Val(Vec2): 10, 20".to_string(),
+            "var Vec3Val(Vec3): 1, 2, 3".to_string(),
+            "def CONSTANT: 100".to_string(),
+            "".to_string(),
+            ":Idle:".to_string(),
+            "---Init: # Setup phase".to_string(),
+            "Set(State, 0)".to_string(),
+            "Log(\"Starting Idle\")".to_string(),
+            "---Action:".to_string(),
+            "CheckInput(Attack, Attack)".to_string(),
+            "---Reaction:".to_string(),
+            "HandleHit()".to_string(),
+            "".to_string(),
+            ":Attack(Helper, Idle):".to_string(),
+            "---Init:".to_string(),
+            "CreateHitbox(0, 0, 50, 50, 100)".to_string(),
+            "PlayAnimation(Attack)".to_string(),
+            "---Action:".to_string(),
+            "If(AnimationFinished())".to_string(),
+            "ChangeState(Idle)".to_string(),
+            "EndIf()".to_string(),
             "".to_string(),
         ];
         parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["bool_variations.casp".to_string()];
+        parser.file_paths = vec!["complete.casp".to_string()];
 
-        parser.parse_variables(0);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        // All should parse
-        assert!(parser.variables.contains_key("Bool1"));
-        assert!(parser.variables.contains_key("Bool2"));
-        assert!(parser.variables.contains_key("Bool3"));
-        assert!(parser.variables.contains_key("Bool4"));
-        assert!(parser.variables.contains_key("Bool5"));
-        assert!(parser.variables.contains_key("Bool6"));
+        assert!(result.is_ok());
+        let character = result.unwrap();
 
-        // Check values are correct
-        assert_eq!(parser.variables.get("Bool1").unwrap().as_bool(), Some(true));
+        // Metadata
+        assert_eq!(character.metadata.name, "Complete Fighter");
+
+        // Variables - all types
+        assert_eq!(character.variables.len(), 6);
         assert_eq!(
-            parser.variables.get("Bool2").unwrap().as_bool(),
-            Some(false)
+            character.variables.get("IntVal").unwrap().var_type,
+            VariableType::Int
         );
-        assert_eq!(parser.variables.get("Bool3").unwrap().as_bool(), Some(true));
         assert_eq!(
-            parser.variables.get("Bool4").unwrap().as_bool(),
-            Some(false)
+            character.variables.get("Vec2Val").unwrap().var_type,
+            VariableType::Vec2
         );
+
+        // Specblocks
+        assert!(character.specblocks.contains_key("Config"));
+
+        // States with all features
+        assert_eq!(character.states.len(), 2);
+        let attack = character.states.get("Attack").unwrap();
+        assert_eq!(attack.state_type, StateType::Helper);
+        assert_eq!(attack.parent, Some("Idle".to_string()));
+        assert!(attack.actions.contains_key("Init"));
+        assert!(attack.actions.contains_key("Action"));
     }
 
     // =========================================================================
-    // Performance and Stress Tests
+    // Basic Template Tests - Verify Rust parser can parse test files
     // =========================================================================
 
     #[test]
-    fn test_stress_many_states() {
+    fn test_basic_template_complete_character_file() {
+        // This test parses the real test_character_complete.casp file
+        // and verifies expected structure
         let mut parser = CastagneParser::new();
+        parser.open_file("test_character_complete.casp");
 
-        // Create 100 states
-        let mut lines = vec![];
-        for i in 0..100 {
-            lines.push(format!(":State{}:", i));
-            lines.push("---Init:".to_string());
-            lines.push(format!("Set(StateID, {})", i));
-            lines.push("".to_string());
+        if parser.aborting || parser.invalid_file {
+            panic!("Failed to load test_character_complete.casp");
         }
 
-        parser.current_lines = lines.clone();
-        parser.line_ids = (1..=lines.len()).collect();
-        parser.file_paths = vec!["stress.casp".to_string()];
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        parser.parse_states(0);
+        assert!(
+            result.is_ok(),
+            "Parser should successfully parse test_character_complete.casp"
+        );
+        let character = result.unwrap();
 
-        // All states should parse
-        assert_eq!(parser.states.len(), 100);
+        // Verify metadata
+        assert_eq!(character.metadata.name, "Complete Test Fighter");
+        assert_eq!(character.metadata.author, "Parser Development Team");
+        assert!(character.metadata.description.contains("comprehensive"));
 
-        // Spot check a few states
-        assert!(parser.states.contains_key("State0"));
-        assert!(parser.states.contains_key("State50"));
-        assert!(parser.states.contains_key("State99"));
-    }
+        // Verify specblocks (2 specblocks: AttackData, PhysicsConfig)
+        assert_eq!(character.specblocks.len(), 2);
+        assert!(character.specblocks.contains_key("AttackData"));
+        assert!(character.specblocks.contains_key("PhysicsConfig"));
 
-    #[test]
-    fn test_stress_many_variables() {
-        let mut parser = CastagneParser::new();
+        let attack_data = character.specblocks.get("AttackData").unwrap();
+        assert_eq!(attack_data.get("LightPunchDamage"), Some(&"5".to_string()));
+        assert_eq!(attack_data.get("HeavyPunchDamage"), Some(&"15".to_string()));
 
-        // Create 200 variables
-        let mut lines = vec![":Variables:".to_string()];
-        for i in 0..200 {
-            lines.push(format!("var Var{}(Int): {}", i, i * 10));
-        }
-        lines.push("".to_string());
+        let physics = character.specblocks.get("PhysicsConfig").unwrap();
+        assert_eq!(physics.get("Gravity"), Some(&"10".to_string()));
+        assert_eq!(physics.get("JumpForce"), Some(&"25".to_string()));
 
-        parser.current_lines = lines.clone();
-        parser.line_ids = (1..=lines.len()).collect();
-        parser.file_paths = vec!["stress_vars.casp".to_string()];
+        // Verify variables (12 vars + 2 defs = 14 total)
+        assert_eq!(character.variables.len(), 14);
 
-        parser.parse_variables(0);
+        // Check integer variables
+        assert!(character.variables.contains_key("Health"));
+        assert_eq!(
+            character.variables.get("Health").unwrap().var_type,
+            VariableType::Int
+        );
+        assert_eq!(character.variables.get("Health").unwrap().value, "150");
 
-        // All variables should parse
-        assert_eq!(parser.variables.len(), 200);
+        // Check string variables
+        assert!(character.variables.contains_key("PlayerName"));
+        assert_eq!(
+            character.variables.get("PlayerName").unwrap().var_type,
+            VariableType::Str
+        );
 
-        // Spot check values
-        assert_eq!(parser.variables.get("Var0").unwrap().value, "0");
-        assert_eq!(parser.variables.get("Var100").unwrap().value, "1000");
-        assert_eq!(parser.variables.get("Var199").unwrap().value, "1990");
+        // Check boolean variables
+        assert!(character.variables.contains_key("IsGrounded"));
+        assert_eq!(
+            character.variables.get("IsGrounded").unwrap().var_type,
+            VariableType::Bool
+        );
+
+        // Check Vec2 variables
+        assert!(character.variables.contains_key("Position"));
+        assert_eq!(
+            character.variables.get("Position").unwrap().var_type,
+            VariableType::Vec2
+        );
+
+        // Check Var type
+        assert!(character.variables.contains_key("Meter"));
+        assert_eq!(
+            character.variables.get("Meter").unwrap().var_type,
+            VariableType::Var
+        );
+
+        // Check constants
+        assert!(character.variables.contains_key("MAX_COMBO"));
+        assert_eq!(
+            character.variables.get("MAX_COMBO").unwrap().mutability,
+            VariableMutability::Define
+        );
+
+        // Verify states (5 states: Idle, Walk, Jump, LightPunch, HeavyPunch)
+        assert_eq!(character.states.len(), 5);
+        assert!(character.states.contains_key("Idle"));
+        assert!(character.states.contains_key("Walk"));
+        assert!(character.states.contains_key("Jump"));
+        assert!(character.states.contains_key("LightPunch"));
+        assert!(character.states.contains_key("HeavyPunch"));
+
+        // Verify Idle state has Init and Action phases
+        let idle = character.states.get("Idle").unwrap();
+        assert!(idle.actions.contains_key("Init"));
+        assert!(idle.actions.contains_key("Action"));
+        assert_eq!(idle.state_type, StateType::Normal);
+
+        // Verify actions in Idle Init phase
+        let idle_init = idle.actions.get("Init").unwrap();
+        assert_eq!(idle_init.len(), 3); // Set AnimationState, Set Velocity, Set IsAttacking
+        assert_eq!(idle_init[0].instruction, "Set");
+        assert_eq!(&idle_init[0].args[0], "AnimationState");
+
+        // Verify LightPunch has Init, Action, and Reaction phases
+        let light_punch = character.states.get("LightPunch").unwrap();
+        assert!(light_punch.actions.contains_key("Init"));
+        assert!(light_punch.actions.contains_key("Action"));
+        assert!(light_punch.actions.contains_key("Reaction"));
     }
 
     #[test]
-    fn test_stress_many_actions_per_state() {
+    fn test_basic_template_basic_character_file() {
+        // Test the simplest test_character.casp file
         let mut parser = CastagneParser::new();
+        parser.open_file("test_character.casp");
 
-        // Create state with 100 actions
-        let mut lines = vec![":StressTest:".to_string(), "---Init:".to_string()];
-
-        for i in 0..100 {
-            lines.push(format!("Action{}(arg{})", i, i));
+        if parser.aborting || parser.invalid_file {
+            panic!("Failed to load test_character.casp");
         }
-        lines.push("".to_string());
-
-        parser.current_lines = lines.clone();
-        parser.line_ids = (1..=lines.len()).collect();
-        parser.file_paths = vec!["stress_actions.casp".to_string()];
 
-        parser.parse_states(0);
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        let state = parser.states.get("StressTest").unwrap();
-        let actions = state.actions.get("Init").unwrap();
+        assert!(
+            result.is_ok(),
+            "Parser should successfully parse test_character.casp"
+        );
+        let character = result.unwrap();
 
-        // All actions should parse
-        assert_eq!(actions.len(), 100);
+        // Basic character should have metadata
+        assert!(!character.metadata.name.is_empty());
 
-        // Check first and last action
-        assert_eq!(actions[0].instruction, "Action0");
-        assert_eq!(actions[99].instruction, "Action99");
+        // Should have at least one state or variable
+        assert!(!character.states.is_empty() || !character.variables.is_empty());
     }
 
     #[test]
-    fn test_stress_deeply_nested_10_levels() {
+    fn test_basic_template_advanced_character_file() {
+        // Test test_character_advanced.casp
         let mut parser = CastagneParser::new();
+        parser.open_file("test_character_advanced.casp");
 
-        // Create deeply nested function call
-        let nested = "Call(L1(L2(L3(L4(L5(L6(L7(L8(L9(L10(value))))))))))";
+        if parser.aborting || parser.invalid_file {
+            panic!("Failed to load test_character_advanced.casp");
+        }
 
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            nested.to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["deep_nest.casp".to_string()];
+        parser.parse_full_file();
+        let result = parser.end_parsing();
 
-        parser.parse_states(0);
+        assert!(
+            result.is_ok(),
+            "Parser should successfully parse test_character_advanced.casp"
+        );
+        let character = result.unwrap();
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        // Advanced character should have metadata
+        assert!(!character.metadata.name.is_empty());
 
-        // Should handle deep nesting
-        assert_eq!(actions.len(), 1);
-        assert_eq!(actions[0].instruction, "Call");
+        // Should have multiple states demonstrating advanced features
+        assert!(!character.states.is_empty());
     }
 
     #[test]
-    fn test_stress_many_arguments() {
-        let mut parser = CastagneParser::new();
-
-        // Create action with 50 arguments
-        let args: Vec<String> = (0..50).map(|i| format!("arg{}", i)).collect();
-        let action_line = format!("ManyArgs({})", args.join(", "));
-
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            action_line,
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["many_args.casp".to_string()];
+    fn test_basic_template_parent_child_skeleton_inheritance() {
+        // Test skeleton inheritance with test_parent.casp and test_child.casp
 
-        parser.parse_states(0);
+        // First parse parent
+        let mut parent_parser = CastagneParser::new();
+        parent_parser.open_file("test_parent.casp");
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+        if parent_parser.aborting || parent_parser.invalid_file {
+            // If files don't exist, skip this test gracefully
+            return;
+        }
 
-        // Should handle many arguments
-        assert_eq!(actions.len(), 1);
-        assert_eq!(actions[0].args.len(), 50);
-    }
+        parent_parser.parse_full_file();
+        let parent_result = parent_parser.end_parsing();
+        assert!(parent_result.is_ok(), "Parent should parse successfully");
+        let parent = parent_result.unwrap();
 
-    // =========================================================================
-    // Integration Tests with Complex Scenarios
-    // =========================================================================
+        // Then parse child
+        let mut child_parser = CastagneParser::new();
+        child_parser.open_file("test_child.casp");
 
-    #[test]
-    fn test_integration_fighting_game_character() {
-        let mut parser = CastagneParser::new();
+        if child_parser.aborting || child_parser.invalid_file {
+            // If files don't exist, skip this test gracefully
+            return;
+        }
 
-        parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Ryu".to_string(),
-            "Author: Capcom".to_string(),
-            "Description: Wandering fighter".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var Health(Int): 1000".to_string(),
-            "var MaxHealth(Int): 1000".to_string(),
-            "var Meter(Int): 0".to_string(),
-            "var MaxMeter(Int): 10000".to_string(),
-            "var Damage(Int): 0".to_string(),
-            "var PosX(Int): 0".to_string(),
-            "var PosY(Int): 0".to_string(),
-            "var VelX(Int): 0".to_string(),
-            "var VelY(Int): 0".to_string(),
-            "var Facing(Int): 1".to_string(),
-            "var ComboCounter(Int): 0".to_string(),
-            "def GROUND_Y: 0".to_string(),
-            "def JUMP_FORCE: -150".to_string(),
-            "def WALK_SPEED: 30".to_string(),
-            "".to_string(),
-            ":Config:".to_string(),
-            "MaxSpeed: 100".to_string(),
-            "JumpHeight: 150".to_string(),
-            "Gravity: 10".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
-            "---Init:".to_string(),
-            "Set(VelX, 0)".to_string(),
-            "Set(VelY, 0)".to_string(),
-            "ResetCombo()".to_string(),
-            "---Action:".to_string(),
-            "CheckInput(Left, Walk)".to_string(),
-            "CheckInput(Right, Walk)".to_string(),
-            "CheckInput(Jump, Jump)".to_string(),
-            "CheckInput(Attack, LightPunch)".to_string(),
-            "".to_string(),
-            ":Walk(Idle):".to_string(),
-            "---Init:".to_string(),
-            "Set(VelX, WALK_SPEED)".to_string(),
-            "PlayAnimation(Walk)".to_string(),
-            "---Action:".to_string(),
-            "Move(VelX, 0)".to_string(),
-            "CheckInput(None, Idle)".to_string(),
-            "CheckInput(Jump, Jump)".to_string(),
-            "".to_string(),
-            ":Jump(Idle):".to_string(),
-            "---Init:".to_string(),
-            "Set(VelY, JUMP_FORCE)".to_string(),
-            "PlayAnimation(Jump)".to_string(),
-            "PlaySound(Jump)".to_string(),
-            "---Action:".to_string(),
-            "Add(VelY, Gravity)".to_string(),
-            "Move(VelX, VelY)".to_string(),
-            "If(Grounded())".to_string(),
-            "ChangeState(Idle)".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
-            ":LightPunch(Helper):".to_string(),
-            "---Init:".to_string(),
-            "Set(Damage, 100)".to_string(),
-            "Set(Duration, 15)".to_string(),
-            "PlayAnimation(LightPunch)".to_string(),
-            "---Action:".to_string(),
-            "CreateHitbox(10, 10, 40, 20, Damage)".to_string(),
-            "---Reaction:".to_string(),
-            "If(Hit())".to_string(),
-            "Add(ComboCounter, 1)".to_string(),
-            "Add(Meter, 100)".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
-            ":Hadoken(Special):".to_string(),
-            "---Init:".to_string(),
-            "Sub(Meter, 1000)".to_string(),
-            "CreateProjectile(Fireball)".to_string(),
-            "PlayAnimation(Hadoken)".to_string(),
-            "PlaySound(Hadoken)".to_string(),
-            "---Action:".to_string(),
-            "If(AnimationFinished())".to_string(),
-            "ChangeState(Idle)".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["ryu.casp".to_string()];
+        child_parser.parse_full_file();
+        let child_result = child_parser.end_parsing();
+        assert!(child_result.is_ok(), "Child should parse successfully");
+        let child = child_result.unwrap();
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        // Verify both parsed
+        assert!(!parent.metadata.name.is_empty());
+        assert!(!child.metadata.name.is_empty());
 
-        assert!(result.is_some());
-        let character = result.unwrap();
+        // Child might reference parent in skeleton field
+        if let Some(skeleton) = &child.metadata.skeleton {
+            assert!(
+                !skeleton.is_empty(),
+                "Skeleton reference should not be empty"
+            );
+        }
+    }
 
-        // Check metadata
-        assert_eq!(character.metadata.name, "Ryu");
-        assert_eq!(character.metadata.author, "Capcom");
+    #[test]
+    fn test_basic_template_all_variable_type_conversions() {
+        // Verify type conversion works for all types
+        let mut parser = CastagneParser::new();
 
-        // Check variables (11 var + 3 def = 14 total)
-        assert_eq!(character.variables.len(), 14);
-        assert!(character.variables.contains_key("Health"));
-        assert!(character.variables.contains_key("Meter"));
-        assert!(character.variables.contains_key("WALK_SPEED"));
+        parser.variables.insert(
+            "IntVar".into(),
+            ParsedVariable {
+                name: "IntVar".into(),
+                mutability: VariableMutability::Variable,
+                var_type: VariableType::Int,
+                subtype: String::new(),
+                value: "42".to_string(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            },
+        );
 
-        // Check states
-        assert_eq!(character.states.len(), 5);
-        assert!(character.states.contains_key("Idle"));
-        assert!(character.states.contains_key("Walk"));
-        assert!(character.states.contains_key("Jump"));
-        assert!(character.states.contains_key("LightPunch"));
-        assert!(character.states.contains_key("Hadoken"));
+        parser.variables.insert(
+            "BoolVar".into(),
+            ParsedVariable {
+                name: "BoolVar".into(),
+                mutability: VariableMutability::Variable,
+                var_type: VariableType::Bool,
+                subtype: String::new(),
+                value: "true".to_string(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            },
+        );
 
-        // Check state types
-        assert_eq!(
-            character.states.get("Idle").unwrap().state_type,
-            StateType::Normal
+        parser.variables.insert(
+            "StrVar".into(),
+            ParsedVariable {
+                name: "StrVar".into(),
+                mutability: VariableMutability::Variable,
+                var_type: VariableType::Str,
+                subtype: String::new(),
+                value: "Hello".to_string(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            },
         );
+
+        parser.variables.insert(
+            "Vec2Var".into(),
+            ParsedVariable {
+                name: "Vec2Var".into(),
+                mutability: VariableMutability::Variable,
+                var_type: VariableType::Vec2,
+                subtype: String::new(),
+                value: "10, 20".to_string(),
+                section: None,
+                expression: None,
+                origin: SourceRef::default(),
+            },
+        );
+
+        // Verify types are correct (matching GDScript parser behavior)
+        // Note: We can't test actual to_variant() calls since they require Godot runtime
         assert_eq!(
-            character.states.get("Walk").unwrap().state_type,
-            StateType::Normal
+            parser.variables.get("IntVar").unwrap().var_type,
+            VariableType::Int
         );
         assert_eq!(
-            character.states.get("LightPunch").unwrap().state_type,
-            StateType::Helper
+            parser.variables.get("BoolVar").unwrap().var_type,
+            VariableType::Bool
         );
         assert_eq!(
-            character.states.get("Hadoken").unwrap().state_type,
-            StateType::Special
+            parser.variables.get("StrVar").unwrap().var_type,
+            VariableType::Str
         );
-
-        // Check state parents
         assert_eq!(
-            character.states.get("Walk").unwrap().parent,
-            Some("Idle".to_string())
+            parser.variables.get("Vec2Var").unwrap().var_type,
+            VariableType::Vec2
         );
 
-        // Check specblocks
-        assert!(character.specblocks.contains_key("Config"));
+        // Verify values can be accessed (as parser would return them)
+        assert_eq!(parser.variables.get("IntVar").unwrap().as_int(), Some(42));
         assert_eq!(
-            character.specblocks.get("Config").unwrap().get("Gravity"),
-            Some(&"10".to_string())
+            parser.variables.get("BoolVar").unwrap().as_bool(),
+            Some(true)
         );
+        assert_eq!(parser.variables.get("StrVar").unwrap().value, "Hello");
     }
 
     #[test]
-    fn test_integration_projectile_character() {
-        let mut parser = CastagneParser::new();
+    fn test_basic_template_comment_stripping() {
+        // Verify inline comment handling
+        let parser = CastagneParser::new();
 
-        parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: Fireball".to_string(),
-            "Author: Game Dev".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var Damage(Int): 200".to_string(),
-            "var Speed(Int): 50".to_string(),
-            "var Lifetime(Int): 60".to_string(),
-            "var CurrentFrame(Int): 0".to_string(),
-            "".to_string(),
-            ":Active(Helper):".to_string(),
-            "---Init:".to_string(),
-            "PlayAnimation(Fireball)".to_string(),
-            "CreateHitbox(0, 0, 20, 20, Damage)".to_string(),
-            "---Action:".to_string(),
-            "MoveForward(Speed)".to_string(),
-            "Add(CurrentFrame, 1)".to_string(),
-            "If(CurrentFrame, Lifetime)".to_string(),
-            "Destroy()".to_string(),
-            "EndIf()".to_string(),
-            "---Reaction:".to_string(),
-            "If(Hit())".to_string(),
-            "Destroy()".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
+        // Test cases that should match GDScript behavior
+        let test_cases = vec![
+            // (input, expected_output)
+            ("Set(x, 5) # comment", "Set(x, 5) "),
+            (
+                "var Health(Int): 100 # player health",
+                "var Health(Int): 100 ",
+            ),
+            (
+                r#"Log("Text # not comment") # real comment"#,
+                r#"Log("Text # not comment") "#,
+            ),
+            ("# just comment", ""),
+            ("NoComment", "NoComment"),
         ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["fireball.casp".to_string()];
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        for (input, expected) in test_cases {
+            let result = parser.strip_inline_comment(input);
+            assert_eq!(
+                result, expected,
+                "Comment stripping for '{}' should match GDScript behavior",
+                input
+            );
+        }
+    }
 
-        assert!(result.is_some());
-        let character = result.unwrap();
+    #[test]
+    fn test_basic_template_argument_parsing() {
+        // Verify argument parsing works correctly
+        let parser = CastagneParser::new();
 
-        // Check it parsed as a projectile/helper
-        assert_eq!(character.metadata.name, "Fireball");
-        assert_eq!(character.states.len(), 1);
-        assert!(character.states.contains_key("Active"));
-        assert_eq!(
-            character.states.get("Active").unwrap().state_type,
-            StateType::Helper
-        );
+        // Test cases that should match GDScript behavior
+        let test_cases = vec![
+            // (input, expected_count, description)
+            ("a, b, c", 3, "simple args"),
+            ("a", 1, "single arg"),
+            ("", 0, "empty args"),
+            (r#""string, with, commas", other"#, 2, "string with commas"),
+            ("Nested(a, b), c", 2, "nested function call"),
+            ("a,b,c", 3, "no spaces"),
+            ("a  ,  b  ,  c", 3, "extra spaces"),
+        ];
+
+        for (input, expected_count, desc) in test_cases {
+            let result = parser.parse_arguments(input);
+            assert_eq!(
+                result.len(),
+                expected_count,
+                "Argument parsing for '{}' ({}) should match GDScript behavior",
+                input,
+                desc
+            );
+        }
     }
 
     #[test]
-    fn test_integration_multifile_inheritance() {
-        // This test simulates skeleton inheritance
-        // In a real scenario, this would load from separate files
+    fn test_basic_template_state_header_parsing() {
+        // Verify state header parsing works correctly
+        let parser = CastagneParser::new();
 
-        let mut parser = CastagneParser::new();
+        // Test various state header formats (without colons - parse_state_header expects preprocessed input)
+        let test_cases = vec![
+            // (input, (expected_name, expected_type, expected_parent))
+            ("Idle", ("Idle", StateType::Normal, None)),
+            ("Walk(Idle)", ("Walk", StateType::Normal, Some("Idle"))),
+            ("Attack(Helper)", ("Attack", StateType::Helper, None)),
+            (
+                "Special(Special, Base)",
+                ("Special", StateType::Special, Some("Base")),
+            ),
+            (
+                "Projectile(Helper, Base)",
+                ("Projectile", StateType::Helper, Some("Base")),
+            ),
+        ];
 
-        // Simulate parent character data
-        let parent_metadata = CharacterMetadata {
-            name: "BaseCharacter".to_string(),
-            author: "Framework".to_string(),
-            description: "Base template".to_string(),
-            skeleton: None,
-            other_fields: HashMap::new(),
-        };
+        for (input, expected_data) in test_cases {
+            let (exp_name, exp_type, exp_parent) = expected_data;
+            let (name, state_type, parent) = parser.parse_state_header(input);
+            assert_eq!(name, exp_name, "State name should match for '{}'", input);
+            assert_eq!(
+                state_type, exp_type,
+                "State type should match for '{}'",
+                input
+            );
+            assert_eq!(
+                parent,
+                exp_parent.map(|s| s.to_string()),
+                "Parent should match for '{}'",
+                input
+            );
+        }
+    }
 
-        parser.metadata = parent_metadata;
+    #[test]
+    fn test_include_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Variables:\nvar Health(Int): 100\nvar Speed(Int): 5\n:Throw:\n---Init:\nFrom(Base)\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("throws.casp"),
+            ":Variables:\nvar Speed(Int): 7\n:Throw:\n---Init:\nFrom(Throws)\n:ThrowTech:\n---Init:\nFrom(Throws)\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("universal.casp"),
+            ":Config:\nWalkSpeed: 3\n:ThrowTech:\n---Init:\nFrom(Universal)\n:Burst:\n---Init:\nFrom(Universal)\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("child.casp"),
+            format!(
+                ":Character:\nName: Child\nSkeleton: {}\nInclude: {}, {}\n:Burst:\n---Init:\nFrom(Child)\n",
+                path("base.casp"),
+                path("throws.casp"),
+                path("universal.casp")
+            ),
+        )
+        .unwrap();
 
-        // Add parent variables
-        parser.variables.insert(
-            "BaseHealth".to_string(),
-            ParsedVariable {
-                name: "BaseHealth".to_string(),
-                mutability: VariableMutability::Variable,
-                var_type: VariableType::Int,
-                subtype: String::new(),
-                value: "1000".to_string(),
-            },
+        let mut parser = CastagneParser::new();
+        let character = parser.create_full_character(&path("child.casp")).unwrap();
+        let origin = |state: &str| character.states[state].actions["Init"][0].args[0].to_string();
+
+        assert_eq!(character.metadata.name, "Child");
+        assert_eq!(character.metadata.includes.len(), 2);
+        assert_eq!(origin("Throw"), "Throws");
+        assert_eq!(origin("ThrowTech"), "Universal");
+        assert_eq!(origin("Burst"), "Child");
+        assert_eq!(character.variables["Health"].value, "100");
+        assert_eq!(character.variables["Speed"].value, "7");
+        assert_eq!(character.specblocks["Config"]["WalkSpeed"], "3");
+        // Included states are not part of this file's index
+        assert_eq!(character.source_index.states().len(), 1);
+
+        let at = |file: &str, line| SourceRef {
+            file: path(file),
+            line,
+        };
+        assert_eq!(character.variables["Health"].origin, at("base.casp", 4));
+        assert_eq!(character.variables["Speed"].origin, at("throws.casp", 2));
+        assert_eq!(character.states["Throw"].origin, at("throws.casp", 3));
+        assert_eq!(
+            character.states["ThrowTech"].origin,
+            at("universal.casp", 3)
         );
+        assert_eq!(character.states["Burst"].origin, at("child.casp", 5));
+        assert_eq!(
+            character.specblock_origins["Config"]["WalkSpeed"],
+            at("universal.casp", 2)
+        );
+        assert_eq!(
+            character.states["Burst"].origin.to_string(),
+            format!("{}:5", path("child.casp"))
+        );
+    }
 
-        // Now parse child that overrides/extends
-        parser.current_lines = vec![
-            ":Character:".to_string(),
-            "Name: DerivedCharacter".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var ChildHealth(Int): 1500".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
-            "---Init:".to_string(),
-            "Set(x, 1)".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["child.casp".to_string()];
-
-        parser.parse_full_file();
-        let result = parser.end_parsing();
-
-        assert!(result.is_some());
-        let character = result.unwrap();
+    #[test]
+    fn test_include_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nFrom(Base)\n",
+        )
+        .unwrap();
+        for (name, speed) in [("a", 5), ("b", 7)] {
+            std::fs::write(
+                path(&format!("{}.casp", name)),
+                format!(
+                    ":Character:\nSkeleton: {}\n:Variables:\nvar Speed(Int): {}\n:Throw:\n---Init:\nFrom({})\n:Burst:\n---Init:\nFrom({})\n",
+                    path("base.casp"),
+                    speed,
+                    name,
+                    name
+                ),
+            )
+            .unwrap();
+        }
+        let child = path("child.casp");
+        std::fs::write(
+            &child,
+            format!(
+                ":Character:\nName: Child\nInclude: {}, {}\n:Burst:\n---Init:\nFrom(Child)\n",
+                path("a.casp"),
+                path("b.casp")
+            ),
+        )
+        .unwrap();
+
+        let parse = |resolution| {
+            let config = ParserConfig::new().with_conflict_resolution(resolution);
+            let mut parser = CastagneParser::with_config(config);
+            let character = parser.create_full_character(&child).unwrap();
+            let from = character.states["Throw"].actions["Init"][0].args[0].to_string();
+            let mut conflicts: Vec<Diagnostic> = parser
+                .get_diagnostics()
+                .iter()
+                .filter(|d| d.code == INHERITANCE_CONFLICT)
+                .cloned()
+                .collect();
+            conflicts.sort_by(|a, b| a.message.cmp(&b.message));
+            (from, character.variables["Speed"].value.clone(), conflicts)
+        };
 
-        // Should have child metadata
-        assert_eq!(character.metadata.name, "DerivedCharacter");
+        let (from, speed, conflicts) = parse(ConflictResolution::LastWins);
+        assert_eq!((from.as_str(), speed.as_str()), ("b", "7"));
+        // Idle comes from the skeleton both share, Burst is settled by the child
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[0].message,
+            format!(
+                "State Throw is defined differently by {} and {}, using {}",
+                path("a.casp"),
+                path("b.casp"),
+                path("b.casp")
+            )
+        );
+        assert!(conflicts[1].message.starts_with("Variable Speed"));
+        assert_eq!(
+            conflicts[0].notes,
+            vec![
+                format!("{} defines it at {}:5", path("a.casp"), path("a.casp")),
+                format!("{} defines it at {}:5", path("b.casp"), path("b.casp")),
+                format!("Define Throw in {} to choose", child),
+            ]
+        );
 
-        // Should have both parent and child variables
-        assert!(character.variables.contains_key("BaseHealth"));
-        assert!(character.variables.contains_key("ChildHealth"));
+        let (from, speed, conflicts) = parse(ConflictResolution::FirstWins);
+        assert_eq!((from.as_str(), speed.as_str()), ("a", "5"));
+        assert!(conflicts[0]
+            .message
+            .ends_with(&format!("using {}", path("a.casp"))));
     }
 
     #[test]
-    fn test_integration_complex_conditionals() {
-        let mut parser = CastagneParser::new();
+    fn test_inheritance_directives() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp");
+        let child = dir.path().join("child.casp");
+        std::fs::write(
+            &base,
+            ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n:Walk:\n---Init:\nStop\n:Run:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &child,
+            format!(
+                ":Character:\nSkeleton: {}\nNoInherit: Run, Dash\n:Walk(Override):\n---Init:\nMove\n:Jump(Helper, Override):\n---Init:\nJump\n",
+                base.display()
+            ),
+        )
+        .unwrap();
 
-        parser.current_lines = vec![
-            ":Test:".to_string(),
-            "---Init:".to_string(),
-            "If(Health, MaxHealth)".to_string(),
-            "Set(Color, Green)".to_string(),
-            "Else()".to_string(),
-            "If(Health, HalfHealth)".to_string(),
-            "Set(Color, Yellow)".to_string(),
-            "Else()".to_string(),
-            "Set(Color, Red)".to_string(),
-            "EndIf()".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["conditionals.casp".to_string()];
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character(child.to_str().unwrap())
+            .unwrap();
 
-        parser.parse_states(0);
+        assert_eq!(character.metadata.no_inherit, vec!["Run", "Dash"]);
+        assert!(!character.states.contains_key("Run"));
+        assert!(character.states.contains_key("Idle"));
+        let walk = &character.states["Walk"];
+        assert_eq!(walk.parent, None);
+        assert_eq!(walk.actions["Init"][0].instruction, "Move");
+        assert_eq!(character.states["Jump"].state_type, StateType::Helper);
+
+        let found: Vec<(&str, usize, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = d.span.as_ref().unwrap();
+                (d.code.as_str(), span.line, span.column)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![(UNMATCHED_NO_INHERIT, 3, 16), (UNMATCHED_OVERRIDE, 7, 14)]
+        );
+        assert!(parser.errors[0].starts_with("NoInherit: no parent defines a state Dash"));
+    }
 
-        let test = parser.states.get("Test").unwrap();
-        let actions = test.actions.get("Init").unwrap();
+    #[test]
+    fn test_include_cycle_is_fatal() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.casp");
+        let b = dir.path().join("b.casp");
+        std::fs::write(&a, format!(":Character:\nInclude: {}\n", b.display())).unwrap();
+        std::fs::write(&b, format!(":Character:\nInclude: {}\n", a.display())).unwrap();
 
-        // Should parse all conditional statements
-        assert!(actions.len() >= 8);
+        let mut parser = CastagneParser::new();
+        let result = parser.create_full_character(a.to_str().unwrap());
 
-        // Check structure
-        assert_eq!(actions[0].instruction, "If");
-        assert!(actions.iter().any(|a| a.instruction == "Else"));
-        assert!(actions.iter().any(|a| a.instruction == "EndIf"));
+        assert!(result.is_err());
+        assert!(parser.errors.iter().any(|e| e.contains("Include cycle")));
     }
 
     #[test]
-    fn test_integration_all_features_combined() {
+    fn test_parse_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("a.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("b.casp")),
+        )
+        .unwrap();
+        std::fs::write(
+            path("b.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("a.casp")),
+        )
+        .unwrap();
+
         let mut parser = CastagneParser::new();
+        let failure = parser.create_full_character(&path("a.casp")).unwrap_err();
+        assert_eq!(
+            failure,
+            ParseFailure::Skeleton {
+                path: path("b.casp"),
+                cause: Box::new(ParseFailure::SkeletonCycle {
+                    chain: vec![path("a.casp"), path("b.casp"), path("a.casp")],
+                }),
+            }
+        );
+        assert_eq!(parser.failure(), Some(&failure));
+        assert_eq!(
+            parser.get_errors().last().unwrap(),
+            &format!("Failed to load skeleton file: {}", path("b.casp"))
+        );
 
-        parser.current_lines = vec![
-            "# Complete character with all features".to_string(),
-            ":Character:".to_string(),
-            "Name: Complete Fighter # Full featured".to_string(),
-            "Author: Test".to_string(),
-            "Description: Uses all parser features".to_string(),
-            "".to_string(),
-            ":Config:".to_string(),
-            "Speed: 100 # Movement speed".to_string(),
-            "".to_string(),
-            ":Variables:".to_string(),
-            "var IntVal(Int): 42".to_string(),
-            "var StrVal(Str): Hello".to_string(),
-            "var BoolVal(Bool): true".to_string(),
-            "var Vec2Val(Vec2): 10, 20".to_string(),
-            "var Vec3Val(Vec3): 1, 2, 3".to_string(),
-            "def CONSTANT: 100".to_string(),
-            "".to_string(),
-            ":Idle:".to_string(),
-            "---Init: # Setup phase".to_string(),
-            "Set(State, 0)".to_string(),
-            "Log(\"Starting Idle\")".to_string(),
-            "---Action:".to_string(),
-            "CheckInput(Attack, Attack)".to_string(),
-            "---Reaction:".to_string(),
-            "HandleHit()".to_string(),
-            "".to_string(),
-            ":Attack(Helper, Idle):".to_string(),
-            "---Init:".to_string(),
-            "CreateHitbox(0, 0, 50, 50, 100)".to_string(),
-            "PlayAnimation(Attack)".to_string(),
-            "---Action:".to_string(),
-            "If(AnimationFinished())".to_string(),
-            "ChangeState(Idle)".to_string(),
-            "EndIf()".to_string(),
-            "".to_string(),
-        ];
-        parser.line_ids = (1..=parser.current_lines.len()).collect();
-        parser.file_paths = vec!["complete.casp".to_string()];
+        let failure = parser
+            .create_full_character(&path("missing.casp"))
+            .unwrap_err();
+        assert!(
+            matches!(&failure, ParseFailure::Io { path: missing, .. } if missing.ends_with("missing.casp"))
+        );
+        assert_eq!(parser.get_errors(), [failure.to_string()]);
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        let source = ":Character:\nFormatVersion: 99\n";
+        let failure = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap_err();
+        assert!(matches!(failure, ParseFailure::Syntax { span: None, .. }));
 
-        assert!(result.is_some());
-        let character = result.unwrap();
+        parser
+            .create_full_character_from_source("test.casp", ":Idle:\n---Init:\nStop\n")
+            .unwrap();
+        assert_eq!(parser.failure(), None);
+    }
 
-        // Metadata
-        assert_eq!(character.metadata.name, "Complete Fighter");
+    #[test]
+    fn test_dependency_diagnostics_trace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nAnim(Idle\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("model.casp"),
+            format!(
+                ":Character:\nName: Model\nSkeleton: {}\n",
+                path("base.casp")
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            path("child.casp"),
+            format!(
+                ":Character:\nName: Child\n\nInclude: {}\n",
+                path("model.casp")
+            ),
+        )
+        .unwrap();
 
-        // Variables - all types
-        assert_eq!(character.variables.len(), 6);
+        let mut parser = CastagneParser::new();
+        parser.create_full_character(&path("child.casp")).unwrap();
+        let unbalanced = parser
+            .get_diagnostics()
+            .iter()
+            .find(|d| d.code == UNBALANCED_DELIMITER)
+            .unwrap();
         assert_eq!(
-            character.variables.get("IntVal").unwrap().var_type,
-            VariableType::Int
+            unbalanced.span.as_ref().unwrap().file,
+            Some(path("base.casp"))
         );
+        assert_eq!(unbalanced.span.as_ref().unwrap().line, 5);
         assert_eq!(
-            character.variables.get("Vec2Val").unwrap().var_type,
-            VariableType::Vec2
+            unbalanced.notes,
+            [
+                format!("in the skeleton of {}:3", path("model.casp")),
+                format!("in a file included from {}:4", path("child.casp")),
+            ]
         );
 
-        // Specblocks
-        assert!(character.specblocks.contains_key("Config"));
+        // What stopped a skeleton is reported in its file
+        std::fs::write(
+            path("model.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("gone.casp")),
+        )
+        .unwrap();
+        parser
+            .create_full_character(&path("model.casp"))
+            .unwrap_err();
+        let failure = parser
+            .get_diagnostics()
+            .iter()
+            .find(|d| d.code == PARSE_FAILURE)
+            .unwrap();
+        assert!(failure
+            .message
+            .starts_with(&format!("File {}", path("gone.casp"))));
+        assert_eq!(
+            failure.notes,
+            [format!("in the skeleton of {}:2", path("model.casp"))]
+        );
 
-        // States with all features
-        assert_eq!(character.states.len(), 2);
-        let attack = character.states.get("Attack").unwrap();
-        assert_eq!(attack.state_type, StateType::Helper);
-        assert_eq!(attack.parent, Some("Idle".to_string()));
-        assert!(attack.actions.contains_key("Init"));
-        assert!(attack.actions.contains_key("Action"));
+        parser
+            .create_full_character(&path("child.casp"))
+            .unwrap_err();
+        let failures: Vec<_> = parser
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.code == PARSE_FAILURE)
+            .collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].notes.len(), 2);
     }
 
-    // =========================================================================
-    // Basic Template Tests - Verify Rust parser can parse test files
-    // =========================================================================
-
     #[test]
-    fn test_basic_template_complete_character_file() {
-        // This test parses the real test_character_complete.casp file
-        // and verifies expected structure
-        let mut parser = CastagneParser::new();
-        parser.open_file("test_character_complete.casp");
+    fn test_conditional_lines_and_blocks() {
+        let source = ":Character:
+Name: Test
+?DEMO Description: Demo build
+:Variables:
+var Health(Int): 100
+?DEMO var Health(Int): 50
+?!DEMO var Meter(Int): 3
+:Idle:
+---Init:
+Move(1)
+?DEMO Move(2)
+?DEMO :Secret:
+---Init:
+Unlock()
+:Walk:
+---Init:
+Move(3)
+";
+
+        let full = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        assert_eq!(full.variables["Health"].value, "100");
+        assert!(full.variables.contains_key("Meter"));
+        assert_eq!(full.states["Idle"].actions["Init"].len(), 1);
+        assert!(!full.states.contains_key("Secret"));
+        assert!(full.states.contains_key("Walk"));
+        assert_eq!(full.metadata.description, "");
+
+        let config = ParserConfig::new().with_flag("DEMO");
+        let demo = CastagneParser::with_config(config)
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        assert_eq!(demo.variables["Health"].value, "50");
+        assert!(!demo.variables.contains_key("Meter"));
+        assert_eq!(demo.states["Idle"].actions["Init"].len(), 2);
+        assert_eq!(demo.states["Idle"].actions["Init"][1].line_number, 11);
+        assert_eq!(
+            demo.states["Secret"].actions["Init"][0].instruction,
+            "Unlock"
+        );
+        assert_eq!(demo.metadata.description, "Demo build");
+    }
 
-        if parser.aborting || parser.invalid_file {
-            panic!("Failed to load test_character_complete.casp");
-        }
+    #[test]
+    fn test_template_expansion() {
+        let source = ":Character:
+Name: Test
+:Template JumpCancel(Target, Frames):
+CancelWindow(Frames)
+Transition(Target, 1)
+:JumpA:
+---Action:
+Move(1)
+UseTemplate(JumpCancel, Jump, 4)
+Move(2)
+";
+
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        assert!(!character.states.contains_key("Template JumpCancel"));
+        assert!(!character.specblocks.contains_key("Template JumpCancel"));
+        let actions = &character.states["JumpA"].actions["Action"];
+        let instructions: Vec<&str> = actions.iter().map(|a| a.instruction.as_str()).collect();
+        assert_eq!(
+            instructions,
+            vec!["Move", "CancelWindow", "Transition", "Move"]
+        );
+        assert_eq!(actions[1].args, vec!["4"]);
+        assert_eq!(actions[2].args, vec!["Jump", "1"]);
+        assert_eq!(actions[2].line_number, 9);
+        assert_eq!(character.action_at(10).unwrap().args, vec!["2"]);
+        assert_eq!(character.templates["JumpCancel"].line_number, 3);
+    }
 
-        assert!(
-            result.is_some(),
-            "Parser should successfully parse test_character_complete.casp"
+    #[test]
+    fn test_template_parameters_inside_arguments() {
+        let source = ":Template Hit(Damage, Bonus):
+Attack(Add(Damage, 1), \"Damage\")
+Set(Total, Damage * 2 + Bonus, DamageMax)
+:Jab:
+---Init:
+UseTemplate(Hit, 10, Base + 5)
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let actions = &character.states["Jab"].actions["Init"];
+        assert_eq!(actions[0].args, ["Add(10, 1)", "\"Damage\""]);
+        assert_eq!(
+            actions[1].args,
+            ["Total", "10 * 2 + (Base + 5)", "DamageMax"]
         );
-        let character = result.unwrap();
+    }
 
-        // Verify metadata
-        assert_eq!(character.metadata.name, "Complete Test Fighter");
-        assert_eq!(character.metadata.author, "Parser Development Team");
-        assert!(character.metadata.description.contains("comprehensive"));
+    #[test]
+    fn test_template_errors_name_their_source() {
+        let source = ":Template Outer(X):
+UseTemplate(Missing, X)
+:Template Loop:
+UseTemplate(Loop)
+:Idle:
+---Init:
+UseTemplate(Outer, 1)
+UseTemplate(Outer)
+UseTemplate(Loop)
+";
 
-        // Verify specblocks (2 specblocks: AttackData, PhysicsConfig)
-        assert_eq!(character.specblocks.len(), 2);
-        assert!(character.specblocks.contains_key("AttackData"));
-        assert!(character.specblocks.contains_key("PhysicsConfig"));
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        let attack_data = character.specblocks.get("AttackData").unwrap();
-        assert_eq!(attack_data.get("LightPunchDamage"), Some(&"5".to_string()));
-        assert_eq!(attack_data.get("HeavyPunchDamage"), Some(&"15".to_string()));
+        assert!(character.states["Idle"].actions["Init"].is_empty());
+        assert_eq!(
+            parser.errors,
+            vec![
+                "Unknown template Missing (line 7, via template Outer at line 2)",
+                "Template Outer expects 1 arguments, got 0 (line 8)",
+                "Template Loop expands recursively (line 9, via template Loop at line 4)",
+            ]
+        );
+    }
 
-        let physics = character.specblocks.get("PhysicsConfig").unwrap();
-        assert_eq!(physics.get("Gravity"), Some(&"10".to_string()));
-        assert_eq!(physics.get("JumpForce"), Some(&"25".to_string()));
+    #[test]
+    fn test_states_carry_attack_notation() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":2B:\n---Init:\nAttack(1)\n:j.236A(Special):\n---Init:\n:Idle:\n",
+            )
+            .unwrap();
 
-        // Verify variables (12 vars + 2 defs = 14 total)
-        assert_eq!(character.variables.len(), 14);
+        let crouching = character.states["2B"].attack.as_ref().unwrap();
+        assert!(crouching.is_crouching());
+        let special = character.states["j.236A"].attack.as_ref().unwrap();
+        assert!(special.airborne);
+        assert_eq!(special.motion.as_deref(), Some("236"));
+        assert!(character.states["Idle"].attack.is_none());
+    }
 
-        // Check integer variables
-        assert!(character.variables.contains_key("Health"));
+    #[test]
+    fn test_variables_sections_and_entities() {
+        let source = ":Character:
+Name: Test
+:Variables:
+var Health(Int): 1000
+def MaxSpeed: 12
+:Variables-Internals-Core:
+internal StateTimer(Int)
+var Register(Int): 0
+:Fireball---Variables:
+var Speed(Int): 8
+:Idle:
+---Init:
+Move(1)
+";
+
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+
+        assert!(parser.errors.is_empty());
+        assert!(!character.states.contains_key("Variables-Internals-Core"));
+        assert!(!character.specblocks.contains_key("Fireball---Variables"));
+        assert_eq!(character.variables["Health"].section, None);
+
+        let timer = &character.variables["StateTimer"];
+        assert_eq!(timer.mutability, VariableMutability::Internal);
+        assert_eq!(timer.section.as_deref(), Some("Internals-Core"));
+        let internals: Vec<&str> = character
+            .section_variables("Internals-Core")
+            .iter()
+            .map(|var| var.name.as_str())
+            .collect();
+        assert_eq!(internals, vec!["Register", "StateTimer"]);
+
+        assert!(!character.variables.contains_key("Speed"));
         assert_eq!(
-            character.variables.get("Health").unwrap().var_type,
-            VariableType::Int
+            character.variable(Some("Fireball"), "Speed").unwrap().value,
+            "8"
         );
-        assert_eq!(character.variables.get("Health").unwrap().value, "150");
-
-        // Check string variables
-        assert!(character.variables.contains_key("PlayerName"));
         assert_eq!(
-            character.variables.get("PlayerName").unwrap().var_type,
-            VariableType::Str
+            character
+                .variable(Some("Fireball"), "MaxSpeed")
+                .unwrap()
+                .value,
+            "12"
         );
+        assert!(character.variable(Some("Fireball"), "Health").is_none());
+        assert!(character.variable(None, "Health").is_some());
+    }
 
-        // Check boolean variables
-        assert!(character.variables.contains_key("IsGrounded"));
+    #[test]
+    fn test_internal_variables_cannot_be_assigned() {
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", ":Variables:\ninternal Timer(Int): 5\n")
+            .unwrap();
+
+        assert!(!character.variables.contains_key("Timer"));
         assert_eq!(
-            character.variables.get("IsGrounded").unwrap().var_type,
-            VariableType::Bool
+            parser.errors,
+            vec!["Can't assign an internal variable (line 2)"]
         );
+    }
 
-        // Check Vec2 variables
-        assert!(character.variables.contains_key("Position"));
+    #[test]
+    fn test_internal_variables_mark_declared_ones() {
+        let source = ":Variables:
+var PositionX(Int): 40
+internal PositionX
+internal Timer(Int)
+var Timer(Int): 3
+internal Missing
+def Gravity: 5
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+
+        let position = &character.variables["PositionX"];
+        assert_eq!(position.mutability, VariableMutability::Internal);
         assert_eq!(
-            character.variables.get("Position").unwrap().var_type,
-            VariableType::Vec2
+            (&position.var_type, position.value.as_str()),
+            (&VariableType::Int, "40")
         );
-
-        // Check Var type
-        assert!(character.variables.contains_key("Meter"));
         assert_eq!(
-            character.variables.get("Meter").unwrap().var_type,
-            VariableType::Var
+            character.variables["Timer"].mutability,
+            VariableMutability::Internal
         );
-
-        // Check constants
-        assert!(character.variables.contains_key("MAX_COMBO"));
+        // Declared by an engine module
+        let missing = &character.variables["Missing"];
+        assert_eq!(missing.mutability, VariableMutability::Internal);
+        assert_eq!(missing.var_type, VariableType::Var);
         assert_eq!(
-            character.variables.get("MAX_COMBO").unwrap().mutability,
-            VariableMutability::Define
+            parser.errors,
+            ["Variable Timer has the same name as an internal variable, use internal to redeclare it (line 5)"]
         );
 
-        // Verify states (5 states: Idle, Walk, Jump, LightPunch, HeavyPunch)
-        assert_eq!(character.states.len(), 5);
-        assert!(character.states.contains_key("Idle"));
-        assert!(character.states.contains_key("Walk"));
-        assert!(character.states.contains_key("Jump"));
-        assert!(character.states.contains_key("LightPunch"));
-        assert!(character.states.contains_key("HeavyPunch"));
+        // Defines are constants to the GDScript parser
+        let gravity = serde_json::to_value(&character.variables["Gravity"]).unwrap();
+        assert_eq!(gravity["mutability"], "Constant");
+        let define: VariableMutability = serde_json::from_str("\"Define\"").unwrap();
+        assert_eq!(define, VariableMutability::Define);
+    }
 
-        // Verify Idle state has Init and Action phases
-        let idle = character.states.get("Idle").unwrap();
-        assert!(idle.actions.contains_key("Init"));
-        assert!(idle.actions.contains_key("Action"));
-        assert_eq!(idle.state_type, StateType::Normal);
+    #[test]
+    fn test_color_variables() {
+        let source = ":Variables:
+var Tint(Color): \"#FF8000\"
+var Shadow(Color): rgba(0, 0, 0, 128)
+var Outline(Color)
+var Glow(Color): bright
+";
 
-        // Verify actions in Idle Init phase
-        let idle_init = idle.actions.get("Init").unwrap();
-        assert_eq!(idle_init.len(), 3); // Set AnimationState, Set Velocity, Set IsAttacking
-        assert_eq!(idle_init[0].instruction, "Set");
-        assert_eq!(idle_init[0].args[0], "AnimationState");
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        // Verify LightPunch has Init, Action, and Reaction phases
-        let light_punch = character.states.get("LightPunch").unwrap();
-        assert!(light_punch.actions.contains_key("Init"));
-        assert!(light_punch.actions.contains_key("Action"));
-        assert!(light_punch.actions.contains_key("Reaction"));
+        assert_eq!(character.variables["Tint"].var_type, VariableType::Color);
+        assert_eq!(
+            Rgba::parse(&character.variables["Tint"].value),
+            Some(Rgba::new(255, 128, 0, 255))
+        );
+        assert_eq!(
+            Rgba::parse(&character.variables["Shadow"].value),
+            Some(Rgba::new(0, 0, 0, 128))
+        );
+        assert_eq!(
+            parser.errors,
+            vec!["Invalid default for Glow: bright is not a color"]
+        );
     }
 
     #[test]
-    fn test_basic_template_basic_character_file() {
-        // Test the simplest test_character.casp file
+    fn test_expression_defaults_are_normalized() {
+        let source = ":Variables:
+var Knockback(Vec2): (5, -2)
+var Launch(Vec2): Vec2(10, 20) + Knockback
+var HitstopTime(Int): 0.25s
+var Recovery(Int): 12f
+def Scale: 3
+var Speed(Var): Scale * 2
+var Label(Var): idle
+var Plain(Vec2): 0, 0
+var Broken(Int): 0.01s
+var Loop(Int): Loop + 1
+";
+
         let mut parser = CastagneParser::new();
-        parser.open_file("test_character.casp");
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let value = |name: &str| character.variables[name].value.as_str();
 
-        if parser.aborting || parser.invalid_file {
-            panic!("Failed to load test_character.casp");
-        }
+        assert_eq!(value("Launch"), "(15, 18)");
+        assert_eq!(
+            character.variables["Launch"].expression.as_deref(),
+            Some("Vec2(10, 20) + Knockback")
+        );
+        assert_eq!(value("HitstopTime"), "15");
+        assert_eq!(value("Recovery"), "12");
+        assert_eq!(value("Speed"), "6");
+        assert_eq!(value("Label"), "idle");
+        assert_eq!(value("Plain"), "0, 0");
+        assert!(character.variables["Plain"].expression.is_none());
+        assert_eq!(
+            parser.errors,
+            vec![
+                "Invalid default for Broken: 0.6 is not an integer",
+                "Invalid default for Loop: Circular reference to Loop",
+            ]
+        );
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+        let config = ParserConfig {
+            frames_per_second: 30,
+            ..ParserConfig::default()
+        };
+        let character = CastagneParser::with_config(config)
+            .create_full_character_from_source("test.casp", ":Variables:\nvar T(Int): 0.5s\n")
+            .unwrap();
+        assert_eq!(character.variables["T"].value, "15");
+    }
 
-        assert!(
-            result.is_some(),
-            "Parser should successfully parse test_character.casp"
+    #[test]
+    fn test_hex_binary_and_separated_literals() {
+        let source = ":Variables:
+var PaletteMask(Int): 0xFF00FF
+var InputMask(Int): 0b1010
+var Big(Int): 1_000_000
+var Offset(Int): -0x10
+var Combined(Int): 0x10 + 0b1
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+
+        let int = |name: &str| character.variables[name].as_int();
+        assert_eq!(int("PaletteMask"), Some(0xFF00FF));
+        // Written in decimal, the literal kept as written
+        let mask = &character.variables["PaletteMask"];
+        assert_eq!(mask.value, "16711935");
+        assert_eq!(mask.expression.as_deref(), Some("0xFF00FF"));
+        assert_eq!(int("InputMask"), Some(10));
+        assert_eq!(int("Big"), Some(1_000_000));
+        assert_eq!(int("Offset"), Some(-16));
+        assert_eq!(int("Combined"), Some(17));
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let source = ":Variables:
+var Health(Int): 3_000_000_000
+var Score(Int, 64): 3_000_000_000
+var Huge(Int, 64): 0x1_0000_0000_0000_0000
+var Doubled(Int): 2_000_000_000 * 2
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let found: Vec<(&str, usize, usize, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = d.span.as_ref().unwrap();
+                (d.message.as_str(), span.line, span.column, span.length)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    "Default of Health is out of range: 3_000_000_000 doesn't fit in -2147483648 to 2147483647",
+                    2,
+                    17,
+                    13
+                ),
+                (
+                    "Default of Huge is out of range: 0x1_0000_0000_0000_0000 doesn't fit in -9223372036854775808 to 9223372036854775807",
+                    4,
+                    19,
+                    23
+                ),
+                (
+                    "Default of Doubled is out of range: 4000000000 doesn't fit in -2147483648 to 2147483647",
+                    5,
+                    0,
+                    0
+                ),
+            ]
+        );
+        assert_eq!(
+            parser.get_diagnostics()[0].notes,
+            ["Declare it as a 64-bit Int for larger values: Health(Int, 64)"]
         );
-        let character = result.unwrap();
 
-        // Basic character should have metadata
-        assert!(!character.metadata.name.is_empty());
+        let score = &character.variables["Score"];
+        assert!(score.is_wide_int());
+        assert_eq!(score.value, "3000000000");
+        assert_eq!(score.as_wide_int(), Some(3_000_000_000));
+        assert_eq!(score.as_int(), None);
+    }
 
-        // Should have at least one state or variable
-        assert!(character.states.len() > 0 || character.variables.len() > 0);
+    #[test]
+    fn test_float_variables() {
+        let source = ":Variables:
+var Gravity(Float): -0.5
+var Fall(Float): Gravity * 3
+var Whole(Float): 2
+var Big(Float): 1e3
+var Small(Float): 2.5E-2
+var Broken(Float): fast
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let gravity = &character.variables["Gravity"];
+        assert_eq!(gravity.var_type, VariableType::Float);
+        assert_eq!(gravity.as_float(), Some(-0.5));
+        assert_eq!(character.variables["Fall"].as_float(), Some(-1.5));
+        assert_eq!(character.variables["Whole"].as_float(), Some(2.0));
+        assert_eq!(character.variables["Big"].as_float(), Some(1000.0));
+        assert_eq!(character.variables["Small"].as_float(), Some(0.025));
+        assert_eq!(parser.get_errors().len(), 1);
+        assert!(parser.get_errors().iter().any(|e| e.contains("Broken")));
+
+        let json = serde_json::to_value(gravity).unwrap();
+        assert_eq!(json["var_type"], "Float");
+        assert_eq!(json["value"], "-0.5");
     }
 
     #[test]
-    fn test_basic_template_advanced_character_file() {
-        // Test test_character_advanced.casp
+    fn test_string_escapes_and_multiline_strings() {
+        let source = r#":Character:
+Name: "Tester\u00e9"
+Description: """
+    A wandering swordsman.
+    Says "hi" a lot.
+    """
+:Variables:
+var Greeting(Str): "Hello\n\tWorld"
+var Plain(Str): Hello
+var Broken(Str): "bad \q"
+:Idle:
+---Init:
+Move(1)
+"#;
         let mut parser = CastagneParser::new();
-        parser.open_file("test_character_advanced.casp");
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        if parser.aborting || parser.invalid_file {
-            panic!("Failed to load test_character_advanced.casp");
-        }
+        assert_eq!(character.metadata.name, "Tester\u{e9}");
+        assert_eq!(
+            character.metadata.description,
+            "A wandering swordsman.\nSays \"hi\" a lot."
+        );
+        assert_eq!(character.variables["Greeting"].value, "Hello\n\tWorld");
+        assert_eq!(character.variables["Plain"].value, "Hello");
+        assert_eq!(character.variables["Broken"].value, "\"bad \\q\"");
+        assert_eq!(
+            parser.get_errors(),
+            ["Invalid string for Broken: Unknown escape sequence \\q (line 10)"]
+        );
+        // Lines after the multi-line string keep their numbers
+        assert_eq!(character.states["Idle"].actions["Init"][0].line_number, 13);
+    }
 
-        parser.parse_full_file();
-        let result = parser.end_parsing();
+    #[test]
+    fn test_block_comments_and_line_continuations() {
+        let source = r#":Character:
+Name: Tester /* inline */
+/*
+:Disabled:
+---Init:
+Move(1)
+*/
+:Idle:
+---Init:
+Hitbox(0, 10000, \
+       0, 20000) # body
+Log("a /* not a comment */") /* trailing
+comment */ Move(2)
+Set(x, "C:\\") # path \
+"#;
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        assert!(
-            result.is_some(),
-            "Parser should successfully parse test_character_advanced.casp"
+        assert!(parser.get_errors().is_empty(), "{:?}", parser.get_errors());
+        assert_eq!(character.metadata.name, "Tester");
+        assert!(!character.states.contains_key("Disabled"));
+
+        let actions = &character.states["Idle"].actions["Init"];
+        let summary: Vec<(&str, Vec<&str>, usize)> = actions
+            .iter()
+            .map(|action| {
+                let args = action.args.iter().collect();
+                (action.instruction.as_str(), args, action.line_number)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Hitbox", vec!["0", "10000", "0", "20000"], 10),
+                ("Log", vec![r#""a /* not a comment */""#], 12),
+                ("Move", vec!["2"], 13),
+                ("Set", vec!["x", r#""C:\\""#], 14),
+            ]
         );
-        let character = result.unwrap();
-
-        // Advanced character should have metadata
-        assert!(!character.metadata.name.is_empty());
 
-        // Should have multiple states demonstrating advanced features
-        assert!(character.states.len() > 0);
+        let mut parser = CastagneParser::new();
+        let _ =
+            parser.create_full_character_from_source("test.casp", ":Idle:\n/* open\n---Init:\n");
+        assert_eq!(parser.get_errors(), ["Unterminated block comment (line 2)"]);
     }
 
     #[test]
-    fn test_basic_template_parent_child_skeleton_inheritance() {
-        // Test skeleton inheritance with test_parent.casp and test_child.casp
-
-        // First parse parent
-        let mut parent_parser = CastagneParser::new();
-        parent_parser.open_file("test_parent.casp");
+    fn test_state_locals() {
+        let source = "\
+:Variables:
+var Health(Int): 100
+def Reach: 3
+:Walk:
+---Init:
+let Speed: Reach * 2
+Move(Speed)
+---Action:
+let Health: 1  # Shadows the variable
+let Speed: 5
+let : 1
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("walk.casp", source)
+            .unwrap();
+        let state = &character.states["Walk"];
+        let locals: Vec<(&str, &str, &str, usize)> = state
+            .locals
+            .iter()
+            .map(|l| {
+                (
+                    l.name.as_str(),
+                    l.value.as_str(),
+                    l.phase.as_str(),
+                    l.position,
+                )
+            })
+            .collect();
+        assert_eq!(
+            locals,
+            [
+                ("Speed", "Reach * 2", "Init", 0),
+                ("Health", "1", "Action", 0),
+                ("Speed", "5", "Action", 0)
+            ]
+        );
+        assert_eq!(state.local("Speed").unwrap().line_number, 10);
+        assert_eq!(state.actions["Init"].len(), 1);
+
+        let shadowed: Vec<(&str, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.code == SHADOWED_VARIABLE)
+            .map(|d| (d.message.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            shadowed,
+            [
+                (
+                    "Local Health of state Walk shadows the variable of the character",
+                    9
+                ),
+                ("Local Speed of state Walk shadows the one of line 6", 10)
+            ]
+        );
+        assert_eq!(parser.get_errors(), ["Expected let Name: Value (line 11)"]);
+    }
 
-        if parent_parser.aborting || parent_parser.invalid_file {
-            // If files don't exist, skip this test gracefully
-            return;
-        }
+    #[test]
+    fn test_severity_overrides() {
+        let source = ":Idle:\n---Init:\nMove(1\nLFlag:\nStop\n";
+        let parse = |config: ParserConfig| {
+            let mut parser = CastagneParser::with_config(config);
+            parser
+                .create_full_character_from_source("test.casp", source)
+                .unwrap();
+            let found: Vec<(String, Severity)> = parser
+                .get_diagnostics()
+                .iter()
+                .map(|d| (d.code.clone(), d.severity))
+                .collect();
+            (found, parser.get_errors().to_vec())
+        };
+        let delimiter = || UNBALANCED_DELIMITER.to_string();
+        let endif = || MISSING_ENDIF.to_string();
 
-        parent_parser.parse_full_file();
-        let parent_result = parent_parser.end_parsing();
-        assert!(parent_result.is_some(), "Parent should parse successfully");
-        let parent = parent_result.unwrap();
+        let (found, errors) = parse(ParserConfig::default());
+        assert_eq!(
+            found,
+            vec![(delimiter(), Severity::Error), (endif(), Severity::Warning)]
+        );
+        assert_eq!(errors, ["Unclosed '(' (line 3, column 5)"]);
 
-        // Then parse child
-        let mut child_parser = CastagneParser::new();
-        child_parser.open_file("test_child.casp");
+        let config = ParserConfig::default()
+            .with_severity(UNBALANCED_DELIMITER, Severity::Hint)
+            .with_deny_warnings(true);
+        let (found, errors) = parse(config);
+        assert_eq!(
+            found,
+            vec![(delimiter(), Severity::Hint), (endif(), Severity::Error)]
+        );
+        assert_eq!(
+            errors,
+            ["Branch LFlag: is never closed with endif (line 4, column 1)"]
+        );
 
-        if child_parser.aborting || child_parser.invalid_file {
-            // If files don't exist, skip this test gracefully
-            return;
-        }
+        let (found, errors) = parse(ParserConfig::default().without_diagnostic(MISSING_ENDIF));
+        assert_eq!(found, vec![(delimiter(), Severity::Error)]);
+        assert_eq!(errors.len(), 1);
+    }
 
-        child_parser.parse_full_file();
-        let child_result = child_parser.end_parsing();
-        assert!(child_result.is_some(), "Child should parse successfully");
-        let child = child_result.unwrap();
+    #[test]
+    fn test_resource_limits() {
+        let limits = Limits {
+            max_nesting_depth: 2,
+            max_line_length: 20,
+            max_states: 2,
+            max_skeleton_chain: 1,
+            max_total_lines: 10,
+        };
+        let fail = |source: &str| {
+            let mut parser = CastagneParser::with_config(ParserConfig::new().with_limits(limits));
+            let failure = parser
+                .create_full_character_from_source("test.casp", source)
+                .unwrap_err();
+            let diagnostic = parser.get_diagnostics().last().unwrap().clone();
+            assert_eq!(diagnostic.code, LIMIT_EXCEEDED);
+            assert_eq!(diagnostic.message, failure.to_string());
+            failure
+        };
+        let limit_at = |failure: &ParseFailure| match failure {
+            ParseFailure::LimitExceeded { limit, span, .. } => {
+                (*limit, span.as_ref().map(|span| span.line))
+            }
+            other => panic!("Unexpected failure {:?}", other),
+        };
 
-        // Verify both parsed
-        assert!(!parent.metadata.name.is_empty());
-        assert!(!child.metadata.name.is_empty());
+        let nested = ":Idle:\n---Init:\nLA:\nLB:\nLC:\nStop\nendif\nendif\nendif\n";
+        assert_eq!(limit_at(&fail(nested)), (Limit::NestingDepth, Some(5)));
+        let long = format!(":Idle:\n---Init:\nSay(\"{}\")\n", "a".repeat(20));
+        let failure = fail(&long);
+        assert_eq!(limit_at(&failure), (Limit::LineLength, Some(3)));
+        assert_eq!(
+            failure.to_string(),
+            "Limit exceeded: line longer than 20 bytes (line 3)"
+        );
+        assert_eq!(
+            limit_at(&fail(":A:\n:B:\n:C:\n:D:\n")),
+            (Limit::States, Some(3))
+        );
+        assert_eq!(
+            limit_at(&fail(&"#\n".repeat(11))),
+            (Limit::TotalLines, Some(11))
+        );
 
-        // Child might reference parent in skeleton field
-        if let Some(skeleton) = &child.metadata.skeleton {
-            assert!(skeleton.len() > 0, "Skeleton reference should not be empty");
+        let mut parser = CastagneParser::with_config(ParserConfig::new().with_limits(limits));
+        let streamed = parser
+            .create_full_character_from_reader("test.casp", long.as_bytes())
+            .unwrap_err();
+        assert_eq!(limit_at(&streamed), (Limit::LineLength, Some(3)));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(path("base.casp"), ":Idle:\n---Init:\nStop\n").unwrap();
+        for (name, skeleton) in [("mid.casp", "base.casp"), ("top.casp", "mid.casp")] {
+            let source = format!(":Character:\nSkeleton: {}\n", path(skeleton));
+            std::fs::write(path(name), source).unwrap();
         }
+        let limits = Limits {
+            max_skeleton_chain: 1,
+            ..Limits::default()
+        };
+        let mut parser = CastagneParser::with_config(ParserConfig::new().with_limits(limits));
+        assert!(parser.create_full_character(&path("mid.casp")).is_ok());
+        let failure = parser.create_full_character(&path("top.casp")).unwrap_err();
+        assert_eq!(limit_at(failure.root_cause()), (Limit::SkeletonChain, None));
+
+        let mut parser =
+            CastagneParser::with_config(ParserConfig::new().with_limits(Limits::unlimited()));
+        assert!(parser
+            .create_full_character_from_source("test.casp", nested)
+            .is_ok());
     }
 
     #[test]
-    fn test_basic_template_all_variable_type_conversions() {
-        // Verify type conversion works for all types
-        let mut parser = CastagneParser::new();
-
-        parser.variables.insert(
-            "IntVar".to_string(),
-            ParsedVariable {
-                name: "IntVar".to_string(),
-                mutability: VariableMutability::Variable,
-                var_type: VariableType::Int,
-                subtype: String::new(),
-                value: "42".to_string(),
-            },
+    fn test_cancelled_parses_stop() {
+        let source = ":Character:\nName: Test\n:Idle:\n---Init:\nStop\n";
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let config = ParserConfig::new().with_cancellation(token.clone());
+        let mut parser = CastagneParser::with_config(config).with_profiler(move |phase, _| {
+            if phase == ParsePhase::Metadata {
+                canceller.cancel();
+            }
+        });
+        let failure = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap_err();
+        assert_eq!(failure, ParseFailure::Cancelled);
+        assert_eq!(parser.get_errors(), ["Parse cancelled"]);
+        assert!(parser.states.is_empty());
+
+        // Streamed and skeleton parses stop too
+        let streamed = parser.create_full_character_from_reader("test.casp", source.as_bytes());
+        assert_eq!(streamed.unwrap_err(), ParseFailure::Cancelled);
+        let dir = tempfile::tempdir().unwrap();
+        let skeleton = dir.path().join("base.casp");
+        std::fs::write(&skeleton, ":Idle:\n---Init:\nStop\n").unwrap();
+        let child = format!(":Character:\nSkeleton: {}\n", skeleton.display());
+        let mut parser =
+            CastagneParser::with_config(ParserConfig::new().with_cancellation(token.clone()));
+        parser.metadata.skeleton = Some(skeleton.display().to_string());
+        parser.load_inherited_files();
+        assert_eq!(
+            parser.failure().map(ParseFailure::root_cause),
+            Some(&ParseFailure::Cancelled)
         );
+        let timed_out = CancellationToken::with_timeout(Duration::ZERO);
+        let mut parser =
+            CastagneParser::with_config(ParserConfig::new().with_cancellation(timed_out));
+        assert!(parser
+            .create_full_character_from_source("test.casp", &child)
+            .is_err());
+    }
 
-        parser.variables.insert(
-            "BoolVar".to_string(),
-            ParsedVariable {
-                name: "BoolVar".to_string(),
-                mutability: VariableMutability::Variable,
-                var_type: VariableType::Bool,
-                subtype: String::new(),
-                value: "true".to_string(),
-            },
-        );
+    #[test]
+    fn test_pragmas_silence_diagnostics() {
+        let source = ":Idle:
+---Init:
+Move(1 # castagne-ignore: unbalanced-delimiter
+Move(2
+LFlag:
+---Action:
+# castagne-ignore: missing-endif
+LOther:
+Stop
+";
+        for streamed in [false, true] {
+            let mut parser = CastagneParser::new();
+            match streamed {
+                true => parser.create_full_character_from_reader("test.casp", source.as_bytes()),
+                false => parser.create_full_character_from_source("test.casp", source),
+            }
+            .unwrap();
 
-        parser.variables.insert(
-            "StrVar".to_string(),
-            ParsedVariable {
-                name: "StrVar".to_string(),
-                mutability: VariableMutability::Variable,
-                var_type: VariableType::Str,
-                subtype: String::new(),
-                value: "Hello".to_string(),
-            },
-        );
+            let codes: Vec<(&str, usize)> = parser
+                .get_diagnostics()
+                .iter()
+                .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+                .collect();
+            assert_eq!(codes, vec![(UNBALANCED_DELIMITER, 4), (MISSING_ENDIF, 5)]);
+            assert_eq!(parser.get_errors(), ["Unclosed '(' (line 4, column 5)"]);
+            let suppressed = &parser.metrics().suppressed;
+            assert_eq!(suppressed[UNBALANCED_DELIMITER], 1);
+            assert_eq!(suppressed[MISSING_ENDIF], 1);
+        }
+    }
 
-        parser.variables.insert(
-            "Vec2Var".to_string(),
-            ParsedVariable {
-                name: "Vec2Var".to_string(),
-                mutability: VariableMutability::Variable,
-                var_type: VariableType::Vec2,
-                subtype: String::new(),
-                value: "10, 20".to_string(),
-            },
-        );
+    #[test]
+    fn test_unbalanced_delimiters_are_reported() {
+        let source = ":Idle:
+---Init:
+    Set(x, 1
+Log(\"open)
+Move(1))
+Hitbox(Add(1, 2], 3)
+Log(\"ok (\\\" )\")
+Move(2)
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
 
-        // Verify types are correct (matching GDScript parser behavior)
-        // Note: We can't test actual to_variant() calls since they require Godot runtime
-        assert_eq!(
-            parser.variables.get("IntVar").unwrap().var_type,
-            VariableType::Int
-        );
+        let actions = &character.states["Idle"].actions["Init"];
+        let instructions: Vec<&str> = actions.iter().map(|a| a.instruction.as_str()).collect();
+        assert_eq!(instructions, vec!["Log", "Move", "Hitbox", "Log", "Move"]);
+
+        let found: Vec<(usize, usize, &str)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = d.span.as_ref().unwrap();
+                assert_eq!(d.code, UNBALANCED_DELIMITER);
+                assert_eq!(span.file.as_deref(), Some("test.casp"));
+                (span.line, span.column, d.message.as_str())
+            })
+            .collect();
         assert_eq!(
-            parser.variables.get("BoolVar").unwrap().var_type,
-            VariableType::Bool
+            found,
+            vec![
+                (3, 7, "Unclosed '('"),
+                (4, 4, "Unclosed string"),
+                (5, 7, "Unexpected ')'"),
+                (6, 10, "'(' closed by ']' at column 16"),
+            ]
         );
+        assert_eq!(parser.get_errors()[0], "Unclosed '(' (line 3, column 8)");
+    }
+
+    #[test]
+    fn test_broken_invariants_are_internal_errors() {
+        assert_eq!(block_header_name(" :Idle(Helper): "), Ok("Idle(Helper)"));
         assert_eq!(
-            parser.variables.get("StrVar").unwrap().var_type,
-            VariableType::Str
+            block_header_name(":"),
+            Err(ParseError::NotABlockHeader(":".to_string()))
         );
         assert_eq!(
-            parser.variables.get("Vec2Var").unwrap().var_type,
-            VariableType::Vec2
+            slice("é", 0, 1),
+            Err(ParseError::Slice {
+                start: 0,
+                end: 1,
+                len: 2
+            })
         );
+        assert_eq!(slice("abc", 1, 3), Ok("bc"));
+        assert_eq!(char_column("éa:", 3), 2);
+        assert_eq!(char_column("éa:", 1), 0);
 
-        // Verify values can be accessed (as parser would return them)
-        assert_eq!(parser.variables.get("IntVar").unwrap().as_int(), Some(42));
+        let source = ":Idle:\n---Init:\nMove(1)\n:\n:Walk:\n---Init:\nMove(2)\n";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        assert!(character.states.contains_key("Walk"));
+        let internal = |d: &&Diagnostic| d.code == INTERNAL_ERROR;
+        assert!(!parser.get_diagnostics().iter().any(|d| internal(&d)));
+
+        parser.internal_error(ParseError::EmptyBlock, 4);
+        let diagnostic = parser.get_diagnostics().iter().find(internal).unwrap();
+        assert_eq!(diagnostic.span.as_ref().unwrap().line, 4);
         assert_eq!(
-            parser.variables.get("BoolVar").unwrap().as_bool(),
-            Some(true)
+            parser.get_errors().last().unwrap(),
+            "Internal parser error: empty block, please report it (line 4, column 1)"
         );
-        assert_eq!(parser.variables.get("StrVar").unwrap().value, "Hello");
     }
 
     #[test]
-    fn test_basic_template_comment_stripping() {
-        // Verify inline comment handling
-        let parser = CastagneParser::new();
+    fn test_bom_crlf_and_invalid_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+
+        let mut windows = b"\xEF\xBB\xBF:Character:\r\nName: Windows\r\n".to_vec();
+        windows.extend_from_slice(b":Idle:\r---Init:\r\nMove(1)\r\n");
+        std::fs::write(path("windows.casp"), windows).unwrap();
+        let character = CastagneParser::new()
+            .create_full_character(&path("windows.casp"))
+            .unwrap();
+        assert_eq!(character.metadata.name, "Windows");
+        assert_eq!(character.states["Idle"].actions["Init"][0].line_number, 5);
 
-        // Test cases that should match GDScript behavior
-        let test_cases = vec![
-            // (input, expected_output)
-            ("Set(x, 5) # comment", "Set(x, 5) "),
-            (
-                "var Health(Int): 100 # player health",
-                "var Health(Int): 100 ",
-            ),
-            (
-                r#"Log("Text # not comment") # real comment"#,
-                r#"Log("Text # not comment") "#,
-            ),
-            ("# just comment", ""),
-            ("NoComment", "NoComment"),
-        ];
+        std::fs::write(path("latin1.casp"), b":Character:\nName: Ren\xE9\n").unwrap();
+        let mut parser = CastagneParser::new();
+        assert!(parser.create_full_character(&path("latin1.casp")).is_err());
+        assert!(parser.get_errors()[0].ends_with("is not valid UTF-8 (line 2, column 10)"));
+        assert_eq!(parser.get_diagnostics()[0].code, INVALID_ENCODING);
 
-        for (input, expected) in test_cases {
-            let result = parser.strip_inline_comment(input);
-            assert_eq!(
-                result, expected,
-                "Comment stripping for '{}' should match GDScript behavior",
-                input
-            );
-        }
+        let mut parser = CastagneParser::with_config(ParserConfig::new().with_lossy_decoding(true));
+        let character = parser.create_full_character(&path("latin1.casp")).unwrap();
+        assert_eq!(character.metadata.name, "Ren\u{fffd}");
+        assert_eq!(parser.get_diagnostics()[0].severity, Severity::Warning);
+
+        std::fs::write(path("utf16.casp"), b"\xFF\xFE:\x00C\x00").unwrap();
+        let mut parser = CastagneParser::new();
+        assert!(parser.create_full_character(&path("utf16.casp")).is_err());
+        assert!(parser.get_errors()[0].ends_with("is encoded as UTF-16, save it as UTF-8"));
     }
 
     #[test]
-    fn test_basic_template_argument_parsing() {
-        // Verify argument parsing works correctly
-        let parser = CastagneParser::new();
+    fn test_names_are_interned_across_characters() {
+        let interner = Interner::new();
+        let source = ":Idle:\n---Init:\nMove(1)\n:Walk:\n---Init:\nMove(2)\n";
+        let first = CastagneParser::new()
+            .with_interner(interner.clone())
+            .create_full_character_from_source("a.casp", source)
+            .unwrap();
+        let second = CastagneParser::new()
+            .with_interner(interner.clone())
+            .create_full_character_from_source("b.casp", source)
+            .unwrap();
 
-        // Test cases that should match GDScript behavior
-        let test_cases = vec![
-            // (input, expected_count, description)
-            ("a, b, c", 3, "simple args"),
-            ("a", 1, "single arg"),
-            ("", 0, "empty args"),
-            (r#""string, with, commas", other"#, 2, "string with commas"),
-            ("Nested(a, b), c", 2, "nested function call"),
-            ("a,b,c", 3, "no spaces"),
-            ("a  ,  b  ,  c", 3, "extra spaces"),
+        let move_a = &first.states["Idle"].actions["Init"][0].instruction;
+        let move_b = &second.states["Walk"].actions["Init"][0].instruction;
+        assert!(std::ptr::eq(move_a.as_ptr(), move_b.as_ptr()));
+        // Idle, Walk, Init and Move
+        assert_eq!(interner.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_metrics_and_profiler() {
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let mut parser = CastagneParser::new()
+            .with_profiler(move |phase, _| recorder.lock().unwrap().push(phase));
+
+        let source = ":Character:\nName: Test\n\n# States\n:Idle:\n---Init:\nStop\n";
+        let _ = parser.create_full_character_from_source("test.casp", source);
+
+        let expected = vec![
+            ParsePhase::Io,
+            ParsePhase::Preprocess,
+            ParsePhase::Metadata,
+            ParsePhase::Inheritance,
+            ParsePhase::Specblocks,
+            ParsePhase::Variables,
+            ParsePhase::Templates,
+            ParsePhase::States,
         ];
+        assert_eq!(*seen.lock().unwrap(), expected);
 
-        for (input, expected_count, desc) in test_cases {
-            let result = parser.parse_arguments(input);
-            assert_eq!(
-                result.len(),
-                expected_count,
-                "Argument parsing for '{}' ({}) should match GDScript behavior",
-                input,
-                desc
-            );
-        }
+        let metrics = parser.metrics();
+        let phases: Vec<_> = metrics.phases.iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(phases, expected);
+        assert_eq!(
+            (metrics.lines, metrics.blank_lines, metrics.comment_lines),
+            (7, 1, 1)
+        );
+        assert_eq!(metrics.code_lines(), 5);
+        assert_eq!(
+            metrics.total(),
+            metrics.phases.iter().map(|(_, duration)| *duration).sum()
+        );
     }
 
     #[test]
-    fn test_basic_template_state_header_parsing() {
-        // Verify state header parsing works correctly
-        let parser = CastagneParser::new();
+    fn test_streaming_parse_matches_full_parse() {
+        let source = "\u{feff}# Generated\r\n:Character:\r\nName: Streamed\r\n\
+            :Variables:\nvar Speed(Int): 2 * Scale\ndef Scale: 3\n\
+            :Template Hit(Damage):\nAttack(Damage)\n\
+            /* :NotABlock:\n*/\n\
+            :PhysicsSystem:\nGravity: -1\n\
+            :Desc:\nSay(\"\"\"\n:StillText:\n\"\"\")\n\
+            ?Debug :DebugState:\n---Init:\nLog(1)\n\
+            :Idle:\n---Init:\nUseTemplate(Hit, 10)\nMove(1, \\\n 2)\n";
+        let full = CastagneParser::new()
+            .create_full_character_from_source("gen.casp", source)
+            .unwrap();
+        let mut parser = CastagneParser::new();
+        let streamed = parser
+            .create_full_character_from_reader("gen.casp", source.as_bytes())
+            .unwrap();
 
-        // Test various state header formats (without colons - parse_state_header expects preprocessed input)
-        let test_cases = vec![
-            // (input, (expected_name, expected_type, expected_parent))
-            ("Idle", ("Idle", StateType::Normal, None)),
-            ("Walk(Idle)", ("Walk", StateType::Normal, Some("Idle"))),
-            ("Attack(Helper)", ("Attack", StateType::Helper, None)),
-            (
-                "Special(Special, Base)",
-                ("Special", StateType::Special, Some("Base")),
-            ),
-            (
-                "Projectile(Helper, Base)",
-                ("Projectile", StateType::Helper, Some("Base")),
-            ),
-        ];
+        assert_eq!(
+            streamed.to_json_value().unwrap(),
+            full.to_json_value().unwrap()
+        );
+        assert_eq!(streamed.variables["Speed"].value, "6");
+        assert!(!streamed.states.contains_key("NotABlock"));
+        assert!(!streamed.states.contains_key("StillText"));
+        assert!(!streamed.states.contains_key("DebugState"));
+        assert_eq!(streamed.states["Idle"].actions["Init"][1].line_number, 23);
+        assert!(parser.current_lines.is_empty());
+        assert_eq!(parser.metrics().lines, 24);
 
-        for (input, expected_data) in test_cases {
-            let (exp_name, exp_type, exp_parent) = expected_data;
-            let (name, state_type, parent) = parser.parse_state_header(input);
-            assert_eq!(name, exp_name, "State name should match for '{}'", input);
-            assert_eq!(
-                state_type, exp_type,
-                "State type should match for '{}'",
-                input
-            );
-            assert_eq!(
-                parent,
-                exp_parent.map(|s| s.to_string()),
-                "Parent should match for '{}'",
-                input
-            );
-        }
+        let mut parser = CastagneParser::new();
+        let invalid = b":Character:\nName: A\n:Idle:\nSay(\"Ren\xE9\")\n";
+        assert!(parser
+            .create_full_character_from_reader("bad.casp", &invalid[..])
+            .is_err());
+        assert!(parser.get_errors()[0].ends_with("is not valid UTF-8 (line 4, column 9)"));
     }
 }
@@ -0,0 +1,256 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Phases - The phases of a state, in declaration order
+//!
+//! A state body is split by `---Name:` markers. `Phases` keeps them in
+//! the order their first marker appears, each with its index in that
+//! order, so tools listing or running a state see it as written.
+//!
+//! A marker naming a phase already declared in the state doesn't start a
+//! new one: the actions after it are appended to that phase, which keeps
+//! its index. `Phase::markers` lists the lines of every marker merged this
+//! way:
+//!
+//! ```text
+//! ---Init:        Init, index 0
+//! Set(A, 1)
+//! ---Action:      Action, index 1
+//! Move(5)
+//! ---Init:        Init again: Set(B, 2) runs after Set(A, 1)
+//! Set(B, 2)
+//! ```
+//!
+//! Lookups by name work as they did on the map this replaces. In JSON,
+//! phases are an object of actions by phase name, keys in declaration
+//! order; markers aren't kept.
+
+use crate::intern::Symbol;
+use crate::parser::ParsedAction;
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::Index;
+
+/// A phase of a state and its actions
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub name: Symbol,
+    /// Position among the phases of the state, from 0
+    pub index: usize,
+    pub actions: Vec<ParsedAction>,
+    /// 1-indexed lines of the markers declaring it, in order
+    pub markers: Vec<usize>,
+}
+
+impl Phase {
+    /// Whether several markers were merged into it
+    pub fn is_merged(&self) -> bool {
+        self.markers.len() > 1
+    }
+}
+
+/// Phases of a state, in declaration order
+#[derive(Debug, Clone, Default)]
+pub struct Phases {
+    phases: Vec<Phase>,
+}
+
+impl Phases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The phase a marker at `line` declares: a new one after the others,
+    /// or the one of the same name, continued
+    pub fn declare(&mut self, name: Symbol, line: usize) -> &mut Phase {
+        let phase = self.get_or_insert(name);
+        phase.markers.push(line);
+        phase
+    }
+
+    /// Append `actions` to the phase `name`, declaring it if needed
+    pub fn insert(&mut self, name: Symbol, actions: Vec<ParsedAction>) {
+        self.get_or_insert(name).actions.extend(actions);
+    }
+
+    fn get_or_insert(&mut self, name: Symbol) -> &mut Phase {
+        let index = match self.position(&name) {
+            Some(index) => index,
+            None => {
+                self.phases.push(Phase {
+                    name,
+                    index: self.phases.len(),
+                    actions: Vec::new(),
+                    markers: Vec::new(),
+                });
+                self.phases.len() - 1
+            }
+        };
+        &mut self.phases[index]
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.phases.iter().position(|phase| phase.name == name)
+    }
+
+    pub fn phase(&self, name: &str) -> Option<&Phase> {
+        self.phases.iter().find(|phase| phase.name == name)
+    }
+
+    /// All phases, in declaration order
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<ParsedAction>> {
+        self.phase(name).map(|phase| &phase.actions)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Vec<ParsedAction>> {
+        let index = self.position(name)?;
+        Some(&mut self.phases[index].actions)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.phases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phases.is_empty()
+    }
+
+    /// Names and actions of the phases, in declaration order
+    pub fn iter(&self) -> impl Iterator<Item = (&Symbol, &Vec<ParsedAction>)> {
+        self.phases
+            .iter()
+            .map(|phase| (&phase.name, &phase.actions))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Symbol, &mut Vec<ParsedAction>)> {
+        self.phases
+            .iter_mut()
+            .map(|phase| (&phase.name, &mut phase.actions))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Symbol> {
+        self.phases.iter().map(|phase| &phase.name)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Vec<ParsedAction>> {
+        self.phases.iter().map(|phase| &phase.actions)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Vec<ParsedAction>> {
+        self.phases.iter_mut().map(|phase| &mut phase.actions)
+    }
+}
+
+impl Index<&str> for Phases {
+    type Output = Vec<ParsedAction>;
+
+    fn index(&self, name: &str) -> &Vec<ParsedAction> {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no phase named {}", name))
+    }
+}
+
+impl<'a> IntoIterator for &'a Phases {
+    type Item = (&'a Symbol, &'a Vec<ParsedAction>);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl FromIterator<(Symbol, Vec<ParsedAction>)> for Phases {
+    fn from_iter<I: IntoIterator<Item = (Symbol, Vec<ParsedAction>)>>(iter: I) -> Self {
+        let mut phases = Phases::new();
+        for (name, actions) in iter {
+            phases.insert(name, actions);
+        }
+        phases
+    }
+}
+
+impl Serialize for Phases {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (name, actions) in self {
+            map.serialize_entry(name, actions)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Phases {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PhasesVisitor;
+
+        impl<'de> Visitor<'de> for PhasesVisitor {
+            type Value = Phases;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of actions by phase")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Phases, A::Error> {
+                let mut phases = Phases::new();
+                while let Some((name, actions)) = map.next_entry::<Symbol, Vec<ParsedAction>>()? {
+                    phases.insert(name, actions);
+                }
+                Ok(phases)
+            }
+        }
+
+        deserializer.deserialize_map(PhasesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_phases_keep_declaration_order() {
+        let source = "\
+:Idle:
+---Reaction:
+Transition(Walk)
+---Init:
+Set(A, 1)
+---Action:
+Move(5)
+---Init:
+Set(B, 2)
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("idle.casp", source)
+            .unwrap();
+        let actions = &character.states["Idle"].actions;
+        let names: Vec<&str> = actions.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["Reaction", "Init", "Action"]);
+
+        // The second Init marker continues the first phase
+        let init = actions.phase("Init").unwrap();
+        assert_eq!(init.index, 1);
+        assert_eq!(init.markers, [4, 8]);
+        assert!(init.is_merged() && !actions.phase("Action").unwrap().is_merged());
+        let args: Vec<&str> = init.actions.iter().map(|a| &a.args[1]).collect();
+        assert_eq!(args, ["1", "2"]);
+
+        let json = serde_json::to_string(&character.states["Idle"]).unwrap();
+        assert!(json.find("Reaction") < json.find("Init"));
+        let state: crate::parser::ParsedState = serde_json::from_str(&json).unwrap();
+        let names: Vec<&str> = state.actions.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["Reaction", "Init", "Action"]);
+        assert_eq!(state.actions["Init"].len(), 2);
+    }
+}
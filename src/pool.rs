@@ -0,0 +1,267 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pool - Reusable parsers for parsing many files
+//!
+//! A parser keeps the capacity of its maps and line buffers across parses
+//! once `reset`, so parsing hundreds of files with a few parsers allocates
+//! far less than creating one parser per file. A `ParserPool` hands out
+//! such parsers to any number of threads, all sharing one config, one
+//! interner and one skeleton cache. `parse_files_with_progress` reports
+//! each file as it starts, for loading screens, and how long each took.
+
+use crate::intern::Interner;
+use crate::parser::{CastagneParser, ParsedCharacter, ParserConfig};
+use crate::skeleton_cache::SkeletonCache;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Files parsed by `ParserPool::parse_files_with_progress`
+#[derive(Debug, Default)]
+pub struct BatchParse {
+    /// Each file, in the order of the paths, with its errors if it didn't parse
+    pub results: Vec<Result<ParsedCharacter, Vec<String>>>,
+    /// Time each file took to parse, in the order of the paths
+    pub timings: Vec<Duration>,
+}
+
+/// Parsers waiting to be reused
+pub struct ParserPool {
+    config: ParserConfig,
+    interner: Interner,
+    skeleton_cache: SkeletonCache,
+    idle: Mutex<Vec<CastagneParser>>,
+}
+
+impl ParserPool {
+    pub fn new(config: ParserConfig) -> Self {
+        Self {
+            config,
+            interner: Interner::new(),
+            skeleton_cache: SkeletonCache::new(),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Interner shared by every parser of the pool
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// Skeletons and includes parsed by the parsers of the pool
+    pub fn skeleton_cache(&self) -> &SkeletonCache {
+        &self.skeleton_cache
+    }
+
+    /// A parser, reused if one is idle; it returns to the pool when dropped
+    pub fn acquire(&self) -> PooledParser<'_> {
+        let parser = self.lock().pop().unwrap_or_else(|| {
+            CastagneParser::with_config(self.config.clone())
+                .with_interner(self.interner.clone())
+                .with_skeleton_cache(self.skeleton_cache.clone())
+        });
+        PooledParser {
+            pool: self,
+            parser: Some(parser),
+        }
+    }
+
+    /// Number of parsers waiting to be reused
+    pub fn idle_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Parse files on up to `threads` threads, results in the order of `paths`
+    ///
+    /// A file that fails to parse gives the errors of its parser.
+    pub fn parse_files<P: AsRef<str> + Sync>(
+        &self,
+        paths: &[P],
+        threads: usize,
+    ) -> Vec<Result<ParsedCharacter, Vec<String>>> {
+        self.parse_files_with_progress(paths, threads, |_, _, _| {})
+            .results
+    }
+
+    /// Parse files like `parse_files`, calling `progress(path, index,
+    /// total)` from the parsing thread as each file starts, `index` being
+    /// its position in `paths`
+    pub fn parse_files_with_progress<P: AsRef<str> + Sync>(
+        &self,
+        paths: &[P],
+        threads: usize,
+        progress: impl Fn(&str, usize, usize) + Sync,
+    ) -> BatchParse {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(paths.len()));
+        thread::scope(|scope| {
+            for _ in 0..threads.clamp(1, paths.len().max(1)) {
+                scope.spawn(|| {
+                    let mut parser = self.acquire();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            break;
+                        };
+                        progress(path.as_ref(), index, paths.len());
+                        let start = Instant::now();
+                        let result = parser
+                            .create_full_character(path.as_ref())
+                            .map_err(|_| parser.get_errors().to_vec());
+                        results.lock().unwrap_or_else(|e| e.into_inner()).push((
+                            index,
+                            result,
+                            start.elapsed(),
+                        ));
+                    }
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+        results.sort_by_key(|(index, _, _)| *index);
+        let (results, timings) = results
+            .into_iter()
+            .map(|(_, result, time)| (result, time))
+            .unzip();
+        BatchParse { results, timings }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<CastagneParser>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for ParserPool {
+    fn default() -> Self {
+        Self::new(ParserConfig::default())
+    }
+}
+
+/// A parser borrowed from a `ParserPool`
+pub struct PooledParser<'a> {
+    pool: &'a ParserPool,
+    parser: Option<CastagneParser>,
+}
+
+impl Deref for PooledParser<'_> {
+    type Target = CastagneParser;
+
+    fn deref(&self) -> &CastagneParser {
+        self.parser.as_ref().expect("parser is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledParser<'_> {
+    fn deref_mut(&mut self) -> &mut CastagneParser {
+        self.parser.as_mut().expect("parser is only taken on drop")
+    }
+}
+
+impl Drop for PooledParser<'_> {
+    fn drop(&mut self) {
+        if let Some(mut parser) = self.parser.take() {
+            parser.reset();
+            self.pool.lock().push(parser);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsers_are_reused_with_their_capacity() {
+        let pool = ParserPool::default();
+        {
+            let mut parser = pool.acquire();
+            let character = parser
+                .create_full_character_from_source(
+                    "a.casp",
+                    ":Character:\nName: A\n:Idle:\n---Init:\nStop\n",
+                )
+                .unwrap();
+            assert_eq!(character.metadata.name, "A");
+        }
+        assert_eq!(pool.idle_count(), 1);
+
+        let mut parser = pool.acquire();
+        assert_eq!(pool.idle_count(), 0);
+        assert!(parser.states.is_empty());
+        assert!(parser.states.capacity() > 0);
+        let character = parser
+            .create_full_character_from_source("b.casp", ":Character:\nAuthor: B\n")
+            .unwrap();
+        assert_eq!(character.metadata.name, "");
+        assert!(!character.states.contains_key("Idle"));
+    }
+
+    #[test]
+    fn test_parse_files_on_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        for i in 0..6 {
+            let path = dir.path().join(format!("c{}.casp", i));
+            let source = format!(":Character:\nName: C{}\n:Idle:\n---Init:\nMove({})\n", i, i);
+            std::fs::write(&path, source).unwrap();
+            paths.push(path.to_str().unwrap().to_string());
+        }
+        paths.push(
+            dir.path()
+                .join("missing.casp")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+
+        let pool = ParserPool::default();
+        let results = pool.parse_files(&paths, 3);
+
+        assert_eq!(results.len(), 7);
+        for (i, result) in results.iter().take(6).enumerate() {
+            let character = result.as_ref().unwrap();
+            assert_eq!(character.metadata.name, format!("C{}", i));
+        }
+        assert!(results[6].as_ref().unwrap_err()[0].contains("does not exist"));
+        assert!(pool.idle_count() <= 3);
+        assert!(pool.interner().len() >= 3);
+
+        let started = Mutex::new(Vec::new());
+        let batch = pool.parse_files_with_progress(&paths, 2, |path, index, total| {
+            assert_eq!(path, paths[index]);
+            started.lock().unwrap().push((index, total));
+        });
+        let mut started = started.into_inner().unwrap();
+        started.sort();
+        assert_eq!(started, (0..7).map(|i| (i, 7)).collect::<Vec<_>>());
+        assert_eq!(batch.results.len(), 7);
+        assert_eq!(batch.timings.len(), 7);
+    }
+
+    #[test]
+    fn test_pooled_parsers_share_skeletons() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp");
+        std::fs::write(&base, ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n").unwrap();
+        let mut paths = Vec::new();
+        for i in 0..3 {
+            let path = dir.path().join(format!("c{}.casp", i));
+            let source = format!(":Character:\nName: C{}\nSkeleton: {}\n", i, base.display());
+            std::fs::write(&path, source).unwrap();
+            paths.push(path.to_str().unwrap().to_string());
+        }
+
+        let pool = ParserPool::default();
+        let results = pool.parse_files(&paths, 1);
+        assert!(results
+            .iter()
+            .all(|result| result.as_ref().unwrap().states.contains_key("Idle")));
+        let cache = pool.skeleton_cache();
+        assert_eq!((cache.misses(), cache.hits()), (1, 2));
+    }
+}
@@ -0,0 +1,229 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pooling - How many subentities a character may need at once
+//!
+//! A spawn in the `Init` phase runs once per run of its state, one in a
+//! per-frame phase runs on every frame its frame branches (`F5:`, `F3-6:`)
+//! allow. `pooling_hints` counts the spawns of a single run of every state,
+//! calls included, and keeps the largest count of each subentity or helper
+//! state, so the engine can preallocate pools of that size instead of
+//! instantiating them on demand.
+//!
+//! Counts are upper bounds: other branches are assumed taken. A spawn that
+//! repeats for as long as the state lasts, outside frame branches or in
+//! `F5+:` and `F2%4:` ones, has no bound.
+
+use crate::frame_data::{is_branch, FrameRange};
+use crate::parser::ParsedCharacter;
+use crate::subentities::spawned_name;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Phases running once per state run; the others run every frame
+const ONCE_PHASES: &[&str] = &["Init"];
+
+/// How many times something happens in one run of a state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpawnCount {
+    Bounded(usize),
+    /// Every frame the state lasts
+    Unbounded,
+}
+
+impl SpawnCount {
+    fn add(self, other: SpawnCount) -> SpawnCount {
+        match (self, other) {
+            (SpawnCount::Bounded(a), SpawnCount::Bounded(b)) => SpawnCount::Bounded(a + b),
+            _ => SpawnCount::Unbounded,
+        }
+    }
+}
+
+impl fmt::Display for SpawnCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnCount::Bounded(count) => write!(f, "{}", count),
+            SpawnCount::Unbounded => f.write_str("unbounded"),
+        }
+    }
+}
+
+/// Pool to preallocate for a subentity or helper state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolingHint {
+    pub entity: String,
+    pub size: SpawnCount,
+    /// State spawning the most of them
+    pub state: String,
+}
+
+/// Spawns of one run of a state, by subentity or helper state
+pub fn state_spawns(character: &ParsedCharacter, state: &str) -> BTreeMap<String, SpawnCount> {
+    let mut counts = BTreeMap::new();
+    let Some(parsed) = character.states.get(state) else {
+        return counts;
+    };
+    for phase in parsed.actions.keys() {
+        let runs = match ONCE_PHASES.contains(&&**phase) {
+            true => SpawnCount::Bounded(1),
+            false => SpawnCount::Unbounded,
+        };
+        let mut calls = vec![state.to_string()];
+        phase_spawns(character, state, phase, runs, &mut calls, &mut counts);
+    }
+    counts
+}
+
+/// Largest pool each subentity or helper state needs, by name
+pub fn pooling_hints(character: &ParsedCharacter) -> Vec<PoolingHint> {
+    let mut states: Vec<&str> = character.states.keys().map(|name| &**name).collect();
+    states.sort_unstable();
+
+    let mut hints: BTreeMap<String, PoolingHint> = BTreeMap::new();
+    for state in states {
+        for (entity, size) in state_spawns(character, state) {
+            let hint = hints.entry(entity.clone()).or_insert_with(|| PoolingHint {
+                entity,
+                size,
+                state: state.to_string(),
+            });
+            if size > hint.size {
+                hint.size = size;
+                hint.state = state.to_string();
+            }
+        }
+    }
+    hints.into_values().collect()
+}
+
+/// Count the spawns of a phase of `state`, run `runs` times at most,
+/// following calls into the same phase of other states
+fn phase_spawns(
+    character: &ParsedCharacter,
+    state: &str,
+    phase: &str,
+    runs: SpawnCount,
+    calls: &mut Vec<String>,
+    counts: &mut BTreeMap<String, SpawnCount>,
+) {
+    let Some(actions) = character
+        .states
+        .get(state)
+        .and_then(|state| state.actions.get(phase))
+    else {
+        return;
+    };
+
+    // Runs of the actions in each open branch, the phase itself first
+    let mut branches = vec![runs];
+    for action in actions {
+        let instruction = &*action.instruction;
+        let current = *branches.last().unwrap_or(&runs);
+        if instruction.eq_ignore_ascii_case("endif") {
+            if branches.len() > 1 {
+                branches.pop();
+            }
+        } else if instruction.eq_ignore_ascii_case("else") {
+            // The other frames of a frame branch
+            if let [.., outer, branch] = branches.as_mut_slice() {
+                *branch = *outer;
+            }
+        } else if is_branch(instruction) {
+            let frames = match FrameRange::of_branch(character, instruction) {
+                Some(range) => range
+                    .and_then(|range| range.frame_count())
+                    .map_or(SpawnCount::Unbounded, SpawnCount::Bounded),
+                None => current,
+            };
+            branches.push(current.min(frames));
+        } else if let Some(spawned) = spawned_name(action) {
+            if character.is_spawnable(spawned) {
+                let count = counts
+                    .entry(spawned.to_string())
+                    .or_insert(SpawnCount::Bounded(0));
+                *count = count.add(current);
+            }
+        } else if let Some(called) = character.called_state(state, action) {
+            if !calls.contains(&called) {
+                calls.push(called.clone());
+                phase_spawns(character, &called, phase, current, calls, counts);
+                calls.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_pooling_hints() {
+        let source = ":Character:
+Name: Ryu
+:Variables:
+def BarrageEnd: 8
+:Fireball---Subentity:
+:Spark(Helper):
+---Init:
+Stop
+:Hadoken:
+---Init:
+CreateProjectile(Fireball)
+---Action:
+F10:
+CreateProjectile(Fireball)
+endif
+:Barrage:
+---Action:
+F3-BarrageEnd:
+CreateProjectile(Fireball)
+F4:
+Call(Sparks)
+endif
+endif
+:Sparks(Helper):
+---Action:
+CreateSubentity(Spark)
+F2:
+CreateSubentity(Spark)
+else
+Stop
+endif
+:Rain:
+---Action:
+LGrounded:
+F5+:
+CreateProjectile(Fireball)
+endif
+endif
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+
+        let hadoken = state_spawns(&character, "Hadoken");
+        assert_eq!(hadoken["Fireball"], SpawnCount::Bounded(2));
+        let barrage = state_spawns(&character, "Barrage");
+        assert_eq!(barrage["Fireball"], SpawnCount::Bounded(6));
+        // Both spawns of the helper only run on frame 4 when called there
+        assert_eq!(barrage["Spark"], SpawnCount::Bounded(2));
+        assert_eq!(
+            state_spawns(&character, "Sparks")["Spark"],
+            SpawnCount::Unbounded
+        );
+
+        let hints: Vec<String> = pooling_hints(&character)
+            .iter()
+            .map(|hint| format!("{} {} {}", hint.entity, hint.size, hint.state))
+            .collect();
+        assert_eq!(
+            hints,
+            vec!["Fireball unbounded Rain", "Spark unbounded Sparks"]
+        );
+    }
+}
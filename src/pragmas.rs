@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pragmas - Silencing diagnostics from the source
+//!
+//! Some warnings are intentional: an empty phase kept as a placeholder, a
+//! branch closed by an included template. A `# castagne-ignore: code,
+//! other-code` comment silences the diagnostics with those codes, or all of
+//! them without codes. After code it covers its own line; on a line of its
+//! own, the rest of the block it is in, or the whole file before the first
+//! block. Failures stopping the parse can't be silenced.
+
+use crate::borrowed::strip_comment;
+use crate::diagnostics::Diagnostic;
+use crate::parser::is_block_header;
+
+/// Comment silencing diagnostics
+pub const IGNORE_PRAGMA: &str = "castagne-ignore";
+
+/// Lines silenced for some codes
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Suppression {
+    first_line: usize,
+    /// `usize::MAX` until the block it covers ends
+    last_line: usize,
+    /// Codes silenced, all of them when empty
+    codes: Vec<String>,
+}
+
+/// The `castagne-ignore` pragmas of a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Suppressions {
+    rules: Vec<Suppression>,
+    /// Rules covering the rest of the block being read
+    open: Vec<usize>,
+    /// Whether a block was reached: pragmas before the first one cover
+    /// the whole file
+    in_block: bool,
+}
+
+impl Suppressions {
+    /// The pragmas of a whole file
+    pub fn scan<S: AsRef<str>>(lines: &[S]) -> Self {
+        let mut suppressions = Self::default();
+        for (index, line) in lines.iter().enumerate() {
+            suppressions.add_line(index + 1, line.as_ref());
+        }
+        suppressions
+    }
+
+    /// Read the next line of a file, `line` being its 1-indexed number
+    pub fn add_line(&mut self, line: usize, text: &str) {
+        if is_block_header(text) {
+            for rule in self.open.drain(..) {
+                self.rules[rule].last_line = line - 1;
+            }
+            self.in_block = true;
+            return;
+        }
+        let code = strip_comment(text);
+        let Some(codes) = text.get(code.len()..).and_then(pragma_codes) else {
+            return;
+        };
+        let own_line = code.trim().is_empty();
+        if own_line && self.in_block {
+            self.open.push(self.rules.len());
+        }
+        self.rules.push(Suppression {
+            first_line: line,
+            last_line: if own_line { usize::MAX } else { line },
+            codes,
+        });
+    }
+
+    /// Whether a diagnostic with `code` on `line` is silenced
+    pub fn suppresses(&self, code: &str, line: usize) -> bool {
+        self.rules.iter().any(|rule| {
+            (rule.first_line..=rule.last_line).contains(&line)
+                && (rule.codes.is_empty() || rule.codes.iter().any(|c| c == code))
+        })
+    }
+
+    /// Whether a diagnostic of `file` is silenced; diagnostics without a
+    /// line can't be
+    pub fn silences(&self, diagnostic: &Diagnostic, file: &str) -> bool {
+        diagnostic.span.as_ref().is_some_and(|span| {
+            span.file.as_deref().is_none_or(|f| f == file)
+                && self.suppresses(&diagnostic.code, span.line)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Codes of a `castagne-ignore` comment, `None` if it isn't one
+fn pragma_codes(comment: &str) -> Option<Vec<String>> {
+    let text = comment.strip_prefix('#')?.trim();
+    let rest = text.strip_prefix(IGNORE_PRAGMA)?.trim_start();
+    if rest.is_empty() {
+        return Some(Vec::new());
+    }
+    let codes = rest.strip_prefix(':')?.split(',');
+    Some(
+        codes
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{Severity, Span};
+
+    #[test]
+    fn test_suppressions() {
+        let source = "# castagne-ignore: legacy-syntax
+:Idle:
+---Init:
+Move(1 # castagne-ignore: unbalanced-delimiter, missing-endif
+Move(2
+:Walk:
+# castagne-ignore
+---Init:
+Anything()
+:Jump:
+# castagne-ignored: empty-phase
+Move(3) # not castagne-ignore
+Say(\"# castagne-ignore\")
+";
+        let lines: Vec<&str> = source.lines().collect();
+        let suppressions = Suppressions::scan(&lines);
+
+        // Before the first block: the whole file
+        assert!(suppressions.suppresses("legacy-syntax", 12));
+        // After code: that line only, for the codes listed
+        assert!(suppressions.suppresses("unbalanced-delimiter", 4));
+        assert!(suppressions.suppresses("missing-endif", 4));
+        assert!(!suppressions.suppresses("empty-phase", 4));
+        assert!(!suppressions.suppresses("unbalanced-delimiter", 5));
+        // On its own line: the rest of its block, every code
+        assert!(suppressions.suppresses("empty-phase", 9));
+        assert!(!suppressions.suppresses("empty-phase", 6));
+        assert!(!suppressions.suppresses("empty-phase", 10));
+        // Not pragmas
+        assert!(!suppressions.suppresses("empty-phase", 11));
+        assert!(!suppressions.suppresses("empty-phase", 12));
+        assert!(!suppressions.suppresses("empty-phase", 13));
+
+        let diagnostic = |file: Option<&str>| {
+            Diagnostic::new("unbalanced-delimiter", Severity::Error, "").with_span(Span {
+                file: file.map(str::to_string),
+                ..Span::line(4)
+            })
+        };
+        assert!(suppressions.silences(&diagnostic(Some("ryu.casp")), "ryu.casp"));
+        assert!(suppressions.silences(&diagnostic(None), "ryu.casp"));
+        assert!(!suppressions.silences(&diagnostic(Some("base.casp")), "ryu.casp"));
+        assert!(Suppressions::scan(&["Move(1) # castagne"]).is_empty());
+    }
+}
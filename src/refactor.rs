@@ -0,0 +1,216 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Refactor - Renaming states and variables across skeleton chains
+//!
+//! Renaming a state of a skeleton has to rename it in every character
+//! built on it too, or their overrides and transitions silently point at
+//! nothing. `rename_state` and `rename_variable` rewrite the definition,
+//! overrides and usages found by `references::find` in the roster and its
+//! dependencies, and return the edited texts for the caller to save.
+//!
+//! A new state name follows the parser's rules for state headers, so
+//! `5A` and `j.A` are fine; a variable needs an identifier.
+
+use crate::diagnostics::FixIt;
+use crate::fixes::apply_edits;
+use crate::parser::{is_state_name, ParserConfig};
+use crate::references::{find_in_sources, read_chain, Symbol, SymbolKind};
+
+/// A file rewritten by a rename
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenamedFile {
+    pub path: String,
+    pub source: String,
+    /// Number of names replaced in the file
+    pub replacements: usize,
+}
+
+/// Rename a state in the roster files and the files they depend on
+pub fn rename_state<P: AsRef<str>>(
+    roster: &[P],
+    old_name: &str,
+    new_name: &str,
+) -> Result<Vec<RenamedFile>, String> {
+    rename(roster, &Symbol::state(old_name), new_name)
+}
+
+/// Rename a variable or define in the roster files and the files they
+/// depend on
+pub fn rename_variable<P: AsRef<str>>(
+    roster: &[P],
+    old_name: &str,
+    new_name: &str,
+) -> Result<Vec<RenamedFile>, String> {
+    rename(roster, &Symbol::variable(old_name), new_name)
+}
+
+fn rename<P: AsRef<str>>(
+    roster: &[P],
+    symbol: &Symbol,
+    new_name: &str,
+) -> Result<Vec<RenamedFile>, String> {
+    let sources = read_chain(roster, &ParserConfig::default(), |_| None)?;
+    rename_in_sources(&sources, symbol, new_name)
+}
+
+/// Rename a symbol in `(path, source)` pairs ordered parents first,
+/// returning the files that changed
+pub fn rename_in_sources(
+    sources: &[(String, String)],
+    symbol: &Symbol,
+    new_name: &str,
+) -> Result<Vec<RenamedFile>, String> {
+    let kind = match symbol.kind {
+        SymbolKind::State => "State",
+        SymbolKind::Variable => "Variable",
+    };
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+    let valid = match symbol.kind {
+        SymbolKind::State => is_state_name(new_name),
+        SymbolKind::Variable => {
+            !new_name.is_empty()
+                && new_name.chars().all(is_name_char)
+                && !new_name.starts_with(|c: char| c.is_ascii_digit())
+        }
+    };
+    if !valid {
+        return Err(format!(
+            "Invalid {} name: {:?}",
+            kind.to_lowercase(),
+            new_name
+        ));
+    }
+
+    let references = find_in_sources(symbol, sources);
+    if references.definition.is_none() {
+        return Err(format!("{} {} is not defined", kind, symbol.name));
+    }
+    let conflict = Symbol {
+        kind: symbol.kind,
+        name: new_name.to_string(),
+    };
+    if let Some(existing) = find_in_sources(&conflict, sources).definition {
+        return Err(format!(
+            "{} {} already exists ({}, line {})",
+            kind,
+            new_name,
+            existing.file.as_deref().unwrap_or_default(),
+            existing.line
+        ));
+    }
+
+    let mut renamed = Vec::new();
+    for (path, source) in sources {
+        let edits: Vec<FixIt> = references
+            .spans()
+            .filter(|span| span.file.as_deref() == Some(path.as_str()))
+            .map(|span| FixIt {
+                span: span.clone(),
+                replacement: new_name.to_string(),
+            })
+            .collect();
+        if edits.is_empty() {
+            continue;
+        }
+        renamed.push(RenamedFile {
+            path: path.clone(),
+            source: apply_edits(source, &edits),
+            replacements: edits.len(),
+        });
+    }
+    Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_rename_across_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Variables:\nvar Meter(Int): 0\n:Walk:\n---Action:\nAdd(Meter, 1)\nTransition(Walk)\n",
+        )
+        .unwrap();
+        let child = |name: &str| {
+            format!(
+                ":Character:\nName: {}\nSkeleton: {}\n:Walk(Walk):\n---Init:\nIfGt(Meter, 2):\n  Transition(Walk)\nendif\n",
+                name,
+                path("base.casp")
+            )
+        };
+        fs::write(path("ryu.casp"), child("Ryu")).unwrap();
+        fs::write(path("ken.casp"), child("Ken")).unwrap();
+        let roster = [path("ryu.casp"), path("ken.casp")];
+
+        let renamed = rename_state(&roster, "Walk", "Move").unwrap();
+        let files: Vec<(&str, usize)> = renamed
+            .iter()
+            .map(|file| (file.path.rsplit('/').next().unwrap(), file.replacements))
+            .collect();
+        assert_eq!(
+            files,
+            vec![("base.casp", 2), ("ryu.casp", 3), ("ken.casp", 3)]
+        );
+        assert!(renamed[1]
+            .source
+            .ends_with(":Move(Move):\n---Init:\nIfGt(Meter, 2):\n  Transition(Move)\nendif\n"));
+
+        let renamed = rename_variable(&roster, "Meter", "Gauge").unwrap();
+        assert_eq!(renamed.len(), 3);
+        assert!(renamed[0].source.contains("var Gauge(Int): 0\n"));
+        assert!(renamed[2].source.contains("IfGt(Gauge, 2):"));
+    }
+
+    #[test]
+    fn test_rename_in_defaults_and_defines() {
+        let sources = vec![(
+            "meter.casp".to_string(),
+            ":Variables:\ndef MaxMeter(Int): 100\nvar M(Int): MaxMeter\ndef Twice: MaxMeter * 2\n:Idle:\n---Init:\nSet(M, MaxMeter)\n".to_string(),
+        )];
+
+        let renamed = rename_in_sources(&sources, &Symbol::variable("MaxMeter"), "Cap").unwrap();
+
+        assert_eq!(renamed[0].replacements, 4);
+        assert_eq!(
+            renamed[0].source,
+            ":Variables:\ndef Cap(Int): 100\nvar M(Int): Cap\ndef Twice: Cap * 2\n:Idle:\n---Init:\nSet(M, Cap)\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_conflicts() {
+        let sources = vec![(
+            "ryu.casp".to_string(),
+            ":Variables:\nvar Meter(Int): 0\ndef Stock(Int): 3\n:Idle:\n---Init:\nStop\n:Walk:\n---Init:\nStop\n".to_string(),
+        )];
+        assert_eq!(
+            rename_in_sources(&sources, &Symbol::state("Idle"), "Walk"),
+            Err("State Walk already exists (ryu.casp, line 7)".to_string())
+        );
+        assert_eq!(
+            rename_in_sources(&sources, &Symbol::variable("Meter"), "Stock"),
+            Err("Variable Stock already exists (ryu.casp, line 3)".to_string())
+        );
+        assert_eq!(
+            rename_in_sources(&sources, &Symbol::state("Run"), "Dash"),
+            Err("State Run is not defined".to_string())
+        );
+        assert!(rename_in_sources(&sources, &Symbol::state("Idle"), "Bad Name").is_err());
+        assert!(rename_in_sources(&sources, &Symbol::state("Idle"), "Idle(Helper)").is_err());
+        assert!(rename_in_sources(&sources, &Symbol::state("Idle"), "Character").is_err());
+        assert!(rename_in_sources(&sources, &Symbol::variable("Meter"), "5A").is_err());
+        // Numpad notation names states, not variables
+        for name in ["5A", "j.A"] {
+            let renamed = rename_in_sources(&sources, &Symbol::state("Idle"), name).unwrap();
+            assert!(renamed[0].source.contains(&format!("\n:{}:\n", name)));
+        }
+        // A state and a variable may share a name
+        assert!(rename_in_sources(&sources, &Symbol::state("Idle"), "Meter").is_ok());
+    }
+}
@@ -0,0 +1,328 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! References - Definitions and usages of states and variables
+//!
+//! A state or variable is often defined in a skeleton and used in every
+//! character built on it. `find` reads the files of a roster and their
+//! skeleton chains, parents first, and returns where the symbol is defined,
+//! overridden and used, for go-to-definition and "find usages" in editors.
+//! Defines are variables declared with `def` and are found the same way.
+//!
+//! Usages are names in action arguments, state header parents, variable
+//! defaults and `def` expressions, as whole words outside string literals.
+
+use crate::diagnostics::Span;
+use crate::parser::ParserConfig;
+use crate::roster::DependencyGraph;
+use crate::syntax_tree::{NodeId, NodeKind, SyntaxTree};
+use godot::prelude::*;
+use std::fs;
+
+/// What a name refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    State,
+    /// A variable or a define
+    Variable,
+}
+
+/// A state or variable to look up
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+}
+
+impl Symbol {
+    pub fn state(name: &str) -> Self {
+        Self {
+            kind: SymbolKind::State,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn variable(name: &str) -> Self {
+        Self {
+            kind: SymbolKind::Variable,
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Where a symbol appears, every span having its file set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct References {
+    /// Definition in the file highest up the skeleton chain
+    pub definition: Option<Span>,
+    /// Definitions in files inheriting the first one
+    pub overrides: Vec<Span>,
+    pub usages: Vec<Span>,
+}
+
+impl References {
+    /// Definition, overrides then usages
+    pub fn spans(&self) -> impl Iterator<Item = &Span> {
+        self.definition
+            .iter()
+            .chain(&self.overrides)
+            .chain(&self.usages)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.definition.is_none() && self.usages.is_empty()
+    }
+}
+
+/// Find a symbol in the files of a roster and the files they depend on
+pub fn find<P: AsRef<str>>(symbol: &Symbol, roster: &[P]) -> Result<References, String> {
+    let sources = read_chain(roster, &ParserConfig::default(), |_| None)?;
+    Ok(find_in_sources(symbol, &sources))
+}
+
+/// Find a symbol in `(path, source)` pairs ordered parents first
+pub fn find_in_sources<P: AsRef<str>, S: AsRef<str>>(
+    symbol: &Symbol,
+    sources: &[(P, S)],
+) -> References {
+    let mut references = References::default();
+    for (path, source) in sources {
+        let (path, source) = (path.as_ref(), source.as_ref());
+        let tree = SyntaxTree::parse(source);
+        for (id, node) in tree.nodes().iter().enumerate() {
+            if is_definition(&tree, id, symbol.kind) {
+                if tree.text(id) == symbol.name {
+                    let span = span(&tree, source, path, node.start_byte, node.end_byte);
+                    match references.definition {
+                        None => references.definition = Some(span),
+                        Some(_) => references.overrides.push(span),
+                    }
+                }
+            } else if is_usage_site(&tree, id, symbol.kind) {
+                let text = tree.text(id);
+                for offset in word_occurrences(text, &symbol.name) {
+                    let start = node.start_byte + offset;
+                    let end = start + symbol.name.len();
+                    references
+                        .usages
+                        .push(span(&tree, source, path, start, end));
+                }
+            }
+        }
+    }
+    references
+}
+
+/// Sources of the roster files and their dependencies, parents first,
+/// taking a file's text from `open` when it has one
+pub(crate) fn read_chain<P: AsRef<str>>(
+    roster: &[P],
+    config: &ParserConfig,
+    open: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>, String> {
+    let graph = DependencyGraph::build(roster, config);
+    graph
+        .parse_order()
+        .order
+        .into_iter()
+        .map(|path| {
+            let source = match open(&path) {
+                Some(source) => source,
+                None => {
+                    fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?
+                }
+            };
+            Ok((path, source))
+        })
+        .collect()
+}
+
+/// Whether a node is the name of a state or variable definition
+fn is_definition(tree: &SyntaxTree, id: NodeId, kind: SymbolKind) -> bool {
+    let node = tree.node(id);
+    if node.kind != NodeKind::Identifier {
+        return false;
+    }
+    let parent = node.parent.map(|parent| tree.node(parent));
+    match kind {
+        SymbolKind::Variable => parent.is_some_and(|p| p.kind == NodeKind::VariableDeclaration),
+        SymbolKind::State => parent
+            .filter(|p| p.kind == NodeKind::Header)
+            .and_then(|p| p.parent)
+            .is_some_and(|block| tree.node(block).kind == NodeKind::StateBlock),
+    }
+}
+
+/// Whether a node is an argument or value the symbol can be used in
+fn is_usage_site(tree: &SyntaxTree, id: NodeId, kind: SymbolKind) -> bool {
+    let node = tree.node(id);
+    if node.kind == NodeKind::Value {
+        // Default of a variable, or expression of a define
+        let parent = node.parent.map(|parent| tree.node(parent).kind);
+        return kind == SymbolKind::Variable && parent == Some(NodeKind::VariableDeclaration);
+    }
+    if node.kind != NodeKind::Argument {
+        return false;
+    }
+    let owner = node
+        .parent
+        .and_then(|arguments| tree.node(arguments).parent);
+    match owner.map(|owner| tree.node(owner).kind) {
+        Some(NodeKind::Action) => true,
+        // Parent of a state, `:Name(Parent):` or `:Name(Type, Parent):`
+        Some(NodeKind::Header) => kind == SymbolKind::State,
+        _ => false,
+    }
+}
+
+/// Byte offsets of `name` in `text` as a whole word outside strings
+fn word_occurrences<'a>(text: &'a str, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(name).filter_map(move |(start, _)| {
+        let end = start + name.len();
+        let quotes = text[..start].chars().filter(|&c| c == '"').count();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        let whole = !before.is_some_and(is_word) && !after.is_some_and(is_word);
+        (whole && quotes % 2 == 0).then_some(start)
+    })
+}
+
+/// Span of a byte range within one line of `source`
+fn span(tree: &SyntaxTree, source: &str, path: &str, start: usize, end: usize) -> Span {
+    let point = tree.point(start);
+    let line_start = start - point.column;
+    Span {
+        file: Some(path.to_string()),
+        line: point.row + 1,
+        column: source[line_start..start].chars().count(),
+        length: source[start..end].chars().count(),
+    }
+}
+
+/// Godot-facing lookups for the editor's "find usages"
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneReferences {
+    base: Base<RefCounted>,
+}
+
+#[godot_api]
+impl CastagneReferences {
+    /// Spans of a state (or else variable) of a character and its
+    /// skeleton chain, as dictionaries with `role`, `file`, `line`,
+    /// `column` and `length`; `role` is definition, override or usage
+    #[func]
+    pub fn find(&self, character_path: GString, name: GString, is_state: bool) -> VarArray {
+        let name = name.to_string();
+        let symbol = if is_state {
+            Symbol::state(&name)
+        } else {
+            Symbol::variable(&name)
+        };
+        let references = match find(&symbol, &[character_path.to_string()]) {
+            Ok(references) => references,
+            Err(e) => {
+                godot_error!("{}", e);
+                return VarArray::new();
+            }
+        };
+        let roles = references
+            .definition
+            .iter()
+            .map(|span| ("definition", span))
+            .chain(references.overrides.iter().map(|span| ("override", span)))
+            .chain(references.usages.iter().map(|span| ("usage", span)));
+        roles
+            .map(|(role, span)| {
+                let mut entry = VarDictionary::new();
+                entry.set("role", role);
+                entry.set("file", span.file.as_deref().unwrap_or_default());
+                entry.set("line", span.line as i64);
+                entry.set("column", span.column as i64);
+                entry.set("length", span.length as i64);
+                entry.to_variant()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = ":Character:\nName: Base\n:Variables:\nvar Health(Int): 100\ndef MaxHealth(Int): 100\n:Idle:\n---Action:\nSet(Health, Add(MaxHealth, 0))\nTransition(Walk)\n:Walk:\n---Action:\nLog(\"Walk Health\")\n";
+
+    fn locations(spans: &[Span]) -> Vec<(&str, usize, usize)> {
+        spans
+            .iter()
+            .map(|span| (span.file.as_deref().unwrap(), span.line, span.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_across_a_skeleton_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp").to_str().unwrap().to_string();
+        let ryu = dir.path().join("ryu.casp").to_str().unwrap().to_string();
+        fs::write(&base, BASE).unwrap();
+        fs::write(
+            &ryu,
+            format!(
+                ":Character:\nSkeleton: {}\n:Walk(Idle):\n---Init:\n  IfLt(Health, 10):\n  Transition(Idle)\n  endif\n",
+                base
+            ),
+        )
+        .unwrap();
+
+        let health = find(&Symbol::variable("Health"), &[&ryu]).unwrap();
+        let definition = health.definition.clone().unwrap();
+        assert_eq!(
+            (definition.file.as_deref(), definition.line),
+            (Some(base.as_str()), 4)
+        );
+        assert_eq!((definition.column, definition.length), (4, 6));
+        assert_eq!(
+            locations(&health.usages),
+            vec![(base.as_str(), 8, 4), (ryu.as_str(), 5, 7)]
+        );
+
+        let walk = find(&Symbol::state("Walk"), &[&ryu]).unwrap();
+        assert_eq!(locations(&walk.overrides), vec![(ryu.as_str(), 3, 1)]);
+        assert_eq!(locations(&walk.usages), vec![(base.as_str(), 9, 11)]);
+        let idle = find(&Symbol::state("Idle"), &[&ryu]).unwrap();
+        assert_eq!(
+            locations(&idle.usages),
+            vec![(ryu.as_str(), 3, 6), (ryu.as_str(), 6, 13)]
+        );
+        assert_eq!(idle.spans().count(), 3);
+
+        let define = find_in_sources(&Symbol::variable("MaxHealth"), &[("base.casp", BASE)]);
+        assert_eq!(define.definition.unwrap().line, 5);
+        assert_eq!(define.usages.len(), 1);
+        assert!(
+            find_in_sources(&Symbol::state("Health"), &[("base.casp", BASE)])
+                .definition
+                .is_none()
+        );
+        assert!(find(&Symbol::state("Idle"), &["missing.casp"]).is_err());
+    }
+
+    #[test]
+    fn test_usages_in_defaults_and_defines() {
+        let source = ":Character:\nName: Meter\n:Variables:\ndef MaxMeter(Int): 100\nvar M(Int): MaxMeter\ndef Twice: MaxMeter * 2\nvar Label(Str): \"MaxMeter\"\n";
+        let max_meter = find_in_sources(&Symbol::variable("MaxMeter"), &[("meter.casp", source)]);
+
+        assert_eq!(max_meter.definition.unwrap().line, 4);
+        assert_eq!(
+            locations(&max_meter.usages),
+            vec![("meter.casp", 5, 12), ("meter.casp", 6, 11)]
+        );
+        assert!(
+            find_in_sources(&Symbol::state("MaxMeter"), &[("meter.casp", source)])
+                .usages
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,337 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Roster - Parsing many characters that share skeletons and includes
+//!
+//! Parsing each character on its own parses its skeleton chain again for
+//! every character. `DependencyGraph` reads the skeleton and includes of
+//! every file of a roster, and `parse_roster` parses the files parents
+//! first, handing each parsed parent to the files using it. Dependency
+//! cycles across files are reported instead of being followed, and
+//! `parse_roster_with_progress` reports each file as it starts.
+
+use crate::intern::Interner;
+use crate::parser::{CastagneParser, ParsedCharacter, ParserConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Skeleton and include relationships between character files
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Every file, the roster's first, then the dependencies found
+    files: Vec<String>,
+    /// Skeleton then includes of each file, by path as written
+    dependencies: HashMap<String, Vec<String>>,
+    /// Files whose metadata couldn't be read
+    errors: HashMap<String, Vec<String>>,
+}
+
+/// Order to parse a graph in, and the files that can't be parsed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseOrder {
+    /// Each file after its dependencies
+    pub order: Vec<String>,
+    /// Files on or depending on a dependency cycle, with the error
+    pub failed: Vec<(String, String)>,
+}
+
+impl DependencyGraph {
+    /// Read the metadata of `paths` and of the files they depend on
+    pub fn build<P: AsRef<str>>(paths: &[P], config: &ParserConfig) -> Self {
+        let mut graph = Self::default();
+        let mut pending: Vec<String> = paths
+            .iter()
+            .map(|p| config.resolve_skeleton(p.as_ref()))
+            .collect();
+        pending.reverse();
+
+        let mut parser = CastagneParser::with_config(config.clone());
+        while let Some(path) = pending.pop() {
+            if graph.dependencies.contains_key(&path) {
+                continue;
+            }
+            let dependencies = match parser.get_character_metadata(&path) {
+                Ok(metadata) => metadata
+                    .skeleton
+                    .into_iter()
+                    .chain(metadata.includes)
+                    .map(|path| config.resolve_skeleton(&path))
+                    .collect(),
+                Err(_) => {
+                    graph
+                        .errors
+                        .insert(path.clone(), parser.get_errors().to_vec());
+                    Vec::new()
+                }
+            };
+            pending.extend(dependencies.iter().rev().cloned());
+            graph.files.push(path.clone());
+            graph.dependencies.insert(path, dependencies);
+        }
+        graph
+    }
+
+    /// Every file of the graph, the roster's first
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Skeleton then includes of a file
+    pub fn dependencies(&self, path: &str) -> &[String] {
+        self.dependencies.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// Files having `path` as skeleton or include
+    pub fn dependents(&self, path: &str) -> Vec<&str> {
+        self.files
+            .iter()
+            .filter(|file| self.dependencies(file).iter().any(|dep| dep == path))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Errors reading the metadata of a file
+    pub fn errors(&self, path: &str) -> &[String] {
+        self.errors.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// Files in dependency order, leaving out the ones caught in cycles
+    pub fn parse_order(&self) -> ParseOrder {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Done,
+            Failed,
+        }
+
+        fn visit(
+            graph: &DependencyGraph,
+            path: &str,
+            marks: &mut HashMap<String, Mark>,
+            stack: &mut Vec<String>,
+            result: &mut ParseOrder,
+        ) -> bool {
+            match marks.get(path) {
+                Some(Mark::Done) => return true,
+                Some(Mark::Failed) => return false,
+                Some(Mark::Visiting) => {
+                    let start = stack.iter().position(|p| p == path).unwrap_or(0);
+                    let mut cycle = stack[start..].to_vec();
+                    cycle.push(path.to_string());
+                    let message = format!("Dependency cycle: {}", cycle.join(" -> "));
+                    for (depth, file) in stack.iter().enumerate().rev() {
+                        marks.insert(file.clone(), Mark::Failed);
+                        let error = match depth >= start {
+                            true => message.clone(),
+                            false => format!("Depends on a file in a cycle: {}", message),
+                        };
+                        result.failed.push((file.clone(), error));
+                    }
+                    return false;
+                }
+                None => {}
+            }
+
+            marks.insert(path.to_string(), Mark::Visiting);
+            stack.push(path.to_string());
+            for dependency in graph.dependencies(path) {
+                if !visit(graph, dependency, marks, stack, result) {
+                    stack.pop();
+                    if marks.get(path) != Some(&Mark::Failed) {
+                        // Depends on a file that failed in another branch
+                        marks.insert(path.to_string(), Mark::Failed);
+                        result.failed.push((
+                            path.to_string(),
+                            format!("Depends on a file in a cycle: {}", dependency),
+                        ));
+                    }
+                    return false;
+                }
+            }
+            stack.pop();
+            marks.insert(path.to_string(), Mark::Done);
+            result.order.push(path.to_string());
+            true
+        }
+
+        let mut result = ParseOrder::default();
+        let mut marks = HashMap::new();
+        for file in &self.files {
+            visit(self, file, &mut marks, &mut Vec::new(), &mut result);
+        }
+        result.failed.sort();
+        result
+    }
+}
+
+/// Result of parsing a roster
+#[derive(Debug, Default)]
+pub struct RosterParse {
+    pub graph: DependencyGraph,
+    /// Every file of the graph, by path, with its errors if it didn't parse
+    pub characters: HashMap<String, Result<Arc<ParsedCharacter>, Vec<String>>>,
+    /// Time each parsed file took, by path, its dependencies excluded
+    pub timings: HashMap<String, Duration>,
+}
+
+/// Parse characters and their dependencies, each file once
+pub fn parse_roster<P: AsRef<str>>(paths: &[P], config: &ParserConfig) -> RosterParse {
+    parse_roster_with_progress(paths, config, |_, _, _| {})
+}
+
+/// Parse a roster like `parse_roster`, calling `progress(path, index,
+/// total)` as each file of the parse order starts
+pub fn parse_roster_with_progress<P: AsRef<str>>(
+    paths: &[P],
+    config: &ParserConfig,
+    progress: impl Fn(&str, usize, usize),
+) -> RosterParse {
+    let graph = DependencyGraph::build(paths, config);
+    let order = graph.parse_order();
+    let total = order.order.len();
+
+    let interner = Interner::new();
+    let mut characters = HashMap::new();
+    let mut timings = HashMap::new();
+    for (path, error) in order.failed {
+        characters.insert(path, Err(vec![error]));
+    }
+    for (index, path) in order.order.into_iter().enumerate() {
+        progress(&path, index, total);
+        let start = Instant::now();
+        let mut parser =
+            CastagneParser::with_config(config.clone()).with_interner(interner.clone());
+        for dependency in graph.dependencies(&path) {
+            if let Some(Ok(parent)) = characters.get(dependency) {
+                parser = parser.with_parent(dependency, Arc::clone(parent));
+            }
+        }
+        let result = match parser.create_full_character(&path) {
+            Ok(character) => Ok(Arc::new(character)),
+            Err(_) => Err(parser.get_errors().to_vec()),
+        };
+        timings.insert(path.clone(), start.elapsed());
+        characters.insert(path, result);
+    }
+    RosterParse {
+        graph,
+        characters,
+        timings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancellation::CancellationToken;
+    use std::fs;
+
+    #[test]
+    fn test_roster_parses_parents_first_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        fs::write(
+            path("shared.casp"),
+            ":Character:\nName: Shared\n:Walk:\n---Init:\nMove(1)\n",
+        )
+        .unwrap();
+        for name in ["ryu", "ken"] {
+            let source = format!(
+                ":Character:\nName: {}\nSkeleton: {}\nInclude: {}\n",
+                name,
+                path("base.casp"),
+                path("shared.casp")
+            );
+            fs::write(path(&format!("{}.casp", name)), source).unwrap();
+        }
+
+        let roster = [path("ryu.casp"), path("ken.casp")];
+        let graph = DependencyGraph::build(&roster, &ParserConfig::default());
+        assert_eq!(graph.files().len(), 4);
+        assert_eq!(
+            graph.dependencies(&path("ryu.casp")),
+            [path("base.casp"), path("shared.casp")]
+        );
+        assert_eq!(
+            graph.dependents(&path("base.casp")),
+            vec![path("ryu.casp"), path("ken.casp")]
+        );
+
+        let order = graph.parse_order();
+        assert!(order.failed.is_empty());
+        let position = |name: &str| order.order.iter().position(|p| *p == path(name)).unwrap();
+        assert!(position("base.casp") < position("ryu.casp"));
+        assert!(position("shared.casp") < position("ken.casp"));
+
+        let started = std::cell::RefCell::new(Vec::new());
+        let parsed = parse_roster_with_progress(&roster, &ParserConfig::default(), |p, i, n| {
+            started.borrow_mut().push((p.to_string(), i, n));
+        });
+        let started = started.into_inner();
+        assert_eq!(started.len(), 4);
+        assert!(started
+            .iter()
+            .enumerate()
+            .all(|(i, (p, index, total))| *p == order.order[i] && *index == i && *total == 4));
+        assert_eq!(parsed.timings.len(), 4);
+        assert_eq!(parsed.characters.len(), 4);
+        let ryu = parsed.characters[&path("ryu.casp")].as_ref().unwrap();
+        assert_eq!(ryu.metadata.name, "ryu");
+        assert!(ryu.states.contains_key("Idle") && ryu.states.contains_key("Walk"));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let parsed = parse_roster(&roster, &ParserConfig::new().with_cancellation(token));
+        for result in parsed.characters.values() {
+            assert_eq!(result.as_ref().unwrap_err(), &["Parse cancelled"]);
+        }
+    }
+
+    #[test]
+    fn test_cross_file_cycles_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("a.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("b.casp")),
+        )
+        .unwrap();
+        fs::write(
+            path("b.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("a.casp")),
+        )
+        .unwrap();
+        fs::write(
+            path("c.casp"),
+            format!(":Character:\nSkeleton: {}\n", path("a.casp")),
+        )
+        .unwrap();
+        fs::write(path("d.casp"), ":Character:\nName: D\n").unwrap();
+
+        let roster = [path("c.casp"), path("d.casp")];
+        let parsed = parse_roster(&roster, &ParserConfig::default());
+
+        let cycle = format!(
+            "Dependency cycle: {} -> {} -> {}",
+            path("a.casp"),
+            path("b.casp"),
+            path("a.casp")
+        );
+        assert_eq!(
+            parsed.characters[&path("a.casp")].as_ref().unwrap_err(),
+            std::slice::from_ref(&cycle)
+        );
+        assert_eq!(
+            parsed.characters[&path("c.casp")].as_ref().unwrap_err(),
+            &[format!("Depends on a file in a cycle: {}", cycle)]
+        );
+        assert!(parsed.characters[&path("d.casp")].is_ok());
+    }
+}
@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Roster Manifest - The characters of a game (`.casproster`)
+//!
+//! A manifest names the roster, then has one block per character giving
+//! the file to load, where it shows on the select screen and the flag
+//! unlocking it:
+//!
+//! ```text
+//! Name: Main Roster
+//!
+//! :Ryu:
+//! Path: characters/ryu.casp
+//! Order: 1
+//!
+//! :Akuma:
+//! Path: characters/akuma.casp
+//! Unlock: ArcadeCleared
+//! ```
+//!
+//! Paths are relative to the manifest. Entries with an `Order:` come
+//! first, by order, then the others as listed. An entry without `Unlock:`
+//! is available from the start. `validate` checks that every file exists
+//! and parses, parsing the roster with `parse_roster`.
+
+use crate::parser::{block_header_name, ParserConfig};
+use crate::roster::parse_roster;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Extension of roster manifests
+pub const ROSTER_EXTENSION: &str = "casproster";
+
+/// A character of a roster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RosterEntry {
+    /// Name of the entry's block
+    pub id: String,
+    /// Character file, relative to the manifest
+    pub path: String,
+    /// Position on the select screen, from `Order:`
+    pub order: Option<i64>,
+    /// Flag unlocking the character, `None` if it is always available
+    pub unlock: Option<String>,
+    /// Other keys of the block
+    pub other_fields: HashMap<String, String>,
+    /// 1-indexed line of the block header
+    pub line: usize,
+}
+
+/// A parsed `.casproster` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedRoster {
+    pub name: Option<String>,
+    /// Entries in display order
+    pub entries: Vec<RosterEntry>,
+}
+
+/// An entry whose character can't be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryError {
+    pub id: String,
+    /// 1-indexed line of the entry's block header
+    pub line: usize,
+    pub errors: Vec<String>,
+}
+
+impl ParsedRoster {
+    pub fn entry(&self, id: &str) -> Option<&RosterEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// Entries available without unlocking anything
+    pub fn unlocked(&self) -> impl Iterator<Item = &RosterEntry> {
+        self.entries.iter().filter(|entry| entry.unlock.is_none())
+    }
+
+    /// Character files in display order, resolved against `base_dir`
+    pub fn paths(&self, base_dir: &Path) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| base_dir.join(&entry.path).to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Entries whose file, resolved against `base_dir`, is missing or
+    /// doesn't parse, in display order
+    pub fn validate(&self, base_dir: &Path, config: &ParserConfig) -> Vec<EntryError> {
+        let paths = self.paths(base_dir);
+        let existing: Vec<&String> = paths
+            .iter()
+            .filter(|path| Path::new(path).is_file())
+            .collect();
+        let parsed = parse_roster(&existing, config);
+
+        let mut errors = Vec::new();
+        for (entry, path) in self.entries.iter().zip(&paths) {
+            let entry_errors = match parsed.characters.get(path) {
+                Some(Ok(_)) => continue,
+                Some(Err(errors)) => errors.clone(),
+                None => vec![format!("{} does not exist", path)],
+            };
+            errors.push(EntryError {
+                id: entry.id.clone(),
+                line: entry.line,
+                errors: entry_errors,
+            });
+        }
+        errors
+    }
+}
+
+/// Parse a manifest, collecting every error
+pub fn parse_roster_manifest(text: &str) -> Result<ParsedRoster, Vec<String>> {
+    let mut roster = ParsedRoster::default();
+    let mut entries: Vec<RosterEntry> = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Ok(id) = block_header_name(line) {
+            entries.push(RosterEntry {
+                id: id.trim().to_string(),
+                path: String::new(),
+                order: None,
+                unlock: None,
+                other_fields: HashMap::new(),
+                line: line_number,
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            errors.push(format!("Expected Key: Value (line {})", line_number));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let Some(entry) = entries.last_mut() else {
+            match key {
+                "Name" => roster.name = Some(value.to_string()),
+                _ => errors.push(format!(
+                    "Expected Name or a character block (line {})",
+                    line_number
+                )),
+            }
+            continue;
+        };
+        match key {
+            "Path" => entry.path = value.to_string(),
+            "Order" => match value.parse() {
+                Ok(order) => entry.order = Some(order),
+                Err(_) => errors.push(format!(
+                    "Order expects an integer, got {:?} (line {})",
+                    value, line_number
+                )),
+            },
+            "Unlock" if !value.is_empty() => entry.unlock = Some(value.to_string()),
+            _ => {
+                entry
+                    .other_fields
+                    .insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for entry in &entries {
+        if entry.path.is_empty() {
+            errors.push(format!("{} has no Path (line {})", entry.id, entry.line));
+        }
+        if !seen.insert(entry.id.as_str()) {
+            errors.push(format!(
+                "{} is listed twice (line {})",
+                entry.id, entry.line
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Stable: entries with the same order, or none, keep their file order
+    entries.sort_by_key(|entry| (entry.order.is_none(), entry.order));
+    roster.entries = entries;
+    Ok(roster)
+}
+
+/// Read and parse a manifest file
+pub fn load_roster_manifest(path: &str) -> Result<ParsedRoster, Vec<String>> {
+    let text =
+        fs::read_to_string(path).map_err(|e| vec![format!("{} cannot be read: {}", path, e)])?;
+    parse_roster_manifest(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "\
+# Select screen
+Name: Main Roster
+
+:Akuma:
+Path: akuma.casp
+Unlock: ArcadeCleared
+
+:Ryu:
+Path: ryu.casp
+Order: 1
+
+:Ken:
+Path: ken.casp
+Order: 2
+Color: Red
+";
+
+    #[test]
+    fn test_parse_manifest() {
+        let roster = parse_roster_manifest(MANIFEST).unwrap();
+        assert_eq!(roster.name.as_deref(), Some("Main Roster"));
+        let ids: Vec<&str> = roster
+            .entries
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        assert_eq!(ids, ["Ryu", "Ken", "Akuma"]);
+        let akuma = roster.entry("Akuma").unwrap();
+        assert_eq!(akuma.unlock.as_deref(), Some("ArcadeCleared"));
+        assert_eq!(akuma.line, 4);
+        assert_eq!(roster.entry("Ken").unwrap().other_fields["Color"], "Red");
+        assert_eq!(roster.unlocked().count(), 2);
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        let errors =
+            parse_roster_manifest("Title: Roster\n:Ryu:\nOrder: first\n:Ryu:\nPath: ryu.casp\n")
+                .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "Expected Name or a character block (line 1)",
+                "Order expects an integer, got \"first\" (line 3)",
+                "Ryu has no Path (line 2)",
+                "Ryu is listed twice (line 4)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ryu.casp"), ":Character:\nName: Ryu\n").unwrap();
+        fs::write(
+            dir.path().join("ken.casp"),
+            ":Character:\nName: Ken\nSkeleton: missing.casp\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.casproster"), MANIFEST).unwrap();
+
+        let manifest = dir.path().join("main.casproster");
+        let roster = load_roster_manifest(manifest.to_str().unwrap()).unwrap();
+        let errors = roster.validate(dir.path(), &ParserConfig::default());
+        let ids: Vec<&str> = errors.iter().map(|error| error.id.as_str()).collect();
+        assert_eq!(ids, ["Ken", "Akuma"]);
+        assert!(!errors[0].errors.is_empty());
+        assert!(errors[1].errors[0].ends_with("akuma.casp does not exist"));
+        assert_eq!(errors[1].line, 4);
+    }
+}
@@ -0,0 +1,391 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Roundtrip - Writing a character back to .casp and checking nothing is lost
+//!
+//! `to_casp` writes a parsed character as a single .casp file: skeletons
+//! and includes are already merged in, so it has none, and templates and
+//! generic states are written both as blocks and expanded in the states
+//! using them. Defaults are written as declared, literals and expressions
+//! included.
+//!
+//! `verify_roundtrip` puts a file through a full cycle, the way tools
+//! exchange characters:
+//!
+//! ```text
+//! .casp -> ParsedCharacter -> JSON -> ParsedCharacter -> .casp -> ParsedCharacter
+//! ```
+//!
+//! and compares the first character with the last. Line numbers and which
+//! arguments were defaulted only tell how the source was written, so they
+//! aren't compared, nor is the inheritance of the first file.
+
+use crate::parser::{
+    CastagneParser, CharacterMetadata, JsonFormat, ParsedAction, ParsedCharacter, ParsedState,
+    ParsedVariable, StateType, VariableMutability, VariableType,
+};
+use crate::string_literal;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Keys left out of the comparison, at any depth
+const SOURCE_ONLY_KEYS: &[&str] = &["line_number", "defaulted"];
+
+/// A character as the source of a .casp file
+pub fn to_casp(character: &ParsedCharacter) -> String {
+    let mut out = String::new();
+    // Its skeleton is merged in, those of subentities are theirs
+    let metadata = CharacterMetadata {
+        skeleton: None,
+        ..character.metadata.clone()
+    };
+    write_metadata(&mut out, "Character", &metadata);
+    for (entity, metadata) in sorted(&character.subentities) {
+        write_metadata(&mut out, &format!("{}---Subentity", entity), metadata);
+    }
+
+    // One block per entity and section
+    let mut blocks: BTreeMap<(Option<&str>, Option<&str>), Vec<&ParsedVariable>> = BTreeMap::new();
+    let scopes = std::iter::once((None, &character.variables)).chain(
+        character
+            .entity_variables
+            .iter()
+            .map(|(entity, variables)| (Some(entity.as_str()), variables)),
+    );
+    for (entity, variables) in scopes {
+        for variable in variables.values() {
+            blocks
+                .entry((entity, variable.section.as_deref()))
+                .or_default()
+                .push(variable);
+        }
+    }
+    for ((entity, section), mut variables) in blocks {
+        let entity = entity.map(|entity| format!("{}---", entity));
+        let section = section.map(|section| format!("-{}", section));
+        let _ = writeln!(
+            out,
+            "\n:{}Variables{}:",
+            entity.unwrap_or_default(),
+            section.unwrap_or_default()
+        );
+        variables.sort_by(|a, b| a.name.cmp(&b.name));
+        for variable in variables {
+            write_variable(&mut out, variable);
+        }
+    }
+
+    for (name, entries) in sorted(&character.specblocks) {
+        let _ = writeln!(out, "\n:{}:", name);
+        for (key, value) in sorted(entries) {
+            let _ = writeln!(out, "{}: {}", key, value);
+        }
+    }
+
+    for (name, template) in sorted(&character.templates) {
+        let _ = writeln!(out, "\n:Template {}({}):", name, template.params.join(", "));
+        for action in &template.actions {
+            let _ = writeln!(out, "{}", action_line(action));
+        }
+    }
+
+    for generic in sorted(&character.generic_states).into_values() {
+        write_state(&mut out, &generic.state, &generic.params);
+    }
+
+    let mut states: Vec<&ParsedState> = character.states.values().collect();
+    states.sort_by(|a, b| a.name.cmp(&b.name));
+    for state in states {
+        write_state(&mut out, state, &[]);
+    }
+    out
+}
+
+/// Differences found writing the file at `path` back after a JSON
+/// export and import, or why it couldn't be read
+pub fn verify_roundtrip(path: &str) -> Result<(), Vec<String>> {
+    let mut parser = CastagneParser::new();
+    let original = parser
+        .create_full_character(path)
+        .map_err(|failure| vec![failure.to_string()])?;
+    let json = original
+        .to_json(JsonFormat::Internal)
+        .map_err(|e| vec![format!("Couldn't export {}: {}", path, e)])?;
+    let imported: ParsedCharacter = serde_json::from_str(&json)
+        .map_err(|e| vec![format!("Couldn't import {}: {}", path, e)])?;
+
+    let written = to_casp(&imported);
+    let mut parser = CastagneParser::new();
+    let reparsed = parser
+        .create_full_character_from_source(path, &written)
+        .map_err(|failure| vec![format!("Written file doesn't parse: {}", failure)])?;
+
+    let mut differences = Vec::new();
+    compare(
+        &semantics(&original),
+        &semantics(&reparsed),
+        "",
+        &mut differences,
+    );
+    match differences.is_empty() {
+        true => Ok(()),
+        false => Err(differences),
+    }
+}
+
+fn sorted<V>(map: &std::collections::HashMap<String, V>) -> BTreeMap<&String, &V> {
+    map.iter().collect()
+}
+
+/// Set fields of a metadata block, under `header`
+fn write_metadata(out: &mut String, header: &str, metadata: &CharacterMetadata) {
+    let _ = writeln!(out, ":{}:", header);
+    let fields = [
+        ("Name", Some(&metadata.name)),
+        ("Author", Some(&metadata.author)),
+        ("Description", Some(&metadata.description)),
+        ("Skeleton", metadata.skeleton.as_ref()),
+        ("EditorName", metadata.editor_name.as_ref()),
+        ("Version", metadata.version.as_ref()),
+        ("Portrait", metadata.portrait.as_ref()),
+        ("SelectIcon", metadata.select_icon.as_ref()),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            let _ = writeln!(out, "{}: {}", key, literal(value));
+        }
+    }
+    if let Some(count) = metadata.palette_count {
+        let _ = writeln!(out, "PaletteCount: {}", count);
+    }
+    for (key, value) in sorted(&metadata.other_fields) {
+        let _ = writeln!(out, "{}: {}", key, literal(value));
+    }
+}
+
+fn write_variable(out: &mut String, variable: &ParsedVariable) {
+    let default = match (&variable.expression, &variable.var_type) {
+        (Some(expression), _) => expression.clone(),
+        (None, VariableType::Str) => literal(&variable.value),
+        (None, _) => variable.value.clone(),
+    };
+    let declaration = match variable.subtype.as_str() {
+        "" => format!("{}({:?})", variable.name, variable.var_type),
+        subtype => format!("{}({:?}, {})", variable.name, variable.var_type, subtype),
+    };
+    let _ = match variable.mutability {
+        VariableMutability::Variable => writeln!(out, "var {}: {}", declaration, default),
+        VariableMutability::Define => writeln!(out, "def {}: {}", variable.name, default),
+        // Internals can't be assigned: declare the default, then mark it
+        VariableMutability::Internal if variable.value.is_empty() => {
+            writeln!(out, "internal {}", declaration)
+        }
+        VariableMutability::Internal => writeln!(
+            out,
+            "var {}: {}\ninternal {}",
+            declaration, default, variable.name
+        ),
+    };
+}
+
+/// A state block, with the parameters of a generic state
+fn write_state(out: &mut String, state: &ParsedState, params: &[String]) {
+    let name = match params.is_empty() {
+        true => state.name.to_string(),
+        false => format!("{}<{}>", state.name, params.join(", ")),
+    };
+    let header = match (&state.state_type, &state.parent) {
+        (StateType::Normal, None) => name,
+        (StateType::Normal, Some(parent)) => format!("{}({})", name, parent),
+        (state_type, None) => format!("{}({:?})", name, state_type),
+        (state_type, Some(parent)) => format!("{}({:?}, {})", name, state_type, parent),
+    };
+    let _ = writeln!(out, "\n:{}:", header);
+    for line in state.description.iter().flat_map(|text| text.lines()) {
+        let _ = writeln!(out, "## {}", line);
+    }
+    for (phase, actions) in &state.actions {
+        let _ = writeln!(out, "---{}:", phase);
+        let locals = |position: usize| {
+            state
+                .locals
+                .iter()
+                .filter(move |local| local.phase == *phase && local.position == position)
+        };
+        for (position, action) in actions.iter().enumerate() {
+            for local in locals(position) {
+                let _ = writeln!(out, "let {}: {}", local.name, local.value);
+            }
+            let _ = writeln!(out, "{}", action_line(action));
+        }
+        for local in locals(actions.len()) {
+            let _ = writeln!(out, "let {}: {}", local.name, local.value);
+        }
+    }
+}
+
+/// `Instruction(args, +flags)`, or the instruction alone without either
+fn action_line(action: &ParsedAction) -> String {
+    let flags = action.flags.iter().map(|flag| format!("+{}", flag));
+    let args: Vec<String> = action
+        .args
+        .iter()
+        .map(str::to_string)
+        .chain(flags)
+        .collect();
+    match args.is_empty() {
+        true => action.instruction.to_string(),
+        false => format!("{}({})", action.instruction, args.join(", ")),
+    }
+}
+
+/// A text value as written in a file, quoted if reading it raw would change it
+fn literal(value: &str) -> String {
+    let raw = value == value.trim()
+        && !value.contains(|c: char| matches!(c, '"' | '\\' | '#') || c.is_control());
+    match raw {
+        true => value.to_string(),
+        false => string_literal::encode(value),
+    }
+}
+
+/// JSON of a character without what only depends on how it was written
+fn semantics(character: &ParsedCharacter) -> Value {
+    let mut character = character.clone();
+    character.metadata.skeleton = None;
+    character.metadata.includes.clear();
+    character.metadata.no_inherit.clear();
+    let mut json = serde_json::to_value(&character).unwrap_or_default();
+    strip_source_keys(&mut json);
+    json
+}
+
+fn strip_source_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|key, _| !SOURCE_ONLY_KEYS.contains(&key.as_str()));
+            map.values_mut().for_each(strip_source_keys);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_source_keys),
+        _ => {}
+    }
+}
+
+/// Push a message for every value of `expected` that `found` lacks or changes
+fn compare(expected: &Value, found: &Value, path: &str, differences: &mut Vec<String>) {
+    let at = |key: &str| match path {
+        "" => key.to_string(),
+        path => format!("{}.{}", path, key),
+    };
+    match (expected, found) {
+        (Value::Object(expected), Value::Object(found)) => {
+            let keys: std::collections::BTreeSet<&String> =
+                expected.keys().chain(found.keys()).collect();
+            for key in keys {
+                match (expected.get(key), found.get(key)) {
+                    (Some(expected), Some(found)) => {
+                        compare(expected, found, &at(key), differences)
+                    }
+                    (Some(_), None) => differences.push(format!("{} was lost", at(key))),
+                    (None, Some(_)) => differences.push(format!("{} was added", at(key))),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(found)) if expected.len() == found.len() => {
+            for (index, (expected, found)) in expected.iter().zip(found).enumerate() {
+                compare(
+                    expected,
+                    found,
+                    &format!("{}[{}]", path, index),
+                    differences,
+                );
+            }
+        }
+        _ if expected != found => {
+            differences.push(format!("{}: {} became {}", path, expected, found));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_keeps_the_character() {
+        let source = "\
+:Character:
+Name: \"Tester #1\"
+EditorName: Tester
+:Fireball---Subentity:
+Name: Ball
+Skeleton: none
+:Variables:
+var Health(Int): 0x10
+var Speed(Int): Health * 2
+var Greeting(Str): \"Hi\\n\"
+var Flags(Int, 64): 5
+def MaxSpeed: 12
+:Variables-Internals:
+internal Timer(Int)
+:Fireball---Variables:
+var Damage(Int): 8
+:Template Hit(D):
+Damage(D)
+:Idle:
+## Standing still
+---Init:
+let Half: Health / 2
+F2-5:
+Move(1)
+else
+Hitbox(0, 10, +NoCancel)
+endif
+UseTemplate(Hit, 100)
+:Walk(Helper, Idle):
+---Action:
+Move(2)
+:Dash<Speed>:
+---Init:
+Move(Speed)
+:DashFast:
+UseState(Dash, 9)
+";
+        let path = std::env::temp_dir().join("castagne_roundtrip_test.casp");
+        std::fs::write(&path, source).unwrap();
+        let result = verify_roundtrip(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result, Ok(()));
+
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let written = to_casp(&character);
+        assert!(written.contains("Name: \"Tester #1\"\n"));
+        assert!(written.contains("var Health(Int): 0x10\n"));
+        assert!(written.contains(":Walk(Helper, Idle):\n"));
+        assert!(written.contains(":Dash<Speed>:\n---Init:\nMove(Speed)\n"));
+        assert!(written.contains("---Init:\nlet Half: Health / 2\nF2-5:\n"));
+    }
+
+    #[test]
+    fn test_roundtrip_reports_differences() {
+        let expected = serde_json::json!({"a": {"b": [1, 2]}, "c": 1, "line_number": 3});
+        let found = serde_json::json!({"a": {"b": [1, 3]}, "d": 1});
+        let mut differences = Vec::new();
+        compare(&expected, &found, "", &mut differences);
+        assert_eq!(
+            differences,
+            [
+                "a.b[1]: 2 became 3",
+                "c was lost",
+                "d was added",
+                "line_number was lost"
+            ]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Scenario - Scripted regression tests for a character (`.casp-test`)
+//!
+//! A scenario file names the character under test, then has one block per
+//! scenario listing the inputs to play and what should happen:
+//!
+//! ```text
+//! Character: ryu.casp
+//! Opponent: training-dummy.casp
+//!
+//! :Jab into sweep:
+//! Frames(40)
+//! Press(1, A)
+//! Hold(8, 10, Down, B)
+//! ExpectState(3, 5A)
+//! ExpectDamage(30, 1200)
+//! ```
+//!
+//! Frames start at 1. `ExpectDamage` is the damage dealt to the opponent
+//! so far. `check_scenarios` validates the scenarios against the parsed
+//! character, and the engine plays the input table of each one and
+//! compares the outcome. `simulation::play_scenario` checks the
+//! `ExpectState` lines headless, without hit detection.
+
+use crate::parser::{block_header_name, ParsedCharacter};
+
+/// Extension of scenario files
+pub const SCENARIO_EXTENSION: &str = "casp-test";
+
+/// What a scenario expects on a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// State the character is in
+    State(String),
+    /// Damage dealt to the opponent since the first frame
+    Damage(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    pub frame: usize,
+    pub expected: Expected,
+    pub line: usize,
+}
+
+/// Inputs held from `start` to `end`, both included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputStep {
+    pub start: usize,
+    pub end: usize,
+    pub inputs: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    pub name: String,
+    /// 1-indexed line of the block header
+    pub line: usize,
+    /// Frames to play, from `Frames` or the last frame named
+    pub frames: usize,
+    pub inputs: Vec<InputStep>,
+    pub expectations: Vec<Expectation>,
+}
+
+impl Scenario {
+    /// Inputs held on each frame, the first frame first
+    pub fn input_table(&self) -> Vec<Vec<&str>> {
+        (1..=self.frames)
+            .map(|frame| {
+                let held = self
+                    .inputs
+                    .iter()
+                    .filter(|step| (step.start..=step.end).contains(&frame));
+                let mut inputs: Vec<&str> = held
+                    .flat_map(|step| step.inputs.iter().map(String::as_str))
+                    .collect();
+                inputs.sort_unstable();
+                inputs.dedup();
+                inputs
+            })
+            .collect()
+    }
+}
+
+/// A parsed `.casp-test` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScenarioFile {
+    /// Character file under test, relative to the scenario file
+    pub character: Option<String>,
+    pub opponent: Option<String>,
+    pub scenarios: Vec<Scenario>,
+}
+
+/// Parse a scenario file, collecting every error
+pub fn parse_scenarios(text: &str) -> Result<ScenarioFile, Vec<String>> {
+    let mut file = ScenarioFile::default();
+    let mut errors = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Ok(name) = block_header_name(line) {
+            file.scenarios.push(Scenario {
+                name: name.trim().to_string(),
+                line: line_number,
+                frames: 0,
+                inputs: Vec::new(),
+                expectations: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(scenario) = file.scenarios.last_mut() else {
+            match line.split_once(':') {
+                Some(("Character", path)) => file.character = Some(path.trim().to_string()),
+                Some(("Opponent", path)) => file.opponent = Some(path.trim().to_string()),
+                _ => errors.push(format!(
+                    "Expected Character, Opponent or a scenario block (line {})",
+                    line_number
+                )),
+            }
+            continue;
+        };
+
+        let (command, args) = match line.split_once('(') {
+            Some((command, rest)) if rest.ends_with(')') => {
+                let args: Vec<&str> = rest[..rest.len() - 1].split(',').map(str::trim).collect();
+                (command.trim(), args)
+            }
+            _ => {
+                errors.push(format!(
+                    "Expected a command like Press(1, A) (line {})",
+                    line_number
+                ));
+                continue;
+            }
+        };
+        let frame = |index: usize| -> Result<usize, String> {
+            let arg = args.get(index).copied().unwrap_or_default();
+            match arg.parse() {
+                Ok(frame) if frame > 0 => Ok(frame),
+                _ => Err(format!(
+                    "{} expects a frame from 1, got {:?} (line {})",
+                    command, arg, line_number
+                )),
+            }
+        };
+        let rest = |from: usize| -> Vec<String> {
+            args.iter()
+                .skip(from)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| arg.to_string())
+                .collect()
+        };
+
+        let result = match command {
+            "Frames" => frame(0).map(|count| scenario.frames = count),
+            "Press" => frame(0).map(|at| {
+                scenario.inputs.push(InputStep {
+                    start: at,
+                    end: at,
+                    inputs: rest(1),
+                    line: line_number,
+                })
+            }),
+            "Hold" => frame(0).and_then(|start| {
+                let end = frame(1)?;
+                if end < start {
+                    return Err(format!(
+                        "Hold ends on frame {} before it starts (line {})",
+                        end, line_number
+                    ));
+                }
+                scenario.inputs.push(InputStep {
+                    start,
+                    end,
+                    inputs: rest(2),
+                    line: line_number,
+                });
+                Ok(())
+            }),
+            "ExpectState" => frame(0).and_then(|at| match rest(1).as_slice() {
+                [state] => {
+                    scenario.expectations.push(Expectation {
+                        frame: at,
+                        expected: Expected::State(state.clone()),
+                        line: line_number,
+                    });
+                    Ok(())
+                }
+                _ => Err(format!(
+                    "ExpectState expects a state (line {})",
+                    line_number
+                )),
+            }),
+            "ExpectDamage" => frame(0).and_then(|at| {
+                let damage = args
+                    .get(1)
+                    .and_then(|arg| arg.parse().ok())
+                    .ok_or_else(|| {
+                        format!("ExpectDamage expects an amount (line {})", line_number)
+                    })?;
+                scenario.expectations.push(Expectation {
+                    frame: at,
+                    expected: Expected::Damage(damage),
+                    line: line_number,
+                });
+                Ok(())
+            }),
+            _ => Err(format!(
+                "Unknown scenario command {} (line {})",
+                command, line_number
+            )),
+        };
+        errors.extend(result.err());
+    }
+
+    // Frames left at 0 weren't set with `Frames`
+    for scenario in file.scenarios.iter_mut().filter(|s| s.frames == 0) {
+        let last_input = scenario.inputs.iter().map(|step| step.end);
+        let last_expected = scenario.expectations.iter().map(|e| e.frame);
+        scenario.frames = last_input.chain(last_expected).max().unwrap_or(1);
+    }
+    match errors.is_empty() {
+        true => Ok(file),
+        false => Err(errors),
+    }
+}
+
+/// Problems of scenarios that no run could pass: unknown states and
+/// expectations or inputs past the end of the scenario
+pub fn check_scenarios(file: &ScenarioFile, character: &ParsedCharacter) -> Vec<String> {
+    let mut errors = Vec::new();
+    for scenario in &file.scenarios {
+        for expectation in &scenario.expectations {
+            if expectation.frame > scenario.frames {
+                errors.push(format!(
+                    "Scenario {} expects something on frame {}, after its {} frames (line {})",
+                    scenario.name, expectation.frame, scenario.frames, expectation.line
+                ));
+            }
+            if let Expected::State(state) = &expectation.expected {
+                if !character.states.contains_key(state.as_str()) {
+                    errors.push(format!(
+                        "Scenario {} expects unknown state {} (line {})",
+                        scenario.name, state, expectation.line
+                    ));
+                }
+            }
+        }
+        for step in &scenario.inputs {
+            if step.end > scenario.frames {
+                errors.push(format!(
+                    "Scenario {} holds inputs until frame {}, after its {} frames (line {})",
+                    scenario.name, step.end, scenario.frames, step.line
+                ));
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    const SCENARIOS: &str = "Character: ryu.casp
+Opponent: dummy.casp
+
+:Jab into sweep:
+Frames(12)
+Press(1, A)
+Hold(3, 5, Down, B) # sweep
+Press(4, Down)
+ExpectState(2, 5A)
+ExpectDamage(12, 1200)
+
+:Idle:
+ExpectState(1, Idle)
+ExpectState(3, 6P)
+";
+
+    #[test]
+    fn test_parse_scenarios() {
+        let file = parse_scenarios(SCENARIOS).unwrap();
+        assert_eq!(file.character.as_deref(), Some("ryu.casp"));
+        assert_eq!(file.opponent.as_deref(), Some("dummy.casp"));
+        assert_eq!(file.scenarios.len(), 2);
+
+        let jab = &file.scenarios[0];
+        assert_eq!(
+            (jab.name.as_str(), jab.line, jab.frames),
+            ("Jab into sweep", 4, 12)
+        );
+        let table = jab.input_table();
+        assert_eq!(table.len(), 12);
+        assert_eq!(table[0], vec!["A"]);
+        assert!(table[1].is_empty());
+        assert_eq!(table[3], vec!["B", "Down"]);
+        assert_eq!(jab.expectations[1].expected, Expected::Damage(1200));
+        // Without Frames, a scenario lasts until the last frame it names
+        assert_eq!(file.scenarios[1].frames, 3);
+
+        let errors = parse_scenarios(
+            "Fighter: ryu.casp\n:A:\nPress(0, A)\nHold(5, 2, B)\nJump()\nExpectState(1)\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "Expected Character, Opponent or a scenario block (line 1)",
+                "Press expects a frame from 1, got \"0\" (line 3)",
+                "Hold ends on frame 2 before it starts (line 4)",
+                "Unknown scenario command Jump (line 5)",
+                "ExpectState expects a state (line 6)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_scenarios() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "ryu.casp",
+                ":Idle:\n---Init:\nStop\n:5A:\n---Init:\nAttackRegister(Light)\n",
+            )
+            .unwrap();
+        let mut file = parse_scenarios(SCENARIOS).unwrap();
+        file.scenarios[0].frames = 4;
+
+        assert_eq!(
+            check_scenarios(&file, &character),
+            vec![
+                "Scenario Jab into sweep expects something on frame 12, after its 4 frames (line 10)",
+                "Scenario Jab into sweep holds inputs until frame 5, after its 4 frames (line 7)",
+                "Scenario Idle expects unknown state 6P (line 14)",
+            ]
+        );
+    }
+}
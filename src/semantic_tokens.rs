@@ -0,0 +1,532 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Semantic Tokens - Classified spans for syntax highlighting
+//!
+//! Tokenizes a .casp buffer with the same block rules as the parser
+//! (`:Block:` headers, `---Phase:` markers, `Key: Value` lines, actions)
+//! so editors highlight what the parser actually sees. Lines go through
+//! the parser's `LineScanner` too: a block comment or `"""` string keeps
+//! its highlight over the lines it spans, and a line continued with `\\`
+//! goes on as an expression.
+//!
+//! Lines and columns are 0-indexed and counted in characters, which is
+//! what Godot's CodeEdit and most LSP clients expect.
+
+use crate::parser::{LineScanner, VariablesBlock};
+use godot::prelude::*;
+
+/// Classification of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword = 0,
+    StateName = 1,
+    Phase = 2,
+    Instruction = 3,
+    Variable = 4,
+    String = 5,
+    Comment = 6,
+    Number = 7,
+}
+
+/// A classified span on a single line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub kind: TokenKind,
+}
+
+/// Blocks that reserve their header name
+const KEYWORD_BLOCKS: &[&str] = &["Character", "Variables"];
+
+/// State types allowed in a state header's parentheses
+const STATE_TYPES: &[&str] = &["Helper", "BaseState", "Special", "Specblock"];
+
+/// Words that start a declaration in the Variables block
+const DECLARATION_KEYWORDS: &[&str] = &["var", "def", "internal"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    None,
+    Character,
+    Variables,
+    Other,
+}
+
+/// Tokenize a full .casp buffer
+pub fn tokenize(source: &str) -> Vec<SemanticToken> {
+    let mut tokens = Vec::new();
+    let mut block = Block::None;
+    let mut scanner = LineScanner::default();
+    let mut continued = false;
+
+    for (line_index, raw_line) in source.lines().enumerate() {
+        let scanned = scanner.scan_line(raw_line);
+        let first = tokens.len();
+        let mut chars: Vec<char> = raw_line.chars().collect();
+        let column = |byte: usize| raw_line[..byte].chars().count();
+        // Block comments and `"""` strings are left out of the code
+        let comments = scanned
+            .comments
+            .iter()
+            .map(|range| (range, TokenKind::Comment));
+        let strings = scanned
+            .strings
+            .iter()
+            .map(|range| (range, TokenKind::String));
+        for (range, kind) in comments.chain(strings) {
+            let (start, end) = (column(range.start), column(range.end));
+            chars[start..end].fill(' ');
+            tokens.push(SemanticToken {
+                line: line_index,
+                column: start,
+                length: end - start,
+                kind,
+            });
+        }
+
+        let mut line = LineTokenizer {
+            chars: &chars,
+            line: line_index,
+            tokens: &mut tokens,
+        };
+        block = line.tokenize(block, continued);
+        continued = scanned.continues;
+        tokens[first..].sort_by_key(|token| token.column);
+    }
+
+    tokens
+}
+
+/// Flatten tokens to `[line, column, length, kind]` quadruples
+pub fn to_flat_array(tokens: &[SemanticToken]) -> Vec<u32> {
+    let mut flat = Vec::with_capacity(tokens.len() * 4);
+    for token in tokens {
+        flat.push(token.line as u32);
+        flat.push(token.column as u32);
+        flat.push(token.length as u32);
+        flat.push(token.kind as u32);
+    }
+    flat
+}
+
+struct LineTokenizer<'a> {
+    chars: &'a [char],
+    line: usize,
+    tokens: &'a mut Vec<SemanticToken>,
+}
+
+impl LineTokenizer<'_> {
+    /// Tokenize the line, `continued` when it goes on from the one above
+    fn tokenize(&mut self, block: Block, continued: bool) -> Block {
+        let start = self.skip_whitespace(0, self.chars.len());
+        let comment = self.comment_start(start);
+        let end = self.trim_end(start, comment);
+
+        let block = match continued {
+            true => {
+                self.expression(start, end);
+                block
+            }
+            false => self.code(block, start, end),
+        };
+
+        if comment < self.chars.len() {
+            let comment_end = self.trim_end(comment, self.chars.len());
+            self.push(comment, comment_end, TokenKind::Comment);
+        }
+        block
+    }
+
+    fn code(&mut self, block: Block, start: usize, end: usize) -> Block {
+        if start >= end {
+            return block;
+        }
+
+        if end - start >= 2 && self.chars[start] == ':' && self.chars[end - 1] == ':' {
+            return self.header(start + 1, end - 1);
+        }
+
+        if self.starts_with(start, "---") {
+            let name_start = self.skip_whitespace(start + 3, end);
+            let name_end = self.find(name_start, end, ':').unwrap_or(end);
+            let name_end = self.trim_end(name_start, name_end);
+            self.push(name_start, name_end, TokenKind::Phase);
+            return block;
+        }
+
+        match block {
+            Block::Variables => self.declaration(start, end),
+            Block::Character => self.property(start, end),
+            Block::None | Block::Other => {
+                if self.is_property(start, end) {
+                    self.property(start, end);
+                } else {
+                    self.expression(start, end);
+                }
+            }
+        }
+        block
+    }
+
+    fn header(&mut self, start: usize, end: usize) -> Block {
+        let paren = self.find(start, end, '(');
+        let name_end = self.trim_end(start, paren.unwrap_or(end));
+        let name: String = self.chars[start..name_end].iter().collect();
+
+        let block = if KEYWORD_BLOCKS.contains(&name.as_str()) {
+            self.push(start, name_end, TokenKind::Keyword);
+            if name == "Character" {
+                Block::Character
+            } else {
+                Block::Variables
+            }
+        } else if VariablesBlock::from_header(&name).is_some() {
+            // Section or subentity variables block, like `Variables-Internals`
+            self.push(start, name_end, TokenKind::StateName);
+            Block::Variables
+        } else {
+            self.push(start, name_end, TokenKind::StateName);
+            Block::Other
+        };
+
+        if let Some(paren) = paren {
+            let close = self.rfind(paren, end, ')').unwrap_or(end);
+            self.each_word(paren + 1, close, |word| {
+                if STATE_TYPES.contains(&word) {
+                    TokenKind::Keyword
+                } else {
+                    TokenKind::StateName
+                }
+            });
+        }
+        block
+    }
+
+    fn declaration(&mut self, start: usize, end: usize) {
+        let mut pos = start;
+        let keyword_end = self.word_end(pos, end);
+        let keyword: String = self.chars[pos..keyword_end].iter().collect();
+        if DECLARATION_KEYWORDS.contains(&keyword.as_str()) {
+            self.push(pos, keyword_end, TokenKind::Keyword);
+            pos = self.skip_whitespace(keyword_end, end);
+        }
+
+        let name_end = self.word_end(pos, end);
+        self.push(pos, name_end, TokenKind::Variable);
+        pos = name_end;
+
+        let colon = self.find(pos, end, ':').unwrap_or(end);
+        if let Some(paren) = self.find(pos, colon, '(') {
+            let close = self.find(paren, colon, ')').unwrap_or(colon);
+            self.each_word(paren + 1, close, |_| TokenKind::Keyword);
+        }
+        if colon < end {
+            self.expression(colon + 1, end);
+        }
+    }
+
+    fn is_property(&self, start: usize, end: usize) -> bool {
+        match self.find(start, end, ':') {
+            Some(colon) => {
+                colon + 1 < end
+                    && self.chars[start..colon]
+                        .iter()
+                        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ' '))
+            }
+            None => false,
+        }
+    }
+
+    fn property(&mut self, start: usize, end: usize) {
+        let colon = match self.find(start, end, ':') {
+            Some(colon) => colon,
+            None => return,
+        };
+        let key_end = self.trim_end(start, colon);
+        self.push(start, key_end, TokenKind::Variable);
+
+        let value_start = self.skip_whitespace(colon + 1, end);
+        if value_start >= end {
+            return;
+        }
+        let value: String = self.chars[value_start..end].iter().collect();
+        let kind = if is_number(&value) {
+            TokenKind::Number
+        } else {
+            TokenKind::String
+        };
+        self.push(value_start, end, kind);
+    }
+
+    fn expression(&mut self, start: usize, end: usize) {
+        let mut pos = start;
+        while pos < end {
+            let ch = self.chars[pos];
+            if ch == '"' {
+                let close = self.string_end(pos, end);
+                self.push(pos, close, TokenKind::String);
+                pos = close;
+            } else if ch.is_ascii_digit()
+                || (ch == '-' && pos + 1 < end && self.chars[pos + 1].is_ascii_digit())
+            {
+                let mut number_end = pos + 1;
+                while number_end < end
+                    && (self.chars[number_end].is_alphanumeric() || self.chars[number_end] == '.')
+                {
+                    number_end += 1;
+                }
+                self.push(pos, number_end, TokenKind::Number);
+                pos = number_end;
+            } else if ch.is_alphabetic() || ch == '_' {
+                let word_end = self.word_end(pos, end);
+                let next = self.skip_whitespace(word_end, end);
+                let word: String = self.chars[pos..word_end].iter().collect();
+                let kind = if next < end && self.chars[next] == '(' {
+                    TokenKind::Instruction
+                } else if word == "true" || word == "false" {
+                    TokenKind::Keyword
+                } else if pos == start {
+                    TokenKind::Instruction
+                } else {
+                    TokenKind::Variable
+                };
+                self.push(pos, word_end, kind);
+                pos = word_end;
+            } else {
+                pos += 1;
+            }
+        }
+    }
+
+    fn each_word(&mut self, start: usize, end: usize, classify: impl Fn(&str) -> TokenKind) {
+        let mut pos = start;
+        while pos < end {
+            pos = self.skip_whitespace(pos, end);
+            let part_end = self.find(pos, end, ',').unwrap_or(end);
+            let word_end = self.trim_end(pos, part_end);
+            if pos < word_end {
+                let word: String = self.chars[pos..word_end].iter().collect();
+                let kind = classify(&word);
+                self.push(pos, word_end, kind);
+            }
+            pos = part_end + 1;
+        }
+    }
+
+    fn push(&mut self, start: usize, end: usize, kind: TokenKind) {
+        if start < end {
+            self.tokens.push(SemanticToken {
+                line: self.line,
+                column: start,
+                length: end - start,
+                kind,
+            });
+        }
+    }
+
+    /// Index of the `#` starting an inline comment, or the line length
+    fn comment_start(&self, start: usize) -> usize {
+        let mut in_string = false;
+        let mut pos = start;
+        while pos < self.chars.len() {
+            match self.chars[pos] {
+                '\\' if in_string => pos += 1,
+                '"' => in_string = !in_string,
+                '#' if !in_string => return pos,
+                _ => {}
+            }
+            pos += 1;
+        }
+        self.chars.len()
+    }
+
+    fn string_end(&self, start: usize, end: usize) -> usize {
+        let mut pos = start + 1;
+        while pos < end {
+            match self.chars[pos] {
+                '\\' => pos += 1,
+                '"' => return pos + 1,
+                _ => {}
+            }
+            pos += 1;
+        }
+        end
+    }
+
+    fn word_end(&self, start: usize, end: usize) -> usize {
+        let mut pos = start;
+        while pos < end && (self.chars[pos].is_alphanumeric() || self.chars[pos] == '_') {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn skip_whitespace(&self, start: usize, end: usize) -> usize {
+        let mut pos = start;
+        while pos < end && self.chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn trim_end(&self, start: usize, end: usize) -> usize {
+        let mut pos = end;
+        while pos > start && self.chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    fn find(&self, start: usize, end: usize, target: char) -> Option<usize> {
+        (start..end).find(|&pos| self.chars[pos] == target)
+    }
+
+    fn rfind(&self, start: usize, end: usize, target: char) -> Option<usize> {
+        (start..end).rev().find(|&pos| self.chars[pos] == target)
+    }
+
+    fn starts_with(&self, start: usize, prefix: &str) -> bool {
+        let prefix: Vec<char> = prefix.chars().collect();
+        self.chars[start..].starts_with(&prefix)
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    crate::expression::parse_number(value).is_some()
+}
+
+/// Godot-facing tokenizer for the in-engine code editor
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneSemanticTokens {
+    base: Base<RefCounted>,
+}
+
+#[godot_api]
+impl CastagneSemanticTokens {
+    /// Tokenize a buffer into `[line, column, length, kind]` quadruples
+    #[func]
+    pub fn tokenize(&self, source: GString) -> PackedInt32Array {
+        let flat = to_flat_array(&tokenize(&source.to_string()));
+        flat.into_iter().map(|value| value as i32).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(String, TokenKind)> {
+        tokenize(source)
+            .into_iter()
+            .map(|token| {
+                let line = source.lines().nth(token.line).unwrap();
+                let text: String = line.chars().skip(token.column).take(token.length).collect();
+                (text, token.kind)
+            })
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, TokenKind)]) -> Vec<(String, TokenKind)> {
+        expected
+            .iter()
+            .map(|(text, kind)| (text.to_string(), *kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_headers_and_phases() {
+        let source = ":Character:\nName: Test # the name\n:Jab(Helper, Attack):\n---Init:\n";
+
+        assert_eq!(
+            kinds(source),
+            pairs(&[
+                ("Character", TokenKind::Keyword),
+                ("Name", TokenKind::Variable),
+                ("Test", TokenKind::String),
+                ("# the name", TokenKind::Comment),
+                ("Jab", TokenKind::StateName),
+                ("Helper", TokenKind::Keyword),
+                ("Attack", TokenKind::StateName),
+                ("Init", TokenKind::Phase),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tokenize_variables_block() {
+        let source = ":Variables:\nvar Health(Int): 100\ndef NAME: \"Baston\"\n";
+
+        assert_eq!(
+            kinds(source),
+            pairs(&[
+                ("Variables", TokenKind::Keyword),
+                ("var", TokenKind::Keyword),
+                ("Health", TokenKind::Variable),
+                ("Int", TokenKind::Keyword),
+                ("100", TokenKind::Number),
+                ("def", TokenKind::Keyword),
+                ("NAME", TokenKind::Variable),
+                ("\"Baston\"", TokenKind::String),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tokenize_actions() {
+        let source = ":Idle:\n---Action:\n  Set(Health, Add(-10, MaxHP), \"a # b\")\nEndIf\n";
+
+        assert_eq!(
+            kinds(source),
+            pairs(&[
+                ("Idle", TokenKind::StateName),
+                ("Action", TokenKind::Phase),
+                ("Set", TokenKind::Instruction),
+                ("Health", TokenKind::Variable),
+                ("Add", TokenKind::Instruction),
+                ("-10", TokenKind::Number),
+                ("MaxHP", TokenKind::Variable),
+                ("\"a # b\"", TokenKind::String),
+                ("EndIf", TokenKind::Instruction),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_comments_and_strings_span_lines() {
+        let source = ":Idle:\n/* :Fake:\n---Action:\nMove(1) */ Stop\nLog(\"\"\"\n# not a comment\n\"\"\")\nSet(Health, \\\n  100)\n";
+
+        assert_eq!(
+            kinds(source),
+            pairs(&[
+                ("Idle", TokenKind::StateName),
+                ("/* :Fake:", TokenKind::Comment),
+                ("---Action:", TokenKind::Comment),
+                ("Move(1) */", TokenKind::Comment),
+                ("Stop", TokenKind::Instruction),
+                ("Log", TokenKind::Instruction),
+                ("\"\"\"", TokenKind::String),
+                ("# not a comment", TokenKind::String),
+                ("\"\"\"", TokenKind::String),
+                ("Set", TokenKind::Instruction),
+                ("Health", TokenKind::Variable),
+                ("100", TokenKind::Number),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_flat_array_and_columns() {
+        let tokens = tokenize("# top\n:Idle:\n");
+        assert_eq!(to_flat_array(&tokens), vec![0, 0, 5, 6, 1, 1, 4, 1]);
+
+        // Columns are counted in characters, not bytes
+        let tokens = tokenize(":Character:\nNäme: Ünïcode\n");
+        assert_eq!(tokens[2].column, 6);
+        assert_eq!(tokens[2].length, 7);
+    }
+}
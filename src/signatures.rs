@@ -0,0 +1,384 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Signatures - Parameter names of instructions
+//!
+//! Instructions take positional arguments, which makes long calls hard to
+//! read. Given the parameters of an instruction in `ParserConfig::signatures`,
+//! its calls can name their arguments:
+//!
+//! ```text
+//! CreateHitbox(w=40, h=20, damage=Damage)
+//! CreateHitbox(40, damage=Damage, h=20)
+//! ```
+//!
+//! Named arguments come after the positional ones, in any order. The
+//! parser puts them back in positional order, so `ParsedAction::args` is
+//! the same as for `CreateHitbox(40, 20, Damage)`, and reports a name the
+//! signature doesn't have, a parameter given twice, or one left out
+//! before a given one.
+//!
+//! An argument is named when it starts with a name followed by a single
+//! `=`: `A==B` and `A<=B` are expressions and stay positional.
+//!
+//! Parameters can have a default. A call leaving them out, at the end or
+//! between named arguments, gets the default in their place, and the
+//! action lists their positions in `ParsedAction::defaulted`, so later
+//! passes always see every argument of the signature:
+//!
+//! ```text
+//! CreateHitbox(40, 20)        CreateHitbox(40, 20, 0), damage defaulted
+//! ```
+//!
+//! Trailing modifiers like `+NoCancel`, or names the signature lists as
+//! flags like `AIRONLY`, are flags: they go to `ParsedAction::flags`, in
+//! the order written and without their `+`, instead of the arguments.
+
+use crate::action_args::ActionArgs;
+use crate::parser::ParsedAction;
+
+/// A parameter of an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    /// Value of the argument when a call leaves it out
+    pub default: Option<String>,
+}
+
+impl Parameter {
+    /// A parameter calls must give
+    pub fn required(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            default: None,
+        }
+    }
+
+    /// A parameter taking `default` when left out
+    pub fn optional(name: &str, default: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            default: Some(default.to_string()),
+        }
+    }
+}
+
+impl From<&str> for Parameter {
+    fn from(name: &str) -> Self {
+        Self::required(name)
+    }
+}
+
+/// Parameters of an instruction, in positional order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub instruction: String,
+    pub parameters: Vec<Parameter>,
+    /// Trailing arguments read as flags without a `+`, like `AIRONLY`
+    pub flags: Vec<String>,
+}
+
+impl Signature {
+    /// Position of a parameter
+    pub fn position(&self, parameter: &str) -> Option<usize> {
+        self.parameters
+            .iter()
+            .position(|known| known.name == parameter)
+    }
+
+    /// Names of the parameters, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.parameters
+            .iter()
+            .map(|parameter| parameter.name.as_str())
+    }
+}
+
+/// Signatures of the instructions whose arguments can be named
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signatures {
+    entries: Vec<Signature>,
+}
+
+impl Signatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the parameters of an instruction, replacing those it had
+    pub fn register<P: Into<Parameter>>(
+        &mut self,
+        instruction: &str,
+        parameters: impl IntoIterator<Item = P>,
+    ) -> &mut Self {
+        self.entry(instruction).parameters = parameters.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Read these trailing arguments of an instruction as flags
+    pub fn register_flags<S: Into<String>>(
+        &mut self,
+        instruction: &str,
+        flags: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        self.entry(instruction)
+            .flags
+            .extend(flags.into_iter().map(Into::into));
+        self
+    }
+
+    fn entry(&mut self, instruction: &str) -> &mut Signature {
+        let index = match self
+            .entries
+            .iter()
+            .position(|entry| entry.instruction == instruction)
+        {
+            Some(index) => index,
+            None => {
+                self.entries.push(Signature {
+                    instruction: instruction.to_string(),
+                    parameters: Vec::new(),
+                    flags: Vec::new(),
+                });
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index]
+    }
+
+    pub fn get(&self, instruction: &str) -> Option<&Signature> {
+        self.entries
+            .iter()
+            .find(|entry| entry.instruction == instruction)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Signature> {
+        self.entries.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Arguments before the trailing flags, and the flags without their `+`
+pub fn split_flags<'a>(
+    signature: Option<&Signature>,
+    arguments: &'a ActionArgs,
+) -> (Vec<&'a str>, Vec<String>) {
+    let is_flag = |argument: &str| match argument.strip_prefix('+') {
+        Some(flag) => {
+            flag.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && flag.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => {
+            signature.is_some_and(|signature| signature.flags.iter().any(|flag| flag == argument))
+        }
+    };
+    let count = arguments
+        .iter()
+        .rev()
+        .take_while(|argument| is_flag(argument))
+        .count();
+    let mut arguments: Vec<&str> = arguments.iter().collect();
+    let flags = arguments
+        .split_off(arguments.len() - count)
+        .into_iter()
+        .map(|flag| flag.strip_prefix('+').unwrap_or(flag).to_string())
+        .collect();
+    (arguments, flags)
+}
+
+impl ParsedAction {
+    /// Whether the action was given a flag, `+NoCancel` being `NoCancel`
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|given| given == flag)
+    }
+}
+
+/// Name and value of a named argument, `None` for a positional one
+pub fn split_named(argument: &str) -> Option<(&str, &str)> {
+    let (name, value) = argument.split_once('=')?;
+    let name = name.trim();
+    let is_name = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    (is_name && !value.starts_with('=')).then(|| (name, value.trim()))
+}
+
+/// Arguments of a call to `instruction` in positional order, with the
+/// defaults of those it leaves out, and the positions of the defaulted
+/// ones; or why they can't be ordered
+///
+/// Calls without named arguments to an instruction without a signature
+/// are returned as is.
+pub(crate) fn normalize_arguments(
+    instruction: &str,
+    signature: Option<&Signature>,
+    arguments: &[&str],
+) -> Result<(ActionArgs, Vec<usize>), Vec<String>> {
+    let named = arguments
+        .iter()
+        .any(|argument| split_named(argument).is_some());
+    let Some(signature) = signature else {
+        if named {
+            return Err(vec![format!(
+                "{} has no known signature to name its arguments by",
+                instruction
+            )]);
+        }
+        return Ok((arguments.iter().collect(), Vec::new()));
+    };
+
+    let mut ordered: Vec<Option<String>> = Vec::new();
+    let mut errors = Vec::new();
+    let mut named = false;
+    for argument in arguments {
+        let Some((name, value)) = split_named(argument) else {
+            if named {
+                errors.push(format!(
+                    "Positional argument {} of {} comes after named ones",
+                    argument, instruction
+                ));
+            } else {
+                ordered.push(Some(argument.to_string()));
+            }
+            continue;
+        };
+        named = true;
+        let Some(position) = signature.position(name) else {
+            let expected: Vec<&str> = signature.names().collect();
+            errors.push(format!(
+                "{} has no parameter {}, expected one of {}",
+                instruction,
+                name,
+                expected.join(", ")
+            ));
+            continue;
+        };
+        if ordered.len() <= position {
+            ordered.resize(position + 1, None);
+        }
+        match &ordered[position] {
+            Some(_) => errors.push(format!("{} of {} is given twice", name, instruction)),
+            None => ordered[position] = Some(value.to_string()),
+        }
+    }
+
+    // Left out: gaps must have a default, trailing ones take theirs
+    // until one has none
+    let mut defaulted = Vec::new();
+    for (position, parameter) in signature.parameters.iter().enumerate() {
+        let given = ordered.get(position).is_some_and(Option::is_some);
+        if given {
+            continue;
+        }
+        let gap = position < ordered.len();
+        match &parameter.default {
+            Some(default) if gap => ordered[position] = Some(default.clone()),
+            Some(default) => ordered.push(Some(default.clone())),
+            None if gap => {
+                errors.push(format!(
+                    "{} of {} is missing, later arguments are given",
+                    parameter.name, instruction
+                ));
+                continue;
+            }
+            None => break,
+        }
+        defaulted.push(position);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok((ordered.into_iter().flatten().collect(), defaulted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{CastagneParser, ParserConfig, INVALID_NAMED_ARGUMENT};
+
+    #[test]
+    fn test_named_arguments() {
+        let mut signatures = Signatures::new();
+        signatures.register("CreateHitbox", ["w", "h", "damage"]);
+        signatures.register_flags("Hitbox", ["AIRONLY"]);
+        signatures.register(
+            "Hurtbox",
+            [
+                Parameter::required("x"),
+                Parameter::optional("y", "0"),
+                Parameter::optional("size", "Size"),
+            ],
+        );
+        let config = ParserConfig::new().with_signatures(signatures);
+        let source = "\
+:Jab:
+---Init:
+CreateHitbox(w=40, h=20, damage=Damage)
+CreateHitbox(40, damage=Damage, h = 20)
+Set(A, B==C)
+CreateHitbox(width=40, damage=1)
+CreateHitbox(h=2, 40)
+Move(x=1)
+Hurtbox(5)
+Hurtbox(size=3, x=1)
+Hurtbox
+Hitbox(0, 10, +NoCancel, AIRONLY)
+Hitbox(AIRONLY, 2)
+Move(+5)
+";
+        let mut parser = CastagneParser::with_config(config);
+        let character = parser
+            .create_full_character_from_source("jab.casp", source)
+            .unwrap();
+        let actions = &character.states["Jab"].actions["Init"];
+        assert_eq!(actions[0].args, ["40", "20", "Damage"]);
+        assert_eq!(actions[1].args, ["40", "20", "Damage"]);
+        assert_eq!(actions[2].args, ["A", "B==C"]);
+
+        let errors: Vec<(&str, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.code == INVALID_NAMED_ARGUMENT)
+            .map(|d| (d.message.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "CreateHitbox has no parameter width, expected one of w, h, damage",
+                    6
+                ),
+                ("w of CreateHitbox is missing, later arguments are given", 6),
+                ("h of CreateHitbox is missing, later arguments are given", 6),
+                (
+                    "Positional argument 40 of CreateHitbox comes after named ones",
+                    7
+                ),
+                ("w of CreateHitbox is missing, later arguments are given", 7),
+                ("Move has no known signature to name its arguments by", 8),
+            ]
+        );
+        // Invalid calls are kept as written
+        assert_eq!(actions[3].args, ["width=40", "damage=1"]);
+
+        // Left out parameters take their default
+        assert_eq!(actions[6].args, ["5", "0", "Size"]);
+        assert_eq!(actions[6].defaulted, [1, 2]);
+        assert_eq!(actions[7].args, ["1", "0", "3"]);
+        assert_eq!(actions[7].defaulted, [1]);
+        assert!(actions[7].args.len() == 3 && actions[8].args.is_empty());
+        assert!(actions[0].defaulted.is_empty());
+
+        // Trailing flags are kept apart
+        assert_eq!(actions[9].args, ["0", "10"]);
+        assert_eq!(actions[9].flags, ["NoCancel", "AIRONLY"]);
+        assert!(actions[9].has_flag("NoCancel") && !actions[0].has_flag("NoCancel"));
+        assert_eq!(actions[10].args, ["AIRONLY", "2"]);
+        assert!(actions[10].flags.is_empty());
+        assert_eq!(actions[11].args, ["+5"]);
+    }
+}
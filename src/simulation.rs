@@ -0,0 +1,532 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Simulation - Headless frame stepping of two characters
+//!
+//! Gameplay regressions can be caught in CI without the Godot renderer by
+//! playing scripted inputs and looking at where the characters end up.
+//! `Simulation` steps two parsed characters frame by frame with a small,
+//! deterministic interpreter of their actions, and `snapshot` gives their
+//! state, variables and position.
+//!
+//! Each character starts in its `Init` state. On every frame its flags
+//! are cleared, the `Init` phase runs if the state was just entered, then
+//! the `Action` phase, and the transition with the highest priority, the
+//! first on ties, is taken at the end of the frame. The interpreter knows
+//! the branches (`F`, `I`, `L`, `V`, `P` and the `If` calls), `Flag`,
+//! `Unflag`, `Set`, `Add`, `Sub`, `Mul`, `Div`, `Mod`, `Max`, `Min`,
+//! `Move`, `MoveAbsolute`, `Transition`, `TransitionBuffer`, `Call`,
+//! `CallParent` and `let` temporaries. Everything else, from hitboxes to
+//! animations, is skipped, and other branches are never taken: random ones
+//! would make runs differ.
+
+use crate::expression::parse_integer;
+use crate::frame_data::{is_branch, FrameRange};
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState};
+use crate::scenario::{Expected, Scenario};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// State every character starts in
+pub const ENTRY_STATE: &str = "Init";
+
+/// Depth past which `Call` and `CallParent` are ignored, against cycles
+const MAX_CALL_DEPTH: usize = 16;
+
+/// A character as it is at the end of a frame
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EntitySnapshot {
+    pub state: String,
+    /// Frame of the current state, 1 being the first
+    pub state_frame: usize,
+    pub position: (i64, i64),
+    /// 1 facing right, -1 facing left
+    pub facing: i64,
+    /// Integer variables and defines
+    pub variables: BTreeMap<String, i64>,
+    /// Flags raised during the frame
+    pub flags: BTreeSet<String>,
+}
+
+/// Both characters at the end of a frame
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    /// Frames played, 0 before the first
+    pub frame: usize,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+/// A character being played
+#[derive(Debug, Clone)]
+struct Entity {
+    snapshot: EntitySnapshot,
+    /// Temporaries of the current frame
+    locals: HashMap<String, i64>,
+    /// Target of the transition to take at the end of the frame, with its
+    /// priority
+    transition: Option<(String, i64)>,
+}
+
+/// Two characters stepped frame by frame
+#[derive(Debug, Clone)]
+pub struct Simulation<'a> {
+    characters: [&'a ParsedCharacter; 2],
+    entities: [Entity; 2],
+    frame: usize,
+}
+
+impl<'a> Simulation<'a> {
+    /// Both characters in their `Init` state, the first facing right
+    pub fn new(characters: [&'a ParsedCharacter; 2]) -> Self {
+        let entity = |character: &ParsedCharacter, facing| Entity {
+            snapshot: EntitySnapshot {
+                state: ENTRY_STATE.to_string(),
+                facing,
+                variables: character
+                    .variables
+                    .iter()
+                    .filter_map(|(name, variable)| {
+                        Some((name.to_string(), parse_integer(variable.value.trim())?))
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            locals: HashMap::new(),
+            transition: None,
+        };
+        Self {
+            entities: [entity(characters[0], 1), entity(characters[1], -1)],
+            characters,
+            frame: 0,
+        }
+    }
+
+    /// Play one frame, each character holding its inputs
+    pub fn step(&mut self, inputs: [&[&str]; 2]) {
+        self.frame += 1;
+        for (entity, (character, held)) in self
+            .entities
+            .iter_mut()
+            .zip(self.characters.into_iter().zip(inputs))
+        {
+            entity.step(character, held);
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            frame: self.frame,
+            entities: self
+                .entities
+                .iter()
+                .map(|entity| entity.snapshot.clone())
+                .collect(),
+        }
+    }
+}
+
+impl Entity {
+    fn step(&mut self, character: &ParsedCharacter, inputs: &[&str]) {
+        self.snapshot.flags.clear();
+        self.locals.clear();
+        self.snapshot.state_frame += 1;
+        let state = self.snapshot.state.clone();
+        if self.snapshot.state_frame == 1 {
+            self.run_phase(character, &state, "Init", inputs, 0);
+        }
+        self.run_phase(character, &state, "Action", inputs, 0);
+
+        if let Some((target, _)) = self.transition.take() {
+            if character.states.contains_key(target.as_str()) {
+                self.snapshot.state = target;
+                self.snapshot.state_frame = 0;
+            }
+        }
+    }
+
+    fn run_phase(
+        &mut self,
+        character: &ParsedCharacter,
+        state: &str,
+        phase: &str,
+        inputs: &[&str],
+        depth: usize,
+    ) {
+        let Some(state) = character.states.get(state) else {
+            return;
+        };
+        let Some(actions) = state.actions.get(phase) else {
+            return;
+        };
+        let mut index = 0;
+        while index < actions.len() {
+            for local in &state.locals {
+                if local.phase == phase && local.position == index {
+                    let value = self.int(&local.value);
+                    self.locals.insert(local.name.to_string(), value);
+                }
+            }
+            let action = &actions[index];
+            let instruction = &*action.instruction;
+            if is_branch(instruction) {
+                if !self.condition(character, action, phase, inputs) {
+                    index = branch_end(actions, index, true);
+                }
+            } else if instruction.eq_ignore_ascii_case("else") {
+                index = branch_end(actions, index, false);
+            } else if !instruction.eq_ignore_ascii_case("endif") {
+                self.execute(character, state, action, phase, inputs, depth);
+            }
+            index += 1;
+        }
+    }
+
+    fn execute(
+        &mut self,
+        character: &ParsedCharacter,
+        state: &ParsedState,
+        action: &ParsedAction,
+        phase: &str,
+        inputs: &[&str],
+        depth: usize,
+    ) {
+        let args = &action.args;
+        let text = |index: usize| {
+            args.get(index)
+                .map_or("", |arg| arg.trim().trim_matches('"'))
+        };
+        let int = |entity: &Self, index: usize| entity.int(text(index));
+        let arithmetic: Option<fn(i64, i64) -> Option<i64>> = match &*action.instruction {
+            "Add" => Some(|a, b| a.checked_add(b)),
+            "Sub" => Some(|a, b| a.checked_sub(b)),
+            "Mul" => Some(|a, b| a.checked_mul(b)),
+            "Div" => Some(|a, b| a.checked_div(b)),
+            "Mod" => Some(|a, b| a.checked_rem(b)),
+            "Max" => Some(|a, b| Some(a.max(b))),
+            "Min" => Some(|a, b| Some(a.min(b))),
+            _ => None,
+        };
+        if let Some(operation) = arithmetic {
+            let destination = match args.len() {
+                3.. => text(2),
+                _ => text(0),
+            };
+            if let Some(value) = operation(int(self, 0), int(self, 1)) {
+                self.set(destination, value);
+            }
+            return;
+        }
+
+        match &*action.instruction {
+            "Flag" => {
+                self.snapshot.flags.insert(text(0).to_string());
+            }
+            "Unflag" => {
+                self.snapshot.flags.remove(text(0));
+            }
+            "Set" => {
+                let value = int(self, 1);
+                self.set(text(0), value);
+            }
+            "Move" => {
+                self.snapshot.position.0 += int(self, 0) * self.snapshot.facing;
+                self.snapshot.position.1 += int(self, 1);
+            }
+            "MoveAbsolute" => {
+                self.snapshot.position.0 += int(self, 0);
+                self.snapshot.position.1 += int(self, 1);
+            }
+            "Transition" | "TransitionBuffer" => {
+                if args.is_empty() {
+                    self.transition = None;
+                    return;
+                }
+                let target = text(0);
+                let priority = int(self, 1);
+                let allow_self = matches!(text(2), "true" | "1");
+                if target == self.snapshot.state && !allow_self {
+                    return;
+                }
+                if self
+                    .transition
+                    .as_ref()
+                    .is_none_or(|(_, current)| priority > *current)
+                {
+                    self.transition = Some((target.to_string(), priority));
+                }
+            }
+            "Call" | "CallParent" if depth < MAX_CALL_DEPTH => {
+                let called = match &*action.instruction {
+                    "Call" => Some(text(0)),
+                    _ => state.parent.as_deref(),
+                };
+                if let Some(called) = called {
+                    self.run_phase(character, called, phase, inputs, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the branch `action` opens is taken
+    fn condition(
+        &self,
+        character: &ParsedCharacter,
+        action: &ParsedAction,
+        phase: &str,
+        inputs: &[&str],
+    ) -> bool {
+        let instruction = &*action.instruction;
+        if let Some(comparison) = instruction.strip_prefix("If") {
+            let args = &action.args;
+            let value = |index: usize| self.int(args.get(index).unwrap_or_default());
+            return compare(value(0), comparison_operator(comparison), value(1));
+        }
+        let Some(condition) = instruction.strip_suffix(':') else {
+            return false;
+        };
+        let mut letters = condition.chars();
+        let letter = letters.next();
+        let rest = letters.as_str();
+        match letter {
+            Some('F') => FrameRange::of_branch_in(&character.variables, instruction)
+                .flatten()
+                .is_some_and(|range| range.contains(self.snapshot.state_frame)),
+            Some('I') => inputs.contains(&rest),
+            Some('L') => self.snapshot.flags.contains(rest),
+            Some('P') => rest.split(',').any(|name| name.trim() == phase),
+            Some('V') => self.variable_condition(rest),
+            _ => false,
+        }
+    }
+
+    /// A `V` branch condition: `Name`, true when positive, or a
+    /// comparison like `Meter>=50`
+    fn variable_condition(&self, condition: &str) -> bool {
+        let (left, operator, right) = match condition.find(['<', '>']) {
+            Some(at) => {
+                let (left, right) = condition.split_at(at);
+                match right[1..].strip_prefix('=') {
+                    Some(rest) => (left, &right[..2], rest),
+                    None => (left, &right[..1], &right[1..]),
+                }
+            }
+            None => match condition.split_once('=') {
+                Some((left, right)) => (left, "=", right),
+                None => (condition, ">", "0"),
+            },
+        };
+        compare(self.int(left), Some(operator), self.int(right))
+    }
+
+    /// Value of an integer argument: a literal, a temporary or a variable,
+    /// 0 otherwise
+    fn int(&self, text: &str) -> i64 {
+        let text = text.trim();
+        parse_integer(text)
+            .or_else(|| self.locals.get(text).copied())
+            .or_else(|| self.snapshot.variables.get(text).copied())
+            .unwrap_or(0)
+    }
+
+    fn set(&mut self, name: &str, value: i64) {
+        if let Some(local) = self.locals.get_mut(name) {
+            *local = value;
+        } else {
+            self.snapshot.variables.insert(name.to_string(), value);
+        }
+    }
+}
+
+/// Operator of an `If` call: `IfGt` is `>`
+fn comparison_operator(name: &str) -> Option<&'static str> {
+    match name {
+        "Gt" => Some(">"),
+        "Ge" => Some(">="),
+        "Lt" => Some("<"),
+        "Le" => Some("<="),
+        "Eq" => Some("="),
+        "Ne" => Some("!="),
+        _ => None,
+    }
+}
+
+fn compare(left: i64, operator: Option<&str>, right: i64) -> bool {
+    match operator {
+        Some(">") => left > right,
+        Some(">=") => left >= right,
+        Some("<") => left < right,
+        Some("<=") => left <= right,
+        Some("=") => left == right,
+        Some("!=") => left != right,
+        _ => false,
+    }
+}
+
+/// Index of the `endif` closing the branch opened at `index`, or of its
+/// `else` when `to_else` is set
+fn branch_end(actions: &[ParsedAction], index: usize, to_else: bool) -> usize {
+    let mut depth = 0;
+    for (position, action) in actions.iter().enumerate().skip(index + 1) {
+        let instruction = &*action.instruction;
+        if is_branch(instruction) {
+            depth += 1;
+        } else if instruction.eq_ignore_ascii_case("endif") {
+            if depth == 0 {
+                return position;
+            }
+            depth -= 1;
+        } else if to_else && depth == 0 && instruction.eq_ignore_ascii_case("else") {
+            return position;
+        }
+    }
+    actions.len()
+}
+
+/// Play `frames` frames from the start and return the last snapshot
+///
+/// Each character holds the inputs of its table on each frame, the first
+/// frame first, and nothing past its end.
+pub fn simulate<S: AsRef<str>>(
+    characters: [&ParsedCharacter; 2],
+    inputs: [&[Vec<S>]; 2],
+    frames: usize,
+) -> Snapshot {
+    let mut simulation = Simulation::new(characters);
+    for frame in 0..frames {
+        let held = inputs.map(|table| {
+            table.get(frame).map_or(Vec::new(), |held| {
+                held.iter().map(AsRef::as_ref).collect::<Vec<&str>>()
+            })
+        });
+        simulation.step([&held[0], &held[1]]);
+    }
+    simulation.snapshot()
+}
+
+/// Play a scenario against an opponent holding nothing, returning the
+/// `ExpectState` expectations that failed
+///
+/// `ExpectDamage` needs hit detection, which the interpreter doesn't have:
+/// those expectations are left to the engine.
+pub fn play_scenario(
+    scenario: &Scenario,
+    character: &ParsedCharacter,
+    opponent: &ParsedCharacter,
+) -> Vec<String> {
+    let table = scenario.input_table();
+    let mut simulation = Simulation::new([character, opponent]);
+    let mut failures = Vec::new();
+    for (frame, held) in (1..=scenario.frames).zip(table) {
+        simulation.step([&held, &[]]);
+        let state = &simulation.entities[0].snapshot.state;
+        for expectation in scenario.expectations.iter().filter(|e| e.frame == frame) {
+            if let Expected::State(expected) = &expectation.expected {
+                if expected != state {
+                    failures.push(format!(
+                        "{}: expected state {} on frame {}, got {}",
+                        scenario.name, expected, frame, state
+                    ));
+                }
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+    use crate::scenario::parse_scenarios;
+
+    const FIGHTER: &str = "\
+:Character:
+Name: Tester
+:Variables:
+var Meter(Int): 0
+def WalkSpeed: 4
+:Init:
+---Init:
+Transition(Idle)
+:Idle:
+---Action:
+IA:
+Transition(Jab, 10)
+endif
+IForward:
+Call(Walk)
+else
+Set(Meter, 0)
+endif
+:Walk:
+---Action:
+Move(WalkSpeed)
+Add(Meter, 1)
+VMeter>=3:
+Flag(Tired)
+endif
+:Jab:
+---Action:
+let Recovery: 3
+F1:
+Add(Meter, 10)
+endif
+IfGt(Meter, 100):
+Transition(Idle)
+endif
+F5+:
+Transition(Idle)
+endif
+";
+
+    fn character() -> ParsedCharacter {
+        CastagneParser::new()
+            .create_full_character_from_source("tester.casp", FIGHTER)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_simulate_scripted_inputs() {
+        let character = character();
+        let walk = vec![vec!["Forward"]; 4];
+        let snapshot = simulate([&character, &character], [&walk, &[]], 4);
+
+        assert_eq!(snapshot.frame, 4);
+        let (player, dummy) = (&snapshot.entities[0], &snapshot.entities[1]);
+        // Frame 1 is spent in Init
+        assert_eq!(player.state, "Idle");
+        assert_eq!(player.state_frame, 3);
+        assert_eq!(player.position, (12, 0));
+        assert_eq!(player.variables["Meter"], 3);
+        assert!(player.flags.contains("Tired"));
+        assert_eq!(dummy.position, (0, 0));
+        assert_eq!(dummy.variables["Meter"], 0);
+
+        let mut inputs = walk.clone();
+        inputs.push(vec!["A"]);
+        let snapshot = simulate([&character, &character], [&inputs, &[]], 6);
+        let player = &snapshot.entities[0];
+        assert_eq!((player.state.as_str(), player.state_frame), ("Jab", 1));
+        assert_eq!(player.variables["Meter"], 10);
+        assert!(player.flags.is_empty());
+
+        // Deterministic: the same script gives the same snapshot
+        let again = simulate([&character, &character], [&inputs, &[]], 6);
+        assert_eq!(again, snapshot);
+    }
+
+    #[test]
+    fn test_play_scenario() {
+        let character = character();
+        let file = parse_scenarios(
+            "Character: tester.casp\n:Jab:\nFrames(12)\nPress(2, A)\nExpectState(3, Jab)\nExpectState(7, Jab)\nExpectState(8, Idle)\n",
+        )
+        .unwrap();
+
+        let failures = play_scenario(&file.scenarios[0], &character, &character);
+
+        assert_eq!(failures, ["Jab: expected state Jab on frame 7, got Idle"]);
+    }
+}
@@ -0,0 +1,153 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Skeleton Cache - Parse shared skeletons once per batch
+//!
+//! Most characters of a project inherit the same base files. Parsers sharing
+//! a `SkeletonCache` parse each skeleton or included file once and merge the
+//! cached result into every character using it. Entries are keyed by the
+//! file's resolved path and a hash of its contents, so an edited file is
+//! parsed again instead of being served stale.
+
+use crate::diagnostics::Diagnostic;
+use crate::parser::ParsedCharacter;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Resolved path and content hash of a file
+pub(crate) type CacheKey = (PathBuf, u64);
+
+/// A parsed file and the diagnostics its parse produced
+#[derive(Debug)]
+pub(crate) struct CachedFile {
+    pub character: Arc<ParsedCharacter>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, (u64, Arc<CachedFile>)>,
+    hits: usize,
+    misses: usize,
+}
+
+/// Parsed skeletons and includes shared between parsers
+///
+/// Clones share the same entries. Two parsers missing the same file at
+/// the same time both parse it, the last one being kept.
+#[derive(Debug, Clone, Default)]
+pub struct SkeletonCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl SkeletonCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Key of a file, `None` if it can't be read
+    pub(crate) fn key(path: &str) -> Option<CacheKey> {
+        let bytes = fs::read(path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let resolved = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        Some((resolved, hasher.finish()))
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Arc<CachedFile>> {
+        let mut state = self.lock();
+        let cached = state
+            .entries
+            .get(&key.0)
+            .filter(|(hash, _)| *hash == key.1)
+            .map(|(_, cached)| Arc::clone(cached));
+        match cached {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        cached
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, cached: CachedFile) {
+        self.lock().entries.insert(key.0, (key.1, Arc::new(cached)));
+    }
+
+    /// Number of cached files
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Lookups served from the cache
+    pub fn hits(&self) -> usize {
+        self.lock().hits
+    }
+
+    /// Lookups that had to parse the file
+    pub fn misses(&self) -> usize {
+        self.lock().misses
+    }
+
+    pub fn clear(&self) {
+        *self.lock() = CacheState::default();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_skeleton_is_parsed_once_until_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        for name in ["ryu", "ken"] {
+            let source = format!(
+                ":Character:\nName: {}\nSkeleton: {}\n:Walk:\n---Init:\nMove(1)\n",
+                name,
+                path("base.casp")
+            );
+            fs::write(path(&format!("{}.casp", name)), source).unwrap();
+        }
+
+        let cache = SkeletonCache::new();
+        let parse = |name: &str| {
+            CastagneParser::new()
+                .with_skeleton_cache(cache.clone())
+                .create_full_character(&path(name))
+                .unwrap()
+        };
+        let ryu = parse("ryu.casp");
+        let ken = parse("ken.casp");
+        assert!(ryu.states.contains_key("Idle") && ken.states.contains_key("Idle"));
+        assert_eq!(ken.metadata.name, "ken");
+        assert_eq!((cache.misses(), cache.hits(), cache.len()), (1, 1, 1));
+
+        fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Crouch:\n---Init:\nStop\n",
+        )
+        .unwrap();
+        let ryu = parse("ryu.casp");
+        assert!(ryu.states.contains_key("Crouch") && !ryu.states.contains_key("Idle"));
+        assert_eq!((cache.misses(), cache.hits(), cache.len()), (2, 1, 1));
+    }
+}
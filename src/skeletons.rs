@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Skeletons - Castagne's standard skeletons, built into the crate
+//!
+//! With the `embedded-skeletons` feature, on by default, the base files of
+//! Castagne's modules are compiled in, so characters using them parse in
+//! unit tests and headless tools without the Godot project around. They
+//! are found by logical name, the file name without its extension, so a
+//! character can name one either way:
+//!
+//! ```text
+//! Skeleton: Base-Core
+//! Skeleton: res://castagne_godot4/modules/core/Base-Core.casp
+//! ```
+//!
+//! A file on disk always wins: the parser only reads an embedded skeleton
+//! when the path can't be opened.
+//!
+//! Other skeletons get logical names from `ParserConfig`: an alias stands
+//! for a path, and the search paths are directories the path is also
+//! looked up in, `res://` paths from their root and any path by its file
+//! name, so files keep naming a skeleton the same way when the project is
+//! moved around:
+//!
+//! ```text
+//! Baston          res://castagne/baston/Baston-Model.casp (alias)
+//! res://castagne/baston/Baston-Model.casp
+//!                 <search path>/castagne/baston/Baston-Model.casp
+//!                 <search path>/Baston-Model.casp
+//! ```
+
+use crate::parser::ParserConfig;
+use std::path::Path;
+
+/// A base file compiled into the crate
+#[derive(Debug)]
+pub struct EmbeddedSkeleton {
+    /// File name without its extension, like `Base-Core`
+    pub name: &'static str,
+    /// Where the engine loads it among the base files, lowest first
+    pub order: i32,
+    pub source: &'static str,
+}
+
+/// Embedded base files, in the order the engine loads them
+#[cfg(feature = "embedded-skeletons")]
+pub const SKELETONS: &[EmbeddedSkeleton] = &[
+    EmbeddedSkeleton {
+        name: "Base-Core",
+        order: -9000,
+        source: include_str!("../castagne_godot4/modules/core/Base-Core.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-Attacks",
+        order: -5000,
+        source: include_str!("../castagne_godot4/modules/attacks/Base-Attacks.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-Physics2D",
+        order: 0,
+        source: include_str!("../castagne_godot4/modules/physics/Base-Physics2D.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-Audio",
+        order: 0,
+        source: include_str!("../castagne_godot4/modules/general/Base-Audio.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-Graphics",
+        order: 0,
+        source: include_str!("../castagne_godot4/modules/graphics/Base-Graphics.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-AI",
+        order: 5000,
+        source: include_str!("../castagne_godot4/modules/general/Base-AI.casp"),
+    },
+    EmbeddedSkeleton {
+        name: "Base-Training",
+        order: 9000,
+        source: include_str!("../castagne_godot4/modules/general/Base-Training.casp"),
+    },
+];
+
+#[cfg(not(feature = "embedded-skeletons"))]
+pub const SKELETONS: &[EmbeddedSkeleton] = &[];
+
+/// File name of a path without its `.casp` extension
+pub fn logical_name(path: &str) -> &str {
+    let file = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    file.strip_suffix(".casp").unwrap_or(file)
+}
+
+/// The embedded skeleton a logical name or path refers to
+pub fn get(path: &str) -> Option<&'static EmbeddedSkeleton> {
+    let name = logical_name(path);
+    SKELETONS.iter().find(|skeleton| skeleton.name == name)
+}
+
+impl ParserConfig {
+    /// File a `Skeleton:` or `Include:` path refers to, through the aliases
+    /// then the search paths; the path itself when none has it
+    pub fn resolve_skeleton(&self, path: &str) -> String {
+        let path = self.skeleton_aliases.get(path).map_or(path, String::as_str);
+        if self.skeleton_paths.is_empty() || Path::new(path).is_file() {
+            return path.to_string();
+        }
+        let relative = path.strip_prefix("res://").unwrap_or(path);
+        let file = format!("{}.casp", logical_name(relative));
+        self.skeleton_paths
+            .iter()
+            .flat_map(|dir| [dir.join(relative), dir.join(&file)])
+            .find(|candidate| candidate.is_file())
+            .map_or_else(|| path.to_string(), |found| found.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_skeleton_search_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let baston = dir.path().join("castagne/baston");
+        std::fs::create_dir_all(&baston).unwrap();
+        std::fs::write(
+            baston.join("Baston-Model.casp"),
+            ":Character:\nName: Baston\n:Stand:\n---Init:\nMove(1)\n",
+        )
+        .unwrap();
+        let model = baston.join("Baston-Model.casp").display().to_string();
+
+        let config = ParserConfig::new()
+            .with_skeleton_path(dir.path())
+            .with_skeleton_path(&baston)
+            .with_skeleton_alias("Baston", "res://castagne/baston/Baston-Model.casp");
+        assert_eq!(config.resolve_skeleton("Baston"), model);
+        assert_eq!(config.resolve_skeleton("Baston-Model"), model);
+        assert_eq!(config.resolve_skeleton("Missing"), "Missing");
+
+        let source = "\
+:Character:
+Name: Tester
+Skeleton: Baston
+:Idle:
+---Init:
+Move(2)
+";
+        let mut parser = CastagneParser::with_config(config);
+        let character = parser
+            .create_full_character_from_source("tester.casp", source)
+            .unwrap();
+        assert!(parser.get_errors().is_empty(), "{:?}", parser.get_errors());
+        assert_eq!(character.metadata.skeleton.as_deref(), Some("Baston"));
+        assert!(character.states.contains_key("Stand"));
+    }
+
+    #[cfg(feature = "embedded-skeletons")]
+    #[test]
+    fn test_embedded_skeletons() {
+        assert_eq!(
+            logical_name("res://modules/core/Base-Core.casp"),
+            "Base-Core"
+        );
+        assert_eq!(get("Base-AI").unwrap().order, 5000);
+        assert!(get("Base-Nothing").is_none());
+        for skeleton in SKELETONS {
+            let mut parser = CastagneParser::new();
+            parser
+                .create_full_character_from_source(skeleton.name, skeleton.source)
+                .unwrap();
+            assert!(parser.get_errors().is_empty(), "{}", skeleton.name);
+        }
+
+        let source = "\
+:Character:
+Name: Tester
+Skeleton: Base-Core
+Include: res://castagne_godot4/modules/attacks/Base-Attacks.casp
+:Idle:
+---Init:
+Move(1)
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("tester.casp", source)
+            .unwrap();
+        assert!(parser.get_errors().is_empty(), "{:?}", parser.get_errors());
+        assert!(character.states.contains_key("NeutralState"));
+        assert!(character.states.contains_key("AirThrowF"));
+        assert!(character.states.contains_key("Idle"));
+    }
+}
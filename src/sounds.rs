@@ -0,0 +1,204 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sounds - Every sound cue of a character and where it is used
+//!
+//! Sound effects are declared in a specblock as `AUDIO_SFX_<Name>___Filepath`
+//! and `AUDIO_SFX_<Name>___Volume` entries, and played by states with
+//! `SFXPlay(Name)` (`PlaySound` in older files) or tuned with
+//! `SFXParam(Name, ...)`. `sound_catalog` gathers both into one list, so
+//! audio designers get the cues a character needs and the engine can
+//! preload their streams when the character is picked.
+//!
+//! Cue names go through the defines like animation names do. Cues played
+//! but never declared are listed without a file.
+
+use crate::parser::ParsedCharacter;
+use crate::source_index::SourceRef;
+use std::collections::BTreeMap;
+
+/// Instructions using the sound cue named by their first argument
+pub const SOUND_INSTRUCTIONS: &[&str] = &["SFXPlay", "PlaySound", "SFXParam"];
+
+const SFX_PREFIX: &str = "AUDIO_SFX_";
+
+/// Separator between a structure instance and its field, as in Castagne
+const STRUCT_SEPARATOR: &str = "___";
+
+/// Volume of a cue that doesn't set one, as in the Castagne audio module
+const DEFAULT_VOLUME: i64 = 1000;
+
+/// An action using a sound cue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundUsage {
+    pub state: String,
+    pub phase: String,
+    pub instruction: String,
+    pub origin: SourceRef,
+}
+
+/// A sound cue with its file and the actions using it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundCue {
+    pub name: String,
+    /// Audio file to load, `None` when the cue isn't declared
+    pub path: Option<String>,
+    pub volume: i64,
+    /// Where its file is declared
+    pub declaration: Option<SourceRef>,
+    /// Sorted by file and line
+    pub usages: Vec<SoundUsage>,
+}
+
+impl SoundCue {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: None,
+            volume: DEFAULT_VOLUME,
+            declaration: None,
+            usages: Vec::new(),
+        }
+    }
+}
+
+impl ParsedCharacter {
+    /// Sound cues declared or used by the character, sorted by name
+    pub fn sound_catalog(&self) -> Vec<SoundCue> {
+        let mut cues: BTreeMap<String, SoundCue> = BTreeMap::new();
+
+        for (block_name, block) in &self.specblocks {
+            for (key, value) in block {
+                let Some((name, field)) = key
+                    .strip_prefix(SFX_PREFIX)
+                    .and_then(|rest| rest.split_once(STRUCT_SEPARATOR))
+                else {
+                    continue;
+                };
+                let entry = cues
+                    .entry(name.to_string())
+                    .or_insert_with(|| SoundCue::new(name));
+                let value = value.trim();
+                match field {
+                    "Filepath" => {
+                        entry.path = Some(value)
+                            .filter(|path| !path.is_empty() && *path != "res://")
+                            .map(str::to_string);
+                        entry.declaration = self
+                            .specblock_origins
+                            .get(block_name)
+                            .and_then(|origins| origins.get(key))
+                            .cloned();
+                    }
+                    "Volume" => entry.volume = value.parse().unwrap_or(DEFAULT_VOLUME),
+                    _ => {}
+                }
+            }
+        }
+
+        for state in self.states.values() {
+            for (phase, actions) in &state.actions {
+                for action in actions {
+                    if !SOUND_INSTRUCTIONS.contains(&&*action.instruction) {
+                        continue;
+                    }
+                    let Some(name) = action.args.first().map(|arg| self.timeline_name(arg)) else {
+                        continue;
+                    };
+                    if name.is_empty() {
+                        continue;
+                    }
+                    let entry = cues
+                        .entry(name.clone())
+                        .or_insert_with(|| SoundCue::new(&name));
+                    entry.usages.push(SoundUsage {
+                        state: state.name.to_string(),
+                        phase: phase.to_string(),
+                        instruction: action.instruction.to_string(),
+                        origin: SourceRef {
+                            file: state.origin.file.clone(),
+                            line: action.line_number,
+                        },
+                    });
+                }
+            }
+        }
+
+        let mut cues: Vec<SoundCue> = cues.into_values().collect();
+        for cue in &mut cues {
+            cue.usages.sort_by(|a, b| {
+                (&a.origin.file, a.origin.line).cmp(&(&b.origin.file, b.origin.line))
+            });
+        }
+        cues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_sound_catalog() {
+        let source = ":Character:
+Name: Ryu
+:Audio:
+AUDIO_SFX_Hadoken___Filepath: res://ryu/hadoken.wav
+AUDIO_SFX_Hadoken___Volume: 800
+AUDIO_SFX_Step___Filepath: res://ryu/step.wav
+:Variables:
+def VoiceLine: Shoryuken
+:Hadoken:
+---Init:
+SFXPlay(Hadoken)
+---Action:
+F12:
+SFXParam(Hadoken, 2)
+endif
+:Shoryu:
+---Init:
+PlaySound(VoiceLine)
+:Walk:
+---Action:
+SFXPlay(\"Step\")
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+
+        let catalog = character.sound_catalog();
+        let names: Vec<&str> = catalog.iter().map(|cue| cue.name.as_str()).collect();
+        assert_eq!(names, vec!["Hadoken", "Shoryuken", "Step"]);
+
+        let hadoken = &catalog[0];
+        assert_eq!(hadoken.path.as_deref(), Some("res://ryu/hadoken.wav"));
+        assert_eq!(hadoken.volume, 800);
+        assert_eq!(hadoken.declaration.as_ref().unwrap().line, 4);
+        let usages: Vec<(&str, &str, usize)> = hadoken
+            .usages
+            .iter()
+            .map(|usage| {
+                (
+                    usage.phase.as_str(),
+                    usage.instruction.as_str(),
+                    usage.origin.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            usages,
+            vec![("Init", "SFXPlay", 11), ("Action", "SFXParam", 14)]
+        );
+
+        // Played through a define but never declared
+        let shoryuken = &catalog[1];
+        assert_eq!(shoryuken.path, None);
+        assert_eq!(shoryuken.volume, 1000);
+        assert_eq!(shoryuken.usages[0].state, "Shoryu");
+
+        let step = &catalog[2];
+        assert_eq!(step.usages.len(), 1);
+        assert_eq!(step.usages[0].origin.file, "ryu.casp");
+    }
+}
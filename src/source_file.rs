@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Source File - Character files kept as raw bytes for zero-copy parsing
+//!
+//! `borrowed::parse` needs the whole source as one `&str`. A `SourceFile`
+//! holds it without splitting it into lines: with the `mmap` feature,
+//! `open` memory-maps the file so even the file contents aren't copied,
+//! otherwise it reads the file into a single buffer.
+
+use crate::borrowed::{self, ParsedCharacterRef};
+use crate::parser::decode_source;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+enum Contents {
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+/// Bytes of a character file
+pub struct SourceFile {
+    path: PathBuf,
+    contents: Contents,
+}
+
+impl SourceFile {
+    /// Open a file, memory-mapping it when the `mmap` feature is enabled
+    ///
+    /// The file must not be modified while mapped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        #[cfg(feature = "mmap")]
+        {
+            let file = fs::File::open(path.as_ref())?;
+            // Empty files can't be mapped on every platform
+            if file.metadata()?.len() > 0 {
+                // SAFETY: the mapping is read-only, and callers are told not to
+                // modify the file while it is mapped
+                let map = unsafe { memmap2::Mmap::map(&file)? };
+                return Ok(Self {
+                    path: path.as_ref().to_path_buf(),
+                    contents: Contents::Mapped(map),
+                });
+            }
+        }
+        Self::read(path)
+    }
+
+    /// Read a whole file into memory
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            contents: Contents::Read(fs::read(path.as_ref())?),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        match self.contents {
+            #[cfg(feature = "mmap")]
+            Contents::Mapped(_) => true,
+            Contents::Read(_) => false,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match &self.contents {
+            #[cfg(feature = "mmap")]
+            Contents::Mapped(map) => map,
+            Contents::Read(bytes) => bytes,
+        }
+    }
+
+    /// The file as text, or why it isn't valid UTF-8
+    pub fn text(&self) -> Result<&str, String> {
+        std::str::from_utf8(self.bytes()).map_err(|_| {
+            let message = decode_source(self.bytes(), false, 1)
+                .err()
+                .unwrap_or_default();
+            format!("File {} {}", self.path.display(), message)
+        })
+    }
+
+    /// Parse the file without copying its text, see `borrowed::parse`
+    pub fn parse(&self) -> Result<ParsedCharacterRef<'_>, String> {
+        borrowed::parse(self.text()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ryu.casp");
+        fs::write(
+            &path,
+            "\u{feff}:Character:\nName: Ryu\n:Idle:\n---Init:\nMove(1)\n",
+        )
+        .unwrap();
+
+        let file = SourceFile::open(&path).unwrap();
+        assert_eq!(file.is_mapped(), cfg!(feature = "mmap"));
+        assert_eq!(file.path(), path);
+        let character = file.parse().unwrap();
+        assert_eq!(character.metadata.name, "Ryu");
+        assert_eq!(
+            character.states["Idle"].phase("Init").unwrap()[0].args,
+            vec!["1"]
+        );
+
+        let empty = dir.path().join("empty.casp");
+        fs::write(&empty, "").unwrap();
+        assert!(!SourceFile::open(&empty).unwrap().is_mapped());
+
+        let latin1 = dir.path().join("latin1.casp");
+        fs::write(&latin1, b":Character:\nName: Ren\xE9\n").unwrap();
+        let error = SourceFile::read(&latin1).unwrap().parse().unwrap_err();
+        assert!(error.ends_with("latin1.casp is not valid UTF-8 (line 2, column 10)"));
+    }
+}
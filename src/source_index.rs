@@ -0,0 +1,238 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Source Index - Line to definition lookups
+//!
+//! Built while parsing the states of the main file. Maps any line to the
+//! enclosing state, phase and action, and each state back to its line
+//! range, for "go to state", breakpoints and error gutter markers.
+//!
+//! Line numbers are 1-indexed like `ParsedAction::line_number`. States
+//! inherited from a skeleton are not in the child's index, their
+//! `SourceRef` origin says where they come from.
+
+use std::fmt;
+
+/// File and line a merged symbol was declared at
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SourceRef {
+    /// Empty when unknown, e.g. for characters built from borrowed data
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Lines covered by a phase inside a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseSpan {
+    pub name: String,
+    /// Line of the `---Phase:` marker
+    pub start_line: usize,
+    /// Last line before the next phase marker or state
+    pub end_line: usize,
+    /// Lines of the actions in this span, in order
+    pub action_lines: Vec<usize>,
+    /// Index in `state.actions[phase]` of the first action of this span,
+    /// non-zero when a phase marker is repeated
+    pub action_offset: usize,
+}
+
+/// Lines covered by a state block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSpan {
+    pub name: String,
+    /// Line of the `:State:` header
+    pub start_line: usize,
+    /// Last line before the next block
+    pub end_line: usize,
+    pub phases: Vec<PhaseSpan>,
+}
+
+impl StateSpan {
+    pub fn contains(&self, line: usize) -> bool {
+        self.start_line <= line && line <= self.end_line
+    }
+
+    pub(crate) fn shift(&mut self, delta: isize) {
+        let apply = |line: &mut usize| *line = (*line as isize + delta) as usize;
+        apply(&mut self.start_line);
+        apply(&mut self.end_line);
+        for phase in &mut self.phases {
+            apply(&mut phase.start_line);
+            apply(&mut phase.end_line);
+            phase.action_lines.iter_mut().for_each(apply);
+        }
+    }
+}
+
+/// What encloses a given line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub state: String,
+    pub phase: Option<String>,
+    /// Index into `state.actions[phase]` of the action on that line
+    pub action_index: Option<usize>,
+}
+
+/// Index of state definitions, ordered by line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceIndex {
+    states: Vec<StateSpan>,
+}
+
+impl SourceIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All state spans, ordered by line
+    pub fn states(&self) -> &[StateSpan] {
+        &self.states
+    }
+
+    /// State block containing `line`
+    pub fn state_at(&self, line: usize) -> Option<&StateSpan> {
+        self.states.iter().find(|span| span.contains(line))
+    }
+
+    /// State, phase and action enclosing `line`
+    pub fn location_at(&self, line: usize) -> Option<SourceLocation> {
+        let state = self.state_at(line)?;
+        let phase = state
+            .phases
+            .iter()
+            .find(|phase| phase.start_line <= line && line <= phase.end_line);
+
+        Some(SourceLocation {
+            state: state.name.clone(),
+            phase: phase.map(|phase| phase.name.clone()),
+            action_index: phase.and_then(|phase| {
+                phase
+                    .action_lines
+                    .iter()
+                    .position(|&l| l == line)
+                    .map(|index| phase.action_offset + index)
+            }),
+        })
+    }
+
+    /// Header and last line of a state; the last definition wins, like in parsing
+    pub fn state_range(&self, name: &str) -> Option<(usize, usize)> {
+        self.states
+            .iter()
+            .rev()
+            .find(|span| span.name == name)
+            .map(|span| (span.start_line, span.end_line))
+    }
+
+    pub(crate) fn push(&mut self, span: StateSpan) {
+        self.states.push(span);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Drop the spans starting at the given lines
+    pub(crate) fn remove_starting_at(&mut self, start_lines: &[usize]) {
+        self.states
+            .retain(|span| !start_lines.contains(&span.start_line));
+    }
+
+    /// Shift spans starting after `line` by `delta` lines
+    pub(crate) fn shift_after(&mut self, line: usize, delta: isize) {
+        for span in &mut self.states {
+            if span.start_line > line {
+                span.shift(delta);
+            }
+        }
+    }
+
+    pub(crate) fn extend(&mut self, spans: impl IntoIterator<Item = StateSpan>) {
+        self.states.extend(spans);
+        self.states.sort_by_key(|span| span.start_line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    const SOURCE: &str = ":Character:
+Name: Test
+:Idle:
+---Init:
+Set(Health, 100)
+
+---Action:
+Move(1)
+Move(2)
+:Walk:
+# comment
+---Action:
+Move(10)
+";
+
+    fn index() -> (crate::parser::ParsedCharacter, SourceIndex) {
+        let character = CastagneParser::new()
+            .create_full_character_from_source("test.casp", SOURCE)
+            .unwrap();
+        let index = character.source_index.clone();
+        (character, index)
+    }
+
+    #[test]
+    fn test_state_ranges() {
+        let (_, index) = index();
+
+        assert_eq!(index.state_range("Idle"), Some((3, 9)));
+        assert_eq!(index.state_range("Walk"), Some((10, 13)));
+        assert_eq!(index.state_range("Run"), None);
+        assert_eq!(index.states().len(), 2);
+    }
+
+    #[test]
+    fn test_location_at_line() {
+        let (character, index) = index();
+
+        let location = index.location_at(9).unwrap();
+        assert_eq!(location.state, "Idle");
+        assert_eq!(location.phase.as_deref(), Some("Action"));
+        assert_eq!(location.action_index, Some(1));
+        assert_eq!(character.action_at(9).unwrap().args, vec!["2"]);
+
+        // Blank line inside a phase
+        let location = index.location_at(6).unwrap();
+        assert_eq!(location.phase.as_deref(), Some("Init"));
+        assert_eq!(location.action_index, None);
+
+        // Between the header and the first phase
+        let location = index.location_at(11).unwrap();
+        assert_eq!(location.state, "Walk");
+        assert_eq!(location.phase, None);
+
+        assert!(index.location_at(1).is_none());
+    }
+
+    #[test]
+    fn test_repeated_phase_action_index() {
+        let character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":Idle:\n---Init:\nA()\n---Action:\nB()\n---Init:\nC()\n",
+            )
+            .unwrap();
+
+        let location = character.source_index.location_at(7).unwrap();
+        assert_eq!(location.phase.as_deref(), Some("Init"));
+        assert_eq!(location.action_index, Some(1));
+        assert_eq!(character.action_at(7).unwrap().instruction, "C");
+    }
+}
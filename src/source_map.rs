@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Source Map - Preprocessed lines of every file of a parse
+//!
+//! Blame views and error displays across skeleton files need the text
+//! the parser actually read, not the file on disk: block comments
+//! stripped, multi-line strings folded and conditional lines resolved.
+//! `CastagneParser::source_map` returns those lines for the file parsed
+//! and, with `ParserConfig::with_source_lines`, for each skeleton and
+//! include merged into it. Every `SourceRef` origin of the parsed
+//! character, and `ParsedState::action_origin`, points into it.
+//!
+//! Parents reused from `with_parent` or a skeleton cache weren't read by
+//! this parse and have no lines, nor does a streamed file, which only ever
+//! holds one block.
+
+use crate::parser::{ParsedAction, ParsedState};
+use crate::source_index::SourceRef;
+
+/// Preprocessed lines of one file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileLines {
+    pub path: String,
+    pub lines: Vec<String>,
+    /// 1-indexed line in the file of each of `lines`
+    pub line_ids: Vec<usize>,
+}
+
+impl FileLines {
+    /// Preprocessed text of line `line` of the file
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let index = self.line_ids.iter().position(|id| *id == line)?;
+        self.lines.get(index).map(String::as_str)
+    }
+}
+
+/// Lines of the file parsed, then of its skeletons and includes in the
+/// order they were merged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    pub files: Vec<FileLines>,
+}
+
+impl SourceMap {
+    pub fn file(&self, path: &str) -> Option<&FileLines> {
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    /// Paths of the files, the one parsed first
+    pub fn file_paths(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|file| file.path.as_str())
+    }
+
+    /// Preprocessed text at an origin
+    pub fn line(&self, origin: &SourceRef) -> Option<&str> {
+        self.file(&origin.file)?.line(origin.line)
+    }
+}
+
+impl ParsedState {
+    /// File and line of one of the state's actions, in the file the state
+    /// was defined in
+    pub fn action_origin(&self, action: &ParsedAction) -> SourceRef {
+        SourceRef {
+            file: self.origin.file.clone(),
+            line: action.line_number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{CastagneParser, ParserConfig};
+
+    #[test]
+    fn test_source_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("base.casp"),
+            ":Character:\nName: Base\n:Idle:\n---Init:\n/* old */ Anim(Idle)\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("moves.casp"),
+            ":Character:\n:Jab:\n---Action:\nAttack(1)\n",
+        )
+        .unwrap();
+        let source = format!(
+            ":Character:\nSkeleton: {}\nInclude: {}\n:Walk:\n---Init:\nSay(\"\"\"\nHi\n\"\"\")\n",
+            path("base.casp"),
+            path("moves.casp")
+        );
+        std::fs::write(path("child.casp"), &source).unwrap();
+
+        let config = ParserConfig::default().with_source_lines(true);
+        let mut parser = CastagneParser::with_config(config);
+        let character = parser.create_full_character(&path("child.casp")).unwrap();
+        let map = parser.source_map();
+        let paths: Vec<&str> = map.file_paths().collect();
+        assert_eq!(
+            paths,
+            [path("child.casp"), path("base.casp"), path("moves.casp")]
+        );
+
+        let child = map.file(&path("child.casp")).unwrap();
+        assert_eq!(child.line_ids.len(), 8);
+        assert_eq!(child.line(6), Some("Say(\"Hi\")"));
+        assert_eq!(child.line(7), Some(""));
+
+        let idle = &character.states["Idle"];
+        assert_eq!(map.line(&idle.origin), Some(":Idle:"));
+        let anim = idle.action_origin(&idle.actions["Init"][0]);
+        assert_eq!(anim.file, path("base.casp"));
+        assert_eq!(map.line(&anim), Some(" Anim(Idle)"));
+        let jab = &character.states["Jab"];
+        assert_eq!(
+            map.line(&jab.action_origin(&jab.actions["Action"][0])),
+            Some("Attack(1)")
+        );
+
+        // Without the option, only the file parsed is kept
+        let mut parser = CastagneParser::new();
+        parser.create_full_character(&path("child.casp")).unwrap();
+        assert_eq!(parser.source_map().files.len(), 1);
+    }
+}
@@ -0,0 +1,569 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Specs - Typed views of the standard specblocks
+//!
+//! Specblocks are parsed as raw `key -> string` maps. The accessors here
+//! read the well-known ones (`Graphics` spritesheets and palettes,
+//! `PhysicsMovement`, `AttacksTypes`) into structs, using the Castagne module defaults for
+//! missing keys and reporting values that don't parse as diagnostics.
+
+use crate::color::{parse_color_list, Rgba};
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::parser::ParsedCharacter;
+use std::collections::{BTreeMap, HashMap};
+
+pub const INVALID_SPEC_VALUE: &str = "invalid-spec-value";
+
+/// Diagnostic code of a spritesheet that doesn't fit its image
+pub const SPRITESHEET_MISMATCH: &str = "spritesheet-mismatch";
+
+/// Diagnostic code of a `Sprite` frame past the end of its spritesheet
+pub const SPRITE_OUT_OF_BOUNDS: &str = "sprite-out-of-bounds";
+
+/// Separator between a structure instance and its field, as in Castagne
+const STRUCT_SEPARATOR: &str = "___";
+
+const SPRITESHEET_PREFIX: &str = "GRAPHICS_SPRITESHEET_";
+const PALETTE_PREFIX: &str = "GRAPHICS_SPRITE_PALETTE_";
+const ATTACK_TYPE_PREFIX: &str = "ATTACK_";
+
+/// A spritesheet declared in the `Graphics` specblock
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpritesheetSpec {
+    pub name: String,
+    pub path: String,
+    pub sprites_x: i64,
+    pub sprites_y: i64,
+    pub origin_x: i64,
+    pub origin_y: i64,
+    pub pixel_size: i64,
+    pub palette_mode: i64,
+}
+
+/// An alternate color scheme declared in the `Graphics` specblock
+///
+/// Palette 0 is the default look; `PaletteApply` switches to the others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteSpec {
+    pub name: String,
+    pub display_name: String,
+    pub sprite_palette_path: Option<String>,
+    pub model_path: Option<String>,
+    pub extra: i64,
+    /// Replacement colors from the `Colors` list, in order
+    pub colors: Vec<Rgba>,
+}
+
+/// Movement settings from the `PhysicsMovement` specblock
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhysicsSpec {
+    pub gravity: i64,
+    pub friction_ground: i64,
+    pub friction_air: i64,
+    pub air_actions_max: i64,
+    pub walk_speed_forward: i64,
+    pub walk_speed_back: i64,
+    pub jumpsquat_time: i64,
+}
+
+/// Settings of one attack type (`Light`, `Medium`, ...) from `AttacksTypes`
+///
+/// Defaults differ per type in Castagne, so missing values stay `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttackTypeSpec {
+    pub name: String,
+    pub proration_damage_starter: Option<i64>,
+    pub proration_damage: Option<i64>,
+    pub proration_hitstun_starter: Option<i64>,
+    pub proration_hitstun: Option<i64>,
+    pub can_jump_cancel_on_hit: Option<bool>,
+    pub can_jump_cancel_on_block: Option<bool>,
+    /// `CanCancelInto<Type>` flags, by target type
+    pub cancels_into: BTreeMap<String, bool>,
+}
+
+/// The `AttacksTypes` specblock
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttackDataSpec {
+    pub min_damage: i64,
+    /// Attack types, sorted by name
+    pub types: Vec<AttackTypeSpec>,
+}
+
+/// Reads typed values out of one specblock, collecting errors
+pub(crate) struct SpecReader<'a> {
+    block_name: &'a str,
+    pub(crate) block: Option<&'a HashMap<String, String>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> SpecReader<'a> {
+    pub(crate) fn new(character: &'a ParsedCharacter, block_name: &'a str) -> Self {
+        Self {
+            block_name,
+            block: character.specblocks.get(block_name),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub(crate) fn raw(&self, key: &str) -> Option<&'a str> {
+        self.block?.get(key).map(|value| value.trim())
+    }
+
+    pub(crate) fn int(&mut self, key: &str) -> Option<i64> {
+        let value = self.raw(key)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.invalid(key, value, "an integer");
+                None
+            }
+        }
+    }
+
+    fn int_or(&mut self, key: &str, default: i64) -> i64 {
+        self.int(key).unwrap_or(default)
+    }
+
+    fn bool(&mut self, key: &str) -> Option<bool> {
+        let value = self.raw(key)?;
+        match value {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => {
+                self.invalid(key, value, "a boolean");
+                None
+            }
+        }
+    }
+
+    fn colors(&mut self, key: &str) -> Vec<Rgba> {
+        let Some(value) = self.raw(key) else {
+            return Vec::new();
+        };
+        parse_color_list(value).unwrap_or_else(|| {
+            self.invalid(key, value, "a list of colors");
+            Vec::new()
+        })
+    }
+
+    fn invalid(&mut self, key: &str, value: &str, expected: &str) {
+        self.diagnostics.push(Diagnostic::new(
+            INVALID_SPEC_VALUE,
+            Severity::Error,
+            format!(
+                "{}.{} should be {}, got {:?}",
+                self.block_name, key, expected, value
+            ),
+        ));
+    }
+
+    pub(crate) fn finish<T>(self, value: T) -> Result<T, Vec<Diagnostic>> {
+        if self.diagnostics.is_empty() {
+            Ok(value)
+        } else {
+            Err(self.diagnostics)
+        }
+    }
+}
+
+impl ParsedCharacter {
+    /// Spritesheets of the `Graphics` specblock, sorted by name
+    pub fn spritesheets(&self) -> Result<Vec<SpritesheetSpec>, Vec<Diagnostic>> {
+        let mut reader = SpecReader::new(self, "Graphics");
+
+        let mut names: Vec<&str> = reader
+            .block
+            .into_iter()
+            .flat_map(|block| block.keys())
+            .filter_map(|key| key.strip_prefix(SPRITESHEET_PREFIX))
+            .map(|rest| rest.split(STRUCT_SEPARATOR).next().unwrap_or(rest))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let mut spritesheets = Vec::new();
+        for name in names {
+            let key = |field: &str| {
+                format!(
+                    "{}{}{}{}",
+                    SPRITESHEET_PREFIX, name, STRUCT_SEPARATOR, field
+                )
+            };
+            spritesheets.push(SpritesheetSpec {
+                name: name.to_string(),
+                path: reader.raw(&key("Path")).unwrap_or("res://").to_string(),
+                sprites_x: reader.int_or(&key("SpritesX"), 1),
+                sprites_y: reader.int_or(&key("SpritesY"), 1),
+                origin_x: reader.int_or(&key("OriginX"), 0),
+                origin_y: reader.int_or(&key("OriginY"), 0),
+                pixel_size: reader.int_or(&key("PixelSize"), 100000),
+                palette_mode: reader.int_or(&key("PaletteMode"), 0),
+            });
+        }
+        reader.finish(spritesheets)
+    }
+
+    /// Check the spritesheets against their images and the `Sprite(Name,
+    /// Frame)` actions against the spritesheets; `texture_size` gives the
+    /// width and height of the image at a path, `None` if it can't be read
+    pub fn validate_spritesheets(
+        &self,
+        texture_size: impl Fn(&str) -> Option<(i64, i64)>,
+    ) -> Vec<Diagnostic> {
+        let spritesheets = match self.spritesheets() {
+            Ok(spritesheets) => spritesheets,
+            Err(diagnostics) => return diagnostics,
+        };
+
+        let mut diagnostics = Vec::new();
+        let mut report = |severity, code: &str, message: String, span: Option<Span>| {
+            let mut diagnostic = Diagnostic::new(code, severity, message);
+            diagnostic.span = span;
+            diagnostics.push(diagnostic);
+        };
+        for sheet in &spritesheets {
+            let span = |field: &str| {
+                let key = format!(
+                    "{}{}{}{}",
+                    SPRITESHEET_PREFIX, sheet.name, STRUCT_SEPARATOR, field
+                );
+                let origin = self.specblock_origins.get("Graphics")?.get(&key)?;
+                Some(Span {
+                    file: Some(origin.file.clone()),
+                    ..Span::line(origin.line)
+                })
+            };
+            if sheet.sprites_x <= 0 || sheet.sprites_y <= 0 {
+                let message = format!(
+                    "Spritesheet {} has {}x{} sprites",
+                    sheet.name, sheet.sprites_x, sheet.sprites_y
+                );
+                let span = span("SpritesX").or_else(|| span("SpritesY"));
+                report(Severity::Error, SPRITESHEET_MISMATCH, message, span);
+                continue;
+            }
+            if sheet.pixel_size <= 0 {
+                let message = format!(
+                    "Spritesheet {} has a pixel size of {}",
+                    sheet.name, sheet.pixel_size
+                );
+                report(
+                    Severity::Error,
+                    SPRITESHEET_MISMATCH,
+                    message,
+                    span("PixelSize"),
+                );
+            }
+            let Some((width, height)) = texture_size(&sheet.path) else {
+                let message = format!("Spritesheet {} cannot load {}", sheet.name, sheet.path);
+                report(Severity::Error, SPRITESHEET_MISMATCH, message, span("Path"));
+                continue;
+            };
+            let axes = [
+                ("SpritesX", "columns", sheet.sprites_x, "wide", width),
+                ("SpritesY", "rows", sheet.sprites_y, "high", height),
+            ];
+            for (field, cells, count, direction, pixels) in axes {
+                if pixels % count != 0 {
+                    let message = format!(
+                        "Spritesheet {} has {} {} but its image is {} pixels {}",
+                        sheet.name, count, cells, pixels, direction
+                    );
+                    report(
+                        Severity::Warning,
+                        SPRITESHEET_MISMATCH,
+                        message,
+                        span(field),
+                    );
+                }
+            }
+        }
+
+        let mut states: Vec<_> = self.states.values().collect();
+        states.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        for state in states {
+            let mut actions: Vec<_> = state.actions.values().flatten().collect();
+            actions.sort_by_key(|action| action.line_number);
+            for action in actions {
+                if action.args.len() != 2 {
+                    continue;
+                }
+                let (name, frame) = (&action.args[0], &action.args[1]);
+                if &*action.instruction != "Sprite" {
+                    continue;
+                }
+                let name = self.timeline_name(name);
+                let Some(sheet) = spritesheets.iter().find(|sheet| sheet.name == name) else {
+                    continue;
+                };
+                let Some(frame) = self.timeline_int(frame) else {
+                    continue;
+                };
+                let sprites = sheet.sprites_x * sheet.sprites_y;
+                if sprites > 0 && !(0..sprites).contains(&frame) {
+                    let message = format!(
+                        "State {} shows sprite {} of {}, which has {} sprites",
+                        state.name, frame, sheet.name, sprites
+                    );
+                    let span = Span {
+                        file: Some(state.origin.file.clone()),
+                        ..Span::line(action.line_number)
+                    };
+                    report(Severity::Error, SPRITE_OUT_OF_BOUNDS, message, Some(span));
+                }
+            }
+        }
+        diagnostics
+    }
+
+    /// Movement settings of the `PhysicsMovement` specblock
+    pub fn physics(&self) -> Result<PhysicsSpec, Vec<Diagnostic>> {
+        let mut reader = SpecReader::new(self, "PhysicsMovement");
+        let physics = PhysicsSpec {
+            gravity: reader.int_or("MOVE_Gravity", -200),
+            friction_ground: reader.int_or("MOVE_Friction_Ground", 100),
+            friction_air: reader.int_or("MOVE_Friction_Air", 2),
+            air_actions_max: reader.int_or("MOVE_AirActionsMax", 0),
+            walk_speed_forward: reader.int_or("MOVE_Walk_SpeedF", 1000),
+            walk_speed_back: reader.int_or("MOVE_Walk_SpeedB", -800),
+            jumpsquat_time: reader.int_or("MOVE_Jump_JumpsquatTime", 3),
+        };
+        reader.finish(physics)
+    }
+
+    /// Attack type settings of the `AttacksTypes` specblock
+    pub fn attack_data(&self) -> Result<AttackDataSpec, Vec<Diagnostic>> {
+        let mut reader = SpecReader::new(self, "AttacksTypes");
+        let min_damage = reader.int_or("ATTACK_MinDamage", 20);
+
+        let mut keys: Vec<&String> = reader.block.into_iter().flat_map(|b| b.keys()).collect();
+        keys.sort();
+
+        let mut types: BTreeMap<String, AttackTypeSpec> = BTreeMap::new();
+        for key in keys {
+            let Some((type_name, field)) = key
+                .strip_prefix(ATTACK_TYPE_PREFIX)
+                .and_then(|rest| rest.split_once('_'))
+            else {
+                continue;
+            };
+            let spec = types
+                .entry(type_name.to_string())
+                .or_insert_with(|| AttackTypeSpec {
+                    name: type_name.to_string(),
+                    ..Default::default()
+                });
+            match field {
+                "ProrationDamageStarter" => spec.proration_damage_starter = reader.int(key),
+                "ProrationDamage" => spec.proration_damage = reader.int(key),
+                "ProrationHitstunStarter" => spec.proration_hitstun_starter = reader.int(key),
+                "ProrationHitstun" => spec.proration_hitstun = reader.int(key),
+                "CanJumpCancelOnHit" => spec.can_jump_cancel_on_hit = reader.bool(key),
+                "CanJumpCancelOnBlock" => spec.can_jump_cancel_on_block = reader.bool(key),
+                _ => {
+                    if let Some(target) = field.strip_prefix("CanCancelInto") {
+                        if let Some(value) = reader.bool(key) {
+                            spec.cancels_into.insert(target.to_string(), value);
+                        }
+                    }
+                }
+            }
+        }
+
+        reader.finish(AttackDataSpec {
+            min_damage,
+            types: types.into_values().collect(),
+        })
+    }
+
+    /// Palettes of the `Graphics` specblock, numbered ones in order, then
+    /// the others by name
+    pub fn palettes(&self) -> Result<Vec<PaletteSpec>, Vec<Diagnostic>> {
+        let mut reader = SpecReader::new(self, "Graphics");
+
+        let mut names: Vec<&str> = reader
+            .block
+            .into_iter()
+            .flat_map(|block| block.keys())
+            .filter_map(|key| {
+                key.strip_prefix(PALETTE_PREFIX)?
+                    .split_once(STRUCT_SEPARATOR)
+            })
+            .map(|(name, _)| name)
+            .collect();
+        names.sort_by_key(|name| (name.parse::<i64>().map_err(|_| *name), *name));
+        names.dedup();
+
+        let mut palettes = Vec::new();
+        for name in names {
+            let key =
+                |field: &str| format!("{}{}{}{}", PALETTE_PREFIX, name, STRUCT_SEPARATOR, field);
+            let path = |field: &str| reader.raw(&key(field)).map(str::to_string);
+            palettes.push(PaletteSpec {
+                name: name.to_string(),
+                display_name: path("DisplayName").unwrap_or_else(|| name.to_string()),
+                sprite_palette_path: path("SpritePalettePath"),
+                model_path: path("ModelPath"),
+                extra: reader.int_or(&key("Extra"), 0),
+                colors: reader.colors(&key("Colors")),
+            });
+        }
+        reader.finish(palettes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Rgba;
+    use crate::parser::CastagneParser;
+
+    fn parse(source: &str) -> crate::parser::ParsedCharacter {
+        CastagneParser::new()
+            .create_full_character_from_source("test.casp", source)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_spritesheets_with_defaults() {
+        let character = parse(
+            ":Graphics:
+GRAPHICS_Scale: 3000
+GRAPHICS_SPRITESHEET_Stickman___Path: res://stickman.png
+GRAPHICS_SPRITESHEET_Stickman___SpritesX: 16
+GRAPHICS_SPRITESHEET_Stickman___SpritesY: 4
+GRAPHICS_SPRITESHEET_Effects___OriginY: 6
+",
+        );
+
+        let spritesheets = character.spritesheets().unwrap();
+
+        assert_eq!(spritesheets.len(), 2);
+        assert_eq!(spritesheets[0].name, "Effects");
+        assert_eq!(spritesheets[0].path, "res://");
+        assert_eq!(spritesheets[0].origin_y, 6);
+        assert_eq!(spritesheets[1].path, "res://stickman.png");
+        assert_eq!(
+            (spritesheets[1].sprites_x, spritesheets[1].sprites_y),
+            (16, 4)
+        );
+        assert_eq!(spritesheets[1].pixel_size, 100000);
+    }
+
+    #[test]
+    fn test_validate_spritesheets() {
+        let character = parse(
+            ":Graphics:
+GRAPHICS_SPRITESHEET_Stickman___Path: res://stickman.png
+GRAPHICS_SPRITESHEET_Stickman___SpritesX: 16
+GRAPHICS_SPRITESHEET_Stickman___SpritesY: 4
+GRAPHICS_SPRITESHEET_Effects___Path: res://effects.png
+GRAPHICS_SPRITESHEET_Effects___SpritesX: 3
+GRAPHICS_SPRITESHEET_Missing___Path: res://missing.png
+:Idle:
+---Action:
+Sprite(Stickman, 63)
+Sprite(Stickman, 64)
+Sprite(Effects, 1)
+",
+        );
+
+        let diagnostics = character.validate_spritesheets(|path| match path {
+            "res://stickman.png" => Some((1024, 256)),
+            "res://effects.png" => Some((100, 40)),
+            _ => None,
+        });
+
+        let found: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (super::SPRITESHEET_MISMATCH, 6),
+                (super::SPRITESHEET_MISMATCH, 7),
+                (super::SPRITE_OUT_OF_BOUNDS, 11)
+            ]
+        );
+        assert!(diagnostics[0].message.contains("3 columns"));
+        assert!(diagnostics[1]
+            .message
+            .contains("cannot load res://missing.png"));
+        let fitting = character.validate_spritesheets(|_| Some((960, 240)));
+        assert_eq!(fitting.len(), 1);
+    }
+
+    #[test]
+    fn test_physics_reports_invalid_values() {
+        let character = parse(":PhysicsMovement:\nMOVE_Gravity: -150\nMOVE_Walk_SpeedF: fast\n");
+
+        let diagnostics = character.physics().unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, super::INVALID_SPEC_VALUE);
+        assert!(diagnostics[0].message.contains("MOVE_Walk_SpeedF"));
+
+        let defaults = parse(":Idle:\n---Init:\n").physics().unwrap();
+        assert_eq!(defaults.gravity, -200);
+    }
+
+    #[test]
+    fn test_attack_types() {
+        let character = parse(
+            ":AttacksTypes:
+ATTACK_MinDamage: 30
+ATTACK_Light_ProrationDamage: 900
+ATTACK_Light_CanCancelIntoMediums: true
+ATTACK_Light_CanCancelIntoLights: false
+ATTACK_Heavy_CanJumpCancelOnHit: 1
+",
+        );
+
+        let data = character.attack_data().unwrap();
+
+        assert_eq!(data.min_damage, 30);
+        let names: Vec<&str> = data.types.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Heavy", "Light"]);
+        assert_eq!(data.types[0].can_jump_cancel_on_hit, Some(true));
+        assert_eq!(data.types[1].proration_damage, Some(900));
+        assert_eq!(data.types[1].proration_hitstun, None);
+        assert!(data.types[1].cancels_into["Mediums"]);
+        assert!(!data.types[1].cancels_into["Lights"]);
+    }
+
+    #[test]
+    fn test_palettes() {
+        let source = ":Graphics:
+GRAPHICS_SPRITE_PALETTE_10___DisplayName: Shadow
+GRAPHICS_SPRITE_PALETTE_2___DisplayName: Green
+GRAPHICS_SPRITE_PALETTE_2___Colors: \"#000\", rgb(0, 255, 0)
+GRAPHICS_SPRITE_PALETTE_2___Extra: 1
+GRAPHICS_SPRITE_PALETTE_0___SpritePalettePath: res://palettes/blue.png
+GRAPHICS_SPRITE_PALETTE_Event___ModelPath: res://event.tscn
+";
+        let palettes = parse(source).palettes().unwrap();
+
+        let names: Vec<&str> = palettes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["0", "2", "10", "Event"]);
+        assert_eq!(palettes[0].display_name, "0");
+        assert_eq!(
+            palettes[0].sprite_palette_path.as_deref(),
+            Some("res://palettes/blue.png")
+        );
+        assert_eq!(palettes[1].display_name, "Green");
+        assert_eq!(
+            palettes[1].colors,
+            vec![Rgba::new(0, 0, 0, 255), Rgba::new(0, 255, 0, 255)]
+        );
+        assert_eq!(palettes[1].extra, 1);
+        assert!(palettes[2].colors.is_empty());
+        assert_eq!(palettes[3].model_path.as_deref(), Some("res://event.tscn"));
+
+        let broken = parse(":Graphics:\nGRAPHICS_SPRITE_PALETTE_1___Colors: \"#000, teal\"\n");
+        let errors = broken.palettes().unwrap_err();
+        assert!(errors[0].message.contains("a list of colors"));
+    }
+}
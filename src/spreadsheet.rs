@@ -0,0 +1,411 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Spreadsheet - CSV export and import of variables and frame data
+//!
+//! Designers balance in spreadsheets: `variables_csv` and `attacks_csv`
+//! write one row per variable or attack, and the `apply_*` functions read
+//! an edited table back onto a `ParsedCharacter`. Empty cells and cells
+//! equal to the current value leave the character untouched. Problems
+//! with individual rows are returned as diagnostics and the other rows
+//! still apply.
+
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::frame_data::{attacks, AttackData};
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState};
+
+pub const VARIABLE_COLUMNS: &[&str] = &["Name", "Type", "Mutability", "Value"];
+pub const ATTACK_COLUMNS: &[&str] = &[
+    "State",
+    "Input",
+    "Type",
+    "Damage",
+    "Duration",
+    "AdvantageHit",
+    "AdvantageBlock",
+];
+
+/// Every variable with its type and default, sorted by name
+pub fn variables_csv(character: &ParsedCharacter) -> String {
+    let mut names: Vec<&str> = character.variables.keys().map(|k| k.as_str()).collect();
+    names.sort();
+
+    let mut rows = vec![VARIABLE_COLUMNS.iter().map(|c| c.to_string()).collect()];
+    for name in names {
+        let variable = &character.variables[name];
+        rows.push(vec![
+            variable.name.to_string(),
+            format!("{:?}", variable.var_type),
+            format!("{:?}", variable.mutability),
+            variable.value.clone(),
+        ]);
+    }
+    write_csv(&rows)
+}
+
+/// Every attack with its frame data, sorted by state name
+pub fn attacks_csv(character: &ParsedCharacter) -> String {
+    let field = |value: &Option<String>| value.clone().unwrap_or_default();
+
+    let mut rows = vec![ATTACK_COLUMNS.iter().map(|c| c.to_string()).collect()];
+    for attack in attacks(character) {
+        rows.push(vec![
+            attack.state.clone(),
+            field(&attack.input),
+            field(&attack.attack_type),
+            field(&attack.damage),
+            field(&attack.duration),
+            field(&attack.advantage_hit),
+            field(&attack.advantage_block),
+        ]);
+    }
+    write_csv(&rows)
+}
+
+/// Apply edited variable values; type and mutability columns are informative
+pub fn apply_variables_csv(character: &mut ParsedCharacter, csv: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (line, row) in rows(csv, VARIABLE_COLUMNS, &mut diagnostics) {
+        let (name, value) = (&row[0], &row[3]);
+        match character.variables.get_mut(name.as_str()) {
+            Some(variable) => {
+                if !value.is_empty() {
+                    variable.value = value.clone();
+                }
+            }
+            None => diagnostics.push(row_error(
+                "csv-unknown-variable",
+                format!("Unknown variable {}", name),
+                line,
+            )),
+        }
+    }
+    diagnostics
+}
+
+/// Apply edited frame data onto the attack instructions of each state
+///
+/// Values are written into the existing instruction; a missing one is
+/// added right after the state's `AttackRegister`. An Input on a state
+/// registered with `AttackRegisterNoNotation` is reported, not applied.
+pub fn apply_attacks_csv(character: &mut ParsedCharacter, csv: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (line, row) in rows(csv, ATTACK_COLUMNS, &mut diagnostics) {
+        let state = match character.states.get_mut(row[0].as_str()) {
+            Some(state) => state,
+            None => {
+                diagnostics.push(row_error(
+                    "csv-unknown-state",
+                    format!("Unknown state {}", row[0]),
+                    line,
+                ));
+                continue;
+            }
+        };
+        let Some(current) = AttackData::from_state(state) else {
+            diagnostics.push(row_error(
+                "csv-unknown-state",
+                format!("State {} registers no attack", row[0]),
+                line,
+            ));
+            continue;
+        };
+
+        let changed = |value: &String, current: &Option<String>| {
+            !value.is_empty() && current.as_ref() != Some(value)
+        };
+
+        if changed(&row[1], &current.input) && !set_arg(state, "AttackRegister", 1, &row[1]) {
+            diagnostics.push(row_error(
+                "csv-invalid-cell",
+                format!(
+                    "State {} has no AttackRegister to take the Input {}",
+                    row[0], row[1]
+                ),
+                line,
+            ));
+        }
+        if changed(&row[2], &current.attack_type) {
+            let register = match find_action(state, "AttackRegister") {
+                Some(_) => "AttackRegister",
+                None => "AttackRegisterNoNotation",
+            };
+            set_arg(state, register, 0, &row[2]);
+        }
+        if changed(&row[3], &current.damage) {
+            set_arg(state, "AttackDamage", 0, &row[3]);
+        }
+        if changed(&row[4], &current.duration) {
+            set_arg(state, "AttackDuration", 0, &row[4]);
+        }
+        let (hit, block) = match find_action(state, "AttackFrameAdvantage") {
+            Some(_) => (("AttackFrameAdvantage", 0), ("AttackFrameAdvantage", 1)),
+            None => (
+                ("AttackFrameAdvantageHit", 0),
+                ("AttackFrameAdvantageBlock", 0),
+            ),
+        };
+        if changed(&row[5], &current.advantage_hit) {
+            set_arg(state, hit.0, hit.1, &row[5]);
+        }
+        if changed(&row[6], &current.advantage_block) {
+            set_arg(state, block.0, block.1, &row[6]);
+        }
+    }
+    diagnostics
+}
+
+fn find_action<'a>(state: &'a mut ParsedState, instruction: &str) -> Option<&'a mut ParsedAction> {
+    state
+        .actions
+        .values_mut()
+        .flatten()
+        .filter(|action| action.instruction == instruction)
+        .min_by_key(|action| action.line_number)
+}
+
+/// Set an argument of the first `instruction`, adding the instruction if needed
+///
+/// An instruction is only added for its first argument, as the ones before
+/// `index` would have no value: returns false when nothing was set.
+fn set_arg(state: &mut ParsedState, instruction: &str, index: usize, value: &str) -> bool {
+    if let Some(action) = find_action(state, instruction) {
+        let mut args = action.args.to_vec();
+        if args.len() <= index {
+            // A one-argument AttackFrameAdvantage applies to both hit and block
+            let filler = args.first().cloned().unwrap_or_default();
+            args.resize(index + 1, filler);
+        }
+        args[index] = value.to_string();
+        action.args = args.into();
+        return true;
+    }
+    if index > 0 {
+        return false;
+    }
+
+    let register = state.actions.iter_mut().find_map(|(phase, actions)| {
+        actions
+            .iter()
+            .position(|action| action.instruction.starts_with("AttackRegister"))
+            .map(|position| (phase.clone(), position))
+    });
+    if let Some((phase, position)) = register {
+        let actions = state.actions.get_mut(&phase).unwrap();
+        let line_number = actions[position].line_number;
+        let frames = actions[position].frames.clone();
+        actions.insert(
+            position + 1,
+            ParsedAction {
+                instruction: instruction.into(),
+                args: vec![value.to_string()].into(),
+                line_number,
+                frames,
+                defaulted: Vec::new(),
+                flags: Vec::new(),
+            },
+        );
+        return true;
+    }
+    false
+}
+
+fn row_error(code: &str, message: String, line: usize) -> Diagnostic {
+    Diagnostic::new(code, Severity::Error, message).with_span(Span::line(line))
+}
+
+/// Data rows with their 1-indexed line, after checking the header
+fn rows(
+    csv: &str,
+    columns: &[&str],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<(usize, Vec<String>)> {
+    let mut records = read_csv(csv).into_iter();
+    match records.next() {
+        Some((_, header)) if header == columns => {}
+        _ => {
+            diagnostics.push(row_error(
+                "csv-malformed-row",
+                format!("Expected header {}", columns.join(",")),
+                1,
+            ));
+            return Vec::new();
+        }
+    }
+
+    records
+        .filter(|(line, row)| {
+            if row.len() == columns.len() {
+                return true;
+            }
+            diagnostics.push(row_error(
+                "csv-malformed-row",
+                format!("Expected {} cells, got {}", columns.len(), row.len()),
+                *line,
+            ));
+            false
+        })
+        .collect()
+}
+
+fn write_csv(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|cell| {
+                if cell.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", cell.replace('"', "\"\""))
+                } else {
+                    cell.clone()
+                }
+            })
+            .collect();
+        out.push_str(&cells.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Parse CSV with quoted cells, skipping blank lines
+fn read_csv(csv: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut cell)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut cell));
+                if row.len() > 1 || !row[0].is_empty() {
+                    records.push((row_line, std::mem::take(&mut row)));
+                }
+                row.clear();
+                line += 1;
+                row_line = line;
+            }
+            '\n' => {
+                cell.push(ch);
+                line += 1;
+            }
+            _ => cell.push(ch),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        records.push((row_line, row));
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    const SOURCE: &str = ":Character:
+Name: Tester
+:Variables:
+var Health(Int): 10000
+var Gravity(Vec2): (0, -5)
+def MaxMeter: 100
+:5A:
+---Init:
+AttackRegister(Light)
+AttackDamage(300)
+AttackFrameAdvantage(2)
+";
+
+    fn character() -> ParsedCharacter {
+        CastagneParser::new()
+            .create_full_character_from_source("test.casp", SOURCE)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_export_tables() {
+        let character = character();
+
+        assert_eq!(
+            variables_csv(&character),
+            "Name,Type,Mutability,Value\nGravity,Vec2,Variable,\"(0, -5)\"\nHealth,Int,Variable,10000\nMaxMeter,Var,Define,100\n"
+        );
+        assert_eq!(
+            attacks_csv(&character),
+            "State,Input,Type,Damage,Duration,AdvantageHit,AdvantageBlock\n5A,5A,Light,300,,2,2\n"
+        );
+    }
+
+    #[test]
+    fn test_import_roundtrip_and_edits() {
+        let mut character = character();
+        let unchanged = attacks_csv(&character);
+        assert!(apply_attacks_csv(&mut character, &unchanged).is_empty());
+        assert_eq!(attacks_csv(&character), unchanged);
+
+        let diagnostics = apply_attacks_csv(
+            &mut character,
+            "State,Input,Type,Damage,Duration,AdvantageHit,AdvantageBlock\n5A,5A,Light,350,22,,-1\n",
+        );
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            attacks_csv(&character),
+            "State,Input,Type,Damage,Duration,AdvantageHit,AdvantageBlock\n5A,5A,Light,350,22,2,-1\n"
+        );
+        let duration = &character.states["5A"].actions["Init"][1];
+        assert_eq!(duration.instruction, "AttackDuration");
+
+        let diagnostics = apply_variables_csv(
+            &mut character,
+            "Name,Type,Mutability,Value\n\"Gravity\",Vec2,Variable,\"(0, -7)\"\nHealht,Int,Variable,1\n",
+        );
+        assert_eq!(character.variables["Gravity"].value, "(0, -7)");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "csv-unknown-variable");
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_import_rejects_input_without_notation() {
+        let mut character = CastagneParser::new()
+            .create_full_character_from_source(
+                "test.casp",
+                ":Character:\nName: Tester\n:2B:\n---Init:\nAttackRegisterNoNotation(Low)\n",
+            )
+            .unwrap();
+
+        let diagnostics = apply_attacks_csv(
+            &mut character,
+            "State,Input,Type,Damage,Duration,AdvantageHit,AdvantageBlock\n2B,3B,Medium,,,,\n",
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "csv-invalid-cell");
+        let init = &character.states["2B"].actions["Init"];
+        assert_eq!(init.len(), 1);
+        assert_eq!(init[0].instruction, "AttackRegisterNoNotation");
+        assert_eq!(init[0].args, ["Medium"]);
+    }
+
+    #[test]
+    fn test_import_rejects_wrong_header() {
+        let mut character = character();
+
+        let diagnostics = apply_variables_csv(&mut character, "Name,Value\nHealth,1\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(character.variables["Health"].value, "10000");
+    }
+}
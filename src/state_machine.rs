@@ -0,0 +1,376 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! State Machine - The state flow as Godot AnimationTree data
+//!
+//! Godot's `AnimationNodeStateMachine` shows states as nodes linked by
+//! transitions, each advancing on a condition that can be toggled while
+//! previewing. `state_machine` turns every `Transition` of a character into
+//! such a link, following calls into helper states, with the branches it
+//! is under as its condition: `F5 and not LFlag`. States are laid out in
+//! columns by distance from `Init`, so the imported tree reads left to
+//! right.
+//!
+//! Godot keeps one transition per pair of states, so every `Transition`
+//! from a state to another makes a single one, advancing without a
+//! condition when one of them is unconditional. Subentity and AI states
+//! run in other state machines and are left out.
+
+use crate::ai::AI_STATE_PREFIX;
+use crate::frame_data::is_branch;
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState, StateType};
+use crate::string_literal;
+use crate::subentities::ENTITY_SEPARATOR;
+use godot::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+
+/// State every character starts in
+const ENTRY_STATE: &str = "Init";
+
+/// Distance between two columns and two rows of the layout, in pixels
+const COLUMN_WIDTH: f32 = 220.0;
+const ROW_HEIGHT: f32 = 100.0;
+
+/// A state of the machine
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMachineNode {
+    pub name: String,
+    /// Position in the graph editor
+    pub position: (f32, f32),
+}
+
+/// Every `Transition` from one state to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateMachineTransition {
+    pub from: String,
+    pub to: String,
+    /// Branches around each `Transition`, outermost first
+    pub conditions: Vec<Vec<String>>,
+    /// Line of each `Transition`
+    pub lines: Vec<usize>,
+}
+
+impl StateMachineTransition {
+    /// Condition the transition advances on, empty when it always can
+    pub fn advance_condition(&self) -> String {
+        if self.conditions.iter().any(Vec::is_empty) {
+            return String::new();
+        }
+        let alternatives: Vec<String> = self
+            .conditions
+            .iter()
+            .map(|branches| branches.join(" and "))
+            .collect();
+        // Conditions are parameter paths in the AnimationTree
+        alternatives.join(" or ").replace('/', "_")
+    }
+}
+
+/// States and transitions of a character, as an `AnimationNodeStateMachine`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateMachine {
+    /// State the machine starts in, if the character has one
+    pub start: Option<String>,
+    /// States, by column then name
+    pub nodes: Vec<StateMachineNode>,
+    /// Transitions, by source then target
+    pub transitions: Vec<StateMachineTransition>,
+}
+
+impl StateMachine {
+    pub fn node(&self, name: &str) -> Option<&StateMachineNode> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    pub fn transition(&self, from: &str, to: &str) -> Option<&StateMachineTransition> {
+        self.transitions
+            .iter()
+            .find(|transition| transition.from == from && transition.to == to)
+    }
+
+    /// The machine as a dictionary with `start`, `states` (`name`,
+    /// `position`) and `transitions` (`from`, `to`, `advance_condition`,
+    /// `lines`), for a script building an `AnimationNodeStateMachine`
+    pub fn to_dictionary(&self) -> VarDictionary {
+        let states: VarArray = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut row = VarDictionary::new();
+                row.set("name", node.name.as_str());
+                row.set("position", Vector2::new(node.position.0, node.position.1));
+                row.to_variant()
+            })
+            .collect();
+        let transitions: VarArray = self
+            .transitions
+            .iter()
+            .map(|transition| {
+                let lines: PackedInt64Array =
+                    transition.lines.iter().map(|line| *line as i64).collect();
+                let mut row = VarDictionary::new();
+                row.set("from", transition.from.as_str());
+                row.set("to", transition.to.as_str());
+                row.set("advance_condition", transition.advance_condition().as_str());
+                row.set("lines", lines);
+                row.to_variant()
+            })
+            .collect();
+
+        let mut dictionary = VarDictionary::new();
+        dictionary.set("start", self.start.as_deref().unwrap_or_default());
+        dictionary.set("states", states);
+        dictionary.set("transitions", transitions);
+        dictionary
+    }
+}
+
+impl ParsedCharacter {
+    /// The state flow of the character, see the module documentation
+    pub fn state_machine(&self) -> StateMachine {
+        let mut names: Vec<&str> = self
+            .states
+            .values()
+            .filter(|state| is_node(state))
+            .map(|state| state.name.as_str())
+            .collect();
+        names.sort_unstable();
+
+        let mut found: BTreeMap<(String, String), StateMachineTransition> = BTreeMap::new();
+        for name in &names {
+            let mut links = Vec::new();
+            self.collect_transitions(
+                name,
+                &mut Vec::new(),
+                &mut vec![name.to_string()],
+                &mut links,
+            );
+            for (to, conditions, line) in links {
+                if to == *name || !names.contains(&to.as_str()) {
+                    continue;
+                }
+                let transition = found
+                    .entry((name.to_string(), to.clone()))
+                    .or_insert_with(|| StateMachineTransition {
+                        from: name.to_string(),
+                        to,
+                        conditions: Vec::new(),
+                        lines: Vec::new(),
+                    });
+                transition.conditions.push(conditions);
+                transition.lines.push(line);
+            }
+        }
+        let transitions: Vec<StateMachineTransition> = found.into_values().collect();
+
+        let start = names
+            .contains(&ENTRY_STATE)
+            .then(|| ENTRY_STATE.to_string());
+        let nodes = layout(&names, start.as_deref(), &transitions);
+        StateMachine {
+            start,
+            nodes,
+            transitions,
+        }
+    }
+
+    /// Targets of the `Transition`s a state runs, with their branches and
+    /// line, following calls; `visited` holds the states being walked
+    fn collect_transitions(
+        &self,
+        name: &str,
+        branches: &mut Vec<String>,
+        visited: &mut Vec<String>,
+        links: &mut Vec<(String, Vec<String>, usize)>,
+    ) {
+        let Some(state) = self.states.get(name) else {
+            return;
+        };
+        let mut phases: Vec<&Vec<ParsedAction>> = state.actions.values().collect();
+        phases.sort_by_key(|actions| actions.first().map(|action| action.line_number));
+
+        for actions in phases {
+            // Branches of the caller stay open through the whole phase
+            let outer = branches.len();
+            for action in actions {
+                let instruction = &*action.instruction;
+                if instruction.eq_ignore_ascii_case("endif") {
+                    if branches.len() > outer {
+                        branches.pop();
+                    }
+                } else if instruction.eq_ignore_ascii_case("else") {
+                    if let Some(branch) = branches.get_mut(outer..).and_then(<[_]>::last_mut) {
+                        *branch = match branch.strip_prefix("not ") {
+                            Some(negated) => negated.to_string(),
+                            None => format!("not {}", branch),
+                        };
+                    }
+                } else if is_branch(instruction) {
+                    branches.push(branch_label(action));
+                } else if instruction.starts_with("Transition") {
+                    if let Some(target) = action.args.first() {
+                        links.push((state_name(target), branches.clone(), action.line_number));
+                    }
+                } else if let Some(called) = self.called_state(name, action) {
+                    if !visited.contains(&called) {
+                        visited.push(called.clone());
+                        self.collect_transitions(&called, branches, visited, links);
+                        visited.pop();
+                    }
+                }
+            }
+            branches.truncate(outer);
+        }
+    }
+}
+
+/// Whether a state is a node of the main entity's machine
+fn is_node(state: &ParsedState) -> bool {
+    matches!(state.state_type, StateType::Normal | StateType::Special)
+        && !state.name.contains(ENTITY_SEPARATOR)
+        && !state.name.starts_with(AI_STATE_PREFIX)
+}
+
+/// A branch as written, without its colon: `LFlag`, `IfLt(Health, 1)`
+fn branch_label(action: &ParsedAction) -> String {
+    let name = action.instruction.trim_end_matches(':');
+    match action.args.is_empty() {
+        true => name.to_string(),
+        false => format!("{}({})", name, action.args.join(", ")),
+    }
+}
+
+/// State named by a `Transition` argument, quoted or not
+fn state_name(arg: &str) -> String {
+    let arg = arg.trim();
+    match string_literal::decode(arg) {
+        Some(Ok(decoded)) => decoded,
+        _ => arg.to_string(),
+    }
+}
+
+/// Positions of the states: columns by distance from the start, then from
+/// the first state not reached yet
+fn layout(
+    names: &[&str],
+    start: Option<&str>,
+    transitions: &[StateMachineTransition],
+) -> Vec<StateMachineNode> {
+    let mut columns: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut first_column = 0;
+    let roots = start.into_iter().chain(names.iter().copied());
+    for root in roots {
+        if columns.contains_key(root) {
+            continue;
+        }
+        let mut queue = VecDeque::from([(root, first_column)]);
+        columns.insert(root, first_column);
+        while let Some((name, column)) = queue.pop_front() {
+            first_column = first_column.max(column + 1);
+            let targets = transitions.iter().filter(|t| t.from == name);
+            for transition in targets {
+                let target = transition.to.as_str();
+                if !columns.contains_key(target) {
+                    columns.insert(target, column + 1);
+                    queue.push_back((target, column + 1));
+                }
+            }
+        }
+    }
+
+    let mut ordered: Vec<(usize, &str)> = columns.into_iter().map(|(n, c)| (c, n)).collect();
+    ordered.sort_unstable();
+    let mut rows: BTreeMap<usize, usize> = BTreeMap::new();
+    ordered
+        .into_iter()
+        .map(|(column, name)| {
+            let row = rows.entry(column).or_default();
+            let position = (column as f32 * COLUMN_WIDTH, *row as f32 * ROW_HEIGHT);
+            *row += 1;
+            StateMachineNode {
+                name: name.to_string(),
+                position,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_state_machine() {
+        let source = ":Character:
+Name: Ryu
+:Init:
+---Init:
+Transition(Stand)
+:Stand:
+---Action:
+Call(Movement)
+LJump:
+Transition(\"Jump\")
+endif
+:Movement(Helper):
+---Action:
+IfLt(Health, 1):
+Transition(KO)
+else
+Transition(Walk)
+endif
+:Walk:
+---Action:
+F5:
+Transition(Stand)
+endif
+Transition(Stand)
+Transition(Walk)
+:Jump:
+---Action:
+Transition(Nowhere)
+:KO:
+:Lonely:
+:AI-Init:
+---AI:
+Transition(Stand)
+:Fireball---Idle:
+---Action:
+Transition(Stand)
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        let machine = character.state_machine();
+
+        assert_eq!(machine.start.as_deref(), Some("Init"));
+        let names: Vec<&str> = machine.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["Init", "Stand", "Jump", "KO", "Walk", "Lonely"]);
+        assert_eq!(machine.node("Init").unwrap().position, (0.0, 0.0));
+        assert_eq!(machine.node("Jump").unwrap().position, (440.0, 0.0));
+        assert_eq!(machine.node("Walk").unwrap().position, (440.0, 200.0));
+        assert_eq!(machine.node("Lonely").unwrap().position, (660.0, 0.0));
+
+        let condition = |from: &str, to: &str| {
+            machine
+                .transition(from, to)
+                .map(|transition| transition.advance_condition())
+        };
+        assert_eq!(condition("Init", "Stand").as_deref(), Some(""));
+        assert_eq!(condition("Stand", "Jump").as_deref(), Some("LJump"));
+        assert_eq!(condition("Stand", "KO").as_deref(), Some("IfLt(Health, 1)"));
+        assert_eq!(
+            condition("Stand", "Walk").as_deref(),
+            Some("not IfLt(Health, 1)")
+        );
+        // An unconditional transition wins
+        let walk = machine.transition("Walk", "Stand").unwrap();
+        assert_eq!(walk.conditions, [vec!["F5".to_string()], vec![]]);
+        assert_eq!(walk.advance_condition(), "");
+        // Helpers, subentities, AI states, self and unknown targets are left out
+        assert_eq!(machine.transitions.len(), 5);
+        assert!(machine.node("Movement").is_none());
+    }
+}
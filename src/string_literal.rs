@@ -0,0 +1,263 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! String Literal - Escape sequences and multi-line strings
+//!
+//! Quoted values understand `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\'` and
+//! `\uXXXX`. Dialogue and descriptions can span lines with `"""`: such a
+//! string is folded onto its first line as a regular quoted literal, and
+//! the lines it covered are left blank so line numbers don't move.
+
+const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// Decode a quoted literal, `None` if `text` isn't exactly one string
+pub fn decode(text: &str) -> Option<Result<String, String>> {
+    let text = text.trim();
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let body = text.get(1..text.len() - 1).filter(|_| text.len() > 1)?;
+    if !text.ends_with(quote) {
+        return None;
+    }
+
+    let mut result = String::new();
+    let mut chars = body.chars();
+    while let Some(ch) = chars.next() {
+        if ch == quote {
+            // `"a" + "b"` starts and ends with quotes but isn't one literal
+            return None;
+        }
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        let decoded = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let valid = hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit());
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) if valid => c,
+                    _ => return Some(Err(format!("Invalid unicode escape \\u{}", hex))),
+                }
+            }
+            Some(c) => return Some(Err(format!("Unknown escape sequence \\{}", c))),
+            None => return Some(Err("Unfinished escape sequence".to_string())),
+        };
+        result.push(decoded);
+    }
+    Some(Ok(result))
+}
+
+/// Write `text` as a double-quoted literal `decode` reads back as is
+pub fn encode(text: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Fold `"""` strings into single-line literals
+///
+/// Returns the index of each line opening a string that never closes.
+pub fn join_multiline(lines: &mut [String]) -> Vec<usize> {
+    let mut unterminated = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(start) = find_opening(&lines[i]) else {
+            i += 1;
+            continue;
+        };
+
+        let head = lines[i][..start].to_string();
+        let mut rest = lines[i][start + TRIPLE_QUOTE.len()..].to_string();
+        let mut body = Vec::new();
+        let mut last = i;
+        let closed = loop {
+            if let Some(end) = find_closing(&rest) {
+                body.push(rest[..end].to_string());
+                break Some(rest[end + TRIPLE_QUOTE.len()..].to_string());
+            }
+            body.push(std::mem::take(&mut rest));
+            last += 1;
+            match lines.get_mut(last) {
+                Some(line) => rest = std::mem::take(line),
+                None => break None,
+            }
+        };
+
+        match closed {
+            // Scan the same line again, the tail may open another string
+            Some(tail) => lines[i] = format!("{}{}{}", head, quote(&dedent(body)), tail),
+            None => {
+                lines[i] = head;
+                unterminated.push(i);
+                i += 1;
+            }
+        }
+    }
+    unterminated
+}
+
+/// Byte offset of a `"""` outside strings and comments
+fn find_opening(line: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escape_next = false;
+    for (pos, ch) in line.char_indices() {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_string => escape_next = true,
+            '"' if !in_string && line[pos..].starts_with(TRIPLE_QUOTE) => return Some(pos),
+            '"' => in_string = !in_string,
+            '#' if !in_string => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Byte offset of the `"""` closing a string, skipping escaped quotes
+fn find_closing(text: &str) -> Option<usize> {
+    let mut escape_next = false;
+    for (pos, ch) in text.char_indices() {
+        if escape_next {
+            escape_next = false;
+        } else if ch == '\\' {
+            escape_next = true;
+        } else if text[pos..].starts_with(TRIPLE_QUOTE) {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+/// Drop the blank lines next to the delimiters and the common indentation
+fn dedent(mut body: Vec<String>) -> String {
+    if body.len() > 1 && body.last().is_some_and(|line| line.trim().is_empty()) {
+        body.pop();
+    }
+    let indented = if body[0].trim().is_empty() && body.len() > 1 {
+        body.remove(0);
+        0
+    } else {
+        1
+    };
+
+    let indent = body[indented..]
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    for line in body.iter_mut().skip(indented) {
+        *line = line.get(indent..).unwrap_or("").to_string();
+    }
+    body.join("\n")
+}
+
+/// Write `text` as a one-line quoted literal, keeping its escapes as is
+fn quote(text: &str) -> String {
+    let mut result = String::from("\"");
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                // A backslash at the end of a line joins it with the next
+                Some('\n') => {}
+                Some(next) => {
+                    result.push(ch);
+                    result.push(next);
+                }
+                None => result.push(ch),
+            },
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            _ => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_escapes() {
+        assert_eq!(
+            decode(r#""Line1\nCol\t\"x\" \u00e9\\""#),
+            Some(Ok("Line1\nCol\t\"x\" é\\".to_string()))
+        );
+        assert_eq!(decode("'it\\'s'"), Some(Ok("it's".to_string())));
+        assert_eq!(decode("\"\""), Some(Ok(String::new())));
+        assert_eq!(decode("Hello"), None);
+        assert_eq!(decode("\"a\" + \"b\""), None);
+        assert_eq!(
+            decode(r#""bad \q""#),
+            Some(Err("Unknown escape sequence \\q".to_string()))
+        );
+        assert_eq!(
+            decode(r#""\u12""#),
+            Some(Err("Invalid unicode escape \\u12".to_string()))
+        );
+
+        let text = "Say \"hi\"\n\t# C:\\ \u{7}é";
+        assert_eq!(encode(text), r#""Say \"hi\"\n\t# C:\\ \u0007é""#);
+        assert_eq!(decode(&encode(text)), Some(Ok(text.to_string())));
+    }
+
+    #[test]
+    fn test_join_multiline() {
+        let mut lines: Vec<String> = [
+            "Description: \"\"\"",
+            "    A wandering \"swordsman\".",
+            "      # Not a comment",
+            "    Likes tea.",
+            "    \"\"\"",
+            "Say(\"\"\"Hi",
+            "there\"\"\", 2) # \"\"\" ignored",
+            "Poem(\"\"\"one \\",
+            "two\"\"\")",
+            "Open: \"\"\"never closed",
+            "last",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+        let unterminated = join_multiline(&mut lines);
+
+        assert_eq!(
+            lines[0],
+            r#"Description: "A wandering \"swordsman\".\n  # Not a comment\nLikes tea.""#
+        );
+        assert!(lines[1..5].iter().all(|line| line.is_empty()));
+        assert_eq!(lines[5], r#"Say("Hi\nthere", 2) # """ ignored"#);
+        assert_eq!(lines[7], r#"Poem("one two")"#);
+        assert_eq!(unterminated, vec![9]);
+        assert_eq!(lines[9], "Open: ");
+        assert_eq!(
+            decode(&lines[0][13..]).unwrap().unwrap(),
+            "A wandering \"swordsman\".\n  # Not a comment\nLikes tea."
+        );
+    }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Subentities - Projectiles and other entities a character spawns
+//!
+//! A subentity is declared with a `:Fireball---Subentity:` metadata block
+//! and has its own `:Fireball---Action:` states, inheriting those of its
+//! skeleton entity (`Base` unless it sets `Skeleton: none`). States spawn
+//! one with `CreateEntity`, `CreateSubentity` or `CreateProjectile`, which
+//! the parser checks name a subentity or a helper state.
+//!
+//! The engine instantiates subentities in the middle of a match; knowing
+//! what a state may spawn, through the states it calls and what those
+//! spawn, lets it fill its pools when the character is loaded instead.
+
+use crate::parser::{ParsedAction, ParsedCharacter, StateType};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Instructions spawning the entity named by their first argument
+pub const SPAWN_INSTRUCTIONS: &[&str] = &["CreateEntity", "CreateSubentity", "CreateProjectile"];
+
+/// Separator between an entity and the name of one of its states
+pub(crate) const ENTITY_SEPARATOR: &str = "---";
+
+/// Entity a subentity inherits from when it doesn't set `Skeleton`
+const DEFAULT_ENTITY_SKELETON: &str = "Base";
+
+/// Name an action spawns, if it is a spawn written with a name rather
+/// than a number or a string
+pub(crate) fn spawned_name(action: &ParsedAction) -> Option<&str> {
+    if !SPAWN_INSTRUCTIONS.contains(&&*action.instruction) {
+        return None;
+    }
+    let name = action.args.first()?.trim();
+    let mut chars = name.chars();
+    let starts_name = chars.next()?.is_alphabetic();
+    let is_name = chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    (starts_name && is_name).then_some(name)
+}
+
+impl ParsedCharacter {
+    /// Whether a spawn of `name` creates a subentity or runs a helper state
+    pub fn is_spawnable(&self, name: &str) -> bool {
+        self.subentities.contains_key(name)
+            || self
+                .states
+                .get(name)
+                .is_some_and(|state| state.state_type == StateType::Helper)
+    }
+
+    /// Subentities and helper states `state` may spawn, directly, through
+    /// the states it calls or through what it spawns, sorted
+    pub fn subentity_dependencies(&self, state: &str) -> Vec<String> {
+        let mut dependencies = BTreeSet::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![state.to_string()];
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let Some(state) = self.states.get(name.as_str()) else {
+                continue;
+            };
+            for action in state.actions.values().flatten() {
+                if let Some(spawned) = spawned_name(action) {
+                    if self.subentities.contains_key(spawned) {
+                        pending.extend(self.entity_states(spawned));
+                    } else if self.is_spawnable(spawned) {
+                        pending.push(spawned.to_string());
+                    } else {
+                        continue;
+                    }
+                    dependencies.insert(spawned.to_string());
+                    continue;
+                }
+                pending.extend(self.called_state(&name, action));
+            }
+        }
+        dependencies.into_iter().collect()
+    }
+
+    /// State run by a `Call` family action of state `caller`, if any
+    ///
+    /// Calls from a subentity state name a state of the same entity, or of
+    /// the entity it inherits from, except `CallFromMain`.
+    pub(crate) fn called_state(&self, caller: &str, action: &ParsedAction) -> Option<String> {
+        let instruction = &*action.instruction;
+        if !instruction.starts_with("Call") {
+            return None;
+        }
+        if instruction.ends_with("Parent") {
+            return self.states.get(caller)?.parent.clone();
+        }
+        let target = action.args.first()?.trim();
+        let entity = caller
+            .split_once(ENTITY_SEPARATOR)
+            .map(|(entity, _)| entity);
+        match entity {
+            Some(entity) if instruction != "CallFromMain" => self.entity_state(entity, target),
+            _ => Some(target.to_string()),
+        }
+    }
+
+    /// `subentity_dependencies` of every state spawning something
+    pub fn subentity_dependency_map(&self) -> BTreeMap<String, Vec<String>> {
+        self.states
+            .keys()
+            .map(|name| (name.to_string(), self.subentity_dependencies(name)))
+            .filter(|(_, dependencies)| !dependencies.is_empty())
+            .collect()
+    }
+
+    /// An entity followed by the entities it inherits from
+    fn entity_chain(&self, entity: &str) -> Vec<&str> {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut current = Some(entity);
+        while let Some(entity) = current.filter(|entity| !chain.contains(entity)) {
+            let Some((name, metadata)) = self.subentities.get_key_value(entity) else {
+                break;
+            };
+            chain.push(name);
+            current = match metadata.skeleton.as_deref() {
+                Some(skeleton) if skeleton.eq_ignore_ascii_case("none") => None,
+                Some(skeleton) => Some(skeleton),
+                None => Some(DEFAULT_ENTITY_SKELETON),
+            };
+        }
+        chain
+    }
+
+    /// Full name of the state `name` of an entity, defined by the entity
+    /// or the closest entity it inherits from
+    fn entity_state(&self, entity: &str, name: &str) -> Option<String> {
+        let mut chain = self.entity_chain(entity);
+        if chain.is_empty() {
+            chain.push(entity);
+        }
+        chain
+            .into_iter()
+            .map(|entity| format!("{}{}{}", entity, ENTITY_SEPARATOR, name))
+            .find(|state| self.states.contains_key(state.as_str()))
+    }
+
+    /// Full names of the states of an entity, inherited ones included
+    fn entity_states(&self, entity: &str) -> Vec<String> {
+        let chain = self.entity_chain(entity);
+        self.states
+            .keys()
+            .filter(|name| {
+                name.split_once(ENTITY_SEPARATOR)
+                    .is_some_and(|(owner, _)| chain.contains(&owner))
+            })
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{CastagneParser, UNKNOWN_SUBENTITY};
+
+    const SOURCE: &str = ":Character:
+Name: Ryu
+:Variables:
+var Shot(Str): Fireball
+:Base---Subentity:
+Skeleton: none
+:Base---Action:
+---Action:
+Call(Action-Hit)
+:Base---Action-Hit:
+---Action:
+CreateEntity(Spark)
+:Fireball---Subentity:
+Author: Capcom
+:Fireball---Init:
+Set(Speed, 8)
+:Spark---Subentity:
+:Spark---Action:
+Stop
+:Dust(Helper):
+---Init:
+Stop
+:Hadoken:
+---Init:
+CreateProjectile(Fireball)
+Call(Cloud)
+:Cloud(Helper):
+---Action:
+CreateSubentity(Dust)
+:Jab:
+---Action:
+CreateSubentity(Shot)
+CreateProjectile(100, 90)
+CreateProjectile(Shoryu)
+";
+
+    #[test]
+    fn test_subentity_metadata_and_dependencies() {
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("ryu.casp", SOURCE)
+            .unwrap();
+
+        let mut entities: Vec<&str> = character.subentities.keys().map(String::as_str).collect();
+        entities.sort_unstable();
+        assert_eq!(entities, vec!["Base", "Fireball", "Spark"]);
+        assert_eq!(character.subentities["Fireball"].author, "Capcom");
+        assert_eq!(
+            character.subentities["Base"].skeleton.as_deref(),
+            Some("none")
+        );
+        assert!(!character.states.contains_key("Fireball---Subentity"));
+        assert!(!character.specblocks.contains_key("Base---Subentity"));
+
+        // Fireball inherits the Base states spawning sparks
+        assert_eq!(
+            character.subentity_dependencies("Hadoken"),
+            vec!["Dust", "Fireball", "Spark"]
+        );
+        assert_eq!(
+            character.subentity_dependencies("Base---Action"),
+            vec!["Spark"]
+        );
+        assert!(character.subentity_dependencies("Dust").is_empty());
+        let map = character.subentity_dependency_map();
+        assert!(map.contains_key("Cloud") && !map.contains_key("Dust"));
+
+        let unknown: Vec<_> = parser
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.code == UNKNOWN_SUBENTITY)
+            .collect();
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown[0].message.contains("Shoryu"));
+        assert_eq!(unknown[0].span.as_ref().unwrap().line, 34);
+        assert!(parser.errors.is_empty());
+    }
+}
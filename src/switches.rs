@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Switches - Picking actions by the value of an expression
+//!
+//! `Switch(Expression)` runs the actions of the first `Case` whose values
+//! include the value of the expression, or those of `Default` if none
+//! does, up to `EndSwitch`:
+//!
+//! ```text
+//! Switch(Stance)
+//! Case(Stand)
+//! Anim(Idle)
+//! Case(Crouch, CrouchBlock)
+//! Anim(Crouch)
+//! Default
+//! Anim(Air)
+//! EndSwitch
+//! ```
+//!
+//! As with branches and loops, the instructions stay flat in the actions
+//! of a phase; `switches` gives the structure back. The parser reports a
+//! switch never closed and a `Case` or `Default` outside of one.
+//!
+//! A variable whose subtype lists values separated by `|` is an enum:
+//! `var Stance(Str, Stand|Crouch|Air): Stand`. The `non-exhaustive-switch`
+//! lint reports switches on one that leave some of its values unhandled.
+
+use crate::parser::{ParsedAction, ParsedVariable};
+
+pub const SWITCH: &str = "Switch";
+pub const CASE: &str = "Case";
+pub const DEFAULT: &str = "Default";
+pub const END_SWITCH: &str = "EndSwitch";
+
+/// A case of a switch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    /// Values it handles, as written
+    pub values: Vec<String>,
+    /// Index of its `Case` action
+    pub index: usize,
+}
+
+/// A switch of a phase, with the positions of its parts in the actions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Switch {
+    /// Expression switched on, as written
+    pub subject: String,
+    /// Index of the `Switch` action
+    pub start: usize,
+    /// Index of the `EndSwitch` action
+    pub end: usize,
+    pub cases: Vec<Case>,
+    /// Index of the `Default` action
+    pub default: Option<usize>,
+}
+
+impl Switch {
+    /// Values handled by a case, in order
+    pub fn case_values(&self) -> impl Iterator<Item = &str> {
+        self.cases
+            .iter()
+            .flat_map(|case| case.values.iter().map(String::as_str))
+    }
+
+    /// Actions run for a case, a `Case` action given by its index
+    pub fn case_body<'a>(&self, actions: &'a [ParsedAction], index: usize) -> &'a [ParsedAction] {
+        let end = self
+            .cases
+            .iter()
+            .map(|case| case.index)
+            .chain(self.default)
+            .filter(|&next| next > index)
+            .min()
+            .unwrap_or(self.end);
+        &actions[index + 1..end]
+    }
+
+    /// Values of `variable` no case handles, empty with a `Default` or if
+    /// the variable isn't an enum
+    pub fn missing_cases<'a>(&self, variable: &'a ParsedVariable) -> Vec<&'a str> {
+        if self.default.is_some() {
+            return Vec::new();
+        }
+        let handled: Vec<&str> = self.case_values().collect();
+        variable
+            .enum_values()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|value| !handled.contains(value))
+            .collect()
+    }
+}
+
+/// Switches closed within `actions`, by position of their `Switch` action
+///
+/// `Case` and `Default` only belong to the innermost switch when no
+/// branch opened since is still open.
+pub fn switches(actions: &[ParsedAction]) -> Vec<Switch> {
+    // Open switches, `None` for branches
+    let mut open: Vec<Option<Switch>> = Vec::new();
+    let mut found = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let instruction = &*action.instruction;
+        match instruction {
+            SWITCH => open.push(Some(Switch {
+                subject: action.args.first().map(str::to_string).unwrap_or_default(),
+                start: index,
+                end: index,
+                cases: Vec::new(),
+                default: None,
+            })),
+            CASE => {
+                if let Some(Some(switch)) = open.last_mut() {
+                    switch.cases.push(Case {
+                        values: action.args.to_vec(),
+                        index,
+                    });
+                }
+            }
+            DEFAULT => {
+                if let Some(Some(switch)) = open.last_mut() {
+                    switch.default.get_or_insert(index);
+                }
+            }
+            END_SWITCH => {
+                if let Some(Some(switch)) = open.last_mut() {
+                    switch.end = index;
+                    found.extend(open.pop().flatten());
+                }
+            }
+            _ if instruction.eq_ignore_ascii_case("endif") => {
+                if let Some(None) = open.last() {
+                    open.pop();
+                }
+            }
+            _ if instruction.ends_with(':') => open.push(None),
+            _ => {}
+        }
+    }
+    found.sort_by_key(|found| found.start);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{CastagneParser, CASE_OUTSIDE_SWITCH, UNCLOSED_SWITCH};
+
+    #[test]
+    fn test_switches() {
+        let source = "\
+:Variables:
+var Stance(Str, Stand|Crouch|Air): Stand
+:Idle:
+---Action:
+Switch(Stance)
+Case(Stand)
+Anim(Idle)
+Case(Crouch, CrouchBlock)
+Anim(Crouch)
+LFlag:
+Case(Air)
+endif
+EndSwitch
+Case(Stand)
+Switch(Stance)
+Default
+Stop
+";
+        let mut parser = CastagneParser::new();
+        let character = parser
+            .create_full_character_from_source("test.casp", source)
+            .unwrap();
+        let found: Vec<(&str, usize)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (CASE_OUTSIDE_SWITCH, 11),
+                (CASE_OUTSIDE_SWITCH, 14),
+                (UNCLOSED_SWITCH, 15)
+            ]
+        );
+
+        let actions = &character.states["Idle"].actions["Action"];
+        let found = switches(actions);
+        assert_eq!(found.len(), 1);
+        let switch = &found[0];
+        assert_eq!(
+            (switch.subject.as_str(), switch.start, switch.end),
+            ("Stance", 0, 8)
+        );
+        assert_eq!(
+            switch.case_values().collect::<Vec<_>>(),
+            ["Stand", "Crouch", "CrouchBlock"]
+        );
+        let body = switch.case_body(actions, 3);
+        assert_eq!(body.len(), 4);
+        let stance = &character.variables["Stance"];
+        assert_eq!(stance.enum_values().unwrap(), ["Stand", "Crouch", "Air"]);
+        assert_eq!(switch.missing_cases(stance), ["Air"]);
+    }
+}
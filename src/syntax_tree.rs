@@ -0,0 +1,803 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Syntax Tree - Concrete syntax tree of a .casp buffer for editor tooling
+//!
+//! Cuts a buffer into blocks, phases, branches and actions with the same
+//! rules as the parser, keeping every node's byte range and parent. Editors
+//! fold and select by structure from it without reimplementing the grammar.
+//! Lines are read through the parser's `LineScanner`, so a block comment is
+//! a comment node whatever it holds, and an action continued with `\\` or
+//! holding a `"""` string spans its rows.
+//!
+//! The shape follows tree-sitter: nodes have a snake_case `type`, byte
+//! ranges and `{row, column}` points with 0-indexed rows and columns counted
+//! in bytes, and `to_json` matches the fields of a web-tree-sitter node.
+//! `changed_range` gives the blocks to refresh after an edit.
+
+use crate::parser::{
+    is_block_header, looks_like_specblock, split_arguments, split_variables_header, LineScanner,
+};
+use godot::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::ops::Range;
+
+/// Index of a node in `SyntaxTree::nodes`
+pub type NodeId = usize;
+
+/// Kind of a syntax node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    SourceFile,
+    CharacterBlock,
+    VariablesBlock,
+    Specblock,
+    StateBlock,
+    /// `:Name(Type, Parent):` line of a block
+    Header,
+    /// Actions following a `---Phase:` marker
+    Phase,
+    PhaseMarker,
+    /// Actions from a line ending in `:` to its `endif`
+    Branch,
+    Else,
+    Endif,
+    Action,
+    /// Parenthesized arguments of an action or header
+    Arguments,
+    Argument,
+    /// `Key: Value` line of the character block or a specblock
+    Field,
+    VariableDeclaration,
+    Identifier,
+    Value,
+    Comment,
+}
+
+impl NodeKind {
+    /// Name of the kind, like tree-sitter's `Node.type`
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeKind::SourceFile => "source_file",
+            NodeKind::CharacterBlock => "character_block",
+            NodeKind::VariablesBlock => "variables_block",
+            NodeKind::Specblock => "specblock",
+            NodeKind::StateBlock => "state_block",
+            NodeKind::Header => "header",
+            NodeKind::Phase => "phase",
+            NodeKind::PhaseMarker => "phase_marker",
+            NodeKind::Branch => "branch",
+            NodeKind::Else => "else",
+            NodeKind::Endif => "endif",
+            NodeKind::Action => "action",
+            NodeKind::Arguments => "arguments",
+            NodeKind::Argument => "argument",
+            NodeKind::Field => "field",
+            NodeKind::VariableDeclaration => "variable_declaration",
+            NodeKind::Identifier => "identifier",
+            NodeKind::Value => "value",
+            NodeKind::Comment => "comment",
+        }
+    }
+
+    /// Nodes spanning several lines, which editors can fold
+    pub fn is_foldable(self) -> bool {
+        matches!(
+            self,
+            NodeKind::CharacterBlock
+                | NodeKind::VariablesBlock
+                | NodeKind::Specblock
+                | NodeKind::StateBlock
+                | NodeKind::Phase
+                | NodeKind::Branch
+        )
+    }
+}
+
+/// Position in the buffer, in bytes from the start of the row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Point {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// A node of the tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub start: Point,
+    pub end: Point,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+/// Concrete syntax tree of a buffer, the root being node 0
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyntaxTree {
+    nodes: Vec<SyntaxNode>,
+    #[serde(skip)]
+    source: String,
+    #[serde(skip)]
+    line_starts: Vec<usize>,
+}
+
+impl SyntaxTree {
+    pub fn parse(source: &str) -> Self {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(pos, _)| pos + 1))
+            .collect();
+        let mut builder = Builder {
+            tree: SyntaxTree {
+                nodes: Vec::new(),
+                source: source.to_string(),
+                line_starts,
+            },
+            open: Vec::new(),
+            line: LogicalLine::default(),
+            last_comment: None,
+        };
+        let root = builder.push(NodeKind::SourceFile, 0, 0);
+        builder.open.push(root);
+        builder.lines();
+        let mut tree = builder.finish();
+        tree.nodes[0].end_byte = source.len();
+        tree.nodes[0].end = tree.point(source.len());
+        tree
+    }
+
+    pub fn root(&self) -> &SyntaxNode {
+        &self.nodes[0]
+    }
+
+    /// All nodes in document order, parents before their children
+    pub fn nodes(&self) -> &[SyntaxNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: NodeId) -> &SyntaxNode {
+        &self.nodes[id]
+    }
+
+    pub fn text(&self, id: NodeId) -> &str {
+        let node = &self.nodes[id];
+        &self.source[node.start_byte..node.end_byte]
+    }
+
+    /// Smallest node covering the byte range, for expanding a selection
+    pub fn descendant_for_byte_range(&self, start: usize, end: usize) -> NodeId {
+        let mut id = 0;
+        while let Some(&child) = self.nodes[id].children.iter().find(|&&child| {
+            let node = &self.nodes[child];
+            node.start_byte <= start && end <= node.end_byte
+        }) {
+            id = child;
+        }
+        id
+    }
+
+    /// Rows of the foldable nodes spanning more than one row
+    pub fn folding_ranges(&self) -> Vec<(usize, usize)> {
+        self.nodes
+            .iter()
+            .filter(|node| node.kind.is_foldable() && node.end.row > node.start.row)
+            .map(|node| (node.start.row, node.end.row))
+            .collect()
+    }
+
+    /// Byte range of `new` covering the top-level blocks that differ from
+    /// this tree's, `None` when both buffers are the same
+    pub fn changed_range(&self, new: &SyntaxTree) -> Option<Range<usize>> {
+        let old_blocks = &self.root().children;
+        let new_blocks = &new.root().children;
+        let same = |a: NodeId, b: NodeId| {
+            self.nodes[a].kind == new.nodes[b].kind && self.text(a) == new.text(b)
+        };
+
+        let prefix = old_blocks
+            .iter()
+            .zip(new_blocks)
+            .take_while(|(&a, &b)| same(a, b))
+            .count();
+        let max_suffix = old_blocks.len().min(new_blocks.len()) - prefix;
+        let suffix = old_blocks
+            .iter()
+            .rev()
+            .zip(new_blocks.iter().rev())
+            .take(max_suffix)
+            .take_while(|(&a, &b)| same(a, b))
+            .count();
+
+        let changed = &new_blocks[prefix..new_blocks.len() - suffix];
+        match (changed.first(), changed.last()) {
+            (Some(&first), Some(&last)) => {
+                Some(new.nodes[first].start_byte..new.nodes[last].end_byte)
+            }
+            _ if self.source != new.source => {
+                // Blocks removed, or only text between blocks changed
+                let at = new_blocks
+                    .get(prefix)
+                    .map_or(new.source.len(), |&id| new.nodes[id].start_byte);
+                let from = prefix
+                    .checked_sub(1)
+                    .map_or(0, |i| new.nodes[new_blocks[i]].end_byte);
+                Some(from..at)
+            }
+            _ => None,
+        }
+    }
+
+    /// The tree as nested web-tree-sitter style objects
+    pub fn to_json(&self) -> Value {
+        self.node_json(0)
+    }
+
+    fn node_json(&self, id: NodeId) -> Value {
+        let node = &self.nodes[id];
+        let children: Vec<Value> = node.children.iter().map(|&c| self.node_json(c)).collect();
+        json!({
+            "type": node.kind.name(),
+            "startIndex": node.start_byte,
+            "endIndex": node.end_byte,
+            "startPosition": node.start,
+            "endPosition": node.end,
+            "children": children,
+        })
+    }
+
+    pub(crate) fn point(&self, byte: usize) -> Point {
+        let row = self.line_starts.partition_point(|&start| start <= byte) - 1;
+        Point {
+            row,
+            column: byte - self.line_starts[row],
+        }
+    }
+}
+
+/// A line as the parser reads it, block comments removed and continued
+/// lines joined, with the source byte each of its bytes comes from
+#[derive(Default)]
+struct LogicalLine {
+    text: String,
+    bytes: Vec<usize>,
+    /// Block comments and `#` comments dropped after a `\\`, as source
+    /// ranges, each with whether it goes on from the line above
+    comments: Vec<(Range<usize>, bool)>,
+}
+
+impl LogicalLine {
+    fn push(&mut self, text: &str, at: usize) {
+        self.text.push_str(text);
+        self.bytes.extend(at..at + text.len());
+    }
+
+    /// Source range of `range` of the text
+    fn source_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.bytes.get(range.start).copied();
+        let end = range.end.checked_sub(1).map(|last| self.bytes[last] + 1);
+        match (start, end) {
+            (Some(start), Some(end)) if range.start < range.end => start..end,
+            (Some(start), _) => start..start,
+            (None, end) => {
+                let end = end.or(self.bytes.last().map(|last| last + 1)).unwrap_or(0);
+                end..end
+            }
+        }
+    }
+}
+
+/// Cut `source` into the lines the parser reads, with its `LineScanner`
+///
+/// A line continued with `\\` or inside a `"""` string takes the next one
+/// along. Lines swallowed by a block comment are left with no text.
+fn logical_lines(source: &str, line_starts: &[usize]) -> Vec<LogicalLine> {
+    let mut scanner = LineScanner::default();
+    let mut lines = Vec::new();
+    let mut current: Option<LogicalLine> = None;
+    for (row, raw) in source.split('\n').enumerate() {
+        let line = raw.strip_suffix('\r').unwrap_or(raw);
+        let start = line_starts[row];
+        let (in_string, in_comment) = (scanner.in_multiline_string, scanner.in_block_comment);
+        let scanned = scanner.scan_line(line);
+        let continued = current.is_some();
+        let mut logical = current.take().unwrap_or_default();
+        let mut pieces: Vec<(&str, usize)> = scanned
+            .code
+            .iter()
+            .map(|range| (&line[range.clone()], start + range.start))
+            .collect();
+        if in_string {
+            logical.push("\n", start - 1);
+        } else if continued {
+            // Joined like the parser joins a continued line
+            if let Some((text, at)) = pieces.first_mut() {
+                let trimmed = text.trim_start();
+                *at += text.len() - trimmed.len();
+                *text = trimmed;
+            }
+            let has_code = pieces.iter().any(|(text, _)| !text.trim().is_empty());
+            if has_code && !logical.text.is_empty() {
+                let at = logical.bytes.last().map_or(start, |last| last + 1);
+                logical.push(" ", at);
+            }
+        }
+        for (text, at) in pieces {
+            logical.push(text, at);
+        }
+        for (index, range) in scanned.comments.iter().enumerate() {
+            let goes_on = index == 0 && in_comment;
+            logical
+                .comments
+                .push((start + range.start..start + range.end, goes_on));
+        }
+        if scanned.continues || scanner.in_multiline_string {
+            current = Some(logical);
+        } else {
+            lines.push(logical);
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+/// Builds the tree line by line, keeping the chain of open nodes
+struct Builder {
+    tree: SyntaxTree,
+    open: Vec<NodeId>,
+    /// Line being read; `leaf` and `open` take ranges of its text
+    line: LogicalLine,
+    /// Block comment that may go on over the next line
+    last_comment: Option<NodeId>,
+}
+
+impl Builder {
+    fn lines(&mut self) {
+        let source = std::mem::take(&mut self.tree.source);
+        let mut lines = logical_lines(&source, &self.tree.line_starts);
+        for index in 0..lines.len() {
+            self.line = std::mem::take(&mut lines[index]);
+            let comments = std::mem::take(&mut self.line.comments);
+            let text = self.line.text.clone();
+            let code = crate::borrowed::strip_comment(&text);
+            let code_start = code.len() - code.trim_start().len();
+            let code_end = code.trim_end().len();
+            let line_end = text.trim_end().len();
+            let first_byte = self.line.source_range(code_start..code_end).start;
+            let (before, after): (Vec<_>, Vec<_>) = comments
+                .into_iter()
+                .partition(|(comment, _)| code_start == code_end || comment.start < first_byte);
+            self.block_comments(before);
+
+            if code_start < code_end {
+                self.code(
+                    &text[code_start..code_end],
+                    code_start,
+                    lines[index + 1..].iter().map(|line| line.text.as_str()),
+                );
+            }
+            if code.len() < line_end {
+                let comment = self.line.source_range(code.len()..line_end);
+                self.push(NodeKind::Comment, comment.start, comment.end);
+                self.last_comment = None;
+            }
+            self.block_comments(after);
+        }
+        self.tree.source = source;
+    }
+
+    /// Comment nodes for comments the scanner removed, a block comment
+    /// over several lines being one node
+    fn block_comments(&mut self, comments: Vec<(Range<usize>, bool)>) {
+        for (comment, goes_on) in comments {
+            match self.last_comment {
+                Some(id) if goes_on && self.tree.nodes.len() == id + 1 => {
+                    let end = self.tree.point(comment.end);
+                    let node = &mut self.tree.nodes[id];
+                    node.end_byte = comment.end;
+                    node.end = end;
+                }
+                _ => {
+                    self.last_comment =
+                        Some(self.push(NodeKind::Comment, comment.start, comment.end));
+                }
+            }
+        }
+    }
+
+    fn code<'a>(&mut self, code: &str, start: usize, next_lines: impl Iterator<Item = &'a str>) {
+        let end = start + code.len();
+        if is_block_header(code) {
+            self.close_to(NodeKind::SourceFile);
+            let header = &code[1..code.len() - 1];
+            let kind = if header == "Character" {
+                NodeKind::CharacterBlock
+            } else if split_variables_header(header).is_some() {
+                NodeKind::VariablesBlock
+            } else if looks_like_specblock(next_lines) {
+                NodeKind::Specblock
+            } else {
+                NodeKind::StateBlock
+            };
+            self.open(kind, start, end);
+            self.header(header, start + 1, start, end);
+            return;
+        }
+
+        let block = self.block_kind();
+        if let Some(marker) = code.strip_prefix("---") {
+            if block == Some(NodeKind::StateBlock) {
+                self.close_to(NodeKind::StateBlock);
+                self.open(NodeKind::Phase, start, end);
+                let marker_node = self.open(NodeKind::PhaseMarker, start, end);
+                let name = marker.trim_end_matches(':');
+                let name_start = start + 3 + (name.len() - name.trim_start().len());
+                self.leaf(
+                    NodeKind::Identifier,
+                    name_start,
+                    start + 3 + name.trim_end().len(),
+                );
+                self.close(marker_node);
+                return;
+            }
+        }
+
+        match block {
+            Some(NodeKind::VariablesBlock) => self.declaration(code, start),
+            Some(NodeKind::CharacterBlock | NodeKind::Specblock) => self.field(code, start),
+            Some(NodeKind::StateBlock) if self.in_kind(NodeKind::Phase) => {
+                self.statement(code, start)
+            }
+            _ => self.field(code, start),
+        }
+    }
+
+    /// A line inside a phase, opening or closing branches
+    fn statement(&mut self, code: &str, start: usize) {
+        let end = start + code.len();
+        if code.eq_ignore_ascii_case("endif") {
+            if self.in_kind(NodeKind::Branch) {
+                self.close_to(NodeKind::Branch);
+                self.leaf(NodeKind::Endif, start, end);
+                let branch = *self.open.last().expect("branch is open");
+                self.close(branch);
+            } else {
+                self.leaf(NodeKind::Endif, start, end);
+            }
+        } else if code.eq_ignore_ascii_case("else") {
+            self.leaf(NodeKind::Else, start, end);
+        } else if let Some(condition) = code.strip_suffix(':') {
+            self.open(NodeKind::Branch, start, end);
+            self.action(condition.trim_end(), start);
+        } else {
+            self.action(code, start);
+        }
+    }
+
+    fn action(&mut self, code: &str, start: usize) {
+        let node = self.open(NodeKind::Action, start, start + code.len());
+        match (code.find('('), code.rfind(')')) {
+            (Some(open), Some(close)) if open < close => {
+                let name = code[..open].trim_end();
+                self.leaf(NodeKind::Identifier, start, start + name.len());
+                self.arguments(code, start, open, close);
+            }
+            _ => {
+                self.leaf(NodeKind::Identifier, start, start + code.len());
+            }
+        }
+        self.close(node);
+    }
+
+    /// Arguments between the parentheses at `open` and `close` of `text`
+    fn arguments(&mut self, text: &str, start: usize, open: usize, close: usize) {
+        let node = self.open(NodeKind::Arguments, start + open, start + close + 1);
+        let inner = &text[open + 1..close];
+        for argument in split_arguments(inner) {
+            let argument_start = start + open + 1 + offset_in(inner, argument);
+            self.leaf(
+                NodeKind::Argument,
+                argument_start,
+                argument_start + argument.len(),
+            );
+        }
+        self.close(node);
+    }
+
+    fn header(&mut self, header: &str, header_start: usize, start: usize, end: usize) {
+        let node = self.open(NodeKind::Header, start, end);
+        let name_len = header.find('(').unwrap_or(header.len());
+        let name = header[..name_len].trim();
+        let name_start = header_start + (header.len() - header.trim_start().len());
+        self.leaf(NodeKind::Identifier, name_start, name_start + name.len());
+        if let (Some(open), Some(close)) = (header.find('('), header.rfind(')')) {
+            if open < close {
+                self.arguments(header, header_start, open, close);
+            }
+        }
+        self.close(node);
+    }
+
+    fn declaration(&mut self, code: &str, start: usize) {
+        let node = self.open(NodeKind::VariableDeclaration, start, start + code.len());
+        let (declaration, value) = match code.split_once(':') {
+            Some((declaration, value)) => (declaration, Some(value)),
+            None => (code, None),
+        };
+        let rest = ["var ", "def ", "internal "]
+            .iter()
+            .find_map(|keyword| declaration.strip_prefix(keyword))
+            .unwrap_or(declaration)
+            .trim_start();
+        let name_start = start + offset_in(code, rest);
+        let name_len = rest
+            .find(|c: char| c == '(' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        self.leaf(NodeKind::Identifier, name_start, name_start + name_len);
+        if let Some(value) = value {
+            self.value(value, start + declaration.len() + 1);
+        }
+        self.close(node);
+    }
+
+    fn field(&mut self, code: &str, start: usize) {
+        let node = self.open(NodeKind::Field, start, start + code.len());
+        match code.split_once(':') {
+            Some((key, value)) => {
+                self.leaf(NodeKind::Identifier, start, start + key.trim_end().len());
+                self.value(value, start + key.len() + 1);
+            }
+            None => self.leaf(NodeKind::Identifier, start, start + code.len()),
+        }
+        self.close(node);
+    }
+
+    fn value(&mut self, value: &str, start: usize) {
+        let trimmed = value.trim();
+        if !trimmed.is_empty() {
+            let value_start = start + (value.len() - value.trim_start().len());
+            self.leaf(NodeKind::Value, value_start, value_start + trimmed.len());
+        }
+    }
+
+    /// Kind of the block being read, `None` before the first header
+    fn block_kind(&self) -> Option<NodeKind> {
+        self.open.get(1).map(|&id| self.tree.nodes[id].kind)
+    }
+
+    fn in_kind(&self, kind: NodeKind) -> bool {
+        self.open.iter().any(|&id| self.tree.nodes[id].kind == kind)
+    }
+
+    fn push(&mut self, kind: NodeKind, start: usize, end: usize) -> NodeId {
+        let id = self.tree.nodes.len();
+        let parent = self.open.last().copied();
+        self.tree.nodes.push(SyntaxNode {
+            kind,
+            start_byte: start,
+            end_byte: end,
+            start: self.tree.point(start),
+            end: self.tree.point(end),
+            parent,
+            children: Vec::new(),
+        });
+        if let Some(parent) = parent {
+            self.tree.nodes[parent].children.push(id);
+        }
+        id
+    }
+
+    fn leaf(&mut self, kind: NodeKind, start: usize, end: usize) {
+        let range = self.line.source_range(start..end);
+        self.push(kind, range.start, range.end);
+    }
+
+    fn open(&mut self, kind: NodeKind, start: usize, end: usize) -> NodeId {
+        let range = self.line.source_range(start..end);
+        let id = self.push(kind, range.start, range.end);
+        self.open.push(id);
+        id
+    }
+
+    /// Extend a node over its last child, once nothing more is added to it
+    fn close(&mut self, id: NodeId) {
+        if let Some(&last) = self.tree.nodes[id].children.last() {
+            let (end_byte, end) = (self.tree.nodes[last].end_byte, self.tree.nodes[last].end);
+            let node = &mut self.tree.nodes[id];
+            if end_byte > node.end_byte {
+                node.end_byte = end_byte;
+                node.end = end;
+            }
+        }
+        if self.open.last() == Some(&id) {
+            self.open.pop();
+        }
+    }
+
+    /// Close the open nodes inside the innermost one of `kind`
+    fn close_to(&mut self, kind: NodeKind) {
+        while let Some(&id) = self.open.last() {
+            if self.tree.nodes[id].kind == kind {
+                break;
+            }
+            self.close(id);
+        }
+    }
+
+    fn finish(mut self) -> SyntaxTree {
+        while let Some(&id) = self.open.last() {
+            self.close(id);
+        }
+        self.tree
+    }
+}
+
+/// Byte offset of `inner`, a subslice of `outer`, from the start of `outer`
+fn offset_in(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+/// Godot-facing syntax tree for the in-engine code editor
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneSyntaxTree {
+    base: Base<RefCounted>,
+}
+
+#[godot_api]
+impl CastagneSyntaxTree {
+    /// The syntax tree of a buffer as web-tree-sitter style JSON
+    #[func]
+    pub fn parse_to_json(&self, source: GString) -> GString {
+        let json = SyntaxTree::parse(&source.to_string()).to_json();
+        GString::from(json.to_string().as_str())
+    }
+
+    /// Foldable row ranges of a buffer, as `[start_row, end_row]` pairs
+    #[func]
+    pub fn folding_ranges(&self, source: GString) -> PackedInt32Array {
+        SyntaxTree::parse(&source.to_string())
+            .folding_ranges()
+            .into_iter()
+            .flat_map(|(start, end)| [start as i32, end as i32])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = ":Character:\nName: Ryu # the hero\n:Variables:\nvar Health(Int): 1000\n:Idle:\n---Init:\nSet(Health, Add(1, 2))\n---Action:\nIfLt(Health, 1):\n\tTransition(KO)\nelse\n\tStop\nendif\n";
+
+    fn kinds(tree: &SyntaxTree, id: NodeId) -> Vec<(&'static str, &str)> {
+        tree.node(id)
+            .children
+            .iter()
+            .map(|&child| (tree.node(child).kind.name(), tree.text(child)))
+            .collect()
+    }
+
+    #[test]
+    fn test_tree_structure() {
+        let tree = SyntaxTree::parse(SOURCE);
+        let blocks = kinds(&tree, 0);
+        assert_eq!(
+            blocks.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+            vec!["character_block", "variables_block", "state_block"]
+        );
+        let character = tree.root().children[0];
+        assert_eq!(tree.text(character), ":Character:\nName: Ryu # the hero");
+        let name_field = tree.node(character).children[1];
+        assert_eq!(
+            kinds(&tree, name_field),
+            vec![("identifier", "Name"), ("value", "Ryu")]
+        );
+
+        let variables = tree.root().children[1];
+        let declaration = tree.node(variables).children[1];
+        assert_eq!(
+            kinds(&tree, declaration),
+            vec![("identifier", "Health"), ("value", "1000")]
+        );
+
+        let idle = tree.root().children[2];
+        let action_phase = tree.node(idle).children[2];
+        let branch = tree.node(action_phase).children[1];
+        assert_eq!(
+            kinds(&tree, branch),
+            vec![
+                ("action", "IfLt(Health, 1)"),
+                ("action", "Transition(KO)"),
+                ("else", "else"),
+                ("action", "Stop"),
+                ("endif", "endif"),
+            ]
+        );
+        let condition = tree.node(branch).children[0];
+        let arguments = tree.node(condition).children[1];
+        assert_eq!(
+            kinds(&tree, arguments),
+            vec![("argument", "Health"), ("argument", "1")]
+        );
+        assert_eq!(tree.node(arguments).parent, Some(condition));
+        assert_eq!(tree.node(branch).start, Point { row: 8, column: 0 });
+        assert_eq!(tree.node(branch).end, Point { row: 12, column: 5 });
+        assert_eq!(
+            tree.folding_ranges(),
+            vec![(0, 1), (2, 3), (4, 12), (5, 6), (7, 12), (8, 12)]
+        );
+    }
+
+    #[test]
+    fn test_selection_and_json() {
+        let tree = SyntaxTree::parse(SOURCE);
+        let at = SOURCE.find("Add(1").unwrap();
+        let argument = tree.descendant_for_byte_range(at, at + 3);
+        assert_eq!(tree.node(argument).kind, NodeKind::Argument);
+        assert_eq!(tree.text(argument), "Add(1, 2)");
+        let action = tree
+            .node(argument)
+            .parent
+            .and_then(|id| tree.node(id).parent);
+        assert_eq!(tree.text(action.unwrap()), "Set(Health, Add(1, 2))");
+
+        let json = tree.to_json();
+        assert_eq!(json["type"], "source_file");
+        assert_eq!(json["endIndex"], SOURCE.len());
+        let header = &json["children"][2]["children"][0];
+        assert_eq!(header["type"], "header");
+        assert_eq!(
+            header["children"][0]["startPosition"],
+            json!({"row": 4, "column": 1})
+        );
+    }
+
+    #[test]
+    fn test_lines_read_like_the_parser() {
+        let source = ":Idle:\n---Init:\n/* :Fake:\n---Action:\nMove(1) */ Stop\nSet(Health, \\\n  100)\nLog(\"\"\"\n:NotAHeader:\n\"\"\")\n";
+        let tree = SyntaxTree::parse(source);
+
+        assert_eq!(
+            kinds(&tree, 0),
+            vec![("state_block", &source[..source.len() - 1])]
+        );
+        let init = tree.node(tree.root().children[0]).children[1];
+        assert_eq!(
+            kinds(&tree, init),
+            vec![
+                ("phase_marker", "---Init:"),
+                ("comment", "/* :Fake:\n---Action:\nMove(1) */"),
+                ("action", "Stop"),
+                ("action", "Set(Health, \\\n  100)"),
+                ("action", "Log(\"\"\"\n:NotAHeader:\n\"\"\")"),
+            ]
+        );
+        let set = tree.node(init).children[3];
+        let arguments = tree.node(set).children[1];
+        assert_eq!(
+            kinds(&tree, arguments),
+            vec![("argument", "Health"), ("argument", "100")]
+        );
+        assert_eq!(tree.node(arguments).end, Point { row: 6, column: 6 });
+    }
+
+    #[test]
+    fn test_changed_range() {
+        let old = SyntaxTree::parse(SOURCE);
+        assert_eq!(old.changed_range(&SyntaxTree::parse(SOURCE)), None);
+
+        let edited = SOURCE.replace("var Health(Int): 1000", "var Health(Int): 900");
+        let new = SyntaxTree::parse(&edited);
+        let changed = old.changed_range(&new).unwrap();
+        assert_eq!(&edited[changed], ":Variables:\nvar Health(Int): 900");
+
+        // Unterminated branches end with their phase
+        let tree = SyntaxTree::parse(":Idle:\n---Init:\nIfLt(A, 1):\nStop\n---Action:\nStop\n");
+        let init = tree.node(tree.root().children[0]).children[1];
+        assert_eq!(tree.node(init).end.row, 3);
+    }
+}
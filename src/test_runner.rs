@@ -6,9 +6,26 @@
 //!
 //! This test runner validates the Rust parser against golden master JSON files.
 //! The engine logic is now in GDScript, so we only test the parser here.
+//! The same files are also written back to .casp and parsed again, see
+//! `test_roundtrip`.
+//! Character scenarios (`.casp-test` files) are checked here and played by
+//! the engine, see `load_scenarios`. Two characters can also be stepped
+//! headless through scripted inputs, see `simulate`.
 
 use crate::parser::CastagneParser;
+use crate::roundtrip::verify_roundtrip;
+use crate::scenario::{check_scenarios, parse_scenarios, Expected, ScenarioFile};
+use crate::simulation::{simulate, EntitySnapshot};
 use godot::prelude::*;
+use std::path::Path;
+
+/// Character files checked with `verify_roundtrip` when present
+const ROUNDTRIP_FILES: &[&str] = &[
+    "castagne/examples/fighters/baston/Baston-Model.casp",
+    "castagne/examples/fighters/baston/Baston-2D.casp",
+    "castagne/editor/tutorials/assets/TutorialBaston.casp",
+    "test_character_complete.casp",
+];
 
 /// Test runner for parser validation
 #[derive(GodotClass)]
@@ -29,9 +46,9 @@ impl INode for CastagneTestRunner {
 impl CastagneTestRunner {
     /// Run parser comparison tests against golden masters
     #[func]
-    pub fn run_comparison_tests(&mut self) -> Dictionary {
+    pub fn run_comparison_tests(&mut self) -> VarDictionary {
         godot_print!("=== Running Castagne Parser Tests ===");
-        let mut results = Dictionary::new();
+        let mut results = VarDictionary::new();
 
         // Test parser operations against golden masters
         results.set("parser_basic_character", self.test_parser_basic_character());
@@ -43,6 +60,7 @@ impl CastagneTestRunner {
             "parser_advanced_character",
             self.test_parser_advanced_character(),
         );
+        results.set("roundtrip", self.test_roundtrips());
 
         // Print summary
         let passed = results
@@ -111,6 +129,176 @@ impl CastagneTestRunner {
         )
     }
 
+    /// Check that a file comes back the same after an export to JSON and
+    /// a write back to .casp, see `verify_roundtrip`
+    #[func]
+    pub fn test_roundtrip(&mut self, path: GString) -> bool {
+        self.roundtrip(&path.to_string())
+    }
+
+    /// Roundtrip the character files of the comparison tests found
+    fn test_roundtrips(&self) -> bool {
+        let mut passed = true;
+        for casp_file in ROUNDTRIP_FILES {
+            if !Path::new(casp_file).exists() {
+                godot_print!("⚠ Skipping roundtrip of {}: file not found", casp_file);
+                continue;
+            }
+            passed &= self.roundtrip(casp_file);
+        }
+        passed
+    }
+
+    fn roundtrip(&self, casp_file: &str) -> bool {
+        godot_print!("Testing roundtrip ({})...", casp_file);
+        match verify_roundtrip(casp_file) {
+            Ok(()) => {
+                godot_print!("  ✅ Roundtrip passed!");
+                true
+            }
+            Err(differences) => {
+                for difference in &differences {
+                    godot_error!("  {}", difference);
+                }
+                godot_error!("  ❌ Roundtrip failed - {} differences", differences.len());
+                false
+            }
+        }
+    }
+
+    /// Load a `.casp-test` file for the engine to play, as a dictionary
+    /// with `character` and `opponent` (paths next to the scenario file,
+    /// empty if unset), `scenarios` and `errors`
+    ///
+    /// Each scenario has `name`, `frames`, `inputs` (the inputs held on each
+    /// frame, first frame first) and `expectations`, each with `frame`,
+    /// `kind` (`State` or `Damage`) and `value`. Scenarios with errors
+    /// can't pass and shouldn't be played.
+    #[func]
+    pub fn load_scenarios(&self, path: GString) -> VarDictionary {
+        let path = path.to_string();
+        let mut result = VarDictionary::new();
+        let (file, errors) = match std::fs::read_to_string(&path) {
+            Ok(text) => match parse_scenarios(&text) {
+                Ok(file) => {
+                    let errors = scenario_errors(&path, &file);
+                    (file, errors)
+                }
+                Err(errors) => (ScenarioFile::default(), errors),
+            },
+            Err(e) => (
+                ScenarioFile::default(),
+                vec![format!("Cannot read {}: {}", path, e)],
+            ),
+        };
+
+        let beside = |name: &Option<String>| match name {
+            Some(name) => relative_to(&path, name),
+            None => String::new(),
+        };
+        result.set("character", beside(&file.character).as_str());
+        result.set("opponent", beside(&file.opponent).as_str());
+        let scenarios: VarArray = file
+            .scenarios
+            .iter()
+            .map(|scenario| {
+                let inputs: VarArray = scenario
+                    .input_table()
+                    .into_iter()
+                    .map(|held| {
+                        let held: PackedStringArray = held.into_iter().map(GString::from).collect();
+                        held.to_variant()
+                    })
+                    .collect();
+                let expectations: VarArray = scenario
+                    .expectations
+                    .iter()
+                    .map(|expectation| {
+                        let mut row = VarDictionary::new();
+                        row.set("frame", expectation.frame as i64);
+                        match &expectation.expected {
+                            Expected::State(state) => {
+                                row.set("kind", "State");
+                                row.set("value", state.as_str());
+                            }
+                            Expected::Damage(damage) => {
+                                row.set("kind", "Damage");
+                                row.set("value", *damage);
+                            }
+                        }
+                        row.to_variant()
+                    })
+                    .collect();
+                let mut row = VarDictionary::new();
+                row.set("name", scenario.name.as_str());
+                row.set("frames", scenario.frames as i64);
+                row.set("inputs", inputs);
+                row.set("expectations", expectations);
+                row.to_variant()
+            })
+            .collect();
+        result.set("scenarios", scenarios);
+        let errors: PackedStringArray = errors.iter().map(|e| GString::from(e.as_str())).collect();
+        result.set("errors", errors);
+        result
+    }
+
+    /// Play `frames` frames of two characters without the engine and
+    /// return the last snapshot, see `simulation`
+    ///
+    /// `inputs` holds the inputs of the character on each frame, first
+    /// frame first, as arrays of strings; the opponent holds nothing. The
+    /// dictionary has `frame`, `entities` (the character then the
+    /// opponent, each with `state`, `state_frame`, `position`, `facing`,
+    /// `variables` and `flags`) and `errors`.
+    #[func]
+    pub fn simulate(
+        &self,
+        character: GString,
+        opponent: GString,
+        inputs: VarArray,
+        frames: i64,
+    ) -> VarDictionary {
+        let mut result = VarDictionary::new();
+        let mut errors = PackedStringArray::new();
+        let mut load = |path: GString| {
+            let mut parser = CastagneParser::new();
+            let character = parser.create_full_character(&path.to_string());
+            for error in &parser.errors {
+                errors.push(error.as_str());
+            }
+            character.ok()
+        };
+        let (Some(character), Some(opponent)) = (load(character), load(opponent)) else {
+            result.set("errors", errors);
+            return result;
+        };
+
+        let table: Vec<Vec<String>> = inputs
+            .iter_shared()
+            .map(|held| {
+                held.try_to::<PackedStringArray>()
+                    .map(|held| held.as_slice().iter().map(GString::to_string).collect())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let snapshot = simulate(
+            [&character, &opponent],
+            [&table, &[]],
+            frames.max(0) as usize,
+        );
+
+        let entities: VarArray = snapshot
+            .entities
+            .iter()
+            .map(|entity| entity_dictionary(entity).to_variant())
+            .collect();
+        result.set("frame", snapshot.frame as i64);
+        result.set("entities", entities);
+        result.set("errors", errors);
+        result
+    }
+
     /// Helper method to test parser against a golden master file
     fn test_parser_with_golden_master(&self, casp_file: &str, golden_master_file: &str) -> bool {
         use std::fs;
@@ -143,8 +331,8 @@ impl CastagneTestRunner {
         // Parse the .casp file with Rust parser
         let mut parser = CastagneParser::new();
         let rust_result = match parser.create_full_character(casp_file) {
-            Some(character) => character,
-            None => {
+            Ok(character) => character,
+            Err(_) => {
                 godot_error!("❌ Rust parser failed to parse {}", casp_file);
                 for error in &parser.errors {
                     godot_error!("   Parser error: {}", error);
@@ -155,14 +343,8 @@ impl CastagneTestRunner {
 
         godot_print!("  → Serializing Rust parser output to JSON");
 
-        // Serialize Rust parser output to JSON
-        let rust_json = match rust_result.to_json_value() {
-            Ok(json) => json,
-            Err(e) => {
-                godot_error!("❌ Failed to serialize Rust parser output: {}", e);
-                return false;
-            }
-        };
+        // Serialize Rust parser output in the layout of the golden masters
+        let rust_json = rust_result.to_golden_master();
 
         godot_print!("  → Comparing outputs");
 
@@ -232,3 +414,46 @@ impl CastagneTestRunner {
         }
     }
 }
+
+/// A character of a simulation snapshot as a dictionary
+fn entity_dictionary(entity: &EntitySnapshot) -> VarDictionary {
+    let mut variables = VarDictionary::new();
+    for (name, value) in &entity.variables {
+        variables.set(name.as_str(), *value);
+    }
+    let flags: PackedStringArray = entity
+        .flags
+        .iter()
+        .map(|f| GString::from(f.as_str()))
+        .collect();
+    let mut dictionary = VarDictionary::new();
+    dictionary.set("state", entity.state.as_str());
+    dictionary.set("state_frame", entity.state_frame as i64);
+    let (x, y) = entity.position;
+    dictionary.set("position", Vector2i::new(x as i32, y as i32));
+    dictionary.set("facing", entity.facing);
+    dictionary.set("variables", variables);
+    dictionary.set("flags", flags);
+    dictionary
+}
+
+/// `name` resolved next to the file at `path`
+fn relative_to(path: &str, name: &str) -> String {
+    match Path::new(path).parent() {
+        Some(dir) => dir.join(name).to_string_lossy().into_owned(),
+        None => name.to_string(),
+    }
+}
+
+/// Errors of the scenarios of the file at `path` against its character
+fn scenario_errors(path: &str, file: &ScenarioFile) -> Vec<String> {
+    let Some(character) = &file.character else {
+        return vec![format!("{} doesn't name its Character", path)];
+    };
+    let character_path = relative_to(path, character);
+    let mut parser = CastagneParser::new();
+    match parser.create_full_character(&character_path) {
+        Ok(character) => check_scenarios(file, &character),
+        Err(_) => parser.errors.clone(),
+    }
+}
@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Training - Move properties for a training mode overlay
+//!
+//! A training overlay shows the damage, guard and cancels of the move
+//! being performed. Rather than reading raw actions every frame, it looks
+//! up the rows built here once per character. Values go through the
+//! defines, calls are followed (the `AttackCancels-*` helpers add most
+//! cancels) and every branch is assumed taken, so a cancel only available
+//! with enough meter is still listed.
+//!
+//! Castagne has no meter instruction of its own: meter gain is read from
+//! `AttackParam(MeterGain, N)`.
+
+use crate::frame_data::AttackData;
+use crate::parser::{ParsedAction, ParsedCharacter};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Damage of an attack that doesn't set one, as in the Castagne attack module
+const DEFAULT_DAMAGE: i64 = 100;
+
+/// Attack parameter holding the meter gained on hit
+const METER_GAIN_PARAM: &str = "MeterGain";
+
+/// How a move has to be blocked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GuardType {
+    #[default]
+    Mid,
+    Low,
+    Overhead,
+    Unblockable,
+}
+
+impl fmt::Display for GuardType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GuardType::Mid => "Mid",
+            GuardType::Low => "Low",
+            GuardType::Overhead => "Overhead",
+            GuardType::Unblockable => "Unblockable",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a training overlay shows about one attack state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MoveProperties {
+    pub state: String,
+    pub input: Option<String>,
+    pub attack_type: Option<String>,
+    pub damage: i64,
+    pub chip_damage: i64,
+    pub guard: GuardType,
+    /// States it cancels into with `AttackCancel`, sorted
+    pub cancels: Vec<String>,
+    /// Attack types it cancels into, from `AttackAddRegisteredCancels` and
+    /// the `CanCancelInto` flags of its type, sorted
+    pub cancel_types: Vec<String>,
+    pub meter_gain: Option<i64>,
+    pub duration: Option<i64>,
+    pub advantage_hit: Option<i64>,
+    pub advantage_block: Option<i64>,
+}
+
+/// Properties of every attack of a character, sorted by state name
+pub fn move_properties(character: &ParsedCharacter) -> Vec<MoveProperties> {
+    let type_cancels = character.attack_data().map(|data| data.types).ok();
+
+    let mut states: Vec<&str> = character.states.keys().map(|name| &**name).collect();
+    states.sort_unstable();
+    let mut moves = Vec::new();
+    for state in states {
+        let Some(attack) = AttackData::from_state(&character.states[state]) else {
+            continue;
+        };
+        let mut properties = MoveProperties {
+            state: attack.state,
+            input: attack.input,
+            attack_type: attack
+                .attack_type
+                .map(|name| character.timeline_name(&name)),
+            damage: DEFAULT_DAMAGE,
+            ..Default::default()
+        };
+        let mut cancels = BTreeSet::new();
+        let mut cancel_types = BTreeSet::new();
+        let mut calls = vec![state.to_string()];
+        read_state(
+            character,
+            state,
+            &mut properties,
+            &mut cancels,
+            &mut cancel_types,
+            &mut calls,
+        );
+
+        let own_type = properties.attack_type.as_deref();
+        for spec in type_cancels.iter().flatten() {
+            if Some(spec.name.as_str()) == own_type {
+                let flagged = spec.cancels_into.iter().filter(|(_, allowed)| **allowed);
+                cancel_types.extend(flagged.map(|(target, _)| target.clone()));
+            }
+        }
+        properties.cancels = cancels.into_iter().collect();
+        properties.cancel_types = cancel_types.into_iter().collect();
+        moves.push(properties);
+    }
+    moves
+}
+
+/// Apply the attack actions of `state` and the states it calls, in order
+fn read_state(
+    character: &ParsedCharacter,
+    state: &str,
+    properties: &mut MoveProperties,
+    cancels: &mut BTreeSet<String>,
+    cancel_types: &mut BTreeSet<String>,
+    calls: &mut Vec<String>,
+) {
+    let Some(parsed) = character.states.get(state) else {
+        return;
+    };
+    let mut actions: Vec<&ParsedAction> = parsed.actions.values().flatten().collect();
+    actions.sort_by_key(|action| action.line_number);
+
+    for action in actions {
+        let name = |index: usize| {
+            action
+                .args
+                .get(index)
+                .map(|arg| character.timeline_name(arg))
+        };
+        let int = |index: usize| {
+            action
+                .args
+                .get(index)
+                .and_then(|arg| character.timeline_int(arg))
+        };
+        match &*action.instruction {
+            "AttackDamage" => properties.damage = int(0).unwrap_or(properties.damage),
+            "AttackChipDamage" => properties.chip_damage = int(0).unwrap_or(0),
+            "AttackDuration" => properties.duration = int(0),
+            "AttackFrameAdvantage" | "AttackFA" => {
+                properties.advantage_hit = int(0);
+                properties.advantage_block = int(1).or_else(|| int(0));
+            }
+            "AttackFrameAdvantageHit" | "AttackFAHit" => properties.advantage_hit = int(0),
+            "AttackFrameAdvantageBlock" | "AttackFABlock" => properties.advantage_block = int(0),
+            "AttackFlag" | "AttackMustBlock" => {
+                let guard = match name(0).as_deref() {
+                    Some("Low") => GuardType::Low,
+                    Some("Overhead") => GuardType::Overhead,
+                    _ => continue,
+                };
+                if properties.guard != GuardType::Unblockable {
+                    properties.guard = guard;
+                }
+            }
+            "AttackUnblockable" | "AttackUnblockableGround" | "AttackUnblockableAirborne" => {
+                properties.guard = GuardType::Unblockable;
+            }
+            "AttackParam" if name(0).as_deref() == Some(METER_GAIN_PARAM) => {
+                properties.meter_gain = int(1);
+            }
+            "AttackCancel" => cancels.extend(name(0)),
+            "AttackAddRegisteredCancels" => cancel_types.extend(name(0)),
+            _ => {
+                if let Some(called) = character.called_state(state, action) {
+                    if !calls.contains(&called) {
+                        calls.push(called.clone());
+                        read_state(character, &called, properties, cancels, cancel_types, calls);
+                        calls.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CastagneParser;
+
+    #[test]
+    fn test_move_properties() {
+        let source = ":Character:
+Name: Ryu
+:AttacksTypes:
+ATTACK_Light_CanCancelIntoMedium: 1
+ATTACK_Light_CanCancelIntoHeavy: 0
+:Variables:
+def SweepDamage: 900
+:2B:
+---Init:
+AttackRegister(Light)
+AttackDamage(400)
+AttackChipDamage(20)
+AttackFlag(Low)
+AttackFrameAdvantage(2, -3)
+AttackParam(MeterGain, 50)
+Call(SpecialCancels)
+:SpecialCancels(Helper):
+---Init:
+LMeterFull:
+AttackAddRegisteredCancels(Super)
+endif
+AttackCancel(Hadoken, 236C)
+:3C:
+---Init:
+AttackRegister(Heavy)
+AttackDamage(SweepDamage)
+AttackDuration(40)
+AttackMustBlock(Overhead)
+AttackUnblockableAirborne()
+:5A:
+---Init:
+AttackRegister(Light)
+:Idle:
+---Init:
+Stop
+";
+        let character = CastagneParser::new()
+            .create_full_character_from_source("ryu.casp", source)
+            .unwrap();
+        let moves = move_properties(&character);
+
+        let names: Vec<&str> = moves.iter().map(|m| m.state.as_str()).collect();
+        assert_eq!(names, vec!["2B", "3C", "5A"]);
+
+        assert_eq!(
+            moves[0],
+            MoveProperties {
+                state: "2B".to_string(),
+                input: Some("2B".to_string()),
+                attack_type: Some("Light".to_string()),
+                damage: 400,
+                chip_damage: 20,
+                guard: GuardType::Low,
+                cancels: vec!["Hadoken".to_string()],
+                cancel_types: vec!["Medium".to_string(), "Super".to_string()],
+                meter_gain: Some(50),
+                duration: None,
+                advantage_hit: Some(2),
+                advantage_block: Some(-3),
+            }
+        );
+        assert_eq!(moves[1].damage, 900);
+        assert_eq!(moves[1].duration, Some(40));
+        assert_eq!(moves[1].guard, GuardType::Unblockable);
+        assert!(moves[1].cancel_types.is_empty());
+        assert_eq!(moves[2].damage, DEFAULT_DAMAGE);
+        assert_eq!(moves[2].guard.to_string(), "Mid");
+    }
+}
@@ -0,0 +1,179 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Validation - Diagnostics of an unsaved buffer for the code editor
+//!
+//! The in-engine editor underlines problems as the author types, before the
+//! file is saved. `validate_buffer` parses the buffer in memory, keeps
+//! going past errors like any parse, runs the lints on the result and
+//! returns everything located in the buffer, ordered by position, with
+//! the severities of the parser config.
+
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::lint::{lint_source, LintConfig};
+use crate::parser::{CastagneParser, ParserConfig};
+use godot::prelude::*;
+
+/// Parse and lint diagnostics of `text`, the unsaved content of `path`
+pub fn validate_buffer(
+    path: &str,
+    text: &str,
+    config: &ParserConfig,
+    lints: &LintConfig,
+) -> Vec<Diagnostic> {
+    let mut parser = CastagneParser::with_config(config.clone());
+    let character = parser.create_full_character_from_source(path, text).ok();
+    let mut diagnostics = parser_diagnostics(&parser);
+    if let Some(character) = &character {
+        // The severities of the parser config apply to lints too
+        let linted = lint_source(character, text, lints);
+        diagnostics.extend(linted.into_iter().filter_map(|mut diagnostic| {
+            diagnostic.severity = config.severity_of(&diagnostic.code, diagnostic.severity)?;
+            Some(diagnostic)
+        }));
+    }
+
+    // Only what can be shown in the buffer, unlocated errors first
+    diagnostics.retain(|diagnostic| match &diagnostic.span {
+        Some(span) => span.file.as_deref().is_none_or(|file| file == path),
+        None => true,
+    });
+    diagnostics.sort_by_key(|diagnostic| {
+        diagnostic
+            .span
+            .as_ref()
+            .map(|span| (span.line, span.column))
+    });
+    diagnostics
+}
+
+/// Diagnostics of the last parse, with the errors reported without one
+/// added as error diagnostics, spanning the line they mention if any
+pub(crate) fn parser_diagnostics(parser: &CastagneParser) -> Vec<Diagnostic> {
+    let mut diagnostics = parser.get_diagnostics().to_vec();
+    for error in parser.get_errors() {
+        if diagnostics.iter().any(|d| error.starts_with(&d.message)) {
+            continue;
+        }
+        let mut diagnostic = Diagnostic::new("", Severity::Error, error.as_str());
+        diagnostic.span = error_line(error).map(Span::line);
+        diagnostics.push(diagnostic);
+    }
+    diagnostics
+}
+
+/// Line mentioned by a `... (line N)` error message
+pub(crate) fn error_line(error: &str) -> Option<usize> {
+    let rest = &error[error.rfind("(line ")? + 6..];
+    let end = rest.find(|c: char| !c.is_ascii_digit())?;
+    rest[..end].parse().ok()
+}
+
+/// Godot-facing validation for the in-engine code editor
+#[derive(GodotClass)]
+#[class(base=RefCounted, init)]
+pub struct CastagneValidator {
+    base: Base<RefCounted>,
+    /// Path the buffer is saved at, to find its skeleton and includes
+    #[var]
+    file_path: GString,
+}
+
+#[godot_api]
+impl CastagneValidator {
+    /// Diagnostics of a buffer as dictionaries with `severity`, `code`,
+    /// `message`, `notes`, `line` (1-indexed, 0 if unknown), `column` and
+    /// `length` (0 for the whole line)
+    #[func]
+    pub fn validate_buffer(&self, text: GString) -> VarArray {
+        let diagnostics = validate_buffer(
+            &self.file_path.to_string(),
+            &text.to_string(),
+            &ParserConfig::default(),
+            &LintConfig::default(),
+        );
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span.as_ref();
+                let notes: PackedStringArray = diagnostic
+                    .notes
+                    .iter()
+                    .map(|note| GString::from(note.as_str()))
+                    .collect();
+                let mut entry = VarDictionary::new();
+                entry.set("severity", diagnostic.severity.to_string().as_str());
+                entry.set("code", diagnostic.code.as_str());
+                entry.set("message", diagnostic.message.as_str());
+                entry.set("notes", notes);
+                entry.set("line", span.map_or(0, |span| span.line as i64));
+                entry.set("column", span.map_or(0, |span| span.column as i64));
+                entry.set("length", span.map_or(0, |span| span.length as i64));
+                entry.to_variant()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{MISSING_ENDIF, UNBALANCED_DELIMITER};
+
+    #[test]
+    fn test_validate_buffer() {
+        let text = ":Character:\nName: Ryu\n:Jab:\n---Init:\nAttackDuration(-5)\nSet(Meter, 1\nIfGt(Meter, 2):\nStop\n";
+        let diagnostics = validate_buffer(
+            "ryu.casp",
+            text,
+            &ParserConfig::default(),
+            &LintConfig::default(),
+        );
+        let found: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("attack-without-reaction", 5),
+                ("negative-duration", 5),
+                (UNBALANCED_DELIMITER, 6),
+                (MISSING_ENDIF, 7)
+            ]
+        );
+
+        let config = ParserConfig::default()
+            .without_diagnostic("attack-without-reaction")
+            .with_severity(UNBALANCED_DELIMITER, Severity::Warning)
+            .with_deny_warnings(true);
+        let diagnostics = validate_buffer("ryu.casp", text, &config, &LintConfig::default());
+        let found: Vec<(&str, Severity)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("negative-duration", Severity::Error),
+                (UNBALANCED_DELIMITER, Severity::Error),
+                (MISSING_ENDIF, Severity::Error)
+            ]
+        );
+
+        let clean = validate_buffer(
+            "ryu.casp",
+            ":Character:\nName: Ryu\n:Jab:\n---Init:\nStop\n",
+            &ParserConfig::default(),
+            &LintConfig::default(),
+        );
+        assert!(clean.is_empty());
+    }
+
+    #[test]
+    fn test_error_line() {
+        assert_eq!(error_line("Bad value (line 12, column 3)"), Some(12));
+        assert_eq!(error_line("File missing"), None);
+    }
+}
@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Visitor - Read-only traversal of a parsed character
+//!
+//! Implement the `visit_*` methods you care about and call
+//! `visit_character`. The default implementations walk into children,
+//! so overriding a method and still wanting the children visited means
+//! calling the matching `walk_*` function.
+//!
+//! Traversal order is deterministic, so diagnostics come out stable:
+//! variables, specblocks and states are sorted by name even though they
+//! are stored in HashMaps, and phases come in declaration order.
+
+use crate::parser::{ParsedAction, ParsedCharacter, ParsedState, ParsedVariable};
+use std::collections::HashMap;
+
+pub trait Visitor {
+    fn visit_character(&mut self, character: &ParsedCharacter) {
+        walk_character(self, character);
+    }
+
+    fn visit_variable(&mut self, _variable: &ParsedVariable) {}
+
+    fn visit_specblock(&mut self, _name: &str, _entries: &HashMap<String, String>) {}
+
+    fn visit_state(&mut self, state: &ParsedState) {
+        walk_state(self, state);
+    }
+
+    fn visit_phase(&mut self, state: &ParsedState, phase: &str, actions: &[ParsedAction]) {
+        walk_phase(self, state, phase, actions);
+    }
+
+    fn visit_action(&mut self, _state: &ParsedState, _phase: &str, _action: &ParsedAction) {}
+}
+
+pub fn walk_character<V: Visitor + ?Sized>(visitor: &mut V, character: &ParsedCharacter) {
+    for name in sorted_keys(&character.variables) {
+        visitor.visit_variable(&character.variables[name]);
+    }
+    for name in sorted_keys(&character.specblocks) {
+        visitor.visit_specblock(name, &character.specblocks[name]);
+    }
+    for name in sorted_keys(&character.states) {
+        visitor.visit_state(&character.states[name]);
+    }
+}
+
+pub fn walk_state<V: Visitor + ?Sized>(visitor: &mut V, state: &ParsedState) {
+    for (phase, actions) in &state.actions {
+        visitor.visit_phase(state, phase, actions);
+    }
+}
+
+pub fn walk_phase<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    state: &ParsedState,
+    phase: &str,
+    actions: &[ParsedAction],
+) {
+    for action in actions {
+        visitor.visit_action(state, phase, action);
+    }
+}
+
+fn sorted_keys<K: AsRef<str> + Ord, T>(map: &HashMap<K, T>) -> Vec<&str> {
+    let mut keys: Vec<&K> = map.keys().collect();
+    keys.sort();
+    keys.into_iter().map(AsRef::as_ref).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::StateType;
+    use crate::phases::Phases;
+
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Visitor for Recorder {
+        fn visit_state(&mut self, state: &ParsedState) {
+            self.events.push(format!("state {}", state.name));
+            walk_state(self, state);
+        }
+
+        fn visit_action(&mut self, state: &ParsedState, phase: &str, action: &ParsedAction) {
+            self.events
+                .push(format!("{}.{}: {}", state.name, phase, action.instruction));
+        }
+    }
+
+    fn state(name: &str, phases: &[(&str, &[&str])]) -> ParsedState {
+        let mut actions = Phases::new();
+        for (phase, instructions) in phases {
+            let list = instructions
+                .iter()
+                .map(|instruction| ParsedAction {
+                    instruction: (*instruction).into(),
+                    args: Default::default(),
+                    line_number: 1,
+                    frames: Vec::new(),
+                    defaulted: Vec::new(),
+                    flags: Vec::new(),
+                })
+                .collect();
+            actions.insert((*phase).into(), list);
+        }
+        ParsedState {
+            name: name.into(),
+            state_type: StateType::Normal,
+            parent: None,
+            actions,
+            attack: None,
+            description: None,
+            origin: Default::default(),
+            locals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_walk_order() {
+        let mut parser = crate::parser::CastagneParser::new();
+        let mut character = parser.end_parsing().unwrap();
+        character
+            .states
+            .insert("Walk".into(), state("Walk", &[("Init", &["A"])]));
+        character.states.insert(
+            "Idle".into(),
+            state("Idle", &[("Init", &["B"]), ("Action", &["C", "D"])]),
+        );
+
+        let mut recorder = Recorder { events: Vec::new() };
+        recorder.visit_character(&character);
+
+        assert_eq!(
+            recorder.events,
+            vec![
+                "state Idle",
+                "Idle.Init: B",
+                "Idle.Action: C",
+                "Idle.Action: D",
+                "state Walk",
+                "Walk.Init: A",
+            ]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Watcher - Hot reload of character files while playtesting
+//!
+//! Watches a character file and the skeletons and includes it depends on,
+//! found as the parser finds them with the watcher's `ParserConfig`
+//! (skeleton aliases and search paths). `poll()` checks for
+//! changes, re-parses, and hands the new character to the registered
+//! callbacks. By default changes are detected by polling file modification
+//! times (cheap enough to call every frame from Godot); with the `notify`
+//! feature, `start_notify()` switches to filesystem notifications and
+//! `poll()` only drains the event queue. Either way callbacks run on the
+//! thread calling `poll()`, which is what the Godot scene tree needs.
+
+use crate::parser::{CastagneParser, JsonFormat, ParsedCharacter, ParserConfig};
+use crate::roster::DependencyGraph;
+use godot::prelude::*;
+use std::fs;
+use std::time::SystemTime;
+
+type ReloadCallback = Box<dyn FnMut(&ParsedCharacter)>;
+
+/// Last seen state of a watched file
+#[derive(Debug, Clone, PartialEq)]
+struct FileStamp {
+    path: String,
+    modified: Option<SystemTime>,
+    len: Option<u64>,
+}
+
+impl FileStamp {
+    fn read(path: &str) -> Self {
+        let metadata = fs::metadata(path).ok();
+        Self {
+            path: path.to_string(),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            len: metadata.as_ref().map(|m| m.len()),
+        }
+    }
+}
+
+/// Watches a character file and its dependencies
+pub struct CharacterWatcher {
+    character_path: String,
+    config: ParserConfig,
+    stamps: Vec<FileStamp>,
+    callbacks: Vec<ReloadCallback>,
+    errors: Vec<String>,
+    #[cfg(feature = "notify")]
+    notifier: Option<Notifier>,
+}
+
+impl CharacterWatcher {
+    pub fn new(character_path: &str) -> Self {
+        Self::with_config(character_path, ParserConfig::default())
+    }
+
+    /// Watch with `config`, used both to find the dependencies and to parse
+    pub fn with_config(character_path: &str, config: ParserConfig) -> Self {
+        let mut watcher = Self {
+            character_path: character_path.to_string(),
+            config,
+            stamps: Vec::new(),
+            callbacks: Vec::new(),
+            errors: Vec::new(),
+            #[cfg(feature = "notify")]
+            notifier: None,
+        };
+        watcher.refresh_watched_files();
+        watcher
+    }
+
+    /// Register a callback invoked with every successfully reloaded character
+    pub fn on_reload(&mut self, callback: impl FnMut(&ParsedCharacter) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// The character file followed by its skeletons and includes
+    pub fn watched_files(&self) -> Vec<&str> {
+        self.stamps.iter().map(|s| s.path.as_str()).collect()
+    }
+
+    /// Errors from the last failed reload
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Check for changes and reload if needed
+    ///
+    /// Returns the new character when a reload happened and succeeded.
+    pub fn poll(&mut self) -> Option<ParsedCharacter> {
+        if !self.has_changes() {
+            return None;
+        }
+        self.reload()
+    }
+
+    /// Re-parse now, regardless of file changes
+    pub fn reload(&mut self) -> Option<ParsedCharacter> {
+        self.refresh_watched_files();
+
+        let mut parser = CastagneParser::with_config(self.config.clone());
+        let character = parser.create_full_character(&self.character_path).ok();
+        self.errors = parser.errors;
+
+        if let Some(ref character) = character {
+            for callback in &mut self.callbacks {
+                callback(character);
+            }
+        }
+        character
+    }
+
+    fn has_changes(&self) -> bool {
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = &self.notifier {
+            return notifier.drain();
+        }
+
+        self.stamps
+            .iter()
+            .any(|stamp| FileStamp::read(&stamp.path) != *stamp)
+    }
+
+    /// Recompute the dependencies (they may have changed) and file stamps
+    ///
+    /// Skeletons and includes are resolved the way `load_inherited_files`
+    /// resolves them, through the config's aliases and search paths.
+    fn refresh_watched_files(&mut self) {
+        let graph = DependencyGraph::build(&[&self.character_path], &self.config);
+        let paths = graph.files().to_vec();
+
+        self.stamps = paths.iter().map(|path| FileStamp::read(path)).collect();
+
+        #[cfg(feature = "notify")]
+        if self.notifier.is_some() {
+            self.notifier = Notifier::new(&paths).ok();
+        }
+    }
+
+    /// Use filesystem notifications instead of polling modification times
+    #[cfg(feature = "notify")]
+    pub fn start_notify(&mut self) -> notify::Result<()> {
+        let paths: Vec<String> = self.stamps.iter().map(|s| s.path.clone()).collect();
+        self.notifier = Some(Notifier::new(&paths)?);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "notify")]
+struct Notifier {
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(feature = "notify")]
+impl Notifier {
+    fn new(paths: &[String]) -> notify::Result<Self> {
+        use notify::Watcher;
+
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for path in paths {
+            watcher.watch(
+                std::path::Path::new(path),
+                notify::RecursiveMode::NonRecursive,
+            )?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Whether any modification event arrived since the last call
+    fn drain(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            if let Ok(event) = event {
+                changed |= event.kind.is_modify() || event.kind.is_create();
+            }
+        }
+        changed
+    }
+}
+
+/// Godot node polling a watched character every frame
+#[derive(GodotClass)]
+#[class(base=Node)]
+pub struct CastagneCharacterWatcher {
+    base: Base<Node>,
+    watcher: Option<CharacterWatcher>,
+}
+
+#[godot_api]
+impl INode for CastagneCharacterWatcher {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            base,
+            watcher: None,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        if !watcher.has_changes() {
+            return;
+        }
+        let character = watcher.reload();
+        let errors = watcher.errors().join("\n");
+        self.emit_result(character, errors);
+    }
+}
+
+#[godot_api]
+impl CastagneCharacterWatcher {
+    /// Emitted with the path and JSON of the reloaded character
+    #[signal]
+    fn character_reloaded(path: GString, character_json: GString);
+
+    /// Emitted when a changed file no longer parses
+    #[signal]
+    fn reload_failed(path: GString, errors: GString);
+
+    /// Start watching a character file and its skeleton chain
+    #[func]
+    pub fn watch(&mut self, path: GString) {
+        self.watcher = Some(CharacterWatcher::new(&path.to_string()));
+    }
+
+    /// Stop watching
+    #[func]
+    pub fn stop(&mut self) {
+        self.watcher = None;
+    }
+
+    /// Files currently watched
+    #[func]
+    pub fn get_watched_files(&self) -> PackedStringArray {
+        match &self.watcher {
+            Some(watcher) => watcher
+                .watched_files()
+                .into_iter()
+                .map(GString::from)
+                .collect(),
+            None => PackedStringArray::new(),
+        }
+    }
+
+    fn emit_result(&mut self, character: Option<ParsedCharacter>, errors: String) {
+        let path = match &self.watcher {
+            Some(watcher) => GString::from(watcher.character_path.as_str()),
+            None => return,
+        };
+        match character.map(|c| c.to_json(JsonFormat::Internal)) {
+            Some(Ok(json)) => {
+                self.signals()
+                    .character_reloaded()
+                    .emit(&path, &GString::from(json.as_str()));
+            }
+            Some(Err(e)) => {
+                let message = GString::from(e.to_string().as_str());
+                self.signals().reload_failed().emit(&path, &message);
+            }
+            None => {
+                let message = GString::from(errors.as_str());
+                self.signals().reload_failed().emit(&path, &message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn write(path: &std::path::Path, content: &str) {
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_watches_skeleton_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.casp");
+        let middle = dir.path().join("middle.casp");
+        let child = dir.path().join("child.casp");
+        write(&base, ":Character:\nName: Base\n");
+        write(
+            &middle,
+            &format!(":Character:\nName: Middle\nSkeleton: {}\n", base.display()),
+        );
+        write(
+            &child,
+            &format!(":Character:\nName: Child\nSkeleton: {}\n", middle.display()),
+        );
+
+        let watcher = CharacterWatcher::new(child.to_str().unwrap());
+
+        assert_eq!(
+            watcher.watched_files(),
+            vec![
+                child.to_str().unwrap(),
+                middle.to_str().unwrap(),
+                base.to_str().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watches_includes_through_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let skeletons = dir.path().join("skeletons");
+        fs::create_dir(&skeletons).unwrap();
+        let base = skeletons.join("base.casp");
+        let moves = dir.path().join("moves.casp");
+        let child = dir.path().join("child.casp");
+        write(&base, ":Character:\nName: Base\n");
+        write(
+            &moves,
+            ":Character:\nName: Moves\n:Variables:\nvar Speed(Int): 1\n",
+        );
+        write(
+            &child,
+            ":Character:\nName: Child\nSkeleton: base.casp\nInclude: Moves\n",
+        );
+        let config = ParserConfig::default()
+            .with_skeleton_path(&skeletons)
+            .with_skeleton_alias("Moves", moves.to_str().unwrap());
+
+        let mut watcher = CharacterWatcher::with_config(child.to_str().unwrap(), config);
+
+        assert_eq!(
+            watcher.watched_files(),
+            vec![
+                child.to_str().unwrap(),
+                base.to_str().unwrap(),
+                moves.to_str().unwrap()
+            ]
+        );
+        write(
+            &moves,
+            ":Character:\nName: Moves\n:Variables:\nvar Speed(Int): 12\n",
+        );
+        let character = watcher
+            .poll()
+            .expect("include change should trigger a reload");
+        assert_eq!(character.variables["Speed"].value, "12");
+    }
+
+    #[test]
+    fn test_poll_reloads_on_skeleton_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent = dir.path().join("parent.casp");
+        let child = dir.path().join("child.casp");
+        write(
+            &parent,
+            ":Character:\nName: Parent\n:Variables:\nvar Health(Int): 100\n",
+        );
+        write(
+            &child,
+            &format!(":Character:\nName: Child\nSkeleton: {}\n", parent.display()),
+        );
+
+        let mut watcher = CharacterWatcher::new(child.to_str().unwrap());
+        let reloads = Rc::new(RefCell::new(Vec::new()));
+        let seen = reloads.clone();
+        watcher.on_reload(move |character| {
+            seen.borrow_mut()
+                .push(character.variables["Health"].value.clone());
+        });
+
+        assert!(watcher.poll().is_none());
+
+        write(
+            &parent,
+            ":Character:\nName: Parent\n:Variables:\nvar Health(Int): 12000\n",
+        );
+        let character = watcher.poll().expect("change should trigger a reload");
+
+        assert_eq!(character.variables["Health"].value, "12000");
+        assert_eq!(*reloads.borrow(), vec!["12000".to_string()]);
+        assert!(watcher.poll().is_none());
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let child = dir.path().join("child.casp");
+        write(&child, ":Character:\nName: Child\n");
+
+        let mut watcher = CharacterWatcher::new(child.to_str().unwrap());
+        write(
+            &child,
+            ":Character:\nName: Child\nSkeleton: missing-skeleton.casp\n",
+        );
+
+        assert!(watcher.poll().is_none());
+        assert!(!watcher.errors().is_empty());
+    }
+}
@@ -418,9 +418,9 @@ mod tests {
 
     // Helper function to load and parse golden master JSON
     fn load_golden_master(path: &str) -> Value {
-        let json_content =
-            fs::read_to_string(path).expect(&format!("Failed to load golden master: {}", path));
+        let json_content = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to load golden master: {}", path));
         serde_json::from_str(&json_content)
-            .expect(&format!("Failed to parse golden master JSON: {}", path))
+            .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 }
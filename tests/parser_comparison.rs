@@ -26,10 +26,10 @@ mod tests {
 
     /// Load a golden master JSON file
     fn load_golden_master(path: &str) -> Value {
-        let json_content =
-            fs::read_to_string(path).expect(&format!("Failed to load golden master: {}", path));
+        let json_content = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to load golden master: {}", path));
         serde_json::from_str(&json_content)
-            .expect(&format!("Failed to parse golden master JSON: {}", path))
+            .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 
     /// Validate the structure of a golden master
@@ -104,7 +104,7 @@ mod tests {
 
         // Check variables count
         let variables = golden["variables"].as_object().unwrap();
-        assert!(variables.len() > 0, "Baston-Model should have variables");
+        assert!(!variables.is_empty(), "Baston-Model should have variables");
 
         println!("✓ Baston-Model golden master is valid");
         println!("  States: {}", states.len());
@@ -455,7 +455,10 @@ mod tests {
         let val1 = json!([1, 2]);
         let val2 = json!([1, 2, 3]);
         let diffs = compare_json_values(&val1, &val2, "root");
-        assert!(diffs.len() > 0, "Array length mismatch should be detected");
+        assert!(
+            !diffs.is_empty(),
+            "Array length mismatch should be detected"
+        );
 
         println!("✓ Comparison helper functions validated");
     }
@@ -681,7 +684,7 @@ mod tests {
         println!("\n=== Sample Diff Report Format ===");
         print_comparison_report(&diffs, "Test comparison");
 
-        assert!(diffs.len() > 0, "Should detect differences in test data");
+        assert!(!diffs.is_empty(), "Should detect differences in test data");
         println!("\n✓ Diff reporting format validated");
     }
 }
@@ -132,7 +132,7 @@ mod tests {
                                 if let Some(args) = action["args"].as_array() {
                                     arg_count_by_function
                                         .entry(func_name.to_string())
-                                        .or_insert_with(Vec::new)
+                                        .or_default()
                                         .push(args.len());
                                 }
                             }
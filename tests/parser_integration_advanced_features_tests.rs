@@ -554,11 +554,13 @@ mod tests {
             let golden = load_golden_master(file_path);
             let metadata = &golden["metadata"];
 
-            if !metadata["author"].is_null() && metadata["author"].as_str().unwrap_or("") != "" {
+            if !metadata["author"].is_null()
+                && !metadata["author"].as_str().unwrap_or("").is_empty()
+            {
                 *field_presence.get_mut("author").unwrap() += 1;
             }
             if !metadata["description"].is_null()
-                && metadata["description"].as_str().unwrap_or("") != ""
+                && !metadata["description"].as_str().unwrap_or("").is_empty()
             {
                 *field_presence.get_mut("description").unwrap() += 1;
             }
@@ -77,7 +77,10 @@ mod tests {
         // Pretty print
         let pretty = serde_json::to_string_pretty(&golden).expect("Should pretty print JSON");
 
-        assert!(pretty.len() > 0, "Pretty printed JSON should have content");
+        assert!(
+            !pretty.is_empty(),
+            "Pretty printed JSON should have content"
+        );
         assert!(pretty.contains('\n'), "Pretty print should have newlines");
         assert!(
             pretty.contains("  "),
@@ -148,7 +151,7 @@ mod tests {
             if let Some(parent) = state_data["Parent"].as_str() {
                 children_map
                     .entry(parent.to_string())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(state_name.clone());
             }
         }
@@ -231,7 +234,7 @@ mod tests {
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(test_file);
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle large character file");
         }
@@ -257,7 +260,7 @@ mod tests {
             let mut parser = CastagneParser::new();
             let result = parser.create_full_character(file);
 
-            if result.is_some() {
+            if result.is_ok() {
                 parsed_count += 1;
             }
         }
@@ -364,7 +367,8 @@ InvalidLine3
 
         for file in &files {
             if file_exists(file) {
-                let content = fs::read_to_string(file).expect(&format!("Should read {}", file));
+                let content =
+                    fs::read_to_string(file).unwrap_or_else(|_| panic!("Should read {}", file));
 
                 let has_character = content.contains(":Character:");
                 let has_variables = content.contains(":Variables:");
@@ -459,7 +463,7 @@ InvalidLine3
         let result = parser.create_full_character(test_file);
         let duration = start.elapsed();
 
-        assert!(result.is_some(), "Parser should succeed");
+        assert!(result.is_ok(), "Parser should succeed");
 
         println!("✓ Parse performance: {:?}", duration);
         println!("  (Note: First run includes compilation time)");
@@ -482,7 +486,7 @@ InvalidLine3
         for _ in 0..iterations {
             let mut parser = CastagneParser::new();
             let result = parser.create_full_character(test_file);
-            assert!(result.is_some(), "Each parse should succeed");
+            assert!(result.is_ok(), "Each parse should succeed");
         }
 
         let total_duration = start.elapsed();
@@ -573,7 +577,7 @@ InvalidLine3
 
         println!("✓ Combo system detection:");
         println!("  Attack states: {}", attack_states.len());
-        if attack_states.len() > 0 {
+        if !attack_states.is_empty() {
             println!(
                 "  Examples: {:?}",
                 attack_states.iter().take(5).collect::<Vec<_>>()
@@ -42,7 +42,7 @@ mod tests {
 
         let subentities_obj = subentities.as_object().unwrap();
         assert!(
-            subentities_obj.len() > 0,
+            !subentities_obj.is_empty(),
             "Should have at least one subentity"
         );
 
@@ -377,8 +377,12 @@ mod tests {
             let states = golden["states"].as_object().unwrap();
             let variables = golden["variables"].as_object().unwrap();
 
-            assert!(states.len() > 0, "Iteration {} should load states", i);
-            assert!(variables.len() > 0, "Iteration {} should load variables", i);
+            assert!(!states.is_empty(), "Iteration {} should load states", i);
+            assert!(
+                !variables.is_empty(),
+                "Iteration {} should load variables",
+                i
+            );
         }
 
         println!("✓ Memory efficiency validated (3 iterations)");
@@ -432,7 +436,7 @@ mod tests {
             "✓ Combo system patterns validated ({} attack states)",
             attack_states.len()
         );
-        if attack_states.len() > 0 {
+        if !attack_states.is_empty() {
             println!(
                 "  Example attacks: {:?}",
                 attack_states.iter().take(5).collect::<Vec<_>>()
@@ -468,7 +472,7 @@ mod tests {
             }
         }
 
-        if ai_states.len() > 0 {
+        if !ai_states.is_empty() {
             println!(
                 "✓ AI state patterns validated ({} AI states)",
                 ai_states.len()
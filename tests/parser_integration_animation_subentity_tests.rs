@@ -241,7 +241,7 @@ mod tests {
         println!("  Subentities: {}", subentities.len());
 
         // Typically main character has many more states than subentities
-        if subentities.len() > 0 {
+        if !subentities.is_empty() {
             let ratio = main_states.len() as f64 / subentities.len() as f64;
             println!("  States to subentities ratio: {:.2}", ratio);
         }
@@ -369,7 +369,7 @@ mod tests {
                 if name_lower.contains(pattern) {
                     found_patterns
                         .entry(pattern.to_string())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(state_name.clone());
                 }
             }
@@ -53,7 +53,7 @@ var LargeNegative(Int): -1000000000
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse extreme int values");
+        assert!(character.is_ok(), "Should parse extreme int values");
         let character = character.unwrap();
 
         assert!(
@@ -90,7 +90,7 @@ var Pi(Float): 3.14159265359
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse special float values");
+        assert!(character.is_ok(), "Should parse special float values");
         let character = character.unwrap();
 
         assert!(
@@ -125,7 +125,7 @@ var LargeVec(Vec3): 999.999, 888.888, 777.777
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse vector extreme values");
+        assert!(character.is_ok(), "Should parse vector extreme values");
         let character = character.unwrap();
 
         assert!(
@@ -174,7 +174,7 @@ Level5Init()
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         assert!(
-            character.is_some(),
+            character.is_ok(),
             "Should parse deep inheritance (6 levels)"
         );
         let character = character.unwrap();
@@ -224,7 +224,7 @@ End()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse state with many phases");
+        assert!(character.is_ok(), "Should parse state with many phases");
         let character = character.unwrap();
 
         let complex_state = &character.states["ComplexState"];
@@ -268,10 +268,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(
-            character.is_some(),
-            "Should parse deeply nested conditionals"
-        );
+        assert!(character.is_ok(), "Should parse deeply nested conditionals");
         let character = character.unwrap();
 
         let test_state = &character.states["Test"];
@@ -280,7 +277,7 @@ EndIf
             "Should have Action phase"
         );
         assert!(
-            test_state.actions["Action"].len() > 0,
+            !test_state.actions["Action"].is_empty(),
             "Should have actions"
         );
 
@@ -304,7 +301,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse 100 variables");
+        assert!(character.is_ok(), "Should parse 100 variables");
         let character = character.unwrap();
 
         assert!(
@@ -332,7 +329,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse 50 states");
+        assert!(character.is_ok(), "Should parse 50 states");
         let character = character.unwrap();
 
         assert!(
@@ -360,7 +357,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse 200 actions");
+        assert!(character.is_ok(), "Should parse 200 actions");
         let character = character.unwrap();
 
         let test_state = &character.states["TestState"];
@@ -400,7 +397,7 @@ var NormalString(Str): "Short"
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse long strings");
+        assert!(character.is_ok(), "Should parse long strings");
         let character = character.unwrap();
 
         assert!(
@@ -434,10 +431,7 @@ var Mixed(Str): "Mixed: !@# 123 ABC あいう"
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(
-            character.is_some(),
-            "Should parse strings with special chars"
-        );
+        assert!(character.is_ok(), "Should parse strings with special chars");
         let character = character.unwrap();
 
         assert!(
@@ -475,10 +469,7 @@ var Russian(Str): "Привет мир"
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(
-            character.is_some(),
-            "Should parse Unicode and emoji strings"
-        );
+        assert!(character.is_ok(), "Should parse Unicode and emoji strings");
         let character = character.unwrap();
 
         assert!(
@@ -522,7 +513,7 @@ MixedArgs("string", 123, true, 45.67)
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse complex action arguments");
+        assert!(character.is_ok(), "Should parse complex action arguments");
         let character = character.unwrap();
 
         let test_state = &character.states["TestState"];
@@ -559,7 +550,7 @@ MultipleArgs("arg1", "arg2", "arg3", "arg4", "arg5", "arg6", "arg7", "arg8", "ar
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         assert!(
-            character.is_some(),
+            character.is_ok(),
             "Should parse actions with very long arguments"
         );
         let character = character.unwrap();
@@ -607,7 +598,7 @@ Action()
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         assert!(
-            character.is_some(),
+            character.is_ok(),
             "Should parse file with comments everywhere"
         );
         let character = character.unwrap();
@@ -652,10 +643,7 @@ Name: Test
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(
-            character.is_some(),
-            "Should parse file with mostly comments"
-        );
+        assert!(character.is_ok(), "Should parse file with mostly comments");
         let character = character.unwrap();
 
         assert_eq!(
@@ -685,7 +673,7 @@ Name: Test
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse many specblocks");
+        assert!(character.is_ok(), "Should parse many specblocks");
         let character = character.unwrap();
 
         assert!(
@@ -713,10 +701,7 @@ Name: Test
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(
-            character.is_some(),
-            "Should parse specblock with many fields"
-        );
+        assert!(character.is_ok(), "Should parse specblock with many fields");
         let character = character.unwrap();
 
         let large_block = &character.specblocks["LargeBlock"];
@@ -754,8 +739,11 @@ var Var5(Int): 5
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         // Parser should handle inconsistent indentation gracefully
-        if let Some(character) = character {
-            assert!(character.variables.len() > 0, "Should parse some variables");
+        if let Ok(character) = character {
+            assert!(
+                !character.variables.is_empty(),
+                "Should parse some variables"
+            );
             println!(
                 "✓ Inconsistent indentation handled ({} variables parsed)",
                 character.variables.len()
@@ -774,7 +762,7 @@ var Var5(Int): 5
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should handle trailing whitespace");
+        assert!(character.is_ok(), "Should handle trailing whitespace");
         let character = character.unwrap();
 
         assert_eq!(
@@ -847,7 +835,7 @@ ApplyComboScaling(0.5)
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse complex combo system");
+        assert!(character.is_ok(), "Should parse complex combo system");
         let character = character.unwrap();
 
         assert!(character.states.len() >= 7, "Should have combo states");
@@ -931,7 +919,7 @@ CheckTransition_Idle()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse advanced state machine");
+        assert!(character.is_ok(), "Should parse advanced state machine");
         let character = character.unwrap();
 
         assert!(
@@ -983,7 +971,7 @@ var ZeroVec3(Vec3): 0.0, 0.0, 0.0
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse all types together");
+        assert!(character.is_ok(), "Should parse all types together");
         let character = character.unwrap();
 
         assert!(
@@ -602,7 +602,7 @@ mod tests {
 
             mutability_by_type
                 .entry(var_type)
-                .or_insert_with(std::collections::HashMap::new)
+                .or_default()
                 .entry(mutability)
                 .and_modify(|count| *count += 1)
                 .or_insert(1);
@@ -33,6 +33,7 @@ mod tests {
             .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 
+    #[allow(dead_code)]
     fn create_temp_casp(content: &str) -> NamedTempFile {
         let mut file = NamedTempFile::new().expect("Failed to create temp file");
         file.write_all(content.as_bytes())
@@ -325,15 +326,11 @@ mod tests {
 
             if value != "null" {
                 match var_type {
-                    "Vec2" => {
-                        if value == "0,0" || value == "0.0,0.0" {
-                            zero_vec2_count += 1;
-                        }
+                    "Vec2" if (value == "0,0" || value == "0.0,0.0") => {
+                        zero_vec2_count += 1;
                     }
-                    "Vec3" => {
-                        if value == "0,0,0" || value == "0.0,0.0,0.0" {
-                            zero_vec3_count += 1;
-                        }
+                    "Vec3" if (value == "0,0,0" || value == "0.0,0.0,0.0") => {
+                        zero_vec3_count += 1;
                     }
                     _ => {}
                 }
@@ -609,8 +609,8 @@ mod tests {
                 continue;
             }
 
-            let content =
-                fs::read_to_string(module_file).expect(&format!("Should read {}", module_file));
+            let content = fs::read_to_string(module_file)
+                .unwrap_or_else(|_| panic!("Should read {}", module_file));
 
             // Basic validation - should be a valid .casp file
             assert!(!content.is_empty(), "{} should not be empty", module_file);
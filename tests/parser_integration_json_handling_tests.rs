@@ -532,16 +532,10 @@ mod tests {
                     println!("  Found {} numeric defines", defines_obj.len());
 
                     // Check some numeric values
-                    let mut checked = 0;
-                    for (key, value) in defines_obj.iter() {
-                        if checked >= 5 {
-                            break;
-                        }
-
+                    for (key, value) in defines_obj.iter().take(5) {
                         assert!(value.is_number(), "Define '{}' should be number", key);
 
                         println!("    {} = {}", key, value);
-                        checked += 1;
                     }
 
                     println!("  ✓ Validated numeric defines");
@@ -719,7 +713,7 @@ mod tests {
         }
 
         // Sort by size
-        sizes.sort_by(|a, b| b.1.cmp(&a.1));
+        sizes.sort_by_key(|b| std::cmp::Reverse(b.1));
 
         for (path, size, states, vars) in &sizes {
             let kb = *size as f64 / 1024.0;
@@ -537,7 +537,7 @@ mod tests {
         }
 
         // Sort by line count
-        sizes.sort_by(|a, b| b.1.cmp(&a.1));
+        sizes.sort_by_key(|b| std::cmp::Reverse(b.1));
 
         for (path, lines, chars) in &sizes {
             let kb = *chars as f64 / 1024.0;
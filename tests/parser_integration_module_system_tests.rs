@@ -515,7 +515,7 @@ mod tests {
         );
 
         let modules = golden["transformed_data"].as_object().unwrap();
-        assert!(modules.len() > 0, "Should have at least one module");
+        assert!(!modules.is_empty(), "Should have at least one module");
 
         println!(
             "✓ Transformed data completeness validated ({} modules)",
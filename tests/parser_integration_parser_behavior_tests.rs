@@ -55,7 +55,7 @@ def MinHealth: 0
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse int variables");
         }
@@ -96,7 +96,7 @@ var AnimState(Str): "idle"
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse string variables");
         }
@@ -129,7 +129,7 @@ var Velocity(Vec2): 10.5, -5.2
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse Vec2 variables");
         }
@@ -162,7 +162,7 @@ var IsAttacking(Bool): false
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse Bool variables");
         }
@@ -201,7 +201,7 @@ CheckInput()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse basic state");
         }
@@ -243,7 +243,7 @@ InitDerived()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse state inheritance");
         }
@@ -282,7 +282,7 @@ JumpInit()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse multiple states");
         }
@@ -316,7 +316,7 @@ Action3()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse simple actions");
         }
@@ -349,7 +349,7 @@ Multiply(Velocity, 2.0)
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse actions with arguments");
         }
@@ -382,7 +382,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse conditional actions");
         }
@@ -419,7 +419,7 @@ var Health(Int): 100
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse specblocks");
         }
@@ -460,7 +460,7 @@ var Health(Int): 100  # Health variable
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle comments");
         }
@@ -498,8 +498,7 @@ var Health(Int): 100
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         // Parser might still produce output despite errors
-        if character.is_some() {
-            let character = character.unwrap();
+        if let Ok(character) = character {
             // Should at least parse the valid parts
             assert!(
                 character.variables.contains_key("Health"),
@@ -531,7 +530,7 @@ var  Mana(Int)  :  50
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle extra whitespace");
         }
@@ -568,7 +567,7 @@ Name: Test
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle empty sections");
         }
@@ -607,7 +606,7 @@ ReactionPhase()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle phase ordering");
         }
@@ -649,7 +648,7 @@ Version: 1.0
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should extract metadata");
         }
@@ -688,7 +687,7 @@ Version: 1.0
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(test_file);
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should parse character");
         }
@@ -727,7 +726,7 @@ var EmojiTest(Str): "Test with émojis ✅"
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        if character.is_none() {
+        if character.is_err() {
             eprintln!("Parser errors: {:?}", parser.errors);
             panic!("Parser should handle Unicode");
         }
@@ -19,10 +19,10 @@ mod tests {
     // ============================================================================
 
     fn load_golden_master(path: &str) -> Value {
-        let json_content =
-            fs::read_to_string(path).expect(&format!("Failed to load golden master: {}", path));
+        let json_content = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to load golden master: {}", path));
         serde_json::from_str(&json_content)
-            .expect(&format!("Failed to parse golden master JSON: {}", path))
+            .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 
     fn validate_character_structure(json: &Value) {
@@ -183,9 +183,9 @@ mod tests {
                         value
                     );
                 }
-                "Vec2" | "Vec3" => {
+                "Vec2" | "Vec3"
                     // Vec should be comma-separated numbers or null
-                    if value != "null" {
+                    if value != "null" => {
                         let parts: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
                         let expected_len = if var_type == "Vec2" { 2 } else { 3 };
                         assert_eq!(
@@ -203,7 +203,6 @@ mod tests {
                             );
                         }
                     }
-                }
                 _ => {} // Str can be anything
             }
         }
@@ -548,7 +547,7 @@ mod tests {
             let states = golden["states"].as_object().unwrap();
             let variables = golden["variables"].as_object().unwrap();
 
-            assert!(states.len() > 0, "{} has no states", file_path);
+            assert!(!states.is_empty(), "{} has no states", file_path);
             // Variables section exists (validated by unwrap above)
 
             println!("  ✓ {} states, {} variables", states.len(), variables.len());
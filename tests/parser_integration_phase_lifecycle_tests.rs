@@ -97,7 +97,7 @@ mod tests {
         let golden = load_golden_master("golden_masters/Baston-Model.json");
         let states = golden["states"].as_object().unwrap();
 
-        let standard_phases = vec![
+        let standard_phases = [
             "Init",
             "Action",
             "Reaction",
@@ -216,7 +216,7 @@ mod tests {
                     if let Some(actions) = phase_data["Actions"].as_array() {
                         phase_action_counts
                             .entry(phase_name.clone())
-                            .or_insert_with(Vec::new)
+                            .or_default()
                             .push(actions.len());
                     }
                 }
@@ -201,7 +201,7 @@ CheckRecovery()
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
         assert!(
-            character.is_some(),
+            character.is_ok(),
             "Should parse complete Street Fighter character"
         );
         let character = character.unwrap();
@@ -211,7 +211,7 @@ CheckRecovery()
 
         // Variables should include Health, Meter, MaxMeter, ComboCounter, Position, Velocity, Grounded, Crouching, Blocking
         assert!(
-            character.variables.len() > 0,
+            !character.variables.is_empty(),
             "Should have variables, got {}",
             character.variables.len()
         );
@@ -226,7 +226,7 @@ CheckRecovery()
 
         // States should include all the fighting game states
         assert!(
-            character.states.len() > 0,
+            !character.states.is_empty(),
             "Should have states, got {}",
             character.states.len()
         );
@@ -355,7 +355,7 @@ PushOpponent()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse anime fighter character");
+        assert!(character.is_ok(), "Should parse anime fighter character");
         let character = character.unwrap();
 
         assert!(
@@ -453,7 +453,7 @@ SetOnBlock(-3)
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse detailed frame data");
+        assert!(character.is_ok(), "Should parse detailed frame data");
         let character = character.unwrap();
 
         assert!(
@@ -571,7 +571,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse hitbox system");
+        assert!(character.is_ok(), "Should parse hitbox system");
         let character = character.unwrap();
 
         assert!(
@@ -669,7 +669,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse meter management system");
+        assert!(character.is_ok(), "Should parse meter management system");
         let character = character.unwrap();
 
         assert!(
@@ -757,7 +757,7 @@ EndIf
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse input buffer system");
+        assert!(character.is_ok(), "Should parse input buffer system");
         let character = character.unwrap();
 
         assert!(
@@ -847,7 +847,7 @@ ReturnToNeutral()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse cancel system");
+        assert!(character.is_ok(), "Should parse cancel system");
         let character = character.unwrap();
 
         assert!(
@@ -949,7 +949,7 @@ CheckHit()
         let mut parser = CastagneParser::new();
         let character = parser.create_full_character(file.path().to_str().unwrap());
 
-        assert!(character.is_some(), "Should parse defensive mechanics");
+        assert!(character.is_ok(), "Should parse defensive mechanics");
         let character = character.unwrap();
 
         assert!(
@@ -34,6 +34,7 @@ mod tests {
             .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 
+    #[allow(dead_code)]
     fn create_temp_casp(content: &str) -> NamedTempFile {
         let mut file = NamedTempFile::new().expect("Failed to create temp file");
         file.write_all(content.as_bytes())
@@ -33,6 +33,7 @@ mod tests {
             .unwrap_or_else(|_| panic!("Failed to parse golden master JSON: {}", path))
     }
 
+    #[allow(dead_code)]
     fn create_temp_casp(content: &str) -> NamedTempFile {
         let mut file = NamedTempFile::new().expect("Failed to create temp file");
         file.write_all(content.as_bytes())
@@ -495,15 +496,15 @@ mod tests {
                 continue;
             }
 
-            if !state_data.get("Phases").is_some() {
+            if state_data.get("Phases").is_none() {
                 invalid_states.push(format!("{}: missing Phases", state_name));
             }
 
-            if !state_data.get("Parent").is_some() {
+            if state_data.get("Parent").is_none() {
                 invalid_states.push(format!("{}: missing Parent", state_name));
             }
 
-            if !state_data.get("Type").is_some() {
+            if state_data.get("Type").is_none() {
                 invalid_states.push(format!("{}: missing Type", state_name));
             }
         }
@@ -530,15 +531,15 @@ mod tests {
                 continue;
             }
 
-            if !var_data.get("Type").is_some() {
+            if var_data.get("Type").is_none() {
                 invalid_vars.push(format!("{}: missing Type", var_name));
             }
 
-            if !var_data.get("Value").is_some() {
+            if var_data.get("Value").is_none() {
                 invalid_vars.push(format!("{}: missing Value", var_name));
             }
 
-            if !var_data.get("Mutability").is_some() {
+            if var_data.get("Mutability").is_none() {
                 invalid_vars.push(format!("{}: missing Mutability", var_name));
             }
         }
@@ -575,14 +576,14 @@ mod tests {
                                 continue;
                             }
 
-                            if !action.get("function").is_some() {
+                            if action.get("function").is_none() {
                                 invalid_actions.push(format!(
                                     "{}.{} action {}: missing function",
                                     state_name, phase_name, i
                                 ));
                             }
 
-                            if !action.get("args").is_some() {
+                            if action.get("args").is_none() {
                                 invalid_actions.push(format!(
                                     "{}.{} action {}: missing args",
                                     state_name, phase_name, i
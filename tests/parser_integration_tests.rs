@@ -35,7 +35,7 @@ mod tests {
         let mut parser = CastagneParser::new();
         let result = parser.create_full_character(test_file);
 
-        if result.is_none() {
+        if result.is_err() {
             eprintln!("Parser errors:");
             for error in &parser.errors {
                 eprintln!("  - {}", error);
@@ -51,10 +51,10 @@ mod tests {
             "Character should have a name"
         );
         assert!(
-            character.variables.len() > 0,
+            !character.variables.is_empty(),
             "Character should have variables"
         );
-        assert!(character.states.len() > 0, "Character should have states");
+        assert!(!character.states.is_empty(), "Character should have states");
 
         println!("✓ Parser successfully parsed basic character");
         println!("  Name: {}", character.metadata.name);
@@ -74,7 +74,7 @@ mod tests {
         let mut parser = CastagneParser::new();
         let result = parser.create_full_character(test_file);
 
-        if result.is_none() {
+        if result.is_err() {
             eprintln!("Parser errors:");
             for error in &parser.errors {
                 eprintln!("  - {}", error);
@@ -149,7 +149,7 @@ mod tests {
         let mut has_bool = false;
         let mut has_vec2 = false;
 
-        for (_var_name, var_data) in &character.variables {
+        for var_data in character.variables.values() {
             match var_data.var_type {
                 VariableType::Int => has_int = true,
                 VariableType::Str => has_str = true,
@@ -308,8 +308,8 @@ mod tests {
         let mut parser = CastagneParser::new();
         let result = parser.create_full_character(nonexistent_file);
 
-        assert!(result.is_none(), "Parser should fail on missing file");
-        assert!(parser.errors.len() > 0, "Parser should report errors");
+        assert!(result.is_err(), "Parser should fail on missing file");
+        assert!(!parser.errors.is_empty(), "Parser should report errors");
 
         println!("✓ Parser handles missing files gracefully");
         println!("  Errors reported: {}", parser.errors.len());
@@ -427,7 +427,7 @@ mod tests {
         for i in 0..5 {
             let mut parser = CastagneParser::new();
             let result = parser.create_full_character(test_file);
-            assert!(result.is_some(), "Parse #{} should succeed", i + 1);
+            assert!(result.is_ok(), "Parse #{} should succeed", i + 1);
         }
 
         println!("✓ Parser handles repeated parsing (5 iterations)");
@@ -456,9 +456,8 @@ mod tests {
             let mut parser = CastagneParser::new();
             let result = parser.create_full_character(module_file);
 
-            if result.is_some() {
+            if let Ok(character) = result {
                 parsed_count += 1;
-                let character = result.unwrap();
                 println!(
                     "  ✓ Parsed {}: {} states, {} variables",
                     module_file,
@@ -427,7 +427,7 @@ mod tests {
                 if !parent.is_empty() {
                     children_map
                         .entry(parent.to_string())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(state_name.clone());
                 }
             }
@@ -512,7 +512,7 @@ mod tests {
                 if !parent.is_empty() {
                     sibling_groups
                         .entry(parent.to_string())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(state_name.clone());
                 }
             }
@@ -465,7 +465,7 @@ mod tests {
             .lines()
             .filter(|line| {
                 let trimmed = line.trim();
-                (trimmed.starts_with("var CAST_REG_") || trimmed.starts_with("def CAST_REG_"))
+                trimmed.starts_with("var CAST_REG_") || trimmed.starts_with("def CAST_REG_")
             })
             .collect();
 
@@ -620,7 +620,7 @@ mod tests {
     #[test]
     fn e2e_variable_empty_string_defaults() {
         let core_path = "castagne_godot4/modules/core/Base-Core.casp";
-        let content = load_module_file(core_path);
+        let _content = load_module_file(core_path);
 
         println!("✓ Checking for empty string defaults:");
 